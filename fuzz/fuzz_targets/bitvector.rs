@@ -0,0 +1,34 @@
+#![no_main]
+
+use compressor::bitvector::Bitvector;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Deserialize arbitrary bytes, and check that valid bitvectors serialize
+    // back into the bytes that were read.
+    if let Some((bv, read)) = Bitvector::deserialize(data) {
+        assert!(read <= data.len());
+        let mut output = Vec::new();
+        assert_eq!(bv.serialize(&mut output), read);
+        assert_eq!(output, data[..read]);
+    }
+
+    // Push the bytes as pairs of (width, bits), and check the round trip.
+    let mut bv = Bitvector::new();
+    let mut pushed = Vec::new();
+    for pair in data.chunks_exact(2) {
+        let width = (pair[0] % 64) as usize;
+        let bits = Bitvector::clear_upper_bits(pair[1] as u64, width);
+        bv.push_word(bits, width);
+        pushed.push((bits, width));
+    }
+    let mut output = Vec::new();
+    let written = bv.serialize(&mut output);
+    let (mut bv2, read) = Bitvector::deserialize(&output).unwrap();
+    assert_eq!(read, written);
+    assert_eq!(bv, bv2);
+    for (bits, width) in pushed.iter().rev() {
+        assert_eq!(bv2.pop_word(*width), *bits);
+    }
+    assert!(bv2.is_empty());
+});
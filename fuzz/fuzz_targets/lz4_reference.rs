@@ -0,0 +1,33 @@
+#![no_main]
+
+use compressor::lz::{reference, LZ4Decoder, LZ4Encoder};
+use compressor::{Context, Decoder, Encoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Our streams decode with the reference decoder.
+    let mut compressed: Vec<u8> = Vec::new();
+    let ctx = Context::new(9, 1 << 20);
+    let _ = LZ4Encoder::new(data, &mut compressed, ctx).encode();
+    let decompressed = reference::decompress(&compressed, data.len()).unwrap();
+    assert_eq!(decompressed, data);
+
+    // The streams of the reference encoder decode with our decoder.
+    let compressed = reference::compress(data, 9).unwrap();
+    let mut decompressed: Vec<u8> = Vec::new();
+    let (consumed, _) = LZ4Decoder::new(&compressed, &mut decompressed)
+        .decode()
+        .unwrap();
+    assert_eq!(consumed, compressed.len());
+    assert_eq!(decompressed, data);
+
+    // Both decoders agree on the content of the inputs that they accept.
+    let theirs = reference::decompress(data, 1 << 16);
+    let mut ours: Vec<u8> = Vec::new();
+    let res = LZ4Decoder::new(data, &mut ours).decode();
+    if let (Some(theirs), Some((consumed, _))) = (theirs, res) {
+        if consumed == data.len() && ours.len() <= 1 << 16 {
+            assert_eq!(theirs, ours);
+        }
+    }
+});
@@ -0,0 +1,144 @@
+//! Selects the compression level and the coder of the blocks automatically.
+//! The encoder compresses a few pages that are sampled across the input with
+//! several candidate settings, and measures the size of each. The cost of a
+//! candidate is the expected time to compress the samples on the reference
+//! machine of 'budget', so the choice depends only on the bytes of the input,
+//! and the same input always selects the same settings. The candidates are
+//! ordered from the fastest to the slowest, and a slower candidate wins only if
+//! it makes the samples smaller than the faster winner by at least 1% for each
+//! doubling of the compression time. The rest of the input is compressed with
+//! the winner. Each page records the codec that it was encoded with in its
+//! signature, so the decoder needs no extra information.
+
+use crate::budget::expected_time;
+use crate::full::encode_or_nop;
+use crate::Context;
+use std::ops::Range;
+use std::time::Duration;
+
+/// The level that asks the full encoder to select the level automatically.
+/// This is not a compression level.
+pub const AUTO_LEVEL: u8 = u8::MAX;
+
+/// A setting that the automatic selection picks: the level, and whether the
+/// blocks use the context-mixing coder (see 'Context::with_hybrid').
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub level: u8,
+    pub hybrid: bool,
+}
+
+impl Candidate {
+    const fn new(level: u8, hybrid: bool) -> Self {
+        Self { level, hybrid }
+    }
+
+    /// Return 'ctx' with the settings of the candidate.
+    pub fn apply(self, mut ctx: Context) -> Context {
+        ctx.level = self.level;
+        ctx.hybrid = self.hybrid;
+        ctx
+    }
+}
+
+/// The candidates that are measured on the samples.
+const CANDIDATES: [Candidate; 6] = [
+    Candidate::new(1, false),
+    Candidate::new(4, false),
+    Candidate::new(9, false),
+    Candidate::new(10, false),
+    Candidate::new(12, false),
+    Candidate::new(12, true),
+];
+
+/// The context-mixing coder is slower than tANS, but the matcher takes most of
+/// the time of the levels that it is useful for.
+const HYBRID_SLOWDOWN: f64 = 1.1;
+
+/// The number of pages that are sampled, and the max size of each sample.
+const SAMPLE_PAGES: usize = 3;
+const SAMPLE_SIZE: usize = 1 << 16;
+
+/// A slower candidate must make the samples this much smaller for each
+/// doubling of the compression time to win.
+const SAVING_PER_DOUBLING: f64 = 0.01;
+
+/// The size and the expected compression time of the samples with one
+/// candidate.
+#[derive(Clone, Copy, Debug)]
+pub struct Trial {
+    pub candidate: Candidate,
+    pub size: usize,
+    pub time: Duration,
+}
+
+/// Return the ranges of up to 'SAMPLE_PAGES' pages of up to 'page_size' bytes
+/// that are spread evenly across an input of 'len' bytes.
+pub fn sample_ranges(len: usize, page_size: usize) -> Vec<Range<usize>> {
+    let page_size = page_size.clamp(1, SAMPLE_SIZE);
+    if len <= page_size * SAMPLE_PAGES {
+        return (0..len)
+            .step_by(page_size)
+            .map(|start| start..len.min(start + page_size))
+            .collect();
+    }
+    let stride = (len - page_size) / (SAMPLE_PAGES - 1);
+    (0..SAMPLE_PAGES)
+        .map(|i| i * stride..i * stride + page_size)
+        .collect()
+}
+
+/// Return the pages of 'input' that are sampled. See 'sample_ranges'.
+pub fn sample_pages(input: &[u8], page_size: usize) -> Vec<&[u8]> {
+    let ranges = sample_ranges(input.len(), page_size);
+    ranges.into_iter().map(|range| &input[range]).collect()
+}
+
+/// Compress 'samples' with each of the candidates, and return the results in
+/// the order of the expected compression time.
+pub fn run_trials(samples: &[&[u8]]) -> Vec<Trial> {
+    let len = samples.iter().map(|s| s.len()).sum();
+    let mut trials: Vec<Trial> = CANDIDATES
+        .iter()
+        .map(|candidate| {
+            let ctx = candidate.apply(Context::new(0, SAMPLE_SIZE));
+            let size =
+                samples.iter().map(|s| encode_or_nop(s, ctx).len()).sum();
+            let mut time = expected_time(len, candidate.level, 1.);
+            if candidate.hybrid {
+                time = time.mul_f64(HYBRID_SLOWDOWN);
+            }
+            Trial {
+                candidate: *candidate,
+                size,
+                time,
+            }
+        })
+        .collect();
+    trials.sort_by_key(|trial| trial.time);
+    trials
+}
+
+/// Return the candidate of the winner of 'trials', which are ordered by their
+/// compression time. See the module comment.
+pub fn pick_winner(trials: &[Trial]) -> Candidate {
+    let mut winner = trials[0];
+    for trial in &trials[1..] {
+        let saving = 1. - trial.size as f64 / winner.size.max(1) as f64;
+        let slowdown =
+            trial.time.as_secs_f64() / winner.time.as_secs_f64().max(1e-9);
+        if saving >= SAVING_PER_DOUBLING * slowdown.log2().max(1.) {
+            winner = *trial;
+        }
+    }
+    winner.candidate
+}
+
+/// Return the candidate that compresses the pages 'samples' best, for the
+/// price of the compression time.
+pub fn select(samples: &[&[u8]]) -> Candidate {
+    if samples.iter().all(|s| s.is_empty()) {
+        return CANDIDATES[0];
+    }
+    pick_winner(&run_trials(samples))
+}
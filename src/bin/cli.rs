@@ -6,23 +6,611 @@ extern crate env_logger;
 extern crate log;
 
 use clap::{Arg, ArgAction, Command};
-use compressor::full::{FullDecoder, FullEncoder};
+use compressor::auto::{self, AUTO_LEVEL};
+use compressor::block::FAST_LEVEL;
+use compressor::checkpoint::Checkpoint;
+use compressor::digest::{self, DigestHasher, Sha256, SHA256_ID};
+use compressor::frame::{self, FrameHasher, FRAME_TRAILER_LEN};
+use compressor::full::{decode_or_nop, encode_or_nop};
+use compressor::full::{FullDecoder, FullEncoder, ARITH_LEVEL};
+use compressor::inspect::{self, PageEntry};
 use compressor::lz::{LZ4Decoder, LZ4Encoder};
-use compressor::utils::signatures::{FILE_EXTENSION, FULL_SIG, LZ4_SIG};
+use compressor::metadata::{self, Metadata, MAX_METADATA_LEN};
+use compressor::pager;
+use compressor::pipeline::Pipeline;
+use compressor::profile::{Hint, TuningProfile};
+use compressor::recompress;
+use compressor::registry::{self, HEADER_LEN};
+use compressor::repair;
+use compressor::seal::{HmacSeal, PageTransform};
+use compressor::sparse::SparseWriter;
+use compressor::utils::hash::{xxh32, xxh64};
+use compressor::utils::leb128;
+use compressor::utils::signatures::{
+    CHECKED_PAGE_SIG, CONST_PAGE_SIG, DIGEST_SIG, FILE_EXTENSION, FULL_SIG,
+    LZ4_SIG, METADATA_SIG, PAGER_SIG, RECOMPRESS_SIG, RESET_TABLE_SIG,
+    SEAL_TRAILER_SIG,
+};
+use compressor::volume::MIN_VOLUME_SIZE;
+use compressor::volume::{volume_path, VolumeReader, VolumeWriter};
 use compressor::{Context, Decoder, Encoder};
 
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+use std::time::UNIX_EPOCH;
 use std::{fs, time::Instant};
 use std::{fs::File, io::Write};
 
 const DEFAULT_COMPRESSION_LEVEL: u8 = 4;
 
-fn save_file(data: &[u8], path: &str, no_write: bool) {
+/// The size of each page when streaming files through the pipeline for
+/// '--resume' and '--page-log', if the user did not select one with
+/// '--page-size'.
+const PIPELINE_PAGE_SIZE: usize = 1 << 24;
+
+/// The number of pages that can wait between the stages of the pipeline.
+const PIPELINE_DEPTH: usize = 2;
+
+/// Read the pages of the file at 'path' that the automatic level selection
+/// samples, without reading the whole file.
+fn read_samples(path: &str) -> io::Result<Vec<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let mut samples = Vec::new();
+    for range in auto::sample_ranges(len, PIPELINE_PAGE_SIZE) {
+        let mut sample = vec![0; range.len()];
+        file.seek(SeekFrom::Start(range.start as u64))?;
+        file.read_exact(&mut sample)?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+/// Read the first bytes of the file at 'path', which tell its type.
+fn read_file_header(path: &str) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path)?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Open the output sink. Returns a sink that drops the data if 'no_write' is
+/// set.
+fn create_sink(path: &str, no_write: bool) -> io::Result<Box<dyn Write>> {
+    if no_write {
+        log::info!("Not saving the result.");
+        return Ok(Box::new(io::sink()));
+    }
+    Ok(Box::new(io::BufWriter::new(File::create(path)?)))
+}
+
+/// Tracks the progress of a resumable compression in a state file.
+struct Progress {
+    /// The path of the state file.
+    path: String,
+    /// The last state that was saved.
+    state: Checkpoint,
+}
+
+impl Progress {
+    /// Save the state. The state is written to a temporary file that replaces
+    /// the state file, so a crash never leaves a partial state behind.
+    fn save(&self) -> io::Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, self.state.encode())?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// Return a value that identifies the content of the file at 'path', based on
+/// its size and modification time.
+fn input_fingerprint(path: &str) -> io::Result<u64> {
+    let meta = fs::metadata(path)?;
+    let modified = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut bytes = Vec::new();
+    bytes.extend(meta.len().to_le_bytes());
+    bytes.extend(modified.as_nanos().to_le_bytes());
+    Ok(xxh64(&bytes, 0))
+}
+
+/// Open the output file 'path' of a resumable compression of the file at
+/// 'input_path'. If the state file of an earlier compression of the same input
+/// exists, the output is truncated to the last page that was saved, and the
+/// compression continues from there.
+fn open_resumable(
+    input_path: &str,
+    path: &str,
+    level: u8,
+    page_size: usize,
+) -> io::Result<(io::BufWriter<File>, Progress)> {
+    let fresh = Checkpoint {
+        fingerprint: input_fingerprint(input_path)?,
+        level,
+        page_size: page_size as u64,
+        pages: 0,
+        offset: 0,
+    };
+    let state_path = format!("{}.state", path);
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let output_len = output.metadata()?.len();
+    let state = fs::read(&state_path)
+        .ok()
+        .and_then(|data| Checkpoint::decode(&data))
+        .filter(|state| state.matches(&fresh) && state.offset <= output_len)
+        .unwrap_or(fresh);
+    if state.pages > 0 {
+        log::info!("Resuming after {} pages.", state.pages);
+    }
+
+    output.set_len(state.offset)?;
+    output.seek(SeekFrom::End(0))?;
+    let progress = Progress {
+        path: state_path,
+        state,
+    };
+    Ok((io::BufWriter::new(output), progress))
+}
+
+/// Compress the file at 'input_path' into 'sink' with the full compressor.
+/// The file is read, compressed and written in pages by three threads, so that
+/// the IO overlaps with the compression. The output is identical to the output
+/// of the full encoder with the page size of 'ctx'. If 'progress'
+/// is set, the compression starts after the pages that it records, and the
+/// progress is saved after each page. The frame checksum, if enabled, is
+/// computed while the pages are written, and the digest of the input while
+/// the pages are read. The 'metadata' is saved in the header of the stream.
+/// If 'page_log' is set, a JSON line that describes each page is written to
+/// it. Returns the number of bytes read and written.
+fn compress_pipelined(
+    input_path: &str,
+    sink: &mut dyn Write,
+    ctx: Context,
+    metadata: &Metadata,
+    mut progress: Option<&mut Progress>,
+    mut page_log: Option<&mut dyn Write>,
+) -> io::Result<(usize, usize)> {
+    let mut file = File::open(input_path)?;
+    let len = file.metadata()?.len() as usize;
+    let page_size = ctx.block_size;
+    let parts = 1 + len / page_size;
+
+    let mut written = 0;
+    let mut first = 0;
+    let mut hasher = FrameHasher::new();
+    if let Some(progress) = progress.as_deref() {
+        first = progress.state.pages as usize;
+        written = progress.state.offset as usize;
+        file.seek(SeekFrom::Start((first * page_size) as u64))?;
+    }
+    if first == 0 {
+        let mut header = Vec::new();
+        header.extend(FULL_SIG);
+        metadata
+            .encode(&mut header)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        pager::write_header(parts, &mut header);
+        sink.write_all(&header)?;
+        hasher.update(&header);
+        written = header.len();
+    }
+
+    let digest = thread::scope(|s| {
+        let (raw_tx, raw_rx) = sync_channel::<Vec<u8>>(PIPELINE_DEPTH);
+        let (page_tx, page_rx) =
+            sync_channel::<(Vec<u8>, PageEntry)>(PIPELINE_DEPTH);
+
+        // Read the pages from the disk.
+        let reader = s.spawn(move || -> io::Result<Option<Sha256>> {
+            let mut digest = ctx.digest.then(Sha256::new);
+            for _ in first..parts {
+                let mut chunk = Vec::with_capacity(page_size);
+                (&mut file).take(page_size as u64).read_to_end(&mut chunk)?;
+                if let Some(digest) = digest.as_mut() {
+                    digest.update(&chunk);
+                }
+                if raw_tx.send(chunk).is_err() {
+                    break;
+                }
+            }
+            Ok(digest)
+        });
+
+        // Compress the pages.
+        s.spawn(move || {
+            for (idx, chunk) in (first..).zip(raw_rx) {
+                let start = Instant::now();
+                let mut page = Vec::new();
+                pager::encode_page(&chunk, ctx, encode_or_nop, &mut page);
+                let (offset, time) = (idx * page_size, start.elapsed());
+                let entry =
+                    PageEntry::new(idx, offset, chunk.len(), &page, ctx, time);
+                if page_tx.send((page, entry)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Write the compressed pages.
+        for (page, entry) in page_rx {
+            sink.write_all(&page)?;
+            hasher.update(&page);
+            written += page.len();
+            if let Some(log) = page_log.as_deref_mut() {
+                writeln!(log, "{}", entry.to_json())?;
+            }
+
+            // Save the progress after the page reaches the output file.
+            if let Some(progress) = progress.as_deref_mut() {
+                sink.flush()?;
+                progress.state.pages += 1;
+                progress.state.offset = written as u64;
+                progress.save()?;
+            }
+        }
+        reader.join().unwrap()
+    })?;
+
+    // The digest is saved before the frame checksum, which covers it.
+    if let Some(digest) = digest {
+        let mut trailer = Vec::new();
+        digest::write_digest_trailer(&digest, &mut trailer);
+        sink.write_all(&trailer)?;
+        hasher.update(&trailer);
+        written += trailer.len();
+    }
+    if ctx.frame_checksum {
+        let mut trailer = Vec::new();
+        frame::write_frame_trailer(hasher.finish(), &mut trailer);
+        sink.write_all(&trailer)?;
+        written += trailer.len();
+    }
+    sink.flush()?;
+    if let Some(log) = page_log {
+        log.flush()?;
+    }
+    Ok((len, written))
+}
+
+/// Read a record that starts with 'prefix_len' fixed bytes and ends with a
+/// LEB128 number from 'reader'. Returns the bytes of the record.
+fn read_record(
+    reader: &mut dyn Read,
+    prefix_len: usize,
+) -> io::Result<Vec<u8>> {
+    let mut record = vec![0; prefix_len];
+    reader.read_exact(&mut record)?;
+    for _ in 0..leb128::MAX_LEN {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        record.push(byte[0]);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(record)
+}
+
+/// Read the header of a paged stream of the full compressor from 'reader'.
+/// The metadata and the reset table of the stream are skipped. Returns the
+/// size of the header and the number of pages, or None if the stream is not a
+/// paged stream.
+fn read_paged_header(reader: &mut dyn Read) -> Option<(usize, usize)> {
+    let mut sig = [0; FULL_SIG.len()];
+    reader.read_exact(&mut sig).ok()?;
+    if sig != FULL_SIG {
+        return None;
+    }
+    let mut read = FULL_SIG.len();
+    let mut next = [0; METADATA_SIG.len()];
+    reader.read_exact(&mut next).ok()?;
+    let records = [
+        (METADATA_SIG, MAX_METADATA_LEN),
+        (RESET_TABLE_SIG, usize::MAX),
+    ];
+    for (sig, max_len) in records {
+        if next != sig {
+            continue;
+        }
+        let mut chained = (&next[..]).chain(&mut *reader);
+        let record = read_record(&mut chained, sig.len()).ok()?;
+        let (_, len) = leb128::decode_len(&record[sig.len()..])?;
+        if len > max_len {
+            return None;
+        }
+        let body = (&mut *reader).take(len as u64);
+        if io::copy(&mut { body }, &mut io::sink()).ok()? != len as u64 {
+            return None;
+        }
+        read += record.len() + len;
+        reader.read_exact(&mut next).ok()?;
+    }
+    let mut chained = (&next[..]).chain(reader);
+    let header = read_record(&mut chained, PAGER_SIG.len()).ok()?;
+    let (len, parts) = pager::read_header(&header)?;
+    Some((read + len, parts))
+}
+
+/// A reader that computes the frame checksum of the bytes that it reads.
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: FrameHasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// A page record that was read from a paged stream.
+enum PageRecord {
+    /// An encoded page.
+    Packet(Vec<u8>),
+    /// A page with a single repeated byte and its length.
+    Constant(u8, usize),
+    /// The body of a page with long zero runs.
+    Sparse(Vec<u8>),
+}
+
+/// The size of the trailers of a stream, true if its frame checksum matches,
+/// and the id and the value of its digest.
+type Trailers = (usize, bool, Option<(u8, Vec<u8>)>);
+
+/// Read the digest trailer and the frame trailer that follow the pages of a
+/// paged stream from 'file', if any.
+fn read_trailers(file: &mut HashingReader<File>) -> io::Result<Trailers> {
+    let mut read = 0;
+    let mut digest = None;
+    let mut trailer = Vec::new();
+    (&mut file.inner)
+        .take(DIGEST_SIG.len() as u64)
+        .read_to_end(&mut trailer)?;
+    if trailer == DIGEST_SIG {
+        // The signature was read around the hasher of the frame checksum.
+        file.hasher.update(&trailer);
+        let mut reader = (&DIGEST_SIG[..]).chain(&mut *file);
+        let mut record = read_record(&mut reader, DIGEST_SIG.len() + 1)?;
+        let (_, len) = leb128::decode_len(&record[DIGEST_SIG.len() + 1..])
+            .ok_or(io::ErrorKind::InvalidData)?;
+        file.take(len as u64).read_to_end(&mut record)?;
+        if let Some((len, id, value)) = digest::read_digest_trailer(&record) {
+            read += len;
+            digest = Some((id, value.to_vec()));
+        }
+        trailer.clear();
+        (&mut file.inner)
+            .take(SEAL_TRAILER_SIG.len() as u64)
+            .read_to_end(&mut trailer)?;
+    }
+
+    // Skip the seal, which is verified by the in-memory decoder.
+    if trailer == SEAL_TRAILER_SIG {
+        file.hasher.update(&trailer);
+        let mut reader = (&SEAL_TRAILER_SIG[..]).chain(&mut *file);
+        let key_id = read_record(&mut reader, SEAL_TRAILER_SIG.len())?;
+        let mut record = read_record(&mut *file, 0)?;
+        let (_, len) =
+            leb128::decode_len(&record).ok_or(io::ErrorKind::InvalidData)?;
+        file.take(len as u64).read_to_end(&mut record)?;
+        read += key_id.len() + record.len();
+        trailer.clear();
+    }
+
+    // Check the frame checksum that follows the pages, if any.
+    let checksum = file.hasher.finish();
+    let rest = FRAME_TRAILER_LEN - trailer.len();
+    (&mut file.inner)
+        .take(rest as u64)
+        .read_to_end(&mut trailer)?;
+    match frame::read_frame_trailer(&trailer) {
+        Some(sum) => Ok((read + trailer.len(), sum == checksum, digest)),
+        None => Ok((read, true, digest)),
+    }
+}
+
+/// Decompress the paged stream at 'input_path' into 'sink' using the same
+/// pipeline as 'compress_pipelined'. Returns None if the stream is invalid,
+/// or if its frame checksum or its digest does not match. Returns the number of bytes read
+/// and written.
+fn decompress_pipelined(
+    input_path: &str,
+    sink: &mut dyn Write,
+) -> io::Result<Option<(usize, usize)>> {
+    let mut file = HashingReader {
+        inner: File::open(input_path)?,
+        hasher: FrameHasher::new(),
+    };
+    let (mut read, parts) = match read_paged_header(&mut file) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    let mut written = 0;
+    let mut valid = true;
+
+    thread::scope(|s| {
+        let (raw_tx, raw_rx) =
+            sync_channel::<(PageRecord, Option<(u32, usize)>)>(PIPELINE_DEPTH);
+        let (page_tx, page_rx) = sync_channel::<Option<Vec<u8>>>(0);
+
+        // Read the encoded pages from the disk.
+        let reader = s.spawn(move || -> io::Result<Trailers> {
+            let mut read = 0;
+            for _ in 0..parts {
+                let mut sig = [0; 2];
+                file.read_exact(&mut sig)?;
+                // The checksum of a checked page precedes the page record.
+                let mut check = None;
+                if sig == CHECKED_PAGE_SIG {
+                    let mut reader = sig.chain(&mut file);
+                    let header = read_record(&mut reader, sig.len() + 4)?;
+                    read += header.len();
+                    match pager::read_checked_header(&header) {
+                        Some((_, checksum, len)) => {
+                            check = Some((checksum, len))
+                        }
+                        None => break,
+                    }
+                    file.read_exact(&mut sig)?;
+                }
+                let mut reader = sig.chain(&mut file);
+                let record = if sig == CONST_PAGE_SIG {
+                    let header = read_record(&mut reader, sig.len() + 1)?;
+                    read += header.len();
+                    match pager::read_constant_page(&header) {
+                        Some((_, val, len)) => PageRecord::Constant(val, len),
+                        None => break,
+                    }
+                } else {
+                    let header = read_record(&mut reader, sig.len())?;
+                    let (length, sparse) =
+                        match pager::read_page_header(&header) {
+                            Some((_, length)) => (length, false),
+                            None => match pager::read_sparse_header(&header) {
+                                Some((_, length)) => (length, true),
+                                None => break,
+                            },
+                        };
+                    let mut packet = Vec::with_capacity(length);
+                    (&mut file).take(length as u64).read_to_end(&mut packet)?;
+                    read += header.len() + packet.len();
+                    if sparse {
+                        PageRecord::Sparse(packet)
+                    } else {
+                        PageRecord::Packet(packet)
+                    }
+                };
+                if raw_tx.send((record, check)).is_err() {
+                    break;
+                }
+            }
+
+            let (trailer_read, frame_valid, digest) = read_trailers(&mut file)?;
+            Ok((read + trailer_read, frame_valid, digest))
+        });
+
+        // Decompress the pages.
+        s.spawn(move || {
+            for (record, check) in raw_rx {
+                let page = match record {
+                    PageRecord::Constant(val, len) => Some(vec![val; len]),
+                    PageRecord::Sparse(body) => {
+                        pager::decode_sparse_body(&body, decode_or_nop)
+                    }
+                    PageRecord::Packet(packet) => {
+                        match decode_or_nop(&packet) {
+                            Some((read, page)) if read == packet.len() => {
+                                Some(page)
+                            }
+                            _ => None,
+                        }
+                    }
+                };
+                // Reject the checked pages that decode into the wrong bytes.
+                let page = page.filter(|page| match check {
+                    Some((sum, len)) => {
+                        page.len() == len && xxh32(page, 0) == sum
+                    }
+                    None => true,
+                });
+                let failed = page.is_none();
+                if page_tx.send(page).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        // Write the decompressed pages, and compute the digest of the output.
+        let mut pages = 0;
+        let mut hasher = Sha256::new();
+        for page in page_rx {
+            match page {
+                Some(page) => {
+                    sink.write_all(&page)?;
+                    hasher.update(&page);
+                    written += page.len();
+                    pages += 1;
+                }
+                None => valid = false,
+            }
+        }
+        valid &= pages == parts;
+        let (trailer_read, frame_valid, digest) = reader.join().unwrap()?;
+        read += trailer_read;
+        valid &= frame_valid;
+        // The digests of unknown hash functions are skipped.
+        if let Some((SHA256_ID, value)) = digest {
+            valid &= hasher.finish() == value;
+        }
+        Ok::<(), io::Error>(())
+    })?;
+
+    sink.flush()?;
+    if !valid {
+        return Ok(None);
+    }
+    Ok(Some((read, written)))
+}
+
+/// Create the volume with the index 'index' of the output at 'path'.
+fn create_volume(path: &str, index: usize) -> io::Result<io::BufWriter<File>> {
+    let path = volume_path(path, index);
+    log::info!("Writing {}.", path);
+    Ok(io::BufWriter::new(File::create(path)?))
+}
+
+/// Read the volumes of the stream at 'path', in order, until the next volume
+/// is missing.
+fn read_volumes(path: &str) -> io::Result<Vec<u8>> {
+    let mut files = Vec::new();
+    while let Ok(file) = File::open(volume_path(path, files.len())) {
+        files.push(file);
+    }
+    let mut data = Vec::new();
+    VolumeReader::new(files).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Parse a size in bytes, with an optional K, M or G suffix.
+fn parse_size(val: &str) -> Option<usize> {
+    let (num, scale) = match val.chars().last()?.to_ascii_uppercase() {
+        'K' => (&val[..val.len() - 1], 1 << 10),
+        'M' => (&val[..val.len() - 1], 1 << 20),
+        'G' => (&val[..val.len() - 1], 1 << 30),
+        _ => (val, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(scale)
+}
+
+/// Save 'data' to 'path', or to volumes of 'split' bytes if 'split' is set.
+fn save_file(data: &[u8], path: &str, no_write: bool, split: Option<usize>) {
     if no_write {
         log::info!("Not saving the result.");
         return;
     }
-    let mut f = File::create(path).expect("Can't create file");
-    f.write_all(data).expect("Unable to write data");
+    if let Some(size) = split {
+        let mut writer = VolumeWriter::new(size, |i| create_volume(path, i));
+        writer.write_all(data).expect("Unable to write data");
+        let volumes = writer.finish().expect("Unable to write data");
+        log::info!("Wrote {} volumes.", volumes);
+        return;
+    }
+    let f = File::create(path).expect("Can't create file");
+    let mut writer = SparseWriter::new(io::BufWriter::new(f));
+    writer.write_all(data).expect("Unable to write data");
+    writer.finish().expect("Unable to write data");
     log::info!("Wrote {}.", &path);
 }
 
@@ -59,6 +647,8 @@ fn operate(
     input: &[u8],
     output: &mut Vec<u8>,
     ctx: Context,
+    metadata: &Metadata,
+    seal: Option<&dyn PageTransform>,
 ) -> Option<(usize, usize)> {
     let x = Timer::new();
 
@@ -68,7 +658,11 @@ fn operate(
                 "Compressing using the Full compressor at level {}",
                 ctx.level
             );
-            let mut encoder = FullEncoder::new(input, output, ctx);
+            let mut encoder =
+                FullEncoder::new(input, output, ctx).with_metadata(metadata)?;
+            if let Some(seal) = seal {
+                encoder = encoder.with_seal(seal);
+            }
             let written = encoder.encode();
             return Some((input.len(), written));
         }
@@ -91,10 +685,26 @@ fn operate(
         return stat;
     }
 
+    if input.starts_with(&RECOMPRESS_SIG) {
+        log::info!("Restoring a recompressed file");
+        let (format, restored) = recompress::restore(input)?;
+        log::info!("Restored the {} wrapper.", format);
+        output.extend(&restored);
+        return Some((input.len(), restored.len()));
+    }
+
     if input.starts_with(&FULL_SIG) {
         log::info!("Decompressing the Full compression");
         let mut decoder = FullDecoder::new(input, output);
+        if let Some(seal) = seal {
+            decoder = decoder.with_seal(seal);
+        }
         let stat = decoder.decode();
+        if stat.is_none() && seal.is_some() {
+            log::error!(
+                "The stream is invalid, or is not sealed with the key."
+            );
+        }
         return stat;
     }
     drop(x);
@@ -147,9 +757,168 @@ fn main() {
                 .short('l')
                 .long("level")
                 .value_name("level")
-                .help("Selects the compression level.")
+                .help("Selects the compression level, or 'auto'.")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("fast")
+                .long("fast")
+                .value_name("N")
+                .help("Selects the fastest level with the acceleration N (1-5).")
+                .num_args(1)
+                .conflicts_with("level"),
+        )
+        .arg(
+            Arg::new("split")
+                .long("split-size")
+                .value_name("SIZE")
+                .help("Split the output into volumes of SIZE bytes (K, M, G).")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("pagesize")
+                .long("page-size")
+                .value_name("SIZE")
+                .help("Compress in pages of SIZE bytes (K, M, G), and overlap the file IO with the compression.")
+                .num_args(1)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Save the progress, and continue an interrupted compression.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["split", "nowrite", "decompress"]),
+        )
+        .arg(
+            Arg::new("pagelog")
+                .long("page-log")
+                .value_name("FILE")
+                .help("Write a JSON line that describes each compressed page to FILE.")
+                .num_args(1)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("sealkey")
+                .long("seal-key")
+                .value_name("FILE")
+                .help("Seal the stream with HMAC-SHA256 and the key in FILE, or verify the seal when decompressing.")
+                .num_args(1)
+                .conflicts_with("resume"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("FILE")
+                .help("Reuse the automatic choices that FILE saved for similar files, and save the new choices.")
+                .num_args(1)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("long")
+                .long("long")
+                .help("Find repetitions across the whole file, up to 2GB apart.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("global")
+                .long("global")
+                .help("Find long repetitions across the pages of the file.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("long"),
+        )
+        .arg(
+            Arg::new("checksum")
+                .long("checksum")
+                .help("Save a checksum with each page, to detect damaged pages.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sparse")
+                .long("sparse")
+                .help("Save the long zero runs as holes, which are extracted without writing them. Matches don't cross the holes.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("no-rle")
+                .long("no-rle")
+                .help("Don't collapse the long byte runs of the blocks before matching them.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("hybrid")
+                .long("hybrid")
+                .help("Code the literals and the sequences with the context-mixing coder. This is slower, and compresses better at levels 11 and 12.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("frame")
+                .long("frame-checksum")
+                .help("Save a checksum of the compressed bytes, for scrubbing.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("resume"),
+        )
+        .arg(
+            Arg::new("digest")
+                .long("digest")
+                .help("Save the SHA-256 digest of the input, to check the extracted data.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("resume"),
+        )
+        .arg(
+            Arg::new("scrub")
+                .long("scrub")
+                .help("Check the frame checksum of a file, without decompressing it.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["compress", "repair", "resume", "split"]),
+        )
+        .arg(
+            Arg::new("repair")
+                .long("repair")
+                .help("Recover the undamaged pages of a damaged file.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["compress", "resume", "split"]),
+        )
+        .arg(
+            Arg::new("info")
+                .long("info")
+                .help("Print the streams of each block of a compressed file.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["compress", "repair", "resume", "split"]),
+        )
+        .arg(
+            Arg::new("recompress")
+                .long("recompress")
+                .help("Decode the deflate streams of gzip and zip files before compressing them, and restore them bit-exactly on decompression. Streams that zlib does not reproduce are kept as is.")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["decompress", "resume", "split"]),
+        )
+        .arg(
+            Arg::new("filters")
+                .long("filters")
+                .value_name("LIST")
+                .help("The transforms before compression: 'auto', 'none', or a list such as 'delta:2,rle'.")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("comment")
+                .long("comment")
+                .value_name("TEXT")
+                .help("Save a comment in the header of the compressed file.")
+                .num_args(1)
+                .conflicts_with("decompress"),
+        )
+        .arg(
+            Arg::new("meta")
+                .long("meta")
+                .value_name("KEY=VALUE")
+                .help("Save an entry of metadata in the header of the compressed file.")
+                .action(ArgAction::Append)
+                .conflicts_with("decompress"),
+        )
         .arg(
             Arg::new("nowrite")
                 .long("no-write")
@@ -170,19 +939,95 @@ fn main() {
     let cli_decompress = matches.get_flag("decompress");
     let cli_checked = matches.get_flag("checked");
     let cli_nowrite = matches.get_flag("nowrite");
-    let cli_level: u8 = if let Some(val) = matches.get_one::<String>("level") {
-        val.parse::<u8>().unwrap_or(DEFAULT_COMPRESSION_LEVEL)
-    } else {
-        DEFAULT_COMPRESSION_LEVEL
+    let cli_resume = matches.get_flag("resume");
+    let cli_long = matches.get_flag("long");
+    let cli_global = matches.get_flag("global");
+    let cli_checksum = matches.get_flag("checksum");
+    let cli_sparse = matches.get_flag("sparse");
+    let mut cli_hybrid = matches.get_flag("hybrid");
+    let cli_no_rle = matches.get_flag("no-rle");
+    let cli_frame = matches.get_flag("frame");
+    let cli_digest = matches.get_flag("digest");
+    let cli_scrub = matches.get_flag("scrub");
+    let cli_repair = matches.get_flag("repair");
+    let cli_info = matches.get_flag("info");
+    let cli_recompress = matches.get_flag("recompress");
+    let cli_page_log = matches.get_one::<String>("pagelog");
+    let cli_profile = matches.get_one::<String>("profile");
+    let cli_seal = matches.get_one::<String>("sealkey").map(|path| {
+        HmacSeal::new(&fs::read(path).expect("Can't read the seal key"))
+    });
+    let seal = cli_seal.as_ref().map(|seal| seal as &dyn PageTransform);
+    let mut cli_level: u8 = match matches.get_one::<String>("level") {
+        Some(val) if val == "auto" => AUTO_LEVEL,
+        Some(val) => val.parse::<u8>().unwrap_or(DEFAULT_COMPRESSION_LEVEL),
+        None => DEFAULT_COMPRESSION_LEVEL,
     };
+    let cli_fast = match matches.get_one::<String>("fast") {
+        Some(val) => match val.parse::<u8>() {
+            Ok(acceleration) => Some(acceleration),
+            Err(_) => {
+                log::error!("Invalid acceleration {}.", val);
+                return;
+            }
+        },
+        None => None,
+    };
+    if cli_fast.is_some() {
+        cli_level = FAST_LEVEL;
+    }
+    let cli_filters = matches
+        .get_one::<String>("filters")
+        .cloned()
+        .unwrap_or_else(|| String::from("auto"));
+    let mut cli_metadata = Metadata::new();
+    if let Some(comment) = matches.get_one::<String>("comment") {
+        cli_metadata = cli_metadata.with_comment(comment);
+    }
+    for entry in matches.get_many::<String>("meta").unwrap_or_default() {
+        let Some((key, value)) = entry.split_once('=') else {
+            log::error!("Invalid metadata {}.", entry);
+            return;
+        };
+        cli_metadata = cli_metadata.with(key, value);
+    }
+    if cli_metadata.encode(&mut Vec::new()).is_none() {
+        log::error!("The metadata is larger than {} bytes.", MAX_METADATA_LEN);
+        return;
+    }
     let mut cli_output_path = matches.get_one::<String>("output").cloned();
     let cli_mode = matches
         .get_one::<String>("mode")
         .cloned()
         .unwrap_or_else(|| String::from("full"));
 
+    let cli_split = match matches.get_one::<String>("split") {
+        Some(val) => match parse_size(val) {
+            Some(size) if size >= MIN_VOLUME_SIZE => Some(size),
+            _ => {
+                log::error!("Invalid volume size {}.", val);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let cli_page_size = match matches.get_one::<String>("pagesize") {
+        Some(val) => match parse_size(val) {
+            Some(size) if size > 0 => Some(size),
+            _ => {
+                log::error!("Invalid page size {}.", val);
+                return;
+            }
+        },
+        None => None,
+    };
+
     let input_path = matches.get_one::<String>("INPUT").unwrap();
-    let input = fs::read(input_path).expect("Can't open the input file");
+
+    // The input is the first volume of a split stream.
+    let volume_base = input_path.strip_suffix(&volume_path("", 0));
+    let input_path = &volume_base.unwrap_or(input_path).to_string();
 
     // The user did not specify if this is compress of decompress. Try to figure
     // out using the extension.
@@ -191,7 +1036,127 @@ fn main() {
         cli_compress = true;
     }
 
-    let ctx = Context::new(cli_level, 1 << 31);
+    // Reuse the choices of an earlier run on a similar file, instead of
+    // measuring the levels again. See 'profile'.
+    let mut profile = None;
+    let mut hint: Option<Hint> = None;
+    if let (true, Some(path)) =
+        (cli_compress && cli_mode == "full", cli_profile)
+    {
+        let loaded = fs::read(path).ok().and_then(|data| {
+            let profile = TuningProfile::decode(&data);
+            if profile.is_none() {
+                log::info!("Ignoring the invalid profile {}.", path);
+            }
+            profile
+        });
+        let loaded = loaded.unwrap_or_default();
+        let header = read_file_header(input_path).unwrap_or_default();
+        let kind = registry::detect_file_type(input_path, &header);
+        let samples = read_samples(input_path).expect("Can't read input");
+        let refs: Vec<&[u8]> = samples.iter().map(|s| &s[..]).collect();
+        hint = loaded.lookup(kind, &refs).cloned();
+        if hint.is_some() {
+            log::info!("Using the choices of the profile {}.", path);
+        }
+        profile = Some((path, loaded, kind, samples));
+    }
+
+    // Measure a few levels and coders on samples of the file. Only the levels
+    // that were measured are saved in the profile.
+    let mut measured_level = None;
+    if cli_compress && cli_level == AUTO_LEVEL {
+        cli_level = if let Some(hint) = &hint {
+            hint.level
+        } else if cli_mode == "full" {
+            let samples = read_samples(input_path).expect("Can't read input");
+            let samples: Vec<&[u8]> = samples.iter().map(|s| &s[..]).collect();
+            let choice = auto::select(&samples);
+            measured_level = Some(choice.level);
+            cli_hybrid |= choice.hybrid;
+            choice.level
+        } else {
+            DEFAULT_COMPRESSION_LEVEL
+        };
+        log::info!("Selected level {}.", cli_level);
+        if cli_hybrid {
+            log::info!("Selected the context-mixing coder.");
+        }
+    }
+
+    let Some(mut ctx) = Context::try_new(cli_level, 1 << 31) else {
+        log::error!("Invalid compression level {}.", cli_level);
+        return;
+    };
+    if let Some(acceleration) = cli_fast {
+        ctx = ctx.with_fast_level(acceleration);
+    }
+    if cli_long {
+        ctx = ctx.with_long_window();
+    }
+    if cli_checksum {
+        ctx = ctx.with_page_checksums();
+    }
+    if cli_sparse {
+        ctx = ctx.with_sparse_holes();
+    }
+    if cli_hybrid {
+        ctx = ctx.with_hybrid();
+    }
+    if cli_no_rle {
+        ctx = ctx.with_rle_pass(false);
+    }
+    if cli_frame {
+        ctx = ctx.with_frame_checksum();
+    }
+    if cli_digest {
+        ctx = ctx.with_digest();
+    }
+    // Keep the pages of the pipeline, and add references between them.
+    if cli_global {
+        ctx = Context {
+            block_size: PIPELINE_PAGE_SIZE,
+            ..ctx
+        }
+        .with_global_matching();
+    }
+
+    // Select the transforms by the type of the input, unless the user
+    // selected them.
+    if cli_compress && cli_mode == "full" {
+        let pipeline = match cli_filters.as_str() {
+            "none" => None,
+            "auto" if hint.is_some() => hint.as_ref().and_then(|h| h.pipeline),
+            "auto" => {
+                let header = read_file_header(input_path).unwrap_or_default();
+                let kind = registry::detect_file_type(input_path, &header);
+                if let Some(kind) = kind {
+                    log::info!("The input is a {} file.", kind);
+                }
+                kind.and_then(|kind| registry::default_pipeline(kind, &header))
+            }
+            spec => match Pipeline::parse(spec) {
+                Some(pipeline) => Some(pipeline),
+                None => {
+                    log::error!("Invalid filters {}.", spec);
+                    return;
+                }
+            },
+        };
+        if let Some(pipeline) = pipeline {
+            log::info!("Using the filters {}.", pipeline);
+            ctx = ctx.with_pipeline(pipeline);
+        }
+    }
+
+    // Save the choices of this run for the next files.
+    if let (Some((path, mut profile, kind, samples)), Some(level)) =
+        (profile, measured_level)
+    {
+        let refs: Vec<&[u8]> = samples.iter().map(|s| &s[..]).collect();
+        profile.record(kind, level, ctx.pipeline, &refs);
+        fs::write(path, profile.encode()).expect("Can't write the profile");
+    }
 
     // Come up with a file name.
     if cli_output_path.is_none() {
@@ -207,13 +1172,276 @@ fn main() {
 
     let mode = cli_mode == "full";
     let out = &cli_output_path.unwrap();
+
+    // Check the compressed bytes, without decompressing them.
+    if cli_scrub {
+        let input = match volume_base {
+            Some(base) => read_volumes(base),
+            None => fs::read(input_path),
+        };
+        let input = input.expect("Can't open the input file");
+        match frame::verify_frame(&input) {
+            Some(true) => log::info!("The frame checksum is correct."),
+            Some(false) => {
+                log::error!("The frame checksum does not match.");
+                std::process::exit(1);
+            }
+            None => {
+                log::error!("The input has no frame checksum.");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Describe the blocks of the stream.
+    if cli_info {
+        let input = match volume_base {
+            Some(base) => read_volumes(base),
+            None => fs::read(input_path),
+        };
+        let input = input.expect("Can't open the input file");
+        if let Some(metadata) = metadata::read_metadata(&input) {
+            for (key, value) in metadata.entries() {
+                println!("{}: {}", key, value);
+            }
+        }
+        let Some(blocks) = inspect::inspect_stream(&input) else {
+            log::error!("The input is not a paged compressed stream.");
+            return;
+        };
+        for (i, block) in blocks.iter().enumerate() {
+            println!("{}: {}", i, block);
+        }
+        return;
+    }
+
+    // Recover the undamaged pages, and save the map of the missing ranges.
+    if cli_repair {
+        let input = match volume_base {
+            Some(base) => read_volumes(base),
+            None => fs::read(input_path),
+        };
+        let input = input.expect("Can't open the input file");
+        let Some(report) = repair::repair(&input) else {
+            log::error!("The input is not a compressed stream.");
+            return;
+        };
+        for page in &report.missing {
+            log::info!("Page {} is damaged.", page.index);
+        }
+        if report.truncated {
+            log::info!("The rest of the pages can't be located.");
+        }
+        log::info!("Recovered {} of {} pages.", report.recovered, report.pages);
+        save_file(&report.output, out, cli_nowrite, None);
+        if !report.is_complete() && !cli_nowrite {
+            let map_path = out.clone() + ".map";
+            fs::write(&map_path, report.to_map()).expect("Can't write the map");
+            log::info!("Wrote the missing ranges to {}.", map_path);
+        }
+        return;
+    }
+
+    // Decode the content of inputs that are already in a compressed format.
+    // Other inputs are compressed as usual.
+    if cli_compress && cli_recompress {
+        let input = fs::read(input_path).expect("Can't open the input file");
+        match recompress::detect_format(&input) {
+            Some(format) => {
+                log::info!("The input is a {} file.", format);
+                if let Some(dest) = recompress::recompress(&input, ctx) {
+                    log::info!(
+                        "Recompressed from {} to {} bytes.",
+                        input.len(),
+                        dest.len()
+                    );
+                    save_file(&dest, out, cli_nowrite, None);
+                    return;
+                }
+                log::info!(
+                    "The {} file has no parts that can be restored.",
+                    format
+                );
+            }
+            None => log::info!("The input is not a known compressed format."),
+        }
+    }
+
+    // Stream the file through the pipeline if the user selected a page size,
+    // or asked for a feature of the pipeline. Otherwise the file is compressed
+    // in memory with the page size of the library. The in-memory path is also
+    // used when the result needs to be checked without writing it to disk, for
+    // long windows, which need the whole file in one page, and for global
+    // matching, which needs the whole file to find the repetitions.
+    let page_size = match cli_page_size {
+        Some(size) => Some(size),
+        None if cli_resume || cli_page_log.is_some() => {
+            Some(PIPELINE_PAGE_SIZE)
+        }
+        None => None,
+    };
+    let pipelined = mode
+        && page_size.is_some()
+        && cli_level != ARITH_LEVEL
+        && !cli_long
+        && !cli_global
+        && cli_seal.is_none()
+        && !(cli_checked && cli_nowrite);
+    if cli_resume && !pipelined {
+        log::error!("Only the pipelined full compressor can be resumed.");
+        return;
+    }
+    if cli_page_log.is_some() && !pipelined {
+        log::error!("Only the pipelined full compressor logs the pages.");
+        return;
+    }
+    if cli_compress && pipelined {
+        let page_size = page_size.unwrap();
+        let ctx = Context {
+            block_size: page_size,
+            ..ctx
+        };
+        log::info!(
+            "Compressing using the Full compressor at level {}",
+            cli_level
+        );
+        let x = Timer::new();
+        let mut page_log = cli_page_log.map(|path| {
+            let file = File::create(path).expect("Can't open the page log");
+            io::BufWriter::new(file)
+        });
+        let page_log = page_log.as_mut().map(|log| log as &mut dyn Write);
+        let stat = match cli_split {
+            _ if cli_resume => {
+                let (mut sink, mut progress) =
+                    open_resumable(input_path, out, cli_level, page_size)
+                        .expect("Can't open the output file");
+                let stat = compress_pipelined(
+                    input_path,
+                    &mut sink,
+                    ctx,
+                    &cli_metadata,
+                    Some(&mut progress),
+                    page_log,
+                );
+                if stat.is_ok() {
+                    let _ = fs::remove_file(&progress.path);
+                }
+                stat
+            }
+            Some(size) if !cli_nowrite => {
+                let mut sink =
+                    VolumeWriter::new(size, |i| create_volume(out, i));
+                let stat = compress_pipelined(
+                    input_path,
+                    &mut sink,
+                    ctx,
+                    &cli_metadata,
+                    None,
+                    page_log,
+                );
+                let volumes = sink.finish().expect("Can't write the output");
+                log::info!("Wrote {} volumes.", volumes);
+                stat
+            }
+            _ => {
+                let mut sink = create_sink(out, cli_nowrite)
+                    .expect("Can't open the output file");
+                compress_pipelined(
+                    input_path,
+                    &mut sink,
+                    ctx,
+                    &cli_metadata,
+                    None,
+                    page_log,
+                )
+            }
+        };
+        let (from, to) = stat.expect("Can't compress the input file");
+        drop(x);
+        log::info!("Compressed from {} to {} bytes.", from, to);
+        log::info!("Compression ratio is {:.4}x.", from as f64 / to as f64);
+
+        if cli_checked {
+            let input =
+                fs::read(input_path).expect("Can't open the input file");
+            let dest = match cli_split {
+                Some(_) => read_volumes(out),
+                None => fs::read(out),
+            };
+            let dest = dest.expect("Can't open the output file");
+            let mut decoded = Vec::new();
+            if let Some((from, to)) = operate(
+                false,
+                mode,
+                &dest,
+                &mut decoded,
+                ctx,
+                &cli_metadata,
+                seal,
+            ) {
+                log::info!("Decompressed from {} to {} bytes.", from, to);
+                if input == decoded {
+                    log::info!("Correct!");
+                } else {
+                    log::info!("Incorrect!");
+                }
+            } else {
+                log::info!("Could not decompress the file!");
+            }
+        }
+        return;
+    }
+
+    // The pipeline skips the seal, so sealed streams are verified in memory.
+    if !cli_compress && volume_base.is_none() && cli_seal.is_none() {
+        let paged = File::open(input_path)
+            .ok()
+            .and_then(|mut file| read_paged_header(&mut file))
+            .is_some();
+        if paged {
+            log::info!("Decompressing the Full compression");
+            let x = Timer::new();
+            let stat = if cli_nowrite {
+                let mut sink = create_sink(out, cli_nowrite)
+                    .expect("Can't open the output file");
+                decompress_pipelined(input_path, &mut sink)
+            } else {
+                let file =
+                    File::create(out).expect("Can't open the output file");
+                let mut sink = SparseWriter::new(io::BufWriter::new(file));
+                let stat = decompress_pipelined(input_path, &mut sink);
+                sink.finish().expect("Can't write the output file");
+                stat
+            };
+            let stat = stat.expect("Can't decompress the input file");
+            drop(x);
+            if let Some((from, to)) = stat {
+                log::info!("Decompressed from {} to {} bytes.", from, to);
+                return;
+            }
+            // The pipeline decodes each page on its own, so it fails on pages
+            // with references to earlier pages. The in-memory decoder handles
+            // them, and reports streams that are really invalid.
+            log::info!("Decompressing the whole stream in memory.");
+        }
+    }
+
+    let input = match volume_base {
+        Some(base) => read_volumes(base),
+        None => fs::read(input_path),
+    };
+    let input = input.expect("Can't open the input file");
     let mut dest = Vec::new();
 
     if cli_compress {
-        if let Some((from, to)) = operate(true, mode, &input, &mut dest, ctx) {
+        if let Some((from, to)) =
+            operate(true, mode, &input, &mut dest, ctx, &cli_metadata, seal)
+        {
             log::info!("Compressed from {} to {} bytes.", from, to);
             log::info!("Compression ratio is {:.4}x.", from as f64 / to as f64);
-            save_file(&dest, out, cli_nowrite);
+            save_file(&dest, out, cli_nowrite, cli_split);
         } else {
             log::info!("Compression failed");
             return;
@@ -222,9 +1450,15 @@ fn main() {
         if cli_checked {
             let mut decoded = Vec::new();
 
-            if let Some((from, to)) =
-                operate(false, mode, &dest, &mut decoded, ctx)
-            {
+            if let Some((from, to)) = operate(
+                false,
+                mode,
+                &dest,
+                &mut decoded,
+                ctx,
+                &cli_metadata,
+                seal,
+            ) {
                 log::info!("Decompressed from {} to {} bytes.", from, to);
                 if input == decoded {
                     log::info!("Correct!");
@@ -242,9 +1476,11 @@ fn main() {
         return;
     }
 
-    if let Some((from, to)) = operate(false, mode, &input, &mut dest, ctx) {
+    if let Some((from, to)) =
+        operate(false, mode, &input, &mut dest, ctx, &cli_metadata, seal)
+    {
         log::info!("Decompressed from {} to {} bytes.", from, to);
-        save_file(&dest, out, cli_nowrite);
+        save_file(&dest, out, cli_nowrite, None);
     } else {
         log::info!("Decompression failed");
     }
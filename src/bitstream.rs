@@ -0,0 +1,200 @@
+//! Reads and writes streams of bits that are packed into bytes, in one of two
+//! bit orders. 'Bitvector' packs the bits into words for the entropy coder of
+//! this crate, while other formats pack the bits into bytes. DEFLATE fills
+//! each byte from the least significant bit, and many hardware codecs fill
+//! each byte from the most significant bit. The writer and the reader of a
+//! stream must use the same order. In both orders a value that is written with
+//! 'write' is returned by 'read' with the same number of bits:
+//!
+//! - 'Lsb': the first bit of the stream is bit 0 of the first byte, and the
+//!   values are written from their least significant bit.
+//! - 'Msb': the first bit of the stream is bit 7 of the first byte, and the
+//!   values are written from their most significant bit.
+//!
+//! Formats that mix the orders, like the Huffman codes of DEFLATE, which are
+//! written from their most significant bit into an 'Lsb' stream, reverse the
+//! values with 'reverse_bits'.
+
+/// The max number of bits of a value that is written or read at once.
+pub const MAX_BITS: usize = 56;
+
+/// The order of the bits in the bytes of a stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The bytes are filled from the least significant bit, like DEFLATE.
+    Lsb,
+    /// The bytes are filled from the most significant bit.
+    Msb,
+}
+
+/// Return the lowest 'num' bits of 'bits'.
+fn low_bits(bits: u64, num: usize) -> u64 {
+    if num == 0 {
+        return 0;
+    }
+    bits & (u64::MAX >> (64 - num))
+}
+
+/// Return the lowest 'num' bits of 'bits' in the reverse order.
+pub fn reverse_bits(bits: u64, num: usize) -> u64 {
+    debug_assert!(num <= 64, "Reversing too many bits");
+    if num == 0 {
+        return 0;
+    }
+    bits.reverse_bits() >> (64 - num)
+}
+
+/// Reverse the order of the bits of each byte of 'data'. This converts a
+/// stream of single bits between the 'Lsb' and the 'Msb' orders.
+pub fn reverse_bits_in_bytes(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        *byte = byte.reverse_bits();
+    }
+}
+
+/// Writes values of up to 'MAX_BITS' bits to a stream of bytes.
+pub struct BitWriter<'a> {
+    /// The output stream.
+    output: &'a mut Vec<u8>,
+    /// The order of the bits in the bytes.
+    order: BitOrder,
+    /// The bits that don't fill a byte yet.
+    acc: u64,
+    /// The number of bits in 'acc', which is less than 8.
+    count: usize,
+}
+
+impl<'a> BitWriter<'a> {
+    pub fn new(output: &'a mut Vec<u8>, order: BitOrder) -> Self {
+        Self {
+            output,
+            order,
+            acc: 0,
+            count: 0,
+        }
+    }
+
+    /// Write the lowest 'num' bits of 'bits'.
+    pub fn write(&mut self, bits: u64, num: usize) {
+        debug_assert!(num <= MAX_BITS, "Writing too many bits");
+        let bits = low_bits(bits, num);
+        match self.order {
+            BitOrder::Lsb => {
+                self.acc |= bits << self.count;
+                self.count += num;
+                while self.count >= 8 {
+                    self.output.push(self.acc as u8);
+                    self.acc >>= 8;
+                    self.count -= 8;
+                }
+            }
+            BitOrder::Msb => {
+                self.acc = (self.acc << num) | bits;
+                self.count += num;
+                while self.count >= 8 {
+                    self.count -= 8;
+                    self.output.push((self.acc >> self.count) as u8);
+                }
+                self.acc = low_bits(self.acc, self.count);
+            }
+        }
+    }
+
+    /// Pad the last byte with zero bits, so the next value starts at a new
+    /// byte. Returns the number of bits that were added.
+    pub fn align(&mut self) -> usize {
+        let pad = (8 - self.count) % 8;
+        self.write(0, pad);
+        pad
+    }
+
+    /// Write the last byte, and return the number of padding bits that it
+    /// has. The output is whole bytes, so readers can't tell the padding from
+    /// the data, and formats save the number of values or an end marker.
+    pub fn finish(mut self) -> usize {
+        self.align()
+    }
+}
+
+/// Reads values of up to 'MAX_BITS' bits from a stream of bytes.
+pub struct BitReader<'a> {
+    /// The input stream.
+    input: &'a [u8],
+    /// The order of the bits in the bytes.
+    order: BitOrder,
+    /// The offset of the next byte to load.
+    cursor: usize,
+    /// The bits that were loaded and were not read.
+    acc: u64,
+    /// The number of bits in 'acc'.
+    count: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(input: &'a [u8], order: BitOrder) -> Self {
+        Self {
+            input,
+            order,
+            cursor: 0,
+            acc: 0,
+            count: 0,
+        }
+    }
+
+    /// Load bytes until 'acc' holds at least 'num' bits, or until the end of
+    /// the input.
+    fn refill(&mut self, num: usize) {
+        while self.count < num && self.cursor < self.input.len() {
+            let byte = self.input[self.cursor] as u64;
+            match self.order {
+                BitOrder::Lsb => self.acc |= byte << self.count,
+                BitOrder::Msb => self.acc = (self.acc << 8) | byte,
+            }
+            self.cursor += 1;
+            self.count += 8;
+        }
+    }
+
+    /// Read a value of 'num' bits. Returns None if the input has fewer bits
+    /// left, and then nothing is consumed.
+    pub fn read(&mut self, num: usize) -> Option<u64> {
+        debug_assert!(num <= MAX_BITS, "Reading too many bits");
+        self.refill(num);
+        if self.count < num {
+            return None;
+        }
+        self.count -= num;
+        let val = match self.order {
+            BitOrder::Lsb => {
+                let val = low_bits(self.acc, num);
+                self.acc >>= num;
+                val
+            }
+            BitOrder::Msb => {
+                let val = low_bits(self.acc >> self.count, num);
+                self.acc = low_bits(self.acc, self.count);
+                val
+            }
+        };
+        Some(val)
+    }
+
+    /// Skip the rest of the current byte, so the next value starts at a new
+    /// byte.
+    pub fn align(&mut self) {
+        let skip = self.count % 8;
+        let _ = self.read(skip);
+    }
+
+    /// Return the number of bits that are left in the input.
+    pub fn bits_left(&self) -> usize {
+        self.count + (self.input.len() - self.cursor) * 8
+    }
+
+    /// Return the number of bytes that hold the bits that were read, counting
+    /// the current byte if some of its bits were read. Formats that embed a
+    /// bit stream continue to parse their own data from here.
+    pub fn consumed(&self) -> usize {
+        self.cursor - self.count / 8
+    }
+}
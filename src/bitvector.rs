@@ -70,7 +70,7 @@ impl Bitvector {
             self.len += num;
 
             // If the free word is filled, flush it.
-            if self.len % 64 == 0 && num > 0 {
+            if self.len.is_multiple_of(64) && num > 0 {
                 self.data.push(self.last);
                 self.last = 0;
             }
@@ -131,6 +131,29 @@ impl Bitvector {
         (upper << (lower_len % 64)) | lower
     }
 
+    /// Return the words of the bitvector in the order in which the bits were
+    /// pushed, followed by a zero word. The bits that start at bit 'pos' are
+    /// found in the word 'pos / 64' and in the word after it, so values can be
+    /// read in the order of 'push_word' without popping them. See 'read_bits'.
+    pub fn to_words(&self) -> Vec<u64> {
+        let mut words = Vec::with_capacity(self.data.len() + 2);
+        words.extend(&self.data);
+        words.push(self.last);
+        words.push(0);
+        words
+    }
+
+    /// Return the 'num' bits (up to 63) that start at bit 'pos' of 'words',
+    /// which were returned by 'to_words'.
+    #[inline]
+    pub fn read_bits(words: &[u64], pos: usize, num: usize) -> u64 {
+        let offset = pos % 64;
+        let low = words[pos / 64] >> offset;
+        // Shift in two steps, because shifting by 64 bits is not allowed.
+        let high = (words[pos / 64 + 1] << 1) << (63 - offset);
+        (low | high) & ((1 << num) - 1)
+    }
+
     #[must_use]
     pub fn len(&self) -> usize {
         self.len
@@ -168,49 +191,36 @@ impl Bitvector {
     }
 
     /// Load the bit-vector from a stream of bytes. Returns the bitvector and
-    /// the number of bytes that were read.
+    /// the number of bytes that were read, or None if the input is too short
+    /// for the length field or if the free word has bits above the length.
     pub fn deserialize(input: &[u8]) -> Option<(Self, usize)> {
-        if input.len() < 4 {
-            return None;
-        }
-        // Read the length.
-        let bytes: [u8; 4] = input[0..4].try_into().unwrap();
-        let length_field = u32::from_be_bytes(bytes) as usize;
-        let input: &[u8] = &input[4..];
-
-        // Read the free word.
-        let bytes: [u8; 8] = input[0..8].try_into().unwrap();
-        let last: u64 = u64::from_be_bytes(bytes);
-        let input: &[u8] = &input[8..];
-
-        // Read the packed payload.
-        let mut payload: Vec<u64> = Vec::new();
-        let len = input.len();
-        let available_u64s = len / 8;
-
-        // Check that we have enough data in the buffer to fill the bitvector.
-        if length_field >= (1 + available_u64s) * 64 {
+        // Read the length and the free word.
+        let length_field =
+            u32::from_be_bytes(input.get(0..4)?.try_into().ok()?);
+        let length_field = length_field as usize;
+        let last = u64::from_be_bytes(input.get(4..12)?.try_into().ok()?);
+
+        // Bits beyond the bitstream are always zero.
+        if last != Self::clear_upper_bits(last, length_field % 64) {
             return None;
         }
 
-        // The unit of idx is 'u8'. We count the number of bytes that were
-        // deserialized into u8.
-        let mut idx = 0;
-        let mut len_to_read = length_field;
-        while len_to_read >= 64 {
-            let bytes: [u8; 8] = input[idx..idx + 8].try_into().unwrap();
-            payload.push(u64::from_be_bytes(bytes));
-            idx += 8;
-            len_to_read -= 64;
-        }
+        // Read the packed payload. Each full word holds 64 bits, and the
+        // remaining bits are in the free word.
+        let words = length_field / 64;
+        let packed = input.get(12..)?.get(..words * 8)?;
+        let data = packed
+            .chunks_exact(8)
+            .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+            .collect();
 
         Some((
             Bitvector {
-                data: payload,
+                data,
                 len: length_field,
                 last,
             },
-            idx + 12,
+            12 + words * 8,
         ))
     }
 }
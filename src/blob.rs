@@ -0,0 +1,109 @@
+//! A container for compressed data that can be stored as a field of other
+//! types. With the 'serde' feature the blob implements 'Serialize' and
+//! 'Deserialize', and is saved in its compressed form.
+
+use crate::full::{FullDecoder, FullEncoder};
+use crate::{Context, Decoder, Encoder};
+
+/// Holds a buffer that was compressed with the full compressor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedBlob {
+    /// The compressed stream.
+    data: Vec<u8>,
+}
+
+impl CompressedBlob {
+    /// Compress 'input' with the encoder context 'ctx'.
+    pub fn from_plain(input: &[u8], ctx: Context) -> Self {
+        let mut data = Vec::new();
+        let _ = FullEncoder::new(input, &mut data, ctx).encode();
+        Self { data }
+    }
+
+    /// Wrap the compressed stream 'data'. The stream is validated when it is
+    /// decompressed.
+    pub fn from_compressed(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    /// Return the compressed stream.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Return the compressed stream and consume the blob.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Return the size of the compressed stream.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Return True if the compressed stream is empty.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Return the decompressed content, or None if the stream is invalid.
+    pub fn decompress(&self) -> Option<Vec<u8>> {
+        let mut output = Vec::new();
+        let (read, _) = FullDecoder::new(&self.data, &mut output).decode()?;
+        if read != self.data.len() {
+            return None;
+        }
+        Some(output)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::CompressedBlob;
+    use serde::de::{Deserializer, SeqAccess, Visitor};
+    use serde::{Deserialize, Serialize, Serializer};
+    use std::fmt;
+
+    impl Serialize for CompressedBlob {
+        fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+            ser.serialize_bytes(&self.data)
+        }
+    }
+
+    /// Reads the compressed stream. Formats without a native bytes type, such
+    /// as JSON, save the bytes as a sequence of numbers.
+    struct BlobVisitor;
+
+    impl<'de> Visitor<'de> for BlobVisitor {
+        type Value = CompressedBlob;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a compressed byte array")
+        }
+
+        fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(CompressedBlob::from_compressed(v.to_vec()))
+        }
+
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(CompressedBlob::from_compressed(v))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element::<u8>()? {
+                data.push(byte);
+            }
+            Ok(CompressedBlob::from_compressed(data))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for CompressedBlob {
+        fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+            de.deserialize_byte_buf(BlobVisitor)
+        }
+    }
+}
@@ -3,40 +3,295 @@
 //! and entropy encoding.
 
 use crate::bitvector::Bitvector;
-use crate::coding::entropy::{EntropyDecoder, EntropyEncoder};
-use crate::lz::matcher::select_matcher;
+use crate::coding::entropy::{
+    read_histogram, EntropyDecoder, EntropyEncoder, EntropyTables,
+};
+use crate::coding::hist::{normalize_to_total_sum, Histogram};
+use crate::coding::literal::{detect_alignment, MAX_ALIGNMENT};
+use crate::coding::literal::{LiteralDecoder, LiteralEncoder};
+use crate::coding::residual::{ResidualDecoder, ResidualEncoder};
+use crate::limits::check_output;
+use crate::lz::lz4::compress_bound;
+use crate::lz::matcher::{select_long_matcher, select_matcher_with};
+use crate::lz::{copy_match, LZ4Decoder, LZ4Encoder};
 use crate::nop::{NopDecoder, NopEncoder};
-use crate::pager::{
-    DecodeHandlerTy, EncodeHandlerTy, PagerDecoder, PagerEncoder,
+use crate::pager::{DecodeHandlerTy, EncodeHandlerTy, PagerDecoder};
+use crate::pager::{PagerEncoder, TableDecodeHandlerTy, TableEncodeHandlerTy};
+use crate::pipeline::Pipeline;
+use crate::rle;
+use crate::utils::leb128;
+use crate::utils::signatures::MATCHED_LIT_SIG;
+use crate::utils::signatures::{match_signature, BLOCK_SIG, SMALL_BLOCK_SIG};
+use crate::utils::signatures::{
+    FAST_BLOCK_SIG, LONG_BLOCK_SIG, OFFSET_CTX_SIG,
+};
+use crate::utils::signatures::{
+    PIPELINE_BLOCK_SIG, RESIDUAL_SIG, REUSED_TABLE_SIG, RLE_BLOCK_SIG,
 };
-use crate::utils::signatures::{match_signature, BLOCK_SIG};
 
 use crate::utils::array_encoding::decode as decode_arr;
 use crate::utils::array_encoding::encode as encode_arr;
 
+use crate::utils::stream_vbyte;
 use crate::utils::two_stream_encoding;
-use crate::utils::variable_length_encoding::decode_array32 as decode_vl32;
-use crate::utils::variable_length_encoding::encode_array32 as encode_vl32;
+use crate::utils::unchecked;
+use crate::utils::variable_length_encoding;
+use crate::utils::variable_length_encoding::decode as decode_vl;
+use crate::utils::variable_length_encoding::encode as encode_vl;
 
-use crate::{Context, Decoder, Encoder};
+use crate::scratch::{recycle_u32, recycle_u8, take_u32, take_u8};
+use crate::{restore_on_error, Context, Decoder, Encoder};
+use std::cell::RefCell;
 
 /// This is the maximum number of length bits that we allow for offsets. (1<<X)
 /// This is also the number of symbols that we use to encode tokens.
-const OFFSET_BITS: usize = 24;
+pub const OFFSET_BITS: usize = 24;
+
+/// The number of offset bits and token symbols of blocks with long offsets.
+pub const LONG_OFFSET_BITS: usize = 32;
+
+/// The range of the sizes of the entropy pages. See 'entropy_page_size'.
+pub const MIN_ENTROPY_PAGE_SIZE: usize = 1 << 14;
+pub const MAX_ENTROPY_PAGE_SIZE: usize = 1 << 20;
+
+/// The estimated number of bits that the table of an entropy page spends on
+/// each symbol that the page uses.
+const TABLE_BITS_PER_SYMBOL: f64 = 8.;
+
+/// Blocks up to this size are encoded with the compact small-block encoding.
+const SMALL_BLOCK_LIMIT: usize = 2048;
+
+/// The size of the table of the predefined entropy coder of small blocks.
+const SMALL_TABLE_SIZE: usize = 512;
+
+/// The encodings of the payload of small blocks.
+const SMALL_RAW: u8 = 0;
+const SMALL_ENTROPY: u8 = 1;
+/// The mode of raw records that use the regular block encoding.
+const RAW_BLOCK: u8 = 2;
+const LONG_RAW_BLOCK: u8 = 3;
+
+/// Lengths that don't fit in the 4-bit fields of a sequence token are saved as
+/// this value, followed by the remainder.
+const NIBBLE_ESCAPE: u32 = 15;
+
+/// The fastest level, which saves the matches of a greedy matcher in the LZ4
+/// format, without lazy parsing and without entropy coding.
+pub const FAST_LEVEL: u8 = 0;
+
+/// The encodings of the remainders of the sequence stream.
+const EXTRA_VARINT: u8 = 0;
+const EXTRA_VBYTE: u8 = 1;
+
+/// The codecs of the byte streams of regular blocks: the literals, the
+/// sequence tokens, the remainders of the sequences and the offset tokens.
+/// Each stream is coded with the entropy coder of the stream, saved raw, or
+/// saved as a run of one byte. The codecs of the four streams are saved in the
+/// two-bit fields of the selector byte that follows the block signature, with
+/// the literals in the low bits. See 'select_codec'.
+pub const CODEC_ENTROPY: u8 = 0;
+pub const CODEC_RAW: u8 = 1;
+pub const CODEC_RLE: u8 = 2;
+/// The remainders of the sequences are coded with the entropy coder, and the
+/// first page may reuse the last table of the sequence tokens instead of
+/// saving a table of its own. See 'select_shared_codec'. Only these two
+/// streams share tables: the literals are coded with split tables or with the
+/// match byte model, and the offset tokens have an alphabet of their own.
+pub const CODEC_SHARED: u8 = 3;
+
+/// Return the codec of the stream 'index' in the selector byte 'selector'.
+pub fn stream_codec(selector: u8, index: usize) -> u8 {
+    (selector >> (index * 2)) & 3
+}
+
+/// Return the smallest coding of the stream 'input' and its codec: 'coded',
+/// which is the output of the entropy coder of the stream, the raw bytes, or
+/// the length of the run if all of the bytes are the same.
+fn select_codec(input: &[u8], coded: Vec<u8>) -> (u8, Vec<u8>) {
+    let mut other = take_u8();
+    let codec = match input.first() {
+        Some(&first) if input.iter().all(|&b| b == first) => {
+            leb128::encode(input.len() as u64, &mut other);
+            other.push(first);
+            CODEC_RLE
+        }
+        _ => {
+            encode_arr(input, &mut other);
+            CODEC_RAW
+        }
+    };
+    if other.len() < coded.len() {
+        recycle_u8(coded);
+        return (codec, other);
+    }
+    recycle_u8(other);
+    (CODEC_ENTROPY, coded)
+}
+
+/// Decode the stream at the start of 'input' that was coded with the codec
+/// 'codec'. Streams of the entropy codec are decoded with 'callback'. Returns
+/// the number of bytes read and the stream.
+fn decode_codec(
+    codec: u8,
+    input: &[u8],
+    callback: DecodeHandlerTy,
+) -> Option<(usize, Vec<u8>)> {
+    match codec {
+        CODEC_ENTROPY => decode_paged_ent(input, callback),
+        _ => decode_stored(codec, input),
+    }
+}
+
+/// Decode the stream at the start of 'input' that was saved with the raw or
+/// the RLE codec 'codec'. Returns the number of bytes read and the stream.
+fn decode_stored(codec: u8, input: &[u8]) -> Option<(usize, Vec<u8>)> {
+    match codec {
+        CODEC_RAW => {
+            let (read, len) = leb128::decode_len(input)?;
+            let bytes = input.get(read..)?.get(..len)?;
+            let mut decoded = take_u8();
+            decoded.extend(bytes);
+            Some((read + len, decoded))
+        }
+        CODEC_RLE => {
+            let (read, len) = leb128::decode_len(input)?;
+            let byte = *input.get(read)?;
+            let mut decoded = take_u8();
+            decoded.resize(check_output(len)?, byte);
+            Some((read + 1, decoded))
+        }
+        _ => None,
+    }
+}
+
+/// Decode a stream of the codec 'codec' that must fill all of 'input'.
+fn decode_codec_exact(
+    codec: u8,
+    input: &[u8],
+    callback: DecodeHandlerTy,
+) -> Option<Vec<u8>> {
+    let (read, decoded) = decode_codec(codec, input, callback)?;
+    if read != input.len() {
+        recycle_u8(decoded);
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Decode a stream of the codec 'codec' that must fill all of 'input', where
+/// the pages of the entropy codecs were encoded with 'select_ent'. Streams of
+/// the shared codec start with the table 'table'. Returns the stream and the
+/// last table of the stream.
+fn decode_shared_codec_exact(
+    codec: u8,
+    input: &[u8],
+    table: Option<Vec<u32>>,
+) -> Option<(Vec<u8>, Option<Vec<u32>>)> {
+    let (read, decoded, table) = match codec {
+        CODEC_ENTROPY => decode_paged_ent_with(input, decode_ent_or_nop, None)?,
+        CODEC_SHARED => {
+            decode_paged_ent_with(input, decode_ent_or_nop, Some(table?))?
+        }
+        _ => {
+            let (read, decoded) = decode_stored(codec, input)?;
+            (read, decoded, None)
+        }
+    };
+    if read != input.len() {
+        recycle_u8(decoded);
+        return None;
+    }
+    Some((decoded, table))
+}
+
+/// The number of contexts of the offset tokens. See 'offset_context'.
+pub const OFFSET_CONTEXTS: usize = 9;
+
+/// Return the context of the offset token that follows the tokens 'prev1'
+/// and 'prev2', where 'prev1' is the last token. Offsets are autocorrelated:
+/// repeated offsets tend to follow repeated offsets, and near matches tend to
+/// follow near matches.
+fn offset_context(prev1: u8, prev2: u8) -> usize {
+    offset_class(prev1) * 3 + offset_class(prev2)
+}
+
+/// Return the class of the offset token 'token': short offsets, medium
+/// offsets or long offsets.
+fn offset_class(token: u8) -> usize {
+    match token {
+        0..=1 => 0,
+        2..=9 => 1,
+        _ => 2,
+    }
+}
+
+/// Encode the offset tokens 'tokens' in one stream for each context of the
+/// previous two tokens, so that each context gets its own entropy table.
+fn encode_offset_contexts<const SYMS: usize>(
+    tokens: &[u8],
+    ctx: Context,
+) -> Vec<u8> {
+    let mut streams = vec![Vec::new(); OFFSET_CONTEXTS];
+    let (mut prev1, mut prev2) = (0, 0);
+    for &token in tokens {
+        streams[offset_context(prev1, prev2)].push(token);
+        (prev1, prev2) = (token, prev1);
+    }
+    let mut encoded = OFFSET_CTX_SIG.to_vec();
+    for stream in streams {
+        let res = encode_paged_ent(&stream, ctx, encode_offset_entropy::<SYMS>);
+        encode_arr(&res, &mut encoded);
+        recycle_u8(res);
+    }
+    encoded
+}
+
+/// Decode the tokens that were encoded with 'encode_offset_contexts'. Returns
+/// the number of bytes read and the tokens, in order.
+fn decode_offset_contexts<const SYMS: usize>(
+    input: &[u8],
+) -> Option<(usize, Vec<u8>)> {
+    let mut read = OFFSET_CTX_SIG.len();
+    let mut streams = Vec::with_capacity(OFFSET_CONTEXTS);
+    for _ in 0..OFFSET_CONTEXTS {
+        let mut stream = take_u8();
+        read += decode_arr(&input[read..], &mut stream)?;
+        streams.push(decode_paged_ent_exact(
+            &stream,
+            decode_offset_entropy::<SYMS>,
+        )?);
+        recycle_u8(stream);
+    }
 
-/// Selects the size of each entropy unit.
-const ENTROPY_PAGE_SIZE: usize = 1 << 18;
+    // Merge the streams in the order of the contexts. Each token is taken
+    // from the stream of its context, so all of the tokens are used.
+    let total = streams.iter().map(|s| s.len()).sum();
+    let mut cursors = [0; OFFSET_CONTEXTS];
+    let mut tokens = take_u8();
+    let (mut prev1, mut prev2) = (0, 0);
+    for _ in 0..total {
+        let context = offset_context(prev1, prev2);
+        let token = *streams[context].get(cursors[context])?;
+        cursors[context] += 1;
+        tokens.push(token);
+        (prev1, prev2) = (token, prev1);
+    }
+    for stream in streams {
+        recycle_u8(stream);
+    }
+    Some((read, tokens))
+}
 
 /// Encode a list of offsets, with a histogram that favors short indices, into
 /// two streams: tokens and extra bits. The tokens are compressed with fse, and
 /// the extra bits are encoded into a bitstream. See 'two_stream_encoding' for
-/// details.
+/// details. Returns the codec of the tokens and the encoded stream.
 pub fn encode_offset_stream<const SYMS: usize>(
     input: &[u32],
     ctx: Context,
-) -> Vec<u8> {
+) -> (u8, Vec<u8>) {
     let mut bv = Bitvector::new();
-    let mut tokens = Vec::new();
+    let mut tokens = take_u8();
     let mut encoded = Vec::new();
 
     // Split the offsets into two streams: tokens and bitvector.
@@ -44,63 +299,524 @@ pub fn encode_offset_stream<const SYMS: usize>(
         tokens.push(two_stream_encoding::encode32(*val, &mut bv) as u8);
     }
 
+    // Keep the tokens in one stream, or split them by their context.
     let res = encode_paged_ent(&tokens, ctx, encode_offset_entropy::<SYMS>);
-    encoded.extend(res);
+    let split = encode_offset_contexts::<SYMS>(&tokens, ctx);
+    let (res, split) = if split.len() < res.len() {
+        (split, res)
+    } else {
+        (res, split)
+    };
+    let (codec, res) = select_codec(&tokens, res);
+    encoded.extend(&res);
+    for buffer in [res, split, tokens] {
+        recycle_u8(buffer);
+    }
 
     // Append the bitstream after the tokens.
     let _ = bv.serialize(&mut encoded);
-    encoded
+    (codec, encoded)
 }
 
-/// Decode the list of offsets that were encoded with 'encode_offset_stream'.
+/// Decode the list of offsets that were encoded with 'encode_offset_stream',
+/// with the tokens of the codec 'codec'.
 pub fn decode_offset_stream<const SYMS: usize>(
     input: &[u8],
+    codec: u8,
 ) -> Option<Vec<u32>> {
-    let (read, tokens) =
-        decode_paged_ent(input, decode_offset_entropy::<SYMS>)?;
+    let entropy = codec == CODEC_ENTROPY;
+    let (read, tokens) = if entropy && match_signature(input, &OFFSET_CTX_SIG) {
+        decode_offset_contexts::<SYMS>(input)?
+    } else {
+        decode_codec(codec, input, decode_offset_entropy::<SYMS>)?
+    };
 
-    let (mut bv, bv_read) = Bitvector::deserialize(&input[read..])?;
+    let (bv, bv_read) = Bitvector::deserialize(&input[read..])?;
     // Check that all of the data was read.
     if read + bv_read != input.len() {
         return None;
     }
 
-    let mut res: Vec<u32> = Vec::new();
+    let mut res: Vec<u32> = take_u32();
+    two_stream_encoding::decode_tokens(&tokens, &bv, &mut res)?;
+    recycle_u8(tokens);
+    Some(res)
+}
 
-    // We need to process the values in reverse, because the bits are
-    // stored in the bitvector in reverse.
-    for tok in tokens.iter().rev() {
-        res.push(two_stream_encoding::decode32(*tok as u32, &mut bv));
+/// Read the header of the literals 'literals' that are coded in the context of
+/// the match byte (see 'MATCHED_LIT_SIG'). Returns the size of the header, the
+/// number of literals and the alignment of the block.
+pub fn read_matched_header(literals: &[u8]) -> Option<(usize, usize, usize)> {
+    if !match_signature(literals, &MATCHED_LIT_SIG) {
+        return None;
     }
-    res.reverse();
-    Some(res)
+    let start = MATCHED_LIT_SIG.len();
+    let (read, count) = leb128::decode_len(&literals[start..])?;
+    let align = *literals.get(start + read)? as usize;
+    if !align.is_power_of_two() || align > MAX_ALIGNMENT {
+        return None;
+    }
+    Some((start + read + 1, count, align))
+}
+
+/// Return the position of the match of each sequence modulo 'align', which is
+/// a power of two. Only the low bits of the positions are needed, so the sums
+/// may wrap.
+pub fn match_lanes(
+    lit_lens: &[u32],
+    mat_lens: &[u32],
+    align: usize,
+) -> Vec<u8> {
+    let mut pos: usize = 0;
+    let mut lanes = Vec::with_capacity(lit_lens.len());
+    for (lit_len, mat_len) in lit_lens.iter().zip(mat_lens) {
+        pos = pos.wrapping_add(*lit_len as usize);
+        lanes.push((pos & (align - 1)) as u8);
+        pos = pos.wrapping_add(*mat_len as usize);
+    }
+    lanes
+}
+
+/// Encode the offset tokens 'tokens' of a block, which include the bias of 3
+/// for the previous offsets, with 'encode_offset_stream'. The offsets of
+/// blocks that are aligned to 'align' bytes are mostly multiples of the
+/// alignment, so their low bits are saved before the stream, in one stream for
+/// each position of the match modulo the alignment (see 'match_lanes'), and
+/// the rest of the offset is saved in the token. Returns the codec of the
+/// tokens and the encoded stream.
+pub fn encode_match_offsets(
+    tokens: &[u32],
+    long: bool,
+    lanes: &[u8],
+    align: usize,
+    ctx: Context,
+) -> (u8, Vec<u8>) {
+    let mut encoded = Vec::new();
+    let mut high: Vec<u32> = take_u32();
+    let mut low_bits = vec![Vec::new(); align];
+    let shift = align.trailing_zeros();
+    for (i, &token) in tokens.iter().enumerate() {
+        if align == 1 || token < 3 {
+            high.push(token);
+            continue;
+        }
+        let offset = token - 3;
+        low_bits[lanes[i] as usize].push((offset as usize & (align - 1)) as u8);
+        high.push((offset >> shift) + 3);
+    }
+    if align > 1 {
+        for lane in low_bits {
+            let coded =
+                encode_paged_ent(&lane, ctx, encode_offset_entropy::<8>);
+            let (codec, coded) = select_codec(&lane, coded);
+            encoded.push(codec);
+            encode_arr(&coded, &mut encoded);
+            recycle_u8(coded);
+        }
+    }
+
+    let (codec, stream) = if long {
+        encode_offset_stream::<LONG_OFFSET_BITS>(&high, ctx)
+    } else {
+        encode_offset_stream::<OFFSET_BITS>(&high, ctx)
+    };
+    encoded.extend(&stream);
+    recycle_u8(stream);
+    recycle_u32(high);
+    (codec, encoded)
+}
+
+/// Read the streams of the low bits of the offsets of a block that is aligned
+/// to 'align' bytes, one for each lane. Returns the number of bytes read and
+/// the streams. See 'encode_match_offsets'.
+pub fn read_offset_lanes(
+    input: &[u8],
+    align: usize,
+) -> Option<(usize, Vec<Vec<u8>>)> {
+    let mut read = 0;
+    let mut lanes = Vec::new();
+    if align == 1 {
+        return Some((read, lanes));
+    }
+    for _ in 0..align {
+        let codec = *input.get(read)?;
+        let mut stream = take_u8();
+        read += 1 + decode_arr(input.get(read + 1..)?, &mut stream)?;
+        let lane =
+            decode_codec_exact(codec, &stream, decode_offset_entropy::<8>);
+        recycle_u8(stream);
+        let lane = lane?;
+        if lane.iter().any(|low| *low as usize >= align) {
+            return None;
+        }
+        lanes.push(lane);
+    }
+    Some((read, lanes))
+}
+
+/// Decode the offset stream of a block, with tokens of the codec 'codec',
+/// into the distances of the matches. Blocks with 'long' offsets have more
+/// offset tokens. The low bits of the offsets of blocks that are aligned to
+/// 'align' bytes are read from the stream of the lane of each match in
+/// 'lanes'. See 'encode_match_offsets'.
+pub fn decode_match_offsets(
+    input: &[u8],
+    long: bool,
+    codec: u8,
+    lanes: &[u8],
+    align: usize,
+) -> Option<Vec<u32>> {
+    let (read, low_bits) = read_offset_lanes(input, align)?;
+    let input = &input[read..];
+    let mut tokens = if long {
+        decode_offset_stream::<LONG_OFFSET_BITS>(input, codec)?
+    } else {
+        decode_offset_stream::<OFFSET_BITS>(input, codec)?
+    };
+
+    // Put the low bits back into the offsets. All of them must be used.
+    if align > 1 {
+        if lanes.len() != tokens.len() {
+            recycle_u32(tokens);
+            return None;
+        }
+        let mut cursors = vec![0; align];
+        for (token, lane) in tokens.iter_mut().zip(lanes) {
+            if *token < 3 {
+                continue;
+            }
+            let lane = *lane as usize;
+            let low = *low_bits.get(lane)?.get(cursors[lane])? as u32;
+            cursors[lane] += 1;
+            let high = (*token - 3).checked_mul(align as u32)?;
+            *token = (high | low).checked_add(3)?;
+        }
+        if cursors
+            .iter()
+            .zip(&low_bits)
+            .any(|(c, lane)| *c != lane.len())
+        {
+            recycle_u32(tokens);
+            return None;
+        }
+    }
+
+    let mut offsets: Vec<u32> = take_u32();
+
+    // Decode the offsets. Zero means that we need to use the previous
+    // offset.
+    let mut prev_off1 = 0;
+    let mut prev_off2 = 0;
+    let mut prev_off3 = 0;
+
+    // Decode the offset (the first 3 values refer to previous offsets).
+    for &offset in &tokens {
+        let off = match offset {
+            0 => prev_off1,
+            1 => prev_off2,
+            2 => prev_off3,
+            _ => offset,
+        };
+        prev_off3 = prev_off2;
+        prev_off2 = prev_off1;
+        prev_off1 = offset;
+        offsets.push(off.checked_sub(3)?);
+    }
+    recycle_u32(tokens);
+    Some(offsets)
+}
+
+/// Encode a length into the 4-bit field of a sequence token. Lengths that
+/// don't fit in the field are saturated, and the remainder is appended to
+/// 'extra'.
+fn encode_length_nibble(val: u32, extra: &mut Vec<u32>) -> u8 {
+    if val < NIBBLE_ESCAPE {
+        return val as u8;
+    }
+    extra.push(val - NIBBLE_ESCAPE);
+    NIBBLE_ESCAPE as u8
+}
+
+/// Decode a length from the 4-bit field 'nibble' of a sequence token, and from
+/// the next remainder in 'extra'.
+fn decode_length_nibble(
+    nibble: u8,
+    extra: &mut impl Iterator<Item = u32>,
+) -> Option<u32> {
+    let val = nibble as u32;
+    if val < NIBBLE_ESCAPE {
+        return Some(val);
+    }
+    val.checked_add(extra.next()?)
+}
+
+/// Return the smallest coding of the stream 'input' and its codec, like
+/// 'select_codec'. The entropy pages start with the table 'table' of the
+/// stream before it, which the first page reuses only if the histograms are
+/// compatible (see 'ent_or_nop'), so the stream is coded once. This saves the
+/// table of short streams.
+fn select_shared_codec(
+    input: &[u8],
+    ctx: Context,
+    table: Option<&[u32]>,
+) -> (u8, Vec<u8>) {
+    let shared = table.is_some();
+    let table = table.map(<[u32]>::to_vec);
+    let (coded, _) = encode_paged_ent_with(input, ctx, select_ent(ctx), table);
+    let (codec, coded) = select_codec(input, coded);
+    if shared && codec == CODEC_ENTROPY {
+        return (CODEC_SHARED, coded);
+    }
+    (codec, coded)
+}
+
+/// Encode the remainders 'extra' of the sequence stream, and return the
+/// encoding mode, the codec and the coded stream. The byte-unary varints
+/// compress well when the remainders are short, and StreamVByte is more
+/// compact and faster to decode when they are long, so the smaller of the two
+/// is kept. The entropy pages may share the table 'table' of the tokens.
+fn encode_extra_stream(
+    extra: &[u32],
+    ctx: Context,
+    table: Option<&[u32]>,
+) -> (u8, u8, Vec<u8>) {
+    let mut varints = take_u8();
+    for val in extra {
+        encode_vl(*val, &mut varints);
+    }
+    let mut vbytes = take_u8();
+    stream_vbyte::encode(extra, &mut vbytes);
+
+    let varint_stream = select_shared_codec(&varints, ctx, table);
+    let vbyte_stream = select_shared_codec(&vbytes, ctx, table);
+    recycle_u8(varints);
+    recycle_u8(vbytes);
+    if vbyte_stream.1.len() < varint_stream.1.len() {
+        recycle_u8(varint_stream.1);
+        return (EXTRA_VBYTE, vbyte_stream.0, vbyte_stream.1);
+    }
+    recycle_u8(vbyte_stream.1);
+    (EXTRA_VARINT, varint_stream.0, varint_stream.1)
+}
+
+/// Decode the remainders that were encoded with 'encode_extra_stream' in the
+/// mode 'mode' and the codec 'codec'. Streams of the shared codec start with
+/// the last table 'table' of the tokens.
+fn decode_extra_stream(
+    mode: u8,
+    codec: u8,
+    input: &[u8],
+    table: Option<Vec<u32>>,
+) -> Option<Vec<u32>> {
+    let (bytes, _) = decode_shared_codec_exact(codec, input, table)?;
+    let mut extra: Vec<u32> = take_u32();
+    match mode {
+        EXTRA_VARINT => {
+            variable_length_encoding::decode_all(&bytes, &mut extra)?
+        }
+        EXTRA_VBYTE => {
+            if stream_vbyte::decode(&bytes, &mut extra)? != bytes.len() {
+                return None;
+            }
+        }
+        _ => return None,
+    }
+    recycle_u8(bytes);
+    Some(extra)
 }
 
-// Perform entropy encoding on an input with valid tokens.
+/// Encode the literal lengths and the match lengths of a list of sequences.
+/// The two lengths of each sequence are fused into a single token, with the
+/// literal length in the high nibble and the match length in the low nibble,
+/// like the LZ4 token. The tokens are compressed in one entropy pass, which
+/// captures the correlation between the two lengths. The remainders of long
+/// lengths are rare, and are kept in a separate stream so that they don't
+/// disturb the histogram of the tokens. Returns the codecs of the tokens and
+/// of the remainders, in the low bits of a selector (see 'stream_codec'), and
+/// the encoded stream.
+pub fn encode_sequence_stream(
+    lit_lens: &[u32],
+    mat_lens: &[u32],
+    ctx: Context,
+) -> (u8, Vec<u8>) {
+    assert_eq!(lit_lens.len(), mat_lens.len(), "Invalid sequence list");
+    let mut tokens = take_u8();
+    let mut extra = take_u32();
+
+    for (lit_len, mat_len) in lit_lens.iter().zip(mat_lens.iter()) {
+        let high = encode_length_nibble(*lit_len, &mut extra);
+        let low = encode_length_nibble(*mat_len, &mut extra);
+        tokens.push((high << 4) | low);
+    }
+
+    let (token_stream, table) =
+        encode_paged_ent_with(&tokens, ctx, select_ent(ctx), None);
+    let (token_codec, token_stream) = select_codec(&tokens, token_stream);
+    // The remainders may share the table of the tokens, if the tokens are
+    // saved with the entropy coder.
+    let table = table.filter(|_| token_codec == CODEC_ENTROPY);
+    let (mode, extra_codec, extra_stream) =
+        encode_extra_stream(&extra, ctx, table.as_deref());
+    let mut encoded = Vec::new();
+    encode_arr(&token_stream, &mut encoded);
+    encoded.push(mode);
+    encode_arr(&extra_stream, &mut encoded);
+    for buffer in [tokens, token_stream, extra_stream] {
+        recycle_u8(buffer);
+    }
+    recycle_u32(extra);
+    (token_codec | (extra_codec << 2), encoded)
+}
+
+/// Decode the sequences that were encoded with 'encode_sequence_stream', with
+/// the codecs 'codecs'. Returns the literal lengths and the match lengths.
+pub fn decode_sequence_stream(
+    input: &[u8],
+    codecs: u8,
+) -> Option<(Vec<u32>, Vec<u32>)> {
+    let mut token_stream: Vec<u8> = take_u8();
+    let mut extra_stream: Vec<u8> = take_u8();
+    let mut read = decode_arr(input, &mut token_stream)?;
+    let mode = *input.get(read)?;
+    read += 1;
+    read += decode_arr(&input[read..], &mut extra_stream)?;
+    // Check that all of the data was read.
+    if read != input.len() {
+        return None;
+    }
+    let token_codec = stream_codec(codecs, 0);
+    // The tokens don't have a table to share.
+    let (tokens, table) =
+        decode_shared_codec_exact(token_codec, &token_stream, None)?;
+    let extra = decode_extra_stream(
+        mode,
+        stream_codec(codecs, 1),
+        &extra_stream,
+        table,
+    )?;
+
+    let mut lit_lens: Vec<u32> = take_u32();
+    let mut mat_lens: Vec<u32> = take_u32();
+
+    let mut remainders = extra.iter().copied();
+    for tok in &tokens {
+        lit_lens.push(decode_length_nibble(tok >> 4, &mut remainders)?);
+        mat_lens.push(decode_length_nibble(tok & 0xf, &mut remainders)?);
+    }
+
+    for buffer in [token_stream, extra_stream, tokens] {
+        recycle_u8(buffer);
+    }
+    recycle_u32(extra);
+    Some((lit_lens, mat_lens))
+}
+
+// Perform entropy encoding on the list of tokens. If some of the tokens are
+// outside of the alphabet then fall back to nop encoding.
 fn encode_offset_entropy<const SYMS: usize>(
     input: &[u8],
     ctx: Context,
 ) -> Vec<u8> {
-    let mut coded: Vec<u8> = Vec::new();
-    let _ = EntropyEncoder::<SYMS, 4096>::new(input, &mut coded, ctx).encode();
+    let mut coded: Vec<u8> = take_u8();
+    let mut encoder = EntropyEncoder::<SYMS, 4096>::new(input, &mut coded, ctx);
+    if encoder.try_encode().is_some() {
+        return coded;
+    }
+    assert_eq!(coded.len(), 0);
+    let _ = NopEncoder::new(input, &mut coded, ctx).encode();
     coded
 }
 
-// Decode the entropy encoding of a list of valid tokens.
+// Decode the entropy (or nop) encoding of a list of tokens.
 fn decode_offset_entropy<const SYMS: usize>(
     input: &[u8],
 ) -> Option<(usize, Vec<u8>)> {
-    let mut decoded: Vec<u8> = Vec::new();
+    let mut decoded: Vec<u8> = take_u8();
     let mut decoder = EntropyDecoder::<SYMS, 4096>::new(input, &mut decoded);
-    let (read, _) = decoder.decode()?;
-    Some((read, decoded))
+    if let Some((read, _)) = decoder.decode() {
+        return Some((read, decoded));
+    }
+
+    decoded.clear();
+    if let Some((read, _)) = NopDecoder::new(input, &mut decoded).decode() {
+        return Some((read, decoded));
+    }
+
+    None
+}
+
+/// Return the table 'table' of the previous entropy page if it can encode
+/// 'input',
+/// and the normalized histogram of 'input' is close enough to the table. The
+/// distance between the histograms is the number of bits that coding 'input'
+/// with the table adds, which must not exceed the estimated cost of saving a
+/// new table (see 'TABLE_BITS_PER_SYMBOL'). Homogeneous streams then save one
+/// table for many pages.
+fn reusable_page_table<'t>(
+    input: &[u8],
+    table: &'t Option<Vec<u32>>,
+) -> Option<&'t [u32]> {
+    let table = table.as_deref()?;
+    if input.is_empty() {
+        return None;
+    }
+    let mut hist = Histogram::<256>::from_data(input);
+    let counts = *hist.get_bins();
+    hist.normalize(4096);
+    let (mut extra_bits, mut table_bits) = (0., 0.);
+    let norm_counts = hist.get_bins().iter().zip(table);
+    for (count, (norm, prev)) in counts.iter().zip(norm_counts) {
+        if *count == 0 {
+            continue;
+        }
+        if *prev == 0 {
+            return None;
+        }
+        extra_bits += *count as f64 * (*norm as f64 / *prev as f64).log2();
+        table_bits += TABLE_BITS_PER_SYMBOL;
+    }
+    (extra_bits <= table_bits).then_some(table)
 }
 
-//. Try to perform entropy encoding, but if it fails use nop encoding.
-fn ent_or_nop(input: &[u8], ctx: Context) -> Vec<u8> {
-    let mut encoded: Vec<u8> = Vec::new();
+/// Try to perform entropy encoding, but if it fails use nop encoding. Pages
+/// with a histogram that is close to the table 'table' of the previous entropy
+/// page start with 'REUSED_TABLE_SIG', and are encoded with that table, which
+/// is not saved again. Pages that save a new table replace 'table'.
+fn ent_or_nop(
+    input: &[u8],
+    ctx: Context,
+    table: &mut Option<Vec<u32>>,
+) -> Vec<u8> {
+    let mut encoded: Vec<u8> = take_u8();
     type EncoderTy<'a> = EntropyEncoder<'a, 256, 4096>;
+    if let Some(prev) = reusable_page_table(input, table) {
+        encoded.extend(REUSED_TABLE_SIG);
+        let mut encoder = EncoderTy::new(input, &mut encoded, ctx);
+        if let Some(size) = encoder.try_encode_with_table(prev) {
+            if REUSED_TABLE_SIG.len() + size < input.len() {
+                return encoded;
+            }
+        }
+        encoded.clear();
+    }
+
     let new_size = EncoderTy::new(input, &mut encoded, ctx).encode();
+    // Streams that happen to start with the signature of the pages that
+    // reuse the table would be decoded as such pages.
+    if new_size < input.len() && !match_signature(&encoded, &REUSED_TABLE_SIG) {
+        *table = read_histogram::<256, 4096>(&encoded);
+        return encoded;
+    }
+    encoded.clear();
+    let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+    encoded
+}
+
+/// Encode the input with the context-mixing coder, or use nop encoding if it's
+/// not useful.
+fn residual_or_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+    let mut encoded: Vec<u8> = take_u8();
+    let new_size = ResidualEncoder::new(input, &mut encoded, ctx).encode();
 
     if new_size < input.len() {
         return encoded;
@@ -110,12 +826,82 @@ fn ent_or_nop(input: &[u8], ctx: Context) -> Vec<u8> {
     encoded
 }
 
-/// Try to perform entropy encoding, but if it fails use nop encoding.
-fn decode_ent_or_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
-    let mut decoded: Vec<u8> = Vec::new();
+/// Decode a page that was encoded with 'residual_or_nop'.
+fn decode_residual(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let mut decoded: Vec<u8> = take_u8();
+    let (read, _) = ResidualDecoder::new(input, &mut decoded).decode()?;
+    Some((read, decoded))
+}
+
+/// Return the handler that encodes the pages of the token streams with the
+/// settings of 'ctx'. See 'Context::with_hybrid'.
+fn select_ent(ctx: Context) -> TableEncodeHandlerTy {
+    if ctx.hybrid {
+        // The residual pages don't save tables.
+        return |input, ctx, _| residual_or_nop(input, ctx);
+    }
+    ent_or_nop
+}
+
+/// Encode the literals with the split entropy encoding, which allows faster
+/// decoding, or use nop encoding if it's not useful.
+fn split_ent_or_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+    let mut encoded: Vec<u8> = take_u8();
+    type EncoderTy<'a> = EntropyEncoder<'a, 256, 4096>;
+    let mut encoder = EncoderTy::new(input, &mut encoded, ctx);
+    let new_size = encoder.try_encode_split().unwrap();
+
+    if new_size < input.len() {
+        return encoded;
+    }
+    encoded.clear();
+    let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+    encoded
+}
+
+/// Decode the literals that were encoded with 'split_ent_or_nop'.
+fn decode_split_ent_or_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+    if match_signature(input, &RESIDUAL_SIG) {
+        return decode_residual(input);
+    }
+    let mut decoded: Vec<u8> = take_u8();
 
     type DecoderTy<'a> = EntropyDecoder<'a, 256, 4096>;
+    if let Some((read, _)) = DecoderTy::new(input, &mut decoded).decode_split()
+    {
+        return Some((read, decoded));
+    }
+
+    decoded.clear();
+    if let Some((read, _)) = NopDecoder::new(input, &mut decoded).decode() {
+        return Some((read, decoded));
+    }
+
+    None
+}
+
+/// Decode a page that was encoded with 'ent_or_nop', where 'table' is the
+/// table of the previous entropy page.
+fn decode_ent_or_nop(
+    input: &[u8],
+    table: &mut Option<Vec<u32>>,
+) -> Option<(usize, Vec<u8>)> {
+    if match_signature(input, &RESIDUAL_SIG) {
+        return decode_residual(input);
+    }
+    let mut decoded: Vec<u8> = take_u8();
+
+    type DecoderTy<'a> = EntropyDecoder<'a, 256, 4096>;
+    if match_signature(input, &REUSED_TABLE_SIG) {
+        let prev = table.as_deref()?;
+        let stream = &input[REUSED_TABLE_SIG.len()..];
+        let mut decoder = DecoderTy::new(stream, &mut decoded);
+        let (read, _) = decoder.decode_with_table(prev)?;
+        return Some((REUSED_TABLE_SIG.len() + read, decoded));
+    }
+
     if let Some((read, _)) = DecoderTy::new(input, &mut decoded).decode() {
+        *table = read_histogram::<256, 4096>(input);
         return Some((read, decoded));
     }
 
@@ -127,30 +913,307 @@ fn decode_ent_or_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
     None
 }
 
+/// Return the estimated number of bits that an entropy page with the
+/// histogram 'hist' takes, with its table.
+fn entropy_page_cost(hist: &[u32; 256]) -> f64 {
+    let total = hist.iter().sum::<u32>() as f64;
+    let mut bits = 0.;
+    for &count in hist.iter().filter(|&&count| count > 0) {
+        let count = count as f64;
+        bits += TABLE_BITS_PER_SYMBOL - count * (count / total).log2();
+    }
+    bits
+}
+
+/// Return the size of the entropy pages of the stream 'input'. Each power of
+/// two between 'MIN_ENTROPY_PAGE_SIZE' and 'MAX_ENTROPY_PAGE_SIZE' is scored
+/// by the estimated size of the stream: the order-0 cost of the bytes of each
+/// page, and the cost of the table of each page. Stable histograms favor large
+/// pages, which save fewer tables, and histograms that drift favor small
+/// pages, which adapt to the changes. Short streams fit in one page.
+pub fn entropy_page_size(input: &[u8]) -> usize {
+    let mut hists: Vec<[u32; 256]> = input
+        .chunks(MIN_ENTROPY_PAGE_SIZE)
+        .map(|chunk| *Histogram::<256>::from_data(chunk).get_bins())
+        .collect();
+    let cost = |hists: &[[u32; 256]]| -> f64 {
+        hists.iter().map(entropy_page_cost).sum()
+    };
+
+    // Merge the histograms of neighboring pages to score the next size. Ties
+    // go to the larger pages.
+    let mut size = MIN_ENTROPY_PAGE_SIZE;
+    let (mut best, mut best_cost) = (size, cost(&hists));
+    while size < MAX_ENTROPY_PAGE_SIZE && hists.len() > 1 {
+        hists = hists
+            .chunks(2)
+            .map(|pair| {
+                let mut merged = pair[0];
+                for hist in &pair[1..] {
+                    for (a, b) in merged.iter_mut().zip(hist) {
+                        *a += b;
+                    }
+                }
+                merged
+            })
+            .collect();
+        size *= 2;
+        let bits = cost(&hists);
+        if bits <= best_cost {
+            (best, best_cost) = (size, bits);
+        }
+    }
+    best
+}
+
 fn encode_paged_ent(
     input: &[u8],
     ctx: Context,
     callback: EncodeHandlerTy,
 ) -> Vec<u8> {
-    let mut encoded: Vec<u8> = Vec::new();
+    let mut encoded: Vec<u8> = take_u8();
     let mut encoder = PagerEncoder::new(input, &mut encoded, ctx);
     encoder.set_callback(callback);
-    encoder.set_page_size(ENTROPY_PAGE_SIZE);
+    encoder.set_page_size(entropy_page_size(input));
+    encoder.set_sized_header(true);
     let _ = encoder.encode();
     encoded
 }
 
+/// Encode the paged stream 'input', where the first page may reuse the table
+/// 'table' (see 'ent_or_nop'). Returns the stream and the table that the page
+/// after the stream could reuse.
+fn encode_paged_ent_with(
+    input: &[u8],
+    ctx: Context,
+    callback: TableEncodeHandlerTy,
+    table: Option<Vec<u32>>,
+) -> (Vec<u8>, Option<Vec<u32>>) {
+    let mut encoded: Vec<u8> = take_u8();
+    let mut encoder = PagerEncoder::new(input, &mut encoded, ctx);
+    encoder.set_table_callback(callback, table);
+    encoder.set_page_size(entropy_page_size(input));
+    encoder.set_sized_header(true);
+    let _ = encoder.encode();
+    let table = encoder.take_table();
+    (encoded, table)
+}
+
 fn decode_paged_ent(
     input: &[u8],
     callback: DecodeHandlerTy,
 ) -> Option<(usize, Vec<u8>)> {
-    let mut decoded: Vec<u8> = Vec::new();
-    let mut encoder = PagerDecoder::new(input, &mut decoded);
-    encoder.set_callback(callback);
-    let (read, _) = encoder.decode()?;
+    let mut decoded: Vec<u8> = take_u8();
+    let mut decoder = PagerDecoder::new(input, &mut decoded);
+    decoder.set_callback(callback);
+    let (read, _) = decoder.decode()?;
     Some((read, decoded))
 }
 
+/// Decode a paged stream that was encoded with 'encode_paged_ent_with' and the
+/// table 'table'. Returns the number of bytes read, the decoded stream and the
+/// last table of the stream.
+fn decode_paged_ent_with(
+    input: &[u8],
+    callback: TableDecodeHandlerTy,
+    table: Option<Vec<u32>>,
+) -> Option<(usize, Vec<u8>, Option<Vec<u32>>)> {
+    let mut decoded: Vec<u8> = take_u8();
+    let mut decoder = PagerDecoder::new(input, &mut decoded);
+    decoder.set_table_callback(callback, table);
+    let (read, _) = decoder.decode()?;
+    let table = decoder.take_table();
+    Some((read, decoded, table))
+}
+
+/// Decode a paged stream that must fill all of 'input'.
+fn decode_paged_ent_exact(
+    input: &[u8],
+    callback: DecodeHandlerTy,
+) -> Option<Vec<u8>> {
+    let (read, decoded) = decode_paged_ent(input, callback)?;
+    if read != input.len() {
+        recycle_u8(decoded);
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Return the predefined normalized histogram that is used for encoding small
+/// blocks. Small blocks are encoded as LZ4 streams, so the table favors the
+/// small numbers of the tokens and lengths, zeros and printable text.
+fn small_block_histogram() -> [u32; 256] {
+    let mut hist = [1; 256];
+    for (sym, weight) in hist.iter_mut().enumerate() {
+        *weight = match sym as u8 {
+            0 => 48,
+            1..=15 => 8,
+            b' ' => 40,
+            b'e' | b't' | b'a' | b'o' | b'i' | b'n' | b's' | b'r' => 24,
+            b'a'..=b'z' => 12,
+            b'0'..=b'9' => 6,
+            b'A'..=b'Z' | b'!'..=b'/' | b':'..=b'@' => 4,
+            0x10 | 0xf0 | 0xff => 6,
+            _ => 1,
+        };
+    }
+    normalize_to_total_sum(&mut hist, SMALL_TABLE_SIZE as u32);
+    hist
+}
+
+type SmallTables = EntropyTables<256, SMALL_TABLE_SIZE>;
+
+thread_local! {
+    /// The tables of the predefined entropy coder of small blocks. Building
+    /// the tables costs more than encoding a small block, so each thread keeps
+    /// the tables between blocks.
+    static SMALL_TABLES: RefCell<Option<SmallTables>> =
+        const { RefCell::new(None) };
+}
+
+/// Take the cached tables of the small-block entropy coder.
+fn take_small_tables() -> SmallTables {
+    SMALL_TABLES
+        .with(|cache| cache.borrow_mut().take())
+        .unwrap_or_default()
+}
+
+/// Return the tables of the small-block entropy coder to the cache.
+fn recycle_small_tables(tables: SmallTables) {
+    SMALL_TABLES.with(|cache| *cache.borrow_mut() = Some(tables));
+}
+
+/// Encode the content of a small block as an LZ4 stream, and compress the
+/// LZ4 stream with a predefined entropy table if that helps. Returns the mode
+/// of the encoding and the payload.
+fn encode_small_payload(input: &[u8], ctx: Context) -> (u8, Vec<u8>) {
+    let mut lz = take_u8();
+    let _ = LZ4Encoder::new(input, &mut lz, ctx).encode();
+
+    let mut coded = take_u8();
+    type EncoderTy<'a> = EntropyEncoder<'a, 256, SMALL_TABLE_SIZE>;
+    let hist = small_block_histogram();
+    let tables = take_small_tables();
+    let mut encoder = EncoderTy::with_tables(&lz, &mut coded, tables);
+    let entropy = encoder.try_encode_with_table(&hist).is_some();
+    recycle_small_tables(encoder.into_tables());
+
+    if entropy && coded.len() < lz.len() {
+        recycle_u8(lz);
+        return (SMALL_ENTROPY, coded);
+    }
+    recycle_u8(coded);
+    (SMALL_RAW, lz)
+}
+
+/// Decode the payload of a small block that was encoded with the mode 'mode'.
+/// The decoded content may not exceed 'max_len' bytes.
+fn decode_small_payload(
+    mode: u8,
+    payload: &[u8],
+    max_len: usize,
+) -> Option<Vec<u8>> {
+    let mut lz = take_u8();
+    let lz_stream = match mode {
+        SMALL_RAW => payload,
+        SMALL_ENTROPY => {
+            type DecoderTy<'a> = EntropyDecoder<'a, 256, SMALL_TABLE_SIZE>;
+            let hist = small_block_histogram();
+            let tables = take_small_tables();
+            let mut decoder = DecoderTy::with_tables(payload, &mut lz, tables);
+            let res = decoder.decode_with_table(&hist);
+            recycle_small_tables(decoder.into_tables());
+            let (read, _) = res?;
+            if read != payload.len() {
+                return None;
+            }
+            &lz
+        }
+        _ => return None,
+    };
+
+    let mut result = Vec::new();
+    let mut decoder = LZ4Decoder::new(lz_stream, &mut result);
+    let (read, _) = decoder.decode_strict(max_len).ok()?;
+    if read != lz_stream.len() {
+        return None;
+    }
+    recycle_u8(lz);
+    Some(result)
+}
+
+/// Encode a small block. The content is encoded without the stream headers and
+/// the tables of the regular block encoding. See 'encode_small_payload'.
+fn encode_small_block(input: &[u8], ctx: Context) -> Vec<u8> {
+    let (mode, payload) = encode_small_payload(input, ctx);
+    let mut result = vec![mode];
+    encode_vl(payload.len() as u32, &mut result);
+    result.extend(&payload);
+    recycle_u8(payload);
+    result
+}
+
+/// Decode a small block that was encoded with 'encode_small_block'. Returns
+/// the number of bytes read and the decoded content.
+fn decode_small_block(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let mode = *input.first()?;
+    let (read, len) = decode_vl(&input[1..])?;
+    let start = 1 + read;
+    let payload = input.get(start..start + len as usize)?;
+    let result = decode_small_payload(mode, payload, usize::MAX)?;
+    Some((start + len as usize, result))
+}
+
+/// Compress 'input' into a record without signatures or lengths, for embedding
+/// in other formats. The caller needs to store the length of the record and
+/// the length of 'input', and pass both to 'decompress_block_raw'. Records of
+/// incompressible inputs are the input itself.
+pub fn compress_block_raw(input: &[u8], ctx: Context) -> Vec<u8> {
+    let (mode, payload) = if input.len() <= SMALL_BLOCK_LIMIT {
+        encode_small_payload(input, ctx)
+    } else {
+        let long = use_long_offsets(input.len(), ctx);
+        let mode = if long { LONG_RAW_BLOCK } else { RAW_BLOCK };
+        (mode, BlockEncoder::encode_buffer(input, long, ctx))
+    };
+
+    // The record must be shorter than the input, to tell it apart from a
+    // stored record.
+    if payload.len() + 1 >= input.len() {
+        recycle_u8(payload);
+        return input.to_vec();
+    }
+    let mut result = Vec::with_capacity(payload.len() + 1);
+    result.push(mode);
+    result.extend(&payload);
+    recycle_u8(payload);
+    result
+}
+
+/// Decompress the record 'input' that was created by 'compress_block_raw' from
+/// an input of 'len' bytes. Returns None if the record is invalid.
+pub fn decompress_block_raw(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    if input.len() == len {
+        return Some(input.to_vec());
+    }
+    let (mode, payload) = input.split_first()?;
+    let result = if *mode == RAW_BLOCK || *mode == LONG_RAW_BLOCK {
+        let long = *mode == LONG_RAW_BLOCK;
+        let (read, result) =
+            BlockDecoder::decode_buffer(payload, long, usize::MAX)?;
+        if read != payload.len() {
+            return None;
+        }
+        result
+    } else {
+        decode_small_payload(*mode, payload, len)?
+    };
+    if result.len() != len {
+        return None;
+    }
+    Some(result)
+}
+
 /// Drives the encoding of a single block.
 pub struct BlockEncoder<'a> {
     /// The uncompressed input.
@@ -159,38 +1222,106 @@ pub struct BlockEncoder<'a> {
     output: &'a mut Vec<u8>,
     /// Encoder context.
     ctx: Context,
+    /// The block is inside a pipeline block or an RLE block. See 'nested'.
+    nested: bool,
+}
+
+/// The lowest level that tries to code the literals in the context of the
+/// match byte. See 'coding::literal'.
+const MATCHED_LIT_LEVEL: u8 = 10;
+
+/// The max offset of matches. The offsets are saved with a bias of 3, to
+/// allow the special encoding of the previous offsets, in 24 bits.
+pub const MAX_MATCH_OFFSET: usize = 16777210;
+
+/// The max offset of matches in blocks with long offsets. See
+/// 'Context::with_long_window'.
+pub const MAX_LONG_MATCH_OFFSET: usize = 1 << 31;
+
+/// Return true if a block of 'len' bytes is encoded with long offsets. Blocks
+/// that fit in the regular window don't need them.
+fn use_long_offsets(len: usize, ctx: Context) -> bool {
+    ctx.long_window && len > MAX_MATCH_OFFSET
+}
+
+/// A run of literals followed by a match. See 'encode_sequences'.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sequence {
+    /// The number of literals before the match.
+    pub lit_len: u32,
+    /// The length of the match, or zero for sequences without a match.
+    pub mat_len: u32,
+    /// The distance from the end of the literals to the start of the match.
+    pub offset: u32,
 }
 
 impl<'a> BlockEncoder<'a> {
-    fn encode_buffer(input: &'a [u8], ctx: Context) -> Vec<u8> {
-        // The max offset is 1 << MAX_OFFSET_BITS - 3 to allow the special
-        // encoding of offsets.
-        let matcher = select_matcher::<16777210, 65536>(ctx.level, input);
+    /// Encode the block inside a pipeline block or an RLE block. The inner
+    /// block skips the pipeline and the runs, so blocks nest one level deep,
+    /// and the decoder rejects deeper nesting (see 'BlockDecoder::nested').
+    pub(crate) fn nested(mut self) -> Self {
+        self.nested = true;
+        self
+    }
 
-        let mut lits: Vec<u8> = Vec::new();
-        let mut lit_lens: Vec<u32> = Vec::new();
-        let mut mat_offsets: Vec<u32> = Vec::new();
-        let mut mat_lens: Vec<u32> = Vec::new();
+    /// Encode the literals of the sequences in 'input' with 'LiteralEncoder',
+    /// in the context of the previous byte, the position modulo the alignment
+    /// of the block, and the match byte for the first literal after each
+    /// match. The alignment 'align' is saved in the header of the stream. See
+    /// 'encode_streams' for the sequences.
+    fn encode_matched_literals(
+        input: &[u8],
+        align: usize,
+        lit_lens: &[u32],
+        mat_lens: &[u32],
+        offsets: &[u32],
+    ) -> Vec<u8> {
+        let mut stream = MATCHED_LIT_SIG.to_vec();
+        let count: u64 = lit_lens.iter().map(|x| *x as u64).sum();
+        leb128::encode(count, &mut stream);
+        stream.push(align as u8);
+
+        let mut encoder =
+            LiteralEncoder::new(&mut stream).with_alignment(align);
+        let mut pos = 0;
+        for i in 0..lit_lens.len() {
+            for j in 0..lit_lens[i] as usize {
+                let prev = if pos > 0 { input[pos - 1] } else { 0 };
+                let after_match = j == 0 && i > 0 && mat_lens[i - 1] > 0;
+                let match_byte =
+                    after_match.then(|| input[pos - offsets[i - 1] as usize]);
+                encoder.encode(input[pos], pos, prev, match_byte);
+                pos += 1;
+            }
+            pos += mat_lens[i] as usize;
+        }
+        encoder.finish();
+        stream
+    }
+
+    /// Serialize and entropy encode the literals 'lits' and the sequences that
+    /// are saved in 'lit_lens', 'mat_lens' and 'offsets'. Sequences without a
+    /// match have a zero offset. At high levels, when the uncompressed block
+    /// 'input' is known, the literals may be coded with the match byte context
+    /// instead. The offsets of blocks with 'long' offsets have more tokens.
+    fn encode_streams(
+        input: Option<&[u8]>,
+        long: bool,
+        lits: &[u8],
+        lit_lens: &[u32],
+        mat_lens: &[u32],
+        offsets: &[u32],
+        ctx: Context,
+    ) -> Vec<u8> {
+        let mut mat_offsets: Vec<u32> = take_u32();
 
         let mut prev_off1 = 0;
         let mut prev_off2 = 0;
         let mut prev_off3 = 0;
 
-        for (lit, mat) in matcher {
-            // Serialize the literals and the length of each segment.
-            let literals = &input[lit.clone()];
-            lits.extend(literals);
-            lit_lens.push(lit.len() as u32);
-
-            // Calculate the offset to the match.
-            let mut match_offset = lit.end - mat.start;
-
-            // Don't encode empty matches. These show up at stream ends.
-            if mat.is_empty() {
-                match_offset = 0;
-            }
+        for offset in offsets {
             // Add a bias of 3 to allow us to encode previous matches.
-            match_offset += 3;
+            let mut match_offset = offset + 3;
 
             // Check if we are encoding one of the previous matches.
             if prev_off1 == match_offset {
@@ -204,44 +1335,238 @@ impl<'a> BlockEncoder<'a> {
             prev_off3 = prev_off2;
             prev_off2 = prev_off1;
             prev_off1 = match_offset;
-
-            // Store the match length and offsets.
-            mat_offsets.push(match_offset as u32);
-            mat_lens.push(mat.len() as u32);
+            mat_offsets.push(match_offset);
         }
 
-        // Turn everything to U8 arrays.
-        let mut lit_len_u8: Vec<u8> = Vec::new();
-        let mut mat_len_u8: Vec<u8> = Vec::new();
-
-        encode_vl32(&lit_lens, &mut lit_len_u8);
-        encode_vl32(&mat_lens, &mut mat_len_u8);
-
         // Entropy encode what is possible.
-        let lit_stream2 = encode_paged_ent(&lits, ctx, ent_or_nop);
-        let lit_len_stream2 = encode_paged_ent(&lit_len_u8, ctx, ent_or_nop);
-        let mat_off_u8 = encode_offset_stream::<OFFSET_BITS>(&mat_offsets, ctx);
-        let mat_len_stream2 = encode_paged_ent(&mat_len_u8, ctx, ent_or_nop);
+        let callback: EncodeHandlerTy = if ctx.hybrid {
+            residual_or_nop
+        } else {
+            split_ent_or_nop
+        };
+        let mut lit_stream2 = encode_paged_ent(lits, ctx, callback);
+        let (mut off_codec, mut mat_off_u8) =
+            encode_match_offsets(&mat_offsets, long, &[], 1, ctx);
+        let mut align = 1;
+        if let Some(input) = input.filter(|_| ctx.level >= MATCHED_LIT_LEVEL) {
+            // The detector may see fields that the models can't use, so try
+            // the detected alignment against no alignment. The alignment is
+            // saved with the literals, and applies to the offsets too.
+            let detected = detect_alignment(input);
+            let mut aligns = vec![1];
+            if detected > 1 {
+                aligns.push(detected);
+            }
+            for candidate in aligns {
+                let coded = Self::encode_matched_literals(
+                    input, candidate, lit_lens, mat_lens, offsets,
+                );
+                let lanes = match_lanes(lit_lens, mat_lens, candidate);
+                let (codec, offs) = encode_match_offsets(
+                    &mat_offsets,
+                    long,
+                    &lanes,
+                    candidate,
+                    ctx,
+                );
+                if coded.len() + offs.len()
+                    < lit_stream2.len() + mat_off_u8.len()
+                {
+                    recycle_u8(std::mem::replace(&mut lit_stream2, coded));
+                    recycle_u8(std::mem::replace(&mut mat_off_u8, offs));
+                    (off_codec, align) = (codec, candidate);
+                } else {
+                    recycle_u8(coded);
+                    recycle_u8(offs);
+                }
+            }
+        }
+        let (lit_codec, lit_stream2) = select_codec(lits, lit_stream2);
+        // Literals that are saved without the match byte don't save the
+        // alignment, so the offsets are coded without it.
+        if lit_codec != CODEC_ENTROPY && align > 1 {
+            recycle_u8(mat_off_u8);
+            (off_codec, mat_off_u8) =
+                encode_match_offsets(&mat_offsets, long, &[], 1, ctx);
+        }
+        let (seq_codecs, seq_stream) =
+            encode_sequence_stream(lit_lens, mat_lens, ctx);
 
         // To the wire!
         let mut result = Vec::new();
+        result.push(lit_codec | (seq_codecs << 2) | (off_codec << 6));
         encode_arr(&lit_stream2, &mut result);
-        encode_arr(&lit_len_stream2, &mut result);
+        encode_arr(&seq_stream, &mut result);
         encode_arr(&mat_off_u8, &mut result);
-        encode_arr(&mat_len_stream2, &mut result);
+
+        // Return the intermediate buffers to the pool.
+        recycle_u32(mat_offsets);
+        for buffer in [lit_stream2, seq_stream, mat_off_u8] {
+            recycle_u8(buffer);
+        }
         result
     }
 
+    fn encode_buffer(input: &'a [u8], long: bool, ctx: Context) -> Vec<u8> {
+        let matcher = if long {
+            select_long_matcher::<MAX_LONG_MATCH_OFFSET, 65536>(
+                ctx.level, ctx.parse, input,
+            )
+        } else {
+            select_matcher_with::<MAX_MATCH_OFFSET, 65536>(
+                ctx.level, ctx.parse, input,
+            )
+        };
+
+        let mut lits: Vec<u8> = take_u8();
+        let mut lit_lens: Vec<u32> = take_u32();
+        let mut offsets: Vec<u32> = take_u32();
+        let mut mat_lens: Vec<u32> = take_u32();
+
+        for (lit, mat) in matcher {
+            // Serialize the literals and the length of each segment.
+            lits.extend(&input[lit.clone()]);
+            lit_lens.push(lit.len() as u32);
+
+            // Don't encode empty matches. These show up at stream ends.
+            let offset = if mat.is_empty() {
+                0
+            } else {
+                lit.end - mat.start
+            };
+            offsets.push(offset as u32);
+            mat_lens.push(mat.len() as u32);
+        }
+
+        let result = Self::encode_streams(
+            Some(input),
+            long,
+            &lits,
+            &lit_lens,
+            &mat_lens,
+            &offsets,
+            ctx,
+        );
+
+        // Return the intermediate buffers to the pool.
+        recycle_u8(lits);
+        for buffer in [lit_lens, offsets, mat_lens] {
+            recycle_u32(buffer);
+        }
+        result
+    }
+
+    /// Encode a block from the literals 'literals' and the matches in
+    /// 'sequences' that the caller found, instead of running the matcher. The
+    /// literals of all of the sequences are concatenated in 'literals'. The
+    /// result is a block that 'BlockDecoder' decodes. Returns None if the
+    /// sequences don't use all of the literals, or if a match starts before
+    /// the beginning of the block or is too far. Matches may reach up to
+    /// 'MAX_LONG_MATCH_OFFSET' bytes back if the context enables long windows.
+    pub fn encode_sequences(
+        literals: &[u8],
+        sequences: &[Sequence],
+        ctx: Context,
+    ) -> Option<Vec<u8>> {
+        let mut lit_lens: Vec<u32> = Vec::with_capacity(sequences.len());
+        let mut mat_lens: Vec<u32> = Vec::with_capacity(sequences.len());
+        let mut offsets: Vec<u32> = Vec::with_capacity(sequences.len());
+        let max_offset = if ctx.long_window {
+            MAX_LONG_MATCH_OFFSET
+        } else {
+            MAX_MATCH_OFFSET
+        };
+
+        // Validate the sequences against the decoded length of the block.
+        let mut lit_total = 0;
+        let mut written = 0;
+        for seq in sequences {
+            lit_total += seq.lit_len as usize;
+            written += seq.lit_len as usize;
+            let offset = if seq.mat_len == 0 { 0 } else { seq.offset };
+            if seq.mat_len > 0
+                && (offset == 0
+                    || offset as usize > written
+                    || offset as usize > max_offset)
+            {
+                return None;
+            }
+            written += seq.mat_len as usize;
+            lit_lens.push(seq.lit_len);
+            mat_lens.push(seq.mat_len);
+            offsets.push(offset);
+        }
+        if lit_total != literals.len() {
+            return None;
+        }
+
+        let long = use_long_offsets(written, ctx);
+        let streams = Self::encode_streams(
+            None, long, literals, &lit_lens, &mat_lens, &offsets, ctx,
+        );
+        let mut result = if long { LONG_BLOCK_SIG } else { BLOCK_SIG }.to_vec();
+        result.extend(&streams);
+        recycle_u8(streams);
+        Some(result)
+    }
+
+    /// Encode the block as an LZ4 stream with the matcher of 'FAST_LEVEL'.
+    fn encode_fast(&mut self) -> usize {
+        let start = self.output.len();
+        self.output.extend(FAST_BLOCK_SIG);
+        leb128::encode(self.input.len() as u64, self.output);
+        let mut stream = take_u8();
+        stream.reserve(compress_bound(self.input.len()));
+        let _ = LZ4Encoder::new(self.input, &mut stream, self.ctx).encode();
+        leb128::encode(stream.len() as u64, self.output);
+        self.output.extend(&stream);
+        recycle_u8(stream);
+        self.output.len() - start
+    }
+
     fn encode_impl(&mut self) -> usize {
+        // The pipeline of the context replaces the default stages.
+        if let Some(pipeline) = self.ctx.pipeline.filter(|_| !self.nested) {
+            return pipeline.encode(self.input, self.ctx, self.output);
+        }
+
+        // The fastest level skips all of the stages after the matcher.
+        if self.ctx.level == FAST_LEVEL {
+            return self.encode_fast();
+        }
+
+        // Use the compact encoding for small blocks, where the headers and
+        // tables of the regular encoding would exceed the payload.
+        if self.input.len() <= SMALL_BLOCK_LIMIT {
+            self.output.extend(SMALL_BLOCK_SIG);
+            let res = encode_small_block(self.input, self.ctx);
+            self.output.extend(&res);
+            return res.len() + SMALL_BLOCK_SIG.len();
+        }
+
+        // Collapse long runs, which are slow to match, and encode the rest of
+        // the block after the run tokens.
+        let collapse = self.ctx.rle_pass && !self.nested;
+        let runs = collapse.then(|| rle::collapse(self.input)).flatten();
+        if let Some((collapsed, runs)) = runs {
+            self.output.extend(RLE_BLOCK_SIG);
+            let written = RLE_BLOCK_SIG.len() + encode_arr(&runs, self.output);
+            let mut encoder =
+                BlockEncoder::new(&collapsed, self.output, self.ctx).nested();
+            return written + encoder.encode();
+        }
+
         // Write the magic signature.
-        self.output.extend(BLOCK_SIG);
+        let long = use_long_offsets(self.input.len(), self.ctx);
+        let sig = if long { LONG_BLOCK_SIG } else { BLOCK_SIG };
+        self.output.extend(sig);
 
         // Compress the content and write it to the output.
-        let res = Self::encode_buffer(self.input, self.ctx);
+        let res = Self::encode_buffer(self.input, long, self.ctx);
         self.output.extend(&res);
 
         // Bytes written plus the signature.
-        res.len() + BLOCK_SIG.len()
+        res.len() + sig.len()
     }
 }
 
@@ -251,94 +1576,364 @@ pub struct BlockDecoder<'a> {
     input: &'a [u8],
     /// The output stream.
     output: &'a mut Vec<u8>,
+    /// The max number of bytes to decode. See 'with_limit'.
+    limit: usize,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
+    /// Reject pipeline blocks and RLE blocks. See 'nested'.
+    nested: bool,
 }
 
 impl<'a> BlockDecoder<'a> {
-    fn decode_buffer(input: &'a [u8]) -> Option<(usize, Vec<u8>)> {
-        let mut literals: Vec<u8> = Vec::new();
-        let mut lit_lens: Vec<u8> = Vec::new();
-        let mut mat_offs: Vec<u8> = Vec::new();
-        let mut mat_lens: Vec<u8> = Vec::new();
-
-        let mut read = 0;
-        read += decode_arr(&input[read..], &mut literals)?;
-        read += decode_arr(&input[read..], &mut lit_lens)?;
-        read += decode_arr(&input[read..], &mut mat_offs)?;
-        read += decode_arr(&input[read..], &mut mat_lens)?;
+    /// Decode the inner block of a pipeline block or of an RLE block, which
+    /// may not be a pipeline block or an RLE block itself. Crafted streams
+    /// would otherwise nest blocks until the stack overflows.
+    pub(crate) fn nested(mut self) -> Self {
+        self.nested = true;
+        self
+    }
 
-        let literals2 = decode_paged_ent(&literals, decode_ent_or_nop)?.1;
-        let lit_lens2 = decode_paged_ent(&lit_lens, decode_ent_or_nop)?.1;
-        let mat_offs2 = decode_offset_stream::<OFFSET_BITS>(&mat_offs)?;
-        let mat_lens2 = decode_paged_ent(&mat_lens, decode_ent_or_nop)?.1;
+    /// Stop after the first 'limit' bytes of the block are decoded. The
+    /// sequences of regular blocks after the limit are not copied, and are
+    /// not validated. The decoder still reports the size of the whole block.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
 
-        let mut lit_lens3: Vec<u32> = Vec::new();
-        let mut mat_offs3: Vec<u32> = Vec::new();
-        let mut mat_lens3: Vec<u32> = Vec::new();
+    /// Decode the streams of a block, up to 'limit' bytes. Blocks with 'long'
+    /// offsets have more offset tokens.
+    fn decode_buffer(
+        input: &'a [u8],
+        long: bool,
+        limit: usize,
+    ) -> Option<(usize, Vec<u8>)> {
+        let mut literals: Vec<u8> = take_u8();
+        let mut sequences: Vec<u8> = take_u8();
+        let mut mat_offs: Vec<u8> = take_u8();
 
-        // Decode the offsets. Zero means that we need to use the previous
-        // offset.
-        let mut prev_off1 = 0;
-        let mut prev_off2 = 0;
-        let mut prev_off3 = 0;
+        let selector = *input.first()?;
+        let mut read = 1;
+        read += decode_arr(&input[read..], &mut literals)?;
+        read += decode_arr(&input[read..], &mut sequences)?;
+        read += decode_arr(&input[read..], &mut mat_offs)?;
 
-        // Decode the offset (the first 3 values refer to previous offsets).
-        for offset in mat_offs2 {
-            let off = match offset {
-                0 => prev_off1,
-                1 => prev_off2,
-                2 => prev_off3,
-                _ => offset,
-            };
-            prev_off3 = prev_off2;
-            prev_off2 = prev_off1;
-            prev_off1 = offset;
-            mat_offs3.push(off - 3);
+        // Literals that are coded in the context of the match byte are decoded
+        // while the sequences are copied.
+        let mut coded = None;
+        let lit_codec = stream_codec(selector, 0);
+        let matched = lit_codec == CODEC_ENTROPY
+            && match_signature(&literals, &MATCHED_LIT_SIG);
+        let mut align = 1;
+        let literals2 = if matched {
+            let (read, count, lit_align) = read_matched_header(&literals)?;
+            let stream = &literals[read..];
+            let decoder =
+                LiteralDecoder::new(stream)?.with_alignment(lit_align);
+            coded = Some((decoder, stream.len(), count));
+            align = lit_align;
+            take_u8()
+        } else {
+            decode_codec_exact(lit_codec, &literals, decode_split_ent_or_nop)?
+        };
+        let lit_count = match &coded {
+            Some((_, _, count)) => check_output(*count)?,
+            None => literals2.len(),
+        };
+        let (lit_lens3, mat_lens3) =
+            decode_sequence_stream(&sequences, selector >> 2)?;
+        let lanes = match_lanes(&lit_lens3, &mat_lens3, align);
+        let off_codec = stream_codec(selector, 3);
+        let mat_offs3 =
+            decode_match_offsets(&mat_offs, long, off_codec, &lanes, align)?;
+        if mat_offs3.len() != lit_lens3.len() {
+            return None;
         }
 
-        let _ = decode_vl32(&lit_lens2, &mut lit_lens3)?;
-        let _ = decode_vl32(&mat_lens2, &mut mat_lens3)?;
         let mut result: Vec<u8> = Vec::new();
 
         let mut lit_cursor = 0;
         let mut out_cursor = 0;
+        let mut stopped = false;
         for i in 0..lit_lens3.len() {
+            if out_cursor >= limit {
+                stopped = true;
+                break;
+            }
             let lit_len = lit_lens3[i] as usize;
             let mat_len = mat_lens3[i] as usize;
             let mat_off = mat_offs3[i] as usize;
 
             // Copy the literals.
-            let lit = &literals2[lit_cursor..lit_cursor + lit_len];
+            if lit_cursor + lit_len > lit_count {
+                return None;
+            }
+            match coded.as_mut() {
+                Some((decoder, _, _)) => {
+                    // Only the first literal after a match has a match byte.
+                    for j in 0..lit_len {
+                        let prev = result.last().copied().unwrap_or(0);
+                        let after_match =
+                            j == 0 && i > 0 && mat_lens3[i - 1] > 0;
+                        let match_byte = after_match.then(|| {
+                            result[out_cursor - mat_offs3[i - 1] as usize]
+                        });
+                        let pos = result.len();
+                        result.push(decoder.decode(pos, prev, match_byte)?);
+                    }
+                }
+                None => result.extend(unchecked::range(
+                    &literals2,
+                    lit_cursor..lit_cursor + lit_len,
+                )),
+            }
             lit_cursor += lit_len;
             out_cursor += lit_len;
-            result.extend(lit);
 
-            // Copy the match.
-            for i in 0..mat_len {
-                result.push(result[out_cursor - mat_off + i]);
+            // Copy the match. The match must start inside of the decoded
+            // output, and only the empty matches at the end of the stream have
+            // no distance. The length is not bounded by the input.
+            if mat_len > 0 && mat_off == 0 {
+                return None;
+            }
+            out_cursor.checked_sub(mat_off)?;
+            check_output(out_cursor.checked_add(mat_len)?)?;
+            if mat_len > 0 {
+                copy_match(&mut result, mat_off, mat_len);
             }
             out_cursor += mat_len;
         }
 
+        // All of the literals must be used.
+        if !stopped && lit_cursor != lit_count {
+            return None;
+        }
+        if let Some((decoder, len, _)) = coded.filter(|_| !stopped) {
+            if decoder.read() != len {
+                return None;
+            }
+        }
+        result.truncate(limit);
+
+        // Return the intermediate buffers to the pool.
+        for buffer in [literals, sequences, mat_offs, literals2] {
+            recycle_u8(buffer);
+        }
+        for buffer in [lit_lens3, mat_offs3, mat_lens3] {
+            recycle_u32(buffer);
+        }
         Some((read, result))
     }
 
+    /// Decode a block that was encoded with 'encode_fast'. Returns the number
+    /// of bytes read and the decoded content.
+    fn decode_fast(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        let mut read = FAST_BLOCK_SIG.len();
+        let (used, len) = leb128::decode_len(&input[read..])?;
+        read += used;
+        let (used, size) = leb128::decode_len(&input[read..])?;
+        read += used;
+        let stream = input.get(read..)?.get(..size)?;
+        let mut result = Vec::with_capacity(check_output(len)?);
+        let mut decoder = LZ4Decoder::new(stream, &mut result);
+        let (used, written) = decoder.decode_strict(check_output(len)?).ok()?;
+        if used != size || written != len {
+            return None;
+        }
+        Some((read + size, result))
+    }
+
+    /// Write the first 'limit' bytes of the decoded block 'buff' to the
+    /// output. Returns the number of bytes written.
+    fn write_limited(&mut self, buff: &[u8]) -> usize {
+        let buff = &buff[..buff.len().min(self.limit)];
+        self.output.extend(buff);
+        buff.len()
+    }
+
     fn decode_impl(&mut self) -> Option<(usize, usize)> {
+        if match_signature(self.input, &FAST_BLOCK_SIG) {
+            let (read, buff) = Self::decode_fast(self.input)?;
+            return Some((read, self.write_limited(&buff)));
+        }
+
+        if match_signature(self.input, &SMALL_BLOCK_SIG) {
+            let sig_len = SMALL_BLOCK_SIG.len();
+            let (read, buff) = decode_small_block(&self.input[sig_len..])?;
+            return Some((sig_len + read, self.write_limited(&buff)));
+        }
+
+        if match_signature(self.input, &PIPELINE_BLOCK_SIG) {
+            if self.nested {
+                return None;
+            }
+            let (read, buff) = Pipeline::decode(self.input)?;
+            return Some((read, self.write_limited(&buff)));
+        }
+
+        if match_signature(self.input, &RLE_BLOCK_SIG) {
+            if self.nested {
+                return None;
+            }
+            let mut runs = Vec::new();
+            let mut read = RLE_BLOCK_SIG.len();
+            read += decode_arr(&self.input[read..], &mut runs)?;
+            let mut collapsed = Vec::new();
+            let mut decoder =
+                BlockDecoder::new(&self.input[read..], &mut collapsed).nested();
+            read += decoder.decode()?.0;
+            let buff = rle::expand(&collapsed, &runs)?;
+            return Some((read, self.write_limited(&buff)));
+        }
+
+        let long = match_signature(self.input, &LONG_BLOCK_SIG);
         let sig_len = BLOCK_SIG.len();
-        if !match_signature(self.input, &BLOCK_SIG) {
+        if !long && !match_signature(self.input, &BLOCK_SIG) {
             return None;
         }
 
         // Decode the content.
-        let (read, buff) = Self::decode_buffer(&self.input[sig_len..])?;
+        let (read, buff) =
+            Self::decode_buffer(&self.input[sig_len..], long, self.limit)?;
 
         self.output.extend(&buff);
         Some((sig_len + read, buff.len()))
     }
 }
 
+/// The sequences of a regular block. See 'BlockReader'.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockSequences {
+    /// The literals of all of the sequences, concatenated.
+    pub literals: Vec<u8>,
+    /// The sequences, in order.
+    pub sequences: Vec<Sequence>,
+    /// True if the block uses long offsets (see 'LONG_BLOCK_SIG').
+    pub long: bool,
+}
+
+impl BlockSequences {
+    /// Return the number of bytes that the sequences decode to.
+    pub fn decoded_len(&self) -> usize {
+        self.sequences
+            .iter()
+            .map(|seq| seq.lit_len as usize + seq.mat_len as usize)
+            .sum()
+    }
+}
+
+/// Parses a regular block into its literals and sequences, without copying
+/// the matches, for tools that work on the sequences, such as analyzers and
+/// transcoders to other LZ formats. 'BlockEncoder::encode_sequences' encodes
+/// the sequences back into a block.
+pub struct BlockReader<'a> {
+    /// The encoded block.
+    input: &'a [u8],
+}
+
+impl<'a> BlockReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    /// Parse the block. Returns the size of the block and its sequences.
+    /// Returns None if the block is invalid, or if it is not a regular block:
+    /// fast and small blocks are LZ4 streams, and the sequences of RLE and
+    /// pipeline blocks describe the transformed bytes. Literals that are coded
+    /// in the context of the match byte (see 'MATCHED_LIT_SIG') depend on the
+    /// decoded bytes, so these blocks are decoded to recover the literals.
+    pub fn read(&self) -> Option<(usize, BlockSequences)> {
+        let long = match_signature(self.input, &LONG_BLOCK_SIG);
+        if !long && !match_signature(self.input, &BLOCK_SIG) {
+            return None;
+        }
+        let body = &self.input[BLOCK_SIG.len()..];
+        let selector = *body.first()?;
+        let mut arrays = [take_u8(), take_u8(), take_u8()];
+        let mut read = 1;
+        for array in arrays.iter_mut() {
+            read += decode_arr(&body[read..], array)?;
+        }
+        let [literals, sequences, mat_offs] = arrays;
+
+        let lit_codec = stream_codec(selector, 0);
+        let matched = lit_codec == CODEC_ENTROPY
+            && match_signature(&literals, &MATCHED_LIT_SIG);
+        let align = match matched {
+            true => read_matched_header(&literals)?.2,
+            false => 1,
+        };
+        let (lit_lens, mat_lens) =
+            decode_sequence_stream(&sequences, selector >> 2)?;
+        let lanes = match_lanes(&lit_lens, &mat_lens, align);
+        let off_codec = stream_codec(selector, 3);
+        let offsets =
+            decode_match_offsets(&mat_offs, long, off_codec, &lanes, align)?;
+        if offsets.len() != lit_lens.len() {
+            return None;
+        }
+
+        // Check the sequences like the decoder does.
+        let mut written = 0usize;
+        let mut lit_count = 0;
+        let mut seqs = Vec::with_capacity(lit_lens.len());
+        for i in 0..lit_lens.len() {
+            let seq = Sequence {
+                lit_len: lit_lens[i],
+                mat_len: mat_lens[i],
+                offset: offsets[i],
+            };
+            lit_count += seq.lit_len as usize;
+            written = written.checked_add(seq.lit_len as usize)?;
+            if (seq.mat_len > 0 && seq.offset == 0)
+                || seq.offset as usize > written
+            {
+                return None;
+            }
+            written = check_output(written.checked_add(seq.mat_len as usize)?)?;
+            seqs.push(seq);
+        }
+
+        let lits = if matched {
+            let (_, decoded) =
+                BlockDecoder::decode_buffer(body, long, usize::MAX)?;
+            let mut lits = Vec::with_capacity(lit_count);
+            let mut pos = 0;
+            for seq in &seqs {
+                lits.extend(&decoded[pos..pos + seq.lit_len as usize]);
+                pos += seq.lit_len as usize + seq.mat_len as usize;
+            }
+            lits
+        } else {
+            decode_codec_exact(lit_codec, &literals, decode_split_ent_or_nop)?
+        };
+        if lits.len() != lit_count {
+            return None;
+        }
+
+        for buffer in [literals, sequences, mat_offs] {
+            recycle_u8(buffer);
+        }
+        for buffer in [lit_lens, mat_lens, offsets] {
+            recycle_u32(buffer);
+        }
+        let block = BlockSequences {
+            literals: lits,
+            sequences: seqs,
+            long,
+        };
+        Some((BLOCK_SIG.len() + read, block))
+    }
+}
+
 impl<'a> Encoder<'a> for BlockEncoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
-        BlockEncoder { input, output, ctx }
+        BlockEncoder {
+            input,
+            output,
+            ctx,
+            nested: false,
+        }
     }
 
     fn encode(&mut self) -> usize {
@@ -348,10 +1943,21 @@ impl<'a> Encoder<'a> for BlockEncoder<'a> {
 
 impl<'a> Decoder<'a> for BlockDecoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
-        BlockDecoder { input, output }
+        BlockDecoder {
+            input,
+            output,
+            limit: usize::MAX,
+            read: 0,
+            nested: false,
+        }
     }
 
     fn decode(&mut self) -> Option<(usize, usize)> {
-        self.decode_impl()
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
     }
+
+    decoder_state!();
 }
@@ -0,0 +1,123 @@
+//! Picks the compression level that fits a time budget. The encoder compresses
+//! a sample of the input at level 1 to measure the speed of the machine on
+//! this kind of data, and scales a calibration table of the throughput of each
+//! level. The highest level that is expected to finish within the budget is
+//! selected. The pager re-checks the schedule at page boundaries and lowers
+//! the level of the remaining pages if the encoder falls behind.
+
+use crate::block::{BlockEncoder, FAST_LEVEL};
+use crate::full::ARITH_LEVEL;
+use crate::{Context, Encoder};
+use std::time::{Duration, Instant};
+
+/// The throughput of each level, starting at 'FAST_LEVEL', in megabytes per
+/// second on the reference machine.
+const THROUGHPUT: [f64; ARITH_LEVEL as usize + 1] = [
+    45., 30., 28., 25., 22., 20., 19., 17., 15., 13., 1.4, 0.8, 0.3, 1.3,
+];
+
+/// The number of bytes that are compressed to measure the machine.
+const SAMPLE_SIZE: usize = 1 << 16;
+
+/// Return the expected time to compress 'len' bytes at 'level', on a machine
+/// that is 'speed' times as fast as the reference machine.
+pub(crate) fn expected_time(len: usize, level: u8, speed: f64) -> Duration {
+    let rate = THROUGHPUT[level as usize] * speed * 1_000_000.;
+    Duration::from_secs_f64(len as f64 / rate)
+}
+
+/// Return the speed of this machine on 'input', relative to the reference
+/// machine of the calibration table.
+pub fn calibrate(input: &[u8]) -> f64 {
+    // Sample the middle of the input, which is often more typical than the
+    // headers at the start.
+    let start = input.len().saturating_sub(SAMPLE_SIZE) / 2;
+    let sample = &input[start..input.len().min(start + SAMPLE_SIZE)];
+    if sample.is_empty() {
+        return 1.;
+    }
+
+    let mut encoded = Vec::new();
+    let timer = Instant::now();
+    let ctx = Context::new(1, SAMPLE_SIZE);
+    let _ = BlockEncoder::new(sample, &mut encoded, ctx).encode();
+    let elapsed = timer.elapsed().max(Duration::from_micros(1));
+    let expected = expected_time(sample.len(), 1, 1.);
+    expected.as_secs_f64() / elapsed.as_secs_f64()
+}
+
+/// Return the highest level that compresses 'len' bytes within 'budget', on
+/// a machine that is 'speed' times as fast as the reference machine. A level
+/// is only selected if all of the levels below it fit, because the arithmetic
+/// coder is faster than the levels before it. Returns 'FAST_LEVEL' if no level
+/// fits.
+pub fn fit_level(len: usize, budget: Duration, speed: f64) -> u8 {
+    (FAST_LEVEL..=ARITH_LEVEL)
+        .take_while(|level| expected_time(len, *level, speed) <= budget)
+        .last()
+        .unwrap_or(FAST_LEVEL)
+}
+
+/// Return the highest level that compresses 'input' within 'budget' on this
+/// machine.
+pub fn select_level(input: &[u8], budget: Duration) -> u8 {
+    let timer = Instant::now();
+    let speed = calibrate(input);
+    let left = budget.saturating_sub(timer.elapsed());
+    fit_level(input.len(), left, speed)
+}
+
+/// Tracks the progress of a paged encoder against its time budget. See the
+/// module comment.
+pub struct Schedule {
+    /// The time when the encoding started.
+    start: Instant,
+    /// The time when the last page started.
+    page_start: Instant,
+    /// The time budget of the whole input.
+    budget: Duration,
+    /// The number of bytes that are left to compress.
+    left: usize,
+    /// The level of the next page.
+    level: u8,
+}
+
+impl Schedule {
+    /// Create a schedule for compressing 'len' bytes within 'budget',
+    /// starting at level 'level'.
+    pub fn new(len: usize, budget: Duration, level: u8) -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            page_start: now,
+            budget,
+            left: len,
+            level,
+        }
+    }
+
+    /// Return the level of the next page.
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Record that a page of 'len' bytes was compressed, and return the level
+    /// of the next page. The level is lowered if the remaining bytes are not
+    /// expected to finish in time at the pace of the last page.
+    pub fn page_done(&mut self, len: usize) -> u8 {
+        let now = Instant::now();
+        let page_time = now - self.page_start;
+        self.page_start = now;
+        self.left = self.left.saturating_sub(len);
+        if len == 0 || self.left == 0 {
+            return self.level;
+        }
+
+        // Scale the calibration table by the pace of the last page.
+        let expected = expected_time(len, self.level, 1.).as_secs_f64();
+        let speed = expected / page_time.as_secs_f64().max(1e-6);
+        let remaining = self.budget.saturating_sub(now - self.start);
+        self.level = self.level.min(fit_level(self.left, remaining, speed));
+        self.level
+    }
+}
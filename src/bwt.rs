@@ -0,0 +1,113 @@
+//! The Burrows-Wheeler transform. The transform sorts the suffixes of the
+//! input and keeps the byte before each suffix, which groups the bytes that
+//! appear in the same context. Text and other inputs with long contexts turn
+//! into runs of a few bytes, which are cheap to encode. The inverse needs the
+//! row of the whole input in the sorted list, which is saved next to the
+//! transformed bytes.
+
+/// Return the suffix array of 'input': the offsets of the suffixes of the
+/// input, in sorted order. A suffix that is the prefix of another suffix sorts
+/// before it. The suffixes are sorted by their first 'k' bytes, for 'k' that
+/// doubles on each round, until all of the ranks are different.
+pub fn suffix_array(input: &[u8]) -> Vec<u32> {
+    let n = input.len();
+    let mut sa: Vec<u32> = (0..n as u32).collect();
+    let mut rank: Vec<u32> = input.iter().map(|b| *b as u32 + 1).collect();
+    let mut next_rank = vec![0u32; n];
+    if n < 2 {
+        return sa;
+    }
+    let mut k = 1;
+    loop {
+        // The rank of the suffix at 'i' by its first '2k' bytes. Suffixes
+        // that end within 'k' bytes get a zero second key, which sorts first.
+        let key = |i: u32| {
+            let i = i as usize;
+            let second = if i + k < n { rank[i + k] } else { 0 };
+            (rank[i], second)
+        };
+        sa.sort_unstable_by_key(|i| key(*i));
+
+        next_rank[sa[0] as usize] = 1;
+        for w in 1..n {
+            let same = key(sa[w - 1]) == key(sa[w]);
+            let prev = next_rank[sa[w - 1] as usize];
+            next_rank[sa[w] as usize] = prev + !same as u32;
+        }
+        std::mem::swap(&mut rank, &mut next_rank);
+        if rank[sa[n - 1] as usize] as usize == n {
+            break;
+        }
+        k *= 2;
+    }
+    sa
+}
+
+/// Transform 'input'. Returns the transformed bytes, which have the length of
+/// the input, and the primary index that 'inverse' needs. The suffixes are
+/// sorted after an implicit end marker, whose row is not saved.
+pub fn forward(input: &[u8]) -> (Vec<u8>, usize) {
+    let n = input.len();
+    if n == 0 {
+        return (Vec::new(), 0);
+    }
+    let sa = suffix_array(input);
+    let mut output = Vec::with_capacity(n);
+    // The first row is the end marker, which follows the last byte.
+    output.push(input[n - 1]);
+    let mut primary = 0;
+    for (row, &pos) in sa.iter().enumerate() {
+        if pos == 0 {
+            // The row of the whole input is preceded by the end marker.
+            primary = row + 1;
+        } else {
+            output.push(input[pos as usize - 1]);
+        }
+    }
+    (output, primary)
+}
+
+/// Invert the transform of 'input' with the primary index 'primary'. Returns
+/// None if the index is invalid or if the bytes are not a valid transform.
+pub fn inverse(input: &[u8], primary: usize) -> Option<Vec<u8>> {
+    let n = input.len();
+    if n == 0 {
+        return (primary == 0).then(Vec::new);
+    }
+    if primary == 0 || primary > n {
+        return None;
+    }
+
+    // The first row of each byte in the sorted list, after the end marker.
+    let mut counts = [0usize; 256];
+    for byte in input {
+        counts[*byte as usize] += 1;
+    }
+    let mut starts = [0usize; 256];
+    let mut sum = 1;
+    for (start, count) in starts.iter_mut().zip(counts) {
+        *start = sum;
+        sum += count;
+    }
+
+    // Map each saved byte to the row of the suffix that starts with it.
+    let mut next: Vec<u32> = Vec::with_capacity(n);
+    for byte in input {
+        next.push(starts[*byte as usize] as u32);
+        starts[*byte as usize] += 1;
+    }
+
+    // Walk from the end marker to the start of the input. The row of the
+    // primary index, which holds the end marker, ends the walk.
+    let mut output = vec![0; n];
+    let mut row = 0;
+    for out in output.iter_mut().rev() {
+        if row == primary {
+            return None;
+        }
+        let idx = if row < primary { row } else { row - 1 };
+        *out = input[idx];
+        row = next[idx] as usize;
+    }
+    (row == primary).then_some(output)
+}
@@ -0,0 +1,78 @@
+//! Records the progress of a long compression job. The pages of the paged
+//! format are encoded independently, so a job that was stopped can continue
+//! from the last page that was written, instead of starting over.
+
+use crate::utils::hash::xxh32;
+use crate::utils::leb128;
+use crate::utils::signatures::CHECKPOINT_SIG;
+use crate::utils::signatures::{match_signature, read32, write32};
+
+/// The state of a compression job after some of the pages were written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Identifies the input, such as a hash of its size and modification time.
+    pub fingerprint: u64,
+    /// The compression level.
+    pub level: u8,
+    /// The size of each page.
+    pub page_size: u64,
+    /// The number of pages that were written.
+    pub pages: u64,
+    /// The size of the output after the last page that was written.
+    pub offset: u64,
+}
+
+impl Checkpoint {
+    /// Return True if the job in 'other' compresses the same input with the
+    /// same parameters, so it can continue from this checkpoint.
+    pub fn matches(&self, other: &Checkpoint) -> bool {
+        self.fingerprint == other.fingerprint
+            && self.level == other.level
+            && self.page_size == other.page_size
+    }
+
+    /// Serialize the checkpoint. The record ends with a checksum that detects
+    /// records that were only partially written.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend(CHECKPOINT_SIG);
+        leb128::encode(self.fingerprint, &mut output);
+        leb128::encode(self.level as u64, &mut output);
+        leb128::encode(self.page_size, &mut output);
+        leb128::encode(self.pages, &mut output);
+        leb128::encode(self.offset, &mut output);
+        let checksum = xxh32(&output, 0);
+        write32(checksum, &mut output);
+        output
+    }
+
+    /// Deserialize a checkpoint that was saved with 'encode'. Returns None if
+    /// the record is invalid.
+    pub fn decode(input: &[u8]) -> Option<Self> {
+        if !match_signature(input, &CHECKPOINT_SIG) {
+            return None;
+        }
+        let body = &input[..input.len() - 4];
+        if read32(&input[body.len()..])? != xxh32(body, 0) {
+            return None;
+        }
+
+        let mut cursor = CHECKPOINT_SIG.len();
+        let mut next = || -> Option<u64> {
+            let (read, val) = leb128::decode(body.get(cursor..)?)?;
+            cursor += read;
+            Some(val)
+        };
+        let checkpoint = Self {
+            fingerprint: next()?,
+            level: u8::try_from(next()?).ok()?,
+            page_size: next()?,
+            pages: next()?,
+            offset: next()?,
+        };
+        if cursor != body.len() {
+            return None;
+        }
+        Some(checkpoint)
+    }
+}
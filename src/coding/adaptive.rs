@@ -3,12 +3,13 @@
 //! arithmetic encoder that encodes bit after bit with the predicted
 //! probability.
 
+use crate::limits::check_output;
 use crate::models::Model;
 
 use crate::models::mixer::Mixer;
 use crate::utils::signatures::{match_signature, ARITH_SIG};
 use crate::utils::signatures::{read32, write32};
-use crate::{Context, Decoder, Encoder};
+use crate::{restore_on_error, Context, Decoder, Encoder};
 
 use super::arithmetic::{BitonicDecoder, BitonicEncoder};
 
@@ -21,30 +22,95 @@ pub struct AdaptiveArithmeticEncoder<'a> {
     input: &'a [u8],
     /// The output stream.
     output: &'a mut Vec<u8>,
+    /// The max expansion of 'encode_prefix', in percent.
+    max_expansion: Option<u32>,
+    /// Primes the model before the input. See 'with_dict'.
+    dict: &'a [u8],
 }
 
+/// The default max expansion of the coder, in percent of the input.
+/// See 'Context::with_max_expansion'.
+pub const MAX_EXPANSION: u32 = 1;
+
+/// The number of input bytes between the checks of the expansion.
+const CHECK_INTERVAL: usize = 1 << 12;
+
 /// Adaptive Arithmetic Decoder. See AdaptiveArithmeticEncoder for details.
 pub struct AdaptiveArithmeticDecoder<'a> {
     /// The uncompressed input.
     input: &'a [u8],
     /// The output stream.
     output: &'a mut Vec<u8>,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
+    /// Primes the model before the input. See 'with_dict'.
+    dict: &'a [u8],
+}
+
+/// Return a new model that was trained on the bytes of 'dict'. The encoder and
+/// the decoder prime their models in the same way, so the dictionary is not
+/// saved in the stream.
+fn primed_model(dict: &[u8]) -> Mixer {
+    let mut model = Mixer::new();
+    for b in dict {
+        for j in 0..8 {
+            model.update((b >> (7 - j)) & 0x1);
+        }
+    }
+    model
 }
 
 impl<'a> Encoder<'a> for AdaptiveArithmeticEncoder<'a> {
-    fn new(input: &'a [u8], output: &'a mut Vec<u8>, _ctx: Context) -> Self {
-        AdaptiveArithmeticEncoder { input, output }
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
+        AdaptiveArithmeticEncoder {
+            input,
+            output,
+            max_expansion: ctx.max_expansion,
+            dict: &[],
+        }
     }
 
+    /// Encode the whole input, without a limit on the expansion.
     fn encode(&mut self) -> usize {
+        self.encode_with(None).1
+    }
+}
+
+impl<'a> AdaptiveArithmeticEncoder<'a> {
+    /// Prime the model with the bytes of 'dict' before the input, which helps
+    /// small inputs that are similar to the dictionary. The bytes are not
+    /// saved, and the stream must be decoded with the same dictionary (see
+    /// 'AdaptiveArithmeticDecoder::with_dict').
+    pub fn with_dict(mut self, dict: &'a [u8]) -> Self {
+        self.dict = dict;
+        self
+    }
+
+    /// Encode the input, and stop early if the output of an interval of the
+    /// input grows larger than the max expansion of the context (see
+    /// 'Context::with_max_expansion'). The
+    /// stream is a regular stream of the part of the input that was encoded.
+    /// Returns the number of input bytes that were encoded, followed by the
+    /// number of bytes written. The caller encodes the rest of the input.
+    pub fn encode_prefix(&mut self) -> (usize, usize) {
+        self.encode_with(self.max_expansion)
+    }
+
+    /// Encode the input with the max expansion 'max_expansion'. See
+    /// 'encode_prefix'.
+    fn encode_with(&mut self, max_expansion: Option<u32>) -> (usize, usize) {
+        let start = self.output.len();
         self.output.extend(ARITH_SIG);
         write32(self.input.len() as u32, self.output);
         let mut wrote = ARITH_SIG.len() + 4;
+        // The size of the output at the last check of the expansion.
+        let mut checked = wrote;
 
         let mut encoder = BitonicEncoder::new(self.output);
-        let mut model = Mixer::new();
+        let mut model = primed_model(self.dict);
 
         // For each byte:
+        let mut consumed = 0;
         for b in self.input {
             // For each bit:
             for j in 0..8 {
@@ -54,18 +120,40 @@ impl<'a> Encoder<'a> for AdaptiveArithmeticEncoder<'a> {
                 wrote += encoder.encode(bit != 0, p);
                 model.update(bit);
             }
+            consumed += 1;
+
+            // Stop when the model fails to predict the last interval.
+            if let (Some(percent), 0) =
+                (max_expansion, consumed % CHECK_INTERVAL)
+            {
+                let limit = CHECK_INTERVAL as u64 * (100 + percent as u64);
+                if (wrote - checked) as u64 * 100 > limit {
+                    break;
+                }
+                checked = wrote;
+            }
         }
         wrote += encoder.finalize();
-        wrote
+
+        // Save the length of the part that was encoded.
+        if consumed < self.input.len() {
+            let at = start + ARITH_SIG.len();
+            let len = (consumed as u32).to_be_bytes();
+            self.output[at..at + 4].copy_from_slice(&len);
+        }
+        (consumed, wrote)
     }
 }
 
-impl<'a> Decoder<'a> for AdaptiveArithmeticDecoder<'a> {
-    fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
-        AdaptiveArithmeticDecoder { input, output }
+impl<'a> AdaptiveArithmeticDecoder<'a> {
+    /// Decode a stream that was encoded with the dictionary 'dict'.
+    pub fn with_dict(mut self, dict: &'a [u8]) -> Self {
+        self.dict = dict;
+        self
     }
 
-    fn decode(&mut self) -> Option<(usize, usize)> {
+    /// Decode the input. See 'Decoder::decode'.
+    fn decode_impl(&mut self) -> Option<(usize, usize)> {
         let mut cursor = 0;
         // Check the signature.
         if !match_signature(self.input, &ARITH_SIG) {
@@ -74,12 +162,15 @@ impl<'a> Decoder<'a> for AdaptiveArithmeticDecoder<'a> {
         cursor += ARITH_SIG.len();
 
         // Read the length part.
-        let length = read32(&self.input[cursor..])? as usize;
+        let length = check_output(read32(&self.input[cursor..])? as usize)?;
         cursor += 4;
         let stream = &self.input[cursor..];
+        if stream.len() < 4 {
+            return None;
+        }
 
         let mut decoder = BitonicDecoder::new(stream);
-        let mut model = Mixer::new();
+        let mut model = primed_model(self.dict);
 
         let mut wrote = 0;
         // For each byte:
@@ -101,6 +192,26 @@ impl<'a> Decoder<'a> for AdaptiveArithmeticDecoder<'a> {
     }
 }
 
+impl<'a> Decoder<'a> for AdaptiveArithmeticDecoder<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
+        AdaptiveArithmeticDecoder {
+            input,
+            output,
+            read: 0,
+            dict: &[],
+        }
+    }
+
+    fn decode(&mut self) -> Option<(usize, usize)> {
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
+    }
+
+    decoder_state!();
+}
+
 #[test]
 fn test_encoder_decoder_protocol() {
     let text = "this is a message. this is a message.  this is a message.";
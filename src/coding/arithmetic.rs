@@ -53,8 +53,21 @@ impl<'a> BitonicEncoder<'a> {
 
     /// Seal the stream by flushing the state.
     pub fn finalize(&mut self) -> usize {
+        self.finish_segment()
+    }
+
+    /// Seal the current segment by flushing the state, and reset the encoder
+    /// to allow a new segment to be encoded right after this one. The decoder
+    /// consumes exactly the bytes that were written for the segment, so
+    /// segments can be packed back-to-back. See 'BitonicDecoder::start_segment'.
+    /// Return the number of bytes written.
+    pub fn finish_segment(&mut self) -> usize {
         // Encode a zero-probability token which flushes the state.
-        self.encode(true, 0)
+        let wrote = self.encode(true, 0);
+        debug_assert_eq!(wrote, 4, "Expected to flush the whole state");
+        self.low = 0;
+        self.high = 0xffffffff;
+        wrote
     }
 
     /// Only use this method for testing.
@@ -85,23 +98,40 @@ pub struct BitonicDecoder<'a> {
 impl<'a> BitonicDecoder<'a> {
     pub fn new(input: &'a [u8]) -> Self {
         assert!(input.len() >= 4);
-        let mut cursor = 0;
-        let mut state: u32 = 0;
-        for _ in 0..4 {
-            state = state << 8 | input[cursor] as u32;
-            cursor += 1;
-        }
-
-        Self {
+        let mut decoder = Self {
             input,
-            cursor,
+            cursor: 0,
             low: 0,
             high: 0xffffffff,
-            state,
+            state: 0,
+        };
+        decoder.start_segment().unwrap();
+        decoder
+    }
+
+    /// Start decoding a new segment that begins at the current location in the
+    /// input. This is used for decoding segments that were sealed with
+    /// 'BitonicEncoder::finish_segment' and packed back-to-back. Returns None
+    /// if there are not enough bytes in the input.
+    #[must_use]
+    pub fn start_segment(&mut self) -> Option<()> {
+        if self.input.len() < self.cursor + 4 {
+            return None;
+        }
+        let mut state: u32 = 0;
+        for _ in 0..4 {
+            state = state << 8 | self.input[self.cursor] as u32;
+            self.cursor += 1;
         }
+        self.low = 0;
+        self.high = 0xffffffff;
+        self.state = state;
+        Some(())
     }
 
-    /// Return the number of bytes consumed from the input.
+    /// Return the number of bytes consumed from the input. After the last bit
+    /// of a segment is decoded this is the exact offset of the end of the
+    /// segment.
     pub fn read(&self) -> usize {
         self.cursor
     }
@@ -208,3 +238,45 @@ fn test_encoder_decoder_array() {
         assert_eq!(res, test_vector);
     }
 }
+
+#[test]
+fn test_encoder_decoder_segments() {
+    let p = 60000_u16;
+    let q = 1400_u16;
+    let segments: [(Vec<bool>, u16); 3] = [
+        (vec![true, false, true, true, false, true], p),
+        (vec![false; 100], q),
+        (
+            vec![true, true, false, false, true, false, true, false],
+            32768,
+        ),
+    ];
+
+    // Pack the segments back-to-back and record where each segment ends.
+    let mut stream = Vec::new();
+    let mut ends = Vec::new();
+    {
+        let mut encoder = BitonicEncoder::new(&mut stream);
+        let mut wrote = 0;
+        for (bits, prob) in segments.iter() {
+            for bit in bits {
+                wrote += encoder.encode(*bit, *prob);
+            }
+            wrote += encoder.finish_segment();
+            ends.push(wrote);
+        }
+    }
+    assert_eq!(*ends.last().unwrap(), stream.len());
+
+    let mut decoder = BitonicDecoder::new(&stream);
+    for (i, (bits, prob)) in segments.iter().enumerate() {
+        if i > 0 {
+            decoder.start_segment().unwrap();
+        }
+        for bit in bits {
+            assert_eq!(decoder.decode(*prob).unwrap(), *bit);
+        }
+        assert_eq!(decoder.read(), ends[i]);
+    }
+    assert!(decoder.start_segment().is_none());
+}
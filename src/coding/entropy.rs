@@ -2,35 +2,78 @@
 
 use crate::bitvector::Bitvector;
 use crate::coding::hist::{num_bits, Histogram};
-use crate::{Context, Decoder, Encoder};
+use crate::scratch::{recycle_u16, take_u16};
+use crate::utils::leb128;
+use crate::utils::unchecked;
+use crate::{restore_on_error, Context, Decoder, Encoder};
+
+/// The number of interleaved sub-streams in the split encoding.
+pub const SPLIT_STREAMS: usize = 4;
+
+/// Streams of this level and above spread the symbols with the precise layout.
+/// See 'spread_precise'.
+pub const PRECISE_SPREAD_LEVEL: u8 = 10;
 
 type DecodeTable = Vec<(u32, u8)>;
 
+/// The number of bits that the encoder shifts out of a state is the sum of
+/// the state and the delta of the symbol, shifted right by this value. See
+/// 'get_num_bits'.
+const RENORM_SHIFT: u32 = 16;
+
 /// A class that creates the encode/decode table and is used by the encoder and
 /// decoder.
 struct Coder<const ALPHABET: usize, const TABLESIZE: usize> {
     /// This is the main encoder table.
     /// A table of [Symbol x State] (use the get_state accessor).
     encode_table: Vec<u16>,
-    /// Maps symbol to the max state that can encode this symbol, and the number
-    /// of bits that we need to shift the state to get from the upper part of
-    /// the table down to the encode-able state.
-    max_state: Vec<(u16, u16)>,
+    /// Maps symbol to the delta that computes the number of bits that we need
+    /// to shift the state to get from the upper part of the table down to the
+    /// encode-able state (see 'get_num_bits').
+    renorm: Vec<u32>,
     /// This is the main decoder table.
     /// Maps each state to (next_state, sym)
     decode_table: DecodeTable,
     /// The normalized histogram
     norm_hist: Vec<u32>,
+    /// Spread the symbols with the precise layout instead of the prime step.
+    precise: bool,
 }
 
 impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
+    /// Create a coder. The tables are allocated when the coder is initialized,
+    /// so decoders don't allocate them for invalid streams.
     pub fn new() -> Self {
         Self {
-            encode_table: vec![0; ALPHABET * TABLESIZE * 2],
-            max_state: vec![(0, 0); ALPHABET],
-            decode_table: vec![(0, 0); TABLESIZE * 2],
+            encode_table: Vec::new(),
+            renorm: Vec::new(),
+            decode_table: Vec::new(),
             norm_hist: Vec::new(),
+            precise: false,
+        }
+    }
+
+    /// Allocate the tables, if they were not allocated yet. The encode table
+    /// is large, so it is taken from the scratch pool.
+    fn allocate(&mut self) {
+        if self.encode_table.is_empty() {
+            self.encode_table = take_u16();
+            self.encode_table.resize(ALPHABET * TABLESIZE * 2, 0);
+            self.renorm.resize(ALPHABET, 0);
+            self.decode_table.resize(TABLESIZE * 2, (0, 0));
+        }
+    }
+
+    /// Clear the tables to allow the coder to be initialized again. The
+    /// allocated memory is kept.
+    pub fn reset(&mut self) {
+        if self.norm_hist.is_empty() {
+            return;
         }
+        self.encode_table.fill(0);
+        self.renorm.fill(0);
+        self.decode_table.fill((0, 0));
+        self.norm_hist.clear();
     }
 
     /// Check if 'state' is a valid state.
@@ -39,20 +82,35 @@ impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
     }
 
     /// Initialize the coder with the input data and create the
-    /// encoder/decoder tables.
-    pub fn init_from_input(&mut self, input: &[u8]) {
-        let mut hist = Histogram::<ALPHABET>::from_data(input);
+    /// encoder/decoder tables, with the precise layout if 'precise' is set.
+    /// Returns None if the input contains symbols that are outside of the
+    /// alphabet.
+    #[must_use]
+    pub fn init_from_input(
+        &mut self,
+        input: &[u8],
+        precise: bool,
+    ) -> Option<()> {
+        let mut hist = Histogram::<ALPHABET>::try_from_data(input)?;
         hist.normalize(TABLESIZE);
         let norm_hist = hist.get_bins();
-        self.init_from_histogram(norm_hist)
+        self.init_from_histogram(norm_hist, precise);
+        Some(())
     }
 
-    /// Create the encode/decode tables from a valid normalized histogram.
-    pub fn init_from_histogram(&mut self, norm_hist: &[u32]) {
+    /// Create the encode/decode tables from a valid normalized histogram. The
+    /// symbols are spread with the precise layout if 'precise' is set.
+    pub fn init_from_histogram(&mut self, norm_hist: &[u32], precise: bool) {
         assert!(Self::is_valid_histogram(norm_hist));
         assert!(self.norm_hist.is_empty(), "Can't init the coder twice");
+        self.allocate();
         self.norm_hist.extend(norm_hist.iter());
-        let state_list = self.spread_symbols(norm_hist);
+        self.precise = precise;
+        let state_list = if precise {
+            Self::spread_precise(norm_hist)
+        } else {
+            self.spread_symbols(norm_hist)
+        };
         self.create_tables(norm_hist, &state_list);
     }
 
@@ -85,26 +143,50 @@ impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
         }
         // The lowest common denominator of the table at the prime is 1,
         // so we know that the cycle size will be the size of the table.
-        debug_assert!(pos % TABLESIZE == 0);
+        debug_assert!(pos.is_multiple_of(TABLESIZE));
         state_table
     }
 
+    /// Spread the symbols with the precise layout of Jarek Duda, which places
+    /// the i-th state of a symbol of frequency F near (i + 0.5) / F of the
+    /// table. The states of all of the symbols are sorted by this fraction. The
+    /// layout is closer to the ideal spacing than the prime step, which saves
+    /// a few bytes on skewed histograms, but sorting the states is slower.
+    fn spread_precise(sym_occurrences: &[u32]) -> Vec<u8> {
+        // The fraction is saved in the high bits of the key, and the symbol
+        // in the low 8 bits, which breaks the ties.
+        let mut keys: Vec<u64> = Vec::with_capacity(TABLESIZE);
+        for (sym, &occ) in sym_occurrences.iter().enumerate() {
+            for i in 0..occ as u64 {
+                let fraction = ((2 * i + 1) << 40) / (2 * occ as u64);
+                keys.push(fraction << 8 | sym as u64);
+            }
+        }
+        keys.sort_unstable();
+        keys.iter().map(|key| *key as u8).collect()
+    }
+
     /// Return a reference to the encoding table.
     pub fn get_enc_state(&mut self, sym: usize, state: usize) -> &mut u16 {
         debug_assert!(sym < ALPHABET && state < TABLESIZE * 2);
         &mut self.encode_table[(sym * TABLESIZE * 2) + state]
     }
 
-    /// Return the max state and the number of shift bits for each symbol.
-    pub fn get_max_state(&self, sym: usize) -> (u32, u32) {
-        let p = self.max_state[sym];
-        (p.0 as u32, p.1 as u32)
+    /// Return the number of bits that bring 'state' down to the encode-able
+    /// range of the symbol 'sym'. The states below the threshold of the symbol
+    /// shift out 'bits' bits, and the states above it shift one more bit. The
+    /// delta is ((bits + 1) << 16) - threshold, so the sum carries into the
+    /// bit count exactly at the threshold, without branches.
+    pub fn get_num_bits(&self, sym: usize, state: u32) -> u32 {
+        (state + self.renorm[sym]) >> RENORM_SHIFT
     }
 
     /// Given given 'state', a state in the decode table, the method returns a
     /// pair of (new_state, sym) for the decoded symbol and the next state.
     pub fn get_dec_state(&self, state: usize) -> (u32, u8) {
-        self.decode_table[state]
+        // The states have at most one bit more than the table, and the table
+        // has two entries for each state of the table.
+        unchecked::get(&self.decode_table, state)
     }
 
     /// Creates the encode/decode tables. 'normalized_occurrences' is the
@@ -129,10 +211,7 @@ impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
         }
 
         // For each state in the table:
-        for to_state in 0..TABLESIZE {
-            // Map the state to the symbol:
-            let sym = state_list[to_state];
-
+        for (to_state, &sym) in state_list.iter().enumerate() {
             // Keep track the highest state for each symbol.
             let from_state = max_state[sym as usize];
             max_state[sym as usize] += 1;
@@ -148,16 +227,17 @@ impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
 
         // Record how many bits we need to shift the state, which is (at the
         // time of encoding) in the upper part of the table, down to the
-        // encode-able range, which is (F..2F).
-        for sym in 0..ALPHABET {
+        // encode-able range, which is (F..2F). States from the threshold and
+        // up need one more bit.
+        debug_assert!(TABLESIZE * 2 <= 1 << RENORM_SHIFT);
+        for (sym, max) in max_state.iter().enumerate() {
+            // The states for the symbols are spread between F and 2F.
+            let f = norm_hist[sym];
+            debug_assert!(f == 0 || *max == f * 2 - 1);
             let table_bits = num_bits(TABLESIZE as u32);
-            let max_state_bits = num_bits(max_state[sym]);
-            let shift_bits = if table_bits > max_state_bits {
-                table_bits - max_state_bits
-            } else {
-                0
-            };
-            self.max_state[sym] = (max_state[sym] as u16, shift_bits as u16);
+            let shift_bits = table_bits.saturating_sub(num_bits(*max));
+            let threshold = max << shift_bits;
+            self.renorm[sym] = ((shift_bits + 1) << RENORM_SHIFT) - threshold;
         }
 
         if cfg!(debug_assertions) {
@@ -186,21 +266,31 @@ impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
 
         // Check that the symbols are placed in the range F..2F, where F is
         // the normalized frequency.
-        for sym in 0..ALPHABET {
-            if norm_hist[sym] == 0 {
+        for (sym, &f) in norm_hist.iter().enumerate() {
+            if f == 0 {
                 continue;
             }
             // Reference make_tables1 by cbloom
             // https://www.cbloom.com/src/ans_learning.cpp
-            let max_state = self.get_max_state(sym).0;
-            let f = norm_hist[sym];
-            // The states for the symbols are spread between F and 2F.
-            debug_assert!(max_state == f * 2 - 1);
-            // Check that the step that brings the state down works.
-            let above = max_state;
-            let next_iter = above / 2;
-            let entry = self.get_enc_state(sym, next_iter as usize);
-            Self::check_state(*entry as usize);
+            // Check that the step that brings the state down works, for the
+            // lowest and the highest states, and around the threshold.
+            let bits = self.get_num_bits(sym, TABLESIZE as u32);
+            let threshold = (f * 2 - 1) << bits;
+            for state in [TABLESIZE as u32, TABLESIZE as u32 * 2 - 1, threshold]
+            {
+                for state in [state.saturating_sub(1), state] {
+                    if state < TABLESIZE as u32 || state >= TABLESIZE as u32 * 2
+                    {
+                        continue;
+                    }
+                    let bits = self.get_num_bits(sym, state);
+                    let next = (state >> bits) as usize;
+                    debug_assert!(
+                        next < f as usize * 2 - 1 && next >= f as usize - 1
+                    );
+                    Self::check_state(*self.get_enc_state(sym, next) as usize);
+                }
+            }
         }
     }
 }
@@ -208,32 +298,107 @@ impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
 impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
     /// Serialize the normalized histogram as a list of variable-length encoded
     /// integers. Each number is encoded as a sequence of numbers until a number
-    /// below 255 is found (just like the lz4 encoding).
-    /// Return the number of bytes saved.
+    /// below 255 is found (just like the lz4 encoding). The histogram is
+    /// followed by a byte that selects the layout of the symbols (see
+    /// 'spread_precise'). Return the number of bytes saved.
     fn serialize(&mut self, output: &mut Vec<u8>) -> usize {
         use crate::utils::variable_length_encoding::encode;
         let mut written = 0;
         for elem in &self.norm_hist {
             written += encode(*elem, output);
         }
-        written
+        output.push(self.precise as u8);
+        written + 1
     }
 
     /// Load the serialized normalized histogram. This uses the lz4 variable
-    /// length encoding. Check the encoder for more details.
-    fn deserialize(input: &[u8]) -> Option<(Vec<u32>, usize)> {
+    /// length encoding. Check the encoder for more details. Returns None as
+    /// soon as the counts exceed the size of the table, so invalid streams are
+    /// rejected before the tables are built. Returns the histogram, the layout
+    /// of the symbols and the number of bytes read.
+    fn deserialize(input: &[u8]) -> Option<(Vec<u32>, bool, usize)> {
         use crate::utils::variable_length_encoding::decode;
 
         let mut cursor = 0;
-        let mut result: Vec<u32> = Vec::new();
+        let mut sum: u64 = 0;
+        let mut result: Vec<u32> = Vec::with_capacity(ALPHABET);
 
         // For each symbol:
         for _ in 0..ALPHABET {
             let (read, val) = decode(&input[cursor..])?;
             cursor += read;
+            sum += val as u64;
+            if sum > TABLESIZE as u64 {
+                return None;
+            }
             result.push(val);
         }
-        Some((result, cursor))
+        if sum != TABLESIZE as u64 {
+            return None;
+        }
+        let layout = *input.get(cursor)?;
+        if layout > 1 {
+            return None;
+        }
+        Some((result, layout == 1, cursor + 1))
+    }
+}
+
+/// Return the normalized histogram that the encoded stream 'input' starts
+/// with, or None if the stream doesn't start with a valid histogram. The
+/// counts of the histogram sum to TABLESIZE.
+pub fn read_histogram<const ALPHABET: usize, const TABLESIZE: usize>(
+    input: &[u8],
+) -> Option<Vec<u32>> {
+    let (hist, _, _) = Coder::<ALPHABET, TABLESIZE>::deserialize(input)?;
+    if !Coder::<ALPHABET, TABLESIZE>::is_valid_histogram(&hist) {
+        return None;
+    }
+    Some(hist)
+}
+
+impl<const ALPHABET: usize, const TABLESIZE: usize> Drop
+    for Coder<ALPHABET, TABLESIZE>
+{
+    fn drop(&mut self) {
+        recycle_u16(std::mem::take(&mut self.encode_table));
+    }
+}
+
+/// The encode and decode tables of the entropy coder. The tables are expensive
+/// to allocate and build, so callers that encode many short inputs can move the
+/// tables from one encoder or decoder to the next. See 'with_tables'.
+pub struct EntropyTables<const ALPHABET: usize, const TABLESIZE: usize> {
+    coder: Coder<ALPHABET, TABLESIZE>,
+}
+
+impl<const ALPHABET: usize, const TABLESIZE: usize>
+    EntropyTables<ALPHABET, TABLESIZE>
+{
+    pub fn new() -> Self {
+        Self {
+            coder: Coder::new(),
+        }
+    }
+}
+
+impl<const ALPHABET: usize, const TABLESIZE: usize> Default
+    for EntropyTables<ALPHABET, TABLESIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ALPHABET: usize, const TABLESIZE: usize> Coder<ALPHABET, TABLESIZE> {
+    /// Initialize the coder with the normalized histogram 'norm_hist', unless
+    /// the tables were already built for this histogram. Predefined tables
+    /// always use the prime-step layout.
+    fn init_cached(&mut self, norm_hist: &[u32]) {
+        if self.norm_hist != norm_hist || self.precise {
+            self.reset();
+            self.init_from_histogram(norm_hist, false);
+        }
     }
 }
 
@@ -246,6 +411,8 @@ pub struct EntropyEncoder<'a, const ALPHABET: usize, const TABLESIZE: usize> {
     output: &'a mut Vec<u8>,
     /// The coder that manages the encode/decode tables.
     coder: Coder<ALPHABET, TABLESIZE>,
+    /// Spread the symbols with the precise layout. See 'PRECISE_SPREAD_LEVEL'.
+    precise: bool,
 }
 
 impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
@@ -262,10 +429,42 @@ impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
         bv.push_word(val as u64, table_log);
     }
 
-    /// Encode the input buffer and return the output.
-    fn encode_impl(&mut self) -> usize {
+    /// Prepare the encoder for encoding the new input 'input'. The encoded
+    /// stream is appended to the same output stream. This reuses the tables of
+    /// the coder, which are expensive to allocate.
+    pub fn reset(&mut self, input: &'a [u8]) {
+        self.input = input;
+        self.coder.reset();
+    }
+
+    /// Create an encoder that uses the tables 'tables' of an earlier encoder
+    /// or decoder, instead of allocating new tables. The symbols are spread
+    /// with the prime-step layout.
+    pub fn with_tables(
+        input: &'a [u8],
+        output: &'a mut Vec<u8>,
+        tables: EntropyTables<ALPHABET, TABLESIZE>,
+    ) -> Self {
+        Self {
+            input,
+            output,
+            coder: tables.coder,
+            precise: false,
+        }
+    }
+
+    /// Return the tables of the encoder, for reuse by another encoder.
+    pub fn into_tables(self) -> EntropyTables<ALPHABET, TABLESIZE> {
+        EntropyTables { coder: self.coder }
+    }
+
+    /// Encode the input buffer and return the number of bytes written, or
+    /// None if the input contains symbols that are outside of the alphabet.
+    /// Nothing is written to the output stream if the encoding fails.
+    pub fn try_encode(&mut self) -> Option<usize> {
         // Initialize the coder.
-        self.coder.init_from_input(self.input);
+        self.coder.reset();
+        self.coder.init_from_input(self.input, self.precise)?;
 
         let mut bv = Bitvector::new();
         // Encode the data.
@@ -274,7 +473,60 @@ impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
         // Serialize the coder and the bitstream.
         let mut wrote = self.coder.serialize(self.output);
         wrote += bv.serialize(self.output);
-        wrote
+        Some(wrote)
+    }
+
+    /// Encode the input buffer with the predefined normalized histogram
+    /// 'norm_hist', which is not saved in the output stream. This saves the
+    /// cost of the table for short inputs. Returns the number of bytes written,
+    /// or None if the histogram is invalid or can't encode the input.
+    pub fn try_encode_with_table(
+        &mut self,
+        norm_hist: &[u32],
+    ) -> Option<usize> {
+        if !Coder::<ALPHABET, TABLESIZE>::is_valid_histogram(norm_hist) {
+            return None;
+        }
+        if self.input.iter().any(|sym| norm_hist[*sym as usize] == 0) {
+            return None;
+        }
+        self.coder.init_cached(norm_hist);
+
+        let mut bv = Bitvector::new();
+        self.encode_data(self.input, &mut bv);
+        Some(bv.serialize(self.output))
+    }
+
+    /// Encode the input buffer as 'SPLIT_STREAMS' interleaved sub-streams that
+    /// share one table, like the four streams of Huff0. Symbol 'i' is encoded
+    /// in sub-stream 'i % SPLIT_STREAMS'. The sizes of the sub-streams are
+    /// saved after the table, so the decoder can locate the sub-streams and
+    /// decode them without serial dependencies between them. Returns the
+    /// number of bytes written, or None if the input contains symbols that are
+    /// outside of the alphabet.
+    pub fn try_encode_split(&mut self) -> Option<usize> {
+        self.coder.reset();
+        self.coder.init_from_input(self.input, self.precise)?;
+
+        let mut streams: Vec<u8> = Vec::new();
+        let mut sizes = [0; SPLIT_STREAMS];
+        let mut symbols: Vec<u8> = Vec::new();
+        for (i, size) in sizes.iter_mut().enumerate() {
+            symbols.clear();
+            symbols.extend(self.input.iter().skip(i).step_by(SPLIT_STREAMS));
+            let mut bv = Bitvector::new();
+            self.encode_data(&symbols, &mut bv);
+            *size = bv.serialize(&mut streams);
+        }
+
+        // Serialize the coder, the jump table and the sub-streams. The size of
+        // the last sub-stream is implied.
+        let mut wrote = self.coder.serialize(self.output);
+        for size in &sizes[..SPLIT_STREAMS - 1] {
+            wrote += leb128::encode(*size as u64, self.output);
+        }
+        self.output.extend(&streams);
+        Some(wrote + streams.len())
     }
 
     // Encode a single symbol (character).
@@ -294,10 +546,9 @@ impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
         //    bv.push_word(state, 1);
         //    state /= 2;
         //   }
-        let (max_state, mut num_bits) = self.coder.get_max_state(sym as usize);
-        if (*state >> num_bits) >= max_state {
-            num_bits += 1;
-        }
+        // The pre-computed delta gives the number of bits with one add and one
+        // shift, and all of the bits are pushed at once.
+        let num_bits = self.coder.get_num_bits(sym as usize, *state);
 
         bv.push_word(*state as u64, num_bits as usize);
         *state >>= num_bits;
@@ -316,31 +567,119 @@ pub struct EntropyDecoder<'a, const ALPHABET: usize, const TABLESIZE: usize> {
     output: &'a mut Vec<u8>,
     /// The coder that manages the encode/decode tables.
     coder: Coder<ALPHABET, TABLESIZE>,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
 }
 
 /// Serialization methods.
 impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
     EntropyDecoder<'a, ALPHABET, TABLESIZE>
 {
+    /// Prepare the decoder for decoding the new input 'input'. The decoded
+    /// data is appended to the same output stream. This reuses the tables of
+    /// the coder, which are expensive to allocate.
+    pub fn reset(&mut self, input: &'a [u8]) {
+        self.input = input;
+        self.read = 0;
+        self.coder.reset();
+    }
+
+    /// Create a decoder that uses the tables 'tables' of an earlier encoder
+    /// or decoder, instead of allocating new tables.
+    pub fn with_tables(
+        input: &'a [u8],
+        output: &'a mut Vec<u8>,
+        tables: EntropyTables<ALPHABET, TABLESIZE>,
+    ) -> Self {
+        Self {
+            input,
+            output,
+            coder: tables.coder,
+            read: 0,
+        }
+    }
+
+    /// Return the tables of the decoder, for reuse by another decoder.
+    pub fn into_tables(self) -> EntropyTables<ALPHABET, TABLESIZE> {
+        EntropyTables { coder: self.coder }
+    }
+
     /// Try to decode the input, and return the number of bytes read and written
     /// if the encoding was a valid encoding.
     fn decode_impl(&mut self) -> Option<(usize, usize)> {
         if self.input.is_empty() {
             return None;
         }
+        self.coder.reset();
 
         // Deserialize the normalized histogram.
-        let (hist, read) =
+        let (hist, precise, read) =
             Coder::<ALPHABET, TABLESIZE>::deserialize(self.input)?;
         if !Coder::<ALPHABET, TABLESIZE>::is_valid_histogram(&hist) {
             return None;
         }
-        self.coder.init_from_histogram(&hist);
+        self.coder.init_from_histogram(&hist, precise);
 
         let (mut bv, read1) = Bitvector::deserialize(&self.input[read..])?;
         let written = self.decode_data(&mut bv)?;
         Some((read + read1, written))
     }
+
+    /// Decode an input that was encoded with 'try_encode_with_table' and the
+    /// normalized histogram 'norm_hist'. Returns the number of bytes read and
+    /// written if the encoding was valid.
+    pub fn decode_with_table(
+        &mut self,
+        norm_hist: &[u32],
+    ) -> Option<(usize, usize)> {
+        if !Coder::<ALPHABET, TABLESIZE>::is_valid_histogram(norm_hist) {
+            return None;
+        }
+        self.coder.init_cached(norm_hist);
+
+        let (mut bv, read) = Bitvector::deserialize(self.input)?;
+        let written = self.decode_data(&mut bv)?;
+        Some((read, written))
+    }
+
+    /// Decode an input that was encoded with 'try_encode_split', and return
+    /// the number of bytes read and written if the encoding was valid.
+    pub fn decode_split(&mut self) -> Option<(usize, usize)> {
+        if self.input.is_empty() {
+            return None;
+        }
+        self.coder.reset();
+
+        let (hist, precise, mut read) =
+            Coder::<ALPHABET, TABLESIZE>::deserialize(self.input)?;
+        if !Coder::<ALPHABET, TABLESIZE>::is_valid_histogram(&hist) {
+            return None;
+        }
+        self.coder.init_from_histogram(&hist, precise);
+
+        // Read the jump table.
+        let mut sizes = [0; SPLIT_STREAMS - 1];
+        for size in sizes.iter_mut() {
+            let (len, val) = leb128::decode_len(&self.input[read..])?;
+            *size = val;
+            read += len;
+        }
+
+        // Load the sub-streams.
+        let mut streams: Vec<Bitvector> = Vec::new();
+        for i in 0..SPLIT_STREAMS {
+            let start = read;
+            let (bv, bv_read) = Bitvector::deserialize(&self.input[read..])?;
+            read += bv_read;
+            if i < sizes.len() && sizes[i] != read - start {
+                return None;
+            }
+            streams.push(bv);
+        }
+
+        let written = self.decode_split_data(&mut streams)?;
+        Some((read, written))
+    }
 }
 
 /// Compression logic methods.
@@ -382,10 +721,54 @@ impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
         Some(sym)
     }
 
+    /// Read the interleaved sub-streams from the bitvectors 'streams'. The
+    /// states of the sub-streams are independent, so the symbols of the
+    /// different sub-streams can be decoded in parallel.
+    #[must_use]
+    fn decode_split_data(
+        &mut self,
+        streams: &mut [Bitvector],
+    ) -> Option<usize> {
+        let table_log = num_bits(TABLESIZE as u32 - 1) as usize;
+        // The states index the decode table without bounds checks.
+        assert_eq!(self.coder.decode_table.len(), TABLESIZE * 2);
+        let mut states = [0u32; SPLIT_STREAMS];
+        for (state, bv) in states.iter_mut().zip(streams.iter_mut()) {
+            if bv.len() < table_log {
+                return None;
+            }
+            *state = TABLESIZE as u32 + bv.pop_word(table_log) as u32;
+        }
+
+        // Decode one symbol from each sub-stream in turn. Shorter sub-streams
+        // may only end after the longer ones.
+        let mut written = 0;
+        let mut done = false;
+        loop {
+            for (state, bv) in states.iter_mut().zip(streams.iter_mut()) {
+                if bv.is_empty() {
+                    done = true;
+                    continue;
+                }
+                if done {
+                    return None;
+                }
+                let sym = self.decode_one_symbol(bv, state)?;
+                self.output.push(sym);
+                written += 1;
+            }
+            if done {
+                return Some(written);
+            }
+        }
+    }
+
     /// Read a string from the bitvector.
     #[must_use]
     fn decode_data(&mut self, bv: &mut Bitvector) -> Option<usize> {
         let table_log = num_bits(TABLESIZE as u32 - 1) as usize;
+        // The states index the decode table without bounds checks.
+        assert_eq!(self.coder.decode_table.len(), TABLESIZE * 2);
         if bv.len() < table_log {
             return None;
         }
@@ -403,16 +786,20 @@ impl<'a, const ALPHABET: usize, const TABLESIZE: usize>
 impl<'a, const ALPHABET: usize, const TABLESIZE: usize> Encoder<'a>
     for EntropyEncoder<'a, ALPHABET, TABLESIZE>
 {
-    fn new(input: &'a [u8], output: &'a mut Vec<u8>, _ctx: Context) -> Self {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
         EntropyEncoder {
             input,
             output,
             coder: Coder::new(),
+            precise: ctx.level >= PRECISE_SPREAD_LEVEL,
         }
     }
 
+    /// Encode the input buffer. Panics if the input contains symbols that are
+    /// outside of the alphabet. See 'try_encode'.
     fn encode(&mut self) -> usize {
-        self.encode_impl()
+        self.try_encode()
+            .expect("Symbol is outside of the alphabet")
     }
 }
 
@@ -424,10 +811,16 @@ impl<'a, const ALPHABET: usize, const TABLESIZE: usize> Decoder<'a>
             input,
             output,
             coder: Coder::new(),
+            read: 0,
         }
     }
 
     fn decode(&mut self) -> Option<(usize, usize)> {
-        self.decode_impl()
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
     }
+
+    decoder_state!();
 }
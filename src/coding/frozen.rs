@@ -0,0 +1,257 @@
+//! This module implements an arithmetic coder with a model that does not
+//! adapt. The model is trained once on a prefix of the input, and is saved in
+//! the stream. The pages of the input are then coded independently with the
+//! frozen model, so they are encoded and decoded on all of the cores. This
+//! loses some of the ratio of the adaptive coder (see 'adaptive'), which
+//! learns the whole input, and is much faster on multi-core machines.
+//!
+//! The model predicts each bit of a byte in the context of the previous bits
+//! of the byte and of the high bits of the previous byte. The probabilities
+//! are saved with 8 bits of precision.
+
+use super::arithmetic::{BitonicDecoder, BitonicEncoder};
+use crate::limits::check_output;
+use crate::utils::leb128;
+use crate::utils::signatures::{match_signature, FROZEN_SIG};
+use crate::{restore_on_error, Context, Decoder, Encoder};
+use std::thread;
+
+/// The number of high bits of the previous byte that select the context.
+const PREV_BITS: usize = 5;
+
+/// The number of probabilities of the model: a bit tree of 256 nodes for each
+/// context of the previous byte.
+const MODEL_LEN: usize = 256 << PREV_BITS;
+
+/// The max number of bytes that the model is trained on.
+pub const TRAIN_LEN: usize = 1 << 20;
+
+/// A model of the bits of the input that does not adapt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrozenModel {
+    /// The probability of a one bit in each context, in 1/256 units.
+    probs: Vec<u8>,
+}
+
+impl FrozenModel {
+    /// Return the index of the probability of the bit tree node 'node' after
+    /// the byte 'prev'.
+    fn index(prev: u8, node: usize) -> usize {
+        ((prev as usize >> (8 - PREV_BITS)) << 8) | node
+    }
+
+    /// Train a model on the bytes of 'sample'.
+    pub fn train(sample: &[u8]) -> Self {
+        let mut ones = vec![0u32; MODEL_LEN];
+        let mut total = vec![0u32; MODEL_LEN];
+        let mut prev = 0;
+        for &byte in sample {
+            let mut node = 1;
+            for j in 0..8 {
+                let bit = (byte >> (7 - j)) as usize & 0x1;
+                let idx = Self::index(prev, node);
+                ones[idx] += bit as u32;
+                total[idx] += 1;
+                node = node * 2 + bit;
+            }
+            prev = byte;
+        }
+        let probs = ones
+            .iter()
+            .zip(&total)
+            .map(|(ones, total)| {
+                let prob = (*ones as u64 * 256 + 128) / (*total as u64 + 1);
+                prob.clamp(1, 255) as u8
+            })
+            .collect();
+        Self { probs }
+    }
+
+    /// Return the probability of a one bit in the 16-bit range.
+    fn predict(&self, prev: u8, node: usize) -> u16 {
+        ((self.probs[Self::index(prev, node)] as u16) << 8) | 0x80
+    }
+
+    /// Save the model to 'output'. Returns the number of bytes written.
+    pub fn serialize(&self, output: &mut Vec<u8>) -> usize {
+        output.extend(&self.probs);
+        self.probs.len()
+    }
+
+    /// Load a model that was saved with 'serialize'. Returns the model and the
+    /// number of bytes read, or None if the input is invalid.
+    pub fn deserialize(input: &[u8]) -> Option<(Self, usize)> {
+        let probs = input.get(..MODEL_LEN)?;
+        if probs.contains(&0) {
+            return None;
+        }
+        let probs = probs.to_vec();
+        Some((Self { probs }, MODEL_LEN))
+    }
+
+    /// Encode the page 'page' and return the stream.
+    fn encode_page(&self, page: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut encoder = BitonicEncoder::new(&mut output);
+        let mut prev = 0;
+        for &byte in page {
+            let mut node = 1;
+            for j in 0..8 {
+                let bit = (byte >> (7 - j)) & 0x1;
+                let _ = encoder.encode(bit != 0, self.predict(prev, node));
+                node = node * 2 + bit as usize;
+            }
+            prev = byte;
+        }
+        let _ = encoder.finalize();
+        output
+    }
+
+    /// Decode 'len' bytes from the page stream 'stream', which must be
+    /// consumed exactly. Returns None if the stream is invalid.
+    fn decode_page(&self, stream: &[u8], len: usize) -> Option<Vec<u8>> {
+        if stream.len() < 4 {
+            return None;
+        }
+        let mut decoder = BitonicDecoder::new(stream);
+        let mut page = Vec::with_capacity(len);
+        let mut prev = 0;
+        for _ in 0..len {
+            let mut node = 1;
+            for _ in 0..8 {
+                let bit = decoder.decode(self.predict(prev, node))?;
+                node = node * 2 + bit as usize;
+            }
+            prev = (node & 0xff) as u8;
+            page.push(prev);
+        }
+        if decoder.read() != stream.len() {
+            return None;
+        }
+        Some(page)
+    }
+}
+
+/// Apply 'f' to each of the items of 'items' on all of the cores, and return
+/// the results in the order of the items.
+fn map_parallel<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = items.len().div_ceil(threads).max(1);
+    thread::scope(|s| {
+        let workers: Vec<_> = items
+            .chunks(per_thread)
+            .map(|chunk| s.spawn(|| chunk.iter().map(&f).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Encodes the input with a model that is trained on a prefix of the input.
+/// The pages are 'ctx.block_size' bytes.
+pub struct FrozenEncoder<'a> {
+    /// The uncompressed input.
+    input: &'a [u8],
+    /// The output stream.
+    output: &'a mut Vec<u8>,
+    /// Encoder context.
+    ctx: Context,
+}
+
+/// Decodes the streams of 'FrozenEncoder'.
+pub struct FrozenDecoder<'a> {
+    /// The compressed input.
+    input: &'a [u8],
+    /// The output stream.
+    output: &'a mut Vec<u8>,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
+}
+
+impl<'a> Encoder<'a> for FrozenEncoder<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
+        FrozenEncoder { input, output, ctx }
+    }
+
+    fn encode(&mut self) -> usize {
+        assert!(self.ctx.block_size > 0, "Must set page size");
+        let start = self.output.len();
+        let model =
+            FrozenModel::train(&self.input[..TRAIN_LEN.min(self.input.len())]);
+        self.output.extend(FROZEN_SIG);
+        leb128::encode(self.input.len() as u64, self.output);
+        leb128::encode(self.ctx.block_size as u64, self.output);
+        model.serialize(self.output);
+
+        let pages: Vec<&[u8]> =
+            self.input.chunks(self.ctx.block_size).collect();
+        let streams = map_parallel(&pages, |page| model.encode_page(page));
+        for stream in streams {
+            leb128::encode(stream.len() as u64, self.output);
+            self.output.extend(stream);
+        }
+        self.output.len() - start
+    }
+}
+
+impl<'a> FrozenDecoder<'a> {
+    /// Decode the input. See 'Decoder::decode'.
+    fn decode_impl(&mut self) -> Option<(usize, usize)> {
+        if !match_signature(self.input, &FROZEN_SIG) {
+            return None;
+        }
+        let mut cursor = FROZEN_SIG.len();
+        let (read, len) = leb128::decode_len(&self.input[cursor..])?;
+        cursor += read;
+        let (read, page_size) = leb128::decode_len(&self.input[cursor..])?;
+        cursor += read;
+        if check_output(len)? > 0 && page_size == 0 {
+            return None;
+        }
+        let (model, read) = FrozenModel::deserialize(&self.input[cursor..])?;
+        cursor += read;
+
+        // Find the streams of the pages, and decode them on all of the cores.
+        let mut pages = Vec::new();
+        for offset in (0..len).step_by(page_size.max(1)) {
+            let (read, size) = leb128::decode_len(&self.input[cursor..])?;
+            cursor += read;
+            let stream = self.input.get(cursor..cursor.checked_add(size)?)?;
+            cursor += size;
+            pages.push((stream, page_size.min(len - offset)));
+        }
+        let decoded = map_parallel(&pages, |(stream, len)| {
+            model.decode_page(stream, *len)
+        });
+        for page in decoded {
+            self.output.extend(page?);
+        }
+        Some((cursor, len))
+    }
+}
+
+impl<'a> Decoder<'a> for FrozenDecoder<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
+        FrozenDecoder {
+            input,
+            output,
+            read: 0,
+        }
+    }
+
+    fn decode(&mut self) -> Option<(usize, usize)> {
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
+    }
+
+    decoder_state!();
+}
@@ -34,6 +34,17 @@ impl<const BINS: usize> Histogram<BINS> {
         Histogram { values: hist }
     }
 
+    /// Construct a histogram from 'values', or return None if one of the
+    /// values does not fit in the bins of the histogram.
+    pub fn try_from_data<Ty: Into<usize> + Copy>(
+        values: &[Ty],
+    ) -> Option<Histogram<BINS>> {
+        if values.iter().any(|val| Into::<usize>::into(*val) >= BINS) {
+            return None;
+        }
+        Some(Self::from_data(values))
+    }
+
     pub fn get_bins(&self) -> &[u32; BINS] {
         &self.values
     }
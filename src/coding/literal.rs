@@ -0,0 +1,216 @@
+//! An LZMA-style literal coder. Literals are coded bit by bit with adaptive
+//! probabilities, in the context of the top bits of the previous byte. The
+//! literal after a match is often the byte that follows the source of the
+//! match, the "match byte", so that literal is coded in the context of the
+//! bits of the match byte, until the first bit that differs.
+//! Binary data such as tables of pointers or floats is aligned to 2, 4 or 8
+//! bytes, and the bytes at the same offset in each word are alike, so the
+//! position of the literal modulo the alignment is part of the context too.
+//! Reference: the literal coder of LZMA (LzmaEnc.c, LitEnc_EncodeMatched).
+
+use super::arithmetic::{BitonicDecoder, BitonicEncoder};
+
+/// The number of bits of the previous byte that select the probabilities.
+const CTX_BITS: u32 = 3;
+
+/// The number of probabilities of each context: a tree of 256 nodes for
+/// plain literals, and two trees for the bits that follow a matching prefix,
+/// when the next bit of the match byte is 0 or 1.
+const PROBS_PER_CTX: usize = 0x300;
+
+/// The widest alignment that is part of the context.
+pub const MAX_ALIGNMENT: usize = 8;
+
+/// The speed of adaptation. Each bit moves the probability by 1/32 of the
+/// distance to the bit.
+const ADAPT_SHIFT: u32 = 5;
+
+/// Adaptive probabilities of the bits of literals.
+struct LiteralModel {
+    /// The probability that the next bit is 1, for each context and node.
+    probs: Vec<u16>,
+    /// The mask of the position bits that are part of the context.
+    align_mask: usize,
+}
+
+impl LiteralModel {
+    /// Create a model for literals that are aligned to 'align' bytes, which
+    /// is a power of two that is at most 'MAX_ALIGNMENT'.
+    fn new(align: usize) -> Self {
+        assert!(align.is_power_of_two() && align <= MAX_ALIGNMENT);
+        Self {
+            probs: vec![1 << 15; (PROBS_PER_CTX << CTX_BITS) * align],
+            align_mask: align - 1,
+        }
+    }
+
+    /// Code the 8 bits of a literal at position 'pos' that follows 'prev',
+    /// with the match byte 'match_byte', from the highest bit to the lowest.
+    /// The callback 'code' is called with the probability of each bit, and
+    /// returns the bit. Returns the literal.
+    fn walk(
+        &mut self,
+        pos: usize,
+        prev: u8,
+        match_byte: Option<u8>,
+        mut code: impl FnMut(u16) -> Option<bool>,
+    ) -> Option<u8> {
+        let ctx = ((pos & self.align_mask) << CTX_BITS)
+            | (prev >> (8 - CTX_BITS)) as usize;
+        let base = ctx * PROBS_PER_CTX;
+        let probs = &mut self.probs[base..base + PROBS_PER_CTX];
+        let mut matching = match_byte.is_some();
+        let match_byte = match_byte.unwrap_or(0);
+
+        // The node in the tree of the bits that were coded so far.
+        let mut node = 1;
+        for i in (0..8).rev() {
+            let match_bit = (match_byte >> i) & 1;
+            let idx = if matching {
+                0x100 + ((match_bit as usize) << 8) + node
+            } else {
+                node
+            };
+
+            let bit = code(probs[idx])?;
+            if bit {
+                probs[idx] += (u16::MAX - probs[idx]) >> ADAPT_SHIFT;
+            } else {
+                probs[idx] -= probs[idx] >> ADAPT_SHIFT;
+            }
+            node = (node << 1) | bit as usize;
+            matching &= bit as u8 == match_bit;
+        }
+        Some(node as u8)
+    }
+}
+
+/// Encodes literals into a bitstream. See the module comment.
+pub struct LiteralEncoder<'a> {
+    coder: BitonicEncoder<'a>,
+    model: LiteralModel,
+}
+
+impl<'a> LiteralEncoder<'a> {
+    pub fn new(output: &'a mut Vec<u8>) -> Self {
+        Self {
+            coder: BitonicEncoder::new(output),
+            model: LiteralModel::new(1),
+        }
+    }
+
+    /// Use the position of the literals modulo 'align' as context. 'align' is
+    /// a power of two that is at most 'MAX_ALIGNMENT'.
+    pub fn with_alignment(mut self, align: usize) -> Self {
+        self.model = LiteralModel::new(align);
+        self
+    }
+
+    /// Encode the literal 'byte' at position 'pos' that follows the byte
+    /// 'prev', and the match byte 'match_byte' if the literal follows a match.
+    pub fn encode(
+        &mut self,
+        byte: u8,
+        pos: usize,
+        prev: u8,
+        match_byte: Option<u8>,
+    ) {
+        let coder = &mut self.coder;
+        let mut i = 8;
+        let _ = self.model.walk(pos, prev, match_byte, |prob| {
+            i -= 1;
+            let bit = (byte >> i) & 1 == 1;
+            coder.encode(bit, prob);
+            Some(bit)
+        });
+    }
+
+    /// Seal the stream.
+    pub fn finish(mut self) {
+        self.coder.finalize();
+    }
+}
+
+/// Decodes literals that were encoded with 'LiteralEncoder'.
+pub struct LiteralDecoder<'a> {
+    coder: BitonicDecoder<'a>,
+    model: LiteralModel,
+}
+
+impl<'a> LiteralDecoder<'a> {
+    /// Create a decoder for the stream 'input', or return None if the stream
+    /// is too short.
+    pub fn new(input: &'a [u8]) -> Option<Self> {
+        if input.len() < 4 {
+            return None;
+        }
+        Some(Self {
+            coder: BitonicDecoder::new(input),
+            model: LiteralModel::new(1),
+        })
+    }
+
+    /// Use the position of the literals modulo 'align' as context, like the
+    /// encoder did.
+    pub fn with_alignment(mut self, align: usize) -> Self {
+        self.model = LiteralModel::new(align);
+        self
+    }
+
+    /// Decode the literal at position 'pos' that follows the byte 'prev', with
+    /// the match byte 'match_byte' that was passed to the encoder. Returns None
+    /// if the stream ended.
+    pub fn decode(
+        &mut self,
+        pos: usize,
+        prev: u8,
+        match_byte: Option<u8>,
+    ) -> Option<u8> {
+        let coder = &mut self.coder;
+        self.model
+            .walk(pos, prev, match_byte, |prob| coder.decode(prob))
+    }
+
+    /// Return the number of bytes that were read. After the last literal this
+    /// is the size of the stream.
+    pub fn read(&self) -> usize {
+        self.coder.read()
+    }
+}
+
+/// Return the alignment of the fields of 'input': 1 for text and unaligned
+/// data, or 2, 4 or 8 if splitting the bytes by their position modulo the
+/// alignment makes them markedly more predictable.
+pub fn detect_alignment(input: &[u8]) -> usize {
+    // Look at a prefix of the input, which is enough to tell the data type.
+    let input = &input[..input.len().min(1 << 16)];
+
+    // Return the order-0 cost in bits of the bytes, split into 'align' lanes.
+    let cost = |align: usize| -> f64 {
+        let mut hist = vec![[0u32; 256]; align];
+        for (i, byte) in input.iter().enumerate() {
+            hist[i % align][*byte as usize] += 1;
+        }
+        let mut bits = 0.;
+        for lane in hist {
+            let total: u32 = lane.iter().sum();
+            for count in lane.iter().filter(|x| **x > 0) {
+                bits -= *count as f64 * (*count as f64 / total as f64).log2();
+            }
+            // Each lane pays for learning its own probabilities.
+            bits += lane.iter().filter(|x| **x > 0).count() as f64 * 8.;
+        }
+        bits
+    };
+
+    let mut best = 1;
+    let mut best_cost = cost(1);
+    for align in [2, 4, MAX_ALIGNMENT] {
+        let bits = cost(align);
+        if bits < best_cost * 0.95 {
+            best = align;
+            best_cost = bits;
+        }
+    }
+    best
+}
@@ -3,4 +3,7 @@
 pub mod adaptive;
 pub mod arithmetic;
 pub mod entropy;
+pub mod frozen;
 pub mod hist;
+pub mod literal;
+pub mod residual;
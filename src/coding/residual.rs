@@ -0,0 +1,210 @@
+//! This module implements a context-mixing coder for the streams that remain
+//! after LZ matching, such as the literals and the sequence tokens. Each byte
+//! is coded bit by bit. The bits are predicted by adaptive counters in the
+//! contexts of the previous 0 to 3 bytes of the stream, and the predictions are
+//! combined with a logistic mixer. This is much slower than tANS, but since the
+//! residual streams are a fraction of the input it's much faster than running
+//! the context models over all of the bytes.
+
+use super::arithmetic::{BitonicDecoder, BitonicEncoder};
+use crate::limits::check_output;
+use crate::models::logistic::LogisticMixer;
+use crate::utils::leb128;
+use crate::utils::signatures::{match_signature, RESIDUAL_SIG};
+use crate::{restore_on_error, Context, Decoder, Encoder};
+
+/// The number of previous bytes that select the contexts, plus one for the
+/// order-0 context.
+const ORDERS: usize = 4;
+
+/// The number of samples after which the counters adapt at a fixed rate.
+const COUNTER_LIMIT: u32 = 60;
+
+/// The range of the size of the counter tables, in bits.
+const MIN_TABLE_BITS: u32 = 12;
+const MAX_TABLE_BITS: u32 = 22;
+
+/// An adaptive probability, and the number of times that it was updated. The
+/// counter adapts quickly while it has few samples.
+#[derive(Clone, Copy)]
+struct Counter {
+    prob: u16,
+    count: u16,
+}
+
+impl Counter {
+    const NEW: Self = Self {
+        prob: 1 << 15,
+        count: 0,
+    };
+
+    fn update(&mut self, bit: bool) {
+        let target = if bit { 65535 } else { 0 };
+        let delta = (target - self.prob as i32) / (self.count as i32 + 2);
+        self.prob = (self.prob as i32 + delta) as u16;
+        self.count = (self.count + 1).min(COUNTER_LIMIT as u16);
+    }
+}
+
+/// Predicts the bits of bytes in the contexts of the previous bytes.
+struct ResidualModel {
+    /// A table of counters for each order, indexed by a hash of the context.
+    tables: Vec<Vec<Counter>>,
+    /// The mask of the indices of the tables.
+    mask: usize,
+    /// The hashes of the contexts of the next byte.
+    hashes: [u32; ORDERS],
+    /// The indices of the counters of the current bit.
+    slots: [usize; ORDERS],
+    /// The last bytes, with the last byte in the low bits.
+    history: u32,
+    mixer: LogisticMixer,
+}
+
+impl ResidualModel {
+    /// Create a model for a stream of about 'len' bytes.
+    fn new(len: usize) -> Self {
+        let bits = (usize::BITS - len.leading_zeros() + 2)
+            .clamp(MIN_TABLE_BITS, MAX_TABLE_BITS);
+        let mut model = Self {
+            tables: vec![vec![Counter::NEW; 1 << bits]; ORDERS],
+            mask: (1 << bits) - 1,
+            hashes: [0; ORDERS],
+            slots: [0; ORDERS],
+            history: 0,
+            // One set of weights for each node of the tree of bits.
+            mixer: LogisticMixer::new(ORDERS, 256),
+        };
+        model.set_contexts();
+        model
+    }
+
+    /// Compute the hashes of the contexts of the next byte.
+    fn set_contexts(&mut self) {
+        for (order, hash) in self.hashes.iter_mut().enumerate() {
+            let bytes = match order {
+                0 => 0,
+                _ => self.history & (u32::MAX >> (32 - 8 * order)),
+            };
+            *hash = (bytes.wrapping_add(order as u32) << 8)
+                .wrapping_mul(0x9E3779B1)
+                .rotate_left(16 + order as u32);
+        }
+    }
+
+    /// Code the 8 bits of the next byte from the highest bit to the lowest.
+    /// The callback 'code' is called with the probability of each bit being 1,
+    /// and returns the bit. Returns the byte.
+    fn code(
+        &mut self,
+        mut code: impl FnMut(u16) -> Option<bool>,
+    ) -> Option<u8> {
+        let mut node: u32 = 1;
+        for _ in 0..8 {
+            for order in 0..ORDERS {
+                let idx = self.hashes[order] ^ node.wrapping_mul(0x2F0B3A49);
+                self.slots[order] = idx as usize & self.mask;
+                self.mixer.add(self.tables[order][self.slots[order]].prob);
+            }
+            let bit = code(self.mixer.mix(node as usize))?;
+            for order in 0..ORDERS {
+                self.tables[order][self.slots[order]].update(bit);
+            }
+            self.mixer.update(bit);
+            node = (node << 1) | bit as u32;
+        }
+
+        let byte = node as u8;
+        self.history = (self.history << 8) | byte as u32;
+        self.set_contexts();
+        Some(byte)
+    }
+}
+
+/// Encodes a buffer with the context-mixing coder. See the module comment.
+pub struct ResidualEncoder<'a> {
+    /// The uncompressed input.
+    input: &'a [u8],
+    /// The output stream.
+    output: &'a mut Vec<u8>,
+}
+
+/// Decodes buffers that were encoded with 'ResidualEncoder'.
+pub struct ResidualDecoder<'a> {
+    /// The compressed input.
+    input: &'a [u8],
+    /// The output stream.
+    output: &'a mut Vec<u8>,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
+}
+
+impl<'a> Encoder<'a> for ResidualEncoder<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>, _ctx: Context) -> Self {
+        ResidualEncoder { input, output }
+    }
+
+    fn encode(&mut self) -> usize {
+        let start = self.output.len();
+        self.output.extend(RESIDUAL_SIG);
+        leb128::encode(self.input.len() as u64, self.output);
+
+        let mut model = ResidualModel::new(self.input.len());
+        let mut encoder = BitonicEncoder::new(self.output);
+        for byte in self.input {
+            let mut i = 8;
+            let _ = model.code(|prob| {
+                i -= 1;
+                let bit = (byte >> i) & 1 == 1;
+                encoder.encode(bit, prob);
+                Some(bit)
+            });
+        }
+        encoder.finalize();
+        self.output.len() - start
+    }
+}
+
+impl<'a> ResidualDecoder<'a> {
+    /// Decode the input. See 'Decoder::decode'.
+    fn decode_impl(&mut self) -> Option<(usize, usize)> {
+        if !match_signature(self.input, &RESIDUAL_SIG) {
+            return None;
+        }
+        let mut cursor = RESIDUAL_SIG.len();
+        let (read, len) = leb128::decode_len(&self.input[cursor..])?;
+        cursor += read;
+        check_output(len)?;
+
+        let stream = &self.input[cursor..];
+        if stream.len() < 4 {
+            return None;
+        }
+        let mut model = ResidualModel::new(len);
+        let mut decoder = BitonicDecoder::new(stream);
+        for _ in 0..len {
+            let byte = model.code(|prob| decoder.decode(prob))?;
+            self.output.push(byte);
+        }
+        Some((cursor + decoder.read(), len))
+    }
+}
+
+impl<'a> Decoder<'a> for ResidualDecoder<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
+        ResidualDecoder {
+            input,
+            output,
+            read: 0,
+        }
+    }
+
+    fn decode(&mut self) -> Option<(usize, usize)> {
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
+    }
+
+    decoder_state!();
+}
@@ -0,0 +1,198 @@
+//! Implements binary delta encoding. A patch describes the 'target' buffer as
+//! a sequence of literals and matches, where matches can refer to the 'base'
+//! buffer as well as to earlier parts of the target. The base is placed at the
+//! beginning of the match window, so the regular LZ matcher finds the shared
+//! content. The list of operations is compressed with the block encoder.
+
+use crate::block::{BlockDecoder, BlockEncoder};
+use crate::limits::check_output;
+use crate::lz::matcher::select_matcher;
+use crate::utils::hash::xxh32;
+use crate::utils::leb128;
+use crate::utils::signatures::{match_signature, read32, write32, DELTA_SIG};
+use crate::utils::variable_length_encoding::decode as decode_vl;
+use crate::utils::variable_length_encoding::encode as encode_vl;
+use crate::{Context, Decoder, Encoder};
+
+/// The compression level that is used for matching against the base.
+pub const DELTA_LEVEL: u8 = 9;
+
+/// The minimum length of a match that we encode in the patch.
+const MIN_MATCH: usize = 4;
+
+/// The encoding of the numbers in the list of operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpsCoding {
+    /// The variable length encoding of the patches.
+    Patch,
+    /// LEB128, which is shorter for the large offsets of long windows.
+    Leb128,
+}
+
+impl OpsCoding {
+    /// Append the number 'num' to 'ops'.
+    fn encode(self, num: usize, ops: &mut Vec<u8>) {
+        match self {
+            OpsCoding::Patch => encode_vl(num as u32, ops),
+            OpsCoding::Leb128 => leb128::encode(num as u64, ops),
+        };
+    }
+
+    /// Read a number from the start of 'ops'. Returns the number of bytes
+    /// read and the number.
+    fn decode(self, ops: &[u8]) -> Option<(usize, usize)> {
+        match self {
+            OpsCoding::Patch => decode_vl(ops).map(|(r, n)| (r, n as usize)),
+            OpsCoding::Leb128 => leb128::decode_len(ops),
+        }
+    }
+}
+
+/// Append the operation (literals, match length, match offset) to 'ops'.
+fn push_op(
+    ops: &mut Vec<u8>,
+    coding: OpsCoding,
+    literals: &[u8],
+    mat_len: usize,
+    off: usize,
+) {
+    coding.encode(literals.len(), ops);
+    ops.extend(literals);
+    coding.encode(mat_len, ops);
+    coding.encode(off, ops);
+}
+
+/// Return the list of operations that transforms 'base' into 'target', with
+/// the numbers encoded with 'coding'. The operations are not compressed. See
+/// 'apply_ops'.
+pub fn encode_ops(base: &[u8], target: &[u8], coding: OpsCoding) -> Vec<u8> {
+    let mut window = Vec::with_capacity(base.len() + target.len());
+    window.extend(base);
+    window.extend(target);
+    let base_len = base.len();
+
+    let mut ops: Vec<u8> = Vec::new();
+    // Literals that were not written yet, as a range in the window.
+    let mut lit_start = base_len;
+    let mut lit_end = base_len;
+
+    let matcher = select_matcher::<{ 1 << 30 }, 65536>(DELTA_LEVEL, &window);
+    for (lit, mat) in matcher {
+        // The matcher scans the base too. Ignore everything before the target.
+        if lit.end > base_len {
+            lit_end = lit.end;
+        }
+        if mat.is_empty() {
+            continue;
+        }
+
+        let offset = lit.end - mat.start;
+        let start = lit.end.max(base_len);
+        let end = lit.end + mat.len();
+        if end <= base_len {
+            lit_start = base_len;
+            continue;
+        }
+
+        // Matches that are too short (after clipping) are saved as literals.
+        if end - start < MIN_MATCH {
+            lit_end = end;
+            continue;
+        }
+
+        let literals = &window[lit_start..lit_end];
+        push_op(&mut ops, coding, literals, end - start, offset);
+        lit_start = end;
+        lit_end = end;
+    }
+    push_op(&mut ops, coding, &window[lit_start..window.len()], 0, 0);
+    ops
+}
+
+/// Create a patch that transforms 'base' into 'target'.
+pub fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let ops = encode_ops(base, target, OpsCoding::Patch);
+
+    // Write the header and the compressed operations.
+    let mut patch = Vec::new();
+    patch.extend(DELTA_SIG);
+    write32(base.len() as u32, &mut patch);
+    write32(xxh32(base, 0), &mut patch);
+    write32(target.len() as u32, &mut patch);
+    let ctx = Context::new(DELTA_LEVEL, 1 << 20);
+    let _ = BlockEncoder::new(&ops, &mut patch, ctx).encode();
+    patch
+}
+
+/// Apply 'patch' to 'base' and return the target buffer, or None if the patch
+/// is invalid or was not created for this base.
+pub fn apply(base: &[u8], patch: &[u8]) -> Option<Vec<u8>> {
+    if !match_signature(patch, &DELTA_SIG) {
+        return None;
+    }
+    let mut cursor = DELTA_SIG.len();
+    let base_len = read32(&patch[cursor..])? as usize;
+    let base_hash = read32(&patch[cursor + 4..])?;
+    let target_len = read32(&patch[cursor + 8..])? as usize;
+    cursor += 12;
+    if base_len != base.len() || base_hash != xxh32(base, 0) {
+        return None;
+    }
+    check_output(target_len)?;
+
+    let mut ops = Vec::new();
+    let _ = BlockDecoder::new(&patch[cursor..], &mut ops).decode()?;
+    apply_ops(base, &ops, target_len, OpsCoding::Patch)
+}
+
+/// Apply the operations 'ops' of 'encode_ops', with the numbers encoded with
+/// 'coding', to 'base', and return the target buffer of 'target_len' bytes.
+/// Returns None if the operations are invalid.
+pub fn apply_ops(
+    base: &[u8],
+    ops: &[u8],
+    target_len: usize,
+    coding: OpsCoding,
+) -> Option<Vec<u8>> {
+    check_output(target_len)?;
+    let base_len = base.len();
+    let mut target: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    while target.len() < target_len || pos < ops.len() {
+        // Copy the literals.
+        let (read, lit_len) = coding.decode(&ops[pos..])?;
+        pos += read;
+        target.extend(ops.get(pos..)?.get(..lit_len)?);
+        pos += lit_len;
+
+        // Copy the match, from the base or from the target.
+        let (read, mat_len) = coding.decode(&ops[pos..])?;
+        pos += read;
+        let (read, offset) = coding.decode(&ops[pos..])?;
+        pos += read;
+        // Matches start before the current position.
+        if offset == 0 && mat_len > 0 {
+            return None;
+        }
+        let from = (base_len + target.len()).checked_sub(offset)?;
+        if target.len().checked_add(mat_len)? > target_len {
+            return None;
+        }
+        for i in from..from + mat_len {
+            let val = if i < base_len {
+                base[i]
+            } else {
+                target[i - base_len]
+            };
+            target.push(val);
+        }
+        if target.len() > target_len {
+            return None;
+        }
+    }
+
+    if target.len() != target_len {
+        return None;
+    }
+    Some(target)
+}
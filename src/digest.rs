@@ -0,0 +1,192 @@
+//! Saves a cryptographic digest of the uncompressed data after a stream of the
+//! full compressor (see 'Context::with_digest'). The digest is computed while
+//! the input is compressed, and checked while the stream is decoded, so
+//! archives can prove the integrity of the extracted data end to end without
+//! a second pass over it. The hash function is pluggable ('DigestHasher'), and
+//! SHA-256 is built in. The trailer is saved before the frame checksum, so
+//! scrubbing covers it too. Decoders that don't know the trailer skip it, like
+//! any data that follows a stream.
+
+use crate::utils::leb128;
+use crate::utils::signatures::{match_signature, DIGEST_SIG};
+
+/// The id of SHA-256 in the trailer.
+pub const SHA256_ID: u8 = 1;
+
+/// A hash function that computes the digest of the uncompressed data.
+pub trait DigestHasher {
+    /// Return the id of the hash function, which is saved in the trailer.
+    /// The ids up to 127 are reserved for the built-in functions.
+    fn id(&self) -> u8;
+
+    /// Add the bytes 'data' to the hashed data.
+    fn update(&mut self, data: &[u8]);
+
+    /// Return the digest of the hashed data.
+    fn finish(&self) -> Vec<u8>;
+}
+
+/// The round constants of SHA-256.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The initial state of SHA-256.
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+    0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Computes the SHA-256 digest of data that is added in parts.
+/// Reference: FIPS 180-4.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// The bytes of the last block, which is not full yet.
+    pending: Vec<u8>,
+    /// The number of hashed bytes.
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: H0,
+            pending: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mix the block of 64 bytes 'block' into 'state'.
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7)
+                ^ w[i - 15].rotate_right(18)
+                ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17)
+                ^ w[i - 2].rotate_right(19)
+                ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for i in 0..64 {
+            let s1 =
+                e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 =
+                a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (h, g, f, e) = (g, f, e, d.wrapping_add(t1));
+            (d, c, b, a) = (c, b, a, t1.wrapping_add(t2));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+impl DigestHasher for Sha256 {
+    fn id(&self) -> u8 {
+        SHA256_ID
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if !self.pending.is_empty() {
+            let len = data.len().min(64 - self.pending.len());
+            self.pending.extend(&data[..len]);
+            data = &data[len..];
+            if self.pending.len() < 64 {
+                return;
+            }
+            Self::compress(&mut self.state, &self.pending);
+            self.pending.clear();
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            Self::compress(&mut self.state, block);
+        }
+        self.pending.extend(blocks.remainder());
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        // Pad the data with a one bit, zeros, and the length in bits.
+        let mut state = self.state;
+        let mut tail = self.pending.clone();
+        tail.push(0x80);
+        tail.resize(if tail.len() > 56 { 120 } else { 56 }, 0);
+        tail.extend((self.len * 8).to_be_bytes());
+        for block in tail.chunks_exact(64) {
+            Self::compress(&mut state, block);
+        }
+        state.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+}
+
+/// Return the SHA-256 digest of 'data'.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Write the trailer with the digest of the data that 'hasher' hashed to
+/// 'output'. Returns the number of bytes written.
+pub fn write_digest_trailer(
+    hasher: &dyn DigestHasher,
+    output: &mut Vec<u8>,
+) -> usize {
+    let start = output.len();
+    output.extend(DIGEST_SIG);
+    output.push(hasher.id());
+    let digest = hasher.finish();
+    leb128::encode(digest.len() as u64, output);
+    output.extend(digest);
+    output.len() - start
+}
+
+/// Read the trailer at the start of 'input'. Returns the size of the trailer,
+/// the id of the hash function and the digest, or None if the input does not
+/// start with a trailer.
+pub fn read_digest_trailer(input: &[u8]) -> Option<(usize, u8, &[u8])> {
+    if !match_signature(input, &DIGEST_SIG) {
+        return None;
+    }
+    let mut cursor = DIGEST_SIG.len();
+    let id = *input.get(cursor)?;
+    cursor += 1;
+    let (read, len) = leb128::decode_len(&input[cursor..])?;
+    cursor += read;
+    let digest = input.get(cursor..)?.get(..len)?;
+    Some((cursor + len, id, digest))
+}
@@ -0,0 +1,123 @@
+//! A quick estimator of the compressibility of a buffer. The estimator runs a
+//! cheap pass over a sample of the input and predicts the compression ratio
+//! from the entropy of the bytes and from the number of repeated sequences.
+//! Callers can use it to decide if it's worth compressing the input at all.
+
+use crate::coding::hist::Histogram;
+use crate::scratch::{recycle_u32, take_u32};
+use crate::utils::hash::mul_hash32;
+
+/// The max number of bytes that we inspect.
+const MAX_SAMPLE_SIZE: usize = 1 << 16;
+
+/// The size of each window when the input is too large to inspect.
+const WINDOW_SIZE: usize = 1 << 12;
+
+/// The number of bits in the index of the match table.
+const HASH_BITS: usize = 14;
+
+/// The minimum length of a repeated sequence.
+const MIN_MATCH: usize = 4;
+
+/// The estimated number of bytes that it takes to encode a match.
+const MATCH_COST: f32 = 3.0;
+
+/// Pick the bytes to inspect. Small inputs are inspected in full, and large
+/// inputs are inspected in windows that are spread evenly across the input.
+fn sample_windows(input: &[u8]) -> Vec<&[u8]> {
+    if input.len() <= MAX_SAMPLE_SIZE {
+        return vec![input];
+    }
+    let windows = MAX_SAMPLE_SIZE / WINDOW_SIZE;
+    let stride = (input.len() - WINDOW_SIZE) / (windows - 1);
+    (0..windows)
+        .map(|i| &input[i * stride..i * stride + WINDOW_SIZE])
+        .collect()
+}
+
+/// Return the number of bits per byte that an order-0 entropy coder needs to
+/// encode the bytes in 'windows'.
+fn entropy_bits(windows: &[&[u8]]) -> f32 {
+    let mut counts = [0u32; 256];
+    let mut total = 0;
+    for window in windows {
+        let hist = Histogram::<256>::from_data(window);
+        for (count, val) in counts.iter_mut().zip(hist.get_bins().iter()) {
+            *count += val;
+        }
+        total += window.len();
+    }
+
+    let mut bits = 0.;
+    for count in counts.iter().filter(|c| **c != 0) {
+        let p = *count as f32 / total as f32;
+        bits -= p * p.log2();
+    }
+    bits
+}
+
+/// Scan 'window' for sequences that appeared earlier in the sample, and return
+/// the estimated encoded size of the window, when literals take 'bits' bits
+/// each. The table 'table' records the last location of each sequence, and is
+/// shared across the windows, so matches to earlier windows are found too.
+fn estimate_window(
+    window: &[u8],
+    base: usize,
+    sample: &[u8],
+    table: &mut [u32],
+    bits: f32,
+) -> f32 {
+    let literal_cost = bits / 8.;
+    let mut size = 0.;
+    let mut i = 0;
+    while i + MIN_MATCH <= window.len() {
+        let seq = u32::from_le_bytes(window[i..i + 4].try_into().unwrap());
+        let idx = mul_hash32(seq, HASH_BITS);
+        let candidate = table[idx] as usize;
+        table[idx] = (base + i + 1) as u32;
+
+        // The table stores the location plus one, so zero means empty.
+        if candidate != 0 {
+            let prev = &sample[candidate - 1..];
+            let len = prev
+                .iter()
+                .zip(window[i..].iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= MIN_MATCH {
+                // Short matches of skewed data are cheaper as literals.
+                size += MATCH_COST.min(len as f32 * literal_cost);
+                i += len;
+                continue;
+            }
+        }
+        size += literal_cost;
+        i += 1;
+    }
+    size + (window.len() - i) as f32 * literal_cost
+}
+
+/// Return the estimated compression ratio of 'sample' (the uncompressed size
+/// divided by the compressed size). Values close to 1.0 indicate that the input
+/// is random or already compressed. Large inputs are sampled, so the result is
+/// only an approximation.
+pub fn estimate_ratio(sample: &[u8]) -> f32 {
+    if sample.is_empty() {
+        return 1.0;
+    }
+    let windows = sample_windows(sample);
+
+    // Concatenate the windows so that matches can refer to earlier windows.
+    let joined: Vec<u8> = windows.concat();
+    let bits = entropy_bits(&windows);
+    let mut table = take_u32();
+    table.resize(1 << HASH_BITS, 0);
+    let mut size = 0.;
+    let mut base = 0;
+    for window in &windows {
+        size += estimate_window(window, base, &joined, &mut table, bits);
+        base += window.len();
+    }
+    recycle_u32(table);
+    (joined.len() as f32 / size.max(1.)).max(1.0)
+}
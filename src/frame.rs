@@ -0,0 +1,86 @@
+//! Adds a checksum of the compressed bytes to the end of a stream of the full
+//! compressor. The page checksums (see 'Context::with_page_checksums') check
+//! the decoded bytes, so they need a full decompression. The frame checksum
+//! checks the compressed bytes, so storage systems can detect damaged files
+//! while scrubbing, without decompressing them. Decoders that don't know the
+//! trailer skip it, like any data that follows a stream.
+
+use crate::utils::hash::xxh32;
+use crate::utils::signatures::{match_signature, read32, write32};
+use crate::utils::signatures::{FRAME_CHECKSUM_SIG, FULL_SIG};
+
+/// The size of the trailer: the signature and the checksum.
+pub const FRAME_TRAILER_LEN: usize = FRAME_CHECKSUM_SIG.len() + 4;
+
+/// The size of the segments of the frame checksum.
+const SEGMENT_SIZE: usize = 1 << 16;
+
+/// Computes the checksum of a stream that is written in parts. The stream is
+/// hashed in segments of 'SEGMENT_SIZE' bytes, and the hash of each segment is
+/// the seed of the next one, so the checksum doesn't depend on the sizes of
+/// the parts.
+#[derive(Clone, Debug, Default)]
+pub struct FrameHasher {
+    /// The bytes of the last segment, which is not full yet.
+    pending: Vec<u8>,
+    /// The hash of the segments before the pending segment.
+    seed: u32,
+}
+
+impl FrameHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the bytes 'data' to the hashed stream.
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = data.len().min(SEGMENT_SIZE - self.pending.len());
+            self.pending.extend(&data[..len]);
+            data = &data[len..];
+            if self.pending.len() == SEGMENT_SIZE {
+                self.seed = xxh32(&self.pending, self.seed);
+                self.pending.clear();
+            }
+        }
+    }
+
+    /// Return the checksum of the stream.
+    pub fn finish(&self) -> u32 {
+        xxh32(&self.pending, self.seed)
+    }
+}
+
+/// Return the frame checksum of 'stream'.
+pub fn frame_checksum(stream: &[u8]) -> u32 {
+    let mut hasher = FrameHasher::new();
+    hasher.update(stream);
+    hasher.finish()
+}
+
+/// Write the trailer with the checksum 'checksum' of the stream to 'output'.
+pub fn write_frame_trailer(checksum: u32, output: &mut Vec<u8>) {
+    output.extend(FRAME_CHECKSUM_SIG);
+    write32(checksum, output);
+}
+
+/// Read the trailer at the start of 'input'. Returns the checksum, or None if
+/// the input does not start with a trailer.
+pub fn read_frame_trailer(input: &[u8]) -> Option<u32> {
+    if !match_signature(input, &FRAME_CHECKSUM_SIG) {
+        return None;
+    }
+    read32(input.get(FRAME_CHECKSUM_SIG.len()..FRAME_TRAILER_LEN)?)
+}
+
+/// Check the frame checksum of the stream 'input', which ends with its trailer,
+/// without decompressing it. Returns None if the input is not a stream of the
+/// full compressor with a trailer.
+pub fn verify_frame(input: &[u8]) -> Option<bool> {
+    if !match_signature(input, &FULL_SIG) {
+        return None;
+    }
+    let body = input.len().checked_sub(FRAME_TRAILER_LEN)?;
+    let checksum = read_frame_trailer(&input[body..])?;
+    Some(frame_checksum(&input[..body]) == checksum)
+}
@@ -0,0 +1,127 @@
+//! Exchanges a sequence of independently compressed messages over a byte
+//! stream, such as a socket. Each message is saved as a record with the length
+//! of the compressed message, encoded with LEB128, followed by the compressed
+//! message. The messages are compressed with the one-shot API of 'handle', so
+//! each record can be decoded on its own.
+
+use crate::handle::Compressor;
+use crate::utils::leb128;
+use crate::Context;
+use std::io::{self, Read, Write};
+
+/// Return an error that reports an invalid record.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Compresses messages and writes them as records to 'inner'.
+pub struct MessageWriter<W: Write> {
+    /// The output stream.
+    inner: W,
+    /// Compresses the messages, and keeps its buffer between messages.
+    compressor: Compressor,
+    /// The header of the current record.
+    header: Vec<u8>,
+}
+
+impl<W: Write> MessageWriter<W> {
+    /// Create a writer that compresses the messages with the encoder context
+    /// 'ctx' and writes them to 'inner'.
+    pub fn new(inner: W, ctx: Context) -> Self {
+        Self {
+            inner,
+            compressor: Compressor::new(ctx),
+            header: Vec::new(),
+        }
+    }
+
+    /// Compress 'message' and write its record. Returns the size of the record.
+    pub fn write_message(&mut self, message: &[u8]) -> io::Result<usize> {
+        let compressed = self.compressor.compress(message);
+        self.header.clear();
+        leb128::encode(compressed.len() as u64, &mut self.header);
+        self.inner.write_all(&self.header)?;
+        self.inner.write_all(compressed)?;
+        Ok(self.header.len() + compressed.len())
+    }
+
+    /// Flush the output stream.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Return the output stream and consume the writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads the records of 'MessageWriter' from 'inner' and decompresses them.
+pub struct MessageReader<R: Read> {
+    /// The input stream.
+    inner: R,
+    /// Decompresses the messages, and keeps its buffer between messages.
+    compressor: Compressor,
+    /// The compressed message of the current record.
+    record: Vec<u8>,
+}
+
+impl<R: Read> MessageReader<R> {
+    /// Create a reader that reads the records from 'inner'.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            compressor: Compressor::new(Context::new(0, 0)),
+            record: Vec::new(),
+        }
+    }
+
+    /// Read the length of the next record. Returns None if the stream ended
+    /// before the record.
+    fn read_len(&mut self) -> io::Result<Option<usize>> {
+        let mut bytes = Vec::new();
+        for _ in 0..leb128::MAX_LEN {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                if bytes.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            bytes.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        match leb128::decode_len(&bytes) {
+            Some((_, len)) => Ok(Some(len)),
+            None => Err(invalid("invalid record length")),
+        }
+    }
+
+    /// Read and decompress the next message. Returns None at the end of the
+    /// stream. The message is valid until the next call.
+    pub fn read_message(&mut self) -> io::Result<Option<&[u8]>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        // The buffer grows with the data, so a bad length can't exhaust the
+        // memory.
+        self.record.clear();
+        (&mut self.inner)
+            .take(len as u64)
+            .read_to_end(&mut self.record)?;
+        if self.record.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match self.compressor.decompress(&self.record) {
+            Some(message) => Ok(Some(message)),
+            None => Err(invalid("invalid message")),
+        }
+    }
+
+    /// Return the input stream and consume the reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
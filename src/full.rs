@@ -1,13 +1,30 @@
 //! Handles the encoding of the whole file. This module mainly splits the input
 //! into chunks and calls the block compressor.
 
+use crate::auto::{self, sample_pages, AUTO_LEVEL};
 use crate::block::{BlockDecoder, BlockEncoder};
+use crate::budget::select_level;
 use crate::coding::adaptive::AdaptiveArithmeticDecoder as AAD;
 use crate::coding::adaptive::AdaptiveArithmeticEncoder as AAE;
+use crate::coding::frozen::{FrozenDecoder, FrozenEncoder};
+use crate::digest::{read_digest_trailer, sha256, write_digest_trailer};
+use crate::digest::{DigestHasher, Sha256, SHA256_ID};
+use crate::frame::FRAME_TRAILER_LEN;
+use crate::frame::{frame_checksum, read_frame_trailer, write_frame_trailer};
+use crate::inspect::PageEntry;
+use crate::merkle::HashTree;
+use crate::metadata::{skip_metadata, Metadata};
 use crate::nop::{NopDecoder, NopEncoder};
-use crate::pager::{PagerDecoder, PagerEncoder};
-use crate::utils::signatures::{match_signature, ARITH_SIG, FULL_SIG};
-use crate::{Context, Decoder, Encoder};
+use crate::pager::{self, PagerDecoder, PagerEncoder};
+use crate::scratch::recycle_u8;
+use crate::seal::PageTransform;
+use crate::seal::{read_seal_trailer, verify_seal, write_seal_trailer};
+use crate::utils::leb128;
+use crate::utils::signatures::match_signature;
+use crate::utils::signatures::{ARITH_SIG, FULL_SIG, NOP_ENC, PAGER_SIG};
+use crate::utils::signatures::{FROZEN_SIG, STORED_SIG};
+use crate::{restore_on_error, Context, Decoder, Encoder};
+use std::io::IoSlice;
 
 pub struct FullEncoder<'a> {
     /// The uncompressed input.
@@ -16,26 +33,70 @@ pub struct FullEncoder<'a> {
     output: &'a mut Vec<u8>,
     /// Encoder context,
     ctx: Context,
+    /// Computes the digest of the input, instead of SHA-256.
+    hasher: Option<&'a mut dyn DigestHasher>,
+    /// The record of the metadata that is saved after the signature of the
+    /// stream.
+    metadata: Vec<u8>,
+    /// The offsets of the input that the stream can be decoded from.
+    resets: &'a [usize],
+    /// Records a description of each page. See 'with_page_log'.
+    page_log: Option<&'a mut Vec<PageEntry>>,
+    /// Seals the stream. See 'with_seal'.
+    seal: Option<&'a dyn PageTransform>,
+}
+
+/// The level that compresses the whole input with the adaptive arithmetic
+/// coder, without matching. This is the slowest level.
+pub const ARITH_LEVEL: u8 = 13;
+
+/// The max number of bytes that the encoding of a page adds to the page. Pages
+/// that don't compress are stored with the nop encoding, so the worst case is
+/// the header of the nop encoding: the signature and the length of the page.
+/// Decoders reject pages that are larger. See 'max_page_len'.
+pub const MAX_PAGE_EXPANSION: usize = NOP_ENC.len() + leb128::MAX_LEN;
+
+/// Return the max size of the encoding of a page of 'len' bytes.
+pub fn max_page_len(len: usize) -> usize {
+    len + NOP_ENC.len() + leb128::encoded_len(len as u64)
+}
+
+/// Store the page without compression.
+fn store_page(input: &[u8], ctx: Context) -> Vec<u8> {
+    let mut encoded: Vec<u8> = Vec::new();
+    let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+    encoded
 }
 
 /// Try to perform block encoding, but if it's not useful use nop encoding instead.
-fn encode_or_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+/// This is the handler that encodes each page of the full encoder. The page is
+/// always compressed first, because estimates of the page don't see the long
+/// repetitions that the matcher finds.
+pub fn encode_or_nop(input: &[u8], ctx: Context) -> Vec<u8> {
     let mut encoded: Vec<u8> = Vec::new();
     let new_size = BlockEncoder::new(input, &mut encoded, ctx).encode();
 
+    // Store the page if the codec expanded it.
     if new_size < input.len() {
         return encoded;
     }
-    encoded.clear();
-    let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+    recycle_u8(encoded);
+    let encoded = store_page(input, ctx);
+    debug_assert!(encoded.len() <= max_page_len(input.len()));
     encoded
 }
 
 /// Try to perform the block decoding, or fall back to the nop decoder.
-fn decode_or_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+/// This is the handler that decodes each page of the full decoder. Pages that
+/// are larger than 'max_page_len' of the decoded bytes are rejected, because
+/// the encoder stores such pages.
+pub fn decode_or_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
     let mut decoded: Vec<u8> = Vec::new();
 
     if let Some((read, _)) = BlockDecoder::new(input, &mut decoded).decode() {
+        if read > max_page_len(decoded.len()) {
+            return None;
+        }
         return Some((read, decoded));
     }
 
@@ -47,52 +108,601 @@ fn decode_or_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
     None
 }
 
+/// Decode the first 'limit' bytes of a block like 'decode_or_nop'. This is the
+/// handler that decodes the pages of 'decode_prefix'.
+pub fn decode_or_nop_prefix(
+    input: &[u8],
+    limit: usize,
+) -> Option<(usize, Vec<u8>)> {
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut decoder = BlockDecoder::new(input, &mut decoded).with_limit(limit);
+    if let Some((read, _)) = decoder.decode() {
+        return Some((read, decoded));
+    }
+    let (read, mut decoded) = decode_or_nop(input)?;
+    decoded.truncate(limit);
+    Some((read, decoded))
+}
+
 pub struct FullDecoder<'a> {
     /// The uncompressed input.
     input: &'a [u8],
     /// The output stream.
     output: &'a mut Vec<u8>,
+    /// Checks the digest of the output, instead of SHA-256.
+    hasher: Option<&'a mut dyn DigestHasher>,
+    /// Verifies the seal of the stream. See 'with_seal'.
+    seal: Option<&'a dyn PageTransform>,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
 }
 
-impl<'a> Encoder<'a> for FullEncoder<'a> {
-    fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
-        FullEncoder { input, output, ctx }
+impl<'a> FullEncoder<'a> {
+    /// Save the digest of the input that is computed by 'hasher' after the
+    /// stream, even if the context does not ask for a digest. See 'digest'.
+    pub fn with_hasher(mut self, hasher: &'a mut dyn DigestHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
     }
 
-    fn encode(&mut self) -> usize {
+    /// Append a description of each page to 'log': the codec, the filters,
+    /// the sizes and the time of the page (see 'inspect::PageEntry'). Streams
+    /// without pages, such as the arithmetic level, log no pages. The log
+    /// describes the pages even if the encoder stores the input instead.
+    pub fn with_page_log(mut self, log: &'a mut Vec<PageEntry>) -> Self {
+        self.page_log = Some(log);
+        self
+    }
+
+    /// Save 'metadata' after the signature of the stream, where it is read
+    /// without decompressing the stream. See 'metadata'. Returns None if the
+    /// metadata is larger than 'MAX_METADATA_LEN'.
+    pub fn with_metadata(mut self, metadata: &Metadata) -> Option<Self> {
+        self.metadata.clear();
+        metadata.encode(&mut self.metadata)?;
+        Some(self)
+    }
+
+    /// Seal the stream with 'transform': save a trailer with the key id and a
+    /// tag that authenticates the stream and the trailers before it. The frame
+    /// checksum covers the seal. See 'seal::write_seal_trailer'.
+    pub fn with_seal(mut self, transform: &'a dyn PageTransform) -> Self {
+        self.seal = Some(transform);
+        self
+    }
+
+    /// Reset the window and the models of the encoder at each offset of
+    /// 'points', so that the stream can be decoded from these offsets (see
+    /// 'decode_from_reset'). The offsets are saved in a table before the
+    /// pages, with the index of the page that starts at each offset (see
+    /// 'reset_table'). The arithmetic level codes the input with one model,
+    /// and saves no offsets.
+    pub fn with_reset_points(mut self, points: &'a [usize]) -> Self {
+        self.resets = points;
+        self
+    }
+
+    /// Write the signature of the stream and the metadata. Returns the number
+    /// of bytes written.
+    fn write_header(&mut self) -> usize {
         self.output.extend(FULL_SIG);
-        if self.ctx.level == 13 {
+        self.output.extend(&self.metadata);
+        FULL_SIG.len() + self.metadata.len()
+    }
+
+    /// Return the size of the stored encoding of an input of length 'len'.
+    pub(crate) fn stored_size(len: usize) -> usize {
+        FULL_SIG.len()
+            + STORED_SIG.len()
+            + leb128::encoded_len(len as u64)
+            + len
+    }
+
+    /// Write the input without compression, like the STORE method of zip.
+    fn encode_stored(&mut self) -> usize {
+        let header = self.write_header();
+        self.output.extend(STORED_SIG);
+        leb128::encode(self.input.len() as u64, self.output);
+        self.output.extend(self.input);
+        header - FULL_SIG.len() + Self::stored_size(self.input.len())
+    }
+
+    /// Compress the input with the pager or with the arithmetic coder.
+    fn encode_compressed(&mut self) -> usize {
+        if self.ctx.level == AUTO_LEVEL {
+            let samples = sample_pages(self.input, self.ctx.block_size);
+            self.ctx = auto::select(&samples).apply(self.ctx);
+        }
+        if let Some(budget) = self.ctx.time_budget {
+            self.ctx.level = select_level(self.input, budget);
+        }
+        if self.ctx.level == ARITH_LEVEL && self.ctx.frozen_model {
+            let header = self.write_header();
+            let mut encoder =
+                FrozenEncoder::new(self.input, self.output, self.ctx);
+            return header + encoder.encode();
+        }
+        if self.ctx.level == ARITH_LEVEL {
+            let header = self.write_header();
             let mut encoder = AAE::new(self.input, self.output, self.ctx);
-            return FULL_SIG.len() + encoder.encode();
+            let (consumed, written) = encoder.encode_prefix();
+            if consumed == self.input.len() {
+                return header + written;
+            }
+            // The coder stopped because it expanded the input, so the rest of
+            // the input is saved in pages, with the highest level of blocks.
+            let ctx = Context {
+                level: ARITH_LEVEL - 1,
+                ..self.ctx
+            };
+            let rest = &self.input[consumed..];
+            let mut encoder = PagerEncoder::new(rest, self.output, ctx);
+            encoder.set_callback(encode_or_nop);
+            encoder.set_page_size(ctx.block_size);
+            return header + written + encoder.encode();
         }
 
+        let header = self.write_header();
         let mut encoder = PagerEncoder::new(self.input, self.output, self.ctx);
+        encoder.set_reset_points(self.resets);
+        encoder.set_reset_table(true);
+        if let Some(log) = self.page_log.as_deref_mut() {
+            encoder.set_page_log(log);
+        }
         encoder.set_callback(encode_or_nop);
         encoder.set_page_size(self.ctx.block_size);
-        FULL_SIG.len() + encoder.encode()
+        header + encoder.encode()
+    }
+}
+
+impl<'a> Encoder<'a> for FullEncoder<'a> {
+    fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
+        FullEncoder {
+            input,
+            output,
+            ctx,
+            hasher: None,
+            metadata: Vec::new(),
+            resets: &[],
+            page_log: None,
+            seal: None,
+        }
+    }
+
+    fn encode(&mut self) -> usize {
+        let start = self.output.len();
+        let mut written = self.encode_compressed();
+
+        // Store the raw bytes if compression did not save anything.
+        let metadata = self.metadata.len();
+        if written > metadata + Self::stored_size(self.input.len()) {
+            self.output.truncate(start);
+            written = self.encode_stored();
+        }
+
+        // The digest is saved before the frame checksum, which covers it.
+        if let Some(hasher) = self.hasher.as_deref_mut() {
+            hasher.update(self.input);
+            written += write_digest_trailer(hasher, self.output);
+        } else if self.ctx.digest {
+            let mut hasher = Sha256::new();
+            hasher.update(self.input);
+            written += write_digest_trailer(&hasher, self.output);
+        }
+        if self.ctx.hash_tree {
+            written += append_tree_trailer(self.output, start);
+        }
+        if let Some(transform) = self.seal {
+            written += write_seal_trailer(transform, self.output, start);
+        }
+        if self.ctx.frame_checksum {
+            written += append_frame_trailer(self.output, start);
+        }
+        written
     }
 }
 
+/// Append the hash tree of the pages of the stream that starts at 'start' in
+/// 'output'. Streams without pages have no tree. Returns the number of bytes
+/// written.
+fn append_tree_trailer(output: &mut Vec<u8>, start: usize) -> usize {
+    match HashTree::from_stream(&output[start..]) {
+        Some((tree, _)) => tree.write_trailer(output),
+        None => 0,
+    }
+}
+
+/// Append the frame trailer of the stream that starts at 'start' in 'output'.
+/// Returns the number of bytes written.
+fn append_frame_trailer(output: &mut Vec<u8>, start: usize) -> usize {
+    let checksum = frame_checksum(&output[start..]);
+    write_frame_trailer(checksum, output);
+    FRAME_TRAILER_LEN
+}
+
+/// Returns True if 'ctx' inspects the whole input before it compresses the
+/// pages, and can't compress the input one page at a time.
+pub(crate) fn needs_whole_input(ctx: Context) -> bool {
+    ctx.level == AUTO_LEVEL
+        || ctx.level == ARITH_LEVEL
+        || ctx.time_budget.is_some()
+        || ctx.chunking.is_some()
+        || ctx.global_matching
+}
+
+/// Compress the concatenation of the buffers 'bufs' into 'output', without
+/// copying the buffers into one contiguous buffer. The pages that are inside
+/// of one buffer are compressed in place, and only the pages that cross the
+/// boundary of a buffer are copied. The stream is decoded by 'FullDecoder'.
+/// Contexts that inspect the whole input, such as the automatic level and
+/// global matching, fall back to a copy of the input. Returns the number of
+/// bytes written.
+pub fn encode_vectored(
+    bufs: &[IoSlice],
+    output: &mut Vec<u8>,
+    ctx: Context,
+) -> usize {
+    let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+    if needs_whole_input(ctx) {
+        let input: Vec<u8> =
+            bufs.iter().flat_map(|buf| buf.iter()).copied().collect();
+        return FullEncoder::new(&input, output, ctx).encode();
+    }
+    assert!(ctx.block_size > 0, "Must set page size");
+
+    let start = output.len();
+    output.extend(FULL_SIG);
+    let parts = 1 + total / ctx.block_size;
+    pager::write_header(parts, output);
+
+    // The position of the next page, as a buffer index and an offset.
+    let (mut idx, mut offset) = (0, 0);
+    let mut scratch = Vec::new();
+    for i in 0..parts {
+        let len = ctx.block_size.min(total - i * ctx.block_size);
+        while idx < bufs.len() && offset == bufs[idx].len() {
+            (idx, offset) = (idx + 1, 0);
+        }
+        let page: &[u8] = if len == 0 {
+            &[]
+        } else if bufs[idx].len() - offset >= len {
+            offset += len;
+            &bufs[idx][offset - len..offset]
+        } else {
+            scratch.clear();
+            while scratch.len() < len {
+                let take = (len - scratch.len()).min(bufs[idx].len() - offset);
+                scratch.extend(&bufs[idx][offset..offset + take]);
+                offset += take;
+                if offset == bufs[idx].len() {
+                    (idx, offset) = (idx + 1, 0);
+                }
+            }
+            &scratch[..]
+        };
+        pager::encode_page(page, ctx, encode_or_nop, output);
+    }
+
+    finish_paged(bufs, output, start, ctx)
+}
+
+/// Finish the paged stream of the concatenation of 'bufs' that starts at
+/// 'start' in 'output': store the raw bytes if compression did not save
+/// anything, and append the trailers that 'ctx' asks for. Returns the size of
+/// the whole stream.
+pub(crate) fn finish_paged(
+    bufs: &[IoSlice],
+    output: &mut Vec<u8>,
+    start: usize,
+    ctx: Context,
+) -> usize {
+    let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+    let mut written = output.len() - start;
+    if written > FullEncoder::stored_size(total) {
+        output.truncate(start);
+        output.extend(FULL_SIG);
+        output.extend(STORED_SIG);
+        leb128::encode(total as u64, output);
+        for buf in bufs {
+            output.extend(&buf[..]);
+        }
+        written = output.len() - start;
+    }
+
+    let hasher = ctx.digest.then(|| {
+        let mut hasher = Sha256::new();
+        for buf in bufs {
+            hasher.update(buf);
+        }
+        hasher
+    });
+    let hasher = hasher.as_ref().map(|h| h as &dyn DigestHasher);
+    written + append_trailers(output, start, hasher, ctx)
+}
+
+/// Append the trailers that 'ctx' asks for to the stream that starts at
+/// 'start' in 'output'. The digest of 'hasher' is saved if it is set. Returns
+/// the number of bytes written.
+pub(crate) fn append_trailers(
+    output: &mut Vec<u8>,
+    start: usize,
+    hasher: Option<&dyn DigestHasher>,
+    ctx: Context,
+) -> usize {
+    let mut written = 0;
+    if let Some(hasher) = hasher {
+        written += write_digest_trailer(hasher, output);
+    }
+    if ctx.hash_tree {
+        written += append_tree_trailer(output, start);
+    }
+    if ctx.frame_checksum {
+        written += append_frame_trailer(output, start);
+    }
+    written
+}
+
 impl<'a> Decoder<'a> for FullDecoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
-        FullDecoder { input, output }
+        FullDecoder {
+            input,
+            output,
+            hasher: None,
+            seal: None,
+            read: 0,
+        }
     }
 
     fn decode(&mut self) -> Option<(usize, usize)> {
-        if !match_signature(self.input, &FULL_SIG) {
+        let start = self.output.len();
+        let res = self.decode_impl(start);
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
+    }
+
+    decoder_state!();
+}
+
+/// Check the trailers of the stream 'input', which start at 'read', against
+/// the decoded bytes 'decoded'. The digest is checked with 'hasher', or with
+/// SHA-256, and the hash tree is checked against the pages of the stream. The
+/// seal is verified with 'seal', and streams without a seal are rejected if it
+/// is set. Returns the size of the stream with the trailers, or None if a
+/// trailer does not match.
+pub(crate) fn check_trailers<'h>(
+    input: &[u8],
+    mut read: usize,
+    decoded: &[u8],
+    hasher: Option<&mut (dyn DigestHasher + 'h)>,
+    seal: Option<&dyn PageTransform>,
+) -> Option<usize> {
+    // Check the digest of the decoded bytes, if the stream has one.
+    match read_digest_trailer(&input[read..]) {
+        Some((len, id, digest)) => {
+            let valid = match hasher {
+                Some(hasher) if hasher.id() == id => {
+                    hasher.update(decoded);
+                    hasher.finish() == digest
+                }
+                Some(_) => false,
+                None if id == SHA256_ID => sha256(decoded) == digest,
+                // The digests of unknown hash functions are skipped.
+                None => true,
+            };
+            if !valid {
+                return None;
+            }
+            read += len;
+        }
+        None if hasher.is_some() => return None,
+        None => {}
+    }
+
+    // Check the hash tree against the pages, if the stream has one.
+    if let Some((len, tree)) = HashTree::read_trailer(&input[read..]) {
+        if HashTree::from_stream(input)?.0 != tree {
             return None;
         }
-        let buffer = &self.input[FULL_SIG.len()..];
+        read += len;
+    }
+
+    // Verify the seal, if the caller has the key. Seals are skipped otherwise.
+    match read_seal_trailer(&input[read..]) {
+        Some((len, key_id, tag)) => {
+            if let Some(transform) = seal {
+                if !verify_seal(&input[..read], key_id, tag, transform) {
+                    return None;
+                }
+            }
+            read += len;
+        }
+        None if seal.is_some() => return None,
+        None => {}
+    }
+
+    // Check the frame checksum, if the stream has one.
+    match read_frame_trailer(&input[read..]) {
+        Some(checksum) if checksum == frame_checksum(&input[..read]) => {
+            Some(read + FRAME_TRAILER_LEN)
+        }
+        Some(_) => None,
+        None => Some(read),
+    }
+}
+
+impl<'a> FullDecoder<'a> {
+    /// Check the digest of the output with 'hasher'. Streams without a digest
+    /// of the same hash function are rejected. See 'digest'.
+    pub fn with_hasher(mut self, hasher: &'a mut dyn DigestHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Verify the seal of the stream with 'transform'. Streams without a seal,
+    /// or that were sealed with a different key, are rejected. See
+    /// 'FullEncoder::with_seal'.
+    pub fn with_seal(mut self, transform: &'a dyn PageTransform) -> Self {
+        self.seal = Some(transform);
+        self
+    }
+
+    /// Decode the stream and check its trailers against the bytes that were
+    /// written after 'start'. Returns the number of bytes read and written.
+    fn decode_impl(&mut self, start: usize) -> Option<(usize, usize)> {
+        let (read, written) = self.decode_stream()?;
+        let decoded = &self.output[start..];
+        let read = check_trailers(
+            self.input,
+            read,
+            decoded,
+            self.hasher.as_deref_mut(),
+            self.seal,
+        )?;
+        Some((read, written))
+    }
+
+    /// Decode the stream, without the trailers. Returns the number of
+    /// bytes read and written.
+    fn decode_stream(&mut self) -> Option<(usize, usize)> {
+        if let Some((read, stored)) = stored_view(self.input) {
+            self.output.extend(stored);
+            return Some((read, stored.len()));
+        }
+        let header = read_full_header(self.input)?;
+        let buffer = &self.input[header..];
 
         if match_signature(buffer, &ARITH_SIG) {
-            let mut decoder = AAD::new(buffer, self.output);
+            let (read, written) = decode_arith(buffer, self.output)?;
+            return Some((read + header, written));
+        }
+        if match_signature(buffer, &FROZEN_SIG) {
+            let mut decoder = FrozenDecoder::new(buffer, self.output);
             let (read, written) = decoder.decode()?;
-            return Some((read + ARITH_SIG.len() + FULL_SIG.len(), written));
+            return Some((read + header, written));
         }
 
         let mut decoder = PagerDecoder::new(buffer, self.output);
         decoder.set_callback(decode_or_nop);
         let (read, written) = decoder.decode()?;
-        Some((read + FULL_SIG.len(), written))
+        Some((read + header, written))
+    }
+}
+
+/// Decode the stream of the arithmetic coder at the start of 'input', and the
+/// pages that follow it if the coder stopped early (see
+/// 'AdaptiveArithmeticEncoder::encode_prefix'). Returns the number of bytes
+/// read and written.
+fn decode_arith(input: &[u8], output: &mut Vec<u8>) -> Option<(usize, usize)> {
+    let (mut read, mut written) = AAD::new(input, output).decode()?;
+    if match_signature(&input[read..], &PAGER_SIG) {
+        let mut decoder = PagerDecoder::new(&input[read..], output);
+        decoder.set_callback(decode_or_nop);
+        let (used, rest) = decoder.decode()?;
+        read += used;
+        written += rest;
+    }
+    Some((read, written))
+}
+
+/// Return the size of the header of the stream 'input': the signature, the
+/// metadata and the table of the reset points, if any. Returns None if the
+/// input is not a stream of the full compressor.
+pub fn read_full_header(input: &[u8]) -> Option<usize> {
+    if !match_signature(input, &FULL_SIG) {
+        return None;
+    }
+    let cursor = FULL_SIG.len() + skip_metadata(&input[FULL_SIG.len()..])?;
+    match pager::read_reset_table_header(&input[cursor..]) {
+        Some((header, len)) => {
+            input.get(cursor + header..)?.get(..len)?;
+            Some(cursor + header + len)
+        }
+        None => Some(cursor),
+    }
+}
+
+/// Return the offset and the first page of each reset point of the stream
+/// 'input', without decompressing it (see 'FullEncoder::with_reset_points').
+/// Streams without reset points return an empty table. Returns None if the
+/// input is not a stream of the full compressor, or if the table is invalid.
+pub fn reset_table(input: &[u8]) -> Option<Vec<(usize, usize)>> {
+    if !match_signature(input, &FULL_SIG) {
+        return None;
+    }
+    let cursor = FULL_SIG.len() + skip_metadata(&input[FULL_SIG.len()..])?;
+    let rest = &input[cursor..];
+    if pager::read_reset_table_header(rest).is_none() {
+        return Some(Vec::new());
+    }
+    pager::read_reset_table(rest).map(|(_, points)| points)
+}
+
+/// Return a view of the content of the stream 'input' if it is stored without
+/// compression (see 'STORED_SIG'), without copying it. Returns the number of
+/// bytes read, without the trailers, and the content. Returns None if the
+/// stream is compressed or invalid. The trailers are not verified.
+pub fn stored_view(input: &[u8]) -> Option<(usize, &[u8])> {
+    let header = read_full_header(input)?;
+    let buffer = &input[header..];
+    if !match_signature(buffer, &STORED_SIG) {
+        return None;
+    }
+    let (read, len) = leb128::decode_len(&buffer[STORED_SIG.len()..])?;
+    let start = STORED_SIG.len() + read;
+    let stored = buffer.get(start..start.checked_add(len)?)?;
+    Some((header + start + len, stored))
+}
+
+/// Return the first 'len' bytes of the stream 'input', or all of the bytes if
+/// the stream is shorter. The paged streams are decoded up to the page that
+/// contains the last byte, and the decoding of that page stops early, so the
+/// cost doesn't depend on the size of the stream. Returns None if the stream is
+/// invalid. The bytes after the prefix are not validated.
+pub fn decode_prefix(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    if let Some((_, stored)) = stored_view(input) {
+        return Some(stored[..len.min(stored.len())].to_vec());
+    }
+    let buffer = &input[read_full_header(input)?..];
+    let mut output = Vec::new();
+
+    if match_signature(buffer, &ARITH_SIG) {
+        // The arithmetic coder can't stop early, so the whole stream is decoded.
+        let _ = decode_arith(buffer, &mut output)?;
+    } else if match_signature(buffer, &FROZEN_SIG) {
+        let _ = FrozenDecoder::new(buffer, &mut output).decode()?;
+    } else {
+        let mut decoder = PagerDecoder::new(buffer, &mut output);
+        decoder.set_callback(decode_or_nop);
+        decoder.set_limit(len, decode_or_nop_prefix);
+        let _ = decoder.decode()?;
+    }
+    output.truncate(len);
+    Some(output)
+}
+
+/// Return the bytes of the stream 'input' from the reset point at 'offset' to
+/// the end of the stream (see 'FullEncoder::with_reset_points'). The pages
+/// before the reset point are skipped without decoding them. Returns None if
+/// the stream has no reset point at 'offset', or if the stream is invalid.
+/// Every stream can be decoded from offset zero. The trailers are not
+/// verified.
+pub fn decode_from_reset(input: &[u8], offset: usize) -> Option<Vec<u8>> {
+    if let Some((_, stored)) = stored_view(input) {
+        return stored.get(offset..).map(|rest| rest.to_vec());
+    }
+    let mut output = Vec::new();
+    if offset == 0 {
+        let _ = FullDecoder::new(input, &mut output).decode()?;
+        return Some(output);
+    }
+    let points = reset_table(input)?;
+    let (_, page) = points.into_iter().find(|(at, _)| *at == offset)?;
+    let buffer = &input[read_full_header(input)?..];
+    let mut decoder = PagerDecoder::new(buffer, &mut output);
+    decoder.set_callback(decode_or_nop);
+    for _ in 0..page {
+        decoder.skip_page()?;
     }
+    let _ = decoder.decode()?;
+    Some(output)
 }
@@ -0,0 +1,169 @@
+//! A high-level interface for compressing and decompressing buffers with the
+//! full compressor. The handles keep their buffers between calls, which avoids
+//! repeated allocations when compressing many small messages.
+
+use crate::full::{FullDecoder, FullEncoder};
+use crate::scratch::{with_scratch, Scratch};
+use crate::{Context, Decoder, Encoder};
+use std::thread;
+
+/// A reusable compressor that owns its output buffer, and the tables of the
+/// matchers and of the entropy coders.
+pub struct Compressor {
+    /// Encoder context.
+    ctx: Context,
+    /// Holds the result of the last operation. The allocation is retained
+    /// between calls.
+    buffer: Vec<u8>,
+    /// The scratch buffers of the encoders, which the calls take the tables
+    /// from. See 'scratch::with_scratch'.
+    scratch: Scratch,
+}
+
+impl Compressor {
+    /// Create a new compressor with the encoder context 'ctx'.
+    pub fn new(ctx: Context) -> Self {
+        Self {
+            ctx,
+            buffer: Vec::new(),
+            scratch: Scratch::new(),
+        }
+    }
+
+    /// Returns the encoder context.
+    pub fn context(&self) -> Context {
+        self.ctx
+    }
+
+    /// Sets the encoder context that is used by the next calls.
+    pub fn set_context(&mut self, ctx: Context) {
+        self.ctx = ctx;
+    }
+
+    /// Compress 'input' and return a reference to the compressed buffer. The
+    /// buffer is valid until the next call.
+    pub fn compress(&mut self, input: &[u8]) -> &[u8] {
+        self.buffer.clear();
+        let (buffer, ctx) = (&mut self.buffer, self.ctx);
+        with_scratch(&mut self.scratch, || {
+            FullEncoder::new(input, buffer, ctx).encode()
+        });
+        &self.buffer
+    }
+
+    /// Decompress 'input' and return a reference to the decompressed buffer,
+    /// or None if the input is not a valid stream. The buffer is valid until
+    /// the next call.
+    pub fn decompress(&mut self, input: &[u8]) -> Option<&[u8]> {
+        self.buffer.clear();
+        let buffer = &mut self.buffer;
+        let (read, _) = with_scratch(&mut self.scratch, || {
+            FullDecoder::new(input, buffer).decode()
+        })?;
+        if read != input.len() {
+            return None;
+        }
+        Some(&self.buffer)
+    }
+
+    /// Returns the number of bytes that the scratch buffers hold.
+    pub fn scratch_bytes(&self) -> usize {
+        self.scratch.bytes()
+    }
+
+    /// Clear the result of the last operation. The output buffer and the
+    /// scratch buffers keep their memory for the next calls.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+/// A compressor handle that can be shared between threads. The handle holds
+/// the immutable configuration, and each call uses its own scratch state.
+#[derive(Clone)]
+pub struct CompressorHandle {
+    /// Encoder context.
+    ctx: Context,
+}
+
+impl CompressorHandle {
+    /// Create a new handle with the encoder context 'ctx'.
+    pub fn new(ctx: Context) -> Self {
+        Self { ctx }
+    }
+
+    /// Returns the encoder context.
+    pub fn context(&self) -> Context {
+        self.ctx
+    }
+
+    /// Compress 'input' and return the compressed buffer.
+    pub fn compress(&self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let _ = FullEncoder::new(input, &mut output, self.ctx).encode();
+        output
+    }
+
+    /// Create a scratch compressor for the calling thread. The scratch state
+    /// retains its allocations between calls.
+    pub fn scratch(&self) -> Compressor {
+        Compressor::new(self.ctx)
+    }
+}
+
+/// A decompressor handle that can be shared between threads.
+#[derive(Clone, Default)]
+pub struct DecompressorHandle {}
+
+impl DecompressorHandle {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Decompress 'input' and return the decompressed buffer, or None if the
+    /// input is not a valid stream.
+    pub fn decompress(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let mut output = Vec::new();
+        let (read, _) = FullDecoder::new(input, &mut output).decode()?;
+        if read != input.len() {
+            return None;
+        }
+        Some(output)
+    }
+}
+
+/// Compress each one of the buffers in 'inputs' and return the compressed
+/// buffers in the same order. This is useful for compressing many small
+/// records, such as the rows of a database. The tables of the encoders and the
+/// scratch buffers are reused from one input to the next.
+pub fn compress_batch(inputs: &[&[u8]], ctx: Context) -> Vec<Vec<u8>> {
+    let mut compressor = Compressor::new(ctx);
+    inputs
+        .iter()
+        .map(|input| compressor.compress(input).to_vec())
+        .collect()
+}
+
+/// Similar to 'compress_batch', but splits the inputs into 'threads' groups
+/// that are compressed in parallel.
+pub fn compress_batch_parallel(
+    inputs: &[&[u8]],
+    ctx: Context,
+    threads: usize,
+) -> Vec<Vec<u8>> {
+    let threads = threads.clamp(1, inputs.len().max(1));
+    if threads == 1 {
+        return compress_batch(inputs, ctx);
+    }
+    let group_size = inputs.len().div_ceil(threads);
+    thread::scope(|s| {
+        let workers: Vec<_> = inputs
+            .chunks(group_size)
+            .map(|group| s.spawn(move || compress_batch(group, ctx)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
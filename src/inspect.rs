@@ -0,0 +1,534 @@
+//! Describes the content of compressed blocks: the sizes of their streams, the
+//! coding of each stream and the list of sequences. This powers the '--info'
+//! flag of the command line tool, and helps to explain changes in the
+//! compression ratio. The alternate format ('{:#}') of 'BlockInfo' also lists
+//! the sequences.
+
+use crate::block::{read_matched_header, read_offset_lanes};
+use crate::block::{stream_codec, BlockDecoder, BlockReader};
+use crate::block::{Sequence, LONG_OFFSET_BITS, OFFSET_BITS, OFFSET_CONTEXTS};
+use crate::block::{CODEC_ENTROPY, CODEC_RAW, CODEC_RLE};
+use crate::coding::entropy::read_histogram;
+use crate::full::read_full_header;
+use crate::pager::{read_checked_header, read_header, read_hole};
+use crate::pager::{read_constant_page, record_len};
+use crate::pager::{read_page_header, read_ref, read_sparse_header};
+use crate::pipeline::{Codec, Pipeline};
+use crate::utils::array_encoding::decode as decode_arr;
+use crate::utils::leb128;
+use crate::utils::signatures::REUSED_TABLE_SIG;
+use crate::utils::signatures::SMALL_BLOCK_SIG;
+use crate::utils::signatures::{match_signature, BLOCK_SIG, DUP_PAGE_SIG};
+use crate::utils::signatures::{FAST_BLOCK_SIG, LONG_BLOCK_SIG, NOP_ENC};
+use crate::utils::signatures::{MATCHED_LIT_SIG, OFFSET_CTX_SIG, RESIDUAL_SIG};
+use crate::utils::signatures::{PIPELINE_BLOCK_SIG, RLE_BLOCK_SIG};
+use crate::{Context, Decoder};
+use std::fmt;
+use std::time::Duration;
+
+/// The size of the tables of the entropy coder of the block streams.
+const TABLE_SIZE: usize = 4096;
+
+/// The kinds of blocks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+    /// A block with separate streams of literals, sequences and offsets.
+    Regular,
+    /// A regular block with long offsets.
+    Long,
+    /// A compact block that keeps all of its streams together.
+    Small,
+    /// A block of runs, followed by a block of the other bytes.
+    Rle,
+    /// An LZ4 stream of the fastest level.
+    Fast,
+    /// A block that was transformed by the stages of a pipeline.
+    Pipeline,
+}
+
+impl BlockKind {
+    /// Return the name of the kind, as the '--info' flag prints it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockKind::Regular => "regular",
+            BlockKind::Long => "long",
+            BlockKind::Small => "small",
+            BlockKind::Rle => "rle",
+            BlockKind::Fast => "fast",
+            BlockKind::Pipeline => "pipeline",
+        }
+    }
+}
+
+/// The coding of a page of a stream.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Coding {
+    /// The bytes are stored without compression.
+    Stored,
+    /// The stream is saved raw, without pages.
+    Raw,
+    /// The stream is a run of one byte.
+    Run,
+    /// The bytes are coded with the context-mixing coder.
+    Residual,
+    /// The bytes are coded with the tANS coder, with a table of 'symbols'
+    /// symbols that spends 'bits' bits on each symbol, on average.
+    Entropy { symbols: usize, bits: f64 },
+    /// The bytes are coded with the table of the previous entropy page.
+    Reused,
+    /// The literals are coded in the context of the match byte, with the
+    /// alignment 'align'.
+    Matched { align: usize },
+    /// Other records, such as constant pages.
+    Other,
+}
+
+/// The size and the coding of a stream of a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamInfo {
+    pub name: &'static str,
+    /// The size of the stream, in bytes.
+    pub size: usize,
+    /// The coding of each page of the stream.
+    pub pages: Vec<Coding>,
+}
+
+/// A description of a compressed block. See 'inspect_block'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+    pub kind: BlockKind,
+    /// The size of the compressed block.
+    pub size: usize,
+    /// The size of the decoded block.
+    pub len: usize,
+    /// The literals, the sequences and the offsets of regular blocks.
+    pub streams: Vec<StreamInfo>,
+    /// The sequences of regular blocks.
+    pub sequences: Vec<Sequence>,
+    /// The block of the bytes that are not in runs, for RLE blocks, or the
+    /// block of the transformed bytes, for pipeline blocks.
+    pub inner: Option<Box<BlockInfo>>,
+}
+
+/// Return the average number of bits that the normalized histogram 'hist'
+/// spends on each symbol, and the number of symbols in the histogram.
+fn histogram_cost(hist: &[u32]) -> (f64, usize) {
+    let total = hist.iter().sum::<u32>() as f64;
+    let mut bits = 0.;
+    for &count in hist.iter().filter(|&&count| count > 0) {
+        let p = count as f64 / total;
+        bits -= p * p.log2();
+    }
+    (bits, hist.iter().filter(|&&count| count > 0).count())
+}
+
+/// Return the coding of the encoded page 'page' of an entropy-coded stream
+/// with the alphabet 'ALPHABET'.
+fn page_coding<const ALPHABET: usize>(page: &[u8]) -> Coding {
+    if match_signature(page, &NOP_ENC) {
+        return Coding::Stored;
+    }
+    if match_signature(page, &RESIDUAL_SIG) {
+        return Coding::Residual;
+    }
+    if match_signature(page, &REUSED_TABLE_SIG) {
+        return Coding::Reused;
+    }
+    match read_histogram::<ALPHABET, TABLE_SIZE>(page) {
+        Some(hist) => {
+            let (bits, symbols) = histogram_cost(&hist);
+            Coding::Entropy { symbols, bits }
+        }
+        None => Coding::Other,
+    }
+}
+
+/// Describe the paged stream 'input', with the alphabet 'ALPHABET'.
+fn paged_stream<const ALPHABET: usize>(
+    name: &'static str,
+    input: &[u8],
+) -> Option<StreamInfo> {
+    let (mut cursor, parts) = read_header(input)?;
+    let mut pages = Vec::new();
+    for _ in 0..parts {
+        let rest = &input[cursor..];
+        let coding = match read_page_header(rest) {
+            Some((read, len)) => {
+                page_coding::<ALPHABET>(rest.get(read..)?.get(..len)?)
+            }
+            None => Coding::Other,
+        };
+        pages.push(coding);
+        cursor += record_len(rest)?;
+    }
+    Some(StreamInfo {
+        name,
+        size: input.len(),
+        pages,
+    })
+}
+
+/// Describe the stream 'input' of the codec 'codec'. Streams of the entropy
+/// codec are paged streams with the alphabet 'ALPHABET'.
+fn coded_stream<const ALPHABET: usize>(
+    name: &'static str,
+    input: &[u8],
+    codec: u8,
+) -> Option<StreamInfo> {
+    let coding = match codec {
+        CODEC_ENTROPY => return paged_stream::<ALPHABET>(name, input),
+        CODEC_RAW => Coding::Raw,
+        CODEC_RLE => Coding::Run,
+        _ => return None,
+    };
+    Some(StreamInfo {
+        name,
+        size: input.len(),
+        pages: vec![coding],
+    })
+}
+
+/// Describe the offset tokens at the start of 'input', with the alphabet
+/// 'ALPHABET' and the codec 'codec'. Tokens that are split by their context
+/// are described as one stream, with the pages of all of the contexts.
+fn offset_stream<const ALPHABET: usize>(
+    input: &[u8],
+    codec: u8,
+) -> Option<StreamInfo> {
+    if codec != CODEC_ENTROPY || !match_signature(input, &OFFSET_CTX_SIG) {
+        return coded_stream::<ALPHABET>("offsets", input, codec);
+    }
+    let mut pages = Vec::new();
+    let mut read = OFFSET_CTX_SIG.len();
+    for _ in 0..OFFSET_CONTEXTS {
+        let mut stream = Vec::new();
+        read += decode_arr(&input[read..], &mut stream)?;
+        pages.extend(paged_stream::<ALPHABET>("offsets", &stream)?.pages);
+    }
+    Some(StreamInfo {
+        name: "offsets",
+        size: input.len(),
+        pages,
+    })
+}
+
+/// Describe the streams of the regular block 'input', after the signature.
+fn regular_block(input: &[u8], long: bool, info: &mut BlockInfo) -> Option<()> {
+    let selector = *input.first()?;
+    let mut arrays = [Vec::new(), Vec::new(), Vec::new()];
+    let mut read = 1;
+    for array in arrays.iter_mut() {
+        read += decode_arr(&input[read..], array)?;
+    }
+    let [literals, sequences, offsets] = arrays;
+
+    let lit_codec = stream_codec(selector, 0);
+    let matched = lit_codec == CODEC_ENTROPY
+        && match_signature(&literals, &MATCHED_LIT_SIG);
+    let align = match matched {
+        true => read_matched_header(&literals)?.2,
+        false => 1,
+    };
+    let literals = if matched {
+        StreamInfo {
+            name: "literals",
+            size: literals.len(),
+            pages: vec![Coding::Matched { align }],
+        }
+    } else {
+        coded_stream::<256>("literals", &literals, lit_codec)?
+    };
+
+    // The tokens of the sequences are followed by the long lengths.
+    let mut tokens = Vec::new();
+    decode_arr(&sequences, &mut tokens)?;
+    let codec = stream_codec(selector, 1);
+    let mut tokens = coded_stream::<256>("sequences", &tokens, codec)?;
+    tokens.size = sequences.len();
+
+    // The low bits of the offsets of aligned blocks come first.
+    let (read, _) = read_offset_lanes(&offsets, align)?;
+    let codec = stream_codec(selector, 3);
+    let mut offset_tokens = match long {
+        true => offset_stream::<LONG_OFFSET_BITS>(&offsets[read..], codec)?,
+        false => offset_stream::<OFFSET_BITS>(&offsets[read..], codec)?,
+    };
+    offset_tokens.size = offsets.len();
+    info.streams = vec![literals, tokens, offset_tokens];
+    Some(())
+}
+
+/// Return the kind of the block at the start of 'input', by its signature.
+fn block_kind(input: &[u8]) -> BlockKind {
+    if match_signature(input, &FAST_BLOCK_SIG) {
+        BlockKind::Fast
+    } else if match_signature(input, &SMALL_BLOCK_SIG) {
+        BlockKind::Small
+    } else if match_signature(input, &RLE_BLOCK_SIG) {
+        BlockKind::Rle
+    } else if match_signature(input, &PIPELINE_BLOCK_SIG) {
+        BlockKind::Pipeline
+    } else if match_signature(input, &LONG_BLOCK_SIG) {
+        BlockKind::Long
+    } else {
+        BlockKind::Regular
+    }
+}
+
+/// Return a description of the compressed block at the start of 'input', or
+/// None if the block is invalid.
+pub fn inspect_block(input: &[u8]) -> Option<BlockInfo> {
+    // The decoder rejects RLE and pipeline blocks that nest deeper than one
+    // level, so the inner blocks are described without deep recursion.
+    let mut decoded = Vec::new();
+    let (size, len) = BlockDecoder::new(input, &mut decoded).decode()?;
+    let kind = block_kind(input);
+    let mut info = BlockInfo {
+        kind,
+        size,
+        len,
+        streams: Vec::new(),
+        sequences: Vec::new(),
+        inner: None,
+    };
+
+    let body = &input[BLOCK_SIG.len()..size];
+    match kind {
+        BlockKind::Regular | BlockKind::Long => {
+            regular_block(body, kind == BlockKind::Long, &mut info)?;
+            info.sequences = BlockReader::new(input).read()?.1.sequences;
+        }
+        BlockKind::Rle => {
+            let mut runs = Vec::new();
+            let read = decode_arr(body, &mut runs)?;
+            info.inner = Some(Box::new(inspect_block(&body[read..])?));
+        }
+        BlockKind::Pipeline => {
+            let (read, pipeline) = Pipeline::payload_offset(input)?;
+            if pipeline.codec() == Codec::Block {
+                info.inner = Some(Box::new(inspect_block(&input[read..])?));
+            }
+        }
+        BlockKind::Small | BlockKind::Fast => {}
+    }
+    Some(info)
+}
+
+/// Add the descriptions of the blocks in the paged stream 'input' to
+/// 'blocks'. Pages that are not blocks, such as stored pages, are skipped.
+fn add_blocks(input: &[u8], blocks: &mut Vec<BlockInfo>) -> Option<()> {
+    let (mut cursor, parts) = read_header(input)?;
+    for _ in 0..parts {
+        let rest = &input[cursor..];
+        cursor += record_len(rest)?;
+        let rest = match read_checked_header(rest) {
+            Some((read, _, _)) => &rest[read..],
+            None => rest,
+        };
+
+        // The data segments of sparse pages are encoded like regular pages.
+        let mut segments = Vec::new();
+        if let Some((read, len)) = read_page_header(rest) {
+            segments.push(rest.get(read..)?.get(..len)?);
+        } else if let Some((read, len)) = read_sparse_header(rest) {
+            let body = rest.get(read..)?.get(..len)?;
+            let (mut cursor, items) = leb128::decode_len(body)?;
+            for _ in 0..items {
+                let item = &body[cursor..];
+                if let Some((read, _)) = read_hole(item) {
+                    cursor += read;
+                } else if let Some((read, _, _, _)) = read_ref(item) {
+                    cursor += read;
+                } else {
+                    let (read, len) = read_page_header(item)?;
+                    segments.push(item.get(read..)?.get(..len)?);
+                    cursor += read + len;
+                }
+            }
+        }
+        blocks.extend(segments.into_iter().filter_map(inspect_block));
+    }
+    Some(())
+}
+
+/// A description of the encoding of one page, for offline tuning and for
+/// tracking the compression ratio across versions of a corpus. The pager
+/// records an entry for each page when it is given a page log (see
+/// 'FullEncoder::with_page_log'), and 'to_json' saves the entry as a line of
+/// a JSON-lines file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageEntry {
+    /// The index of the page in the stream.
+    pub index: usize,
+    /// The offset of the page in the input.
+    pub offset: usize,
+    /// The size of the page.
+    pub input: usize,
+    /// The size of the page record.
+    pub output: usize,
+    /// The codec of the record. See 'record_codec'.
+    pub codec: &'static str,
+    /// The filters of the pipeline of the page, if any.
+    pub filter: Option<Pipeline>,
+    /// The level of the encoder, which the time budget may lower.
+    pub level: u8,
+    /// The time spent on encoding the page.
+    pub time: Duration,
+}
+
+impl PageEntry {
+    /// Describe the page 'index' at 'offset' of the input, of 'len' bytes,
+    /// that was encoded into 'record' with 'ctx' in 'time'.
+    pub fn new(
+        index: usize,
+        offset: usize,
+        len: usize,
+        record: &[u8],
+        ctx: Context,
+        time: Duration,
+    ) -> Self {
+        let (codec, filter) = record_codec(record);
+        Self {
+            index,
+            offset,
+            input: len,
+            output: record.len(),
+            codec,
+            filter,
+            level: ctx.level,
+            time,
+        }
+    }
+
+    /// Return the entry as one line of JSON, without the newline. The names
+    /// of the codecs and the filters don't need to be escaped.
+    pub fn to_json(&self) -> String {
+        let filter = match self.filter {
+            Some(pipeline) => format!("\"{}\"", pipeline),
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"page\":{},\"offset\":{},\"input\":{},\"output\":{},\
+             \"codec\":\"{}\",\"filter\":{},\"level\":{},\"micros\":{}}}",
+            self.index,
+            self.offset,
+            self.input,
+            self.output,
+            self.codec,
+            filter,
+            self.level,
+            self.time.as_micros()
+        )
+    }
+}
+
+/// Return the name of the codec of the page record 'record', such as
+/// "constant", "stored" or the kind of the block, and the pipeline of
+/// pipeline blocks. The codec of records that can't be parsed is "unknown".
+pub fn record_codec(record: &[u8]) -> (&'static str, Option<Pipeline>) {
+    let record = match read_checked_header(record) {
+        Some((read, _, _)) => &record[read..],
+        None => record,
+    };
+    if read_constant_page(record).is_some() {
+        return ("constant", None);
+    }
+    if match_signature(record, &DUP_PAGE_SIG) {
+        return ("duplicate", None);
+    }
+    if read_sparse_header(record).is_some() {
+        return ("sparse", None);
+    }
+    let Some((read, _)) = read_page_header(record) else {
+        return ("unknown", None);
+    };
+    let packet = &record[read..];
+    if match_signature(packet, &NOP_ENC) {
+        return ("stored", None);
+    }
+    let kind = block_kind(packet);
+    let filter = match kind {
+        BlockKind::Pipeline => Pipeline::payload_offset(packet).map(|p| p.1),
+        _ => None,
+    };
+    (kind.name(), filter)
+}
+
+/// Return the descriptions of the blocks of the compressed stream 'input', in
+/// order. Returns None if the input is not a paged stream of the full
+/// compressor.
+pub fn inspect_stream(input: &[u8]) -> Option<Vec<BlockInfo>> {
+    let header = read_full_header(input)?;
+    let mut blocks = Vec::new();
+    add_blocks(&input[header..], &mut blocks)?;
+    Some(blocks)
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "lit {}, match {}, offset {}",
+            self.lit_len, self.mat_len, self.offset
+        )
+    }
+}
+
+impl fmt::Display for Coding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Coding::Stored => write!(f, "stored"),
+            Coding::Raw => write!(f, "raw"),
+            Coding::Run => write!(f, "run"),
+            Coding::Residual => write!(f, "residual"),
+            Coding::Reused => write!(f, "reused table"),
+            Coding::Entropy { symbols, bits } => {
+                write!(f, "entropy ({} symbols, {:.2} bits)", symbols, bits)
+            }
+            Coding::Matched { align } => write!(f, "matched (align {})", align),
+            Coding::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl fmt::Display for StreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} bytes", self.name, self.size)?;
+        for (i, coding) in self.pages.iter().enumerate() {
+            let sep = if i == 0 { "," } else { ";" };
+            write!(f, "{} {}", sep, coding)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BlockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = self.kind.name();
+        write!(f, "{} block: {} -> {} bytes", kind, self.size, self.len)?;
+        for stream in &self.streams {
+            write!(f, "\n  {}", stream)?;
+        }
+        if !self.streams.is_empty() {
+            let matched: u64 =
+                self.sequences.iter().map(|s| s.mat_len as u64).sum();
+            let count = self.sequences.len();
+            write!(f, "\n  {} sequences, {} bytes matched", count, matched)?;
+        }
+        if f.alternate() {
+            for seq in &self.sequences {
+                write!(f, "\n    {}", seq)?;
+            }
+        }
+        if let Some(inner) = &self.inner {
+            let text = match f.alternate() {
+                true => format!("{:#}", inner),
+                false => inner.to_string(),
+            };
+            for line in text.lines() {
+                write!(f, "\n  {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
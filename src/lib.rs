@@ -1,12 +1,75 @@
+/// Implement the methods of 'Decoder' that only depend on the state of the
+/// decoder, for decoders that keep the input in the field 'input', the output
+/// stream in 'output', and the number of bytes that the last call to 'decode'
+/// consumed in 'read'.
+macro_rules! decoder_state {
+    () => {
+        fn discard(&mut self, len: usize) {
+            let len = self.output.len() - len;
+            self.output.truncate(len);
+        }
+
+        fn remaining_input(&self) -> &'a [u8] {
+            &self.input[self.read..]
+        }
+    };
+}
+
+pub mod auto;
+pub mod bitstream;
 pub mod bitvector;
+pub mod blob;
 pub mod block;
+pub mod budget;
+pub mod bwt;
+pub mod checkpoint;
 pub mod coding;
+pub mod delta;
+pub mod digest;
+pub mod estimate;
+pub mod frame;
+pub mod framing;
 pub mod full;
+pub mod handle;
+pub mod inspect;
+pub mod limits;
+pub mod logs;
 pub mod lz;
+pub mod mapped;
+pub mod merkle;
+pub mod metadata;
 pub mod models;
 pub mod nop;
 pub mod pager;
+pub mod pipeline;
+pub mod profile;
+pub mod reader;
+pub mod recompress;
+pub mod registry;
+pub mod repair;
+pub mod rle;
+pub mod scatter;
+pub mod scratch;
+pub mod seal;
+pub mod sparse;
+pub mod transcode;
 pub mod utils;
+pub mod verify;
+pub mod volume;
+
+pub use verify::verify;
+
+use lz::matcher::ParseParams;
+use pipeline::Pipeline;
+use std::time::Duration;
+
+/// Specifies the minimum, average and maximum sizes of content-defined chunks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkSizes {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
 
 /// Stores information about the environment.
 #[derive(Copy, Clone)]
@@ -15,11 +78,214 @@ pub struct Context {
     pub level: u8,
     /// Specifies the size of each block.
     pub block_size: usize,
+    /// When set, the pager splits the input on content-defined boundaries
+    /// instead of using fixed-size blocks.
+    pub chunking: Option<ChunkSizes>,
+    /// When set, the full encoder picks the level that fits the time budget,
+    /// and lowers it if the encoding falls behind. See 'budget'.
+    pub time_budget: Option<Duration>,
+    /// When set, blocks that are larger than the regular window are encoded
+    /// with long offsets, so matches can reach the start of the block.
+    pub long_window: bool,
+    /// When set, the pager finds long repetitions across the whole input
+    /// before it splits it, and saves them as references to earlier pages.
+    /// See 'lz::global'.
+    pub global_matching: bool,
+    /// The acceleration of the fastest level. Higher values trade compression
+    /// ratio for speed. See 'with_fast_level'.
+    pub acceleration: u8,
+    /// When set, the pager saves the checksum and the length of each page
+    /// before its record. See 'pager::write_checked_header'.
+    pub page_checksums: bool,
+    /// When set, the full encoder appends a checksum of the compressed bytes
+    /// to the stream. See 'frame'.
+    pub frame_checksum: bool,
+    /// When set, the full encoder saves the SHA-256 digest of the input after
+    /// the stream. See 'digest'.
+    pub digest: bool,
+    /// When set, the full encoder saves a hash tree of the pages after the
+    /// stream. See 'merkle'.
+    pub hash_tree: bool,
+    /// When set, the blocks are transformed and encoded with the pipeline,
+    /// instead of the default stages of the block encoder.
+    pub pipeline: Option<Pipeline>,
+    /// When set, the matchers with lazy parsing use these parameters instead
+    /// of the ones of the level. See 'with_parse_params'.
+    pub parse: Option<ParseParams>,
+    /// The max expansion of the adaptive arithmetic coder, in percent of the
+    /// input, or None for no limit. See
+    /// 'with_max_expansion'.
+    pub max_expansion: Option<u32>,
+    /// When set, the arithmetic level of the full encoder codes the pages in
+    /// parallel with a model that does not adapt. See 'with_frozen_model'.
+    pub frozen_model: bool,
+    /// When set, the pager saves the long zero runs of the pages as holes.
+    /// See 'with_sparse_holes'.
+    pub sparse_holes: bool,
+    /// When set, the blocks code the literals and the sequence tokens with
+    /// the context-mixing coder. See 'with_hybrid'.
+    pub hybrid: bool,
+    /// When set, the blocks collapse the long byte runs before matching. See
+    /// 'with_rle_pass'.
+    pub rle_pass: bool,
 }
 
+/// The highest compression level. See 'full::ARITH_LEVEL'.
+pub const MAX_LEVEL: u8 = 13;
+
 impl Context {
     pub fn new(level: u8, block_size: usize) -> Self {
-        Self { level, block_size }
+        Self {
+            level,
+            block_size,
+            chunking: None,
+            time_budget: None,
+            long_window: false,
+            global_matching: false,
+            acceleration: 0,
+            page_checksums: false,
+            frame_checksum: false,
+            digest: false,
+            hash_tree: false,
+            pipeline: None,
+            parse: None,
+            max_expansion: Some(coding::adaptive::MAX_EXPANSION),
+            frozen_model: false,
+            sparse_holes: false,
+            hybrid: false,
+            rle_pass: true,
+        }
+    }
+
+    /// Return a context like 'new', or None if 'level' is above 'MAX_LEVEL'.
+    pub fn try_new(level: u8, block_size: usize) -> Option<Self> {
+        (level <= MAX_LEVEL).then(|| Self::new(level, block_size))
+    }
+
+    /// Let the full encoder pick the highest level that is expected to
+    /// compress the input within 'budget', instead of 'level'.
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Let the blocks use offsets of up to 'block::MAX_LONG_MATCH_OFFSET'
+    /// bytes, to find the repetitions of very large blocks. The matcher of
+    /// long blocks uses a larger table, and more memory.
+    pub fn with_long_window(mut self) -> Self {
+        self.long_window = true;
+        self
+    }
+
+    /// Let the pager save the parts of pages that repeat earlier pages as
+    /// references. The pages must be decoded in order, and the decoder must
+    /// keep the earlier pages, so the streaming reader rejects these streams
+    /// (see 'reader').
+    pub fn with_global_matching(mut self) -> Self {
+        self.global_matching = true;
+        self
+    }
+
+    /// Select the fastest level with the acceleration 'acceleration', like the
+    /// negative levels of zstd. Higher values use a smaller table, longer
+    /// matches and larger steps over incompressible data. The values above
+    /// 'lz::matcher::MAX_ACCELERATION' are clamped.
+    pub fn with_fast_level(mut self, acceleration: u8) -> Self {
+        self.level = block::FAST_LEVEL;
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Save a checksum with each page, so decoders detect the pages that
+    /// decode into the wrong bytes, and the undamaged pages of a damaged
+    /// stream can be recovered. See 'repair'.
+    pub fn with_page_checksums(mut self) -> Self {
+        self.page_checksums = true;
+        self
+    }
+
+    /// Append a checksum of the compressed bytes to the stream, so damaged
+    /// streams are detected without decompressing them. See
+    /// 'frame::verify_frame'.
+    pub fn with_frame_checksum(mut self) -> Self {
+        self.frame_checksum = true;
+        self
+    }
+
+    /// Save the SHA-256 digest of the input after the stream, so decoders
+    /// check the decoded bytes end to end. See 'digest'.
+    pub fn with_digest(mut self) -> Self {
+        self.digest = true;
+        self
+    }
+
+    /// Save a hash tree of the pages after the stream, so clients verify the
+    /// ranges of pages that they download, without reading the whole stream.
+    /// Streams without pages have no tree. See 'merkle'.
+    pub fn with_hash_tree(mut self) -> Self {
+        self.hash_tree = true;
+        self
+    }
+
+    /// Encode the blocks with the transforms and the codec of 'pipeline'. See
+    /// 'pipeline::Pipeline'.
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    /// Parse the input with the search depth and the heuristics of 'params',
+    /// instead of the ones of the level. The levels with lazy parsing (1 to
+    /// 10) use them. See 'lz::matcher::ParseParams'.
+    pub fn with_parse_params(mut self, params: ParseParams) -> Self {
+        self.parse = Some(params);
+        self
+    }
+
+    /// Stop the adaptive arithmetic coder of the full encoder when the output
+    /// of a part of the input grows more than 'percent' percent larger than the
+    /// part, and encode the rest of the input with pages of blocks. None
+    /// lets the coder run to the end of the input. See
+    /// 'AdaptiveArithmeticEncoder::encode_prefix'.
+    pub fn with_max_expansion(mut self, percent: Option<u32>) -> Self {
+        self.max_expansion = percent;
+        self
+    }
+
+    /// Let the arithmetic level of the full encoder train a model on a prefix
+    /// of the input, and code the pages with the frozen model on all of the
+    /// cores, instead of adapting the model to the whole input. See
+    /// 'coding::frozen'.
+    pub fn with_frozen_model(mut self) -> Self {
+        self.frozen_model = true;
+        self
+    }
+
+    /// Save the zero runs of at least 'pager::MIN_HOLE_SIZE' bytes as holes,
+    /// so that sparse files keep their holes when they are extracted (see
+    /// 'sparse'). The data between the holes is compressed separately, so
+    /// matches don't cross the holes.
+    pub fn with_sparse_holes(mut self) -> Self {
+        self.sparse_holes = true;
+        self
+    }
+
+    /// Code the literals and the sequence tokens of the blocks with the
+    /// context-mixing coder of 'coding::residual' instead of tANS. This is
+    /// slower than tANS, and is most useful with the matcher of level 12.
+    /// The offsets keep tANS.
+    pub fn with_hybrid(mut self) -> Self {
+        self.hybrid = true;
+        self
+    }
+
+    /// Collapse the byte runs of at least 'rle::MIN_RUN' bytes into run tokens
+    /// before the matcher sees the block, if 'enabled' is set, which is the
+    /// default. The matcher is much faster on long runs, but blocks without
+    /// long runs pay for the scan.
+    pub fn with_rle_pass(mut self, enabled: bool) -> Self {
+        self.rle_pass = enabled;
+        self
     }
 }
 
@@ -30,19 +296,68 @@ pub trait Encoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self;
 
     /// Encode the whole input buffer and return the number of bytes that were
-    /// written into the output stream.
+    /// written into the output stream. The count covers the whole stream: the
+    /// signatures, the headers, the payload and the trailers.
     #[must_use]
     fn encode(&mut self) -> usize;
 }
 
-/// A trait that defines the interface for decoding buffers.
+/// A trait that defines the interface for decoding buffers. Decoders stop at
+/// the end of the encoded stream and report the exact number of bytes that
+/// the stream takes, so streams can be embedded in other data. The bytes
+/// after the stream are not inspected. See 'decode_exact' for the strict mode.
 pub trait Decoder<'a> {
     /// Creates a new Decoder that reads from 'input' and writes into 'output'.
     fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self;
 
     /// Try to decode the buffer 'input', and return the number of input bytes
     /// that were consumed followed by the number of bytes written, or None,
-    /// if the input was invalid.
+    /// if the input was invalid. The number of bytes consumed is counted from
+    /// the start of 'input' and covers the whole stream, like the count of the
+    /// encoder: the signatures, the headers, the payload and the trailers.
+    /// Decoding is all or nothing: if the input is invalid, 'output' is
+    /// restored to its length before the call, so that callers can retry with
+    /// other data.
     #[must_use]
     fn decode(&mut self) -> Option<(usize, usize)>;
+
+    /// Remove the last 'len' bytes of the output, which the last call to
+    /// 'decode' wrote. This lets 'decode_exact' reject a valid stream that is
+    /// followed by other data.
+    fn discard(&mut self, len: usize);
+
+    /// Return the bytes of the input that follow the stream that the last
+    /// call to 'decode' consumed, or the whole input if the call failed or if
+    /// 'decode' was not called. Formats that embed streams continue to parse
+    /// their own data from here.
+    fn remaining_input(&self) -> &'a [u8];
+}
+
+/// Return 'res', and truncate 'output' to its first 'start' bytes if 'res' is
+/// None. This implements the all or nothing contract of 'Decoder::decode'.
+pub(crate) fn restore_on_error<T>(
+    output: &mut Vec<u8>,
+    start: usize,
+    res: Option<T>,
+) -> Option<T> {
+    if res.is_none() {
+        output.truncate(start);
+    }
+    res
+}
+
+/// Decode 'input' with the decoder 'D' in strict mode. Returns the number of
+/// bytes written, or None if the input is invalid or if bytes are left after
+/// the end of the encoded stream. The output is left unchanged on errors.
+pub fn decode_exact<'a, D: Decoder<'a>>(
+    input: &'a [u8],
+    output: &'a mut Vec<u8>,
+) -> Option<usize> {
+    let mut decoder = D::new(input, output);
+    let (_, written) = decoder.decode()?;
+    if !decoder.remaining_input().is_empty() {
+        decoder.discard(written);
+        return None;
+    }
+    Some(written)
 }
@@ -0,0 +1,57 @@
+//! Limits the buffers that decoders allocate for lengths that are declared in
+//! the compressed stream. A few bytes of a corrupt stream can declare a length
+//! of gigabytes, so decoders check declared lengths against the size of the
+//! remaining input before allocating. Records that expand without a bound,
+//! such as constant pages, holes, runs and long matches, are checked against
+//! the sizes that the stream itself declares, such as the max page size in
+//! the header of a paged stream, and against the output cap of the current
+//! thread. The output cap is not bounded unless the caller sets it, because
+//! any cap rejects some of the valid streams.
+
+use std::cell::Cell;
+
+/// The default max number of bytes that a single record may expand into.
+pub const DEFAULT_MAX_OUTPUT: usize = usize::MAX;
+
+thread_local! {
+    static MAX_OUTPUT: Cell<usize> = const { Cell::new(DEFAULT_MAX_OUTPUT) };
+}
+
+/// Set the max number of bytes that a single record may expand into, for the
+/// decoders that run on the current thread.
+pub fn set_max_output(limit: usize) {
+    MAX_OUTPUT.with(|cap| cap.set(limit));
+}
+
+/// Return the max number of bytes that a single record may expand into.
+pub fn max_output() -> usize {
+    MAX_OUTPUT.with(|cap| cap.get())
+}
+
+/// Run 'decode' with the output cap of the current thread lowered to 'limit',
+/// and restore the cap when it returns. The cap is never raised.
+pub fn with_max_output<T>(limit: usize, decode: impl FnOnce() -> T) -> T {
+    let saved = max_output();
+    set_max_output(saved.min(limit));
+    let result = decode();
+    set_max_output(saved);
+    result
+}
+
+/// Return 'len' if a record of 'len' bytes is within the output cap, or None
+/// if the record must be rejected.
+pub fn check_output(len: usize) -> Option<usize> {
+    if len > max_output() {
+        return None;
+    }
+    Some(len)
+}
+
+/// Return 'len' if 'input' has at least 'len' bytes left after 'cursor', or
+/// None if the declared length can't fit in the input.
+pub fn check_input(input: &[u8], cursor: usize, len: usize) -> Option<usize> {
+    if input.len().checked_sub(cursor)? < len {
+        return None;
+    }
+    Some(len)
+}
@@ -0,0 +1,123 @@
+//! Compresses an append-only stream of small chunks, such as the records of a
+//! log that is shipped over the network. Each record is too small to compress
+//! on its own, so each chunk is delta encoded against a window of the chunks
+//! that came before it (see 'delta'). The compressor and the decompressor keep
+//! the same window, so each frame is decoded as soon as it arrives, as long as
+//! the frames are decoded in order. Each frame starts with its index in the
+//! stream and the length of the chunk, encoded with LEB128, followed by the
+//! compressed delta operations. The first frame also records the size of the
+//! window. The frames don't have the header of a 'delta' patch, which would
+//! exceed the size of small records, and the numbers of the operations are
+//! encoded with LEB128, which is shorter for the offsets into the window.
+
+use crate::block::{BlockDecoder, BlockEncoder};
+use crate::delta::{self, OpsCoding, DELTA_LEVEL};
+use crate::utils::leb128;
+use crate::{decode_exact, Context, Encoder};
+
+/// The default size of the window of earlier chunks.
+pub const DEFAULT_LOG_WINDOW: usize = 1 << 16;
+
+/// Append 'chunk' to 'window', and drop the oldest bytes to keep at most
+/// 'size' bytes.
+fn slide_window(window: &mut Vec<u8>, chunk: &[u8], size: usize) {
+    window.extend(chunk);
+    let excess = window.len().saturating_sub(size);
+    window.drain(..excess);
+}
+
+/// Compresses chunks against the window of the chunks before them.
+pub struct LogCompressor {
+    /// The last bytes of the stream.
+    window: Vec<u8>,
+    /// The max size of the window.
+    window_size: usize,
+    /// The index of the next frame.
+    index: u64,
+}
+
+impl LogCompressor {
+    /// Create a compressor that keeps a window of 'window_size' bytes. The
+    /// decompressor must use the same window size.
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window: Vec::new(),
+            window_size,
+            index: 0,
+        }
+    }
+
+    /// Compress 'chunk' and return its frame.
+    pub fn append(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        leb128::encode(self.index, &mut frame);
+        if self.index == 0 {
+            leb128::encode(self.window_size as u64, &mut frame);
+        }
+        leb128::encode(chunk.len() as u64, &mut frame);
+        let ops = delta::encode_ops(&self.window, chunk, OpsCoding::Leb128);
+        let ctx = Context::new(DELTA_LEVEL, 1 << 20);
+        let _ = BlockEncoder::new(&ops, &mut frame, ctx).encode();
+        slide_window(&mut self.window, chunk, self.window_size);
+        self.index += 1;
+        frame
+    }
+}
+
+impl Default for LogCompressor {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_WINDOW)
+    }
+}
+
+/// Decompresses the frames of 'LogCompressor'.
+pub struct LogDecompressor {
+    /// The last bytes of the stream.
+    window: Vec<u8>,
+    /// The max size of the window.
+    window_size: usize,
+    /// The index of the next frame.
+    index: u64,
+}
+
+impl LogDecompressor {
+    /// Create a decompressor that keeps a window of 'window_size' bytes.
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window: Vec::new(),
+            window_size,
+            index: 0,
+        }
+    }
+
+    /// Decompress the next frame and return its chunk. Returns None if the
+    /// frame is invalid, or if it is not the next frame of the stream. The
+    /// state is not modified by invalid frames.
+    pub fn append(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        let (mut read, index) = leb128::decode(frame)?;
+        if index != self.index {
+            return None;
+        }
+        if index == 0 {
+            let (used, window_size) = leb128::decode_len(&frame[read..])?;
+            if window_size != self.window_size {
+                return None;
+            }
+            read += used;
+        }
+        let (used, len) = leb128::decode_len(&frame[read..])?;
+        let mut ops = Vec::new();
+        decode_exact::<BlockDecoder>(&frame[read + used..], &mut ops)?;
+        let coding = OpsCoding::Leb128;
+        let chunk = delta::apply_ops(&self.window, &ops, len, coding)?;
+        slide_window(&mut self.window, &chunk, self.window_size);
+        self.index += 1;
+        Some(chunk)
+    }
+}
+
+impl Default for LogDecompressor {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_WINDOW)
+    }
+}
@@ -0,0 +1,127 @@
+//! Reports which parts of an input are covered by LZ matches and which parts
+//! are saved as literals. Inputs that are mostly literals don't compress well,
+//! and the map shows where the literals are. The map can be saved as JSON, or
+//! as a PPM image of a heat strip that goes from red (literals) to green
+//! (matches).
+
+use super::matcher::select_matcher;
+use crate::block::MAX_MATCH_OFFSET;
+use crate::full::ARITH_LEVEL;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// The max match length of the block encoder.
+const MAX_LEN: usize = 65536;
+
+/// A range of the input that is saved as literals or copied by a match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    Literals(Range<usize>),
+    /// A match, and the distance to the bytes that it copies.
+    Match(Range<usize>, usize),
+}
+
+impl Segment {
+    /// Return the range of the input that the segment covers.
+    pub fn range(&self) -> Range<usize> {
+        match self {
+            Segment::Literals(range) | Segment::Match(range, _) => {
+                range.clone()
+            }
+        }
+    }
+}
+
+/// Return the segments of 'input', in order, as they are found by the matcher
+/// of the compression level 'level'. Empty segments are omitted. The levels
+/// from 'ARITH_LEVEL' don't use a matcher, and report the matches of the
+/// level below it.
+pub fn coverage(input: &[u8], level: u8) -> Vec<Segment> {
+    let level = level.min(ARITH_LEVEL - 1);
+    let mut segments = Vec::new();
+    let matcher = select_matcher::<MAX_MATCH_OFFSET, MAX_LEN>(level, input);
+    for (lit, mat) in matcher {
+        let end = lit.end;
+        if !lit.is_empty() {
+            segments.push(Segment::Literals(lit));
+        }
+        if !mat.is_empty() {
+            let offset = end - mat.start;
+            segments.push(Segment::Match(end..end + mat.len(), offset));
+        }
+    }
+    segments
+}
+
+/// Return the number of bytes that are covered by matches.
+pub fn matched_bytes(segments: &[Segment]) -> usize {
+    segments
+        .iter()
+        .filter(|x| matches!(x, Segment::Match(..)))
+        .map(|x| x.range().len())
+        .sum()
+}
+
+/// Serialize 'segments' as a JSON object with the totals and a list of ranges.
+pub fn to_json(segments: &[Segment]) -> String {
+    let len = segments.last().map_or(0, |x| x.range().end);
+    let matched = matched_bytes(segments);
+    let mut json = String::new();
+    let _ = write!(
+        json,
+        "{{\"len\":{},\"matched\":{},\"literals\":{},\"segments\":[",
+        len,
+        matched,
+        len - matched
+    );
+    for (i, segment) in segments.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = match segment {
+            Segment::Literals(r) => write!(
+                json,
+                "{{\"kind\":\"literals\",\"start\":{},\"end\":{}}}",
+                r.start, r.end
+            ),
+            Segment::Match(r, offset) => write!(
+                json,
+                "{{\"kind\":\"match\",\"start\":{},\"end\":{},\"offset\":{}}}",
+                r.start, r.end, offset
+            ),
+        };
+    }
+    json.push_str("]}");
+    json
+}
+
+/// Render 'segments' as a binary PPM image of 'width' by 'height' pixels.
+/// Each column covers an equal part of the input, and its color goes from red
+/// to green with the fraction of the bytes that are covered by matches.
+pub fn to_ppm(segments: &[Segment], width: usize, height: usize) -> Vec<u8> {
+    assert!(width > 0, "Invalid width");
+    let len = segments.last().map_or(0, |x| x.range().end);
+
+    // Count the bytes and the matched bytes in each column.
+    let mut totals = vec![0; width];
+    let mut matched = vec![0; width];
+    for segment in segments {
+        let is_match = matches!(segment, Segment::Match(..));
+        for pos in segment.range() {
+            totals[pos * width / len] += 1;
+            matched[pos * width / len] += is_match as usize;
+        }
+    }
+
+    let mut row = Vec::with_capacity(width * 3);
+    for (count, total) in matched.iter().zip(totals.iter()) {
+        let green = (count * 255 / total.max(&1)) as u8;
+        row.extend([255 - green, green, 0]);
+    }
+
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for _ in 0..height {
+        image.extend(&row);
+    }
+    image
+}
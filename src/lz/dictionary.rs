@@ -0,0 +1,186 @@
+//! Audits a preset dictionary of the LZ4 encoder (see 'LZ4Encoder::with_dict')
+//! before it is deployed. Each sample is matched against the dictionary, like
+//! the encoder does, and the bytes that the matches copy from the dictionary
+//! are counted for each segment of the dictionary. The report shows the parts
+//! of the dictionary that the samples use, and 'resize_dict' saves a smaller
+//! dictionary with the most used segments. The alternate format ('{:#}') of
+//! 'DictReport' also lists the used segments, from the most used.
+
+use super::lz4::MAX_DICT_SIZE;
+use super::matcher::select_matcher;
+use std::cmp::Reverse;
+use std::fmt;
+use std::ops::Range;
+
+/// The level of the matcher that matches the samples.
+const AUDIT_LEVEL: u8 = 4;
+
+/// The usage of a segment of the dictionary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DictSegment {
+    /// The range of the segment in the dictionary.
+    pub range: Range<usize>,
+    /// The number of bytes that the samples copied from the segment.
+    pub matched: usize,
+    /// The number of samples that copied bytes from the segment.
+    pub samples: usize,
+}
+
+/// A description of the usage of a dictionary. See 'inspect_dict'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DictReport {
+    /// The segments of the part of the dictionary that matches can refer to,
+    /// in order.
+    pub segments: Vec<DictSegment>,
+    /// The number of samples.
+    pub samples: usize,
+    /// The number of samples that copied bytes from the dictionary.
+    pub covered_samples: usize,
+    /// The total size of the samples.
+    pub sample_bytes: usize,
+    /// The number of bytes of the samples that are copied from the dictionary.
+    pub matched: usize,
+}
+
+impl DictReport {
+    /// Return the used segments, from the most used to the least used.
+    pub fn ranked(&self) -> Vec<&DictSegment> {
+        let mut ranked: Vec<_> =
+            self.segments.iter().filter(|s| s.matched > 0).collect();
+        ranked.sort_by_key(|s| (Reverse(s.matched), s.range.start));
+        ranked
+    }
+
+    /// Return the fraction of the bytes of the samples that are copied from
+    /// the dictionary.
+    pub fn coverage(&self) -> f64 {
+        self.matched as f64 / self.sample_bytes.max(1) as f64
+    }
+}
+
+/// Return a description of the usage of the dictionary 'dict' by the samples
+/// 'samples', in segments of 'segment_len' bytes. Only the last bytes of the
+/// dictionary that are in the window of the format are described.
+pub fn inspect_dict(
+    dict: &[u8],
+    samples: &[&[u8]],
+    segment_len: usize,
+) -> DictReport {
+    assert!(segment_len > 0, "Invalid segment length");
+    let base = dict.len().saturating_sub(MAX_DICT_SIZE);
+    let used = &dict[base..];
+    let mut segments: Vec<_> = (0..used.len())
+        .step_by(segment_len)
+        .map(|start| DictSegment {
+            range: base + start..base + (start + segment_len).min(used.len()),
+            matched: 0,
+            samples: 0,
+        })
+        .collect();
+    let mut report = DictReport {
+        segments: Vec::new(),
+        samples: samples.len(),
+        covered_samples: 0,
+        sample_bytes: samples.iter().map(|s| s.len()).sum(),
+        matched: 0,
+    };
+
+    let mut window = Vec::new();
+    for sample in samples {
+        // Place the dictionary right before the sample, like the encoder.
+        window.clear();
+        window.extend(used);
+        window.extend(*sample);
+        let mut touched = vec![false; segments.len()];
+        let mut matched = 0;
+        let matcher = select_matcher::<65536, 65536>(AUDIT_LEVEL, &window);
+        for (lit, mat) in matcher {
+            // Skip the matches inside of the dictionary, and count only the
+            // bytes of the sample that are copied from the dictionary.
+            if lit.end + mat.len() <= used.len() {
+                continue;
+            }
+            let skip = used.len().saturating_sub(lit.end);
+            let src = mat.start + skip..mat.end.min(used.len());
+            if src.is_empty() {
+                continue;
+            }
+            matched += src.len();
+            for idx in src.start / segment_len..=(src.end - 1) / segment_len {
+                let seg = &mut segments[idx];
+                let end = src.end.min(seg.range.end - base);
+                seg.matched += end - src.start.max(seg.range.start - base);
+                touched[idx] = true;
+            }
+        }
+        for (seg, touched) in segments.iter_mut().zip(touched) {
+            seg.samples += touched as usize;
+        }
+        report.matched += matched;
+        report.covered_samples += (matched > 0) as usize;
+    }
+    report.segments = segments;
+    report
+}
+
+/// Return a dictionary of at most 'cap' bytes with the most used segments of
+/// 'dict', as described by 'report'. The segments keep their order in 'dict'.
+/// Segments that the samples did not use are dropped.
+pub fn resize_dict(dict: &[u8], report: &DictReport, cap: usize) -> Vec<u8> {
+    let mut kept = Vec::new();
+    let mut size = 0;
+    for seg in report.ranked() {
+        if size + seg.range.len() <= cap {
+            size += seg.range.len();
+            kept.push(seg.range.clone());
+        }
+    }
+    kept.sort_by_key(|range| range.start);
+    let mut output = Vec::with_capacity(size);
+    for range in kept {
+        output.extend(&dict[range]);
+    }
+    output
+}
+
+impl fmt::Display for DictSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}..{}: {} bytes matched by {} samples",
+            self.range.start, self.range.end, self.matched, self.samples
+        )
+    }
+}
+
+impl fmt::Display for DictReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size: usize = self.segments.iter().map(|s| s.range.len()).sum();
+        let used = self.segments.iter().filter(|s| s.matched > 0).count();
+        write!(
+            f,
+            "dictionary: {} bytes, {} of {} segments used",
+            size,
+            used,
+            self.segments.len()
+        )?;
+        write!(
+            f,
+            "\n  {} of {} sample bytes matched ({:.1}%)",
+            self.matched,
+            self.sample_bytes,
+            self.coverage() * 100.
+        )?;
+        write!(
+            f,
+            "\n  {} of {} samples covered",
+            self.covered_samples, self.samples
+        )?;
+        if f.alternate() {
+            for seg in self.ranked() {
+                write!(f, "\n    {}", seg)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! Finds long repetitions across the whole input, before the input is split
+//! into pages that are encoded independently. The matcher of each page only
+//! sees the page, so repetitions that are farther apart than a page are lost.
+//! This pass samples the positions of the input with a rolling hash, and keeps
+//! an index of the first position of each sampled hash. A sampled position
+//! with a known hash is a candidate, which is verified and extended in both
+//! directions. The sampling depends only on the content, so two copies of a
+//! segment are sampled at the same places.
+//! Reference: the long distance matcher of zstd.
+
+use crate::utils::hash::GEAR;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The minimum length of global matches. Shorter repetitions are left to the
+/// matchers of the pages, because each global match splits its page.
+pub const GLOBAL_MIN_MATCH: usize = 1 << 12;
+
+/// The number of bytes that the rolling hash covers.
+const WINDOW: usize = 64;
+
+/// One in 2^SAMPLE_BITS positions is sampled, on average.
+const SAMPLE_BITS: u32 = 8;
+
+/// A repetition of the 'len' bytes at 'src' at the position 'pos'.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlobalMatch {
+    pub pos: usize,
+    pub src: usize,
+    pub len: usize,
+}
+
+/// Add the parts of 'mat' that fall in each of 'pages' to 'matches'. Each
+/// part is shortened so that its source ends before the start of its page.
+/// Parts that are shorter than 'GLOBAL_MIN_MATCH' are dropped.
+fn split_by_pages(
+    mat: GlobalMatch,
+    pages: &[Range<usize>],
+    matches: &mut Vec<GlobalMatch>,
+) {
+    let end = mat.pos + mat.len;
+    let first = pages.partition_point(|page| page.end <= mat.pos);
+    for page in pages[first..].iter().take_while(|page| page.start < end) {
+        let pos = mat.pos.max(page.start);
+        let src = mat.src + (pos - mat.pos);
+        let len = end.min(page.end) - pos;
+        let len = len.min(page.start.saturating_sub(src));
+        if len >= GLOBAL_MIN_MATCH {
+            matches.push(GlobalMatch { pos, src, len });
+        }
+    }
+}
+
+/// Return the matches of at least 'GLOBAL_MIN_MATCH' bytes in 'input', in
+/// order and without overlaps. The 'pages' are consecutive ranges that cover
+/// the input. Each match is inside of one page, and its source ends before
+/// the start of that page, so a decoder that decodes the pages in order has
+/// already decoded the source.
+pub fn find_global_matches(
+    input: &[u8],
+    pages: &[Range<usize>],
+) -> Vec<GlobalMatch> {
+    // The gear hash mixes the upper bits better than the lower bits.
+    let mask = ((1u64 << SAMPLE_BITS) - 1) << (64 - SAMPLE_BITS);
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    let mut matches = Vec::new();
+    let mut hash: u64 = 0;
+    // The end of the last match.
+    let mut covered = 0;
+
+    for i in 0..input.len() {
+        // The hash depends on the last 64 bytes.
+        hash = (hash << 1).wrapping_add(GEAR[input[i] as usize]);
+        if i + 1 < WINDOW || hash & mask != 0 {
+            continue;
+        }
+        let anchor = i + 1 - WINDOW;
+        let src = *index.entry(hash).or_insert(anchor);
+        if src == anchor
+            || anchor < covered
+            || input[src..src + WINDOW] != input[anchor..anchor + WINDOW]
+        {
+            continue;
+        }
+
+        // Extend the match backwards, up to the last match, and forwards.
+        let mut back = 0;
+        while back < anchor - covered
+            && back < src
+            && input[src - back - 1] == input[anchor - back - 1]
+        {
+            back += 1;
+        }
+        let (pos, src) = (anchor - back, src - back);
+        let mut len = WINDOW + back;
+        while pos + len < input.len() && input[src + len] == input[pos + len] {
+            len += 1;
+        }
+
+        split_by_pages(GlobalMatch { pos, src, len }, pages, &mut matches);
+        covered = pos + len;
+    }
+    matches
+}
@@ -3,9 +3,23 @@
 
 use std::ops::Range;
 
-use super::matcher::select_matcher;
+use super::copy_match;
+use super::matcher::{select_fast_matcher, select_matcher_with};
+use crate::block::{Sequence, FAST_LEVEL};
+use crate::limits::max_output;
+use crate::scratch::{recycle_u8, take_u8};
 use crate::{Context, Decoder, Encoder};
 
+/// The max number of dictionary bytes that matches can refer to. This is the
+/// window of the format, and older bytes of the dictionary are ignored.
+pub const MAX_DICT_SIZE: usize = 65535;
+
+/// Return the max size of the LZ4 encoding of an input of 'len' bytes. This
+/// matches 'LZ4_COMPRESSBOUND' of the reference implementation.
+pub fn compress_bound(len: usize) -> usize {
+    len + len / 255 + 16
+}
+
 /// An LZ4 Encoder.
 pub struct LZ4Encoder<'a> {
     /// The uncompressed input.
@@ -14,15 +28,40 @@ pub struct LZ4Encoder<'a> {
     output: &'a mut Vec<u8>,
     /// The encoder context.
     ctx: Context,
+    /// A preset dictionary that matches can refer to.
+    dict: &'a [u8],
 }
 
 impl<'a> LZ4Encoder<'a> {
+    /// Use the preset dictionary 'dict'. Matches can refer to the last 64KB of
+    /// the dictionary, as if it was placed right before the input. The stream
+    /// must be decoded with the same dictionary (see 'LZ4Decoder::with_dict').
+    pub fn with_dict(mut self, dict: &'a [u8]) -> Self {
+        self.dict = &dict[dict.len().saturating_sub(MAX_DICT_SIZE)..];
+        self
+    }
+
+    /// Encode the input into the buffer 'dst', and return the number of bytes
+    /// written, or None if the buffer is too small. A buffer of
+    /// 'compress_bound(input.len())' bytes is always large enough.
+    pub fn encode_into(&mut self, dst: &mut [u8]) -> Option<usize> {
+        let mut buffer = take_u8();
+        let written =
+            Self::encode_to(self.ctx, self.input, self.dict, &mut buffer);
+        let res = dst.get_mut(..written).map(|dst| {
+            dst.copy_from_slice(&buffer);
+            written
+        });
+        recycle_u8(buffer);
+        res
+    }
+
     /// Encode a single packet into the lz4 stream 'output'. The fields are
     /// The 'literals', and the match operator that is stored in an *unbiased*
     /// 'offset' and a valid match length. If 'is_last' is set then the last
     /// offset block is not encoded. Returns the number of bytes written.
     fn encode_lz4_packet(
-        &mut self,
+        output: &mut Vec<u8>,
         literals: &[u8],
         offset: u16,
         match_length: usize,
@@ -40,7 +79,7 @@ impl<'a> LZ4Encoder<'a> {
         let lit_len = literals.len();
         let high = if lit_len > 15 { 15 } else { lit_len as u8 };
         let low = if match_len > 15 { 15 } else { match_len as u8 };
-        self.output.push((high << 4) | low);
+        output.push((high << 4) | low);
         written += 1;
 
         // A procedure for encoding values over 15 in additional bytes.
@@ -63,10 +102,10 @@ impl<'a> LZ4Encoder<'a> {
             written
         };
 
-        written += encode(&lit_len, self.output);
+        written += encode(&lit_len, output);
 
         // Push literals.
-        self.output.extend(literals.iter());
+        output.extend(literals.iter());
         written += literals.len();
 
         if is_last {
@@ -74,23 +113,55 @@ impl<'a> LZ4Encoder<'a> {
         }
 
         // Push little endian offset field.
-        self.output.push(offset as u8);
-        self.output.push((offset >> 8) as u8);
+        output.push(offset as u8);
+        output.push((offset >> 8) as u8);
         written += 2;
-        written += encode(&match_len, self.output);
+        written += encode(&match_len, output);
         written
     }
 
-    fn encode_impl(&mut self) -> usize {
+    /// Encode 'input' with the dictionary 'dict' into 'output', and return the
+    /// number of bytes written.
+    fn encode_to(
+        ctx: Context,
+        input: &[u8],
+        dict: &[u8],
+        output: &mut Vec<u8>,
+    ) -> usize {
+        if dict.is_empty() {
+            return Self::encode_window(ctx, input, 0, output);
+        }
+        // Place the dictionary right before the input.
+        let mut window = Vec::with_capacity(dict.len() + input.len());
+        window.extend(dict);
+        window.extend(input);
+        Self::encode_window(ctx, &window, dict.len(), output)
+    }
+
+    /// Encode the bytes of 'window' that come after 'start' into 'output',
+    /// using the matcher of the compression level of 'ctx'. Matches can refer to the
+    /// bytes before 'start'. Returns the number of bytes written.
+    fn encode_window(
+        ctx: Context,
+        window: &[u8],
+        start: usize,
+        output: &mut Vec<u8>,
+    ) -> usize {
         let mut written = 0;
-        let len = self.input.len();
+        let len = window.len();
 
         // Encoding rules:
         // https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md#end-of-block-conditions
 
         // Rule #3: blocks < 13 bytes cannot be compressed.
-        if len < 13 {
-            return self.encode_lz4_packet(self.input, 0, 0, true);
+        if len - start < 13 {
+            return Self::encode_lz4_packet(
+                output,
+                &window[start..],
+                0,
+                0,
+                true,
+            );
         }
 
         // Construct a matcher.
@@ -98,39 +169,135 @@ impl<'a> LZ4Encoder<'a> {
         // Select a matcher based on the optimization level. Limit the match
         // length and offset for the properties of the format (we can't encode
         // beyond 16-bit offsets).
-        let matcher = select_matcher::<65536, 65536>(
-            self.ctx.level,
-            &self.input[..(len - 5)],
-        );
+        let searched = &window[..(len - 5)];
+        let matcher = if ctx.level == FAST_LEVEL {
+            select_fast_matcher::<65536, 65536>(ctx.acceleration, searched)
+        } else {
+            select_matcher_with::<65536, 65536>(ctx.level, ctx.parse, searched)
+        };
 
-        let mut last_encoded = 0;
+        // Points to the first literal that was not encoded.
+        let mut last_encoded = start;
         for (lit, mat) in matcher {
-            let literals = &self.input[lit.clone()];
+            // The matcher scans the dictionary too. Skip the matches that end
+            // before the input, and clip the matches that cross into it.
+            let end = lit.end + mat.len();
+            if end <= start {
+                continue;
+            }
+            let mat_start = lit.end.max(start);
 
             // The last match must start at least 12 bytes before the block end.
-            if lit.end + 12 >= len {
-                let literals = &self.input[lit.start..len];
-                written += self.encode_lz4_packet(literals, 0, 0, true);
-                return written;
+            if mat_start + 12 >= len {
+                break;
             }
 
-            written += self.encode_lz4_packet(
-                literals,
+            // Matches that are too short (after clipping) are saved as literals.
+            if end - mat_start < 4 {
+                continue;
+            }
+
+            written += Self::encode_lz4_packet(
+                output,
+                &window[last_encoded..mat_start],
                 (lit.end - mat.start) as u16,
-                mat.len(),
+                end - mat_start,
                 false,
             );
-            last_encoded = lit.end + mat.len();
+            last_encoded = end;
         }
 
         // Encode the last literal block.
-        let last_lit = &self.input[last_encoded..];
-        written += self.encode_lz4_packet(last_lit, 0, 0, true);
+        let last_lit = &window[last_encoded..];
+        written += Self::encode_lz4_packet(output, last_lit, 0, 0, true);
         written
     }
 }
 
-/// An LZ4 Decoder.
+/// The min length of the matches of the LZ4 format.
+const MIN_MATCH: usize = 4;
+
+/// Encode the bytes of 'input' into the LZ4 stream 'output', with the matches
+/// in 'sequences' that the caller found, instead of running the matcher. The
+/// sequences must cover all of 'input'. Matches that the format can't encode,
+/// because they are too short, too far, or too close to the end of the block,
+/// are saved as literals. Returns the number of bytes written, or None if the
+/// sequences don't match the input. See 'LZ4Decoder::decode_sequences'.
+pub fn encode_sequences(
+    input: &[u8],
+    sequences: &[Sequence],
+    output: &mut Vec<u8>,
+) -> Option<usize> {
+    let len = input.len();
+    let mut written = 0;
+    // Points to the first literal that was not encoded.
+    let mut last_encoded = 0;
+    let mut pos = 0usize;
+    for seq in sequences {
+        pos = pos.checked_add(seq.lit_len as usize)?;
+        let (offset, mat_len) = (seq.offset as usize, seq.mat_len as usize);
+        if mat_len > 0 && (offset == 0 || offset > pos) {
+            return None;
+        }
+        let end = pos.checked_add(mat_len)?;
+        if end > len {
+            return None;
+        }
+        // The last match must start at least 12 bytes before the end, and the
+        // last 5 bytes are always literals.
+        let mat_end = end.min(len.saturating_sub(5));
+        let valid = offset <= MAX_DICT_SIZE && pos + 12 < len;
+        if valid && mat_end >= pos + MIN_MATCH {
+            written += LZ4Encoder::encode_lz4_packet(
+                output,
+                &input[last_encoded..pos],
+                offset as u16,
+                mat_end - pos,
+                false,
+            );
+            last_encoded = mat_end;
+        }
+        pos = end;
+    }
+    if pos != len {
+        return None;
+    }
+    let last_lit = &input[last_encoded..];
+    written += LZ4Encoder::encode_lz4_packet(output, last_lit, 0, 0, true);
+    Some(written)
+}
+
+/// The reasons for rejecting an LZ4 stream in 'LZ4Decoder::decode_strict'.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LZ4Error {
+    /// A token, a length field or an offset is cut off, or the stream does
+    /// not end with a sequence of literals.
+    BadToken,
+    /// The literals run past the end of the stream.
+    TruncatedLiterals,
+    /// A match refers to bytes before the beginning of the output (and of the
+    /// dictionary), or has a zero offset.
+    OffsetBeyondOutput,
+    /// The output is larger than the limit that the caller provided.
+    OutputLimit,
+}
+
+impl std::fmt::Display for LZ4Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            LZ4Error::BadToken => "invalid or truncated token",
+            LZ4Error::TruncatedLiterals => "truncated literals",
+            LZ4Error::OffsetBeyondOutput => "match offset beyond the output",
+            LZ4Error::OutputLimit => "output exceeds the limit",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for LZ4Error {}
+
+/// An LZ4 Decoder. The LZ4 block format has no end marker and ends at the end
+/// of the input, so the input must contain a single stream and nothing else.
 pub struct LZ4Decoder<'a> {
     /// The uncompressed input.
     input: &'a [u8],
@@ -138,6 +305,10 @@ pub struct LZ4Decoder<'a> {
     output: &'a mut Vec<u8>,
     /// Points to the current byte to process.
     cursor: usize,
+    /// A preset dictionary that matches can refer to.
+    dict: &'a [u8],
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
 }
 
 impl<'a> LZ4Decoder<'a> {
@@ -146,26 +317,34 @@ impl<'a> LZ4Decoder<'a> {
             input,
             output,
             cursor: 0,
+            dict: &[],
+            read: 0,
         }
     }
 
-    fn decode_following_bytes(&mut self, x: usize) -> Option<usize> {
+    /// Decode a stream that was encoded with the preset dictionary 'dict'.
+    pub fn with_dict(mut self, dict: &'a [u8]) -> Self {
+        self.dict = dict;
+        self
+    }
+
+    fn decode_following_bytes(&mut self, x: usize) -> Result<usize, LZ4Error> {
         let mut x: usize = x;
         let len = self.input.len();
         if x == 15 {
             loop {
                 if self.cursor >= len {
-                    return None;
+                    return Err(LZ4Error::BadToken);
                 }
                 let next = self.input[self.cursor] as usize;
-                x += next;
+                x = x.checked_add(next).ok_or(LZ4Error::BadToken)?;
                 self.cursor += 1;
                 if next != 255 {
-                    return Some(x);
+                    return Ok(x);
                 }
             }
         }
-        Some(x)
+        Ok(x)
     }
 
     /// Decode a single packet from the lz4 stream 'input'.
@@ -174,27 +353,27 @@ impl<'a> LZ4Decoder<'a> {
     fn decode_lz4_packet(
         &mut self,
         end_of_buffer: usize,
-    ) -> Option<(Range<usize>, &'a [u8])> {
+    ) -> Result<(Range<usize>, &'a [u8]), LZ4Error> {
         let num_literals = (self.input[self.cursor] >> 4) as usize;
         let match_len = (self.input[self.cursor] & 0xf) as usize;
         self.cursor += 1;
 
         let num_literals = self.decode_following_bytes(num_literals)?;
 
-        let end = self.cursor + num_literals;
+        let end = self.cursor.saturating_add(num_literals);
         if end > self.input.len() {
-            return None;
+            return Err(LZ4Error::TruncatedLiterals);
         }
         let literal_ref = &self.input[self.cursor..end];
         self.cursor += num_literals;
 
         // Handle the half-token end of stream.
         if self.cursor == end_of_buffer {
-            return Some((0..0, literal_ref));
+            return Ok((0..0, literal_ref));
         }
 
         if self.cursor + 1 >= self.input.len() {
-            return None;
+            return Err(LZ4Error::BadToken);
         }
 
         let offset_low = self.input[self.cursor];
@@ -203,64 +382,138 @@ impl<'a> LZ4Decoder<'a> {
         let match_len = self.decode_following_bytes(match_len)?;
 
         let offset = (offset_low as u16) + ((offset_high as u16) << 8);
+        if offset == 0 {
+            return Err(LZ4Error::OffsetBeyondOutput);
+        }
         let reg = offset as usize..offset as usize + match_len + 4;
-        Some((reg, literal_ref))
+        Ok((reg, literal_ref))
+    }
+
+    /// Decode the input, and don't write more than 'max_output' bytes. This
+    /// is meant for untrusted data, and reports the reason for the failure.
+    /// Returns the number of bytes consumed and the number of bytes written.
+    pub fn decode_strict(
+        &mut self,
+        max_output: usize,
+    ) -> Result<(usize, usize), LZ4Error> {
+        self.decode_with(max_output, |_| {})
     }
 
-    /// Decode the input parameter. Returns the number of bytes consumed and the
-    /// number of bytes written if the operation succeeded.
-    fn decode_impl(&mut self) -> Option<(usize, usize)> {
+    /// Decode the input like 'decode', and return the sequences of the stream
+    /// with the number of bytes consumed. The sequences are what tools that
+    /// convert the stream to other LZ formats need. The last sequence has no
+    /// match. The output is limited to the output cap of the current thread.
+    /// See 'encode_sequences'.
+    pub fn decode_sequences(&mut self) -> Option<(usize, Vec<Sequence>)> {
+        let mut sequences = Vec::new();
+        let (read, _) = self
+            .decode_with(max_output(), |seq| sequences.push(seq))
+            .ok()?;
+        Some((read, sequences))
+    }
+
+    /// Decode the input like 'decode_strict', and report each sequence to
+    /// 'on_sequence'. The output, which starts empty, is cleared if the input
+    /// is invalid.
+    fn decode_with(
+        &mut self,
+        max_output: usize,
+        on_sequence: impl FnMut(Sequence),
+    ) -> Result<(usize, usize), LZ4Error> {
+        let res = self.decode_packets(max_output, on_sequence);
+        if res.is_err() {
+            self.output.clear();
+        }
+        self.read = res.as_ref().map_or(0, |(read, _)| *read);
+        res
+    }
+
+    /// Decode the packets of the input. See 'decode_with'.
+    fn decode_packets(
+        &mut self,
+        max_output: usize,
+        mut on_sequence: impl FnMut(Sequence),
+    ) -> Result<(usize, usize), LZ4Error> {
         assert_eq!(self.output.len(), 0);
         self.cursor = 0;
         let mut written = 0;
 
         let len = self.input.len();
         if len == 0 {
-            return Some((0, 0));
+            return Ok((0, 0));
         }
         while self.cursor < len {
             let (match_op, literals) = self.decode_lz4_packet(len)?;
+            if written + literals.len() > max_output {
+                return Err(LZ4Error::OutputLimit);
+            }
             self.output.extend(literals.iter());
             written += literals.len();
+            on_sequence(Sequence {
+                lit_len: literals.len() as u32,
+                mat_len: match_op.len() as u32,
+                offset: match_op.start as u32,
+            });
             if match_op.start == 0 {
-                return Some((self.cursor, written));
+                return Ok((self.cursor, written));
             }
-            let len = self.output.len();
+            // The dictionary is placed right before the output.
+            let dict_len = self.dict.len();
+            let len = dict_len + self.output.len();
 
             // Check that the match window does not overflow
             if match_op.start > len {
-                return None;
+                return Err(LZ4Error::OffsetBeyondOutput);
+            }
+            if written + match_op.len() > max_output {
+                return Err(LZ4Error::OutputLimit);
+            }
+            // Copy the match into the output stream. Matches that don't reach
+            // into the dictionary are copied with the fast path.
+            if len - match_op.start >= dict_len {
+                copy_match(self.output, match_op.start, match_op.len());
+                written += match_op.len();
+                continue;
             }
-            // Copy the match into the output stream.
             for i in 0..match_op.len() {
-                self.output.push(self.output[len - match_op.start + i]);
+                let pos = len - match_op.start + i;
+                let val = if pos < dict_len {
+                    self.dict[pos]
+                } else {
+                    self.output[pos - dict_len]
+                };
+                self.output.push(val);
             }
             written += match_op.len();
         }
-        None
+        // The stream must end with a sequence of literals.
+        Err(LZ4Error::BadToken)
     }
 }
 
 impl<'a> Encoder<'a> for LZ4Encoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>, ctx: Context) -> Self {
-        Self { input, output, ctx }
+        Self {
+            input,
+            output,
+            ctx,
+            dict: &[],
+        }
     }
 
     fn encode(&mut self) -> usize {
-        self.encode_impl()
+        Self::encode_to(self.ctx, self.input, self.dict, self.output)
     }
 }
 
 impl<'a> Decoder<'a> for LZ4Decoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
-        Self {
-            input,
-            output,
-            cursor: 0,
-        }
+        LZ4Decoder::new(input, output)
     }
 
     fn decode(&mut self) -> Option<(usize, usize)> {
-        self.decode_impl()
+        self.decode_strict(usize::MAX).ok()
     }
+
+    decoder_state!();
 }
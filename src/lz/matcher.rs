@@ -1,10 +1,27 @@
 //! This module implements a reusable Lempel–Ziv matcher.
+use super::FAST_COPY_OFFSET;
+use crate::scratch::{recycle_u32, recycle_u8, take_u32, take_u8};
+use crate::utils::hash::{mul_hash32, mul_hash64};
+use crate::utils::unchecked;
 use std::ops::Range;
 
 /// Used to mark empty cells.
 const EMPTY_CELL: u32 = 0xffffffff;
 // The minimum size of the match word.
 const MIN_MATCH: usize = 4;
+/// The size of the word that the table of long matches hashes.
+const LONG_WORD: usize = 8;
+/// Long matches of this length are taken without searching the banks.
+const LONG_ENOUGH: usize = 64;
+/// The minimum number of bits in the index of the dictionary.
+const MIN_DICT_BITS: usize = 8;
+
+/// Return the number of bits in the index of the dictionary for an input of
+/// length 'len'. Small inputs use a smaller table, which is faster to clear.
+fn dict_bits(len: usize, max_bits: usize) -> usize {
+    let bits = (usize::BITS - len.leading_zeros()) as usize + 1;
+    bits.clamp(MIN_DICT_BITS, max_bits.max(MIN_DICT_BITS))
+}
 
 /// A Lempel–Ziv based matcher. Parameters:
 /// MAX_OFFSET controls the maximum size of match offset.
@@ -12,19 +29,52 @@ const MIN_MATCH: usize = 4;
 /// DICT_SIZE_BITS Controls the size of the cache (1<<x).
 /// DICT_BANKS number of ways in the LRU cache.
 /// PARSE_SEARCH controls the look ahead scan of the matcher (1..4).
+/// TAGGED saves a tag of the hashed bytes with each position, which rejects
+/// most hash collisions without reading the input. The tags take a byte per
+/// entry, so they only pay off for large tables.
+/// LONG_BITS controls the size of a second table (1<<x), which hashes words of
+/// 8 bytes and keeps the last position of each. Long matches are found there
+/// first, and the banks then only need to beat them. Zero disables the table.
 struct LzDictionary<
     'a,
     const MAX_OFFSET: usize,
     const MAX_MATCH: usize,
     const DICT_SIZE_BITS: usize,
     const DICT_BANKS: usize,
+    const TAGGED: bool,
+    const LONG_BITS: usize,
 > {
     /// The input to tokenize.
     input: &'a [u8],
     /// Maps a sequence of bytes to their index in the sequence.
     /// The match could be a hash collision or an uninitialized value.
-    /// Matches may reside in one of the rotating LRU banks.
+    /// Matches may reside in one of the rotating LRU banks. The banks of each
+    /// bucket are contiguous, and start at 'start'.
     dict: Vec<u32>,
+    /// The offset of the first bucket in 'dict', which aligns the buckets to
+    /// cache lines.
+    start: usize,
+    /// The bank of the newest entry of each bucket. The banks of a bucket are
+    /// a ring buffer, and the older entries come before the newest one.
+    heads: Vec<u8>,
+    /// The tag of each entry of 'dict', without the offset 'start'. Empty if
+    /// the dictionary is not tagged. See 'tag'.
+    tags: Vec<u8>,
+    /// The last position of each word of 8 bytes. Empty if LONG_BITS is zero.
+    long: Vec<u32>,
+    /// The number of bits in the index of the table (at most DICT_SIZE_BITS).
+    bits: usize,
+    /// The number of bits in the index of 'long' (at most LONG_BITS).
+    long_bits: usize,
+}
+
+/// The size of a cache line, in entries of the dictionary.
+const CACHE_LINE_ENTRIES: usize = 16;
+
+/// Return the tag of the word 'val'. The tag uses a different multiplier than
+/// the hash of the index, so words that share a bucket rarely share a tag.
+fn tag(val: u32) -> u8 {
+    (val.wrapping_mul(0x9e3779b1) >> 24) as u8
 }
 
 impl<
@@ -33,12 +83,48 @@ impl<
         const MAX_MATCH: usize,
         const DICT_SIZE_BITS: usize,
         const DICT_BANKS: usize,
-    > LzDictionary<'a, MAX_OFFSET, MAX_MATCH, DICT_SIZE_BITS, DICT_BANKS>
+        const TAGGED: bool,
+        const LONG_BITS: usize,
+    >
+    LzDictionary<
+        'a,
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        DICT_BANKS,
+        TAGGED,
+        LONG_BITS,
+    >
 {
     pub fn new(input: &'a [u8]) -> Self {
+        assert!(DICT_BANKS <= 256, "The heads of the banks are bytes");
+        let bits = dict_bits(input.len(), DICT_SIZE_BITS);
+        let mut dict = take_u32();
+        let len = (1 << bits) * DICT_BANKS + CACHE_LINE_ENTRIES - 1;
+        dict.resize(len, EMPTY_CELL);
+        // Buckets with a power of two banks don't cross cache lines.
+        let start = dict.as_ptr().align_offset(CACHE_LINE_ENTRIES * 4);
+        let start = start.min(CACHE_LINE_ENTRIES - 1);
+        let mut heads = take_u8();
+        heads.resize(1 << bits, 0);
+        let mut tags = take_u8();
+        if TAGGED {
+            tags.resize((1 << bits) * DICT_BANKS, 0);
+        }
+        let mut long = take_u32();
+        let long_bits = dict_bits(input.len(), LONG_BITS);
+        if LONG_BITS > 0 {
+            long.resize(1 << long_bits, EMPTY_CELL);
+        }
         Self {
             input,
-            dict: vec![EMPTY_CELL; (1 << DICT_SIZE_BITS) * DICT_BANKS],
+            dict,
+            start,
+            heads,
+            tags,
+            long,
+            bits,
+            long_bits,
         }
     }
 
@@ -53,10 +139,19 @@ impl<
         u32::from_ne_bytes(val)
     }
 
-    fn hash_to_index(val: u32) -> usize {
-        let val = val.wrapping_mul(0x797124e5);
-        let val = val >> (32 - DICT_SIZE_BITS);
-        val as usize
+    fn hash_to_index(&self, val: u32) -> usize {
+        mul_hash32(val, self.bits)
+    }
+
+    /// Return the index in 'long' of the word of 8 bytes at 'idx', or None if
+    /// the word is past the end of the input or the table is disabled.
+    fn long_index(&self, idx: usize) -> Option<usize> {
+        if LONG_BITS == 0 {
+            return None;
+        }
+        let word = self.input.get(idx..idx + LONG_WORD)?;
+        let val = u64::from_ne_bytes(word.try_into().unwrap());
+        Some(mul_hash64(val, self.long_bits))
     }
 
     /// Return True if we can prove that this match is not longer than the best
@@ -82,8 +177,11 @@ impl<
             len += 4;
         }
 
+        // The indices are below 'end', which is inside of the input.
         let end = size.min(b + MAX_MATCH - 4);
-        while b < end && self.input[a] == self.input[b] {
+        while b < end
+            && unchecked::get(self.input, a) == unchecked::get(self.input, b)
+        {
             a += 1;
             b += 1;
             len += 1;
@@ -104,8 +202,38 @@ impl<
         debug_assert_eq!(cache_key, self.get_match_candidate(idx));
         let mut best = 0..0;
 
-        for i in 0..DICT_BANKS {
-            let loc = self.dict[cache_key * DICT_BANKS + i];
+        // Start with the last position of the long word, which lets the banks
+        // skip the short matches.
+        if let Some(long_key) = self.long_index(idx) {
+            let loc = self.long[long_key] as usize;
+            if loc != EMPTY_CELL as usize
+                && idx - loc < MAX_OFFSET
+                && self.input[loc..loc + LONG_WORD]
+                    == self.input[idx..idx + LONG_WORD]
+            {
+                let len = self.get_match_length(loc, idx);
+                if len >= LONG_ENOUGH {
+                    return loc..loc + len;
+                }
+                if len > prev_best {
+                    best = loc..loc + len;
+                    prev_best = len;
+                }
+            }
+        }
+
+        // Visit the banks from the newest entry to the oldest one.
+        let base = cache_key * DICT_BANKS;
+        let word_tag = if TAGGED {
+            tag(self.get_bytes_at(idx))
+        } else {
+            0
+        };
+        let mut bank = self.heads[cache_key] as usize;
+        for _ in 0..DICT_BANKS {
+            let loc = self.dict[self.start + base + bank];
+            let entry = base + bank;
+            bank = if bank == 0 { DICT_BANKS - 1 } else { bank - 1 };
             // Ignore empty cells.
             if loc == EMPTY_CELL {
                 break;
@@ -115,6 +243,10 @@ impl<
             if offset >= MAX_OFFSET {
                 break;
             }
+            // Entries with a different tag are collisions of other words.
+            if TAGGED && self.tags[entry] != word_tag {
+                continue;
+            }
             if self.early_disqualify(loc as usize, idx, prev_best) {
                 continue;
             }
@@ -134,20 +266,27 @@ impl<
 
     /// Return a possible match candidate for a string that starts at 'idx'.
     fn get_match_candidate(&self, idx: usize) -> usize {
-        Self::hash_to_index(self.get_bytes_at(idx))
+        self.hash_to_index(self.get_bytes_at(idx))
     }
-    /// Save the value at index 'idx' to cache entry at 'cache_key' and rotate
-    /// the entries in the cache.
+    /// Save the value at index 'idx' to cache entry at 'cache_key', in place
+    /// of the oldest entry of the bucket.
     fn save_match(&mut self, idx: usize, cache_key: usize) {
         debug_assert_eq!(cache_key, self.get_match_candidate(idx));
 
-        // This is an LRU cache. Move the old entries to make room to the new
-        // entry.
-        let base = cache_key * DICT_BANKS;
-        for i in (0..DICT_BANKS - 1).rev() {
-            self.dict[base + (i + 1)] = self.dict[base + (i)];
+        // This is an LRU cache. The bank after the newest entry holds the
+        // oldest entry, so the ring moves forward instead of moving the
+        // entries.
+        let head = self.heads[cache_key] as usize + 1;
+        let head = if head == DICT_BANKS { 0 } else { head };
+        self.heads[cache_key] = head as u8;
+        let entry = cache_key * DICT_BANKS + head;
+        self.dict[self.start + entry] = idx as u32;
+        if TAGGED {
+            self.tags[entry] = tag(self.get_bytes_at(idx));
+        }
+        if let Some(long_key) = self.long_index(idx) {
+            self.long[long_key] = idx as u32;
         }
-        self.dict[base] = idx as u32;
     }
 
     /// Grow the match region backwards into the literal section.
@@ -179,8 +318,102 @@ impl<
     }
 }
 
+impl<
+        'a,
+        const MAX_OFFSET: usize,
+        const MAX_MATCH: usize,
+        const DICT_SIZE_BITS: usize,
+        const DICT_BANKS: usize,
+        const TAGGED: bool,
+        const LONG_BITS: usize,
+    > Drop
+    for LzDictionary<
+        'a,
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        DICT_BANKS,
+        TAGGED,
+        LONG_BITS,
+    >
+{
+    fn drop(&mut self) {
+        recycle_u32(std::mem::take(&mut self.dict));
+        recycle_u8(std::mem::take(&mut self.heads));
+        recycle_u8(std::mem::take(&mut self.tags));
+        recycle_u32(std::mem::take(&mut self.long));
+    }
+}
+
+/// The estimated cost of a literal in bits, for the priced parsing.
+const LITERAL_PRICE: usize = 8;
+
+/// Return the number of bits in 'val'.
+fn bit_len(val: usize) -> usize {
+    (usize::BITS - val.leading_zeros()) as usize
+}
+
+/// Return the estimated cost in bits of a match with the offset 'offset' and
+/// the length 'len'. The tokens of the offset and of the length take a few
+/// bits each, and the extra bits grow with the log of the values.
+fn match_price(offset: usize, len: usize) -> usize {
+    8 + bit_len(offset) + bit_len(len)
+}
+
+/// The parameters of the lazy parsing of 'Matcher'. The levels select the
+/// search depth, and the context can replace them (see
+/// 'Context::with_parse_params').
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParseParams {
+    /// The number of positions after the start of the match candidate that
+    /// are searched for a better match.
+    pub search: usize,
+    /// The number of bytes that a new match gains over the candidate if it is
+    /// closer. Ties are broken in favor of the closer match.
+    pub closer_bonus: usize,
+    /// The number of bytes that each literal between the candidate and a new
+    /// match costs the new match.
+    pub literal_penalty: usize,
+    /// Compare the matches by their estimated cost in bits, instead of by
+    /// their lengths. See 'match_price'.
+    pub priced: bool,
+}
+
+impl ParseParams {
+    /// Return the parameters that search 'search' positions ahead, with the
+    /// default heuristics. The search depth is at least 1.
+    pub fn new(search: usize) -> Self {
+        Self {
+            search: search.max(1),
+            closer_bonus: 0,
+            literal_penalty: 1,
+            priced: false,
+        }
+    }
+
+    /// Let closer matches gain 'bonus' bytes over the candidate.
+    pub fn with_closer_bonus(mut self, bonus: usize) -> Self {
+        self.closer_bonus = bonus;
+        self
+    }
+
+    /// Let each literal before a new match cost 'penalty' bytes.
+    pub fn with_literal_penalty(mut self, penalty: usize) -> Self {
+        self.literal_penalty = penalty;
+        self
+    }
+
+    /// Compare the matches by their estimated cost in bits.
+    pub fn with_pricing(mut self) -> Self {
+        self.priced = true;
+        self
+    }
+}
+
 /// A Lempel–Ziv based matcher. It performs parsing with a lookahead window of
-/// 'PARSE_SEARCH' items.
+/// 'PARSE_SEARCH' items, unless 'with_params' selects other parameters.
+/// 'TAGGED' and 'LONG_BITS' select the tags and the table of long matches of
+/// the dictionary (see 'LzDictionary').
 pub struct Matcher<
     'a,
     const MAX_OFFSET: usize,
@@ -188,11 +421,23 @@ pub struct Matcher<
     const DICT_SIZE_BITS: usize,
     const DICT_BANKS: usize,
     const PARSE_SEARCH: usize,
+    const TAGGED: bool = false,
+    const LONG_BITS: usize = 0,
 > {
     /// The input to tokenize.
-    dict: LzDictionary<'a, MAX_OFFSET, MAX_MATCH, DICT_SIZE_BITS, DICT_BANKS>,
+    dict: LzDictionary<
+        'a,
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        DICT_BANKS,
+        TAGGED,
+        LONG_BITS,
+    >,
     /// The iterator location in the input.
     cursor: usize,
+    /// The parameters of the parsing.
+    params: ParseParams,
 }
 
 impl<
@@ -202,16 +447,77 @@ impl<
         const DICT_SIZE_BITS: usize,
         const DICT_BANKS: usize,
         const PARSE_SEARCH: usize,
+        const TAGGED: bool,
+        const LONG_BITS: usize,
+    >
+    Matcher<
+        'a,
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        DICT_BANKS,
+        PARSE_SEARCH,
+        TAGGED,
+        LONG_BITS,
     >
-    Matcher<'a, MAX_OFFSET, MAX_MATCH, DICT_SIZE_BITS, DICT_BANKS, PARSE_SEARCH>
 {
     pub fn new(input: &'a [u8]) -> Self {
         Self {
             dict: LzDictionary::new(input),
             cursor: 0,
+            params: ParseParams::new(PARSE_SEARCH),
         }
     }
 
+    /// Parse the input with the parameters 'params', instead of the search
+    /// depth 'PARSE_SEARCH' and the default heuristics.
+    pub fn with_params(mut self, params: ParseParams) -> Self {
+        self.params = ParseParams {
+            search: params.search.max(1),
+            ..params
+        };
+        self
+    }
+
+    /// Return true if the match 'mat' at the cursor is better than the match
+    /// 'can' of the candidate, which starts at 'start' and ends at 'end'.
+    fn is_better(
+        &self,
+        mat: &Range<usize>,
+        can: &Range<usize>,
+        start: usize,
+        end: usize,
+    ) -> bool {
+        // Distance between where the candidate started and where the new
+        // match starts will be filled with literals.
+        let lit_len = self.cursor - start;
+        let closer = can.start < mat.start;
+        let params = &self.params;
+        let (new_gain, old_gain) = if params.priced {
+            // Compare the cost of the bytes that either option covers. The
+            // bytes that only one of them covers are priced as literals.
+            let new_end = self.cursor + mat.len();
+            let last = end.max(new_end);
+            let old_cost = match_price(start - can.start, can.len())
+                + (last - end) * LITERAL_PRICE;
+            let new_cost = lit_len * LITERAL_PRICE
+                + match_price(self.cursor - mat.start, mat.len())
+                + (last - new_end) * LITERAL_PRICE;
+            (old_cost, new_cost)
+        } else {
+            // Check if the new match is bigger. We include the size of the
+            // extra literals in this calculation.
+            let bonus = if closer { params.closer_bonus } else { 0 };
+            (
+                mat.len() + bonus,
+                can.len() + lit_len * params.literal_penalty,
+            )
+        };
+        // If the size is the same then break the tie by looking at the offset
+        // of the match, where lower is better.
+        new_gain > old_gain || (new_gain == old_gain && closer)
+    }
+
     /// Return the next literal and match regions, which could be empty.
     /// The indices in the regions are absolute from the beginning of the
     /// stream.
@@ -231,9 +537,11 @@ impl<
 
         // For each character in the input buffer:
         while self.cursor + MIN_MATCH < input_len {
-            // If we've exceeded the window, return the candidate.
+            // If we've exceeded the window, return the candidate. The window
+            // ends at the end of the candidate, because the positions inside
+            // of the match are hashed once.
             if let Some(can) = &candidate {
-                if self.cursor >= can.2 + PARSE_SEARCH {
+                if self.cursor >= (can.2 + self.params.search).min(can.3) {
                     debug_assert!(self.cursor <= can.3);
                     // When accepting a match, hash the content of the match.
                     for i in self.cursor..(can.3).min(input_len - MIN_MATCH) {
@@ -285,24 +593,8 @@ impl<
             // If we have a new match and a previous candidate, select between
             // them.
             if let Some(can) = &candidate {
-                // Distance between where the candidate started and where the
-                // new match starts will be filled with literals.
-                let lit_len = self.cursor - can.2;
-
-                let candidate_size = can.1.len();
-                let new_match_size = mat.len();
-                let new_mat_closer = can.1.start < mat.start;
-                // Check if the new match is bigger. We include the size of the
-                // extra literals in this calculation. If the size is the same
-                // then break the tie by looking at the offset of the match,
-                // where lower is better.
-                let better_size = new_match_size > candidate_size + lit_len;
-                let same_size_better_offset = new_match_size
-                    == candidate_size + lit_len
-                    && new_mat_closer;
-                if better_size || same_size_better_offset {
+                if self.is_better(&mat, &can.1, can.2, can.3) {
                     // Pick a new match candidate.
-                    debug_assert!(can.3 < self.cursor + mat.len());
                     candidate = Some((
                         lit.clone(),
                         mat.clone(),
@@ -344,12 +636,15 @@ impl<
     }
 }
 
-/// An optimal Lempel–Ziv based matcher.
+/// An optimal Lempel–Ziv based matcher. 'TAGGED' and 'LONG_BITS' select the
+/// tags and the table of long matches of the dictionary (see 'LzDictionary').
 pub struct OptimalMatcher<
     const MAX_OFFSET: usize,
     const MAX_MATCH: usize,
     const DICT_SIZE_BITS: usize,
     const DICT_BANKS: usize,
+    const TAGGED: bool = false,
+    const LONG_BITS: usize = 0,
 > {
     matches: Vec<(Range<usize>, Range<usize>)>,
     curr: usize,
@@ -361,7 +656,17 @@ impl<
         const MAX_MATCH: usize,
         const DICT_SIZE_BITS: usize,
         const DICT_BANKS: usize,
-    > OptimalMatcher<MAX_OFFSET, MAX_MATCH, DICT_SIZE_BITS, DICT_BANKS>
+        const TAGGED: bool,
+        const LONG_BITS: usize,
+    >
+    OptimalMatcher<
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        DICT_BANKS,
+        TAGGED,
+        LONG_BITS,
+    >
 {
     pub fn new(input: &'a [u8]) -> Self {
         Self {
@@ -376,6 +681,8 @@ impl<
             MAX_MATCH,
             DICT_SIZE_BITS,
             DICT_BANKS,
+            TAGGED,
+            LONG_BITS,
         >::new(input);
         let mut all_matches = Vec::new();
         let input_len = dict.len();
@@ -403,13 +710,20 @@ impl<
 
         // Next, figure out which matches are profitable and at what length.
         // This part of the code deletes unprofitable matches and shortens the
-        // remaining matches.
-        let match_cost = 3;
+        // remaining matches. The costs are in quarters of a byte. Matches with
+        // tiny offsets are a little cheaper, because the decoder replicates
+        // them as a pattern, which is the fastest copy.
+        let literal_cost = 4;
         distance_to_end[input_len] = 0;
         for i in (0..input_len).rev() {
             let mat = all_matches[i].clone();
+            let match_cost = if i - mat.start <= FAST_COPY_OFFSET {
+                11
+            } else {
+                12
+            };
             // Cost of not taking the match.
-            let no_match_cost = distance_to_end[i + 1] + 1;
+            let no_match_cost = distance_to_end[i + 1] + literal_cost;
             if mat.is_empty() {
                 distance_to_end[i] = no_match_cost;
                 continue;
@@ -442,8 +756,10 @@ impl<
         while curr < input_len {
             let mut mat = all_matches[curr].clone();
             if !mat.is_empty() {
-                dict.grow_match_backwards(&mut lit, &mut mat);
+                // Growing the match backwards takes bytes from the literal
+                // region, so the match still ends at 'curr + mat.len()'.
                 curr += mat.len();
+                dict.grow_match_backwards(&mut lit, &mut mat);
                 selected_matches.push((lit, mat));
                 lit = curr..curr;
                 continue;
@@ -458,6 +774,185 @@ impl<
     }
 }
 
+/// Return the length of the common prefix of the strings at 'a' and 'b', up
+/// to 'max' bytes. The strings are compared a word at a time.
+fn common_prefix(input: &[u8], a: usize, b: usize, max: usize) -> usize {
+    assert!(a.max(b) + max <= input.len());
+    let word = |at: usize| {
+        let bytes = unchecked::range(input, at..at + 8);
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    };
+    let mut len = 0;
+    while len + 8 <= max {
+        let (x, y) = (word(a + len), word(b + len));
+        if x != y {
+            return len + (x ^ y).trailing_zeros() as usize / 8;
+        }
+        len += 8;
+    }
+    while len < max
+        && unchecked::get(input, a + len) == unchecked::get(input, b + len)
+    {
+        len += 1;
+    }
+    len
+}
+
+/// A greedy matcher for the fastest level. It keeps one position for each
+/// hash, takes the first match that it finds without looking for a longer
+/// one, and doesn't hash the positions inside of matches. Parameters:
+/// MAX_OFFSET and MAX_MATCH limit the offset and the length of matches.
+/// DICT_SIZE_BITS controls the size of the table (1<<x).
+/// MIN_LEN is the minimum length of matches (at least 4).
+/// SKIP_SHIFT controls the acceleration: after 1<<x positions without a match
+/// the matcher skips a byte between probes, then two bytes, and so on, which
+/// speeds up incompressible regions.
+pub struct FastMatcher<
+    'a,
+    const MAX_OFFSET: usize,
+    const MAX_MATCH: usize,
+    const DICT_SIZE_BITS: usize,
+    const MIN_LEN: usize,
+    const SKIP_SHIFT: usize,
+> {
+    /// The input to tokenize.
+    input: &'a [u8],
+    /// Maps the hash of 4 bytes to the last position that was probed.
+    table: Vec<u32>,
+    /// The number of bits in the index of the table.
+    bits: usize,
+    /// The iterator location in the input.
+    cursor: usize,
+}
+
+impl<
+        'a,
+        const MAX_OFFSET: usize,
+        const MAX_MATCH: usize,
+        const DICT_SIZE_BITS: usize,
+        const MIN_LEN: usize,
+        const SKIP_SHIFT: usize,
+    >
+    FastMatcher<'a, MAX_OFFSET, MAX_MATCH, DICT_SIZE_BITS, MIN_LEN, SKIP_SHIFT>
+{
+    pub fn new(input: &'a [u8]) -> Self {
+        let bits = dict_bits(input.len(), DICT_SIZE_BITS);
+        let mut table = take_u32();
+        table.resize(1 << bits, EMPTY_CELL);
+        Self {
+            input,
+            table,
+            bits,
+            cursor: 0,
+        }
+    }
+
+    fn get_bytes_at(&self, idx: usize) -> u32 {
+        let val: [u8; 4] =
+            self.input[idx..idx + 4].try_into().expect("Out of bounds");
+        u32::from_ne_bytes(val)
+    }
+
+    /// Return the next literal and match regions. See 'Matcher'.
+    fn get_next_match_region(
+        &mut self,
+    ) -> Option<(Range<usize>, Range<usize>)> {
+        let input = self.input;
+        let len = input.len();
+        if self.cursor == len {
+            return None;
+        }
+        let start = self.cursor;
+        let mut probes = 1 << SKIP_SHIFT;
+        let mut i = start;
+
+        while i + MIN_LEN.max(MIN_MATCH) <= len {
+            let key = mul_hash32(self.get_bytes_at(i), self.bits);
+            let loc = self.table[key];
+            self.table[key] = i as u32;
+            if loc == EMPTY_CELL
+                || i - loc as usize >= MAX_OFFSET
+                || self.get_bytes_at(loc as usize) != self.get_bytes_at(i)
+            {
+                i += probes >> SKIP_SHIFT;
+                probes += 1;
+                continue;
+            }
+
+            // Extend the match forwards, and backwards into the literals.
+            let mut src = loc as usize;
+            let max_len = MAX_MATCH.min(len - i);
+            let mut mat_len = common_prefix(input, src, i, max_len);
+            if mat_len < MIN_LEN {
+                i += probes >> SKIP_SHIFT;
+                probes += 1;
+                continue;
+            }
+            while i > start
+                && src > 0
+                && mat_len < MAX_MATCH
+                && input[src - 1] == input[i - 1]
+            {
+                i -= 1;
+                src -= 1;
+                mat_len += 1;
+            }
+            self.cursor = i + mat_len;
+            return Some((start..i, src..src + mat_len));
+        }
+
+        // Emit the rest of the input as literals.
+        self.cursor = len;
+        Some((start..len, 0..0))
+    }
+}
+
+impl<
+        'a,
+        const MAX_OFFSET: usize,
+        const MAX_MATCH: usize,
+        const DICT_SIZE_BITS: usize,
+        const MIN_LEN: usize,
+        const SKIP_SHIFT: usize,
+    > Drop
+    for FastMatcher<
+        'a,
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        MIN_LEN,
+        SKIP_SHIFT,
+    >
+{
+    fn drop(&mut self) {
+        recycle_u32(std::mem::take(&mut self.table));
+    }
+}
+
+impl<
+        'a,
+        const MAX_OFFSET: usize,
+        const MAX_MATCH: usize,
+        const DICT_SIZE_BITS: usize,
+        const MIN_LEN: usize,
+        const SKIP_SHIFT: usize,
+    > Iterator
+    for FastMatcher<
+        'a,
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        MIN_LEN,
+        SKIP_SHIFT,
+    >
+{
+    type Item = (Range<usize>, Range<usize>);
+
+    fn next(&mut self) -> Option<(Range<usize>, Range<usize>)> {
+        self.get_next_match_region()
+    }
+}
+
 /// Implement the iterator trait for the matcher.
 impl<
         'a,
@@ -466,6 +961,8 @@ impl<
         const DICT_SIZE_BITS: usize,
         const DICT_BANKS: usize,
         const PARSE_SEARCH: usize,
+        const TAGGED: bool,
+        const LONG_BITS: usize,
     > Iterator
     for Matcher<
         'a,
@@ -474,6 +971,8 @@ impl<
         DICT_SIZE_BITS,
         DICT_BANKS,
         PARSE_SEARCH,
+        TAGGED,
+        LONG_BITS,
     >
 {
     type Item = (Range<usize>, Range<usize>);
@@ -489,8 +988,17 @@ impl<
         const MAX_MATCH: usize,
         const DICT_SIZE_BITS: usize,
         const DICT_BANKS: usize,
+        const TAGGED: bool,
+        const LONG_BITS: usize,
     > Iterator
-    for OptimalMatcher<MAX_OFFSET, MAX_MATCH, DICT_SIZE_BITS, DICT_BANKS>
+    for OptimalMatcher<
+        MAX_OFFSET,
+        MAX_MATCH,
+        DICT_SIZE_BITS,
+        DICT_BANKS,
+        TAGGED,
+        LONG_BITS,
+    >
 {
     type Item = (Range<usize>, Range<usize>);
 
@@ -503,6 +1011,31 @@ impl<
     }
 }
 
+/// The type of the iterators that the select functions return.
+type MatchIter<'a> =
+    Box<dyn Iterator<Item = (Range<usize>, Range<usize>)> + 'a>;
+
+/// Return a 'Matcher' with the dictionary parameters 'BITS', 'BANKS', 'TAGGED'
+/// and 'LONG' that parses the input with 'params'.
+fn lazy<
+    'a,
+    const MAX_OFF: usize,
+    const MAX_LEN: usize,
+    const BITS: usize,
+    const BANKS: usize,
+    const TAGGED: bool,
+    const LONG: usize,
+>(
+    input: &'a [u8],
+    params: ParseParams,
+) -> MatchIter<'a> {
+    let matcher =
+        Matcher::<'a, MAX_OFF, MAX_LEN, BITS, BANKS, 1, TAGGED, LONG>::new(
+            input,
+        );
+    Box::new(matcher.with_params(params))
+}
+
 /// Select the LZ matcher and matcher parameters based on the compression
 /// 'level'.
 /// 'MAX_LEN' and 'MAX_OFFSET' specify the maximum length and offset of matches.
@@ -510,20 +1043,97 @@ impl<
 pub fn select_matcher<'a, const MAX_OFF: usize, const MAX_LEN: usize>(
     level: u8,
     input: &'a [u8],
-) -> Box<dyn Iterator<Item = (Range<usize>, Range<usize>)> + 'a> {
-    return match level {
-        1 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 16, 2, 1>::new(input)),
-        2 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 16, 4, 1>::new(input)),
-        3 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 16, 8, 1>::new(input)),
-        4 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 16, 8, 2>::new(input)),
-        5 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 16, 10, 2>::new(input)),
-        6 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 16, 12, 2>::new(input)),
-        7 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 17, 12, 2>::new(input)),
-        8 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 17, 16, 2>::new(input)),
-        9 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 17, 24, 2>::new(input)),
-        10 => Box::new(Matcher::<'a, MAX_OFF, MAX_LEN, 20, 128, 4>::new(input)),
-        11 => Box::new(OptimalMatcher::<MAX_OFF, MAX_LEN, 21, 128>::new(input)),
-        12 => Box::new(OptimalMatcher::<MAX_OFF, MAX_LEN, 22, 256>::new(input)),
-        _ => panic!(),
-    };
+) -> MatchIter<'a> {
+    select_matcher_with::<MAX_OFF, MAX_LEN>(level, None, input)
+}
+
+/// Select the matcher like 'select_matcher', and parse the input with 'params'
+/// instead of the search depth of the level, if given. The parameters only
+/// apply to the levels with lazy parsing (1 to 10).
+pub fn select_matcher_with<'a, const MAX_OFF: usize, const MAX_LEN: usize>(
+    level: u8,
+    params: Option<ParseParams>,
+    input: &'a [u8],
+) -> MatchIter<'a> {
+    let search = |depth| params.unwrap_or(ParseParams::new(depth));
+    match level {
+        0 => select_fast_matcher::<MAX_OFF, MAX_LEN>(0, input),
+        1 => lazy::<MAX_OFF, MAX_LEN, 16, 2, false, 0>(input, search(1)),
+        2 => lazy::<MAX_OFF, MAX_LEN, 16, 4, false, 0>(input, search(1)),
+        3 => lazy::<MAX_OFF, MAX_LEN, 16, 8, false, 0>(input, search(1)),
+        4 => lazy::<MAX_OFF, MAX_LEN, 16, 8, false, 0>(input, search(2)),
+        5 => lazy::<MAX_OFF, MAX_LEN, 16, 10, false, 0>(input, search(2)),
+        6 => lazy::<MAX_OFF, MAX_LEN, 16, 12, false, 0>(input, search(2)),
+        7 => lazy::<MAX_OFF, MAX_LEN, 17, 12, false, 16>(input, search(2)),
+        8 => lazy::<MAX_OFF, MAX_LEN, 17, 16, false, 16>(input, search(2)),
+        9 => lazy::<MAX_OFF, MAX_LEN, 17, 24, false, 16>(input, search(2)),
+        10 => lazy::<MAX_OFF, MAX_LEN, 20, 128, true, 20>(input, search(4)),
+        11 => Box::new(
+            OptimalMatcher::<MAX_OFF, MAX_LEN, 21, 128, true, 20>::new(input),
+        ),
+        // The levels above 12 don't match, and use the matcher of level 12
+        // when a matcher is needed. See 'Context::try_new' for the levels
+        // that are valid.
+        _ => Box::new(
+            OptimalMatcher::<MAX_OFF, MAX_LEN, 22, 256, true, 22>::new(input),
+        ),
+    }
+}
+
+/// The highest acceleration of the fastest level.
+pub const MAX_ACCELERATION: u8 = 5;
+
+/// Select the parameters of the matcher of the fastest level based on the
+/// 'acceleration'. Each step shrinks the table, raises the minimum match
+/// length and skips ahead sooner when no match is found.
+/// Returns an iterator that iterates over the matches.
+pub fn select_fast_matcher<'a, const MAX_OFF: usize, const MAX_LEN: usize>(
+    acceleration: u8,
+    input: &'a [u8],
+) -> MatchIter<'a> {
+    match acceleration.min(MAX_ACCELERATION) {
+        0 => {
+            Box::new(FastMatcher::<'a, MAX_OFF, MAX_LEN, 16, 4, 6>::new(input))
+        }
+        1 => {
+            Box::new(FastMatcher::<'a, MAX_OFF, MAX_LEN, 15, 4, 5>::new(input))
+        }
+        2 => {
+            Box::new(FastMatcher::<'a, MAX_OFF, MAX_LEN, 14, 5, 4>::new(input))
+        }
+        3 => {
+            Box::new(FastMatcher::<'a, MAX_OFF, MAX_LEN, 13, 6, 3>::new(input))
+        }
+        4 => {
+            Box::new(FastMatcher::<'a, MAX_OFF, MAX_LEN, 12, 8, 2>::new(input))
+        }
+        _ => {
+            Box::new(FastMatcher::<'a, MAX_OFF, MAX_LEN, 11, 8, 1>::new(input))
+        }
+    }
+}
+
+/// Return a matcher for very large inputs, where the offsets are not bounded
+/// by the regular window. The table of the dictionary is larger, so positions
+/// survive longer before they are evicted, but it has fewer banks than the
+/// table of the regular matchers to bound the memory. The parameters 'params'
+/// replace the search depth of the level, like in 'select_matcher_with'.
+pub fn select_long_matcher<'a, const MAX_OFF: usize, const MAX_LEN: usize>(
+    level: u8,
+    params: Option<ParseParams>,
+    input: &'a [u8],
+) -> MatchIter<'a> {
+    let search = |depth| params.unwrap_or(ParseParams::new(depth));
+    match level {
+        1 => lazy::<MAX_OFF, MAX_LEN, 24, 2, true, 0>(input, search(1)),
+        2 | 3 => lazy::<MAX_OFF, MAX_LEN, 24, 4, true, 0>(input, search(1)),
+        4..=6 => lazy::<MAX_OFF, MAX_LEN, 24, 4, true, 0>(input, search(2)),
+        7..=9 => lazy::<MAX_OFF, MAX_LEN, 24, 8, true, 22>(input, search(2)),
+        10 => lazy::<MAX_OFF, MAX_LEN, 24, 16, true, 22>(input, search(4)),
+        // The levels above 11 use the matcher of level 11. See the levels of
+        // 'select_matcher_with'.
+        _ => Box::new(
+            OptimalMatcher::<MAX_OFF, MAX_LEN, 24, 16, true, 22>::new(input),
+        ),
+    }
 }
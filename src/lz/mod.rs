@@ -1,6 +1,57 @@
 //! A collection of modules that implement Lempel–Ziv matching.
 
-mod lz4;
+pub mod coverage;
+pub mod dictionary;
+pub mod global;
+pub mod lz4;
 pub mod matcher;
+#[cfg(feature = "reference-lz4")]
+pub mod reference;
+use crate::utils::unchecked::{copy_chunks, WILD_COPY_SLACK};
 pub use lz4::LZ4Decoder;
 pub use lz4::LZ4Encoder;
+pub use lz4::LZ4Error;
+
+/// Matches with offsets up to this value are copied by replicating a short
+/// pattern, which is the fastest way to decode them.
+pub const FAST_COPY_OFFSET: usize = 8;
+
+/// Matches up to this length are copied in chunks with the 'unsafe-fast'
+/// feature. See 'unchecked::copy_chunks'.
+const SHORT_COPY_LEN: usize = 32;
+
+/// Append 'len' bytes to 'output' that are copied from 'offset' bytes before
+/// the end of 'output'. When the offset is shorter than the length, the copy
+/// overlaps itself and the last 'offset' bytes repeat. Instead of copying such
+/// matches byte by byte, the pattern is doubled until the match is filled.
+/// The 'offset' must be in the range 1..=output.len().
+pub fn copy_match(output: &mut Vec<u8>, offset: usize, len: usize) {
+    debug_assert!(offset > 0 && offset <= output.len());
+    // Short matches are copied in chunks, without calls to memcpy.
+    if cfg!(feature = "unsafe-fast")
+        && offset >= WILD_COPY_SLACK
+        && len <= SHORT_COPY_LEN
+    {
+        copy_chunks(output, offset, len);
+        return;
+    }
+    let start = output.len() - offset;
+    if offset >= len {
+        output.extend_from_within(start..start + len);
+        return;
+    }
+    if offset == 1 {
+        let val = output[start];
+        output.resize(output.len() + len, val);
+        return;
+    }
+
+    // The bytes from 'start' are periodic with the period 'offset', and each
+    // copy of a whole number of periods keeps them periodic.
+    let end = output.len() + len;
+    output.reserve(len);
+    while output.len() < end {
+        let chunk = (output.len() - start).min(end - output.len());
+        output.extend_from_within(start..start + chunk);
+    }
+}
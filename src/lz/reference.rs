@@ -0,0 +1,61 @@
+//! Bindings to the reference LZ4 implementation in C, which are used to check
+//! that the streams of 'LZ4Encoder' and 'LZ4Decoder' are compatible with it.
+//! The module is compiled with the 'reference-lz4' feature.
+
+use lz4_sys::{LZ4_compressBound, LZ4_compress_HC, LZ4_decompress_safe};
+use std::os::raw::{c_char, c_int};
+
+/// Compress 'input' into an LZ4 block with the high compression mode of the
+/// reference implementation at 'level' (1 to 12). Returns None if the input
+/// is too large for the reference implementation.
+pub fn compress(input: &[u8], level: i32) -> Option<Vec<u8>> {
+    let len = c_int::try_from(input.len()).ok()?;
+    // SAFETY: The bound only reads the length.
+    let bound = unsafe { LZ4_compressBound(len) };
+    if bound <= 0 {
+        return None;
+    }
+    let mut output = vec![0u8; bound as usize];
+    // The optimal parser reads the first byte of empty inputs, so the pointer
+    // of empty slices, which is dangling, can't be used.
+    let src = if input.is_empty() { &[0u8][..] } else { input };
+    // SAFETY: The input and the output are valid for their lengths.
+    let written = unsafe {
+        LZ4_compress_HC(
+            src.as_ptr() as *const c_char,
+            output.as_mut_ptr() as *mut c_char,
+            len,
+            bound,
+            level,
+        )
+    };
+    if written <= 0 {
+        return None;
+    }
+    output.truncate(written as usize);
+    Some(output)
+}
+
+/// Decompress the LZ4 block 'input' with the reference implementation, into
+/// at most 'max_len' bytes. Returns None if the block is invalid or if it
+/// decodes into more than 'max_len' bytes.
+pub fn decompress(input: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let len = c_int::try_from(input.len()).ok()?;
+    let capacity = c_int::try_from(max_len).ok()?;
+    let mut output = vec![0u8; max_len];
+    // SAFETY: The input and the output are valid for their lengths, and the
+    // safe decoder does not read or write outside of them.
+    let written = unsafe {
+        LZ4_decompress_safe(
+            input.as_ptr() as *const c_char,
+            output.as_mut_ptr() as *mut c_char,
+            len,
+            capacity,
+        )
+    };
+    if written < 0 {
+        return None;
+    }
+    output.truncate(written as usize);
+    Some(output)
+}
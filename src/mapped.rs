@@ -0,0 +1,225 @@
+//! Compresses buffers that other writers may change while they are compressed,
+//! such as the pages of a database that are mapped with 'mmap'. The buffer is
+//! compressed one page at a time, without copying the whole buffer first.
+//!
+//! Bytes that other threads or processes write to can't be borrowed as
+//! '&[u8]', because Rust assumes that shared references point to memory that
+//! doesn't change. The buffer is borrowed as '&[AtomicU8]' instead, which has
+//! the layout of '&[u8]', so mapped memory is cast to it with
+//! 'slice::from_raw_parts'. Each page is copied into a scratch buffer with
+//! relaxed loads, and the copy is compressed, so the matcher and the entropy
+//! coder never read memory that changes.
+//!
+//! A buffer that changes during the compression still gives a torn stream: a
+//! stream whose pages were copied at different times. The encoder detects
+//! such changes in two ways. The caller can provide a generation counter,
+//! which the writers change before and after each write, like a seqlock. The
+//! generation is checked before the compression, after each page and at the
+//! end. The encoder can also decode the stream and compare it to the buffer.
+//! The caller retries, or takes a lock, when a change is detected.
+
+use crate::digest::{DigestHasher, Sha256};
+use crate::full::{append_trailers, encode_or_nop, needs_whole_input};
+use crate::full::{FullDecoder, FullEncoder};
+use crate::metadata::Metadata;
+use crate::pager::{self, fixed_pages};
+use crate::utils::leb128;
+use crate::utils::signatures::{FULL_SIG, STORED_SIG};
+use crate::{Context, Decoder, Encoder};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Append the bytes of 'range' of the buffer 'input' to 'output'.
+fn copy_range(input: &[AtomicU8], range: Range<usize>, output: &mut Vec<u8>) {
+    output.extend(input[range].iter().map(|b| b.load(Ordering::Relaxed)));
+}
+
+/// The reasons for rejecting the stream of a buffer that changed while it was
+/// compressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MappedError {
+    /// The generation of the buffer changed during the compression, or a write
+    /// was in progress when the compression started.
+    Changed,
+    /// The stream does not decode into the buffer. The index of the first
+    /// page that differs.
+    Torn(usize),
+}
+
+/// Compresses a buffer that may change during the compression. The stream is
+/// decoded by 'FullDecoder'.
+pub struct MappedEncoder<'a> {
+    /// The uncompressed input, which other writers may change.
+    input: &'a [AtomicU8],
+    /// The output stream.
+    output: &'a mut Vec<u8>,
+    /// Encoder context.
+    ctx: Context,
+    /// The metadata that is saved after the signature of the stream.
+    metadata: Option<&'a Metadata>,
+    /// Returns the generation of the buffer. See 'with_generation'.
+    generation: Option<&'a dyn Fn() -> u64>,
+    /// Decode the stream and compare it to the buffer. See
+    /// 'with_verification'.
+    verify: bool,
+}
+
+impl<'a> MappedEncoder<'a> {
+    pub fn new(
+        input: &'a [AtomicU8],
+        output: &'a mut Vec<u8>,
+        ctx: Context,
+    ) -> Self {
+        Self {
+            input,
+            output,
+            ctx,
+            metadata: None,
+            generation: None,
+            verify: false,
+        }
+    }
+
+    /// Save 'metadata' after the signature of the stream, like
+    /// 'FullEncoder::with_metadata'. Returns None if the metadata is larger
+    /// than 'MAX_METADATA_LEN'.
+    pub fn with_metadata(mut self, metadata: &'a Metadata) -> Option<Self> {
+        metadata.encode(&mut Vec::new())?;
+        self.metadata = Some(metadata);
+        Some(self)
+    }
+
+    /// Check the generation of the buffer, which 'generation' returns, during
+    /// the compression. The writers of the buffer increment the generation
+    /// before and after each write, so an odd generation means that a write is
+    /// in progress.
+    pub fn with_generation(mut self, generation: &'a dyn Fn() -> u64) -> Self {
+        self.generation = Some(generation);
+        self
+    }
+
+    /// Decode the stream after it is written, and compare it to the buffer.
+    /// This catches the changes of writers that don't update the generation,
+    /// and costs the time of decoding the stream.
+    pub fn with_verification(mut self) -> Self {
+        self.verify = true;
+        self
+    }
+
+    /// Returns an error if the generation is not 'expected'.
+    fn check_generation(&self, expected: u64) -> Result<(), MappedError> {
+        match self.generation {
+            Some(generation) if generation() != expected => {
+                Err(MappedError::Changed)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Encode the buffer and return the number of bytes written. Returns an
+    /// error if the buffer changed during the compression, and then the
+    /// content of the output is unspecified.
+    pub fn encode(&mut self) -> Result<usize, MappedError> {
+        let expected = self.generation.map_or(0, |generation| generation());
+        if expected % 2 == 1 {
+            return Err(MappedError::Changed);
+        }
+        let start = self.output.len();
+        let written = if needs_whole_input(self.ctx) {
+            self.encode_whole(expected)?
+        } else {
+            self.encode_pages(expected)?
+        };
+        self.check_generation(expected)?;
+        if self.verify {
+            self.verify_stream(start)?;
+        }
+        Ok(written)
+    }
+
+    /// Copy the whole buffer, and compress the copy with 'FullEncoder'. This
+    /// is used by the levels that inspect the whole input before they
+    /// compress the pages.
+    fn encode_whole(&mut self, expected: u64) -> Result<usize, MappedError> {
+        let mut copy = Vec::with_capacity(self.input.len());
+        copy_range(self.input, 0..self.input.len(), &mut copy);
+        self.check_generation(expected)?;
+        let mut encoder = FullEncoder::new(&copy, self.output, self.ctx);
+        if let Some(metadata) = self.metadata {
+            // The size of the metadata was checked by 'with_metadata'.
+            encoder = encoder.with_metadata(metadata).unwrap();
+        }
+        Ok(encoder.encode())
+    }
+
+    /// Write the signature of the stream and the metadata. Returns the number
+    /// of bytes written.
+    fn write_header(&mut self) -> usize {
+        self.output.extend(FULL_SIG);
+        let metadata = self.metadata.map(|m| m.encode(self.output));
+        FULL_SIG.len() + metadata.flatten().unwrap_or(0)
+    }
+
+    /// Encode the pages of the buffer one at a time, and check the generation
+    /// after each page, to stop early when the buffer changes. The stream is
+    /// the stream of 'FullEncoder': the pages are stored if they don't save
+    /// anything, and the digest covers the copies of the pages.
+    fn encode_pages(&mut self, expected: u64) -> Result<usize, MappedError> {
+        let start = self.output.len();
+        let header = self.write_header();
+        let len = self.input.len();
+        let pages = fixed_pages(0..len, self.ctx.block_size, true);
+        pager::write_header(pages.len(), self.output);
+        let mut hasher = self.ctx.digest.then(Sha256::new);
+        let mut page = Vec::with_capacity(self.ctx.block_size.min(len));
+        for range in pages {
+            page.clear();
+            copy_range(self.input, range, &mut page);
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&page);
+            }
+            pager::encode_page(&page, self.ctx, encode_or_nop, self.output);
+            self.check_generation(expected)?;
+        }
+
+        // Store the raw bytes if compression did not save anything. The
+        // buffer is copied again, so the digest covers the new copy.
+        let stored_size =
+            header - FULL_SIG.len() + FullEncoder::stored_size(len);
+        if self.output.len() - start > stored_size {
+            self.output.truncate(start + header);
+            self.output.extend(STORED_SIG);
+            leb128::encode(len as u64, self.output);
+            let stored = self.output.len();
+            copy_range(self.input, 0..len, self.output);
+            if let Some(hasher) = hasher.as_mut() {
+                *hasher = Sha256::new();
+                hasher.update(&self.output[stored..]);
+            }
+        }
+        let hasher = hasher.as_ref().map(|h| h as &dyn DigestHasher);
+        append_trailers(self.output, start, hasher, self.ctx);
+        Ok(self.output.len() - start)
+    }
+
+    /// Decode the stream that starts at 'start' in the output, and compare it
+    /// to the buffer. Returns the index of the first page that differs.
+    fn verify_stream(&self, start: usize) -> Result<(), MappedError> {
+        let mut decoded = Vec::new();
+        let stream = &self.output[start..];
+        if FullDecoder::new(stream, &mut decoded).decode().is_none() {
+            return Err(MappedError::Torn(0));
+        }
+        let page_size = self.ctx.block_size.max(1);
+        let same = decoded.len().min(self.input.len());
+        let diff = decoded
+            .iter()
+            .zip(self.input)
+            .position(|(a, b)| *a != b.load(Ordering::Relaxed))
+            .unwrap_or(same);
+        if diff < same || decoded.len() != self.input.len() {
+            return Err(MappedError::Torn(diff / page_size));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! Saves a hash tree (Merkle tree) of the pages after a paged stream of the
+//! full compressor (see 'Context::with_hash_tree'). The leaves of the tree are
+//! the SHA-256 digests of the page records, and the trailer saves the leaves
+//! with the size of each record. A client that trusts the root of the tree,
+//! which is published separately, loads the trailer from the end of the
+//! stream, checks the leaves and the sizes against the root, and then verifies
+//! any range of pages that it downloaded without reading the rest of the
+//! stream. Decoders check the tree against the pages. The trailer is saved
+//! after the digest and before the frame checksum, which covers it.
+
+use crate::digest::{DigestHasher, Sha256};
+use crate::frame::FRAME_TRAILER_LEN;
+use crate::full::read_full_header;
+use crate::pager::{read_header, record_len};
+use crate::utils::leb128;
+use crate::utils::signatures::HASH_TREE_SIG;
+use crate::utils::signatures::{match_signature, read32, write32};
+use std::ops::Range;
+
+/// The size of the digests of the tree.
+pub const HASH_LEN: usize = 32;
+
+/// The size of the end of the trailer: the size of the trailer and the
+/// signature, which allow clients to find the trailer from the end.
+const TRAILER_END_LEN: usize = 4 + HASH_TREE_SIG.len();
+
+/// A digest of the tree.
+pub type Hash = [u8; HASH_LEN];
+
+/// Return the digest of the bytes 'parts', which start with 'prefix'. The
+/// leaves and the inner nodes use different prefixes, so that a leaf can't be
+/// presented as a node.
+fn hash_parts(prefix: u8, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finish().try_into().unwrap()
+}
+
+/// The hash tree of the pages of a stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashTree {
+    /// The offset of the first page record from the start of the stream.
+    first: usize,
+    /// The size of each page record.
+    lens: Vec<usize>,
+    /// The digest of each page record.
+    leaves: Vec<Hash>,
+}
+
+impl HashTree {
+    /// Build the tree of the paged stream 'stream', which starts with the
+    /// signature of the full compressor. Returns the tree and the offset of
+    /// the end of the pages, or None if the stream is not a paged stream.
+    pub fn from_stream(stream: &[u8]) -> Option<(Self, usize)> {
+        let header = read_full_header(stream)?;
+        let (read, parts) = read_header(&stream[header..])?;
+        let first = header + read;
+        let mut tree = Self {
+            first,
+            lens: Vec::new(),
+            leaves: Vec::new(),
+        };
+        let mut cursor = first;
+        for _ in 0..parts {
+            let len = record_len(&stream[cursor..])?;
+            tree.leaves.push(Self::leaf(&stream[cursor..cursor + len]));
+            tree.lens.push(len);
+            cursor += len;
+        }
+        Some((tree, cursor))
+    }
+
+    /// Return the leaf of the page record 'record'.
+    fn leaf(record: &[u8]) -> Hash {
+        hash_parts(0, &[record])
+    }
+
+    /// Return the number of pages in the tree.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns True if the tree has no pages.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Return the root of the tree. The nodes of the first level hash each
+    /// leaf with the size of its record, so that the sizes in the trailer are
+    /// covered too. Each level hashes pairs of nodes, and the last node of a
+    /// level with an odd number of nodes moves up as is. The root hashes the
+    /// top node with the offset of the first page.
+    pub fn root(&self) -> Hash {
+        let mut level: Vec<Hash> = self
+            .leaves
+            .iter()
+            .zip(&self.lens)
+            .map(|(leaf, len)| {
+                hash_parts(2, &[&(*len as u64).to_le_bytes(), leaf])
+            })
+            .collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_parts(1, &[left, right]),
+                    _ => pair[0],
+                })
+                .collect();
+        }
+        let top = level.first().copied().unwrap_or(hash_parts(1, &[]));
+        hash_parts(3, &[&(self.first as u64).to_le_bytes(), &top])
+    }
+
+    /// Return the range of the stream that holds the records of the pages
+    /// 'pages', which a client downloads to verify them. Returns None if the
+    /// range is outside of the tree or if the sizes of the records overflow.
+    pub fn page_range(&self, pages: Range<usize>) -> Option<Range<usize>> {
+        if pages.start > pages.end || pages.end > self.len() {
+            return None;
+        }
+        let sum = |lens: &[usize]| {
+            lens.iter()
+                .try_fold(0usize, |sum, len| sum.checked_add(*len))
+        };
+        let start = self.first.checked_add(sum(&self.lens[..pages.start])?)?;
+        let end = start.checked_add(sum(&self.lens[pages])?)?;
+        Some(start..end)
+    }
+
+    /// Verify the bytes 'bytes' that were downloaded from the offset 'offset'
+    /// of the stream. The pages that are wholly inside of the range are
+    /// checked against their leaves. Returns the range of the verified pages,
+    /// which may be empty, or None if one of the pages is damaged or if the
+    /// sizes of the records overflow.
+    pub fn verify_range(
+        &self,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Option<Range<usize>> {
+        let end = offset.checked_add(bytes.len())?;
+        let mut start = self.first;
+        let mut verified: Option<Range<usize>> = None;
+        for (page, &len) in self.lens.iter().enumerate() {
+            let record = start..start.checked_add(len)?;
+            start = record.end;
+            if record.start < offset || record.end > end {
+                continue;
+            }
+            let data = &bytes[record.start - offset..record.end - offset];
+            if Self::leaf(data) != self.leaves[page] {
+                return None;
+            }
+            verified = match verified {
+                Some(range) => Some(range.start..page + 1),
+                None => Some(page..page + 1),
+            };
+        }
+        Some(verified.unwrap_or(0..0))
+    }
+
+    /// Write the trailer of the tree to 'output'. Returns the number of bytes
+    /// written.
+    pub fn write_trailer(&self, output: &mut Vec<u8>) -> usize {
+        let start = output.len();
+        output.extend(HASH_TREE_SIG);
+        leb128::encode(self.first as u64, output);
+        leb128::encode(self.len() as u64, output);
+        for len in &self.lens {
+            leb128::encode(*len as u64, output);
+        }
+        for leaf in &self.leaves {
+            output.extend(leaf);
+        }
+        let len = output.len() - start + TRAILER_END_LEN;
+        write32(len as u32, output);
+        output.extend(HASH_TREE_SIG);
+        len
+    }
+
+    /// Read the trailer at the start of 'input'. Returns the size of the
+    /// trailer and the tree, or None if the input does not start with a
+    /// trailer.
+    pub fn read_trailer(input: &[u8]) -> Option<(usize, Self)> {
+        if !match_signature(input, &HASH_TREE_SIG) {
+            return None;
+        }
+        let mut cursor = HASH_TREE_SIG.len();
+        let (read, first) = leb128::decode_len(&input[cursor..])?;
+        cursor += read;
+        let (read, parts) = leb128::decode_len(&input[cursor..])?;
+        cursor += read;
+        // Each page takes at least one byte and a leaf.
+        if parts > input.len() / (HASH_LEN + 1) {
+            return None;
+        }
+        let mut lens = Vec::with_capacity(parts);
+        for _ in 0..parts {
+            let (read, len) = leb128::decode_len(&input[cursor..])?;
+            cursor += read;
+            lens.push(len);
+        }
+        let mut leaves = Vec::with_capacity(parts);
+        for _ in 0..parts {
+            let leaf = input.get(cursor..cursor + HASH_LEN)?;
+            leaves.push(leaf.try_into().unwrap());
+            cursor += HASH_LEN;
+        }
+        let len = read32(input.get(cursor..)?)? as usize;
+        cursor += TRAILER_END_LEN;
+        let sig = input.get(cursor - HASH_TREE_SIG.len()..cursor)?;
+        if len != cursor || sig != HASH_TREE_SIG {
+            return None;
+        }
+        Some((
+            cursor,
+            Self {
+                first,
+                lens,
+                leaves,
+            },
+        ))
+    }
+
+    /// Find the trailer at the end of 'tail', which holds the last bytes of a
+    /// stream, with or without the frame checksum that follows the trailer.
+    /// This allows clients to load the tree without reading the pages.
+    pub fn find_trailer(tail: &[u8]) -> Option<Self> {
+        let frame = tail.len().checked_sub(FRAME_TRAILER_LEN);
+        for end in [Some(tail.len()), frame].into_iter().flatten() {
+            let Some(start) = end.checked_sub(TRAILER_END_LEN) else {
+                continue;
+            };
+            if tail[end - HASH_TREE_SIG.len()..end] != HASH_TREE_SIG {
+                continue;
+            }
+            let len = read32(&tail[start..])? as usize;
+            let Some(begin) = end.checked_sub(len) else {
+                continue;
+            };
+            if let Some((read, tree)) = Self::read_trailer(&tail[begin..end]) {
+                if read == len {
+                    return Some(tree);
+                }
+            }
+        }
+        None
+    }
+}
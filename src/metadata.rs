@@ -0,0 +1,153 @@
+//! Attaches a small map of metadata, such as a comment, the tool that created
+//! the stream or the original path of the file, to a stream of the full
+//! compressor, like the FCOMMENT field of gzip, but structured. The metadata
+//! is saved right after the signature of the stream, so it is read without
+//! decompressing the stream (see 'read_metadata'). The record starts with its
+//! signature and its size, so readers that don't need it skip it. The body is
+//! the number of entries, followed by the key and the value of each entry, as
+//! arrays of UTF-8 bytes.
+
+use crate::utils::array_encoding::{
+    decode as decode_arr, encode as encode_arr,
+};
+use crate::utils::leb128;
+use crate::utils::signatures::{match_signature, FULL_SIG, METADATA_SIG};
+
+/// The max size of the body of the metadata record.
+pub const MAX_METADATA_LEN: usize = 1 << 16;
+
+/// The key of the free-form comment.
+pub const COMMENT_KEY: &str = "comment";
+
+/// An ordered map of UTF-8 keys and values.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of 'key' to 'value', and replace the previous value.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.entries.push((key.to_string(), value.to_string())),
+        }
+        self
+    }
+
+    /// Set the free-form comment of the stream.
+    pub fn with_comment(self, comment: &str) -> Self {
+        self.with(COMMENT_KEY, comment)
+    }
+
+    /// Return the value of 'key', or None if it is not set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Return the comment of the stream, if it has one.
+    pub fn comment(&self) -> Option<&str> {
+        self.get(COMMENT_KEY)
+    }
+
+    /// Return the entries, in the order that they were set.
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the metadata record to 'output'. Nothing is written if the map
+    /// is empty. Returns the number of bytes written, or None if the body
+    /// exceeds 'MAX_METADATA_LEN', and then nothing is written.
+    pub fn encode(&self, output: &mut Vec<u8>) -> Option<usize> {
+        if self.is_empty() {
+            return Some(0);
+        }
+        let mut body = Vec::new();
+        leb128::encode(self.entries.len() as u64, &mut body);
+        for (key, value) in &self.entries {
+            encode_arr(key.as_bytes(), &mut body);
+            encode_arr(value.as_bytes(), &mut body);
+        }
+        if body.len() > MAX_METADATA_LEN {
+            return None;
+        }
+        let start = output.len();
+        output.extend(METADATA_SIG);
+        leb128::encode(body.len() as u64, output);
+        output.extend(body);
+        Some(output.len() - start)
+    }
+
+    /// Decode the metadata record at the start of 'input'. Returns the size
+    /// of the record and the metadata, or None if the record is invalid.
+    pub fn decode(input: &[u8]) -> Option<(usize, Self)> {
+        let (header, len) = read_record_header(input)?;
+        let body = input.get(header..header + len)?;
+        let (mut cursor, count) = leb128::decode_len(body)?;
+        let mut metadata = Self::new();
+        for _ in 0..count {
+            let mut key = Vec::new();
+            cursor += decode_arr(body.get(cursor..)?, &mut key)?;
+            let mut value = Vec::new();
+            cursor += decode_arr(body.get(cursor..)?, &mut value)?;
+            let key = String::from_utf8(key).ok()?;
+            let value = String::from_utf8(value).ok()?;
+            metadata.entries.push((key, value));
+        }
+        if cursor != len {
+            return None;
+        }
+        Some((header + len, metadata))
+    }
+}
+
+/// Read the signature and the size of the metadata record at the start of
+/// 'input'. Returns the size of the header of the record and the size of its
+/// body.
+fn read_record_header(input: &[u8]) -> Option<(usize, usize)> {
+    if !match_signature(input, &METADATA_SIG) {
+        return None;
+    }
+    let (read, len) = leb128::decode_len(&input[METADATA_SIG.len()..])?;
+    if len > MAX_METADATA_LEN {
+        return None;
+    }
+    Some((METADATA_SIG.len() + read, len))
+}
+
+/// Return the size of the metadata record at the start of 'input', which
+/// follows the signature of the full stream, or zero if the stream has no
+/// metadata. Returns None if the record is cut off.
+pub fn skip_metadata(input: &[u8]) -> Option<usize> {
+    if !match_signature(input, &METADATA_SIG) {
+        return Some(0);
+    }
+    let (header, len) = read_record_header(input)?;
+    input.get(header..header + len)?;
+    Some(header + len)
+}
+
+/// Return the metadata of the full stream 'input', without decompressing it.
+/// Streams without metadata return an empty map. Returns None if the input is
+/// not a stream of the full compressor, or if the metadata is invalid.
+pub fn read_metadata(input: &[u8]) -> Option<Metadata> {
+    if !match_signature(input, &FULL_SIG) {
+        return None;
+    }
+    let body = &input[FULL_SIG.len()..];
+    if !match_signature(body, &METADATA_SIG) {
+        return Some(Metadata::new());
+    }
+    Metadata::decode(body).map(|(_, metadata)| metadata)
+}
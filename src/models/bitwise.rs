@@ -2,28 +2,63 @@
 //! sequence by looking at a history of the n-previous bits.
 //! <https://mattmahoney.net/dc/dce.html#Section_412>
 
-use crate::utils::RECIPROCAL_U32;
+use crate::utils::div16;
 
 use super::Model;
 
 pub const MODEL_CTX: usize = 29;
 pub const MODEL_LIMIT: usize = 255;
 
+/// The counters of the buckets of the model. Wider counters allow larger
+/// LIMIT values, and take more memory.
+pub trait Counter: Copy {
+    /// The largest value of the counter.
+    const MAX: usize;
+    fn get(self) -> usize;
+    fn from(val: usize) -> Self;
+}
+
+impl Counter for u8 {
+    const MAX: usize = u8::MAX as usize;
+    fn get(self) -> usize {
+        self as usize
+    }
+    fn from(val: usize) -> Self {
+        val as u8
+    }
+}
+
+impl Counter for u16 {
+    const MAX: usize = u16::MAX as usize;
+    fn get(self) -> usize {
+        self as usize
+    }
+    fn from(val: usize) -> Self {
+        val as u16
+    }
+}
+
 /// A simple model that predicts the probability of the next bit.
 /// CONTEXT_SIZE_BITS defines the size of the cache (history).
-/// LIMIT defines the maximum number of samples for bucket.
-pub struct BitwiseModel<const CONTEXT_SIZE_BITS: usize, const LIMIT: usize> {
+/// LIMIT defines the maximum number of samples for bucket, and must fit in
+/// the counter type 'C'.
+pub struct BitwiseModel<
+    const CONTEXT_SIZE_BITS: usize,
+    const LIMIT: usize,
+    C: Counter = u8,
+> {
     ctx: u64,
-    cache: Vec<(u8, u8)>,
+    cache: Vec<(C, C)>,
 }
 
-impl<const CTX_SIZE_BITS: usize, const LIMIT: usize> Model
-    for BitwiseModel<CTX_SIZE_BITS, LIMIT>
+impl<const CTX_SIZE_BITS: usize, const LIMIT: usize, C: Counter> Model
+    for BitwiseModel<CTX_SIZE_BITS, LIMIT, C>
 {
     fn new() -> Self {
+        assert!(LIMIT <= C::MAX, "The counters can't reach the limit");
         Self {
             ctx: 0,
-            cache: vec![(1, 1); 1 << CTX_SIZE_BITS],
+            cache: vec![(C::from(1), C::from(1)); 1 << CTX_SIZE_BITS],
         }
     }
 
@@ -32,26 +67,25 @@ impl<const CTX_SIZE_BITS: usize, const LIMIT: usize> Model
         // 'CTX_SIZE_BITS' LSB bits in 'ctx'.
         let key = self.ctx % (1 << CTX_SIZE_BITS);
         let (set, cnt) = self.cache[key as usize];
-        let a = set as u64;
-        let b = 1 + cnt as u64;
-
-        // This is equivalent to (a * (1<<16)) / b;
-        ((a * (RECIPROCAL_U32[b as usize] as u64)) >> 16) as u16
+        let a = set.get() as u64;
+        let b = 1 + cnt.get() as u64;
+        div16(a, b) as u16
     }
 
     fn update(&mut self, bit: u8) {
         // Update the probability of the context 'ctx', considering the first
         // 'CTX_SIZE_BITS' LSB bits, with the bit 'bit'.
         let key = self.ctx % (1 << CTX_SIZE_BITS);
-        let (set, cnt) = &mut self.cache[key as usize];
-        *cnt += 1;
-        *set += bit & 1;
+        let (set, cnt) = self.cache[key as usize];
+        let mut cnt = cnt.get() + 1;
+        let mut set = set.get() + (bit & 1) as usize;
         // Normalize the count if LIMIT is exceeded. This allows new data to
         // have a higher weight.
-        if *cnt as usize == LIMIT {
-            *set /= 2;
-            *cnt /= 2;
+        if cnt == LIMIT {
+            set /= 2;
+            cnt /= 2;
         }
+        self.cache[key as usize] = (C::from(set), C::from(cnt));
         // Update the context.
         self.ctx = (self.ctx << 1) + bit as u64;
     }
@@ -96,3 +130,24 @@ fn test_simple_model() {
         assert!(pred > 65_000);
     }
 }
+
+#[test]
+fn test_wide_counters() {
+    // Limits above the size of the table of reciprocals use wider counters.
+    let mut model = BitwiseModel::<7, 4000, u16>::new();
+    for _ in 0..10000 {
+        model.update(1);
+        model.update(1);
+        model.update(1);
+        model.update(0);
+    }
+    // Predict a '1'.
+    let pred = model.predict();
+    assert!(pred > 64_000);
+    for _ in 0..3 {
+        model.update(1);
+    }
+    // Predict a zero.
+    let pred = model.predict();
+    assert!(pred < 1_000);
+}
@@ -0,0 +1,148 @@
+//! This module implements logistic mixing, which combines the predictions of
+//! several models in the logistic domain with weights that are learned online.
+//! Reference: the mixer of the PAQ family of compressors,
+//! <https://mattmahoney.net/dc/dce.html#Section_432>
+
+use std::sync::OnceLock;
+
+/// Probabilities in the logistic domain are 12-bit: 0..4096.
+const PROB_BITS: u32 = 12;
+
+/// The fixed-point scale of the weights of the mixer: 1.0 is 1 << 16.
+const WEIGHT_BITS: u32 = 16;
+
+/// The bound of the weights, which keeps the dot product in range.
+const MAX_WEIGHT: i32 = 1 << 24;
+
+/// The learning rate of the mixer, as a shift of the error.
+const LEARNING_SHIFT: u32 = 12;
+
+/// Return the 12-bit probability 'p' of the stretched probability 'x', which
+/// is ln(p/(1-p)) scaled by 256, in the range -2047..2047.
+pub fn squash(x: i32) -> i32 {
+    const TABLE: [i32; 33] = [
+        1, 2, 3, 6, 10, 16, 27, 45, 73, 120, 194, 310, 488, 747, 1101, 1546,
+        2047, 2549, 2994, 3348, 3607, 3785, 3901, 3975, 4022, 4050, 4068, 4079,
+        4085, 4089, 4092, 4093, 4094,
+    ];
+    if x > 2047 {
+        return 4095;
+    }
+    if x < -2047 {
+        return 1;
+    }
+    let w = x & 127;
+    let idx = ((x >> 7) + 16) as usize;
+    (TABLE[idx] * (128 - w) + TABLE[idx + 1] * w + 64) >> 7
+}
+
+/// Return the inverse of 'squash' for the 12-bit probability 'p'.
+pub fn stretch(p: i32) -> i32 {
+    static TABLE: OnceLock<Vec<i32>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = vec![2047; 1 << PROB_BITS];
+        let mut prev = 0;
+        for x in -2047..=2047 {
+            let v = squash(x) as usize;
+            for item in table.iter_mut().take(v + 1).skip(prev) {
+                *item = x;
+            }
+            prev = prev.max(v + 1);
+        }
+        table
+    });
+    table[p.clamp(0, (1 << PROB_BITS) - 1) as usize]
+}
+
+/// Mixes the predictions of 'inputs' models with one of 'sets' sets of
+/// weights. The caller adds the predictions of the models for the next bit,
+/// selects a set of weights by some small context, and updates the weights
+/// with the bit.
+pub struct LogisticMixer {
+    /// The weights of each set.
+    weights: Vec<i32>,
+    /// The stretched predictions of the current bit.
+    inputs: Vec<i32>,
+    /// The number of inputs of each set.
+    width: usize,
+    /// The offset of the selected set in 'weights'.
+    selected: usize,
+    /// The last mixed prediction, as a 12-bit probability.
+    pr: i32,
+}
+
+impl LogisticMixer {
+    pub fn new(inputs: usize, sets: usize) -> Self {
+        let weight = (1 << WEIGHT_BITS) / inputs as i32;
+        Self {
+            weights: vec![weight; inputs * sets],
+            inputs: Vec::with_capacity(inputs),
+            width: inputs,
+            selected: 0,
+            pr: 1 << (PROB_BITS - 1),
+        }
+    }
+
+    /// Add the prediction 'p' of the next bit being 1, in the 16-bit range.
+    pub fn add(&mut self, p: u16) {
+        debug_assert!(self.inputs.len() < self.width);
+        self.inputs.push(stretch((p >> (16 - PROB_BITS)) as i32));
+    }
+
+    /// Mix the predictions that were added with the weights of the set 'set'.
+    /// Returns the probability of the next bit being 1, in the 16-bit range.
+    #[must_use]
+    pub fn mix(&mut self, set: usize) -> u16 {
+        debug_assert_eq!(self.inputs.len(), self.width);
+        self.selected = set * self.width;
+        let weights = &self.weights[self.selected..self.selected + self.width];
+        let dot: i64 = self
+            .inputs
+            .iter()
+            .zip(weights)
+            .map(|(x, w)| *x as i64 * *w as i64)
+            .sum();
+        self.pr = squash((dot >> WEIGHT_BITS) as i32);
+        (self.pr << (16 - PROB_BITS)) as u16
+    }
+
+    /// Update the selected weights with the bit 'bit', and clear the inputs.
+    pub fn update(&mut self, bit: bool) {
+        let err = ((bit as i32) << PROB_BITS) - self.pr;
+        let weights =
+            &mut self.weights[self.selected..self.selected + self.width];
+        for (w, x) in weights.iter_mut().zip(&self.inputs) {
+            *w = (*w + ((x * err) >> LEARNING_SHIFT))
+                .clamp(-MAX_WEIGHT, MAX_WEIGHT);
+        }
+        self.inputs.clear();
+    }
+}
+
+#[test]
+fn test_stretch_squash() {
+    for p in [1, 100, 1000, 2048, 3000, 4000, 4095] {
+        let diff = squash(stretch(p)) - p;
+        assert!(diff.abs() <= p / 16 + 1, "{} {}", p, diff);
+    }
+    assert_eq!(squash(0), 2047);
+    assert!(stretch(4095) > 2000 && stretch(1) < -2000);
+}
+
+#[test]
+fn test_mixer_learns_the_better_model() {
+    // The first input always predicts the bit, and the second input is noise.
+    let mut mixer = LogisticMixer::new(2, 1);
+    let mut last = 0;
+    for i in 0..2000 {
+        let bit = i % 3 == 0;
+        mixer.add(if bit { 60000 } else { 5000 });
+        mixer.add(if i % 2 == 0 { 60000 } else { 5000 });
+        let p = mixer.mix(0);
+        if i > 1900 {
+            last += (bit == (p > 32768)) as usize;
+        }
+        mixer.update(bit);
+    }
+    assert_eq!(last, 99);
+}
@@ -16,4 +16,5 @@ pub trait Model {
 
 pub mod bitwise;
 pub mod dmc;
+pub mod logistic;
 pub mod mixer;
@@ -1,10 +1,9 @@
 //! This is a simple encoder that serializes the input and does not change it.
 
-use crate::utils::number_encoding::decode32;
-use crate::utils::number_encoding::encode32;
+use crate::utils::leb128;
 use crate::utils::signatures::{match_signature, NOP_ENC};
 use crate::Context;
-use crate::{Decoder, Encoder};
+use crate::{restore_on_error, Decoder, Encoder};
 pub struct NopEncoder<'a> {
     /// The uncompressed input.
     input: &'a [u8],
@@ -15,10 +14,10 @@ pub struct NopEncoder<'a> {
 impl<'a> NopEncoder<'a> {
     fn encode_impl(&mut self) -> usize {
         self.output.extend(NOP_ENC);
-        encode32(self.input.len() as u32, self.output);
+        let len = leb128::encode(self.input.len() as u64, self.output);
         self.output.extend(self.input);
         // Bytes written plus the signature.
-        NOP_ENC.len() + 4 + self.input.len()
+        NOP_ENC.len() + len + self.input.len()
     }
 }
 
@@ -27,24 +26,37 @@ pub struct NopDecoder<'a> {
     input: &'a [u8],
     /// The output stream.
     output: &'a mut Vec<u8>,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
 }
 
 impl<'a> NopDecoder<'a> {
     pub fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
-        Self { input, output }
+        Self {
+            input,
+            output,
+            read: 0,
+        }
     }
 
     fn decode_impl(&mut self) -> Option<(usize, usize)> {
-        let sig_len = NOP_ENC.len();
-        if !match_signature(self.input, &NOP_ENC) {
-            return None;
-        }
-        let (_, buff_len) = decode32(&self.input[sig_len..])?;
-        let start = sig_len + 4;
-        self.output
-            .extend(&self.input[start..start + buff_len as usize]);
-        Some((start + buff_len as usize, buff_len as usize))
+        let (read, payload) = decode_view(self.input)?;
+        self.output.extend(payload);
+        Some((read, payload.len()))
+    }
+}
+
+/// Return a view of the payload of the nop encoding at the start of 'input',
+/// without copying it. Returns the number of bytes read and the payload.
+pub fn decode_view(input: &[u8]) -> Option<(usize, &[u8])> {
+    let sig_len = NOP_ENC.len();
+    if !match_signature(input, &NOP_ENC) {
+        return None;
     }
+    let (read, len) = leb128::decode_len(&input[sig_len..])?;
+    let start = sig_len + read;
+    let payload = input.get(start..start.checked_add(len)?)?;
+    Some((start + len, payload))
 }
 
 impl<'a> Encoder<'a> for NopEncoder<'a> {
@@ -59,10 +71,15 @@ impl<'a> Encoder<'a> for NopEncoder<'a> {
 
 impl<'a> Decoder<'a> for NopDecoder<'a> {
     fn new(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
-        NopDecoder { input, output }
+        NopDecoder::new(input, output)
     }
 
     fn decode(&mut self) -> Option<(usize, usize)> {
-        self.decode_impl()
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
     }
+
+    decoder_state!();
 }
@@ -1,15 +1,664 @@
 //! The 'PagerEncoder' and 'PagerDecoder' are responsible for taking a stream of bytes and
 //! partitioning them into small blocks that are encoded and decoded individually.
 
+use crate::budget::Schedule;
+use crate::inspect::PageEntry;
+use crate::limits::{check_output, with_max_output};
+use crate::lz::global::find_global_matches;
+use crate::nop::decode_view;
+use crate::scratch::recycle_u8;
+use crate::utils::hash::{xxh32, xxh64, GEAR};
+use crate::utils::leb128;
 use crate::utils::signatures::{
-    match_signature, read32, write32, PAGER_SIG, START_PAGE_SIG,
+    match_signature, read32, write32, CHECKED_PAGE_SIG, CONST_PAGE_SIG,
+    DUP_PAGE_SIG, HOLE_SIG, PAGER_SIG, REF_SIG, RESET_TABLE_SIG,
+    SIZED_PAGER_SIG, SPARSE_PAGE_SIG, START_PAGE_SIG,
 };
-use crate::{Context, Decoder, Encoder};
+use crate::{restore_on_error, ChunkSizes, Context, Decoder, Encoder};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Instant;
 
 /// A callback for handling the encoding of each block.
 pub type EncodeHandlerTy = fn(input: &[u8], ctx: Context) -> Vec<u8>;
 /// A callback for handling the decoding of each block.
 pub type DecodeHandlerTy = fn(input: &[u8]) -> Option<(usize, Vec<u8>)>;
+/// A callback for handling the encoding of each block, which is passed the
+/// entropy table that the previous page left, and may replace it. See
+/// 'PagerEncoder::set_table_callback'.
+pub type TableEncodeHandlerTy =
+    fn(input: &[u8], ctx: Context, table: &mut Option<Vec<u32>>) -> Vec<u8>;
+/// A callback for handling the decoding of each block, which tracks the same
+/// table as 'TableEncodeHandlerTy'.
+pub type TableDecodeHandlerTy =
+    fn(input: &[u8], table: &mut Option<Vec<u32>>) -> Option<(usize, Vec<u8>)>;
+/// A callback for decoding the first 'limit' bytes of a block. Returns the
+/// size of the whole block and the decoded bytes.
+pub type PrefixHandlerTy =
+    fn(input: &[u8], limit: usize) -> Option<(usize, Vec<u8>)>;
+
+/// Return a mask with 'bits' set bits that are spread across the upper part of
+/// the word. The gear hash mixes the upper bits better than the lower bits.
+fn chunk_mask(bits: u32) -> u64 {
+    let bits = bits.clamp(1, 48);
+    ((1u64 << bits) - 1) << (60 - bits)
+}
+
+/// Find the end of the first content-defined chunk in 'input' using a
+/// FastCDC-style gear hash. The boundary depends only on the content near it,
+/// so inserting or removing bytes only changes the chunks around the edit.
+/// A stricter mask is used before the average size and a looser mask after it,
+/// which concentrates the chunk sizes around the average.
+/// Reference: FastCDC, Xia et al. USENIX ATC 2016.
+pub fn find_chunk_boundary(input: &[u8], sizes: ChunkSizes) -> usize {
+    let len = input.len();
+    if len <= sizes.min {
+        return len;
+    }
+    let end = len.min(sizes.max);
+    let normal = end.min(sizes.avg);
+    let avg_bits = usize::BITS - sizes.avg.max(2).leading_zeros() - 1;
+    let mask_small = chunk_mask(avg_bits + 1);
+    let mask_large = chunk_mask(avg_bits - 1);
+
+    let mut hash: u64 = 0;
+    let mut i = sizes.min;
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[input[i] as usize]);
+        if hash & mask_small == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < end {
+        hash = (hash << 1).wrapping_add(GEAR[input[i] as usize]);
+        if hash & mask_large == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    end
+}
+
+/// Split 'input' into content-defined chunks. See 'find_chunk_boundary'.
+pub fn split_content_defined(input: &[u8], sizes: ChunkSizes) -> Vec<&[u8]> {
+    assert!(
+        sizes.min <= sizes.avg && sizes.avg <= sizes.max && sizes.max > 0,
+        "Invalid chunk sizes"
+    );
+    let mut parts = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let boundary = find_chunk_boundary(rest, sizes);
+        parts.push(&rest[..boundary]);
+        rest = &rest[boundary..];
+    }
+    // Always emit at least one page.
+    if parts.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+/// Write the header of a paged stream with 'parts' pages into 'output'. This
+/// is used by streaming writers that emit the pages one by one. Returns the
+/// number of bytes written.
+pub fn write_header(parts: usize, output: &mut Vec<u8>) -> usize {
+    output.extend(PAGER_SIG);
+    PAGER_SIG.len() + leb128::encode(parts as u64, output)
+}
+
+/// The max log2 of the page size that the header of a paged stream saves.
+const MAX_PAGE_SIZE_LOG: u8 = 32;
+
+/// Write the header of a paged stream with 'parts' pages of up to 'page_size'
+/// bytes into 'output'. The page size must be a power of two. Decoders reject
+/// pages that are larger. Returns the number of bytes written.
+pub fn write_sized_header(
+    parts: usize,
+    page_size: usize,
+    output: &mut Vec<u8>,
+) -> usize {
+    assert!(page_size.is_power_of_two(), "Invalid page size");
+    let log = page_size.trailing_zeros() as u8;
+    assert!(log <= MAX_PAGE_SIZE_LOG, "Invalid page size");
+    output.extend(SIZED_PAGER_SIG);
+    output.push(log);
+    SIZED_PAGER_SIG.len() + 1 + leb128::encode(parts as u64, output)
+}
+
+/// Read the header of a paged stream. Returns the number of bytes read, the
+/// number of pages in the stream, and the max size of the pages if the header
+/// saves it. See 'write_sized_header'.
+pub fn read_sized_header(
+    input: &[u8],
+) -> Option<(usize, usize, Option<usize>)> {
+    if match_signature(input, &SIZED_PAGER_SIG) {
+        let log = *input.get(SIZED_PAGER_SIG.len())?;
+        if log > MAX_PAGE_SIZE_LOG {
+            return None;
+        }
+        let start = SIZED_PAGER_SIG.len() + 1;
+        let (read, parts) = leb128::decode_len(&input[start..])?;
+        return Some((start + read, parts, Some(1 << log)));
+    }
+    if !match_signature(input, &PAGER_SIG) {
+        return None;
+    }
+    let (read, parts) = leb128::decode_len(&input[PAGER_SIG.len()..])?;
+    Some((PAGER_SIG.len() + read, parts, None))
+}
+
+/// Read the header of a paged stream. Returns the number of bytes read and the
+/// number of pages in the stream.
+pub fn read_header(input: &[u8]) -> Option<(usize, usize)> {
+    read_sized_header(input).map(|(read, parts, _)| (read, parts))
+}
+
+/// Write the table of the reset points 'points', which holds the offset and
+/// the first page of each reset point in increasing order, into 'output'. The
+/// record is saved before the header of the paged stream, and starts with its
+/// signature and the size of its body, so readers that don't need it skip it.
+/// The body is the number of points, followed by the distance of each offset
+/// and page from the previous point. Returns the number of bytes written.
+pub fn write_reset_table(
+    points: &[(usize, usize)],
+    output: &mut Vec<u8>,
+) -> usize {
+    let mut body = Vec::new();
+    leb128::encode(points.len() as u64, &mut body);
+    let mut prev = (0, 0);
+    for &(offset, page) in points {
+        leb128::encode((offset - prev.0) as u64, &mut body);
+        leb128::encode((page - prev.1) as u64, &mut body);
+        prev = (offset, page);
+    }
+    let start = output.len();
+    output.extend(RESET_TABLE_SIG);
+    leb128::encode(body.len() as u64, output);
+    output.extend(body);
+    output.len() - start
+}
+
+/// Read the signature and the size of the reset table at the start of
+/// 'input'. Returns the size of the header of the record and the size of its
+/// body. See 'write_reset_table'.
+pub fn read_reset_table_header(input: &[u8]) -> Option<(usize, usize)> {
+    if !match_signature(input, &RESET_TABLE_SIG) {
+        return None;
+    }
+    let (read, len) = leb128::decode_len(&input[RESET_TABLE_SIG.len()..])?;
+    Some((RESET_TABLE_SIG.len() + read, len))
+}
+
+/// Read the reset table at the start of 'input'. Returns the size of the
+/// record and the offset and the first page of each reset point, or None if
+/// the record is invalid. See 'write_reset_table'.
+pub fn read_reset_table(input: &[u8]) -> Option<(usize, Vec<(usize, usize)>)> {
+    let (header, len) = read_reset_table_header(input)?;
+    let body = input.get(header..)?.get(..len)?;
+    let (mut cursor, count) = leb128::decode_len(body)?;
+    // Each point takes at least two bytes.
+    if count > len / 2 {
+        return None;
+    }
+    let mut points = Vec::with_capacity(count);
+    let mut prev = (0usize, 0usize);
+    for _ in 0..count {
+        let (read, offset) = leb128::decode_len(body.get(cursor..)?)?;
+        cursor += read;
+        let (read, page) = leb128::decode_len(body.get(cursor..)?)?;
+        cursor += read;
+        prev = (prev.0.checked_add(offset)?, prev.1.checked_add(page)?);
+        points.push(prev);
+    }
+    if cursor != len {
+        return None;
+    }
+    Some((header + len, points))
+}
+
+/// Return the ranges of the pages of 'size' bytes that 'range' is split into.
+/// If 'last' is set, the range ends the stream, and the last page may be
+/// empty, so that every stream has at least one page.
+pub(crate) fn fixed_pages(
+    range: Range<usize>,
+    size: usize,
+    last: bool,
+) -> impl ExactSizeIterator<Item = Range<usize>> {
+    assert!(size > 0, "Must set page size");
+    let count = match last {
+        true => 1 + range.len() / size,
+        false => range.len().div_ceil(size),
+    };
+    (0..count).map(move |i| {
+        let start = range.start + size * i;
+        start..(start + size).min(range.end)
+    })
+}
+
+/// Write the encoded page 'compressed' into 'output', and return the number of
+/// bytes written.
+pub fn write_page(compressed: &[u8], output: &mut Vec<u8>) -> usize {
+    output.extend(START_PAGE_SIG);
+    let len = leb128::encode(compressed.len() as u64, output);
+    output.extend(compressed);
+    START_PAGE_SIG.len() + len + compressed.len()
+}
+
+/// Read the header of a page record. Returns the size of the header and the
+/// length of the encoded page that follows it. See 'write_page'.
+pub fn read_page_header(input: &[u8]) -> Option<(usize, usize)> {
+    if !match_signature(input, &START_PAGE_SIG) {
+        return None;
+    }
+    let (read, len) = leb128::decode_len(&input[START_PAGE_SIG.len()..])?;
+    Some((START_PAGE_SIG.len() + read, len))
+}
+
+/// Return the value of the bytes in 'input' if all of the bytes are identical.
+/// Pages like this are common in disk images and sparse files.
+pub fn constant_value(input: &[u8]) -> Option<u8> {
+    let first = *input.first()?;
+    if input.iter().all(|x| *x == first) {
+        return Some(first);
+    }
+    None
+}
+
+/// Write a record for a page of 'len' bytes with the value 'val' into
+/// 'output', and return the number of bytes written.
+pub fn write_constant_page(val: u8, len: usize, output: &mut Vec<u8>) -> usize {
+    output.extend(CONST_PAGE_SIG);
+    output.push(val);
+    CONST_PAGE_SIG.len() + 1 + leb128::encode(len as u64, output)
+}
+
+/// Read a constant page record. Returns the size of the record, and the value
+/// and the length of the page. Pages that are larger than the output cap are
+/// rejected. See 'write_constant_page'.
+pub fn read_constant_page(input: &[u8]) -> Option<(usize, u8, usize)> {
+    if !match_signature(input, &CONST_PAGE_SIG) {
+        return None;
+    }
+    let val = *input.get(CONST_PAGE_SIG.len())?;
+    let start = CONST_PAGE_SIG.len() + 1;
+    let (read, len) = leb128::decode_len(&input[start..])?;
+    Some((start + read, val, check_output(len)?))
+}
+
+/// Zero runs of at least this size are saved as holes. This is the size of a
+/// filesystem block, which is the smallest hole that a sparse file can have.
+pub const MIN_HOLE_SIZE: usize = 1 << 12;
+
+/// Return the offset and the length of the zero runs of at least
+/// 'MIN_HOLE_SIZE' bytes in 'input'.
+pub fn find_holes(input: &[u8]) -> Vec<(usize, usize)> {
+    let mut holes = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] != 0 {
+            i += 1;
+            continue;
+        }
+        let rest = &input[i..];
+        let len = rest.iter().position(|x| *x != 0).unwrap_or(rest.len());
+        if len >= MIN_HOLE_SIZE {
+            holes.push((i, len));
+        }
+        i += len;
+    }
+    holes
+}
+
+/// Write a record for a hole of 'len' zero bytes into 'output', and return
+/// the number of bytes written.
+pub fn write_hole(len: usize, output: &mut Vec<u8>) -> usize {
+    output.extend(HOLE_SIG);
+    HOLE_SIG.len() + leb128::encode(len as u64, output)
+}
+
+/// Read a hole record. Returns the size of the record and the length of the
+/// hole. Holes that are larger than the output cap are rejected. See
+/// 'write_hole'.
+pub fn read_hole(input: &[u8]) -> Option<(usize, usize)> {
+    if !match_signature(input, &HOLE_SIG) {
+        return None;
+    }
+    let (read, len) = leb128::decode_len(&input[HOLE_SIG.len()..])?;
+    Some((HOLE_SIG.len() + read, check_output(len)?))
+}
+
+/// Write a record for a copy of 'len' bytes at 'offset' in the page with the
+/// index 'page' into 'output', and return the number of bytes written. The
+/// copy may continue into the pages that follow that page.
+pub fn write_ref(
+    page: usize,
+    offset: usize,
+    len: usize,
+    output: &mut Vec<u8>,
+) -> usize {
+    output.extend(REF_SIG);
+    let mut written = REF_SIG.len();
+    for val in [page, offset, len] {
+        written += leb128::encode(val as u64, output);
+    }
+    written
+}
+
+/// Read a reference record. Returns the size of the record, followed by the
+/// page index, the offset and the length of the copy. See 'write_ref'.
+pub fn read_ref(input: &[u8]) -> Option<(usize, usize, usize, usize)> {
+    if !match_signature(input, &REF_SIG) {
+        return None;
+    }
+    let mut cursor = REF_SIG.len();
+    let mut vals = [0; 3];
+    for val in vals.iter_mut() {
+        let (read, num) = leb128::decode_len(input.get(cursor..)?)?;
+        *val = num;
+        cursor += read;
+    }
+    let [page, offset, len] = vals;
+    Some((cursor, page, offset, check_output(len)?))
+}
+
+/// Write the header of a checked page record for the page 'page'. The header
+/// saves the checksum and the length of the page, and is followed by the
+/// record of the page. Returns the number of bytes written.
+pub fn write_checked_header(page: &[u8], output: &mut Vec<u8>) -> usize {
+    output.extend(CHECKED_PAGE_SIG);
+    write32(xxh32(page, 0), output);
+    CHECKED_PAGE_SIG.len() + 4 + leb128::encode(page.len() as u64, output)
+}
+
+/// Read the header of a checked page record. Returns the size of the header,
+/// the checksum and the length of the page. A checked header never wraps
+/// another checked header, so headers that do are rejected, and the readers of
+/// the record don't recurse without a bound.
+pub fn read_checked_header(input: &[u8]) -> Option<(usize, u32, usize)> {
+    if !match_signature(input, &CHECKED_PAGE_SIG) {
+        return None;
+    }
+    let start = CHECKED_PAGE_SIG.len();
+    let checksum = read32(input.get(start..)?)?;
+    let (read, len) = leb128::decode_len(input.get(start + 4..)?)?;
+    let header = start + 4 + read;
+    if match_signature(&input[header..], &CHECKED_PAGE_SIG) {
+        return None;
+    }
+    Some((header, checksum, check_output(len)?))
+}
+
+/// A part of a page that is saved without the callback.
+#[derive(Copy, Clone, Debug)]
+enum Gap {
+    /// A run of zeros.
+    Hole,
+    /// A copy of the bytes at an offset in an earlier page, by page index.
+    Ref(usize, usize),
+}
+
+/// Encode the page 'input' as a list of data segments and the records of the
+/// 'gaps', which are sorted by offset and don't overlap, and write it into
+/// 'output'. Each gap has an offset and a length. The record starts with the
+/// length of its body, so that streaming readers can load it in one step.
+/// Returns the number of bytes written.
+fn write_sparse_page(
+    input: &[u8],
+    gaps: &[(usize, usize, Gap)],
+    ctx: Context,
+    callback: &mut dyn FnMut(&[u8], Context) -> Vec<u8>,
+    output: &mut Vec<u8>,
+) -> usize {
+    let mut body = Vec::new();
+    let mut items = 0;
+    let mut start = 0;
+    let end = (input.len(), 0, Gap::Hole);
+    for &(offset, len, gap) in gaps.iter().chain([&end]) {
+        if offset > start {
+            let compressed = callback(&input[start..offset], ctx);
+            write_page(&compressed, &mut body);
+            recycle_u8(compressed);
+            items += 1;
+        }
+        if len > 0 {
+            match gap {
+                Gap::Hole => write_hole(len, &mut body),
+                Gap::Ref(page, src) => write_ref(page, src, len, &mut body),
+            };
+            items += 1;
+        }
+        start = offset + len;
+    }
+
+    let mut header = Vec::new();
+    leb128::encode(items as u64, &mut header);
+    output.extend(SPARSE_PAGE_SIG);
+    let len = leb128::encode((header.len() + body.len()) as u64, output);
+    output.extend(&header);
+    output.extend(&body);
+    SPARSE_PAGE_SIG.len() + len + header.len() + body.len()
+}
+
+/// Read the header of a sparse page record. Returns the size of the header
+/// and the length of the body that follows it. See 'write_sparse_page'.
+pub fn read_sparse_header(input: &[u8]) -> Option<(usize, usize)> {
+    if !match_signature(input, &SPARSE_PAGE_SIG) {
+        return None;
+    }
+    let (read, len) = leb128::decode_len(&input[SPARSE_PAGE_SIG.len()..])?;
+    Some((SPARSE_PAGE_SIG.len() + read, len))
+}
+
+/// Return the size of the page record at the start of 'input', without
+/// decoding the page. Returns None if the record header is invalid or if the
+/// record does not fit in the input.
+pub fn record_len(input: &[u8]) -> Option<usize> {
+    if let Some((read, _, _)) = read_checked_header(input) {
+        return Some(read + record_len(&input[read..])?);
+    }
+    if match_signature(input, &DUP_PAGE_SIG) {
+        let (read, _) = leb128::decode(&input[DUP_PAGE_SIG.len()..])?;
+        return Some(DUP_PAGE_SIG.len() + read);
+    }
+    if match_signature(input, &CONST_PAGE_SIG) {
+        let start = CONST_PAGE_SIG.len() + 1;
+        let (read, _) = leb128::decode(input.get(start..)?)?;
+        return Some(start + read);
+    }
+    let (read, length) =
+        read_sparse_header(input).or_else(|| read_page_header(input))?;
+    input.get(read..)?.get(..length)?;
+    Some(read + length)
+}
+
+/// Decode the body of a sparse page record with 'callback'. Returns the
+/// content of the page, with the holes filled with zeros. Pages with
+/// references to earlier pages are rejected, because the earlier pages are
+/// not known. See 'PagerDecoder'.
+pub fn decode_sparse_body(
+    body: &[u8],
+    callback: DecodeHandlerTy,
+) -> Option<Vec<u8>> {
+    decode_segments(body, callback, |_, _, _, _| None)
+}
+
+/// Return True if the body of a sparse page record refers to earlier pages (see
+/// 'Context::with_global_matching'). Decoders that don't keep the earlier pages,
+/// such as 'decode_sparse_body', can't decode these pages. The data segments
+/// of the body are not decoded.
+pub fn sparse_body_has_refs(body: &[u8]) -> bool {
+    let mut found = false;
+    let _ = decode_segments(
+        body,
+        |packet| Some((packet.len(), Vec::new())),
+        |_, _, _, _| {
+            found = true;
+            None
+        },
+    );
+    found
+}
+
+/// Return a view of the content of the page record 'record' if the page is
+/// stored without compression, without copying it. The checksum of checked
+/// pages is verified. Returns None if the page is compressed, if the record is
+/// invalid, or if bytes are left after it.
+pub fn page_view(record: &[u8]) -> Option<&[u8]> {
+    let (record, check) = match read_checked_header(record) {
+        Some((read, checksum, len)) => (&record[read..], Some((checksum, len))),
+        None => (record, None),
+    };
+    let (read, length) = read_page_header(record)?;
+    if record.get(read..)?.len() != length {
+        return None;
+    }
+    let (used, page) = decode_view(&record[read..])?;
+    if used != length {
+        return None;
+    }
+    if let Some((checksum, len)) = check {
+        if page.len() != len || xxh32(page, 0) != checksum {
+            return None;
+        }
+    }
+    Some(page)
+}
+
+/// Decode the page record 'record' on its own with 'callback'. Returns None if
+/// the record is invalid, if bytes are left after it, or if the page refers to
+/// earlier pages of the stream, such as deduplicated pages.
+pub fn decode_record(
+    record: &[u8],
+    callback: DecodeHandlerTy,
+) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut decoder = PagerDecoder::new(record, &mut output);
+    decoder.set_callback(callback);
+    decoder.parts = Some(1);
+    let _ = decoder.next_page()?;
+    if decoder.cursor != record.len() {
+        return None;
+    }
+    Some(output)
+}
+
+/// Decode the body of a sparse page record with 'callback'. The reference
+/// records are decoded with 'copy', which is called with the page index, the
+/// offset and the length of the copy, and appends the bytes to the page.
+fn decode_segments(
+    body: &[u8],
+    mut callback: impl FnMut(&[u8]) -> Option<(usize, Vec<u8>)>,
+    mut copy: impl FnMut(usize, usize, usize, &mut Vec<u8>) -> Option<()>,
+) -> Option<Vec<u8>> {
+    let (mut cursor, items) = leb128::decode_len(body)?;
+    let mut output = Vec::new();
+    for _ in 0..items {
+        if let Some((read, len)) = read_hole(&body[cursor..]) {
+            cursor += read;
+            output.resize(check_output(output.len().checked_add(len)?)?, 0);
+            continue;
+        }
+        if let Some((read, page, offset, len)) = read_ref(&body[cursor..]) {
+            cursor += read;
+            check_output(output.len().checked_add(len)?)?;
+            copy(page, offset, len, &mut output)?;
+            continue;
+        }
+        let (read, length) = read_page_header(&body[cursor..])?;
+        cursor += read;
+        let packet = body.get(cursor..)?.get(..length)?;
+        let (read, buff) = callback(packet)?;
+        if read != length {
+            return None;
+        }
+        cursor += length;
+        output.extend(&buff);
+        recycle_u8(buff);
+    }
+    if cursor != body.len() {
+        return None;
+    }
+    Some(output)
+}
+
+/// Encode the page 'input' with 'callback' and write the page record into
+/// 'output'. Pages with a single repeated byte, and pages with long zero runs
+/// if 'ctx.sparse_holes' is set, are saved with dedicated records. Returns the
+/// number of bytes written.
+pub fn encode_page(
+    input: &[u8],
+    ctx: Context,
+    mut callback: EncodeHandlerTy,
+    output: &mut Vec<u8>,
+) -> usize {
+    let sparse = ctx.sparse_holes;
+    let ctx = Context {
+        sparse_holes: false,
+        ..ctx
+    };
+    encode_page_with_refs(input, &[], sparse, ctx, &mut callback, output)
+}
+
+/// Encode the page 'input' like 'encode_page', and save the parts of the page
+/// in 'refs' as references to earlier pages. Each reference has an offset and
+/// a length in the page, followed by the index of the earlier page and the
+/// offset in that page. The long zero runs are saved as holes if 'sparse' is
+/// set.
+fn encode_page_with_refs(
+    input: &[u8],
+    refs: &[(usize, usize, usize, usize)],
+    sparse: bool,
+    ctx: Context,
+    callback: &mut dyn FnMut(&[u8], Context) -> Vec<u8>,
+    output: &mut Vec<u8>,
+) -> usize {
+    if ctx.page_checksums {
+        let written = write_checked_header(input, output);
+        let ctx = Context {
+            page_checksums: false,
+            ..ctx
+        };
+        return written
+            + encode_page_with_refs(
+                input, refs, sparse, ctx, callback, output,
+            );
+    }
+
+    // Don't run the pipeline on pages with a single repeated byte.
+    if let Some(val) = constant_value(input) {
+        return write_constant_page(val, input.len(), output);
+    }
+
+    let holes = match sparse {
+        true => find_holes(input),
+        false => Vec::new(),
+    };
+    let mut gaps: Vec<(usize, usize, Gap)> = holes
+        .into_iter()
+        .map(|(offset, len)| (offset, len, Gap::Hole))
+        .chain(refs.iter().map(|r| (r.0, r.1, Gap::Ref(r.2, r.3))))
+        .collect();
+    if !gaps.is_empty() {
+        // Drop the references that overlap holes.
+        gaps.sort_by_key(|gap| gap.0);
+        let mut end = 0;
+        gaps.retain(|gap| {
+            let keep = gap.0 >= end;
+            if keep {
+                end = gap.0 + gap.1;
+            }
+            keep
+        });
+        return write_sparse_page(input, &gaps, ctx, callback, output);
+    }
+
+    let compressed = callback(input, ctx);
+    let written = write_page(&compressed, output);
+    recycle_u8(compressed);
+    written
+}
 
 /// Splits the input stream into segments and encodes each one of them
 /// independently using the registered callback.
@@ -20,8 +669,25 @@ pub struct PagerEncoder<'a> {
     output: &'a mut Vec<u8>,
     /// A callback for encoding each block.
     callback: Option<EncodeHandlerTy>,
+    /// A callback for encoding each block with the table of the page before
+    /// it. See 'set_table_callback'.
+    table_callback: Option<TableEncodeHandlerTy>,
+    /// The entropy table that the next page may reuse.
+    table: Option<Vec<u32>>,
     /// Encoder context.
     ctx: Context,
+    /// Replace pages that were already emitted with a reference to the
+    /// earlier page.
+    dedup: bool,
+    /// Save the page size in the header of the stream.
+    sized: bool,
+    /// The offsets of the input where a page starts that does not depend on
+    /// the pages before it. See 'set_reset_points'.
+    resets: Vec<usize>,
+    /// Save the table of the reset points before the header of the stream.
+    reset_table: bool,
+    /// Records a description of each page. See 'set_page_log'.
+    log: Option<&'a mut Vec<PageEntry>>,
 }
 
 impl<'a> PagerEncoder<'a> {
@@ -30,44 +696,291 @@ impl<'a> PagerEncoder<'a> {
         self.callback = Some(callback)
     }
 
+    /// Register a callback for handling each block that may reuse the entropy
+    /// table of the page before it, instead of 'set_callback'. The first page
+    /// is passed 'table'. The pages are encoded in order, so 'PagerDecoder'
+    /// can track the same table. See 'take_table'.
+    pub fn set_table_callback(
+        &mut self,
+        callback: TableEncodeHandlerTy,
+        table: Option<Vec<u32>>,
+    ) {
+        self.table_callback = Some(callback);
+        self.table = table;
+    }
+
+    /// Return the table that the last encoded page left for the page after it.
+    /// See 'set_table_callback'.
+    pub fn take_table(&mut self) -> Option<Vec<u32>> {
+        self.table.take()
+    }
+
     /// Sets the size of each page in the stream.
     pub fn set_page_size(&mut self, new_size: usize) {
         self.ctx.block_size = new_size
     }
 
+    /// Save the page size in the header of the stream, so that decoders
+    /// reject larger pages. The page size must be a power of two. Streams that
+    /// are split on content-defined boundaries don't save it.
+    pub fn set_sized_header(&mut self, enabled: bool) {
+        self.sized = enabled;
+    }
+
+    /// Split the stream on content-defined boundaries, with pages in the
+    /// range 'min'..='max' bytes and an average size of about 'avg' bytes.
+    pub fn set_chunking(&mut self, min: usize, avg: usize, max: usize) {
+        self.ctx.chunking = Some(ChunkSizes { min, avg, max });
+    }
+
+    /// Save the parts of pages that repeat earlier parts of the input as
+    /// references to the earlier pages. See 'lz::global'.
+    pub fn set_global_matching(&mut self, enabled: bool) {
+        self.ctx.global_matching = enabled;
+    }
+
+    /// Emit a short reference record instead of re-encoding pages that are
+    /// identical to a page that was already emitted. This works best together
+    /// with content-defined chunking (see 'set_chunking').
+    pub fn set_deduplication(&mut self, enabled: bool) {
+        self.dedup = enabled;
+    }
+
+    /// Start a new page at each offset of 'points', and don't let the pages
+    /// that follow an offset refer to the pages before it, with duplicate
+    /// pages or with global matches. The stream can then be decoded from any
+    /// of the offsets, by skipping the pages before it. Offsets outside of the
+    /// input are ignored.
+    pub fn set_reset_points(&mut self, points: &[usize]) {
+        let len = self.input.len();
+        self.resets = points.iter().copied().filter(|p| *p < len).collect();
+        self.resets.retain(|p| *p > 0);
+        self.resets.sort_unstable();
+        self.resets.dedup();
+    }
+
+    /// Save the table of the reset points before the header of the stream,
+    /// if the stream has reset points. See 'write_reset_table'.
+    pub fn set_reset_table(&mut self, enabled: bool) {
+        self.reset_table = enabled;
+    }
+
+    /// Append a description of each page that is encoded to 'log'.
+    pub fn set_page_log(&mut self, log: &'a mut Vec<PageEntry>) {
+        self.log = Some(log);
+    }
+
+    /// Return the offset and the index of the first page of each reset point.
+    /// See 'set_reset_points'.
+    pub fn reset_pages(&self) -> Vec<(usize, usize)> {
+        self.locate_resets(&self.split())
+    }
+
+    /// Return the offset and the index of the page in 'parts' that starts at
+    /// each reset point.
+    fn locate_resets(&self, parts: &[&[u8]]) -> Vec<(usize, usize)> {
+        let mut pages = Vec::new();
+        let mut offset = 0;
+        for (idx, part) in parts.iter().enumerate() {
+            if self.resets.binary_search(&offset).is_ok() {
+                pages.push((offset, idx));
+            }
+            offset += part.len();
+        }
+        pages
+    }
+
+    /// Split the input into pages, with a new page at each reset point.
+    fn split(&self) -> Vec<&'a [u8]> {
+        let mut parts = Vec::new();
+        let mut start = 0;
+        for end in self.resets.iter().copied().chain([self.input.len()]) {
+            let last = end == self.input.len();
+            match self.ctx.chunking {
+                Some(sizes) => parts.extend(split_content_defined(
+                    &self.input[start..end],
+                    sizes,
+                )),
+                None => parts.extend(self.split_fixed(start..end, last)),
+            }
+            start = end;
+        }
+        parts
+    }
+
+    /// Split the range 'range' of the input into pages of a fixed size. The
+    /// last page of the stream may be empty.
+    fn split_fixed(&self, range: Range<usize>, last: bool) -> Vec<&'a [u8]> {
+        fixed_pages(range, self.ctx.block_size, last)
+            .map(|page| &self.input[page])
+            .collect()
+    }
+
+    /// Find the parts of the pages 'parts' that repeat earlier pages. Returns
+    /// the references of each page. See 'encode_page_with_refs'.
+    fn find_refs(
+        &self,
+        parts: &[&[u8]],
+    ) -> Vec<Vec<(usize, usize, usize, usize)>> {
+        let mut pages: Vec<Range<usize>> = Vec::with_capacity(parts.len());
+        let mut start = 0;
+        for part in parts {
+            pages.push(start..start + part.len());
+            start += part.len();
+        }
+
+        let mut refs = vec![Vec::new(); parts.len()];
+        for mat in find_global_matches(self.input, &pages) {
+            // Matches don't refer to the bytes before a reset point.
+            let reset = self.resets.partition_point(|p| *p <= mat.pos);
+            if reset > 0 && mat.src < self.resets[reset - 1] {
+                continue;
+            }
+            let idx = pages.partition_point(|page| page.end <= mat.pos);
+            let src = pages.partition_point(|page| page.end <= mat.src);
+            let offset = mat.pos - pages[idx].start;
+            let src_offset = mat.src - pages[src].start;
+            refs[idx].push((offset, mat.len, src, src_offset));
+        }
+        refs
+    }
+
     /// Perform the encoding.
     fn encode_impl(&mut self) -> usize {
-        let mut parts: Vec<&'a [u8]> = Vec::new();
-        assert!(self.ctx.block_size > 0, "Must set page size");
+        let parts = self.split();
+        let table = self.locate_resets(&parts);
+        let resets: Vec<usize> = table.iter().map(|(_, idx)| *idx).collect();
 
-        // Push the parts to process:
-        for i in 0..(1 + self.input.len() / self.ctx.block_size) {
-            let start = self.ctx.block_size * i;
-            let end = (self.ctx.block_size * (i + 1)).min(self.input.len());
-            parts.push(&self.input[start..end]);
-        }
+        let (plain, shared) = (self.callback, self.table_callback);
+        let mut entropy_table = self.table.take();
+        let mut callback = |input: &[u8], ctx: Context| match shared {
+            Some(shared) => shared(input, ctx, &mut entropy_table),
+            None => plain.unwrap()(input, ctx),
+        };
 
-        let callback = self.callback.unwrap();
+        let mut written = 0;
+        if self.reset_table && !table.is_empty() {
+            written += write_reset_table(&table, self.output);
+        }
 
         // Write the signature and the number of parts.
-        self.output.extend(PAGER_SIG);
-        write32(parts.len() as u32, self.output);
-        let mut written = PAGER_SIG.len() + 4;
+        written += if self.sized && self.ctx.chunking.is_none() {
+            write_sized_header(parts.len(), self.ctx.block_size, self.output)
+        } else {
+            write_header(parts.len(), self.output)
+        };
+
+        // Maps the digest of the content of a page to the first page index.
+        let mut digests: HashMap<u64, usize> = HashMap::new();
+
+        // Lower the level of the remaining pages if the encoder falls behind
+        // its time budget. The pages are encoded without a budget, so that
+        // nested pagers don't keep schedules of their own.
+        let mut schedule = self.ctx.time_budget.map(|budget| {
+            Schedule::new(self.input.len(), budget, self.ctx.level)
+        });
+        let mut ctx = self.ctx;
+        ctx.time_budget = None;
+
+        // The pages are split at the top level only. The nested pagers of the
+        // blocks keep fixed pages and their sized header.
+        ctx.chunking = None;
+
+        // Find the repetitions across the pages before the pages are split.
+        let refs = match self.ctx.global_matching {
+            true => self.find_refs(&parts),
+            false => vec![Vec::new(); parts.len()],
+        };
+        ctx.global_matching = false;
+
+        // Only the pages of the top-level pager are split on holes.
+        let sparse = ctx.sparse_holes;
+        ctx.sparse_holes = false;
 
         // Compress each one of the pages using the pipeline.
-        for part in parts {
-            self.output.extend(START_PAGE_SIG);
-            let compressed = callback(part, self.ctx);
-            self.output.extend((compressed.len() as u32).to_be_bytes());
-            self.output.extend(compressed.iter());
-            written += START_PAGE_SIG.len() + 4 + compressed.len();
+        let mut offset = 0;
+        for (idx, part) in parts.iter().enumerate() {
+            // Pages after a reset point don't duplicate the pages before it.
+            if resets.contains(&idx) {
+                digests.clear();
+            }
+            let (record, start) = (self.output.len(), Instant::now());
+            let mut dup = None;
+            if self.dedup {
+                let first = *digests.entry(xxh64(part, 0)).or_insert(idx);
+                // Check the content, in case of a hash collision.
+                if first != idx && parts[first] == *part {
+                    dup = Some(first);
+                }
+            }
+
+            if let Some(first) = dup {
+                if ctx.page_checksums {
+                    written += write_checked_header(part, self.output);
+                }
+                self.output.extend(DUP_PAGE_SIG);
+                written += DUP_PAGE_SIG.len();
+                written += leb128::encode(first as u64, self.output);
+            } else {
+                written += encode_page_with_refs(
+                    part,
+                    &refs[idx],
+                    sparse,
+                    ctx,
+                    &mut callback,
+                    self.output,
+                );
+            }
+            if let Some(log) = self.log.as_deref_mut() {
+                let record = &self.output[record..];
+                let (len, time) = (part.len(), start.elapsed());
+                log.push(PageEntry::new(idx, offset, len, record, ctx, time));
+            }
+            offset += part.len();
+            if dup.is_none() {
+                if let Some(schedule) = schedule.as_mut() {
+                    ctx.level = schedule.page_done(part.len());
+                }
+            }
         }
 
+        self.table = entropy_table;
         written
     }
 }
 
-/// Decodes a stream that was partitioned into multiple pages.
+/// Return the range of the output that holds the 'len' bytes at 'offset' in
+/// the page 'idx' of the decoded 'pages'. The range may continue into the
+/// pages that follow, if all of them were decoded. Returns None if the bytes
+/// were not decoded.
+fn locate_ref(
+    pages: &[Option<(usize, usize)>],
+    idx: usize,
+    offset: usize,
+    len: usize,
+) -> Option<Range<usize>> {
+    let (start, _) = (*pages.get(idx)?)?;
+    let range = start.checked_add(offset)?
+        ..start.checked_add(offset.checked_add(len)?)?;
+    let mut end = start;
+    for page in &pages[idx..] {
+        let (page_start, page_len) = (*page)?;
+        if page_start != end {
+            return None;
+        }
+        end += page_len;
+        if end >= range.end {
+            return Some(range);
+        }
+    }
+    None
+}
+
+/// Decodes a stream that was partitioned into multiple pages. The stream can
+/// be decoded in one step with 'decode', or one page at a time with
+/// 'next_page'. Pages that fail to decode can be skipped with 'skip_page', to
+/// recover the pages that follow them. Corrupt pages are only detected if they
+/// fail to decode, for example when the callback verifies a checksum.
 pub struct PagerDecoder<'a> {
     /// The uncompressed input.
     input: &'a [u8],
@@ -75,6 +988,26 @@ pub struct PagerDecoder<'a> {
     output: &'a mut Vec<u8>,
     /// A callback for handling the decoding of each block.
     callback: Option<DecodeHandlerTy>,
+    /// A callback for decoding each block with the table of the page before
+    /// it. See 'set_table_callback'.
+    table_callback: Option<TableDecodeHandlerTy>,
+    /// The entropy table that the next page may reuse.
+    table: Option<Vec<u32>>,
+    /// The offset of the next page record in the input.
+    cursor: usize,
+    /// The number of pages that are left, after the header was read.
+    parts: Option<usize>,
+    /// The location of each decoded page in the output stream. Pages that
+    /// were skipped have no location.
+    pages: Vec<Option<(usize, usize)>>,
+    /// Decoding stops when the output reaches this size. See 'set_limit'.
+    limit: usize,
+    /// A callback for decoding the page that crosses the limit.
+    prefix: Option<PrefixHandlerTy>,
+    /// The max size of a decoded page, if the header saves it.
+    max_page: usize,
+    /// The number of input bytes that the last call to 'decode' consumed.
+    read: usize,
 }
 
 impl<'a> PagerDecoder<'a> {
@@ -83,38 +1016,204 @@ impl<'a> PagerDecoder<'a> {
         self.callback = Some(callback)
     }
 
-    /// Decode the input parameter. Returns the number of bytes consumed and the
-    /// number of bytes written if the operation succeeded.
-    fn decode_impl(&mut self) -> Option<(usize, usize)> {
-        let callback = self.callback.unwrap();
-        if !match_signature(self.input, &PAGER_SIG) {
+    /// Sets the callback for decoding each block with the table of the page
+    /// before it, instead of 'set_callback'. The first page is passed
+    /// 'table'. See 'PagerEncoder::set_table_callback'.
+    pub fn set_table_callback(
+        &mut self,
+        callback: TableDecodeHandlerTy,
+        table: Option<Vec<u32>>,
+    ) {
+        self.table_callback = Some(callback);
+        self.table = table;
+    }
+
+    /// Return the table that the last decoded page left for the page after
+    /// it. See 'set_table_callback'.
+    pub fn take_table(&mut self) -> Option<Vec<u32>> {
+        self.table.take()
+    }
+
+    /// Stop decoding when the output reaches 'limit' bytes. The plain pages
+    /// are decoded with 'callback', which can stop inside the block, so the
+    /// output may be a little longer than the limit. The pages that are cut
+    /// by the limit are not verified against their checksums.
+    pub fn set_limit(&mut self, limit: usize, callback: PrefixHandlerTy) {
+        self.limit = limit;
+        self.prefix = Some(callback);
+    }
+
+    /// Return the number of pages that are left in the stream, or None if the
+    /// header of the stream is invalid.
+    pub fn pages_left(&mut self) -> Option<usize> {
+        if self.parts.is_none() {
+            let (read, parts, page_size) = read_sized_header(self.input)?;
+            self.cursor = read;
+            self.parts = Some(parts);
+            self.max_page = page_size.unwrap_or(usize::MAX);
+        }
+        self.parts
+    }
+
+    /// Decode the next page into the output, and return the number of bytes
+    /// written. Returns None if there are no pages left or if the page is
+    /// invalid. Invalid pages are not consumed, and can be skipped with
+    /// 'skip_page'.
+    pub fn next_page(&mut self) -> Option<usize> {
+        let parts = self.pages_left()?;
+        if parts == 0 {
+            return None;
+        }
+        // The records of the page may not expand beyond the page size that
+        // the header declares.
+        let start = self.output.len();
+        let max_page = self.max_page;
+        let (read, written) =
+            with_max_output(max_page, || self.decode_page(self.cursor))?;
+        if written > self.max_page {
+            self.output.truncate(start);
+            self.pages.pop();
             return None;
         }
-        let mut cursor = PAGER_SIG.len();
-        let parts = read32(&self.input[cursor..])?;
-        cursor += 4;
+        self.cursor += read;
+        self.parts = Some(parts - 1);
+        Some(written)
+    }
 
-        let mut written = 0;
-        for _ in 0..parts {
-            // Read the part signature.
-            if !match_signature(&self.input[cursor..], &START_PAGE_SIG) {
+    /// Skip the next page without decoding it, and return the size of its
+    /// record. Returns None if there are no pages left or if the header of the
+    /// record is invalid, because the next page can't be located.
+    pub fn skip_page(&mut self) -> Option<usize> {
+        let parts = self.pages_left()?;
+        if parts == 0 {
+            return None;
+        }
+        let len = record_len(&self.input[self.cursor..])?;
+        self.cursor += len;
+        self.parts = Some(parts - 1);
+        self.pages.push(None);
+        Some(len)
+    }
+
+    /// Return the length of the next page if its record saves it, without
+    /// decoding the page. Returns None if there are no pages left or if the
+    /// length is not known.
+    pub fn next_page_len(&mut self) -> Option<usize> {
+        if self.pages_left()? == 0 {
+            return None;
+        }
+        let input = &self.input[self.cursor..];
+        if let Some((_, _, len)) = read_checked_header(input) {
+            return Some(len);
+        }
+        if match_signature(input, &DUP_PAGE_SIG) {
+            let (_, idx) = leb128::decode_len(&input[DUP_PAGE_SIG.len()..])?;
+            return self.pages.get(idx)?.map(|(_, len)| len);
+        }
+        read_constant_page(input).map(|(_, _, len)| len)
+    }
+
+    /// Skip the next page like 'skip_page', and write 'len' zeros in its
+    /// place, so the pages that follow keep their offsets in the output.
+    /// References to the skipped page are still rejected.
+    pub fn fill_page(&mut self, len: usize) -> Option<usize> {
+        let start = self.output.len();
+        check_output(start.checked_add(len)?)?;
+        let read = self.skip_page()?;
+        self.output.resize(start + len, 0);
+        Some(read)
+    }
+
+    /// Decode the page record at the offset 'at' into the output. Returns the
+    /// size of the record and the number of bytes written. The output is not
+    /// modified if the page is invalid.
+    fn decode_page(&mut self, at: usize) -> Option<(usize, usize)> {
+        let input = &self.input[at..];
+        let start = self.output.len();
+
+        // Check the pages that are saved with a checksum.
+        if let Some((read, checksum, len)) = read_checked_header(input) {
+            let (used, written) = self.decode_page(at + read)?;
+            let cut = written < len && self.output.len() >= self.limit;
+            if !cut
+                && (written != len
+                    || xxh32(&self.output[start..], 0) != checksum)
+            {
+                self.output.truncate(start);
+                self.pages.pop();
                 return None;
             }
-            cursor += START_PAGE_SIG.len();
+            return Some((read + used, written));
+        }
+
+        // Handle pages that are duplicates of earlier pages.
+        if match_signature(input, &DUP_PAGE_SIG) {
+            let (read, idx) = leb128::decode_len(&input[DUP_PAGE_SIG.len()..])?;
+            let (from, len) = (*self.pages.get(idx)?)?;
+            self.output.extend_from_within(from..from + len);
+            self.pages.push(Some((start, len)));
+            return Some((DUP_PAGE_SIG.len() + read, len));
+        }
 
-            // Read the part length.
-            let length = read32(&self.input[cursor..])? as usize;
-            cursor += 4;
+        // Handle pages with a single repeated byte.
+        if let Some((read, val, len)) = read_constant_page(input) {
+            self.output.resize(start + len, val);
+            self.pages.push(Some((start, len)));
+            return Some((read, len));
+        }
+
+        let (plain, shared) = (self.callback, self.table_callback);
+        let table = &mut self.table;
+        let mut callback = |packet: &[u8]| match shared {
+            Some(shared) => shared(packet, table),
+            None => plain.unwrap()(packet),
+        };
+        let (read, buff) = if let Some((read, length)) =
+            read_sparse_header(input)
+        {
+            // Handle pages with long zero runs and references to earlier
+            // pages.
+            let body = input.get(read..)?.get(..length)?;
+            let (pages, output) = (&self.pages, &*self.output);
+            let page =
+                decode_segments(body, &mut callback, |idx, at, len, page| {
+                    let range = locate_ref(pages, idx, at, len)?;
+                    page.extend_from_slice(&output[range]);
+                    Some(())
+                })?;
+            (read + length, page)
+        } else {
+            // Read the part signature and length.
+            let (read, length) = read_page_header(input)?;
+            let packet = input.get(read..)?.get(..length)?;
+            let remaining = self.limit.saturating_sub(start);
+            let (used, buff) = match self.prefix {
+                Some(prefix) => prefix(packet, remaining)?,
+                None => callback(packet)?,
+            };
+            // The packet must be consumed exactly.
+            if used != length {
+                recycle_u8(buff);
+                return None;
+            }
+            (read + length, buff)
+        };
 
-            let packet = &self.input[cursor..cursor + length as usize];
-            let (read, buff) = callback(packet)?;
-            debug_assert_eq!(read, length, "Invalid packet?");
+        let written = buff.len();
+        self.pages.push(Some((start, written)));
+        self.output.extend(&buff);
+        recycle_u8(buff);
+        Some((read, written))
+    }
 
-            cursor += length;
-            written += buff.len();
-            self.output.extend(&buff);
+    /// Decode the input parameter. Returns the number of bytes consumed and the
+    /// number of bytes written if the operation succeeded.
+    fn decode_impl(&mut self) -> Option<(usize, usize)> {
+        let mut written = 0;
+        while self.pages_left()? > 0 && self.output.len() < self.limit {
+            written += self.next_page()?;
         }
-        Some((cursor, written))
+        Some((self.cursor, written))
     }
 }
 
@@ -124,7 +1223,14 @@ impl<'a> Encoder<'a> for PagerEncoder<'a> {
             input,
             output,
             callback: None,
+            table_callback: None,
+            table: None,
             ctx,
+            dedup: false,
+            sized: false,
+            resets: Vec::new(),
+            reset_table: false,
+            log: None,
         }
     }
 
@@ -139,10 +1245,24 @@ impl<'a> Decoder<'a> for PagerDecoder<'a> {
             input,
             output,
             callback: None,
+            table_callback: None,
+            table: None,
+            cursor: 0,
+            parts: None,
+            pages: Vec::new(),
+            limit: usize::MAX,
+            prefix: None,
+            max_page: usize::MAX,
+            read: 0,
         }
     }
 
     fn decode(&mut self) -> Option<(usize, usize)> {
-        self.decode_impl()
+        let start = self.output.len();
+        let res = self.decode_impl();
+        self.read = res.map_or(0, |(read, _)| read);
+        restore_on_error(self.output, start, res)
     }
+
+    decoder_state!();
 }
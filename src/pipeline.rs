@@ -0,0 +1,387 @@
+//! Describes the transforms that are applied to a block before it is encoded,
+//! and the codec that encodes the result. The description is saved in the
+//! header of the block, so the decoder inverts the same transforms in reverse
+//! order, without knowing how the block was encoded. The header is a single
+//! byte with the number of stages and the codec, followed by one byte for
+//! each stage, with the kind of the transform and its parameter. The side data
+//! of the stages, such as the run tokens of 'Transform::Rle', follows the
+//! header. The block codec encodes the result as an inner block, which may not
+//! be a pipeline block or an RLE block itself, so blocks nest one level deep.
+
+use crate::block::{BlockDecoder, BlockEncoder};
+use crate::bwt;
+use crate::rle;
+use crate::utils::array_encoding::{
+    decode as decode_arr, encode as encode_arr,
+};
+use crate::utils::leb128;
+use crate::utils::signatures::{match_signature, PIPELINE_BLOCK_SIG};
+use crate::{Context, Decoder, Encoder};
+use std::fmt;
+
+/// The max number of transforms in a pipeline.
+pub const MAX_STAGES: usize = 4;
+
+/// The max parameter of a transform, which is saved in five bits.
+pub const MAX_PARAM: u8 = 31;
+
+/// A reversible transform of the bytes of a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Collapse the long runs of a single byte. See 'rle'.
+    Rle,
+    /// Replace each byte with its difference from the byte 'stride' bytes
+    /// before it, like the delta filter of xz. Useful for samples of audio and
+    /// images, where neighbors are close.
+    Delta(u8),
+    /// Group the bytes of the records of 'width' bytes by their position in
+    /// the record, so the matcher sees the columns of a table. The bytes after
+    /// the last full record are not moved.
+    Transpose(u8),
+    /// Sort the bytes by the context that follows them. See 'bwt'.
+    Bwt,
+}
+
+impl Transform {
+    /// Return the byte that describes the transform in the header.
+    fn to_byte(self) -> u8 {
+        match self {
+            Transform::Rle => 0,
+            Transform::Delta(stride) => 1 << 5 | stride,
+            Transform::Transpose(width) => 2 << 5 | width,
+            Transform::Bwt => 3 << 5,
+        }
+    }
+
+    /// Return the transform that is described by 'byte', or None if the
+    /// transform is unknown or its parameter is invalid.
+    fn from_byte(byte: u8) -> Option<Self> {
+        let param = byte & MAX_PARAM;
+        let transform = match byte >> 5 {
+            0 if param == 0 => Transform::Rle,
+            1 => Transform::Delta(param),
+            2 => Transform::Transpose(param),
+            3 if param == 0 => Transform::Bwt,
+            _ => return None,
+        };
+        transform.is_valid().then_some(transform)
+    }
+
+    /// Return true if the parameter of the transform is in range.
+    fn is_valid(self) -> bool {
+        match self {
+            Transform::Rle | Transform::Bwt => true,
+            Transform::Delta(stride) => (1..=MAX_PARAM).contains(&stride),
+            Transform::Transpose(width) => (2..=MAX_PARAM).contains(&width),
+        }
+    }
+
+    /// Apply the transform to 'input'. The side data that the inverse needs
+    /// is written to 'side'.
+    fn forward(self, input: &[u8], side: &mut Vec<u8>) -> Vec<u8> {
+        match self {
+            Transform::Rle => {
+                let (collapsed, runs) = rle::collapse(input)
+                    .unwrap_or_else(|| (input.to_vec(), vec![0]));
+                encode_arr(&runs, side);
+                collapsed
+            }
+            Transform::Delta(stride) => {
+                let stride = stride as usize;
+                let mut output = input.to_vec();
+                for i in (stride..input.len()).rev() {
+                    output[i] = input[i].wrapping_sub(input[i - stride]);
+                }
+                output
+            }
+            Transform::Transpose(width) => {
+                let width = width as usize;
+                let rows = input.len() / width;
+                let mut output = Vec::with_capacity(input.len());
+                for col in 0..width {
+                    output
+                        .extend((0..rows).map(|row| input[row * width + col]));
+                }
+                output.extend(&input[rows * width..]);
+                output
+            }
+            Transform::Bwt => {
+                let (output, primary) = bwt::forward(input);
+                leb128::encode(primary as u64, side);
+                output
+            }
+        }
+    }
+
+    /// Invert the transform of 'input' with the side data at the start of
+    /// 'side'. Returns None if the input is invalid.
+    fn inverse(self, input: Vec<u8>, side: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Transform::Rle => {
+                let mut runs = Vec::new();
+                let _ = decode_arr(side, &mut runs)?;
+                rle::expand(&input, &runs)
+            }
+            Transform::Delta(stride) => {
+                let mut output = input;
+                for i in stride as usize..output.len() {
+                    output[i] =
+                        output[i].wrapping_add(output[i - stride as usize]);
+                }
+                Some(output)
+            }
+            Transform::Transpose(width) => {
+                let width = width as usize;
+                let rows = input.len() / width;
+                let mut output = vec![0; input.len()];
+                for (i, val) in input[..rows * width].iter().enumerate() {
+                    output[(i % rows) * width + i / rows] = *val;
+                }
+                output[rows * width..].copy_from_slice(&input[rows * width..]);
+                Some(output)
+            }
+            Transform::Bwt => {
+                let (_, primary) = leb128::decode_len(side)?;
+                bwt::inverse(&input, primary)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Rle => write!(f, "rle"),
+            Transform::Delta(stride) => write!(f, "delta:{}", stride),
+            Transform::Transpose(width) => write!(f, "transpose:{}", width),
+            Transform::Bwt => write!(f, "bwt"),
+        }
+    }
+}
+
+/// The codec that encodes the result of the transforms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// The block encoder, with the level of the encoder context.
+    Block,
+    /// Save the bytes without compression.
+    Stored,
+}
+
+/// An ordered list of transforms and the codec that encodes their result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pipeline {
+    /// The transforms, in the order of the encoder.
+    stages: [Transform; MAX_STAGES],
+    /// The number of transforms in 'stages'.
+    len: usize,
+    /// The final codec.
+    codec: Codec,
+}
+
+impl Pipeline {
+    /// Create a pipeline without transforms, that encodes with 'codec'.
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            stages: [Transform::Rle; MAX_STAGES],
+            len: 0,
+            codec,
+        }
+    }
+
+    /// Add the transform 'transform' after the current transforms. Panics if
+    /// the pipeline is full or if the parameter of the transform is out of
+    /// range.
+    pub fn with(mut self, transform: Transform) -> Self {
+        assert!(self.len < MAX_STAGES, "Too many transforms");
+        assert!(transform.is_valid(), "Invalid transform {:?}", transform);
+        self.stages[self.len] = transform;
+        self.len += 1;
+        self
+    }
+
+    /// Return the transforms, in the order of the encoder.
+    pub fn stages(&self) -> &[Transform] {
+        &self.stages[..self.len]
+    }
+
+    /// Return the final codec.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Parse the list of transforms 'spec', such as "transpose:4,delta:1",
+    /// whose result is encoded with the block encoder. Returns None if the
+    /// list is invalid.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut pipeline = Self::new(Codec::Block);
+        for stage in spec.split(',') {
+            let (name, param) = match stage.split_once(':') {
+                Some((name, param)) => (name, Some(param.parse::<u8>().ok()?)),
+                None => (stage, None),
+            };
+            let transform = match (name, param) {
+                ("rle", None) => Transform::Rle,
+                ("bwt", None) => Transform::Bwt,
+                ("delta", Some(stride)) => Transform::Delta(stride),
+                ("transpose", Some(width)) => Transform::Transpose(width),
+                _ => return None,
+            };
+            if !transform.is_valid() || pipeline.len == MAX_STAGES {
+                return None;
+            }
+            pipeline = pipeline.with(transform);
+        }
+        Some(pipeline)
+    }
+
+    /// Encode 'input' with the pipeline and the encoder context 'ctx', and
+    /// write the block to 'output'. Returns the number of bytes written.
+    pub fn encode(
+        &self,
+        input: &[u8],
+        ctx: Context,
+        output: &mut Vec<u8>,
+    ) -> usize {
+        let start = output.len();
+        output.extend(PIPELINE_BLOCK_SIG);
+        self.write_description(output);
+
+        let mut data = input.to_vec();
+        for transform in self.stages() {
+            data = transform.forward(&data, output);
+        }
+        match self.codec {
+            Codec::Block => {
+                let ctx = Context {
+                    pipeline: None,
+                    ..ctx
+                };
+                let _ = BlockEncoder::new(&data, output, ctx).nested().encode();
+            }
+            Codec::Stored => {
+                encode_arr(&data, output);
+            }
+        }
+        output.len() - start
+    }
+
+    /// Write the description of the pipeline: the number of transforms and
+    /// the codec, followed by the transforms. Returns the number of bytes
+    /// written.
+    pub fn write_description(&self, output: &mut Vec<u8>) -> usize {
+        output.push((self.len as u8) << 4 | self.codec as u8);
+        output.extend(self.stages().iter().map(|t| t.to_byte()));
+        1 + self.len
+    }
+
+    /// Read a description that was written with 'write_description'. Returns
+    /// the size of the description and the pipeline, or None if the
+    /// description is invalid.
+    pub fn read_description(input: &[u8]) -> Option<(usize, Self)> {
+        let header = *input.first()?;
+        let codec = match header & 0xf {
+            0 => Codec::Block,
+            1 => Codec::Stored,
+            _ => return None,
+        };
+        let len = (header >> 4) as usize;
+        if len > MAX_STAGES {
+            return None;
+        }
+        let mut pipeline = Self::new(codec);
+        for byte in input.get(1..1 + len)? {
+            pipeline = pipeline.with(Transform::from_byte(*byte)?);
+        }
+        Some((1 + len, pipeline))
+    }
+
+    /// Read the description of the pipeline of the block 'input'. Returns the
+    /// size of the description and the pipeline, or None if the block is not
+    /// encoded with a pipeline.
+    pub fn read_header(input: &[u8]) -> Option<(usize, Self)> {
+        if !match_signature(input, &PIPELINE_BLOCK_SIG) {
+            return None;
+        }
+        let cursor = PIPELINE_BLOCK_SIG.len();
+        let (read, pipeline) = Self::read_description(&input[cursor..])?;
+        Some((cursor + read, pipeline))
+    }
+
+    /// Return the offset of the side data of each stage of the block 'input',
+    /// and the offset of the encoded data after them. 'cursor' is the offset
+    /// after the description of the pipeline.
+    fn side_offsets(
+        &self,
+        input: &[u8],
+        mut cursor: usize,
+    ) -> Option<(Vec<usize>, usize)> {
+        let mut sides = Vec::with_capacity(self.len);
+        for transform in self.stages() {
+            sides.push(cursor);
+            if matches!(transform, Transform::Rle | Transform::Bwt) {
+                let (read, len) = leb128::decode_len(input.get(cursor..)?)?;
+                // The run tokens are an array, and the primary index of the
+                // transform is a single number.
+                cursor += read;
+                if *transform == Transform::Rle {
+                    cursor = cursor.checked_add(len)?;
+                }
+            }
+        }
+        Some((sides, cursor))
+    }
+
+    /// Return the offset of the encoded data of the block 'input', after the
+    /// description and the side data, and the pipeline of the block.
+    pub fn payload_offset(input: &[u8]) -> Option<(usize, Self)> {
+        let (cursor, pipeline) = Self::read_header(input)?;
+        let (_, cursor) = pipeline.side_offsets(input, cursor)?;
+        input.get(cursor..)?;
+        Some((cursor, pipeline))
+    }
+
+    /// Decode the block 'input' that was encoded with 'encode'. Returns the
+    /// size of the block and the decoded bytes, or None if the block is
+    /// invalid.
+    pub fn decode(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        let (cursor, pipeline) = Self::read_header(input)?;
+
+        // The side data of the stages is saved in the order of the encoder,
+        // before the encoded data.
+        let (sides, mut cursor) = pipeline.side_offsets(input, cursor)?;
+        let rest = input.get(cursor..)?;
+        let mut data = Vec::new();
+        match pipeline.codec {
+            Codec::Block => {
+                let mut decoder = BlockDecoder::new(rest, &mut data).nested();
+                cursor += decoder.decode()?.0;
+            }
+            Codec::Stored => {
+                cursor += decode_arr(rest, &mut data)?;
+            }
+        }
+
+        // Invert the transforms in reverse order.
+        for (transform, side) in pipeline.stages().iter().zip(sides).rev() {
+            data = transform.inverse(data, &input[side..])?;
+        }
+        Some((cursor, data))
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.len == 0 {
+            write!(f, "none")?;
+        }
+        for (i, transform) in self.stages().iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(f, "{}{}", sep, transform)?;
+        }
+        if self.codec == Codec::Stored {
+            write!(f, " (stored)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Saves the choices of the automatic modes, so that batch jobs that compress
+//! many similar files don't repeat the exploratory work of each file. A
+//! profile keeps a hint for each file type (see 'registry'): the level that
+//! the automatic level selected, the filters of the pipeline, and the
+//! distribution of the bytes of the samples that the level was selected on.
+//! A later file of the same type reuses the hint if the distribution of its
+//! samples is close to the saved distribution, and runs the trials otherwise,
+//! which replaces the hint. The profile is saved to a small file between the
+//! runs, with a checksum that detects files that were partially written.
+
+use crate::full::ARITH_LEVEL;
+use crate::pipeline::Pipeline;
+use crate::registry::FileType;
+use crate::utils::hash::xxh32;
+use crate::utils::leb128;
+use crate::utils::signatures::PROFILE_SIG;
+use crate::utils::signatures::{match_signature, read32, write32};
+
+/// The sum of the counts of the saved histograms.
+const HIST_TOTAL: u32 = 1 << 12;
+
+/// The highest level that a hint may save. The automatic level selects one of
+/// the levels of blocks.
+const MAX_HINT_LEVEL: u8 = ARITH_LEVEL - 1;
+
+/// The max distance between the distributions of the samples and a hint for
+/// the hint to be reused, as a fraction of the bytes that would need to move
+/// to turn one distribution into the other.
+pub const MAX_DISTANCE: f64 = 0.1;
+
+/// The choices of the automatic modes for one file type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hint {
+    /// The type of the files, or None for files of an unknown type.
+    pub kind: Option<FileType>,
+    /// The level that the automatic level selected.
+    pub level: u8,
+    /// The filters that were selected for the type, if any.
+    pub pipeline: Option<Pipeline>,
+    /// The histogram of the bytes of the samples, normalized to 'HIST_TOTAL'.
+    hist: Vec<u32>,
+}
+
+/// Return the histogram of the bytes of 'samples', normalized to about
+/// 'HIST_TOTAL'.
+fn histogram(samples: &[&[u8]]) -> Vec<u32> {
+    let mut counts = vec![0u64; 256];
+    for sample in samples {
+        for byte in sample.iter() {
+            counts[*byte as usize] += 1;
+        }
+    }
+    let total = counts.iter().sum::<u64>().max(1);
+    counts
+        .iter()
+        .map(|count| (count * HIST_TOTAL as u64 / total) as u32)
+        .collect()
+}
+
+/// Return the distance between the histograms 'a' and 'b'. See
+/// 'MAX_DISTANCE'.
+fn distance(a: &[u32], b: &[u32]) -> f64 {
+    let diff: u64 = a.iter().zip(b).map(|(x, y)| x.abs_diff(*y) as u64).sum();
+    diff as f64 / (2 * HIST_TOTAL) as f64
+}
+
+impl Hint {
+    /// Return True if the distribution of the bytes of 'samples' is close to
+    /// the distribution of the hint.
+    pub fn matches(&self, samples: &[&[u8]]) -> bool {
+        distance(&self.hist, &histogram(samples)) <= MAX_DISTANCE
+    }
+}
+
+/// The hints of the file types that were compressed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TuningProfile {
+    hints: Vec<Hint>,
+}
+
+impl TuningProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the hints of the profile.
+    pub fn hints(&self) -> &[Hint] {
+        &self.hints
+    }
+
+    /// Return the hint of the files of the type 'kind' if it suits the
+    /// samples 'samples' of a file, or None if the automatic modes need to
+    /// run.
+    pub fn lookup(
+        &self,
+        kind: Option<FileType>,
+        samples: &[&[u8]],
+    ) -> Option<&Hint> {
+        self.hints
+            .iter()
+            .find(|hint| hint.kind == kind && hint.matches(samples))
+    }
+
+    /// Save the choices for the files of the type 'kind' that were made on
+    /// the samples 'samples'. Replaces the previous hint of the type. The
+    /// level must be one of the levels that the automatic level selects.
+    pub fn record(
+        &mut self,
+        kind: Option<FileType>,
+        level: u8,
+        pipeline: Option<Pipeline>,
+        samples: &[&[u8]],
+    ) {
+        assert!(level <= MAX_HINT_LEVEL, "Invalid level");
+        self.hints.retain(|hint| hint.kind != kind);
+        self.hints.push(Hint {
+            kind,
+            level,
+            pipeline,
+            hist: histogram(samples),
+        });
+    }
+
+    /// Serialize the profile.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend(PROFILE_SIG);
+        leb128::encode(self.hints.len() as u64, &mut output);
+        for hint in &self.hints {
+            let name = hint.kind.map(|kind| kind.to_string());
+            let name = name.unwrap_or_default();
+            leb128::encode(name.len() as u64, &mut output);
+            output.extend(name.bytes());
+            output.push(hint.level);
+            match hint.pipeline {
+                Some(pipeline) => {
+                    output.push(1);
+                    pipeline.write_description(&mut output);
+                }
+                None => output.push(0),
+            }
+            for count in &hint.hist {
+                leb128::encode(*count as u64, &mut output);
+            }
+        }
+        let checksum = xxh32(&output, 0);
+        write32(checksum, &mut output);
+        output
+    }
+
+    /// Deserialize a profile that was saved with 'encode'. Returns None if
+    /// the profile is invalid.
+    pub fn decode(input: &[u8]) -> Option<Self> {
+        if !match_signature(input, &PROFILE_SIG) {
+            return None;
+        }
+        let body = &input[..input.len().checked_sub(4)?];
+        if read32(&input[body.len()..])? != xxh32(body, 0) {
+            return None;
+        }
+
+        let mut cursor = PROFILE_SIG.len();
+        let (read, count) = leb128::decode_len(&body[cursor..])?;
+        cursor += read;
+        let mut profile = Self::new();
+        for _ in 0..count {
+            let (read, len) = leb128::decode_len(body.get(cursor..)?)?;
+            cursor += read;
+            let name = body.get(cursor..cursor.checked_add(len)?)?;
+            cursor += len;
+            let kind = match name.is_empty() {
+                true => None,
+                false => {
+                    Some(FileType::from_name(std::str::from_utf8(name).ok()?)?)
+                }
+            };
+            let level = *body.get(cursor)?;
+            if level > MAX_HINT_LEVEL {
+                return None;
+            }
+            let pipeline = match *body.get(cursor + 1)? {
+                0 => None,
+                1 => {
+                    let rest = body.get(cursor + 2..)?;
+                    let (read, pipeline) = Pipeline::read_description(rest)?;
+                    cursor += read;
+                    Some(pipeline)
+                }
+                _ => return None,
+            };
+            cursor += 2;
+            let mut hist = Vec::with_capacity(256);
+            for _ in 0..256 {
+                let (read, count) = leb128::decode(body.get(cursor..)?)?;
+                cursor += read;
+                if count > HIST_TOTAL as u64 {
+                    return None;
+                }
+                hist.push(count as u32);
+            }
+            profile.hints.push(Hint {
+                kind,
+                level,
+                pipeline,
+                hist,
+            });
+        }
+        if cursor != body.len() {
+            return None;
+        }
+        Some(profile)
+    }
+}
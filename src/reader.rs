@@ -0,0 +1,357 @@
+//! A reader that decompresses a stream of the full compressor while it is being
+//! read. Only one page is kept in memory, so large files can be processed line
+//! by line with the 'BufRead' interface, without decompressing them to disk.
+//! Readers with a max page size (see 'with_max_page') run in a bounded amount
+//! of memory, whatever lengths the stream declares. Streams with references to
+//! earlier pages (see 'Context::with_global_matching') or with duplicate pages
+//! (see 'PagerEncoder::set_deduplication') are not supported, because the
+//! earlier pages are not kept, and fail with an error of the kind
+//! 'ErrorKind::Unsupported'.
+
+use crate::full::FullDecoder;
+use crate::full::{decode_or_nop, decode_or_nop_prefix, max_page_len};
+use crate::limits::{check_output, with_max_output};
+use crate::metadata::MAX_METADATA_LEN;
+use crate::pager::{decode_sparse_body, sparse_body_has_refs};
+use crate::utils::hash::xxh32;
+use crate::utils::leb128;
+use crate::utils::signatures::{
+    read32, CHECKED_PAGE_SIG, CONST_PAGE_SIG, DUP_PAGE_SIG, FULL_SIG,
+    METADATA_SIG, PAGER_SIG, RESET_TABLE_SIG, SPARSE_PAGE_SIG, START_PAGE_SIG,
+    STORED_SIG,
+};
+use crate::Decoder;
+use std::io::{self, BufRead, Read};
+
+/// The size of the chunks that are read from streams that are not compressed.
+const STORED_CHUNK_SIZE: usize = 1 << 16;
+
+/// The part of the stream that the reader is processing.
+enum State {
+    /// The header was not read yet.
+    Header,
+    /// Reading a paged stream with the number of pages that are left.
+    Pages(usize),
+    /// Reading a stream that is not compressed with the number of bytes that
+    /// are left.
+    Stored(usize),
+    /// The whole stream was decoded.
+    Done,
+}
+
+/// Return an error that reports an invalid stream.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Return an error that reports a stream that refers to earlier pages.
+fn unsupported(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, msg)
+}
+
+/// Decompresses the full compressor stream that is read from 'inner'.
+pub struct DecompressBufReader<R: Read> {
+    /// The compressed stream.
+    inner: R,
+    /// The decoded content of the current page.
+    buffer: Vec<u8>,
+    /// The number of bytes in 'buffer' that were consumed.
+    pos: usize,
+    /// The decoding state.
+    state: State,
+    /// The max size of a decoded page, if the memory is bounded.
+    max_page: Option<usize>,
+}
+
+impl<R: Read> DecompressBufReader<R> {
+    /// Create a new reader that decompresses the stream in 'inner'.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            pos: 0,
+            state: State::Header,
+            max_page: None,
+        }
+    }
+
+    /// Bound the memory of the reader to about 'max_page' bytes of decoded
+    /// content and the encoding of one page. The matches of a page don't reach
+    /// outside of it, so the page also bounds the window of the decoder. Pages
+    /// that are larger, records that are larger than the encoding of such a
+    /// page (see 'max_page_len'), and streams that are not paged, which are
+    /// decoded in one step, are rejected. The records inside of a page are
+    /// checked against 'max_page' instead of the output cap of the thread (see
+    /// 'limits').
+    pub fn with_max_page(mut self, max_page: usize) -> Self {
+        self.max_page = Some(max_page);
+        self
+    }
+
+    /// Pass each part of the decoded stream to 'sink', without collecting the
+    /// stream, and return the number of bytes decoded. With 'with_max_page',
+    /// the whole stream is decoded in bounded memory.
+    pub fn decode_to(
+        &mut self,
+        mut sink: impl FnMut(&[u8]) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        let mut total = 0;
+        loop {
+            let len = {
+                let part = self.fill_buf()?;
+                if part.is_empty() {
+                    return Ok(total);
+                }
+                sink(part)?;
+                part.len()
+            };
+            self.consume(len);
+            total += len as u64;
+        }
+    }
+
+    /// Return the underlying reader and consume the decompressor.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read 'len' bytes from the compressed stream.
+    fn read_bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; len];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Return an error if the memory is bounded and a page of 'len' bytes is
+    /// larger than the max page size.
+    fn check_page(&self, len: usize) -> io::Result<()> {
+        match self.max_page {
+            Some(max_page) if len > max_page => {
+                Err(invalid("page exceeds the max page size"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the record of 'len' bytes that encodes a page, after checking that
+    /// it fits in the encoding of the largest page.
+    fn read_record(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        if let Some(max_page) = self.max_page {
+            if len > max_page_len(max_page) {
+                return Err(invalid("record exceeds the max page size"));
+            }
+        }
+        let mut record = Vec::new();
+        (&mut self.inner)
+            .take(len as u64)
+            .read_to_end(&mut record)?;
+        Ok(record)
+    }
+
+    /// Decode a page with 'decode', with the output cap of the thread lowered
+    /// to the max page size, if the memory is bounded.
+    fn decode_capped<T>(&self, decode: impl FnOnce() -> T) -> T {
+        with_max_output(self.max_page.unwrap_or(usize::MAX), decode)
+    }
+
+    /// Read a number that is encoded with LEB128 from the compressed stream.
+    fn read_number(&mut self) -> io::Result<usize> {
+        let mut bytes = Vec::new();
+        for _ in 0..leb128::MAX_LEN {
+            let byte = self.read_bytes(1)?[0];
+            bytes.push(byte);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        match leb128::decode_len(&bytes) {
+            Some((_, val)) => Ok(val),
+            None => Err(invalid("invalid length")),
+        }
+    }
+
+    /// Read the header of the stream and select the decoding state.
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = self.read_bytes(FULL_SIG.len())?;
+        if header != FULL_SIG {
+            return Err(invalid("not a compressed stream"));
+        }
+        let mut sig = self.read_bytes(STORED_SIG.len())?;
+
+        // Skip the metadata (see 'metadata').
+        if sig == METADATA_SIG {
+            let len = self.read_number()?;
+            if len > MAX_METADATA_LEN {
+                return Err(invalid("invalid metadata"));
+            }
+            let _ = self.read_bytes(len)?;
+            sig = self.read_bytes(STORED_SIG.len())?;
+        }
+
+        // Skip the table of the reset points (see 'write_reset_table').
+        if sig == RESET_TABLE_SIG {
+            let len = self.read_number()?;
+            let body = (&mut self.inner).take(len as u64);
+            if io::copy(&mut { body }, &mut io::sink())? != len as u64 {
+                return Err(invalid("invalid reset table"));
+            }
+            sig = self.read_bytes(STORED_SIG.len())?;
+        }
+        header.extend(sig);
+        if header.ends_with(&STORED_SIG) {
+            self.state = State::Stored(self.read_number()?);
+            return Ok(());
+        }
+
+        let rest = PAGER_SIG.len() - STORED_SIG.len();
+        header.extend(self.read_bytes(rest)?);
+        if header.ends_with(&PAGER_SIG) {
+            self.state = State::Pages(self.read_number()?);
+            return Ok(());
+        }
+
+        // Streams that are not paged are decoded in one step.
+        if self.max_page.is_some() {
+            return Err(invalid("the stream is not paged"));
+        }
+        self.inner.read_to_end(&mut header)?;
+        let mut decoder = FullDecoder::new(&header, &mut self.buffer);
+        match decoder.decode() {
+            Some((read, _)) if read == header.len() => {}
+            _ => return Err(invalid("invalid stream")),
+        }
+        self.state = State::Done;
+        Ok(())
+    }
+
+    /// Decode the next page into the buffer. Checked pages are verified
+    /// against the checksum and the length of their header (see
+    /// 'pager::write_checked_header').
+    fn read_page(&mut self) -> io::Result<()> {
+        let sig = self.read_bytes(START_PAGE_SIG.len())?;
+        if sig != CHECKED_PAGE_SIG {
+            return self.read_unchecked_page(&sig);
+        }
+        let checksum = read32(&self.read_bytes(4)?).unwrap();
+        let len = self.read_number()?;
+        self.check_page(len)?;
+        // A checked header never wraps another checked header.
+        let sig = self.read_bytes(START_PAGE_SIG.len())?;
+        if sig == CHECKED_PAGE_SIG {
+            return Err(invalid("invalid page"));
+        }
+        self.read_unchecked_page(&sig)?;
+        if self.buffer.len() != len || xxh32(&self.buffer, 0) != checksum {
+            return Err(invalid("page checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Decode the page record that starts with the signature 'sig' into the
+    /// buffer.
+    fn read_unchecked_page(&mut self, sig: &[u8]) -> io::Result<()> {
+        if sig == CONST_PAGE_SIG {
+            let val = self.read_bytes(1)?[0];
+            let len = self.read_number()?;
+            if check_output(len).is_none() {
+                return Err(invalid("page exceeds the output cap"));
+            }
+            self.check_page(len)?;
+            self.buffer.resize(len, val);
+            return Ok(());
+        }
+        if sig == SPARSE_PAGE_SIG {
+            let len = self.read_number()?;
+            let body = self.read_record(len)?;
+            let page =
+                self.decode_capped(|| decode_sparse_body(&body, decode_or_nop));
+            return match page {
+                Some(page) if body.len() == len => {
+                    self.check_page(page.len())?;
+                    self.buffer = page;
+                    Ok(())
+                }
+                None if sparse_body_has_refs(&body) => Err(unsupported(
+                    "references to earlier pages are not supported",
+                )),
+                _ => Err(invalid("invalid page")),
+            };
+        }
+        if sig == DUP_PAGE_SIG {
+            return Err(unsupported("duplicate pages are not supported"));
+        }
+        if sig != START_PAGE_SIG {
+            return Err(invalid("unsupported page record"));
+        }
+
+        let len = self.read_number()?;
+        let packet = self.read_record(len)?;
+        // Stop decoding pages that grow past the max page size.
+        let page = self.decode_capped(|| match self.max_page {
+            Some(max_page) => {
+                decode_or_nop_prefix(&packet, max_page.saturating_add(1))
+            }
+            None => decode_or_nop(&packet),
+        });
+        match page {
+            Some((read, page)) if read == len => {
+                self.check_page(page.len())?;
+                self.buffer = page;
+                Ok(())
+            }
+            _ => Err(invalid("invalid page")),
+        }
+    }
+
+    /// Decode the next part of the stream into the buffer. The buffer is left
+    /// empty at the end of the stream.
+    fn refill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+        if let State::Header = self.state {
+            self.read_header()?;
+        }
+
+        match self.state {
+            State::Pages(0) | State::Stored(0) => self.state = State::Done,
+            State::Pages(left) => {
+                self.read_page()?;
+                self.state = State::Pages(left - 1);
+            }
+            State::Stored(left) => {
+                let len = left.min(STORED_CHUNK_SIZE);
+                self.buffer = self.read_bytes(len)?;
+                self.state = State::Stored(left - len);
+            }
+            State::Header | State::Done => {}
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> BufRead for DecompressBufReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Skip empty pages until there is content or the stream ends.
+        while self.pos == self.buffer.len() {
+            if let State::Done = self.state {
+                break;
+            }
+            self.refill()?;
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buffer.len());
+    }
+}
+
+impl<R: Read> Read for DecompressBufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
@@ -0,0 +1,474 @@
+//! Detects inputs that are already in a known compressed format, such as gzip
+//! and zip, and recompresses their content with the full compressor. The
+//! deflate streams of gzip files and of zip entries are decoded, and the
+//! settings that reproduce each stream are found by encoding the content again
+//! with each level of zlib, until the encoding matches the stream bit-exactly.
+//! Streams that no level reproduces, such as the streams of other deflate
+//! encoders, are kept as is, but their stored blocks are still unwrapped. The
+//! rest of the input, the wrapper, is saved next to the decoded content, the
+//! payload, so the original file is restored by encoding the payload again and
+//! placing it back into the wrapper. Zstd and JPEG inputs are detected, but
+//! are not decoded. The CLI enables this with '--recompress'.
+
+use crate::full::{FullDecoder, FullEncoder};
+use crate::limits::check_output;
+use crate::utils::hash::xxh32;
+use crate::utils::leb128;
+use crate::utils::number_encoding::{decode16, decode32, Endian};
+use crate::utils::signatures::RECOMPRESS_SIG;
+use crate::utils::signatures::{match_signature, read32, write32};
+use crate::{Context, Decoder, Encoder};
+use flate2::{Compress, Compression, Decompress};
+use flate2::{FlushCompress, FlushDecompress, Status};
+use std::fmt;
+use std::ops::Range;
+
+/// The compressed formats that are detected by their magic numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zip,
+    Zstd,
+    Jpeg,
+}
+
+impl Format {
+    /// Return the id of the format in the recompressed stream.
+    fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Return the format with the id 'id'.
+    fn from_id(id: u8) -> Option<Self> {
+        [Format::Gzip, Format::Zip, Format::Zstd, Format::Jpeg]
+            .into_iter()
+            .find(|format| format.id() == id)
+    }
+
+    /// Return true if the content of the format can be decoded and restored.
+    pub fn is_supported(self) -> bool {
+        matches!(self, Format::Gzip | Format::Zip)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Gzip => "gzip",
+            Format::Zip => "zip",
+            Format::Zstd => "zstd",
+            Format::Jpeg => "jpeg",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Return the format of 'input', or None if it is not a known compressed
+/// format.
+pub fn detect_format(input: &[u8]) -> Option<Format> {
+    if match_signature(input, &[0x1f, 0x8b, 0x08]) {
+        return Some(Format::Gzip);
+    }
+    if match_signature(input, &[0x50, 0x4b, 0x03, 0x04]) {
+        return Some(Format::Zip);
+    }
+    if match_signature(input, &[0x28, 0xb5, 0x2f, 0xfd]) {
+        return Some(Format::Zstd);
+    }
+    if match_signature(input, &[0xff, 0xd8, 0xff]) {
+        return Some(Format::Jpeg);
+    }
+    None
+}
+
+/// The levels of zlib that are tried, with the common ones first.
+const ZLIB_LEVELS: [u8; 10] = [6, 9, 1, 5, 4, 3, 2, 7, 8, 0];
+
+/// The size of the chunks of the deflate encoder and decoder.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// The encoding of a part of the input that is saved as its content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// The part is stored without compression, and is its own content.
+    Stored,
+    /// The part is a raw deflate stream that zlib encodes at this level.
+    Deflate(u8),
+}
+
+/// A part of the input that is replaced by its content.
+struct Part {
+    /// The range of the part in the input.
+    range: Range<usize>,
+    /// The encoding of the part.
+    encoding: Encoding,
+    /// The decoded content of the part.
+    content: Vec<u8>,
+}
+
+/// Decode the raw deflate stream at the start of 'input'. Returns the size of
+/// the stream and its content, or None if the stream is invalid or truncated.
+fn inflate(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let mut decoder = Decompress::new(false);
+    let mut content = Vec::new();
+    loop {
+        content.reserve(CHUNK_SIZE);
+        check_output(content.capacity())?;
+        let before = (decoder.total_in(), decoder.total_out());
+        let rest = input.get(decoder.total_in() as usize..)?;
+        let status = decoder
+            .decompress_vec(rest, &mut content, FlushDecompress::None)
+            .ok()?;
+        if status == Status::StreamEnd {
+            return Some((decoder.total_in() as usize, content));
+        }
+        // The output has room, so a call without progress is stuck at the
+        // end of a truncated stream.
+        if (decoder.total_in(), decoder.total_out()) == before {
+            return None;
+        }
+    }
+}
+
+/// Encode 'content' as a raw deflate stream with zlib at 'level', and pass
+/// each chunk of the stream to 'sink', until it returns false. Returns true if
+/// the whole stream was passed.
+fn deflate_with(
+    content: &[u8],
+    level: u8,
+    mut sink: impl FnMut(&[u8]) -> bool,
+) -> bool {
+    let mut encoder = Compress::new(Compression::new(level as u32), false);
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        chunk.clear();
+        let rest = &content[encoder.total_in() as usize..];
+        let status = encoder
+            .compress_vec(rest, &mut chunk, FlushCompress::Finish)
+            .expect("Invalid deflate state");
+        if !sink(&chunk) {
+            return false;
+        }
+        if status == Status::StreamEnd {
+            return true;
+        }
+    }
+}
+
+/// Encode 'content' as a raw deflate stream with zlib at 'level'.
+fn deflate(content: &[u8], level: u8) -> Vec<u8> {
+    let mut stream = Vec::new();
+    deflate_with(content, level, |chunk| {
+        stream.extend(chunk);
+        true
+    });
+    stream
+}
+
+/// Return true if zlib encodes 'content' at 'level' into 'stream'. The
+/// encoding stops at the first chunk that differs.
+fn reproduces(content: &[u8], level: u8, stream: &[u8]) -> bool {
+    let mut pos = 0;
+    let whole = deflate_with(content, level, |chunk| {
+        let matches = stream.get(pos..pos + chunk.len()) == Some(chunk);
+        pos += chunk.len();
+        matches
+    });
+    whole && pos == stream.len()
+}
+
+/// Decode the deflate stream at 'start' of 'input'. Returns the size of the
+/// stream, and the part that replaces it if a level of zlib encodes the
+/// content again bit-exactly. Returns None if the stream is invalid.
+fn deflate_part(input: &[u8], start: usize) -> Option<(usize, Option<Part>)> {
+    let (len, content) = inflate(&input[start..])?;
+    let range = start..start + len;
+    let stream = &input[range.clone()];
+    let level = ZLIB_LEVELS
+        .into_iter()
+        .find(|level| reproduces(&content, *level, stream));
+    let part = level.map(|level| Part {
+        range,
+        encoding: Encoding::Deflate(level),
+        content,
+    });
+    Some((len, part))
+}
+
+/// Return the part of 'input' at 'range', which is stored without compression.
+fn stored_part(input: &[u8], range: Range<usize>) -> Part {
+    let content = input[range.clone()].to_vec();
+    Part {
+        range,
+        encoding: Encoding::Stored,
+        content,
+    }
+}
+
+/// Read the little-endian 16-bit number at 'at'.
+fn read16(input: &[u8], at: usize) -> Option<usize> {
+    decode16(input.get(at..)?, Endian::Little).map(|(_, val)| val as usize)
+}
+
+/// Read the little-endian 32-bit number at 'at'.
+fn read32_le(input: &[u8], at: usize) -> Option<u32> {
+    decode32(input.get(at..)?, Endian::Little).map(|(_, val)| val)
+}
+
+/// Return the offset after the zero-terminated string at 'at'.
+fn skip_string(input: &[u8], at: usize) -> Option<usize> {
+    let len = input.get(at..)?.iter().position(|b| *b == 0)?;
+    Some(at + len + 1)
+}
+
+/// Return the ranges of the payload of the deflate stream at 'cursor' of
+/// 'input', which holds only stored blocks, and the offset after the stream.
+/// Returns None if the stream has compressed blocks.
+fn stored_blocks(
+    input: &[u8],
+    mut cursor: usize,
+) -> Option<(Vec<Range<usize>>, usize)> {
+    // Each stored block starts at a byte boundary, with a header byte and the
+    // length of the block and its complement.
+    let mut ranges = Vec::new();
+    loop {
+        let header = *input.get(cursor)?;
+        if header & 0b110 != 0 {
+            return None;
+        }
+        let len = read16(input, cursor + 1)?;
+        if read16(input, cursor + 3)? != !len & 0xffff {
+            return None;
+        }
+        let range = cursor + 5..cursor + 5 + len;
+        input.get(range.clone())?;
+        cursor = range.end;
+        ranges.push(range);
+        if header & 1 != 0 {
+            return Some((ranges, cursor));
+        }
+    }
+}
+
+/// Return the parts of the gzip stream 'input'. The deflate stream is decoded
+/// if zlib reproduces it, or else its stored blocks are unwrapped. Returns None
+/// if the stream is invalid, or if it has compressed blocks that zlib does
+/// not reproduce.
+fn gzip_parts(input: &[u8]) -> Option<Vec<Part>> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    let flags = *input.get(3)?;
+    let mut cursor = 10;
+    if flags & FEXTRA != 0 {
+        cursor += 2 + read16(input, cursor)?;
+    }
+    if flags & FNAME != 0 {
+        cursor = skip_string(input, cursor)?;
+    }
+    if flags & FCOMMENT != 0 {
+        cursor = skip_string(input, cursor)?;
+    }
+    if flags & FHCRC != 0 {
+        cursor += 2;
+    }
+
+    // The checksum and the length of the member follow the deflate stream.
+    input.get(cursor..)?;
+    let (parts, end) = match deflate_part(input, cursor) {
+        Some((len, Some(part))) => (vec![part], cursor + len),
+        _ => {
+            let (ranges, end) = stored_blocks(input, cursor)?;
+            let parts = ranges
+                .into_iter()
+                .map(|range| stored_part(input, range))
+                .collect();
+            (parts, end)
+        }
+    };
+    input.get(end..end + 8)?;
+    Some(parts)
+}
+
+/// Return the parts of the zip archive 'input': the stored entries and the
+/// deflated entries that zlib reproduces. The scan stops at entries whose end
+/// can't be found, and at invalid deflate streams.
+fn zip_parts(input: &[u8]) -> Option<Vec<Part>> {
+    const HEADER_LEN: usize = 30;
+    const DATA_DESCRIPTOR: usize = 8;
+    const DESCRIPTOR_SIG: [u8; 4] = [0x50, 0x4b, 0x07, 0x08];
+    let mut parts = Vec::new();
+    let mut cursor = 0;
+    while match_signature(&input[cursor..], &[0x50, 0x4b, 0x03, 0x04]) {
+        let flags = read16(input, cursor + 6)?;
+        let method = read16(input, cursor + 8)?;
+        let size = read32_le(input, cursor + 18)?;
+        let name_len = read16(input, cursor + 26)?;
+        let extra_len = read16(input, cursor + 28)?;
+        let start = cursor + HEADER_LEN + name_len + extra_len;
+        input.get(start..)?;
+        let descriptor = flags & DATA_DESCRIPTOR != 0;
+
+        // Entries with a data descriptor don't save their size in the header,
+        // so their end is found by decoding them.
+        cursor = match method {
+            8 => {
+                let Some((len, part)) = deflate_part(input, start) else {
+                    break;
+                };
+                if !descriptor && len != size as usize {
+                    break;
+                }
+                parts.extend(part);
+                start + len
+            }
+            0 if !descriptor && size != u32::MAX => {
+                let range = start..start + size as usize;
+                input.get(range.clone())?;
+                if !range.is_empty() {
+                    parts.push(stored_part(input, range.clone()));
+                }
+                range.end
+            }
+            _ => break,
+        };
+        if descriptor {
+            if match_signature(&input[cursor..], &DESCRIPTOR_SIG) {
+                cursor += DESCRIPTOR_SIG.len();
+            }
+            cursor += 12;
+            input.get(cursor..)?;
+        }
+    }
+    Some(parts)
+}
+
+/// Recompress 'input' with the encoder context 'ctx'. Returns None if the
+/// input is not in a supported format, or if none of its parts can be
+/// restored bit-exactly from their content.
+pub fn recompress(input: &[u8], ctx: Context) -> Option<Vec<u8>> {
+    let format = detect_format(input)?;
+    let parts = match format {
+        Format::Gzip => gzip_parts(input)?,
+        Format::Zip => zip_parts(input)?,
+        Format::Zstd | Format::Jpeg => return None,
+    };
+    if parts.is_empty() {
+        return None;
+    }
+
+    // Save the sizes and the encoding of the parts, and compress the wrapper
+    // followed by the payload. The checksum of the deflate streams detects
+    // zlib builds that encode differently when the input is restored.
+    let mut output = Vec::new();
+    output.extend(RECOMPRESS_SIG);
+    output.push(format.id());
+    leb128::encode(parts.len() as u64, &mut output);
+    let mut wrapper: Vec<u8> = Vec::new();
+    let mut payload: Vec<u8> = Vec::new();
+    let mut cursor = 0;
+    for part in parts {
+        let range = part.range;
+        leb128::encode((range.start - cursor) as u64, &mut output);
+        leb128::encode(range.len() as u64, &mut output);
+        match part.encoding {
+            Encoding::Stored => output.push(0),
+            Encoding::Deflate(level) => {
+                output.extend([1, level]);
+                leb128::encode(part.content.len() as u64, &mut output);
+                write32(xxh32(&input[range.clone()], 0), &mut output);
+            }
+        }
+        wrapper.extend(&input[cursor..range.start]);
+        payload.extend(&part.content);
+        cursor = range.end;
+    }
+    wrapper.extend(&input[cursor..]);
+    leb128::encode((input.len() - cursor) as u64, &mut output);
+    wrapper.extend(payload);
+    let _ = FullEncoder::new(&wrapper, &mut output, ctx).encode();
+    Some(output)
+}
+
+/// Restore the original input from the output of 'recompress'. Returns the
+/// format of the input and its bytes, or None if the stream is invalid, or if
+/// a deflate stream is not encoded again bit-exactly.
+pub fn restore(input: &[u8]) -> Option<(Format, Vec<u8>)> {
+    if !match_signature(input, &RECOMPRESS_SIG) {
+        return None;
+    }
+    let mut cursor = RECOMPRESS_SIG.len();
+    let format = Format::from_id(*input.get(cursor)?)?;
+    cursor += 1;
+    let (read, count) = leb128::decode_len(&input[cursor..])?;
+    cursor += read;
+    // Each part takes at least three bytes.
+    if count > input.len() / 3 {
+        return None;
+    }
+    let mut parts = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (read, gap) = leb128::decode_len(&input[cursor..])?;
+        cursor += read;
+        let (read, len) = leb128::decode_len(&input[cursor..])?;
+        cursor += read;
+        let encoding = *input.get(cursor)?;
+        cursor += 1;
+        let part = match encoding {
+            0 => (gap, len, len, None),
+            1 => {
+                let level = *input.get(cursor)?;
+                if level > 9 {
+                    return None;
+                }
+                cursor += 1;
+                let (read, content) = leb128::decode_len(&input[cursor..])?;
+                cursor += read;
+                let checksum = read32(input.get(cursor..)?)?;
+                cursor += 4;
+                (gap, len, content, Some((level, checksum)))
+            }
+            _ => return None,
+        };
+        parts.push(part);
+    }
+    let (read, tail) = leb128::decode_len(&input[cursor..])?;
+    cursor += read;
+
+    let mut data = Vec::new();
+    let (used, _) = FullDecoder::new(&input[cursor..], &mut data).decode()?;
+    if cursor + used != input.len() {
+        return None;
+    }
+
+    // Encode the content of the parts again, and place them back between the
+    // wrapper parts.
+    let mut output = Vec::with_capacity(data.len());
+    let wrapper_len = parts
+        .iter()
+        .try_fold(tail, |acc, part| acc.checked_add(part.0))?;
+    let (mut wrapper, mut payload) = data.split_at_checked(wrapper_len)?;
+    for (gap, len, content_len, encoding) in parts {
+        let (part, rest) = wrapper.split_at_checked(gap)?;
+        output.extend(part);
+        wrapper = rest;
+        let (content, rest) = payload.split_at_checked(content_len)?;
+        payload = rest;
+        match encoding {
+            None => output.extend(content),
+            Some((level, checksum)) => {
+                let stream = deflate(content, level);
+                if stream.len() != len || xxh32(&stream, 0) != checksum {
+                    return None;
+                }
+                output.extend(stream);
+            }
+        }
+    }
+    if wrapper.len() != tail || !payload.is_empty() {
+        return None;
+    }
+    output.extend(wrapper);
+    Some((format, output))
+}
@@ -0,0 +1,143 @@
+//! Maps common file types to the pipelines that suit them (see 'pipeline').
+//! The type is detected by the magic number at the start of the file, or by
+//! the extension of its name when the format has no magic number, such as
+//! CSV. Some formats are best left to the matcher, and map to no pipeline.
+//! The command line tool uses the registry by default, unless the user selects
+//! the transforms with the '--filters' flag.
+
+use crate::pipeline::{Codec, Pipeline, Transform, MAX_PARAM};
+use crate::utils::number_encoding::{decode16, Endian};
+use crate::utils::signatures::match_signature;
+use std::fmt;
+
+/// The number of bytes at the start of a file that detection looks at.
+pub const HEADER_LEN: usize = 64;
+
+/// The file types that are known to the registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    /// Uncompressed audio samples.
+    Wav,
+    /// Uncompressed pixels.
+    Bmp,
+    /// Text tables.
+    Csv,
+    /// Executables and shared libraries.
+    Elf,
+}
+
+/// An entry of the registry.
+struct Entry {
+    kind: FileType,
+    /// The magic number at the start of the file, if the format has one.
+    magic: Option<&'static [u8]>,
+    /// The extensions of the file names, without the dot, in lower case.
+    extensions: &'static [&'static str],
+}
+
+/// The known file types, in the order that they are matched.
+const ENTRIES: [Entry; 4] = [
+    Entry {
+        kind: FileType::Wav,
+        magic: Some(b"RIFF"),
+        extensions: &["wav"],
+    },
+    Entry {
+        kind: FileType::Bmp,
+        magic: Some(b"BM"),
+        extensions: &["bmp"],
+    },
+    Entry {
+        kind: FileType::Csv,
+        magic: None,
+        extensions: &["csv", "tsv"],
+    },
+    Entry {
+        kind: FileType::Elf,
+        magic: Some(b"\x7fELF"),
+        extensions: &["so", "o"],
+    },
+];
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileType::Wav => "wav",
+            FileType::Bmp => "bmp",
+            FileType::Csv => "csv",
+            FileType::Elf => "elf",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FileType {
+    /// Return the type that is displayed as 'name', or None if the name is
+    /// unknown.
+    pub fn from_name(name: &str) -> Option<Self> {
+        ENTRIES
+            .iter()
+            .map(|entry| entry.kind)
+            .find(|kind| kind.to_string() == name)
+    }
+}
+
+/// Return the little-endian 16-bit number at 'at' of 'header'.
+fn read16(header: &[u8], at: usize) -> Option<usize> {
+    decode16(header.get(at..)?, Endian::Little).map(|(_, val)| val as usize)
+}
+
+/// Return the type of the file 'name' that starts with 'header'. The magic
+/// numbers are checked first, so a file with the wrong extension is detected
+/// by its content. Returns None if the type is unknown.
+pub fn detect_file_type(name: &str, header: &[u8]) -> Option<FileType> {
+    let by_magic = ENTRIES.iter().find(|entry| {
+        entry
+            .magic
+            .is_some_and(|magic| match_signature(header, magic))
+    });
+    if let Some(entry) = by_magic {
+        // RIFF is also the container of other formats, such as AVI.
+        if entry.kind != FileType::Wav || header.get(8..12) == Some(b"WAVE") {
+            return Some(entry.kind);
+        }
+    }
+    let (_, ext) = name.rsplit_once('.')?;
+    let ext = ext.to_ascii_lowercase();
+    ENTRIES
+        .iter()
+        .find(|entry| entry.extensions.contains(&ext.as_str()))
+        .map(|entry| entry.kind)
+}
+
+/// Return the pipeline that is recommended for files of the type 'kind' that
+/// start with 'header', or None if the file is best encoded without
+/// transforms.
+pub fn default_pipeline(kind: FileType, header: &[u8]) -> Option<Pipeline> {
+    let delta = |stride: usize| {
+        let stride = u8::try_from(stride).ok()?;
+        let valid = (1..=MAX_PARAM).contains(&stride);
+        valid
+            .then(|| Pipeline::new(Codec::Block).with(Transform::Delta(stride)))
+    };
+    match kind {
+        // Subtract the previous sample of the same channel. The size of the
+        // frame of all of the channels is saved in the format chunk.
+        FileType::Wav => delta(read16(header, 32)?),
+        // Subtract the previous pixel, for 24-bit and 32-bit pixels. Pixels
+        // with palettes are indices, and their differences are noise.
+        FileType::Bmp => match read16(header, 28)? {
+            bits @ (24 | 32) => delta(bits / 8),
+            _ => None,
+        },
+        // Text and code are left to the matcher, and the block encoder
+        // already collapses the zero padding between sections.
+        FileType::Csv | FileType::Elf => None,
+    }
+}
+
+/// Return the pipeline for the file 'name' that starts with 'header', or None
+/// if its type is unknown or it is best encoded without transforms.
+pub fn lookup(name: &str, header: &[u8]) -> Option<Pipeline> {
+    default_pipeline(detect_file_type(name, header)?, header)
+}
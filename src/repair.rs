@@ -0,0 +1,126 @@
+//! Recovers the undamaged pages of a damaged compressed stream, like the
+//! '--repair' flag of the command line tool. Pages that fail to decode, or
+//! that fail their checksums (see 'Context::with_page_checksums'), are
+//! replaced with zeros when their length is known, so the recovered pages
+//! keep their offsets.
+
+use crate::full::{decode_or_nop, read_full_header, FullDecoder};
+use crate::pager::PagerDecoder;
+use crate::utils::signatures::STORED_SIG;
+use crate::utils::signatures::{match_signature, ARITH_SIG, FROZEN_SIG};
+use crate::Decoder;
+
+/// A page that could not be recovered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingPage {
+    /// The index of the page in the stream.
+    pub index: usize,
+    /// The offset of the page in the recovered output.
+    pub offset: usize,
+    /// The length of the page, which is filled with zeros in the recovered
+    /// output, or None if the record doesn't save the length.
+    pub len: Option<usize>,
+}
+
+/// The results of 'repair'.
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    /// The recovered content.
+    pub output: Vec<u8>,
+    /// The number of pages in the stream.
+    pub pages: usize,
+    /// The number of pages that were recovered.
+    pub recovered: usize,
+    /// The pages that could not be recovered, in order.
+    pub missing: Vec<MissingPage>,
+    /// True if the pages after the last missing page could not be located,
+    /// because the stream is truncated or the record headers are damaged.
+    pub truncated: bool,
+}
+
+impl RepairReport {
+    /// Return true if all of the pages were recovered.
+    pub fn is_complete(&self) -> bool {
+        self.recovered == self.pages
+    }
+
+    /// Return the map of the missing byte ranges, with one line for each
+    /// missing page: the page index, the offset and the length of the range.
+    /// The length of pages with an unknown length is '?'.
+    pub fn to_map(&self) -> String {
+        let mut map = String::new();
+        for page in &self.missing {
+            let len = match page.len {
+                Some(len) => len.to_string(),
+                None => String::from("?"),
+            };
+            map += &format!("{} {} {}\n", page.index, page.offset, len);
+        }
+        if self.truncated {
+            map += &format!("truncated {}\n", self.output.len());
+        }
+        map
+    }
+}
+
+/// Decode the paged stream 'input' one page at a time, and fill the pages
+/// that fail to decode. Returns None if the header of the stream is invalid.
+fn repair_pages(input: &[u8]) -> Option<RepairReport> {
+    let mut report = RepairReport::default();
+    let mut output = Vec::new();
+    let mut decoder = PagerDecoder::new(input, &mut output);
+    decoder.set_callback(decode_or_nop);
+    report.pages = decoder.pages_left()?;
+
+    let mut offset = 0;
+    for index in 0..report.pages {
+        if let Some(written) = decoder.next_page() {
+            offset += written;
+            report.recovered += 1;
+            continue;
+        }
+        let len = decoder.next_page_len();
+        report.missing.push(MissingPage { index, offset, len });
+        let len = len.unwrap_or(0);
+        if decoder.fill_page(len).is_none() {
+            report.truncated = true;
+            break;
+        }
+        offset += len;
+    }
+    drop(decoder);
+    report.output = output;
+    Some(report)
+}
+
+/// Recover the pages of the compressed stream 'input'. Streams that are not
+/// split into pages are recovered whole or not at all. Returns None if the
+/// input is not a stream of the full compressor.
+pub fn repair(input: &[u8]) -> Option<RepairReport> {
+    let body = &input[read_full_header(input)?..];
+    let whole = match_signature(body, &STORED_SIG)
+        || match_signature(body, &ARITH_SIG)
+        || match_signature(body, &FROZEN_SIG);
+    if !whole {
+        return repair_pages(body);
+    }
+
+    let mut report = RepairReport {
+        pages: 1,
+        recovered: 1,
+        ..Default::default()
+    };
+    if FullDecoder::new(input, &mut report.output)
+        .decode()
+        .is_none()
+    {
+        report.recovered = 0;
+        report.output.clear();
+        report.missing.push(MissingPage {
+            index: 0,
+            offset: 0,
+            len: None,
+        });
+    }
+    Some(report)
+}
@@ -0,0 +1,80 @@
+//! A pre-pass that collapses long runs of a single byte before the matcher
+//! sees the data. The matchers find runs with many maximum-length matches,
+//! and the optimal parser evaluates every position of the run, which is slow
+//! on pages with runs that are megabytes long. Each run is replaced with a
+//! single byte and a token that records the length of the run.
+
+use crate::limits::check_output;
+use crate::utils::leb128;
+
+/// Runs of at least this length are collapsed.
+pub const MIN_RUN: usize = 1 << 10;
+
+/// Collapse the runs of 'input'. Returns the collapsed data and the encoded
+/// run tokens, or None if the input has no long runs.
+pub fn collapse(input: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut collapsed = Vec::new();
+    let mut runs = Vec::new();
+    let mut count = 0;
+    // The end of the part of the input that was copied to 'collapsed'.
+    let mut copied = 0;
+    // The position of the last run in 'collapsed'.
+    let mut last = 0;
+
+    let mut i = 0;
+    while i < input.len() {
+        let val = input[i];
+        let rest = &input[i..];
+        let len = rest.iter().position(|x| *x != val).unwrap_or(rest.len());
+        if len >= MIN_RUN {
+            collapsed.extend(&input[copied..=i]);
+            let pos = collapsed.len() - 1;
+            leb128::encode((pos - last) as u64, &mut runs);
+            leb128::encode((len - 1) as u64, &mut runs);
+            last = pos;
+            copied = i + len;
+            count += 1;
+        }
+        i += len;
+    }
+    if count == 0 {
+        return None;
+    }
+    collapsed.extend(&input[copied..]);
+
+    let mut tokens = Vec::new();
+    leb128::encode(count as u64, &mut tokens);
+    tokens.extend(runs);
+    Some((collapsed, tokens))
+}
+
+/// Expand the runs that were collapsed by 'collapse'. Returns None if the
+/// tokens don't match the data.
+pub fn expand(collapsed: &[u8], tokens: &[u8]) -> Option<Vec<u8>> {
+    let (mut cursor, count) = leb128::decode_len(tokens)?;
+    let mut output = Vec::new();
+    let mut copied = 0;
+    let mut last: usize = 0;
+    for _ in 0..count {
+        let (read, delta) = leb128::decode_len(&tokens[cursor..])?;
+        cursor += read;
+        let (read, extra) = leb128::decode_len(&tokens[cursor..])?;
+        cursor += read;
+
+        let pos = last.checked_add(delta)?;
+        let val = *collapsed.get(pos)?;
+        if pos < copied {
+            return None;
+        }
+        output.extend(&collapsed[copied..=pos]);
+        let len = check_output(output.len().checked_add(extra)?)?;
+        output.resize(len, val);
+        copied = pos + 1;
+        last = pos;
+    }
+    if cursor != tokens.len() {
+        return None;
+    }
+    output.extend(&collapsed[copied..]);
+    Some(output)
+}
@@ -0,0 +1,135 @@
+//! Decompresses the pages of a paged stream in parallel, and writes each page
+//! straight to its position in the output file, like 'pwrite'. The caller
+//! provides the offset of each page in the uncompressed output, for example
+//! from the page size of the encoder, so the pages don't wait for each other
+//! and the output is never assembled in memory.
+
+use crate::full::{decode_or_nop, read_full_header, FullDecoder};
+use crate::pager::{self, decode_record, page_view, read_header};
+use crate::utils::signatures::{match_signature, PAGER_SIG};
+use crate::Decoder;
+use std::borrow::Cow;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Return an error that reports an invalid stream.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// An output that accepts writes at absolute offsets, from several threads.
+pub trait WriteAt: Sync {
+    /// Write all of 'data' at the offset 'offset' of the output.
+    fn write_at(&self, data: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Set the size of the output to 'len' bytes, before the pages are
+    /// written.
+    fn set_len(&self, len: u64) -> io::Result<()>;
+}
+
+#[cfg(unix)]
+impl WriteAt for std::fs::File {
+    fn write_at(&self, data: &[u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(self, data, offset)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        std::fs::File::set_len(self, len)
+    }
+}
+
+/// Return the page records of the paged stream 'input', or None if the input
+/// is not a paged stream of the full compressor.
+pub fn split_pages(input: &[u8]) -> Option<Vec<&[u8]>> {
+    let buffer = &input[read_full_header(input)?..];
+    if !match_signature(buffer, &PAGER_SIG) {
+        return None;
+    }
+    let (mut cursor, parts) = read_header(buffer)?;
+    let mut records = Vec::new();
+    for _ in 0..parts {
+        let len = pager::record_len(&buffer[cursor..])?;
+        records.push(&buffer[cursor..cursor + len]);
+        cursor += len;
+    }
+    Some(records)
+}
+
+/// Decompress the stream 'input' into 'output' with 'threads' threads. The
+/// page 'i' is written at 'offsets[i]', and must end where the next page
+/// starts. The output is resized to the offset of the last page before the
+/// pages are written. Streams that are not paged are decoded in one step and
+/// written at offset zero. The pages are decoded on their own, so streams with
+/// deduplicated pages are rejected. Returns the size of the output.
+pub fn decode_at<W: WriteAt>(
+    input: &[u8],
+    offsets: &[u64],
+    output: &W,
+    threads: usize,
+) -> io::Result<u64> {
+    let Some(records) = split_pages(input) else {
+        let mut decoded = Vec::new();
+        match FullDecoder::new(input, &mut decoded).decode() {
+            Some(_) => {}
+            None => return Err(invalid("invalid stream")),
+        }
+        output.set_len(decoded.len() as u64)?;
+        output.write_at(&decoded, 0)?;
+        return Ok(decoded.len() as u64);
+    };
+    if records.len() != offsets.len() {
+        return Err(invalid("the number of offsets does not match the pages"));
+    }
+    if offsets.windows(2).any(|w| w[0] > w[1]) {
+        return Err(invalid("the offsets are not sorted"));
+    }
+    output.set_len(offsets.last().copied().unwrap_or(0))?;
+
+    // The workers take the next page from 'next', and record the end of the
+    // last page, or the first error.
+    let next = AtomicUsize::new(0);
+    let result: Mutex<io::Result<u64>> = Mutex::new(Ok(0));
+    let work = || -> io::Result<()> {
+        loop {
+            let idx = next.fetch_add(1, Ordering::Relaxed);
+            let Some(record) = records.get(idx) else {
+                return Ok(());
+            };
+            // Pages that are stored without compression are written from
+            // the input, without a copy.
+            let page = match page_view(record) {
+                Some(page) => Cow::Borrowed(page),
+                None => match decode_record(record, decode_or_nop) {
+                    Some(page) => Cow::Owned(page),
+                    None => return Err(invalid("invalid page")),
+                },
+            };
+            let end = offsets[idx] + page.len() as u64;
+            if offsets.get(idx + 1).is_some_and(|next| *next != end) {
+                return Err(invalid("the page does not match its offsets"));
+            }
+            output.write_at(&page, offsets[idx])?;
+            if idx + 1 == records.len() {
+                if let Ok(size) = result.lock().unwrap().as_mut() {
+                    *size = end;
+                }
+            }
+        }
+    };
+
+    let threads = threads.clamp(1, records.len().max(1));
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                if let Err(err) = work() {
+                    *result.lock().unwrap() = Err(err);
+                    // Stop the other workers.
+                    next.store(records.len(), Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    result.into_inner().unwrap()
+}
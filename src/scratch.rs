@@ -0,0 +1,171 @@
+//! A pool of scratch buffers for the intermediate vectors that are created
+//! while encoding and decoding pages. Taking a buffer from the pool reuses the
+//! allocation of a buffer that was recycled earlier on the same thread, which
+//! avoids heap churn when processing many small pages. The pools are bounded,
+//! so a thread doesn't keep the buffers of a large job after it is done.
+
+use std::cell::RefCell;
+
+/// The max number of buffers that we keep in each pool.
+const MAX_POOLED_BUFFERS: usize = 32;
+
+/// Don't keep buffers that are larger than this number of elements.
+const MAX_POOLED_CAPACITY: usize = 1 << 26;
+
+/// The max number of bytes that the buffers of each pool hold. A thread has a
+/// pool for each element type, so it keeps at most three times this number of
+/// bytes after a large job.
+pub const MAX_POOLED_BYTES: usize = 1 << 27;
+
+/// A list of empty buffers that are ready to be reused.
+pub struct BufferPool<T> {
+    free: Vec<Vec<T>>,
+    /// The number of bytes that the buffers in 'free' hold.
+    bytes: usize,
+}
+
+impl<T> Default for BufferPool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> BufferPool<T> {
+    pub fn new() -> Self {
+        Self {
+            free: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Return the number of bytes that 'buffer' holds.
+    fn size_of(buffer: &Vec<T>) -> usize {
+        buffer.capacity() * std::mem::size_of::<T>()
+    }
+
+    /// Return an empty buffer, that may have some capacity.
+    pub fn take(&mut self) -> Vec<T> {
+        let buffer = self.free.pop().unwrap_or_default();
+        self.bytes -= Self::size_of(&buffer);
+        buffer
+    }
+
+    /// Return the buffer 'buffer' to the pool. The largest buffers are freed
+    /// when the pool holds more than 'MAX_POOLED_BYTES'.
+    pub fn recycle(&mut self, mut buffer: Vec<T>) {
+        let cap = buffer.capacity();
+        if cap == 0
+            || cap > MAX_POOLED_CAPACITY
+            || self.free.len() >= MAX_POOLED_BUFFERS
+        {
+            return;
+        }
+        buffer.clear();
+        self.bytes += Self::size_of(&buffer);
+        self.free.push(buffer);
+        while self.bytes > MAX_POOLED_BYTES {
+            let largest = (0..self.free.len())
+                .max_by_key(|i| self.free[*i].capacity())
+                .unwrap();
+            let buffer = self.free.swap_remove(largest);
+            self.bytes -= Self::size_of(&buffer);
+        }
+    }
+
+    /// Returns the number of buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Returns True if the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// Returns the number of bytes that the buffers in the pool hold.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+// The buffers are recycled with 'try_with', because the tables of other
+// thread-locals may be dropped after the pools when the thread exits.
+thread_local! {
+    static U8_POOL: RefCell<BufferPool<u8>> = RefCell::new(BufferPool::new());
+    static U16_POOL: RefCell<BufferPool<u16>> = RefCell::new(BufferPool::new());
+    static U32_POOL: RefCell<BufferPool<u32>> = RefCell::new(BufferPool::new());
+}
+
+/// A set of pools that is owned by a caller instead of a thread. See
+/// 'with_scratch'.
+#[derive(Default)]
+pub struct Scratch {
+    u8_pool: BufferPool<u8>,
+    u16_pool: BufferPool<u16>,
+    u32_pool: BufferPool<u32>,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of bytes that the buffers of the pools hold.
+    pub fn bytes(&self) -> usize {
+        self.u8_pool.bytes() + self.u16_pool.bytes() + self.u32_pool.bytes()
+    }
+
+    /// Exchange the pools with the pools of the current thread.
+    fn swap(&mut self) {
+        U8_POOL.with(|pool| {
+            std::mem::swap(&mut *pool.borrow_mut(), &mut self.u8_pool)
+        });
+        U16_POOL.with(|pool| {
+            std::mem::swap(&mut *pool.borrow_mut(), &mut self.u16_pool)
+        });
+        U32_POOL.with(|pool| {
+            std::mem::swap(&mut *pool.borrow_mut(), &mut self.u32_pool)
+        });
+    }
+}
+
+/// Run 'f' with the pools of 'scratch' in place of the pools of the current
+/// thread, so the buffers that 'f' takes and recycles, such as the tables of
+/// the matchers and of the entropy coders, stay in 'scratch' after the call.
+/// The pools of the thread are restored afterwards.
+pub fn with_scratch<T>(scratch: &mut Scratch, f: impl FnOnce() -> T) -> T {
+    scratch.swap();
+    let result = f();
+    scratch.swap();
+    result
+}
+
+/// Take an empty byte buffer from the pool of the current thread.
+pub fn take_u8() -> Vec<u8> {
+    U8_POOL.with(|pool| pool.borrow_mut().take())
+}
+
+/// Return a byte buffer to the pool of the current thread.
+pub fn recycle_u8(buffer: Vec<u8>) {
+    let _ = U8_POOL.try_with(|pool| pool.borrow_mut().recycle(buffer));
+}
+
+/// Take an empty u16 buffer from the pool of the current thread.
+pub fn take_u16() -> Vec<u16> {
+    U16_POOL.with(|pool| pool.borrow_mut().take())
+}
+
+/// Return a u16 buffer to the pool of the current thread.
+pub fn recycle_u16(buffer: Vec<u16>) {
+    let _ = U16_POOL.try_with(|pool| pool.borrow_mut().recycle(buffer));
+}
+
+/// Take an empty u32 buffer from the pool of the current thread.
+pub fn take_u32() -> Vec<u32> {
+    U32_POOL.with(|pool| pool.borrow_mut().take())
+}
+
+/// Return a u32 buffer to the pool of the current thread.
+pub fn recycle_u32(buffer: Vec<u32>) {
+    let _ = U32_POOL.try_with(|pool| pool.borrow_mut().recycle(buffer));
+}
@@ -0,0 +1,238 @@
+//! Applies a transform, such as authenticated encryption, to the pages of a
+//! compressed stream. Encryption must come after compression, so the pages are
+//! compressed first and the transform is applied to each compressed payload.
+//! The stream records the id of the key, so readers can select the right key
+//! before decoding. The same transform can also seal a full stream: the seal
+//! trailer saves a tag that authenticates the whole stream, and is verified by
+//! 'FullDecoder::with_seal'.
+
+use crate::auto::{self, sample_pages, AUTO_LEVEL};
+use crate::digest::{DigestHasher, Sha256};
+use crate::full::{decode_or_nop, encode_or_nop, ARITH_LEVEL};
+use crate::pager::{read_page_header, write_page};
+use crate::scratch::recycle_u8;
+use crate::utils::leb128;
+use crate::utils::signatures::SEAL_TRAILER_SIG;
+use crate::utils::signatures::{match_signature, SEALED_SIG};
+use crate::Context;
+
+/// A transform that is applied to each compressed page, such as AES-GCM or
+/// ChaCha20-Poly1305. The associated data 'aad' contains the stream header and
+/// the index of the page. Authenticated ciphers should verify it, so pages that
+/// are reordered, removed or moved between streams are rejected. The transform
+/// is responsible for its nonce, and may store it in the payload.
+pub trait PageTransform {
+    /// Return the id of the key, which is saved in the stream header.
+    fn key_id(&self) -> u64;
+
+    /// Transform the compressed page 'payload'.
+    fn encrypt(&self, aad: &[u8], payload: &[u8]) -> Vec<u8>;
+
+    /// Reverse 'encrypt'. Returns None if the payload or 'aad' were modified.
+    fn decrypt(&self, aad: &[u8], payload: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Return the associated data of the page 'index' in a stream with 'header'.
+fn page_aad(header: &[u8], index: usize) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    leb128::encode(index as u64, &mut aad);
+    aad
+}
+
+/// Read the key id of the sealed stream 'input'. Returns the size of the
+/// header, the key id, and the number of pages.
+fn read_header(input: &[u8]) -> Option<(usize, u64, usize)> {
+    if !match_signature(input, &SEALED_SIG) {
+        return None;
+    }
+    let mut cursor = SEALED_SIG.len();
+    let (read, key_id) = leb128::decode(&input[cursor..])?;
+    cursor += read;
+    let (read, parts) = leb128::decode_len(&input[cursor..])?;
+    Some((cursor + read, key_id, parts))
+}
+
+/// Return the id of the key that sealed 'input', or None if the input is not a
+/// sealed stream.
+pub fn key_id(input: &[u8]) -> Option<u64> {
+    read_header(input).map(|(_, key_id, _)| key_id)
+}
+
+/// Compress 'input' into pages of 'ctx.block_size' bytes, and apply
+/// 'transform' to each compressed page. The pages are regular blocks, so the
+/// arithmetic level compresses them with the highest level of blocks.
+pub fn seal(
+    input: &[u8],
+    mut ctx: Context,
+    transform: &dyn PageTransform,
+) -> Vec<u8> {
+    assert!(ctx.block_size > 0, "Must set page size");
+    if ctx.level == AUTO_LEVEL {
+        ctx = auto::select(&sample_pages(input, ctx.block_size)).apply(ctx);
+    }
+    ctx.level = ctx.level.min(ARITH_LEVEL - 1);
+    let mut output = Vec::new();
+    output.extend(SEALED_SIG);
+    leb128::encode(transform.key_id(), &mut output);
+    let parts = input.len().div_ceil(ctx.block_size);
+    leb128::encode(parts as u64, &mut output);
+    let header = output.clone();
+
+    for (idx, part) in input.chunks(ctx.block_size).enumerate() {
+        let compressed = encode_or_nop(part, ctx);
+        let sealed = transform.encrypt(&page_aad(&header, idx), &compressed);
+        write_page(&sealed, &mut output);
+        recycle_u8(compressed);
+    }
+    output
+}
+
+/// Decode the stream 'input' that was created by 'seal'. Returns None if the
+/// stream is invalid, if it was sealed with a different key, or if a page fails
+/// to decrypt.
+pub fn unseal(input: &[u8], transform: &dyn PageTransform) -> Option<Vec<u8>> {
+    let (mut cursor, key_id, parts) = read_header(input)?;
+    if key_id != transform.key_id() {
+        return None;
+    }
+    let header = &input[..cursor];
+
+    let mut output = Vec::new();
+    for idx in 0..parts {
+        let (read, len) = read_page_header(&input[cursor..])?;
+        cursor += read;
+        let payload = input.get(cursor..)?.get(..len)?;
+        cursor += len;
+
+        let compressed = transform.decrypt(&page_aad(header, idx), payload)?;
+        let (read, page) = decode_or_nop(&compressed)?;
+        if read != compressed.len() {
+            return None;
+        }
+        output.extend(&page);
+        recycle_u8(page);
+    }
+
+    if cursor != input.len() {
+        return None;
+    }
+    Some(output)
+}
+
+/// Append the seal trailer of the stream that starts at 'start' in 'output':
+/// the key id of 'transform' and the tag of the stream, which is the result of
+/// sealing an empty payload with the stream as the associated data. Returns
+/// the number of bytes written.
+pub fn write_seal_trailer(
+    transform: &dyn PageTransform,
+    output: &mut Vec<u8>,
+    start: usize,
+) -> usize {
+    let tag = transform.encrypt(&output[start..], &[]);
+    let len = output.len();
+    output.extend(SEAL_TRAILER_SIG);
+    leb128::encode(transform.key_id(), output);
+    leb128::encode(tag.len() as u64, output);
+    output.extend(tag);
+    output.len() - len
+}
+
+/// Read the seal trailer at the start of 'input'. Returns the size of the
+/// trailer, the key id and the tag, or None if the input does not start with
+/// a trailer.
+pub fn read_seal_trailer(input: &[u8]) -> Option<(usize, u64, &[u8])> {
+    if !match_signature(input, &SEAL_TRAILER_SIG) {
+        return None;
+    }
+    let mut cursor = SEAL_TRAILER_SIG.len();
+    let (read, key_id) = leb128::decode(&input[cursor..])?;
+    cursor += read;
+    let (read, len) = leb128::decode_len(&input[cursor..])?;
+    cursor += read;
+    let tag = input.get(cursor..)?.get(..len)?;
+    Some((cursor + len, key_id, tag))
+}
+
+/// Return True if 'tag' seals 'stream' with the key 'key_id' of 'transform'.
+pub fn verify_seal(
+    stream: &[u8],
+    key_id: u64,
+    tag: &[u8],
+    transform: &dyn PageTransform,
+) -> bool {
+    key_id == transform.key_id()
+        && transform.decrypt(stream, tag).is_some_and(|p| p.is_empty())
+}
+
+/// A transform that authenticates the payload with HMAC-SHA256, and appends
+/// the tag to the payload. It does not encrypt the payload, so it only seals
+/// the stream against modification. The key id is the HMAC of a constant
+/// string, so it identifies the key without revealing it.
+pub struct HmacSeal {
+    /// The key, hashed if it is longer than a block of SHA-256.
+    key: [u8; HMAC_BLOCK],
+}
+
+/// The size of a block of SHA-256, and of the padded key of HMAC.
+const HMAC_BLOCK: usize = 64;
+
+/// The size of the tag of HMAC-SHA256.
+const HMAC_TAG: usize = 32;
+
+impl HmacSeal {
+    pub fn new(key: &[u8]) -> Self {
+        let mut padded = [0; HMAC_BLOCK];
+        if key.len() > HMAC_BLOCK {
+            padded[..HMAC_TAG].copy_from_slice(&crate::digest::sha256(key));
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+        Self { key: padded }
+    }
+
+    /// Return the HMAC of the concatenation of 'parts'.
+    fn mac(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let pad =
+            |x: u8| -> Vec<u8> { self.key.iter().map(|k| k ^ x).collect() };
+        let mut inner = Sha256::new();
+        inner.update(&pad(0x36));
+        for part in parts {
+            inner.update(part);
+        }
+        let mut outer = Sha256::new();
+        outer.update(&pad(0x5c));
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+
+    /// Return the tag of 'payload' with the associated data 'aad'. The length
+    /// of 'aad' is hashed too, so the boundary between them is authenticated.
+    fn tag(&self, aad: &[u8], payload: &[u8]) -> Vec<u8> {
+        let len = (aad.len() as u64).to_le_bytes();
+        self.mac(&[&len, aad, payload])
+    }
+}
+
+impl PageTransform for HmacSeal {
+    fn key_id(&self) -> u64 {
+        let digest = self.mac(&[b"key id"]);
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    fn encrypt(&self, aad: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut sealed = payload.to_vec();
+        sealed.extend(self.tag(aad, payload));
+        sealed
+    }
+
+    fn decrypt(&self, aad: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+        let body = &payload[..payload.len().checked_sub(HMAC_TAG)?];
+        let tag = self.tag(aad, body);
+        // Compare all of the bytes, so the time does not leak the prefix.
+        let diff = tag
+            .iter()
+            .zip(&payload[body.len()..])
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        (diff == 0).then(|| body.to_vec())
+    }
+}
@@ -0,0 +1,72 @@
+//! Writes files that contain long zero runs as sparse files. The zero runs are
+//! skipped with 'seek' instead of being written, so the filesystem does not
+//! allocate blocks for them. This preserves the sparseness of disk images when
+//! they are restored, and saves the time of writing the zeros.
+
+use crate::pager::MIN_HOLE_SIZE;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// A writer that seeks over zero runs of at least 'MIN_HOLE_SIZE' bytes.
+pub struct SparseWriter<W: Write + Seek> {
+    /// The output file.
+    inner: W,
+    /// Set if the last bytes of the stream were skipped.
+    in_hole: bool,
+}
+
+impl<W: Write + Seek> SparseWriter<W> {
+    /// Create a new writer that writes into 'inner'.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            in_hole: false,
+        }
+    }
+
+    /// Complete the file and return the underlying writer. If the stream ends
+    /// with a hole, the last byte is written to set the length of the file.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.in_hole {
+            self.inner.seek(SeekFrom::Current(-1))?;
+            self.inner.write_all(&[0])?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write + Seek> Write for SparseWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let zeros = buf.iter().position(|x| *x != 0).unwrap_or(buf.len());
+        if zeros >= MIN_HOLE_SIZE || (self.in_hole && zeros > 0) {
+            self.inner.seek(SeekFrom::Current(zeros as i64))?;
+            self.in_hole = true;
+            return Ok(zeros);
+        }
+
+        // Write up to the start of the next hole.
+        let mut len = zeros;
+        while len < buf.len() {
+            let rest = &buf[len..];
+            let nonzero =
+                rest.iter().position(|x| *x == 0).unwrap_or(rest.len());
+            len += nonzero;
+            let rest = &buf[len..];
+            let zeros = rest.iter().position(|x| *x != 0).unwrap_or(rest.len());
+            if zeros >= MIN_HOLE_SIZE {
+                break;
+            }
+            len += zeros;
+        }
+        self.inner.write_all(&buf[..len])?;
+        self.in_hole = false;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,205 @@
+//! Converts LZ4 streams to streams of the full compressor and back, without
+//! running the matcher again. The sequences of one format are reused to emit
+//! the other, and only the entropy coding is done again, which makes it cheap
+//! to upgrade old LZ4 archives. The full streams are split into pages like
+//! 'FullEncoder' does, and the matches that cross into an earlier page are
+//! saved as literals, because pages are decoded independently. Going the other
+//! way, the sequences of the pages are joined into one LZ4 stream, and the
+//! pages that are not regular blocks are matched with the LZ4 matcher.
+
+use crate::block::{BlockEncoder, BlockReader, Sequence};
+use crate::full::{check_trailers, decode_or_nop, finish_paged};
+use crate::full::{read_full_header, FullDecoder};
+use crate::lz::lz4::encode_sequences;
+use crate::lz::{LZ4Decoder, LZ4Encoder};
+use crate::nop::NopEncoder;
+use crate::pager::{self, read_checked_header, read_page_header, record_len};
+use crate::pager::{write_checked_header, write_page, PagerDecoder};
+use crate::utils::signatures::FULL_SIG;
+use crate::{Context, Decoder, Encoder};
+use std::io::IoSlice;
+
+/// The literals and the sequences of one page.
+type PageSequences = (Vec<u8>, Vec<Sequence>);
+
+/// Split the sequences of 'data' at the page boundaries of 'page_size'. The
+/// matches that cross a boundary are cut, and the parts of matches that refer
+/// to an earlier page are saved as literals.
+fn split_pages(
+    data: &[u8],
+    sequences: &[Sequence],
+    page_size: usize,
+) -> Vec<PageSequences> {
+    let mut pages = Vec::new();
+    let (mut lits, mut seqs) = (Vec::new(), Vec::new());
+    // The number of literals since the last match of the page.
+    let mut pending = 0;
+    let mut pos = 0;
+    let mut page_start = 0;
+    for seq in sequences {
+        let mut left = seq.lit_len as usize;
+        let mut mat_left = seq.mat_len as usize;
+        while left + mat_left > 0 {
+            if pos == page_start + page_size {
+                if pending > 0 {
+                    seqs.push(Sequence {
+                        lit_len: pending as u32,
+                        mat_len: 0,
+                        offset: 0,
+                    });
+                }
+                pages.push((lits, seqs));
+                (lits, seqs, pending) = (Vec::new(), Vec::new(), 0);
+                page_start = pos;
+            }
+            let room = page_start + page_size - pos;
+            if left > 0 {
+                // The literals are copied as is.
+                let take = left.min(room);
+                lits.extend(&data[pos..pos + take]);
+                (pos, left, pending) =
+                    (pos + take, left - take, pending + take);
+                continue;
+            }
+            // The bytes of the match that come before the start of the page
+            // are saved as literals.
+            let offset = seq.offset as usize;
+            let take = mat_left.min(room);
+            let cut = (page_start + offset).saturating_sub(pos).min(take);
+            lits.extend(&data[pos..pos + cut]);
+            pending += cut;
+            if take > cut {
+                seqs.push(Sequence {
+                    lit_len: pending as u32,
+                    mat_len: (take - cut) as u32,
+                    offset: offset as u32,
+                });
+                pending = 0;
+            }
+            (pos, mat_left) = (pos + take, mat_left - take);
+        }
+    }
+    if pending > 0 || seqs.is_empty() {
+        seqs.push(Sequence {
+            lit_len: pending as u32,
+            mat_len: 0,
+            offset: 0,
+        });
+    }
+    pages.push((lits, seqs));
+    pages
+}
+
+/// Convert the LZ4 stream 'input' into a stream of the full compressor, with
+/// the page size and the entropy coding of 'ctx'. The pages are encoded from
+/// the sequences of the LZ4 stream, so the level of 'ctx' only selects the
+/// entropy coding. The stream is decoded by 'FullDecoder'. Returns None if the
+/// LZ4 stream is invalid.
+pub fn transcode_lz4_to_full(input: &[u8], ctx: Context) -> Option<Vec<u8>> {
+    assert!(ctx.block_size > 0, "Must set page size");
+    let mut data = Vec::new();
+    let (read, sequences) =
+        LZ4Decoder::new(input, &mut data).decode_sequences()?;
+    if read != input.len() {
+        return None;
+    }
+
+    let pages = split_pages(&data, &sequences, ctx.block_size);
+    let mut output = Vec::new();
+    output.extend(FULL_SIG);
+    pager::write_header(pages.len(), &mut output);
+    let mut start = 0;
+    for (lits, seqs) in pages {
+        let len = seqs
+            .iter()
+            .map(|s| s.lit_len as usize + s.mat_len as usize)
+            .sum::<usize>();
+        let page = &data[start..start + len];
+        start += len;
+        let mut encoded = BlockEncoder::encode_sequences(&lits, &seqs, ctx)?;
+        if encoded.len() >= page.len() {
+            encoded.clear();
+            let _ = NopEncoder::new(page, &mut encoded, ctx).encode();
+        }
+        if ctx.page_checksums {
+            write_checked_header(page, &mut output);
+        }
+        write_page(&encoded, &mut output);
+    }
+    finish_paged(&[IoSlice::new(&data)], &mut output, 0, ctx);
+    Some(output)
+}
+
+/// Return the sequences of the page 'record', if the page is a regular block
+/// that decodes into 'len' bytes.
+fn record_sequences(record: &[u8], len: usize) -> Option<Vec<Sequence>> {
+    let record = match read_checked_header(record) {
+        Some((read, _, _)) => &record[read..],
+        None => record,
+    };
+    let (read, length) = read_page_header(record)?;
+    let packet = record.get(read..)?.get(..length)?;
+    let (used, block) = BlockReader::new(packet).read()?;
+    (used == length && block.decoded_len() == len).then_some(block.sequences)
+}
+
+/// Return the sequences of 'page', found with the LZ4 matcher of 'ctx'.
+/// Returns None if the page is larger than the output cap.
+fn match_page(page: &[u8], ctx: Context) -> Option<Vec<Sequence>> {
+    let mut encoded = Vec::new();
+    let _ = LZ4Encoder::new(page, &mut encoded, ctx).encode();
+    let mut decoded = Vec::new();
+    let mut decoder = LZ4Decoder::new(&encoded, &mut decoded);
+    Some(decoder.decode_sequences()?.1)
+}
+
+/// Convert the stream of the full compressor 'input' into an LZ4 stream. The
+/// sequences of the pages that are regular blocks are reused, and the other
+/// pages, and streams without pages, are matched with the LZ4 matcher of the
+/// level of 'ctx'. Matches that LZ4 can't encode, such as matches that are
+/// further than 64KB, are saved as literals. The trailers of the stream are
+/// verified. Returns None if the stream is invalid, or if bytes are left after
+/// it.
+pub fn transcode_full_to_lz4(input: &[u8], ctx: Context) -> Option<Vec<u8>> {
+    let header = read_full_header(input)?;
+    let buffer = &input[header..];
+    let mut data = Vec::new();
+    let mut sequences = Vec::new();
+    if let Some((mut cursor, parts, _)) = pager::read_sized_header(buffer) {
+        // The page count is not trusted, so the list grows with the pages.
+        let mut pages = Vec::new();
+        let mut decoder = PagerDecoder::new(buffer, &mut data);
+        decoder.set_callback(decode_or_nop);
+        for _ in 0..parts {
+            let len = record_len(&buffer[cursor..])?;
+            let written = decoder.next_page()?;
+            let record = &buffer[cursor..cursor + len];
+            pages.push((written, record_sequences(record, written)));
+            cursor += len;
+        }
+        if check_trailers(input, header + cursor, &data, None, None)?
+            != input.len()
+        {
+            return None;
+        }
+        let mut start = 0;
+        for (len, seqs) in pages {
+            let page = &data[start..start + len];
+            start += len;
+            match seqs {
+                Some(seqs) => sequences.extend(seqs),
+                None => sequences.extend(match_page(page, ctx)?),
+            }
+        }
+    } else {
+        let (read, _) = FullDecoder::new(input, &mut data).decode()?;
+        if read != input.len() {
+            return None;
+        }
+        sequences = match_page(&data, ctx)?;
+    }
+
+    let mut output = Vec::new();
+    encode_sequences(&data, &sequences, &mut output)?;
+    Some(output)
+}
@@ -2,15 +2,49 @@
 
 /// A collection of signatures for the different encoders.
 pub mod signatures {
-    /// Signatures for different encoding kinds.
+    use super::number_encoding;
+    use super::number_encoding::Endian;
+
+    /// Signatures for different encoding kinds. The containers that store
+    /// lengths with LEB128 use the second version of their signature.
     pub const LZ4_SIG: [u8; 4] = [0x17, 0x41, 0x74, 0x17];
-    pub const NOP_ENC: [u8; 2] = [0x90, 0x90];
+    pub const NOP_ENC: [u8; 2] = [0x90, 0x91];
     pub const SIMPLE_ENC: [u8; 2] = [0x12, 34];
-    pub const BLOCK_SIG: [u8; 2] = [0x13, 45];
+    pub const BLOCK_SIG: [u8; 2] = [0x13, 49];
+    pub const SMALL_BLOCK_SIG: [u8; 2] = [0x13, 46];
+    pub const RLE_BLOCK_SIG: [u8; 2] = [0x13, 48];
+    pub const MATCHED_LIT_SIG: [u8; 2] = [0x13, 50];
+    pub const LONG_BLOCK_SIG: [u8; 2] = [0x13, 51];
+    pub const FAST_BLOCK_SIG: [u8; 2] = [0x13, 52];
+    pub const OFFSET_CTX_SIG: [u8; 2] = [0x13, 53];
+    pub const PIPELINE_BLOCK_SIG: [u8; 2] = [0x13, 54];
     pub const ARITH_SIG: [u8; 2] = [0x01, 10];
-    pub const PAGER_SIG: [u8; 4] = [0x9a, 0x93, 0x9a, 0x93];
+    pub const RESIDUAL_SIG: [u8; 2] = [0x01, 11];
+    pub const FROZEN_SIG: [u8; 2] = [0x01, 12];
+    pub const REUSED_TABLE_SIG: [u8; 2] = [0x01, 13];
+    pub const PAGER_SIG: [u8; 4] = [0x9a, 0x93, 0x9a, 0x94];
+    pub const SIZED_PAGER_SIG: [u8; 4] = [0x9a, 0x93, 0x9a, 0x95];
     pub const START_PAGE_SIG: [u8; 2] = [0x71, 75];
+    pub const DUP_PAGE_SIG: [u8; 2] = [0x71, 76];
+    pub const CONST_PAGE_SIG: [u8; 2] = [0x71, 77];
+    pub const HOLE_SIG: [u8; 2] = [0x71, 78];
+    pub const SPARSE_PAGE_SIG: [u8; 2] = [0x71, 79];
+    pub const REF_SIG: [u8; 2] = [0x71, 80];
+    pub const CHECKED_PAGE_SIG: [u8; 2] = [0x71, 81];
     pub const FULL_SIG: [u8; 4] = [0x10, 0x14, 0x82, 0x35];
+    pub const STORED_SIG: [u8; 2] = [0x10, 0x01];
+    pub const FRAME_CHECKSUM_SIG: [u8; 2] = [0x10, 0x02];
+    pub const DIGEST_SIG: [u8; 2] = [0x10, 0x03];
+    pub const METADATA_SIG: [u8; 2] = [0x10, 0x04];
+    pub const HASH_TREE_SIG: [u8; 2] = [0x10, 0x05];
+    pub const RESET_TABLE_SIG: [u8; 2] = [0x10, 0x06];
+    pub const SEAL_TRAILER_SIG: [u8; 2] = [0x10, 0x07];
+    pub const DELTA_SIG: [u8; 4] = [0xde, 0x17, 0xa0, 0x01];
+    pub const VOLUME_SIG: [u8; 4] = [0x10, 0x14, 0x82, 0x56];
+    pub const CHECKPOINT_SIG: [u8; 4] = [0x10, 0x14, 0x82, 0x57];
+    pub const SEALED_SIG: [u8; 4] = [0x10, 0x14, 0x82, 0x58];
+    pub const RECOMPRESS_SIG: [u8; 4] = [0x10, 0x14, 0x82, 0x59];
+    pub const PROFILE_SIG: [u8; 4] = [0x10, 0x14, 0x82, 0x5a];
     pub const FILE_EXTENSION: &str = ".rz";
 
     /// Return True if 'input' starts with 'signature'.
@@ -18,27 +52,21 @@ pub mod signatures {
         input.starts_with(signature)
     }
 
-    /// Write the value 'val' into 'stream'.
+    /// Write the big-endian value 'val' into 'stream'.
     pub fn write32(val: u32, stream: &mut Vec<u8>) {
-        let bytes = val.to_be_bytes();
-        stream.extend(bytes);
+        number_encoding::encode32(val, stream, Endian::Big);
     }
 
-    /// Try to decode a number from the input buffer.
+    /// Try to decode a big-endian number from the input buffer.
     pub fn read32(input: &[u8]) -> Option<u32> {
-        if input.len() < 4 {
-            return None;
-        }
-        if let Ok(x) = input[0..4].try_into() {
-            return Some(u32::from_be_bytes(x));
-        }
-        None
+        number_encoding::decode32(input, Endian::Big).map(|(_, val)| val)
     }
 }
 
 /// Implements run length encoding.
 pub mod run_length_encoding {
     use super::number_encoding;
+    use super::number_encoding::Endian;
 
     pub struct RLEIterator<'a> {
         input: &'a [u8],
@@ -102,7 +130,7 @@ pub mod run_length_encoding {
 
     // Encode the array and return the number of bytes written.
     pub fn encode(array: &[u8], stream: &mut Vec<u8>) -> usize {
-        number_encoding::encode32(array.len() as u32, stream);
+        number_encoding::encode32(array.len() as u32, stream, Endian::Big);
         let mut wrote = 4;
         for item in RLEIterator::new(array) {
             wrote += write_rle(item.1, item.0, stream);
@@ -113,7 +141,7 @@ pub mod run_length_encoding {
     // Decode the array and return the number of items that were read.
     pub fn decode(input: &[u8], output: &mut Vec<u8>) -> Option<usize> {
         let array_len = input.len();
-        let (_, len) = number_encoding::decode32(input)?;
+        let (_, len) = number_encoding::decode32(input, Endian::Big)?;
         let len = len as usize;
         let mut wrote = 0;
         let mut pos = 4;
@@ -139,6 +167,8 @@ pub mod run_length_encoding {
 /// The number 350 is serialized as [255, 95].
 pub mod variable_length_encoding {
     use super::number_encoding;
+    use super::number_encoding::Endian;
+    use crate::limits::check_input;
 
     /// Encode the number 'num' into the stream and return the number of bytes
     /// written.
@@ -180,9 +210,68 @@ pub mod variable_length_encoding {
         Some((read, val))
     }
 
+    /// The number of bytes that are scanned at once by 'decode_all'.
+    const CHUNK: usize = 16;
+
+    /// Return the number of bytes at the start of 'chunk' that are not 255.
+    #[cfg(target_arch = "x86_64")]
+    fn single_byte_prefix(chunk: &[u8; CHUNK]) -> usize {
+        use std::arch::x86_64::*;
+        // SAFETY: SSE2 is always available on x86_64, and the load reads the
+        // 16 bytes of 'chunk'.
+        let mask = unsafe {
+            let bytes = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            _mm_movemask_epi8(_mm_cmpeq_epi8(bytes, _mm_set1_epi8(-1)))
+        };
+        (mask as u32 | 1 << CHUNK).trailing_zeros() as usize
+    }
+
+    /// See the x86 version.
+    #[cfg(target_arch = "aarch64")]
+    fn single_byte_prefix(chunk: &[u8; CHUNK]) -> usize {
+        use std::arch::aarch64::*;
+        // SAFETY: NEON is always available on aarch64, and the load reads the
+        // 16 bytes of 'chunk'.
+        let any = unsafe {
+            vmaxvq_u8(vceqq_u8(vld1q_u8(chunk.as_ptr()), vdupq_n_u8(255)))
+        };
+        if any == 0 {
+            return CHUNK;
+        }
+        chunk.iter().position(|x| *x == 255).unwrap()
+    }
+
+    /// See the x86 version.
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn single_byte_prefix(chunk: &[u8; CHUNK]) -> usize {
+        chunk.iter().position(|x| *x == 255).unwrap_or(CHUNK)
+    }
+
+    /// Decode all of the numbers in 'stream' into 'values'. Returns None if
+    /// the last number is truncated. Most numbers are a single byte, so the
+    /// stream is scanned with SIMD for the continuation byte 255, and the
+    /// bytes before it are copied in one step.
+    pub fn decode_all(stream: &[u8], values: &mut Vec<u32>) -> Option<()> {
+        let mut cursor = 0;
+        while cursor < stream.len() {
+            if let Some(chunk) = stream.get(cursor..cursor + CHUNK) {
+                let len = single_byte_prefix(chunk.try_into().unwrap());
+                values.extend(chunk[..len].iter().map(|x| *x as u32));
+                cursor += len;
+                if len == CHUNK {
+                    continue;
+                }
+            }
+            let (read, val) = decode(&stream[cursor..])?;
+            cursor += read;
+            values.push(val);
+        }
+        Some(())
+    }
+
     // Encode the array and return the number of bytes written.
     pub fn encode_array32(array: &[u32], stream: &mut Vec<u8>) -> usize {
-        number_encoding::encode32(array.len() as u32, stream);
+        number_encoding::encode32(array.len() as u32, stream, Endian::Big);
         let mut written = 4;
         for num in array {
             written += encode(*num, stream);
@@ -195,8 +284,10 @@ pub mod variable_length_encoding {
         stream: &[u8],
         array: &mut Vec<u32>,
     ) -> Option<usize> {
-        let (_, len) = number_encoding::decode32(stream)?;
+        let (_, len) = number_encoding::decode32(stream, Endian::Big)?;
         let mut cursor = 4;
+        // Each number takes at least one byte.
+        check_input(stream, cursor, len as usize)?;
         for _ in 0..len {
             let (read, val) = decode(&stream[cursor..])?;
             cursor += read;
@@ -206,42 +297,62 @@ pub mod variable_length_encoding {
     }
 }
 
-/// Implements encoding and decoding of regular numbers.
+/// Implements encoding and decoding of regular numbers. The byte order is
+/// selected at each call site. The containers of the compressor use big-endian
+/// numbers, and little-endian numbers allow zero-copy access to the arrays of
+/// little-endian formats.
 pub mod number_encoding {
-    pub fn encode32(num: u32, stream: &mut Vec<u8>) -> usize {
-        stream.extend_from_slice(&(num).to_be_bytes());
-        4
+    use crate::limits::check_input;
+
+    /// The byte order of a number.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum Endian {
+        Big,
+        Little,
     }
 
-    pub fn decode32(stream: &[u8]) -> Option<(usize, u32)> {
-        if stream.len() < 4 {
-            return None;
+    pub fn encode32(num: u32, stream: &mut Vec<u8>, endian: Endian) -> usize {
+        match endian {
+            Endian::Big => stream.extend_from_slice(&num.to_be_bytes()),
+            Endian::Little => stream.extend_from_slice(&num.to_le_bytes()),
         }
+        4
+    }
 
-        let bytes: [u8; 4] = stream[0..4].try_into().unwrap();
-        Some((4, u32::from_be_bytes(bytes)))
+    pub fn decode32(stream: &[u8], endian: Endian) -> Option<(usize, u32)> {
+        let bytes: [u8; 4] = stream.get(0..4)?.try_into().unwrap();
+        match endian {
+            Endian::Big => Some((4, u32::from_be_bytes(bytes))),
+            Endian::Little => Some((4, u32::from_le_bytes(bytes))),
+        }
     }
 
-    pub fn encode16(num: u16, stream: &mut Vec<u8>) -> usize {
-        stream.extend_from_slice(&(num).to_be_bytes());
+    pub fn encode16(num: u16, stream: &mut Vec<u8>, endian: Endian) -> usize {
+        match endian {
+            Endian::Big => stream.extend_from_slice(&num.to_be_bytes()),
+            Endian::Little => stream.extend_from_slice(&num.to_le_bytes()),
+        }
         2
     }
 
-    pub fn decode16(stream: &[u8]) -> Option<(usize, u16)> {
-        if stream.len() < 2 {
-            return None;
+    pub fn decode16(stream: &[u8], endian: Endian) -> Option<(usize, u16)> {
+        let bytes: [u8; 2] = stream.get(0..2)?.try_into().unwrap();
+        match endian {
+            Endian::Big => Some((2, u16::from_be_bytes(bytes))),
+            Endian::Little => Some((2, u16::from_le_bytes(bytes))),
         }
-
-        let bytes: [u8; 2] = stream[0..2].try_into().unwrap();
-        Some((2, u16::from_be_bytes(bytes)))
     }
 
     // Encode the array and return the number of bytes written.
-    pub fn encode_array16(array: &[u16], stream: &mut Vec<u8>) -> usize {
-        encode32(array.len() as u32, stream);
+    pub fn encode_array16(
+        array: &[u16],
+        stream: &mut Vec<u8>,
+        endian: Endian,
+    ) -> usize {
+        encode32(array.len() as u32, stream, endian);
         let mut written = 4;
         for num in array {
-            written += encode16(*num, stream);
+            written += encode16(*num, stream, endian);
         }
         written
     }
@@ -250,11 +361,13 @@ pub mod number_encoding {
     pub fn decode_array16(
         stream: &[u8],
         array: &mut Vec<u16>,
+        endian: Endian,
     ) -> Option<usize> {
-        let (_, len) = decode32(stream)?;
+        let (_, len) = decode32(stream, endian)?;
         let mut cursor = 4;
+        check_input(stream, cursor, len as usize * 2)?;
         for _ in 0..len {
-            let (read, val) = decode16(&stream[cursor..])?;
+            let (read, val) = decode16(&stream[cursor..], endian)?;
             cursor += read;
             array.push(val);
         }
@@ -262,26 +375,507 @@ pub mod number_encoding {
     }
 }
 
-/// Implements encoding and decoding of arrays.
+/// Implements the LEB128 encoding of numbers. Each byte holds 7 bits of the
+/// number, starting with the low bits, and the high bit of the byte is set if
+/// more bytes follow. Small numbers, like the lengths of short streams, take a
+/// single byte.
+pub mod leb128 {
+    /// The max number of bytes in the encoding of a 64-bit number.
+    pub const MAX_LEN: usize = 10;
+
+    /// Return the number of bytes in the encoding of 'val'.
+    pub fn encoded_len(val: u64) -> usize {
+        let bits = (u64::BITS - val.leading_zeros()).max(1);
+        bits.div_ceil(7) as usize
+    }
+
+    /// Encode 'val' into 'stream' and return the number of bytes written.
+    pub fn encode(mut val: u64, stream: &mut Vec<u8>) -> usize {
+        let mut written = 1;
+        while val >= 0x80 {
+            stream.push((val as u8) | 0x80);
+            val >>= 7;
+            written += 1;
+        }
+        stream.push(val as u8);
+        written
+    }
+
+    /// Decode a number from 'stream'. Returns the number of bytes read and the
+    /// number, or None if the encoding is truncated or too long.
+    pub fn decode(stream: &[u8]) -> Option<(usize, u64)> {
+        let mut val: u64 = 0;
+        for (i, byte) in stream.iter().take(MAX_LEN).enumerate() {
+            let bits = (*byte & 0x7f) as u64;
+            let shift = 7 * i as u32;
+            // Reject bits that don't fit in 64 bits.
+            if shift == 63 && bits > 1 {
+                return None;
+            }
+            val |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Some((i + 1, val));
+            }
+        }
+        None
+    }
+
+    /// Decode a length from 'stream'. See 'decode'.
+    pub fn decode_len(stream: &[u8]) -> Option<(usize, usize)> {
+        let (read, val) = decode(stream)?;
+        Some((read, usize::try_from(val).ok()?))
+    }
+}
+
+/// Implements encoding and decoding of arrays. The length of the array is
+/// encoded with LEB128.
 pub mod array_encoding {
-    use super::number_encoding;
+    use super::leb128;
 
     // Encode the array and return the number of bytes written.
     pub fn encode(array: &[u8], stream: &mut Vec<u8>) -> usize {
-        number_encoding::encode32(array.len() as u32, stream);
+        let written = leb128::encode(array.len() as u64, stream);
         stream.extend_from_slice(array);
-        array.len() + 4
+        array.len() + written
     }
 
     // Decode the array and return the number of items that were read.
     pub fn decode(stream: &[u8], array: &mut Vec<u8>) -> Option<usize> {
-        let (_, len) = number_encoding::decode32(stream)?;
-        let len = len as usize;
-        if stream[4..].len() < len {
+        let (read, len) = leb128::decode_len(stream)?;
+        let payload = stream[read..].get(..len)?;
+        array.extend(payload);
+        Some(read + len)
+    }
+}
+
+/// Packs arrays of small numbers, such as sensor samples of 1 to 16 bits,
+/// into the minimal number of bits. The array is split into blocks, and each
+/// block saves the distance of its values from the smallest value in the block
+/// (frame of reference), with the bit width of the largest distance.
+pub mod bitpack {
+    use super::leb128;
+    use super::number_encoding::{decode16, encode16, Endian};
+
+    /// The number of values in each block.
+    pub const BLOCK_SIZE: usize = 128;
+
+    /// Return the number of bits that are needed to represent 'val'.
+    fn bit_width(val: u16) -> u32 {
+        u16::BITS - val.leading_zeros()
+    }
+
+    /// Append the lowest 'width' bits of each value in 'values' to 'stream'.
+    /// This is shared with 'pfor', which packs 32-bit values.
+    pub(super) fn pack(
+        values: impl Iterator<Item = u32>,
+        width: u32,
+        stream: &mut Vec<u8>,
+    ) {
+        let mask = (1u64 << width) - 1;
+        let mut acc: u64 = 0;
+        let mut bits = 0;
+        for val in values {
+            acc |= (val as u64 & mask) << bits;
+            bits += width;
+            while bits >= 8 {
+                stream.push(acc as u8);
+                acc >>= 8;
+                bits -= 8;
+            }
+        }
+        if bits > 0 {
+            stream.push(acc as u8);
+        }
+    }
+
+    /// Encode 'values' into 'stream' and return the number of bytes written.
+    pub fn encode(values: &[u16], stream: &mut Vec<u8>) -> usize {
+        let start = stream.len();
+        leb128::encode(values.len() as u64, stream);
+        for block in values.chunks(BLOCK_SIZE) {
+            let min = *block.iter().min().unwrap();
+            let max = *block.iter().max().unwrap();
+            let width = bit_width(max - min);
+            encode16(min, stream, Endian::Big);
+            stream.push(width as u8);
+            pack(block.iter().map(|x| (x - min) as u32), width, stream);
+        }
+        stream.len() - start
+    }
+
+    /// Decode an array that was encoded with 'encode' into 'values'. Returns
+    /// the number of bytes read.
+    pub fn decode(stream: &[u8], values: &mut Vec<u16>) -> Option<usize> {
+        let (mut cursor, len) = leb128::decode_len(stream)?;
+        let mut left = len;
+        while left > 0 {
+            let count = left.min(BLOCK_SIZE);
+            let (read, min) = decode16(stream.get(cursor..)?, Endian::Big)?;
+            cursor += read;
+            let width = *stream.get(cursor)? as u32;
+            cursor += 1;
+            if width > u16::BITS {
+                return None;
+            }
+            let size = (count * width as usize).div_ceil(8);
+            let packed = stream.get(cursor..cursor + size)?;
+            cursor += size;
+
+            let mask = (1u64 << width) - 1;
+            let mut acc: u64 = 0;
+            let mut bits = 0;
+            let mut bytes = packed.iter();
+            for _ in 0..count {
+                while bits < width {
+                    acc |= (*bytes.next()? as u64) << bits;
+                    bits += 8;
+                }
+                let val = min.checked_add((acc & mask) as u16)?;
+                values.push(val);
+                acc >>= width;
+                bits -= width;
+            }
+            left -= count;
+        }
+        Some(cursor)
+    }
+}
+
+/// Implements the StreamVByte encoding of arrays of 32-bit numbers. The
+/// numbers are written with 1 to 4 bytes, and the lengths are saved as 2-bit
+/// codes in a separate stream of control bytes, one byte for each group of
+/// four numbers. The decoder reads the lengths of a whole group from a single
+/// control byte, without the branches of byte-oriented varints.
+/// Reference: Stream VByte, Lemire et al. 2017. <https://arxiv.org/abs/1709.08990>
+pub mod stream_vbyte {
+    use super::leb128;
+
+    /// Return the number of bytes that are needed to represent 'val'.
+    fn byte_len(val: u32) -> usize {
+        (4 - val.leading_zeros() as usize / 8).max(1)
+    }
+
+    /// Return the number of data bytes of the group with the control byte
+    /// 'control'.
+    const fn group_len(control: u8) -> usize {
+        let mut len = 0;
+        let mut i = 0;
+        while i < 4 {
+            len += ((control >> (i * 2)) & 3) as usize + 1;
+            i += 1;
+        }
+        len
+    }
+
+    /// Maps each control byte to the number of data bytes of its group.
+    const GROUP_LEN: [u8; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            table[i] = group_len(i as u8) as u8;
+            i += 1;
+        }
+        table
+    };
+
+    /// Encode 'values' into 'stream' and return the number of bytes written.
+    pub fn encode(values: &[u32], stream: &mut Vec<u8>) -> usize {
+        let start = stream.len();
+        leb128::encode(values.len() as u64, stream);
+        let controls = stream.len();
+        stream.resize(controls + values.len().div_ceil(4), 0);
+        for (i, val) in values.iter().enumerate() {
+            let len = byte_len(*val);
+            stream[controls + i / 4] |= ((len - 1) << ((i % 4) * 2)) as u8;
+            stream.extend(&val.to_le_bytes()[..len]);
+        }
+        stream.len() - start
+    }
+
+    /// Maps each control byte to the shuffle mask that moves the data bytes of
+    /// its group into four 32-bit lanes. Lanes bytes with the mask 0xff are
+    /// set to zero.
+    const SHUFFLE: [[u8; 16]; 256] = {
+        let mut table = [[0xff; 16]; 256];
+        let mut control = 0;
+        while control < 256 {
+            let mut src = 0;
+            let mut lane = 0;
+            while lane < 4 {
+                let len = ((control >> (lane * 2)) & 3) + 1;
+                let mut i = 0;
+                while i < len {
+                    table[control][lane * 4 + i] = src as u8;
+                    src += 1;
+                    i += 1;
+                }
+                lane += 1;
+            }
+            control += 1;
+        }
+        table
+    };
+
+    /// Decode the groups of the control bytes 'controls' from 'data' with
+    /// SIMD shuffles, while 16 bytes can be loaded. Returns the number of
+    /// groups that were decoded and the number of bytes that were read.
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "ssse3")]
+    unsafe fn decode_groups_simd(
+        controls: &[u8],
+        data: &[u8],
+        values: &mut Vec<u32>,
+    ) -> (usize, usize) {
+        use std::arch::x86_64::*;
+        let mut cursor = 0;
+        let mut groups = 0;
+        for control in controls {
+            if cursor + 16 > data.len() {
+                break;
+            }
+            let ptr = data.as_ptr().add(cursor) as *const __m128i;
+            let mask = SHUFFLE[*control as usize].as_ptr() as *const __m128i;
+            let lanes =
+                _mm_shuffle_epi8(_mm_loadu_si128(ptr), _mm_loadu_si128(mask));
+            let mut group = [0u32; 4];
+            _mm_storeu_si128(group.as_mut_ptr() as *mut __m128i, lanes);
+            values.extend_from_slice(&group);
+            cursor += GROUP_LEN[*control as usize] as usize;
+            groups += 1;
+        }
+        (groups, cursor)
+    }
+
+    /// See the x86 version.
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn decode_groups_simd(
+        controls: &[u8],
+        data: &[u8],
+        values: &mut Vec<u32>,
+    ) -> (usize, usize) {
+        use std::arch::aarch64::*;
+        let mut cursor = 0;
+        let mut groups = 0;
+        for control in controls {
+            if cursor + 16 > data.len() {
+                break;
+            }
+            let input = vld1q_u8(data.as_ptr().add(cursor));
+            let mask = vld1q_u8(SHUFFLE[*control as usize].as_ptr());
+            let mut group = [0u32; 4];
+            vst1q_u8(group.as_mut_ptr() as *mut u8, vqtbl1q_u8(input, mask));
+            values.extend_from_slice(&group);
+            cursor += GROUP_LEN[*control as usize] as usize;
+            groups += 1;
+        }
+        (groups, cursor)
+    }
+
+    /// Decode the groups of 'controls' with SIMD if the processor supports
+    /// it. Returns the number of groups that were decoded and the number of
+    /// bytes that were read. See 'decode_groups_simd'.
+    fn decode_groups(
+        controls: &[u8],
+        data: &[u8],
+        values: &mut Vec<u32>,
+    ) -> (usize, usize) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("ssse3") {
+            // SAFETY: The processor supports SSSE3, and the loads are in
+            // the bounds of 'data' and of the shuffle table.
+            return unsafe { decode_groups_simd(controls, data, values) };
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            // SAFETY: NEON is always available on aarch64, and the loads are
+            // in the bounds of 'data' and of the shuffle table.
+            return unsafe { decode_groups_simd(controls, data, values) };
+        }
+        #[allow(unreachable_code)]
+        (0, 0)
+    }
+
+    /// Decode an array that was encoded with 'encode' into 'values'. Returns
+    /// the number of bytes read.
+    pub fn decode(stream: &[u8], values: &mut Vec<u32>) -> Option<usize> {
+        let (read, len) = leb128::decode_len(stream)?;
+        let controls = stream.get(read..read + len.div_ceil(4))?;
+        let mut cursor = read + controls.len();
+        values.reserve(len);
+
+        // Decode the full groups with SIMD, and the rest one value at a time.
+        let (groups, size) =
+            decode_groups(&controls[..len / 4], &stream[cursor..], values);
+        cursor += size;
+
+        for (i, control) in controls.iter().enumerate().skip(groups) {
+            let count = (len - i * 4).min(4);
+            for j in 0..count {
+                let n = ((control >> (j * 2)) & 3) as usize + 1;
+                let mut bytes = [0; 4];
+                bytes[..n].copy_from_slice(stream.get(cursor..cursor + n)?);
+                values.push(u32::from_le_bytes(bytes));
+                cursor += n;
+            }
+        }
+        Some(cursor)
+    }
+}
+
+/// Implements the PFor-Delta encoding of arrays of 32-bit numbers that are
+/// sorted or clustered, such as offsets and posting lists. Sorted arrays save
+/// the differences between neighbors. The array is split into blocks, and each
+/// block saves the distance of its values from the smallest value in the block
+/// (frame of reference) with a bit width that fits most of the values. The few
+/// values that don't fit are patched: their high bits are saved in a list of
+/// exceptions after the packed bits.
+/// Reference: Super-Scalar RAM-CPU Cache Compression, Zukowski et al. 2006.
+pub mod pfor {
+    use super::bitpack::pack;
+    use super::leb128;
+
+    /// The number of values in each block.
+    pub const BLOCK_SIZE: usize = 128;
+
+    /// Return the number of bits that are needed to represent 'val'.
+    fn bit_width(val: u32) -> u32 {
+        u32::BITS - val.leading_zeros()
+    }
+
+    /// Return the number of bytes of a block with the distances 'values',
+    /// packed with 'width' bits, without the header.
+    fn block_cost(values: &[u32], width: u32) -> usize {
+        let exceptions: usize = values
+            .iter()
+            .filter(|val| bit_width(**val) > width)
+            .map(|val| 1 + leb128::encoded_len((val >> width) as u64))
+            .sum();
+        (values.len() * width as usize).div_ceil(8) + exceptions
+    }
+
+    /// Encode one block of 'values' into 'stream'.
+    fn encode_block(values: &[u32], stream: &mut Vec<u8>) {
+        let min = *values.iter().min().unwrap();
+        let dists: Vec<u32> = values.iter().map(|x| x - min).collect();
+        let width = (0..=u32::BITS)
+            .min_by_key(|width| block_cost(&dists, *width))
+            .unwrap();
+        let exceptions: Vec<usize> = (0..dists.len())
+            .filter(|i| bit_width(dists[*i]) > width)
+            .collect();
+
+        leb128::encode(min as u64, stream);
+        stream.push(width as u8);
+        stream.push(exceptions.len() as u8);
+        pack(dists.iter().copied(), width, stream);
+        for i in exceptions {
+            stream.push(i as u8);
+            leb128::encode((dists[i] >> width) as u64, stream);
+        }
+    }
+
+    /// Encode 'values' into 'stream' and return the number of bytes written.
+    pub fn encode(values: &[u32], stream: &mut Vec<u8>) -> usize {
+        let start = stream.len();
+        let sorted = values.windows(2).all(|w| w[0] <= w[1]);
+        leb128::encode(values.len() as u64, stream);
+        stream.push(sorted as u8);
+        let mut prev = 0;
+        for block in values.chunks(BLOCK_SIZE) {
+            if sorted {
+                let deltas: Vec<u32> = block
+                    .iter()
+                    .map(|val| {
+                        let delta = val - prev;
+                        prev = *val;
+                        delta
+                    })
+                    .collect();
+                encode_block(&deltas, stream);
+            } else {
+                encode_block(block, stream);
+            }
+        }
+        stream.len() - start
+    }
+
+    /// Decode one block of 'count' values from 'stream' into 'values'.
+    /// Returns the number of bytes read.
+    fn decode_block(
+        stream: &[u8],
+        count: usize,
+        values: &mut Vec<u32>,
+    ) -> Option<usize> {
+        let (mut cursor, min) = leb128::decode(stream)?;
+        let min = u32::try_from(min).ok()?;
+        let width = *stream.get(cursor)? as u32;
+        let exceptions = *stream.get(cursor + 1)? as usize;
+        cursor += 2;
+        if width > u32::BITS || exceptions > count {
             return None;
         }
-        array.extend(&stream[4..len + 4]);
-        Some(4 + len)
+        let size = (count * width as usize).div_ceil(8);
+        let packed = stream.get(cursor..cursor + size)?;
+        cursor += size;
+
+        let start = values.len();
+        let mask = (1u64 << width) - 1;
+        let mut acc: u64 = 0;
+        let mut bits = 0;
+        let mut bytes = packed.iter();
+        for _ in 0..count {
+            while bits < width {
+                acc |= (*bytes.next()? as u64) << bits;
+                bits += 8;
+            }
+            values.push((acc & mask) as u32);
+            acc >>= width;
+            bits -= width;
+        }
+
+        // Patch the high bits of the exceptions.
+        let block = &mut values[start..];
+        for _ in 0..exceptions {
+            let idx = *stream.get(cursor)? as usize;
+            let (read, high) = leb128::decode(stream.get(cursor + 1..)?)?;
+            cursor += 1 + read;
+            // The high bits must fit in the bits above the packed bits.
+            if width == u32::BITS || high >> (u32::BITS - width) != 0 {
+                return None;
+            }
+            *block.get_mut(idx)? |= (high as u32) << width;
+        }
+        for val in block.iter_mut() {
+            *val = val.checked_add(min)?;
+        }
+        Some(cursor)
+    }
+
+    /// Decode an array that was encoded with 'encode' into 'values'. Returns
+    /// the number of bytes read.
+    pub fn decode(stream: &[u8], values: &mut Vec<u32>) -> Option<usize> {
+        let (mut cursor, len) = leb128::decode_len(stream)?;
+        let sorted = match *stream.get(cursor)? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        cursor += 1;
+        let mut left = len;
+        let mut prev: u32 = 0;
+        while left > 0 {
+            let count = left.min(BLOCK_SIZE);
+            let start = values.len();
+            cursor += decode_block(stream.get(cursor..)?, count, values)?;
+            if sorted {
+                for val in &mut values[start..] {
+                    prev = prev.checked_add(*val)?;
+                    *val = prev;
+                }
+            }
+            left -= count;
+        }
+        Some(cursor)
     }
 }
 
@@ -295,7 +889,9 @@ pub mod array_encoding {
 /// <https://github.com/facebook/zstd/blob/dev/doc/zstd_compression_format.md#offset-codes>
 pub mod two_stream_encoding {
     use super::number_encoding;
+    use super::number_encoding::Endian;
     use crate::bitvector::Bitvector;
+    use crate::limits::check_input;
 
     /// Encode 'val' into a token, and stores the extra bits into 'bv'.
     pub fn encode32(val: u32, bv: &mut Bitvector) -> u32 {
@@ -309,6 +905,33 @@ pub mod two_stream_encoding {
         (1 << code) + bv.pop_word(code as usize) as u32 - 1
     }
 
+    /// Decode the values of all of the tokens in 'tokens' into 'output'. The
+    /// position of the extra bits of each token is the sum of the widths of
+    /// the earlier tokens, so the bits are read in order, without popping them
+    /// from the end of 'bv'. Returns None if the bits don't match the tokens.
+    pub fn decode_tokens(
+        tokens: &[u8],
+        bv: &Bitvector,
+        output: &mut Vec<u32>,
+    ) -> Option<()> {
+        let words = bv.to_words();
+        output.reserve(tokens.len());
+        let mut pos = 0;
+        for code in tokens {
+            let code = *code as usize;
+            if code >= 32 || pos + code > bv.len() {
+                return None;
+            }
+            let bits = Bitvector::read_bits(&words, pos, code);
+            output.push(((1u64 << code) + bits - 1) as u32);
+            pos += code;
+        }
+        if pos != bv.len() {
+            return None;
+        }
+        Some(())
+    }
+
     #[test]
     fn test_two_stream_encoding_simple() {
         let mut bv = Bitvector::new();
@@ -352,7 +975,8 @@ pub mod two_stream_encoding {
         stream: &mut Vec<u8>,
         bv: &mut Bitvector,
     ) -> usize {
-        let written = number_encoding::encode32(array.len() as u32, stream);
+        let written =
+            number_encoding::encode32(array.len() as u32, stream, Endian::Big);
         for val in array {
             stream.push(encode32(*val, bv) as u8);
         }
@@ -368,8 +992,8 @@ pub mod two_stream_encoding {
         // We need to process the values in reverse, because the bits are
         // stored in the bitvector in reverse.
         let mut res = Vec::new();
-        let (read, len) = number_encoding::decode32(stream)?;
-        let len = len as usize;
+        let (read, len) = number_encoding::decode32(stream, Endian::Big)?;
+        let len = check_input(stream, read, len as usize)?;
         for i in 0..len {
             res.push(decode32(stream[read + (len - i - 1)] as u32, bv));
         }
@@ -399,160 +1023,493 @@ pub mod two_stream_encoding {
     }
 }
 
+/// Indexing for the hottest loops of the codecs, such as the match length of
+/// the matcher, the copies of matches and the table lookups of the entropy
+/// decoder. The callers validate the indices before the loops, at the
+/// boundaries of blocks and sequences. With the 'unsafe-fast' feature the
+/// bounds are not checked again, and with the default build every index is
+/// checked, so an error in the validation panics instead of reading out of
+/// bounds.
+pub mod unchecked {
+    use std::ops::Range;
+
+    /// Return the element 'idx' of 'slice'. The index must be in bounds.
+    #[inline(always)]
+    pub fn get<T: Copy>(slice: &[T], idx: usize) -> T {
+        #[cfg(feature = "unsafe-fast")]
+        {
+            debug_assert!(idx < slice.len());
+            // SAFETY: The callers validate the index.
+            unsafe { *slice.get_unchecked(idx) }
+        }
+        #[cfg(not(feature = "unsafe-fast"))]
+        slice[idx]
+    }
+
+    /// Return the elements 'range' of 'slice'. The range must be in bounds.
+    #[inline(always)]
+    pub fn range<T>(slice: &[T], range: Range<usize>) -> &[T] {
+        #[cfg(feature = "unsafe-fast")]
+        {
+            debug_assert!(range.start <= range.end && range.end <= slice.len());
+            // SAFETY: The callers validate the range.
+            unsafe { slice.get_unchecked(range) }
+        }
+        #[cfg(not(feature = "unsafe-fast"))]
+        &slice[range]
+    }
+
+    /// The number of bytes that 'copy_chunks' may write after the copy.
+    pub const WILD_COPY_SLACK: usize = 8;
+
+    /// Append 'len' bytes to 'output' that are copied from 'offset' bytes
+    /// before its end, in chunks of 8 bytes, like 'copy_match'. The last chunk
+    /// may write up to 'WILD_COPY_SLACK' bytes past the copy, into the spare
+    /// capacity of the vector. The offset must be at least 8 and at most the
+    /// length of 'output', so each chunk only reads bytes that were written.
+    #[inline(always)]
+    pub fn copy_chunks(output: &mut Vec<u8>, offset: usize, len: usize) {
+        assert!(offset >= WILD_COPY_SLACK && offset <= output.len());
+        let start = output.len() - offset;
+        #[cfg(feature = "unsafe-fast")]
+        {
+            output.reserve(len + WILD_COPY_SLACK);
+            // SAFETY: The chunks are written below 'len + WILD_COPY_SLACK'
+            // bytes after the end, which were reserved. Each chunk is read at
+            // least 8 bytes before it is written, so the chunks don't overlap,
+            // and they read initialized bytes.
+            unsafe {
+                let base = output.as_mut_ptr();
+                let end = output.len();
+                let mut done = 0;
+                while done < len {
+                    let src = base.add(start + done);
+                    let dst = base.add(end + done);
+                    std::ptr::copy_nonoverlapping(src, dst, WILD_COPY_SLACK);
+                    done += WILD_COPY_SLACK;
+                }
+                output.set_len(end + len);
+            }
+        }
+        #[cfg(not(feature = "unsafe-fast"))]
+        {
+            let mut done = 0;
+            while done < len {
+                let chunk = (len - done).min(WILD_COPY_SLACK);
+                output.extend_from_within(start + done..start + done + chunk);
+                done += chunk;
+            }
+        }
+    }
+}
+
+/// Implements fast non-cryptographic hash functions, for checksums,
+/// dictionary lookups and content-defined chunking.
+/// Reference: <https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md>
+pub mod hash {
+    const PRIME32_1: u32 = 0x9E3779B1;
+    const PRIME32_2: u32 = 0x85EBCA77;
+    const PRIME32_3: u32 = 0xC2B2AE3D;
+    const PRIME32_4: u32 = 0x27D4EB2F;
+    const PRIME32_5: u32 = 0x165667B1;
+
+    const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+    const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+    const PRIME64_3: u64 = 0x165667B19E3779F9;
+    const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+    const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+    fn read32_le(input: &[u8], idx: usize) -> u32 {
+        u32::from_le_bytes(input[idx..idx + 4].try_into().unwrap())
+    }
+
+    fn read64_le(input: &[u8], idx: usize) -> u64 {
+        u64::from_le_bytes(input[idx..idx + 8].try_into().unwrap())
+    }
+
+    fn round32(acc: u32, lane: u32) -> u32 {
+        let acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_2));
+        acc.rotate_left(13).wrapping_mul(PRIME32_1)
+    }
+
+    /// Compute the 32-bit xxHash of 'input' with the seed 'seed'.
+    pub fn xxh32(input: &[u8], seed: u32) -> u32 {
+        let len = input.len();
+        let mut idx = 0;
+
+        let mut acc = if len >= 16 {
+            let mut v1 = seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2);
+            let mut v2 = seed.wrapping_add(PRIME32_2);
+            let mut v3 = seed;
+            let mut v4 = seed.wrapping_sub(PRIME32_1);
+            // Process the input in stripes of 16 bytes.
+            while idx + 16 <= len {
+                v1 = round32(v1, read32_le(input, idx));
+                v2 = round32(v2, read32_le(input, idx + 4));
+                v3 = round32(v3, read32_le(input, idx + 8));
+                v4 = round32(v4, read32_le(input, idx + 12));
+                idx += 16;
+            }
+            v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18))
+        } else {
+            seed.wrapping_add(PRIME32_5)
+        };
+
+        acc = acc.wrapping_add(len as u32);
+
+        // Consume the remaining input.
+        while idx + 4 <= len {
+            let lane = read32_le(input, idx);
+            acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_3));
+            acc = acc.rotate_left(17).wrapping_mul(PRIME32_4);
+            idx += 4;
+        }
+        while idx < len {
+            let lane = input[idx] as u32;
+            acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_5));
+            acc = acc.rotate_left(11).wrapping_mul(PRIME32_1);
+            idx += 1;
+        }
+
+        // Mix the bits of the accumulator.
+        acc ^= acc >> 15;
+        acc = acc.wrapping_mul(PRIME32_2);
+        acc ^= acc >> 13;
+        acc = acc.wrapping_mul(PRIME32_3);
+        acc ^ (acc >> 16)
+    }
+
+    fn round64(acc: u64, lane: u64) -> u64 {
+        let acc = acc.wrapping_add(lane.wrapping_mul(PRIME64_2));
+        acc.rotate_left(31).wrapping_mul(PRIME64_1)
+    }
+
+    fn merge64(acc: u64, val: u64) -> u64 {
+        let acc = acc ^ round64(0, val);
+        acc.wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+    }
+
+    /// Compute the 64-bit xxHash of 'input' with the seed 'seed'.
+    pub fn xxh64(input: &[u8], seed: u64) -> u64 {
+        let len = input.len();
+        let mut idx = 0;
+
+        let mut acc = if len >= 32 {
+            let mut v1 = seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2);
+            let mut v2 = seed.wrapping_add(PRIME64_2);
+            let mut v3 = seed;
+            let mut v4 = seed.wrapping_sub(PRIME64_1);
+            // Process the input in stripes of 32 bytes.
+            while idx + 32 <= len {
+                v1 = round64(v1, read64_le(input, idx));
+                v2 = round64(v2, read64_le(input, idx + 8));
+                v3 = round64(v3, read64_le(input, idx + 16));
+                v4 = round64(v4, read64_le(input, idx + 24));
+                idx += 32;
+            }
+            let mut acc = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            acc = merge64(acc, v1);
+            acc = merge64(acc, v2);
+            acc = merge64(acc, v3);
+            merge64(acc, v4)
+        } else {
+            seed.wrapping_add(PRIME64_5)
+        };
+
+        acc = acc.wrapping_add(len as u64);
+
+        // Consume the remaining input.
+        while idx + 8 <= len {
+            acc ^= round64(0, read64_le(input, idx));
+            acc = acc.rotate_left(27).wrapping_mul(PRIME64_1);
+            acc = acc.wrapping_add(PRIME64_4);
+            idx += 8;
+        }
+        if idx + 4 <= len {
+            let lane = read32_le(input, idx) as u64;
+            acc ^= lane.wrapping_mul(PRIME64_1);
+            acc = acc.rotate_left(23).wrapping_mul(PRIME64_2);
+            acc = acc.wrapping_add(PRIME64_3);
+            idx += 4;
+        }
+        while idx < len {
+            acc ^= (input[idx] as u64).wrapping_mul(PRIME64_5);
+            acc = acc.rotate_left(11).wrapping_mul(PRIME64_1);
+            idx += 1;
+        }
+
+        // Mix the bits of the accumulator.
+        acc ^= acc >> 33;
+        acc = acc.wrapping_mul(PRIME64_2);
+        acc ^= acc >> 29;
+        acc = acc.wrapping_mul(PRIME64_3);
+        acc ^ (acc >> 32)
+    }
+
+    /// Compute the 32-bit FNV-1a hash of 'input'.
+    pub fn fnv1a32(input: &[u8]) -> u32 {
+        let mut hash: u32 = 0x811c9dc5;
+        for b in input {
+            hash ^= *b as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+        hash
+    }
+
+    /// Compute the 64-bit FNV-1a hash of 'input'.
+    pub fn fnv1a64(input: &[u8]) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for b in input {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    /// Hash the word 'val' into an index in the range 0..(1 << bits), using
+    /// multiplicative (Fibonacci) hashing.
+    pub fn mul_hash32(val: u32, bits: usize) -> usize {
+        debug_assert!(bits > 0 && bits <= 32);
+        let val = val.wrapping_mul(0x797124e5);
+        (val >> (32 - bits)) as usize
+    }
+
+    /// Hash the word 'val' into an index in the range 0..(1 << bits), like
+    /// 'mul_hash32' does for 32-bit words.
+    pub fn mul_hash64(val: u64, bits: usize) -> usize {
+        debug_assert!(bits > 0 && bits <= 32);
+        let val = val.wrapping_mul(0x9fb21c651e98df25);
+        (val >> (64 - bits)) as usize
+    }
+
+    /// Generate a table of pseudo-random numbers using splitmix64.
+    const fn build_gear_table() -> [u64; 256] {
+        let mut table = [0; 256];
+        let mut state: u64 = 0;
+        let mut i = 0;
+        while i < 256 {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            table[i] = z ^ (z >> 31);
+            i += 1;
+        }
+        table
+    }
+
+    /// A table of random numbers that is used by the gear hash. The gear hash
+    /// is updated with hash = (hash << 1) + GEAR[byte], and the contribution
+    /// of each byte is shifted out after 64 bytes.
+    pub static GEAR: [u64; 256] = build_gear_table();
+
+    /// The multiplier of the rolling hash polynomial.
+    const ROLLING_BASE: u32 = 0x01000193;
+
+    /// A Rabin–Karp rolling hash over a fixed-size window of bytes. The hash is
+    /// the polynomial sum(b[i] * BASE^(window-1-i)) modulo 2^32, which allows
+    /// sliding the window by one byte in constant time.
+    #[derive(Clone)]
+    pub struct RollingHash {
+        /// The current value of the hash.
+        hash: u32,
+        /// The factor (BASE^window) of the byte that leaves the window.
+        out_factor: u32,
+    }
+
+    impl RollingHash {
+        /// Create a new rolling hash for windows of 'window' bytes.
+        pub fn new(window: usize) -> Self {
+            let mut out_factor: u32 = 1;
+            for _ in 0..window {
+                out_factor = out_factor.wrapping_mul(ROLLING_BASE);
+            }
+            Self {
+                hash: 0,
+                out_factor,
+            }
+        }
+
+        /// Append the byte 'incoming' to the window without removing a byte.
+        /// This is used for filling the first window.
+        pub fn update(&mut self, incoming: u8) {
+            self.hash = self.hash.wrapping_mul(ROLLING_BASE);
+            self.hash = self.hash.wrapping_add(incoming as u32);
+        }
+
+        /// Slide the window by one byte: remove 'outgoing' from the start of
+        /// the window and append 'incoming' at the end.
+        pub fn roll(&mut self, outgoing: u8, incoming: u8) {
+            self.update(incoming);
+            let out = (outgoing as u32).wrapping_mul(self.out_factor);
+            self.hash = self.hash.wrapping_sub(out);
+        }
+
+        /// Return the hash of the current window.
+        pub fn hash(&self) -> u32 {
+            self.hash
+        }
+
+        /// Clear the window.
+        pub fn reset(&mut self) {
+            self.hash = 0;
+        }
+    }
+}
+
+/// Implements fixed-point logistic functions for mixing probabilities without
+/// floating point. Probabilities are represented in the 12-bit domain (0..4096)
+/// and the logistic domain is scaled by 8 bits (-2047..2047).
+/// stretch(p) = ln(p/(1-p)) and squash(x) = 1/(1+exp(-x)) are inverses.
+/// Reference: lpaq1 by Matt Mahoney.
+pub mod prob {
+    /// The number of bits in the probability domain of the tables.
+    pub const PROB_BITS: u32 = 12;
+    /// The max value in the logistic domain.
+    pub const MAX_STRETCH: i32 = 2047;
+    /// The lowest 16-bit probability that we allow after clamping. Probabilities
+    /// of exactly 0 or 1 make the arithmetic coder expand unexpected bits.
+    pub const MIN_PROB16: u16 = 32;
+
+    /// The values of squash(x) at 33 points in the range -2048..2048, that are
+    /// interpolated to compute the rest of the table.
+    const SQUASH_POINTS: [i32; 33] = [
+        1, 2, 3, 6, 10, 16, 27, 45, 73, 120, 194, 310, 488, 747, 1101, 1546,
+        2047, 2549, 2994, 3348, 3607, 3785, 3901, 3975, 4022, 4050, 4068, 4079,
+        4085, 4089, 4092, 4093, 4094,
+    ];
+
+    /// Compute squash(x) by interpolating the points in 'SQUASH_POINTS'.
+    const fn squash_slow(x: i32) -> u16 {
+        if x > MAX_STRETCH {
+            return 4095;
+        }
+        if x < -MAX_STRETCH {
+            return 0;
+        }
+        let w = x & 127;
+        let i = ((x >> 7) + 16) as usize;
+        let a = SQUASH_POINTS[i] * (128 - w);
+        let b = SQUASH_POINTS[i + 1] * w;
+        ((a + b + 64) >> 7) as u16
+    }
+
+    /// Build the squash table, indexed by x + 2048.
+    const fn build_squash_table() -> [u16; 4096] {
+        let mut table = [0; 4096];
+        let mut i = 0;
+        while i < 4096 {
+            table[i] = squash_slow(i as i32 - 2048);
+            i += 1;
+        }
+        table
+    }
+
+    /// Build the stretch table by inverting the squash function.
+    const fn build_stretch_table() -> [i16; 4096] {
+        let mut table = [0; 4096];
+        let mut prev = 0;
+        let mut x = -MAX_STRETCH;
+        while x <= MAX_STRETCH {
+            let p = squash_slow(x) as usize;
+            let mut j = prev;
+            while j <= p {
+                table[j] = x as i16;
+                j += 1;
+            }
+            prev = p + 1;
+            x += 1;
+        }
+        while prev < 4096 {
+            table[prev] = MAX_STRETCH as i16;
+            prev += 1;
+        }
+        table
+    }
+
+    static SQUASH_TABLE: [u16; 4096] = build_squash_table();
+    static STRETCH_TABLE: [i16; 4096] = build_stretch_table();
+
+    /// Return the 12-bit probability 1/(1+exp(-x)), where 'x' is scaled by 8
+    /// bits. Values outside of the range -2047..2047 are saturated.
+    pub fn squash(x: i32) -> u16 {
+        if x > MAX_STRETCH {
+            return 4095;
+        }
+        SQUASH_TABLE[(x.max(-2048) + 2048) as usize]
+    }
+
+    /// Return ln(p/(1-p)) scaled by 8 bits, for the 12-bit probability 'p'.
+    pub fn stretch(p: u16) -> i16 {
+        debug_assert!(p < 4096, "Expected a 12-bit probability");
+        STRETCH_TABLE[(p & 4095) as usize]
+    }
+
+    /// Convert a 16-bit probability into the 12-bit domain.
+    pub fn to_prob12(p: u16) -> u16 {
+        p >> 4
+    }
+
+    /// Convert a 12-bit probability into the 16-bit domain.
+    pub fn to_prob16(p: u16) -> u16 {
+        debug_assert!(p < 4096, "Expected a 12-bit probability");
+        (p << 4) | (p >> 8)
+    }
+
+    /// Clamp the 16-bit probability 'p' away from 0 and 65535.
+    pub fn clamp16(p: u16) -> u16 {
+        p.clamp(MIN_PROB16, u16::MAX - MIN_PROB16)
+    }
+
+    /// Clamp the 12-bit probability 'p' away from 0 and 4095.
+    pub fn clamp12(p: u16) -> u16 {
+        p.clamp(1, 4094)
+    }
+}
+
+/// The number of entries in 'RECIPROCAL_U32'.
+pub const RECIPROCAL_SIZE: usize = 1024;
+
+/// Return the table of the reciprocals, which is defined as (1<<32)/i.
+/// The table is computed at compile time.
+const fn reciprocal_table() -> [u32; RECIPROCAL_SIZE] {
+    let mut table = [0; RECIPROCAL_SIZE];
+    let mut i = 1;
+    while i < RECIPROCAL_SIZE {
+        table[i] = u32::MAX / i as u32;
+        i += 1;
+    }
+    table
+}
+
 /// A lookup table that computes the reciprocal of u16 division.
-/// The tables is defined as (1<<32)/i;
-pub static RECIPROCAL_U32: [u32; 1024] = [
-    0, 0xffffffff, 0x7fffffff, 0x55555555, 0x3fffffff, 0x33333333, 0x2aaaaaaa,
-    0x24924924, 0x1fffffff, 0x1c71c71c, 0x19999999, 0x1745d174, 0x15555555,
-    0x13b13b13, 0x12492492, 0x11111111, 0xfffffff, 0xf0f0f0f, 0xe38e38e,
-    0xd79435e, 0xccccccc, 0xc30c30c, 0xba2e8ba, 0xb21642c, 0xaaaaaaa,
-    0xa3d70a3, 0x9d89d89, 0x97b425e, 0x9249249, 0x8d3dcb0, 0x8888888,
-    0x8421084, 0x7ffffff, 0x7c1f07c, 0x7878787, 0x7507507, 0x71c71c7,
-    0x6eb3e45, 0x6bca1af, 0x6906906, 0x6666666, 0x63e7063, 0x6186186,
-    0x5f417d0, 0x5d1745d, 0x5b05b05, 0x590b216, 0x572620a, 0x5555555,
-    0x5397829, 0x51eb851, 0x5050505, 0x4ec4ec4, 0x4d4873e, 0x4bda12f,
-    0x4a7904a, 0x4924924, 0x47dc11f, 0x469ee58, 0x456c797, 0x4444444,
-    0x4325c53, 0x4210842, 0x4104104, 0x3ffffff, 0x3f03f03, 0x3e0f83e,
-    0x3d22635, 0x3c3c3c3, 0x3b5cc0e, 0x3a83a83, 0x39b0ad1, 0x38e38e3,
-    0x381c0e0, 0x3759f22, 0x369d036, 0x35e50d7, 0x3531dec, 0x3483483,
-    0x33d91d2, 0x3333333, 0x329161f, 0x31f3831, 0x3159721, 0x30c30c3,
-    0x3030303, 0x2fa0be8, 0x2f14990, 0x2e8ba2e, 0x2e05c0b, 0x2d82d82,
-    0x2d02d02, 0x2c8590b, 0x2c0b02c, 0x2b93105, 0x2b1da46, 0x2aaaaaa,
-    0x2a3a0fd, 0x29cbc14, 0x295fad4, 0x28f5c28, 0x288df0c, 0x2828282,
-    0x27c4597, 0x2762762, 0x2702702, 0x26a439f, 0x2647c69, 0x25ed097,
-    0x2593f69, 0x253c825, 0x24e6a17, 0x2492492, 0x243f6f0, 0x23ee08f,
-    0x239e0d5, 0x234f72c, 0x2302302, 0x22b63cb, 0x226b902, 0x2222222,
-    0x21d9ead, 0x2192e29, 0x214d021, 0x2108421, 0x20c49ba, 0x2082082,
-    0x2040810, 0x1ffffff, 0x1fc07f0, 0x1f81f81, 0x1f44659, 0x1f07c1f,
-    0x1ecc07b, 0x1e9131a, 0x1e573ac, 0x1e1e1e1, 0x1de5d6e, 0x1dae607,
-    0x1d77b65, 0x1d41d41, 0x1d0cb58, 0x1cd8568, 0x1ca4b30, 0x1c71c71,
-    0x1c3f8f0, 0x1c0e070, 0x1bdd2b8, 0x1bacf91, 0x1b7d6c3, 0x1b4e81b,
-    0x1b20364, 0x1af286b, 0x1ac5701, 0x1a98ef6, 0x1a6d01a, 0x1a41a41,
-    0x1a16d3f, 0x19ec8e9, 0x19c2d14, 0x1999999, 0x1970e4f, 0x1948b0f,
-    0x1920fb4, 0x18f9c18, 0x18d3018, 0x18acb90, 0x1886e5f, 0x1861861,
-    0x183c977, 0x1818181, 0x17f405f, 0x17d05f4, 0x17ad220, 0x178a4c8,
-    0x1767dce, 0x1745d17, 0x1724287, 0x1702e05, 0x16e1f76, 0x16c16c1,
-    0x16a13cd, 0x1681681, 0x1661ec6, 0x1642c85, 0x1623fa7, 0x1605816,
-    0x15e75bb, 0x15c9882, 0x15ac056, 0x158ed23, 0x1571ed3, 0x1555555,
-    0x1539094, 0x151d07e, 0x1501501, 0x14e5e0a, 0x14cab88, 0x14afd6a,
-    0x149539e, 0x147ae14, 0x1460cbc, 0x1446f86, 0x142d662, 0x1414141,
-    0x13fb013, 0x13e22cb, 0x13c995a, 0x13b13b1, 0x13991c2, 0x1381381,
-    0x13698df, 0x13521cf, 0x133ae45, 0x1323e34, 0x130d190, 0x12f684b,
-    0x12e025c, 0x12c9fb4, 0x12b404a, 0x129e412, 0x1288b01, 0x127350b,
-    0x125e227, 0x1249249, 0x1234567, 0x121fb78, 0x120b470, 0x11f7047,
-    0x11e2ef3, 0x11cf06a, 0x11bb4a4, 0x11a7b96, 0x1194538, 0x1181181,
-    0x116e068, 0x115b1e5, 0x11485f0, 0x1135c81, 0x112358e, 0x1111111,
-    0x10fef01, 0x10ecf56, 0x10db20a, 0x10c9714, 0x10b7e6e, 0x10a6810,
-    0x10953f3, 0x1084210, 0x1073260, 0x10624dd, 0x105197f, 0x1041041,
-    0x103091b, 0x1020408, 0x1010101, 0xffffff, 0xff00ff, 0xfe03f8, 0xfd08e5,
-    0xfc0fc0, 0xfb1885, 0xfa232c, 0xf92fb2, 0xf83e0f, 0xf74e3f, 0xf6603d,
-    0xf57403, 0xf4898d, 0xf3a0d5, 0xf2b9d6, 0xf1d48b, 0xf0f0f0, 0xf00f00,
-    0xef2eb7, 0xee500e, 0xed7303, 0xec9791, 0xebbdb2, 0xeae564, 0xea0ea0,
-    0xe93965, 0xe865ac, 0xe79372, 0xe6c2b4, 0xe5f36c, 0xe52598, 0xe45932,
-    0xe38e38, 0xe2c4a6, 0xe1fc78, 0xe135a9, 0xe07038, 0xdfac1f, 0xdee95c,
-    0xde27eb, 0xdd67c8, 0xdca8f1, 0xdbeb61, 0xdb2f17, 0xda740d, 0xd9ba42,
-    0xd901b2, 0xd84a59, 0xd79435, 0xd6df43, 0xd62b80, 0xd578e9, 0xd4c77b,
-    0xd41732, 0xd3680d, 0xd2ba08, 0xd20d20, 0xd16154, 0xd0b69f, 0xd00d00,
-    0xcf6474, 0xcebcf8, 0xce168a, 0xcd7127, 0xcccccc, 0xcc2978, 0xcb8727,
-    0xcae5d8, 0xca4587, 0xc9a633, 0xc907da, 0xc86a78, 0xc7ce0c, 0xc73293,
-    0xc6980c, 0xc5fe74, 0xc565c8, 0xc4ce07, 0xc4372f, 0xc3a13d, 0xc30c30,
-    0xc27806, 0xc1e4bb, 0xc15250, 0xc0c0c0, 0xc0300c, 0xbfa02f, 0xbf112a,
-    0xbe82fa, 0xbdf59c, 0xbd6910, 0xbcdd53, 0xbc5264, 0xbbc840, 0xbb3ee7,
-    0xbab656, 0xba2e8b, 0xb9a786, 0xb92143, 0xb89bc3, 0xb81702, 0xb79300,
-    0xb70fbb, 0xb68d31, 0xb60b60, 0xb58a48, 0xb509e6, 0xb48a39, 0xb40b40,
-    0xb38cf9, 0xb30f63, 0xb2927c, 0xb21642, 0xb19ab5, 0xb11fd3, 0xb0a59b,
-    0xb02c0b, 0xafb321, 0xaf3add, 0xaec33e, 0xae4c41, 0xadd5e6, 0xad602b,
-    0xaceb0f, 0xac7691, 0xac02b0, 0xab8f69, 0xab1cbd, 0xaaaaaa, 0xaa392f,
-    0xa9c84a, 0xa957fa, 0xa8e83f, 0xa87917, 0xa80a80, 0xa79c7b, 0xa72f05,
-    0xa6c21d, 0xa655c4, 0xa5e9f6, 0xa57eb5, 0xa513fd, 0xa4a9cf, 0xa44029,
-    0xa3d70a, 0xa36e71, 0xa3065e, 0xa29ecf, 0xa237c3, 0xa1d139, 0xa16b31,
-    0xa105a9, 0xa0a0a0, 0xa03c16, 0x9fd809, 0x9f747a, 0x9f1165, 0x9eaecc,
-    0x9e4cad, 0x9deb06, 0x9d89d8, 0x9d2921, 0x9cc8e1, 0x9c6916, 0x9c09c0,
-    0x9baade, 0x9b4c6f, 0x9aee72, 0x9a90e7, 0x9a33cd, 0x99d722, 0x997ae7,
-    0x991f1a, 0x98c3ba, 0x9868c8, 0x980e41, 0x97b425, 0x975a75, 0x97012e,
-    0x96a850, 0x964fda, 0x95f7cc, 0x95a025, 0x9548e4, 0x94f209, 0x949b92,
-    0x944580, 0x93efd1, 0x939a85, 0x93459b, 0x92f113, 0x929ceb, 0x924924,
-    0x91f5bc, 0x91a2b3, 0x915009, 0x90fdbc, 0x90abcc, 0x905a38, 0x900900,
-    0x8fb823, 0x8f67a1, 0x8f1779, 0x8ec7ab, 0x8e7835, 0x8e2917, 0x8dda52,
-    0x8d8be3, 0x8d3dcb, 0x8cf008, 0x8ca29c, 0x8c5584, 0x8c08c0, 0x8bbc50,
-    0x8b7034, 0x8b246a, 0x8ad8f2, 0x8a8dcd, 0x8a42f8, 0x89f874, 0x89ae40,
-    0x89645c, 0x891ac7, 0x88d180, 0x888888, 0x883fdd, 0x87f780, 0x87af6f,
-    0x8767ab, 0x872032, 0x86d905, 0x869222, 0x864b8a, 0x86053c, 0x85bf37,
-    0x85797b, 0x853408, 0x84eedd, 0x84a9f9, 0x84655d, 0x842108, 0x83dcf9,
-    0x839930, 0x8355ac, 0x83126e, 0x82cf75, 0x828cbf, 0x824a4e, 0x820820,
-    0x81c635, 0x81848d, 0x814327, 0x810204, 0x80c121, 0x808080, 0x804020,
-    0x7fffff, 0x7fc01f, 0x7f807f, 0x7f411e, 0x7f01fc, 0x7ec318, 0x7e8472,
-    0x7e460a, 0x7e07e0, 0x7dc9f3, 0x7d8c42, 0x7d4ece, 0x7d1196, 0x7cd49a,
-    0x7c97d9, 0x7c5b53, 0x7c1f07, 0x7be2f6, 0x7ba71f, 0x7b6b82, 0x7b301e,
-    0x7af4f3, 0x7aba01, 0x7a7f48, 0x7a44c6, 0x7a0a7c, 0x79d06a, 0x79968f,
-    0x795ceb, 0x79237d, 0x78ea45, 0x78b144, 0x787878, 0x783fe1, 0x780780,
-    0x77cf53, 0x77975b, 0x775f97, 0x772807, 0x76f0aa, 0x76b981, 0x76828b,
-    0x764bc8, 0x761537, 0x75ded9, 0x75a8ac, 0x7572b2, 0x753ce8, 0x750750,
-    0x74d1e9, 0x749cb2, 0x7467ac, 0x7432d6, 0x73fe30, 0x73c9b9, 0x739572,
-    0x73615a, 0x732d70, 0x72f9b6, 0x72c62a, 0x7292cc, 0x725f9b, 0x722c99,
-    0x71f9c4, 0x71c71c, 0x7194a1, 0x716253, 0x713031, 0x70fe3c, 0x70cc72,
-    0x709ad4, 0x706962, 0x70381c, 0x700700, 0x6fd60f, 0x6fa549, 0x6f74ae,
-    0x6f443c, 0x6f13f5, 0x6ee3d8, 0x6eb3e4, 0x6e8419, 0x6e5478, 0x6e2500,
-    0x6df5b0, 0x6dc68a, 0x6d978b, 0x6d68b5, 0x6d3a06, 0x6d0b80, 0x6cdd21,
-    0x6caee9, 0x6c80d9, 0x6c52ef, 0x6c252c, 0x6bf790, 0x6bca1a, 0x6b9ccb,
-    0x6b6fa1, 0x6b429e, 0x6b15c0, 0x6ae907, 0x6abc74, 0x6a9006, 0x6a63bd,
-    0x6a3799, 0x6a0b99, 0x69dfbd, 0x69b406, 0x698873, 0x695d04, 0x6931b8,
-    0x690690, 0x68db8b, 0x68b0aa, 0x6885eb, 0x685b4f, 0x6830d6, 0x680680,
-    0x67dc4c, 0x67b23a, 0x67884a, 0x675e7c, 0x6734d0, 0x670b45, 0x66e1db,
-    0x66b893, 0x668f6c, 0x666666, 0x663d80, 0x6614bc, 0x65ec17, 0x65c393,
-    0x659b30, 0x6572ec, 0x654ac8, 0x6522c3, 0x64fadf, 0x64d319, 0x64ab74,
-    0x6483ed, 0x645c85, 0x64353c, 0x640e11, 0x63e706, 0x63c018, 0x639949,
-    0x637299, 0x634c06, 0x632591, 0x62ff3a, 0x62d900, 0x62b2e4, 0x628ce5,
-    0x626703, 0x62413f, 0x621b97, 0x61f60d, 0x61d09e, 0x61ab4d, 0x618618,
-    0x6160ff, 0x613c03, 0x611722, 0x60f25d, 0x60cdb5, 0x60a928, 0x6084b6,
-    0x606060, 0x603c25, 0x601806, 0x5ff401, 0x5fd017, 0x5fac49, 0x5f8895,
-    0x5f64fb, 0x5f417d, 0x5f1e18, 0x5eface, 0x5ed79e, 0x5eb488, 0x5e918c,
-    0x5e6ea9, 0x5e4be1, 0x5e2932, 0x5e069c, 0x5de420, 0x5dc1bd, 0x5d9f73,
-    0x5d7d42, 0x5d5b2b, 0x5d392c, 0x5d1745, 0x5cf578, 0x5cd3c3, 0x5cb226,
-    0x5c90a1, 0x5c6f35, 0x5c4de1, 0x5c2ca5, 0x5c0b81, 0x5bea75, 0x5bc980,
-    0x5ba8a3, 0x5b87dd, 0x5b672f, 0x5b4698, 0x5b2618, 0x5b05b0, 0x5ae55e,
-    0x5ac524, 0x5aa500, 0x5a84f3, 0x5a64fc, 0x5a451c, 0x5a2553, 0x5a05a0,
-    0x59e603, 0x59c67c, 0x59a70c, 0x5987b1, 0x59686c, 0x59493e, 0x592a24,
-    0x590b21, 0x58ec33, 0x58cd5a, 0x58ae97, 0x588fe9, 0x587151, 0x5852cd,
-    0x58345f, 0x581605, 0x57f7c0, 0x57d990, 0x57bb75, 0x579d6e, 0x577f7c,
-    0x57619f, 0x5743d5, 0x572620, 0x57087f, 0x56eaf3, 0x56cd7a, 0x56b015,
-    0x5692c4, 0x567587, 0x56585e, 0x563b48, 0x561e46, 0x560158, 0x55e47c,
-    0x55c7b4, 0x55ab00, 0x558e5e, 0x5571d0, 0x555555, 0x5538ed, 0x551c97,
-    0x550055, 0x54e425, 0x54c807, 0x54abfd, 0x549005, 0x54741f, 0x54584c,
-    0x543c8b, 0x5420dc, 0x540540, 0x53e9b5, 0x53ce3d, 0x53b2d7, 0x539782,
-    0x537c3f, 0x53610e, 0x5345ef, 0x532ae2, 0x530fe6, 0x52f4fb, 0x52da22,
-    0x52bf5a, 0x52a4a3, 0x5289fe, 0x526f6a, 0x5254e7, 0x523a75, 0x522014,
-    0x5205c4, 0x51eb85, 0x51d156, 0x51b738, 0x519d2b, 0x51832f, 0x516943,
-    0x514f67, 0x51359c, 0x511be1, 0x510237, 0x50e89c, 0x50cf12, 0x50b598,
-    0x509c2e, 0x5082d4, 0x50698a, 0x505050, 0x503725, 0x501e0b, 0x500500,
-    0x4fec04, 0x4fd319, 0x4fba3d, 0x4fa170, 0x4f88b2, 0x4f7004, 0x4f5766,
-    0x4f3ed6, 0x4f2656, 0x4f0de5, 0x4ef583, 0x4edd30, 0x4ec4ec, 0x4eacb7,
-    0x4e9490, 0x4e7c79, 0x4e6470, 0x4e4c76, 0x4e348b, 0x4e1cae, 0x4e04e0,
-    0x4ded20, 0x4dd56f, 0x4dbdcc, 0x4da637, 0x4d8eb1, 0x4d7739, 0x4d5fcf,
-    0x4d4873, 0x4d3126, 0x4d19e6, 0x4d02b5, 0x4ceb91, 0x4cd47b, 0x4cbd73,
-    0x4ca679, 0x4c8f8d, 0x4c78ae, 0x4c61dd, 0x4c4b19, 0x4c3464, 0x4c1dbb,
-    0x4c0720, 0x4bf093, 0x4bda12, 0x4bc3a0, 0x4bad3a, 0x4b96e2, 0x4b8097,
-    0x4b6a58, 0x4b5428, 0x4b3e04, 0x4b27ed, 0x4b11e3, 0x4afbe6, 0x4ae5f6,
-    0x4ad012, 0x4aba3c, 0x4aa472, 0x4a8eb5, 0x4a7904, 0x4a6360, 0x4a4dc9,
-    0x4a383e, 0x4a22c0, 0x4a0d4e, 0x49f7e8, 0x49e28f, 0x49cd42, 0x49b802,
-    0x49a2cd, 0x498da5, 0x497889, 0x496379, 0x494e75, 0x49397e, 0x492492,
-    0x490fb2, 0x48fade, 0x48e616, 0x48d159, 0x48bca9, 0x48a804, 0x48936b,
-    0x487ede, 0x486a5c, 0x4855e6, 0x48417b, 0x482d1c, 0x4818c8, 0x480480,
-    0x47f043, 0x47dc11, 0x47c7eb, 0x47b3d0, 0x479fc1, 0x478bbc, 0x4777c3,
-    0x4763d5, 0x474ff2, 0x473c1a, 0x47284d, 0x47148b, 0x4700d5, 0x46ed29,
-    0x46d987, 0x46c5f1, 0x46b266, 0x469ee5, 0x468b6f, 0x467804, 0x4664a3,
-    0x46514e, 0x463e02, 0x462ac2, 0x46178b, 0x460460, 0x45f13f, 0x45de28,
-    0x45cb1c, 0x45b81a, 0x45a522, 0x459235, 0x457f52, 0x456c79, 0x4559aa,
-    0x4546e6, 0x45342c, 0x45217c, 0x450ed6, 0x44fc3a, 0x44e9a8, 0x44d720,
-    0x44c4a2, 0x44b22e, 0x449fc3, 0x448d63, 0x447b0d, 0x4468c0, 0x44567d,
-    0x444444, 0x443214, 0x441fee, 0x440dd2, 0x43fbc0, 0x43e9b7, 0x43d7b7,
-    0x43c5c2, 0x43b3d5, 0x43a1f2, 0x439019, 0x437e49, 0x436c82, 0x435ac5,
-    0x434911, 0x433766, 0x4325c5, 0x43142d, 0x43029e, 0x42f118, 0x42df9b,
-    0x42ce28, 0x42bcbd, 0x42ab5c, 0x429a04, 0x4288b4, 0x42776e, 0x426631,
-    0x4254fc, 0x4243d1, 0x4232ae, 0x422195, 0x421084, 0x41ff7c, 0x41ee7c,
-    0x41dd86, 0x41cc98, 0x41bbb2, 0x41aad6, 0x419a02, 0x418937, 0x417874,
-    0x4167ba, 0x415708, 0x41465f, 0x4135bf, 0x412527, 0x411497, 0x410410,
-    0x40f391, 0x40e31a, 0x40d2ac, 0x40c246, 0x40b1e9, 0x40a193, 0x409146,
-    0x408102, 0x4070c5, 0x406090, 0x405064, 0x404040, 0x403024, 0x402010,
-    0x401004,
-];
+pub static RECIPROCAL_U32: [u32; RECIPROCAL_SIZE] = reciprocal_table();
+
+/// Return (a<<16)/b. Divisors that are in the table of reciprocals are
+/// replaced by a multiplication, and the larger divisors fall back to a
+/// division. 'b' must not be zero.
+#[inline(always)]
+pub fn div16(a: u64, b: u64) -> u64 {
+    match RECIPROCAL_U32.get(b as usize) {
+        Some(&reciprocal) => (a * reciprocal as u64) >> 16,
+        None => (a << 16) / b,
+    }
+}
+
+#[test]
+fn test_div16() {
+    for b in 1..RECIPROCAL_SIZE as u64 * 2 {
+        for a in [0, 1, b / 3, b / 2, b] {
+            let expected = (a << 16) / b;
+            let actual = div16(a, b);
+            assert!(actual <= expected && expected - actual <= 1);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Compresses an input, decompresses it, and reports the results, like the
+//! '--check' flag of the command line tool. Downstream projects can call this
+//! in their tests to check the compressor on their own data.
+
+use crate::full::{FullDecoder, FullEncoder};
+use crate::pager::{read_checked_header, read_header, read_hole, read_ref};
+use crate::pager::{read_page_header, read_sparse_header, record_len};
+use crate::pipeline::{Codec, Pipeline};
+use crate::utils::leb128;
+use crate::utils::signatures::FROZEN_SIG;
+use crate::utils::signatures::{match_signature, ARITH_SIG, BLOCK_SIG};
+use crate::utils::signatures::{FAST_BLOCK_SIG, SMALL_BLOCK_SIG, STORED_SIG};
+use crate::utils::signatures::{FULL_SIG, LONG_BLOCK_SIG, NOP_ENC};
+use crate::utils::signatures::{PIPELINE_BLOCK_SIG, RLE_BLOCK_SIG};
+use crate::{decode_exact, Context, Encoder};
+use std::time::{Duration, Instant};
+
+/// The number of compressed bytes that each stage of the format takes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageSizes {
+    /// The literals of the regular blocks.
+    pub literals: usize,
+    /// The literal and match lengths of the regular blocks.
+    pub sequences: usize,
+    /// The match offsets of the regular blocks.
+    pub offsets: usize,
+    /// The small blocks, which keep all of their streams together.
+    pub small_blocks: usize,
+    /// The LZ4 streams of the blocks of the fastest level.
+    pub fast_blocks: usize,
+    /// The output of the arithmetic coder.
+    pub arithmetic: usize,
+    /// The inputs and pages that are stored without compression.
+    pub stored: usize,
+    /// Signatures, headers, checksums, run tokens, and the records of
+    /// constant pages, holes, references and duplicate pages.
+    pub other: usize,
+}
+
+impl StageSizes {
+    /// Return the sizes of the stages of the compressed stream 'input', or
+    /// None if the stream is invalid.
+    fn from_stream(input: &[u8]) -> Option<Self> {
+        let mut sizes = Self::default();
+        if !match_signature(input, &FULL_SIG) {
+            return None;
+        }
+        let body = &input[FULL_SIG.len()..];
+        if match_signature(body, &STORED_SIG) {
+            let (_, len) = leb128::decode_len(&body[STORED_SIG.len()..])?;
+            sizes.stored += len;
+        } else if match_signature(body, &ARITH_SIG) {
+            // The signature is followed by the length of the input.
+            sizes.arithmetic += body.len().checked_sub(ARITH_SIG.len() + 4)?;
+        } else if match_signature(body, &FROZEN_SIG) {
+            sizes.arithmetic += body.len().checked_sub(FROZEN_SIG.len())?;
+        } else {
+            sizes.add_pages(body)?;
+        }
+
+        // Everything that was not counted is part of the container.
+        sizes.other = input.len().checked_sub(sizes.total())?;
+        Some(sizes)
+    }
+
+    /// Add the sizes of the pages in the paged stream 'input'.
+    fn add_pages(&mut self, input: &[u8]) -> Option<()> {
+        let (mut cursor, parts) = read_header(input)?;
+        for _ in 0..parts {
+            let rest = &input[cursor..];
+            cursor += record_len(rest)?;
+            // The checksum header is part of the container.
+            let rest = match read_checked_header(rest) {
+                Some((read, _, _)) => &rest[read..],
+                None => rest,
+            };
+            if let Some((read, length)) = read_page_header(rest) {
+                self.add_page(rest.get(read..)?.get(..length)?)?;
+            } else if let Some((read, length)) = read_sparse_header(rest) {
+                self.add_sparse_body(rest.get(read..)?.get(..length)?)?;
+            }
+        }
+        Some(())
+    }
+
+    /// Add the sizes of the data segments in the body of a sparse page.
+    fn add_sparse_body(&mut self, body: &[u8]) -> Option<()> {
+        let (mut cursor, items) = leb128::decode_len(body)?;
+        for _ in 0..items {
+            if let Some((read, _)) = read_hole(&body[cursor..]) {
+                cursor += read;
+                continue;
+            }
+            if let Some((read, _, _, _)) = read_ref(&body[cursor..]) {
+                cursor += read;
+                continue;
+            }
+            let (read, length) = read_page_header(&body[cursor..])?;
+            cursor += read;
+            self.add_page(body.get(cursor..)?.get(..length)?)?;
+            cursor += length;
+        }
+        Some(())
+    }
+
+    /// Add the sizes of the streams of the encoded page 'page'.
+    fn add_page(&mut self, page: &[u8]) -> Option<()> {
+        self.add_block(page, false)
+    }
+
+    /// Add the sizes of the streams of the block 'page'. The inner blocks of
+    /// RLE and pipeline blocks are 'nested', and may not be RLE or pipeline
+    /// blocks themselves (see 'BlockDecoder::nested').
+    fn add_block(&mut self, page: &[u8], nested: bool) -> Option<()> {
+        if match_signature(page, &NOP_ENC) {
+            let (_, len) = leb128::decode_len(&page[NOP_ENC.len()..])?;
+            self.stored += len;
+        } else if match_signature(page, &FAST_BLOCK_SIG) {
+            self.fast_blocks += page.len() - FAST_BLOCK_SIG.len();
+        } else if match_signature(page, &SMALL_BLOCK_SIG) {
+            self.small_blocks += page.len() - SMALL_BLOCK_SIG.len();
+        } else if match_signature(page, &RLE_BLOCK_SIG) && !nested {
+            // The run tokens are followed by the block of the other bytes.
+            let rest = &page[RLE_BLOCK_SIG.len()..];
+            let (read, len) = leb128::decode_len(rest)?;
+            self.add_block(rest.get(read..)?.get(len..)?, true)?;
+        } else if match_signature(page, &PIPELINE_BLOCK_SIG) && !nested {
+            // The side data of the stages is followed by the encoded block.
+            let (read, pipeline) = Pipeline::payload_offset(page)?;
+            match pipeline.codec() {
+                Codec::Block => self.add_block(&page[read..], true)?,
+                Codec::Stored => {
+                    let (_, len) = leb128::decode_len(&page[read..])?;
+                    self.stored += len;
+                }
+            }
+        } else if match_signature(page, &BLOCK_SIG)
+            || match_signature(page, &LONG_BLOCK_SIG)
+        {
+            // The literals, the sequences and the offsets are saved as arrays,
+            // after the byte of the codecs of the streams.
+            let mut cursor = BLOCK_SIG.len() + 1;
+            let mut streams = [0; 3];
+            for size in streams.iter_mut() {
+                let (read, len) = leb128::decode_len(page.get(cursor..)?)?;
+                *size = len;
+                cursor = cursor.checked_add(read)?.checked_add(len)?;
+                page.get(..cursor)?;
+            }
+            self.literals += streams[0];
+            self.sequences += streams[1];
+            self.offsets += streams[2];
+        } else {
+            return None;
+        }
+        Some(())
+    }
+
+    /// Return the sum of the sizes of all of the stages.
+    pub fn total(&self) -> usize {
+        self.literals
+            + self.sequences
+            + self.offsets
+            + self.small_blocks
+            + self.fast_blocks
+            + self.arithmetic
+            + self.stored
+            + self.other
+    }
+}
+
+/// The results of 'verify'.
+#[derive(Clone, Debug)]
+pub struct VerifyReport {
+    /// The size of the input.
+    pub input_size: usize,
+    /// The size of the compressed stream.
+    pub compressed_size: usize,
+    /// True if the compressed stream decompressed back into the input.
+    pub correct: bool,
+    /// The compressed size of each stage.
+    pub stages: StageSizes,
+    /// The time that compression took.
+    pub compress_time: Duration,
+    /// The time that decompression took.
+    pub decompress_time: Duration,
+}
+
+impl VerifyReport {
+    /// Return the compression ratio.
+    pub fn ratio(&self) -> f64 {
+        self.input_size as f64 / self.compressed_size as f64
+    }
+}
+
+/// Compress 'input' with the context 'ctx', decompress the result, and report
+/// if the round trip was correct, with the sizes and the timing of each step.
+pub fn verify(input: &[u8], ctx: Context) -> VerifyReport {
+    let start = Instant::now();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(input, &mut compressed, ctx).encode();
+    let compress_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut decoded = Vec::new();
+    let stat = decode_exact::<FullDecoder>(&compressed, &mut decoded);
+    let decompress_time = start.elapsed();
+
+    VerifyReport {
+        input_size: input.len(),
+        compressed_size: compressed.len(),
+        correct: stat.is_some() && decoded == input,
+        stages: StageSizes::from_stream(&compressed).unwrap_or_default(),
+        compress_time,
+        decompress_time,
+    }
+}
@@ -0,0 +1,220 @@
+//! Splits a compressed stream into multiple volumes of a fixed size, for media
+//! that limit the size of files. Each volume starts with a continuation header
+//! that records the index of the volume, so that volumes that are missing or
+//! out of order are detected when the stream is read back. The header of the
+//! first volume also records the number of volumes, so that missing volumes at
+//! the end of the stream are detected too.
+
+use crate::utils::leb128;
+use crate::utils::signatures::VOLUME_SIG;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// The number of bytes of the number of volumes in the first header.
+const COUNT_LEN: usize = 8;
+
+/// The offset of the number of volumes in the first header, after the
+/// signature and the index zero.
+const COUNT_OFFSET: usize = VOLUME_SIG.len() + 1;
+
+/// The smallest volume size that leaves room for content after the header.
+pub const MIN_VOLUME_SIZE: usize =
+    VOLUME_SIG.len() + leb128::MAX_LEN + COUNT_LEN + 1;
+
+/// Return the path of the volume with the index 'index' (starting at zero) of
+/// the stream at 'path'. The volumes are named 'path.001', 'path.002', etc.
+pub fn volume_path(path: &str, index: usize) -> String {
+    format!("{}.{:03}", path, index + 1)
+}
+
+/// Writes a stream into volumes of 'volume_size' bytes. The volumes are created
+/// on demand by a callback that receives the index of the volume. The first
+/// volume is kept open, and the number of volumes is written to its header
+/// when the writer is finished.
+pub struct VolumeWriter<W: Write + Seek, F: FnMut(usize) -> io::Result<W>> {
+    /// Creates the volume with the given index.
+    create: F,
+    /// The first volume, once the writer moved to the next volume.
+    first: Option<W>,
+    /// The volume that is being written.
+    current: Option<W>,
+    /// The number of volumes that were created.
+    volumes: usize,
+    /// The number of bytes that can still be written to the current volume.
+    left: usize,
+    /// The size of each volume, including the header.
+    volume_size: usize,
+}
+
+impl<W: Write + Seek, F: FnMut(usize) -> io::Result<W>> VolumeWriter<W, F> {
+    /// Create a new writer that splits the stream into volumes of
+    /// 'volume_size' bytes that are created with 'create'.
+    pub fn new(volume_size: usize, create: F) -> Self {
+        assert!(volume_size >= MIN_VOLUME_SIZE, "Volume size is too small");
+        Self {
+            create,
+            first: None,
+            current: None,
+            volumes: 0,
+            left: 0,
+            volume_size,
+        }
+    }
+
+    /// Close the current volume and start the next one.
+    fn next_volume(&mut self) -> io::Result<()> {
+        if let Some(mut volume) = self.current.take() {
+            volume.flush()?;
+            if self.first.is_none() {
+                self.first = Some(volume);
+            }
+        }
+        let mut header = Vec::new();
+        header.extend(VOLUME_SIG);
+        leb128::encode(self.volumes as u64, &mut header);
+        if self.volumes == 0 {
+            // The number of volumes is written by 'finish'.
+            header.extend([0; COUNT_LEN]);
+        }
+
+        let mut volume = (self.create)(self.volumes)?;
+        volume.write_all(&header)?;
+        self.current = Some(volume);
+        self.left = self.volume_size - header.len();
+        self.volumes += 1;
+        Ok(())
+    }
+
+    /// Flush and close the last volume, write the number of volumes to the
+    /// header of the first volume, and return the number of volumes. An empty
+    /// stream is written as a single volume.
+    pub fn finish(mut self) -> io::Result<usize> {
+        if self.volumes == 0 {
+            self.next_volume()?;
+        }
+        let mut last = self.current.take();
+        if let Some(volume) = last.as_mut() {
+            volume.flush()?;
+        }
+        let first = self.first.as_mut().or(last.as_mut()).unwrap();
+        first.seek(SeekFrom::Start(COUNT_OFFSET as u64))?;
+        first.write_all(&(self.volumes as u64).to_le_bytes())?;
+        first.flush()?;
+        Ok(self.volumes)
+    }
+}
+
+impl<W: Write + Seek, F: FnMut(usize) -> io::Result<W>> Write
+    for VolumeWriter<W, F>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() || self.left == 0 {
+            self.next_volume()?;
+        }
+        let len = buf.len().min(self.left);
+        self.current.as_mut().unwrap().write_all(&buf[..len])?;
+        self.left -= len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some(volume) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads a stream that was split into volumes by 'VolumeWriter'. The volumes
+/// are read in sequence and the continuation headers are removed.
+pub struct VolumeReader<R: Read> {
+    /// The volumes that were not read yet, in reverse order.
+    volumes: Vec<R>,
+    /// The volume that is being read.
+    current: Option<R>,
+    /// The index of the next volume.
+    index: usize,
+    /// The number of volumes, which is read from the header of the first
+    /// volume. At least one volume is expected before it is read.
+    count: usize,
+}
+
+impl<R: Read> VolumeReader<R> {
+    /// Create a new reader that reads the volumes in 'volumes' in order.
+    pub fn new(mut volumes: Vec<R>) -> Self {
+        volumes.reverse();
+        Self {
+            volumes,
+            current: None,
+            index: 0,
+            count: 1,
+        }
+    }
+
+    /// Read and check the header of 'volume'.
+    fn read_header(&mut self, volume: &mut R) -> io::Result<()> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut sig = [0; VOLUME_SIG.len()];
+        volume.read_exact(&mut sig)?;
+        if sig != VOLUME_SIG {
+            return Err(invalid("not a volume"));
+        }
+
+        let mut bytes = Vec::new();
+        for _ in 0..leb128::MAX_LEN {
+            let mut byte = [0];
+            volume.read_exact(&mut byte)?;
+            bytes.push(byte[0]);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        match leb128::decode_len(&bytes) {
+            Some((_, index)) if index == self.index => {}
+            Some(_) => return Err(invalid("volume out of order")),
+            None => return Err(invalid("invalid volume header")),
+        }
+        if self.index >= self.count {
+            return Err(invalid("too many volumes"));
+        }
+        if self.index == 0 {
+            let mut count = [0; COUNT_LEN];
+            volume.read_exact(&mut count)?;
+            self.count = match usize::try_from(u64::from_le_bytes(count)) {
+                Ok(count) if count > 0 => count,
+                _ => return Err(invalid("invalid volume header")),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for VolumeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current.is_none() {
+                let mut volume = match self.volumes.pop() {
+                    Some(volume) => volume,
+                    None if self.index < self.count => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "missing volume",
+                        ));
+                    }
+                    None => return Ok(0),
+                };
+                self.read_header(&mut volume)?;
+                self.current = Some(volume);
+                self.index += 1;
+            }
+
+            let read = self.current.as_mut().unwrap().read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.current = None;
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use compressor::auto::{pick_winner, sample_pages, sample_ranges, Trial};
+use compressor::auto::{run_trials, select, Candidate, AUTO_LEVEL};
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::{Context, Decoder, Encoder};
+use std::time::Duration;
+
+#[test]
+fn test_sample_ranges() {
+    assert!(sample_ranges(0, 100).is_empty());
+    assert_eq!(sample_ranges(250, 100), vec![0..100, 100..200, 200..250]);
+
+    // Large inputs are sampled at the start, the middle and the end.
+    let ranges = sample_ranges(1 << 30, 1 << 31);
+    assert_eq!(ranges.len(), 3);
+    assert_eq!(ranges[0].start, 0);
+    assert_eq!(ranges[2].end, 1 << 30);
+    assert!(ranges.iter().all(|r| r.len() == ranges[0].len()));
+
+    let input = vec![7; 1000];
+    assert_eq!(sample_pages(&input, 400).len(), 3);
+}
+
+#[test]
+fn test_pick_winner() {
+    let trial = |level, size, ms| Trial {
+        candidate: Candidate {
+            level,
+            hybrid: false,
+        },
+        size,
+        time: Duration::from_millis(ms),
+    };
+
+    // A level that is 4 times slower must save 2%.
+    let trials = [trial(1, 1000, 10), trial(9, 975, 40)];
+    assert_eq!(pick_winner(&trials).level, 9);
+    let trials = [trial(1, 1000, 10), trial(9, 985, 40)];
+    assert_eq!(pick_winner(&trials).level, 1);
+
+    // Later levels are compared with the current winner.
+    let trials = [trial(1, 1000, 10), trial(4, 995, 12), trial(9, 900, 20)];
+    assert_eq!(pick_winner(&trials).level, 9);
+}
+
+#[test]
+fn test_auto_level() {
+    let mut input = Vec::new();
+    for i in 0..20000u32 {
+        input.extend(format!("{} ", i * 7 % 1000).as_bytes());
+    }
+    let samples = sample_pages(&input, 1 << 14);
+    let choice = select(&samples);
+    assert!(choice.level > 0);
+
+    // The choice depends only on the input, and the trials are ordered by
+    // their expected time, with the context-mixing coder last.
+    let trials = run_trials(&samples);
+    let again = run_trials(&samples);
+    for (a, b) in trials.iter().zip(&again) {
+        assert_eq!(
+            (a.candidate, a.size, a.time),
+            (b.candidate, b.size, b.time)
+        );
+    }
+    assert!(trials.windows(2).all(|w| w[0].time <= w[1].time));
+    assert!(trials.last().unwrap().candidate.hybrid);
+    assert_eq!(pick_winner(&trials), choice);
+
+    let ctx = Context::new(AUTO_LEVEL, 1 << 14);
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    assert!(compressed.len() < input.len() / 3);
+    let mut decoded = Vec::new();
+    let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+}
+
+#[test]
+fn test_auto_coder() {
+    // Words in a random order compress better with the context-mixing coder.
+    let mut input = Vec::new();
+    let mut seed: u32 = 3;
+    for i in 0..20000u32 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let word =
+            ["alpha", "beta", "gamma", "delta"][(seed >> 16) as usize % 4];
+        input.extend(format!("{} {} {};", word, i % 17, seed >> 28).as_bytes());
+    }
+    let choice = select(&sample_pages(&input, 1 << 16));
+    assert_eq!(
+        choice,
+        Candidate {
+            level: 12,
+            hybrid: true
+        }
+    );
+    let ctx = choice.apply(Context::new(4, 1 << 16));
+    assert!(ctx.level == 12 && ctx.hybrid);
+}
@@ -0,0 +1,42 @@
+use compressor::blob::CompressedBlob;
+use compressor::Context;
+
+#[test]
+fn test_blob_round_trip() {
+    let ctx = Context::new(9, 1 << 20);
+    let text = "A blob stores its content in the compressed form. ".repeat(50);
+    for input in [&b""[..], b"abc", text.as_bytes()] {
+        let blob = CompressedBlob::from_plain(input, ctx);
+        assert_eq!(blob.decompress().unwrap(), input);
+
+        let copy = CompressedBlob::from_compressed(blob.as_bytes().to_vec());
+        assert_eq!(copy, blob);
+        assert_eq!(copy.into_bytes().len(), blob.len());
+    }
+    assert!(CompressedBlob::from_plain(text.as_bytes(), ctx).len() < 200);
+
+    // Invalid streams are rejected when they are decompressed.
+    assert!(CompressedBlob::from_compressed(vec![1, 2, 3])
+        .decompress()
+        .is_none());
+    let mut data = CompressedBlob::from_plain(b"abc", ctx).into_bytes();
+    data.push(0);
+    assert!(CompressedBlob::from_compressed(data).decompress().is_none());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_blob_serde() {
+    let ctx = Context::new(9, 1 << 20);
+    let text = "{\"id\": 17, \"name\": \"compressor\"}".repeat(20);
+    let blob = CompressedBlob::from_plain(text.as_bytes(), ctx);
+
+    // The blob is saved in the compressed form.
+    let json = serde_json::to_string(&blob).unwrap();
+    let expected = serde_json::to_string(&blob.as_bytes()).unwrap();
+    assert_eq!(json, expected);
+
+    let copy: CompressedBlob = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy, blob);
+    assert_eq!(copy.decompress().unwrap(), text.as_bytes());
+}
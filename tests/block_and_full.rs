@@ -1,7 +1,9 @@
 use compressor::block::{decode_offset_stream, encode_offset_stream};
-use compressor::block::{BlockDecoder, BlockEncoder};
+use compressor::block::{decode_sequence_stream, encode_sequence_stream};
+use compressor::block::{BlockDecoder, BlockEncoder, BlockReader};
 use compressor::full::{FullDecoder, FullEncoder};
 use compressor::pager::{PagerDecoder, PagerEncoder};
+use compressor::utils::signatures::{match_signature, OFFSET_CTX_SIG};
 use compressor::{Context, Decoder, Encoder};
 
 #[test]
@@ -163,7 +165,1299 @@ fn test_pager_round_trip() {
 fn test_offset_encoder() {
     let input = [0, 1, 2, 3, 12, 65233, 11241];
     let ctx = Context::new(5, 120);
-    let res = encode_offset_stream::<17>(&input, ctx);
-    let out = decode_offset_stream::<17>(&res).unwrap();
+    let (codec, res) = encode_offset_stream::<17>(&input, ctx);
+    let out = decode_offset_stream::<17>(&res, codec).unwrap();
     assert_eq!(out, input);
 }
+
+#[test]
+fn test_offset_encoder_contexts() {
+    // Runs of short and long offsets make the token contexts worthwhile.
+    let mut input = Vec::new();
+    for i in 0..20000u32 {
+        match (i / 50) % 3 {
+            0 => input.push(i % 2),
+            1 => input.push(100 + i % 300),
+            _ => input.push(30000 + i),
+        }
+    }
+    let ctx = Context::new(5, 1 << 16);
+    let (codec, res) = encode_offset_stream::<17>(&input, ctx);
+    assert!(match_signature(&res, &OFFSET_CTX_SIG));
+    let out = decode_offset_stream::<17>(&res, codec).unwrap();
+    assert_eq!(out, input);
+    assert!(decode_offset_stream::<17>(&res[..res.len() / 2], codec).is_none());
+}
+
+#[test]
+fn test_offset_encoder_large_tokens() {
+    // These offsets generate tokens that don't fit in the 4-symbol alphabet.
+    let input = [0, 1, 2, 3, 12, 65233, 11241];
+    let ctx = Context::new(5, 120);
+    let (codec, res) = encode_offset_stream::<4>(&input, ctx);
+    let out = decode_offset_stream::<4>(&res, codec).unwrap();
+    assert_eq!(out, input);
+}
+
+#[test]
+fn test_sequence_encoder() {
+    let lit_lens = [0, 1, 14, 15, 16, 300, 65536, 1 << 24, 7];
+    let mat_lens = [4, 15, 4, 270, 14, 0, 5, 65536, 0];
+    let ctx = Context::new(5, 120);
+    let (codecs, res) = encode_sequence_stream(&lit_lens, &mat_lens, ctx);
+    let (lits, mats) = decode_sequence_stream(&res, codecs).unwrap();
+    assert_eq!(lits, lit_lens);
+    assert_eq!(mats, mat_lens);
+
+    let (codecs, res) = encode_sequence_stream(&[], &[], ctx);
+    let (lits, mats) = decode_sequence_stream(&res, codecs).unwrap();
+    assert!(lits.is_empty() && mats.is_empty());
+
+    // Truncated streams are rejected.
+    let (codecs, res) = encode_sequence_stream(&lit_lens, &mat_lens, ctx);
+    assert!(decode_sequence_stream(&res[..res.len() - 1], codecs).is_none());
+}
+
+#[test]
+fn test_reused_page_tables() {
+    // The pages of streams with a stable histogram reuse the table of the
+    // first page.
+    let page = 1 << 20;
+    let mut state = 1u64;
+    let mut next = |n: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n) as u32
+    };
+    let (lit_lens, mat_lens): (Vec<u32>, Vec<u32>) =
+        (0..3 * page).map(|_| (next(15), 4 + next(11))).unzip();
+    let ctx = Context::new(5, 1 << 22);
+    let (codecs, res) = encode_sequence_stream(&lit_lens, &mat_lens, ctx);
+    let (lits, mats) = decode_sequence_stream(&res, codecs).unwrap();
+    assert_eq!(lits, lit_lens);
+    assert_eq!(mats, mat_lens);
+
+    // Each page that reuses a table saves at least a byte per symbol.
+    let mut single = 0;
+    for i in 0..3 {
+        let range = i * page..(i + 1) * page;
+        let (_, res) = encode_sequence_stream(
+            &lit_lens[range.clone()],
+            &mat_lens[range],
+            ctx,
+        );
+        single += res.len();
+    }
+    assert!(res.len() + 2 * 15 * 11 < single);
+}
+
+#[test]
+fn test_shared_sequence_tables() {
+    use compressor::block::{stream_codec, CODEC_ENTROPY, CODEC_SHARED};
+
+    // The remainders of the literal lengths have the same histogram as the
+    // tokens, so they share the table of the tokens.
+    let mut state = 1u32;
+    let mut next = |n: u32| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 16) % n
+    };
+    let lit_lens: Vec<u32> = (0..4000).map(|_| 255 + next(15)).collect();
+    let mat_lens: Vec<u32> = (0..4000).map(|_| next(15)).collect();
+    let ctx = Context::new(5, 1 << 20);
+    let (codecs, res) = encode_sequence_stream(&lit_lens, &mat_lens, ctx);
+    assert_eq!(stream_codec(codecs, 0), CODEC_ENTROPY);
+    assert_eq!(stream_codec(codecs, 1), CODEC_SHARED);
+    let (lits, mats) = decode_sequence_stream(&res, codecs).unwrap();
+    assert_eq!(lits, lit_lens);
+    assert_eq!(mats, mat_lens);
+
+    // Blocks with such sequences round trip.
+    let mut input = Vec::new();
+    for (lit, mat) in lit_lens.iter().zip(&mat_lens).take(500) {
+        input.extend((0..*lit).map(|_| next(256) as u8));
+        let start = input.len() - 100;
+        input.extend_from_within(start..start + 4 + *mat as usize);
+    }
+    let mut compressed = Vec::new();
+    let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+    let mut decoded = Vec::new();
+    let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+}
+
+#[test]
+fn test_content_defined_chunking() {
+    use compressor::pager::split_content_defined;
+    use compressor::ChunkSizes;
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let original: Vec<u8> = (0..200_000).map(|_| rng.gen::<u8>()).collect();
+
+    // Insert a few bytes near the beginning of the buffer.
+    let mut shifted = original[..1000].to_vec();
+    shifted.extend([1, 2, 3, 4, 5]);
+    shifted.extend(&original[1000..]);
+
+    let sizes = ChunkSizes {
+        min: 1024,
+        avg: 4096,
+        max: 16384,
+    };
+    let chunks0 = split_content_defined(&original, sizes);
+    let chunks1 = split_content_defined(&shifted, sizes);
+
+    for chunks in [&chunks0, &chunks1] {
+        let total: usize = chunks.iter().map(|c| c.len()).sum();
+        assert!(total == original.len() || total == shifted.len());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= sizes.min && chunk.len() <= sizes.max);
+        }
+    }
+
+    // All of the chunks after the edit are identical.
+    let common = chunks0
+        .iter()
+        .rev()
+        .zip(chunks1.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    assert!(common + 2 >= chunks0.len());
+}
+
+#[test]
+fn test_pager_chunking_round_trip() {
+    fn encode_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+        use compressor::nop::NopEncoder;
+        let mut encoded: Vec<u8> = Vec::new();
+        let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+        encoded
+    }
+
+    fn decode_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        use compressor::nop::NopDecoder;
+        let mut decoded: Vec<u8> = Vec::new();
+        let (read, _) = NopDecoder::new(input, &mut decoded).decode()?;
+        Some((read, decoded))
+    }
+
+    for len in [0, 10, 100, 5000, 50000] {
+        let input: Vec<u8> = (0..len).map(|i| (i * 13 + i / 7) as u8).collect();
+        let mut compressed: Vec<u8> = Vec::new();
+        let ctx = Context::new(9, 0);
+        {
+            let mut encoder = PagerEncoder::new(&input, &mut compressed, ctx);
+            encoder.set_callback(encode_nop);
+            encoder.set_chunking(64, 256, 1024);
+            let written = encoder.encode();
+            assert_eq!(written, compressed.len());
+        }
+
+        let mut decompressed: Vec<u8> = Vec::new();
+        let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+        decoder.set_callback(decode_nop);
+        let (consumed, written) = decoder.decode().unwrap();
+        assert_eq!(consumed, compressed.len());
+        assert_eq!(written, input.len());
+        assert_eq!(decompressed, input);
+    }
+
+    // The pages are encoded without chunking, so the nested pagers of the
+    // blocks keep fixed pages.
+    fn encode_unchunked(input: &[u8], ctx: Context) -> Vec<u8> {
+        assert!(ctx.chunking.is_none());
+        compressor::full::encode_or_nop(input, ctx)
+    }
+    let input = "The pages are chunked by content. ".repeat(1000);
+    let mut compressed: Vec<u8> = Vec::new();
+    let mut encoder = PagerEncoder::new(
+        input.as_bytes(),
+        &mut compressed,
+        Context::new(9, 0),
+    );
+    encoder.set_callback(encode_unchunked);
+    encoder.set_chunking(1024, 4096, 16384);
+    let _ = encoder.encode();
+}
+
+#[test]
+fn test_pager_deduplication() {
+    use compressor::pager::split_content_defined;
+    use compressor::ChunkSizes;
+
+    fn encode_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+        use compressor::nop::NopEncoder;
+        let mut encoded: Vec<u8> = Vec::new();
+        let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+        encoded
+    }
+
+    fn decode_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        use compressor::nop::NopDecoder;
+        let mut decoded: Vec<u8> = Vec::new();
+        let (read, _) = NopDecoder::new(input, &mut decoded).decode()?;
+        Some((read, decoded))
+    }
+
+    // Repeat the same content a few times.
+    let block: Vec<u8> = (0..20000).map(|i| (i * 31 + i / 11) as u8).collect();
+    let mut input = Vec::new();
+    for _ in 0..4 {
+        input.extend(&block);
+    }
+
+    let mut encoded = Vec::new();
+    for dedup in [false, true] {
+        let mut compressed: Vec<u8> = Vec::new();
+        let ctx = Context::new(9, 0);
+        {
+            let mut encoder = PagerEncoder::new(&input, &mut compressed, ctx);
+            encoder.set_callback(encode_nop);
+            encoder.set_chunking(256, 1024, 4096);
+            encoder.set_deduplication(dedup);
+            let written = encoder.encode();
+            assert_eq!(written, compressed.len());
+        }
+
+        // Decode into a non-empty output buffer.
+        let mut decompressed: Vec<u8> = vec![1, 2, 3];
+        let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+        decoder.set_callback(decode_nop);
+        let (consumed, written) = decoder.decode().unwrap();
+        assert_eq!(consumed, compressed.len());
+        assert_eq!(written, input.len());
+        assert_eq!(decompressed[3..], input);
+        encoded.push(compressed);
+    }
+
+    // Check that the duplicated pages were removed.
+    let sizes = ChunkSizes {
+        min: 256,
+        avg: 1024,
+        max: 4096,
+    };
+    assert!(split_content_defined(&input, sizes).len() > 8);
+    assert!(encoded[1].len() < encoded[0].len() / 2);
+}
+
+#[test]
+fn test_pager_constant_pages() {
+    fn encode_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+        use compressor::nop::NopEncoder;
+        let mut encoded: Vec<u8> = Vec::new();
+        let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+        encoded
+    }
+
+    fn decode_nop(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        use compressor::nop::NopDecoder;
+        let mut decoded: Vec<u8> = Vec::new();
+        let (read, _) = NopDecoder::new(input, &mut decoded).decode()?;
+        Some((read, decoded))
+    }
+
+    // A sparse file with zero pages and a few pages of data.
+    let mut input = vec![0u8; 10000];
+    input.extend((0..5000).map(|i| (i * 7 + i / 13) as u8));
+    input.extend(vec![0xffu8; 7000]);
+    input.push(3);
+
+    let mut compressed: Vec<u8> = Vec::new();
+    let ctx = Context::new(9, 1000);
+    {
+        let mut encoder = PagerEncoder::new(&input, &mut compressed, ctx);
+        encoder.set_callback(encode_nop);
+        let written = encoder.encode();
+        assert_eq!(written, compressed.len());
+    }
+    // 18 constant pages of 7 bytes and 5 data pages.
+    assert!(compressed.len() < 18 * 7 + 5 * 1020);
+
+    let mut decompressed: Vec<u8> = Vec::new();
+    let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+    decoder.set_callback(decode_nop);
+    let (consumed, written) = decoder.decode().unwrap();
+    assert_eq!(consumed, compressed.len());
+    assert_eq!(written, input.len());
+    assert_eq!(decompressed, input);
+}
+
+#[test]
+fn test_small_blocks() {
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut compressed: Vec<u8> = Vec::new();
+        let ctx = Context::new(9, 1 << 20);
+        let written = BlockEncoder::new(input, &mut compressed, ctx).encode();
+        assert_eq!(written, compressed.len());
+
+        let mut decompressed: Vec<u8> = Vec::new();
+        let mut decoder = BlockDecoder::new(&compressed, &mut decompressed);
+        assert_eq!(decoder.decode(), Some((written, input.len())));
+        assert_eq!(decompressed, input);
+        compressed
+    }
+
+    let text =
+        "The pager splits the input into pages, and each page is encoded \
+        by the block encoder. Small records, such as the rows of a database, \
+        are often just a few hundred bytes long, and the headers of the \
+        block format would cost more than the payload. ";
+    let text = text.repeat(10);
+
+    // Check the sizes around the threshold of the small encoding.
+    for len in (0..20).chain(2040..2056) {
+        encode(&text.as_bytes()[..len]);
+    }
+
+    // A few hundred bytes of text are compressed.
+    let small = &text.as_bytes()[..300];
+    let size = encode(small).len();
+    assert!(size < 240, "{}", size);
+
+    // Incompressible small blocks don't expand much.
+    let random: Vec<u8> = (0..500u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    assert!(encode(&random).len() <= random.len() + 8);
+}
+
+#[test]
+fn test_raw_blocks() {
+    use compressor::block::{compress_block_raw, decompress_block_raw};
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let ctx = Context::new(9, 1 << 20);
+        let record = compress_block_raw(input, ctx);
+        assert!(record.len() <= input.len());
+        let decompressed = decompress_block_raw(&record, input.len()).unwrap();
+        assert_eq!(decompressed, input);
+        record
+    }
+
+    let text = "Each record is compressed on its own, and the sizes of the \
+        record are stored by the application. "
+        .repeat(200);
+    let text = text.as_bytes();
+    for len in [0, 1, 10, 100, 300, 2048, 2049, 5000, text.len()] {
+        round_trip(&text[..len]);
+    }
+
+    // The raw record is smaller than the regular block.
+    let mut block: Vec<u8> = Vec::new();
+    let ctx = Context::new(9, 1 << 20);
+    let _ = BlockEncoder::new(&text[..300], &mut block, ctx).encode();
+    assert!(round_trip(&text[..300]).len() < block.len());
+
+    // Incompressible inputs are stored as-is.
+    let mut state: u32 = 17;
+    let random: Vec<u8> = (0..500)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    assert_eq!(round_trip(&random), random);
+
+    // The wrong length is rejected.
+    let record = compress_block_raw(text, ctx);
+    assert!(decompress_block_raw(&record, text.len() - 1).is_none());
+    assert!(decompress_block_raw(&record, text.len() + 1).is_none());
+    let record = compress_block_raw(&text[..300], ctx);
+    assert!(decompress_block_raw(&record, 299).is_none());
+    assert!(decompress_block_raw(&record[..record.len() - 1], 300).is_none());
+}
+
+#[test]
+fn test_pager_skip_corrupt_pages() {
+    use compressor::utils::hash::xxh32;
+    use compressor::utils::signatures::{read32, write32};
+
+    // Pages that end with a checksum of their content.
+    fn encode_checked(input: &[u8], _ctx: Context) -> Vec<u8> {
+        let mut encoded = input.to_vec();
+        write32(xxh32(input, 0), &mut encoded);
+        encoded
+    }
+
+    fn decode_checked(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        let body = &input[..input.len().checked_sub(4)?];
+        if read32(&input[body.len()..])? != xxh32(body, 0) {
+            return None;
+        }
+        Some((input.len(), body.to_vec()))
+    }
+
+    let input: Vec<u8> = (0..3500).map(|i| (i * 7 + i / 13) as u8).collect();
+    let mut compressed: Vec<u8> = Vec::new();
+    let ctx = Context::new(9, 1000);
+    let mut encoder = PagerEncoder::new(&input, &mut compressed, ctx);
+    encoder.set_callback(encode_checked);
+    let _ = encoder.encode();
+
+    // Truncated streams are rejected.
+    for len in 0..compressed.len() {
+        let mut decompressed: Vec<u8> = Vec::new();
+        let mut decoder =
+            PagerDecoder::new(&compressed[..len], &mut decompressed);
+        decoder.set_callback(decode_checked);
+        assert!(decoder.decode().is_none());
+    }
+
+    // Corrupt the second page.
+    let offset = compressed.len() / 3;
+    compressed[offset] ^= 1;
+    let mut decompressed: Vec<u8> = Vec::new();
+    let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+    decoder.set_callback(decode_checked);
+    assert_eq!(decoder.pages_left(), Some(4));
+    assert_eq!(decoder.next_page(), Some(1000));
+    assert!(decoder.next_page().is_none());
+    assert!(decoder.skip_page().is_some());
+    assert_eq!(decoder.next_page(), Some(1000));
+    assert_eq!(decoder.next_page(), Some(500));
+    assert_eq!(decoder.pages_left(), Some(0));
+    assert!(decoder.next_page().is_none());
+    assert!(decoder.skip_page().is_none());
+    assert_eq!(decompressed[..1000], input[..1000]);
+    assert_eq!(decompressed[1000..], input[2000..]);
+}
+
+#[test]
+fn test_block_invalid_sequences() {
+    use compressor::nop::NopEncoder;
+    use compressor::utils::array_encoding::encode as encode_arr;
+    use compressor::utils::signatures::BLOCK_SIG;
+
+    fn encode_nop(input: &[u8], ctx: Context) -> Vec<u8> {
+        let mut encoded: Vec<u8> = Vec::new();
+        let _ = NopEncoder::new(input, &mut encoded, ctx).encode();
+        encoded
+    }
+
+    // Build a block from the literals and the raw sequence fields, where the
+    // offsets include the bias of 3 for the previous offsets.
+    fn make_block(lits: &[u8], seqs: &[(u32, u32, u32)]) -> Vec<u8> {
+        let ctx = Context::new(9, 1 << 18);
+        let mut lit_stream = Vec::new();
+        let mut encoder = PagerEncoder::new(lits, &mut lit_stream, ctx);
+        encoder.set_callback(encode_nop);
+        let _ = encoder.encode();
+
+        let lit_lens: Vec<u32> = seqs.iter().map(|x| x.0).collect();
+        let mat_lens: Vec<u32> = seqs.iter().map(|x| x.1).collect();
+        let offsets: Vec<u32> = seqs.iter().map(|x| x.2).collect();
+        let (seq_codecs, seq_stream) =
+            encode_sequence_stream(&lit_lens, &mat_lens, ctx);
+        let (off_codec, off_stream) = encode_offset_stream::<24>(&offsets, ctx);
+
+        // The selector byte holds the codecs of the streams.
+        let mut block = BLOCK_SIG.to_vec();
+        block.push((seq_codecs << 2) | (off_codec << 6));
+        encode_arr(&lit_stream, &mut block);
+        encode_arr(&seq_stream, &mut block);
+        encode_arr(&off_stream, &mut block);
+        block
+    }
+
+    fn decode(block: &[u8]) -> Option<Vec<u8>> {
+        let mut output = Vec::new();
+        let (read, _) = BlockDecoder::new(block, &mut output).decode()?;
+        assert_eq!(read, block.len());
+        Some(output)
+    }
+
+    // A valid block, with a match that overlaps its own output.
+    let block = make_block(b"abcd", &[(4, 8, 3 + 4), (0, 0, 3)]);
+    assert_eq!(decode(&block).unwrap(), b"abcdabcdabcd");
+
+    // A match that starts before the beginning of the output.
+    assert!(decode(&make_block(b"abcd", &[(4, 8, 3 + 5)])).is_none());
+    // A match with no distance.
+    assert!(decode(&make_block(b"abcd", &[(4, 8, 3)])).is_none());
+    // An offset that refers to a previous offset that does not exist.
+    assert!(decode(&make_block(b"abcd", &[(4, 8, 1)])).is_none());
+    // Sequences with more or fewer literals than the block.
+    assert!(decode(&make_block(b"abcd", &[(5, 0, 3)])).is_none());
+    assert!(decode(&make_block(b"abcd", &[(3, 0, 3)])).is_none());
+}
+
+#[test]
+fn test_encode_sequences() {
+    use compressor::block::Sequence;
+
+    let seq = |lit_len, mat_len, offset| Sequence {
+        lit_len,
+        mat_len,
+        offset,
+    };
+    let ctx = Context::new(9, 1 << 18);
+
+    // Records that repeat the previous record with a different id.
+    let record = b"id=0;name=alice;role=admin;";
+    let mut expected = Vec::new();
+    let mut literals = Vec::new();
+    let mut sequences = vec![seq(record.len() as u32, 0, 0)];
+    literals.extend(record);
+    expected.extend(record);
+    for id in b"123456789" {
+        literals.push(*id);
+        sequences.push(seq(1, record.len() as u32 - 1, record.len() as u32));
+        expected.push(*id);
+        let start = expected.len() - record.len();
+        expected.extend_from_within(start..start + record.len() - 1);
+    }
+
+    let block =
+        BlockEncoder::encode_sequences(&literals, &sequences, ctx).unwrap();
+    let mut decoded = Vec::new();
+    let (read, _) = BlockDecoder::new(&block, &mut decoded).decode().unwrap();
+    assert_eq!(read, block.len());
+    assert_eq!(decoded, expected);
+
+    // Invalid sequences are rejected.
+    let encode = |lits: &[u8], seqs: &[Sequence]| {
+        BlockEncoder::encode_sequences(lits, seqs, ctx)
+    };
+    assert!(encode(b"abcd", &[seq(4, 4, 5)]).is_none());
+    assert!(encode(b"abcd", &[seq(4, 4, 0)]).is_none());
+    assert!(encode(b"abcd", &[seq(3, 4, 2)]).is_none());
+    assert!(encode(b"abcd", &[seq(4, 0, 9)]).is_some());
+    assert!(encode(b"", &[]).is_some());
+}
+
+#[test]
+fn test_matched_literals() {
+    // Records that repeat with one changed byte, so the literal after each
+    // match is close to the match byte. The changes are random, so that the
+    // matcher can't find a period.
+    let mut input = Vec::new();
+    let mut seed: u32 = 1;
+    for _ in 0..400 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(b"record:");
+        input.push(b'a' + (seed >> 16) as u8 % 5);
+        input.extend(b";value=");
+        input.push(b'0' + (seed >> 24) as u8 % 10);
+        input.extend(b";\n");
+    }
+
+    let mut sizes = Vec::new();
+    for level in [9, 10, 11, 12] {
+        let mut compressed = Vec::new();
+        let ctx = Context::new(level, 1 << 20);
+        let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+        sizes.push(compressed.len());
+    }
+    eprintln!("SIZES {:?}", sizes);
+    assert!(sizes[1] < sizes[0]);
+}
+
+#[test]
+fn test_aligned_literals() {
+    // Noisy floats, where each byte of the word has its own distribution.
+    let mut input = Vec::new();
+    let mut seed: u32 = 1;
+    for i in 0..20000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let noise = (seed >> 16) as f32 / 65536.;
+        let value = (i as f32 * 0.01).sin() * 100. + noise;
+        input.extend(value.to_le_bytes());
+    }
+
+    let mut sizes = Vec::new();
+    for level in [9, 10] {
+        let mut compressed = Vec::new();
+        let ctx = Context::new(level, 1 << 20);
+        let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+        sizes.push(compressed.len());
+    }
+    assert!(sizes[1] < sizes[0] * 95 / 100);
+}
+
+#[test]
+fn test_aligned_offsets() {
+    use compressor::block::match_lanes;
+    use compressor::block::{decode_match_offsets, encode_match_offsets};
+
+    // Matches that copy earlier records of 12 bytes, with the bias of 3 and
+    // a few repeated offsets.
+    let mut seed: u32 = 7;
+    let (mut lit_lens, mut mat_lens, mut tokens) = (vec![], vec![], vec![]);
+    for i in 0..5000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        lit_lens.push(4 * ((seed >> 16) % 3));
+        mat_lens.push(8);
+        let token = match i % 5 {
+            0 if i > 0 => 0,
+            _ => 12 * (1 + (seed >> 20) % 500) + 3,
+        };
+        tokens.push(token);
+    }
+
+    let ctx = Context::new(10, 1 << 20);
+    let lanes = match_lanes(&lit_lens, &mat_lens, 4);
+    assert!(lanes.iter().all(|lane| *lane == 0));
+    let mut sizes = Vec::new();
+    let mut decoded = Vec::new();
+    for align in [1, 4] {
+        let (codec, stream) =
+            encode_match_offsets(&tokens, false, &lanes, align, ctx);
+        let offsets =
+            decode_match_offsets(&stream, false, codec, &lanes, align).unwrap();
+        assert_eq!(offsets.len(), tokens.len());
+        decoded.push(offsets);
+        sizes.push(stream.len());
+
+        // The low bits of each match must be present.
+        let short = &lanes[1..];
+        let res = decode_match_offsets(&stream, false, codec, short, align);
+        assert_eq!(res.is_none(), align > 1);
+    }
+    assert_eq!(decoded[0], decoded[1]);
+    assert!(sizes[1] < sizes[0]);
+
+    // Aligned records round trip through the block coder and the reader.
+    let mut input = Vec::new();
+    for i in 0..20000u32 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend((i % 300).to_le_bytes());
+        input.extend((0x4100_0000 + (seed >> 16) % 64).to_le_bytes());
+        input.extend((seed >> 30).to_le_bytes());
+    }
+    let mut compressed = Vec::new();
+    let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+    let mut decoded = Vec::new();
+    let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+    let (read, block) = BlockReader::new(&compressed).read().unwrap();
+    assert_eq!(read, compressed.len());
+    assert_eq!(block.decoded_len(), input.len());
+}
+
+#[test]
+fn test_hybrid_coder() {
+    let mut input = Vec::new();
+    let mut seed: u32 = 3;
+    for i in 0..3000u32 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let word =
+            ["alpha", "beta", "gamma", "delta"][(seed >> 16) as usize % 4];
+        input.extend(format!("{} {} {};", word, i % 17, seed >> 28).as_bytes());
+    }
+
+    let mut sizes = Vec::new();
+    let ctx = Context::new(12, 1 << 16);
+    for ctx in [ctx, ctx.with_hybrid(), ctx.with_hybrid().with_fast_level(0)] {
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+        sizes.push(compressed.len());
+    }
+    assert!(sizes[1] < sizes[0]);
+
+    // Level 13 is the arithmetic level, and higher levels are rejected.
+    assert!(Context::try_new(13, 1 << 16).is_some());
+    assert!(Context::try_new(14, 1 << 16).is_none());
+    let mut compressed = Vec::new();
+    let ctx = Context::new(20, 1 << 16);
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    let mut decoded = Vec::new();
+    let _ = FullDecoder::new(&compressed, &mut decoded).decode();
+    assert_eq!(decoded, input);
+}
+
+#[test]
+fn test_long_window() {
+    use compressor::block::{Sequence, MAX_MATCH_OFFSET};
+
+    // Noisy bytes, then a pattern that fills the regular window, then a copy
+    // of the noisy bytes that is farther than the regular window.
+    let mut noise = Vec::new();
+    let mut state: u32 = 7;
+    for _ in 0..(1 << 16) + 4093 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        noise.push((state >> 16) as u8);
+    }
+    let mut input = noise[..1 << 16].to_vec();
+    while input.len() < MAX_MATCH_OFFSET + 1000 {
+        input.extend(&noise[1 << 16..]);
+    }
+    input.extend_from_within(..1 << 16);
+
+    let ctx = Context::new(1, 1 << 31);
+    let mut sizes = Vec::new();
+    for ctx in [ctx, ctx.with_long_window()] {
+        let mut compressed = Vec::new();
+        let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let (read, _) = BlockDecoder::new(&compressed, &mut decoded)
+            .decode()
+            .unwrap();
+        assert_eq!(read, compressed.len());
+        assert!(decoded == input);
+        sizes.push(compressed.len());
+    }
+    assert!(sizes[1] + 60000 < sizes[0], "{:?}", sizes);
+
+    // Sequences with long offsets are rejected unless the window is long.
+    let seq = |lit_len, mat_len, offset| Sequence {
+        lit_len,
+        mat_len,
+        offset,
+    };
+    let far = (input.len() - (1 << 16)) as u32;
+    let sequences = [
+        seq(noise.len() as u32, far - noise.len() as u32, 4093),
+        seq(0, 1 << 16, far),
+        seq(0, 0, 0),
+    ];
+    assert!(BlockEncoder::encode_sequences(&noise, &sequences, ctx).is_none());
+    let long = ctx.with_long_window();
+    let block =
+        BlockEncoder::encode_sequences(&noise, &sequences, long).unwrap();
+    let mut decoded = Vec::new();
+    let _ = BlockDecoder::new(&block, &mut decoded).decode().unwrap();
+    assert!(decoded == input);
+}
+
+#[test]
+fn test_global_matching() {
+    use compressor::full::{decode_or_nop, encode_or_nop};
+
+    // Text without short repetitions, followed by a copy of most of it, which
+    // starts in a later page.
+    let mut text = Vec::new();
+    let mut state: u32 = 3;
+    while text.len() < 150_000 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        text.push(b'a' + ((state >> 16) % 26) as u8);
+    }
+    let mut input = text.clone();
+    input.extend(&text[10_000..140_000]);
+
+    let mut encoded = Vec::new();
+    for global in [false, true] {
+        let mut compressed = Vec::new();
+        let ctx = Context::new(4, 1 << 16);
+        let mut encoder = PagerEncoder::new(&input, &mut compressed, ctx);
+        encoder.set_callback(encode_or_nop);
+        encoder.set_global_matching(global);
+        let written = encoder.encode();
+        assert_eq!(written, compressed.len());
+
+        let mut decompressed = vec![1, 2, 3];
+        let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+        decoder.set_callback(decode_or_nop);
+        let (consumed, written) = decoder.decode().unwrap();
+        assert_eq!(consumed, compressed.len());
+        assert_eq!(written, input.len());
+        assert!(decompressed[3..] == input);
+        encoded.push(compressed);
+    }
+    assert!(encoded[1].len() * 3 < encoded[0].len() * 2);
+
+    // The copy starts in the third page, and refers to the first page, which
+    // is needed to decode it.
+    let mut decompressed = Vec::new();
+    let mut decoder = PagerDecoder::new(&encoded[1], &mut decompressed);
+    decoder.set_callback(decode_or_nop);
+    decoder.skip_page().unwrap();
+    decoder.next_page().unwrap();
+    assert!(decoder.next_page().is_none());
+}
+
+#[test]
+fn test_fast_level() {
+    use compressor::block::FAST_LEVEL;
+
+    let mut input = Vec::new();
+    for i in 0..5000u32 {
+        input.extend(format!("{} {};", i % 97, i % 13).as_bytes());
+    }
+    input.extend([7u8; 3000]);
+
+    let mut compressed = Vec::new();
+    let ctx = Context::new(FAST_LEVEL, 1 << 20);
+    let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+    assert!(compressed.len() < input.len() / 4);
+    let mut decoded = Vec::new();
+    let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+
+    // A truncated block is rejected.
+    let mut decoded = Vec::new();
+    let truncated = &compressed[..compressed.len() - 1];
+    assert!(BlockDecoder::new(truncated, &mut decoded)
+        .decode()
+        .is_none());
+}
+
+#[test]
+fn test_fast_level_acceleration() {
+    use compressor::lz::matcher::MAX_ACCELERATION;
+
+    let mut input = Vec::new();
+    let mut seed: u32 = 5;
+    while input.len() < 200000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(format!("{} ", (seed >> 16) % 3000).as_bytes());
+    }
+
+    let mut sizes = Vec::new();
+    for acceleration in 0..=MAX_ACCELERATION + 1 {
+        let ctx = Context::new(9, 1 << 20).with_fast_level(acceleration);
+        let mut compressed = Vec::new();
+        let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+        sizes.push(compressed.len());
+    }
+    // Higher accelerations compress less, and the values above the maximum
+    // are clamped.
+    assert!(sizes[0] < sizes[MAX_ACCELERATION as usize], "{:?}", sizes);
+    assert_eq!(sizes[MAX_ACCELERATION as usize], sizes[sizes.len() - 1]);
+}
+
+#[test]
+fn test_encode_vectored() {
+    use compressor::full::encode_vectored;
+    use std::io::IoSlice;
+
+    let mut input = Vec::new();
+    for i in 0..20000u32 {
+        input.extend(format!("{} ", i % 1300).as_bytes());
+    }
+    // Buffers of different sizes, with empty buffers and page boundaries
+    // inside and between the buffers.
+    let cuts = [0, 0, 100, 4096, 4096, 9000, 30000, 50000, input.len()];
+    let bufs: Vec<IoSlice> = cuts
+        .windows(2)
+        .map(|w| IoSlice::new(&input[w[0]..w[1]]))
+        .collect();
+
+    for ctx in [
+        Context::new(5, 4096),
+        Context::new(0, 1 << 20),
+        Context::new(9, 1000).with_frame_checksum(),
+        Context::new(13, 1 << 20),
+    ] {
+        let mut compressed = Vec::new();
+        let written = encode_vectored(&bufs, &mut compressed, ctx);
+        assert_eq!(written, compressed.len());
+        let mut decoded = Vec::new();
+        let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+    }
+
+    // One page gives the output of the contiguous encoder.
+    let ctx = Context::new(5, 1 << 20);
+    let mut expected = Vec::new();
+    let _ = FullEncoder::new(&input, &mut expected, ctx).encode();
+    let mut compressed = Vec::new();
+    let _ = encode_vectored(&bufs, &mut compressed, ctx);
+    assert_eq!(compressed, expected);
+
+    // No buffers, and incompressible buffers.
+    let mut compressed = Vec::new();
+    let _ = encode_vectored(&[], &mut compressed, ctx);
+    let mut decoded = Vec::new();
+    assert!(FullDecoder::new(&compressed, &mut decoded)
+        .decode()
+        .is_some());
+    assert!(decoded.is_empty());
+    let random: Vec<u8> = (0..5000).map(|_| rand::random::<u8>()).collect();
+    let bufs = [IoSlice::new(&random[..10]), IoSlice::new(&random[10..])];
+    let mut compressed = Vec::new();
+    let _ = encode_vectored(&bufs, &mut compressed, ctx);
+    assert!(compressed.len() < random.len() + 16);
+    let mut decoded = Vec::new();
+    assert!(FullDecoder::new(&compressed, &mut decoded)
+        .decode()
+        .is_some());
+    assert_eq!(decoded, random);
+}
+
+#[test]
+fn test_decode_prefix() {
+    use compressor::full::decode_prefix;
+
+    let mut input = Vec::new();
+    for i in 0..4000u32 {
+        input.extend(format!("{} ", (i * 7) % 900).as_bytes());
+    }
+
+    for ctx in [
+        Context::new(5, 4096),
+        Context::new(0, 1 << 14),
+        Context::new(9, 1 << 20).with_page_checksums(),
+        Context::new(13, 1 << 20),
+    ] {
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        // Prefixes that end inside of a block, on a page boundary, and past
+        // the end of the input.
+        for len in [0, 1, 100, 4096, 10000, input.len(), input.len() + 10] {
+            let prefix = decode_prefix(&compressed, len).unwrap();
+            assert_eq!(prefix, &input[..len.min(input.len())]);
+        }
+    }
+
+    // A block can be decoded up to a limit, and still reports its size.
+    let mut block = Vec::new();
+    let written =
+        BlockEncoder::new(&input, &mut block, Context::new(5, 1 << 20))
+            .encode();
+    let mut decoded = Vec::new();
+    let stat = BlockDecoder::new(&block, &mut decoded)
+        .with_limit(777)
+        .decode();
+    assert_eq!(stat, Some((written, 777)));
+    assert_eq!(decoded, &input[..777]);
+
+    // Stored streams, and invalid streams.
+    let noise: Vec<u8> = (0..5000).map(|_| rand::random::<u8>()).collect();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&noise, &mut compressed, Context::new(5, 4096))
+        .encode();
+    assert_eq!(decode_prefix(&compressed, 1234).unwrap(), &noise[..1234]);
+    assert!(decode_prefix(b"not a stream", 10).is_none());
+}
+
+#[test]
+fn test_entropy_page_size() {
+    use compressor::block::entropy_page_size;
+    use compressor::block::{MAX_ENTROPY_PAGE_SIZE, MIN_ENTROPY_PAGE_SIZE};
+
+    // Short streams fit in one page.
+    assert_eq!(entropy_page_size(&[]), MIN_ENTROPY_PAGE_SIZE);
+    assert_eq!(entropy_page_size(&[7; 100]), MIN_ENTROPY_PAGE_SIZE);
+
+    // Stable histograms use large pages.
+    let mut state = 1u32;
+    let stable: Vec<u8> = (0..1 << 21)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8 % 16
+        })
+        .collect();
+    assert_eq!(entropy_page_size(&stable), MAX_ENTROPY_PAGE_SIZE);
+    assert_eq!(entropy_page_size(&stable[..40000]), 1 << 16);
+
+    // Histograms that change between the pages use small pages.
+    let drifting: Vec<u8> = stable
+        .chunks(MIN_ENTROPY_PAGE_SIZE)
+        .enumerate()
+        .flat_map(|(i, chunk)| chunk.iter().map(move |b| b + i as u8 * 16))
+        .take(1 << 18)
+        .collect();
+    assert_eq!(entropy_page_size(&drifting), MIN_ENTROPY_PAGE_SIZE);
+
+    // The blocks still round trip.
+    for input in [&stable[..1 << 18], &drifting[..]] {
+        let mut compressed = Vec::new();
+        let ctx = Context::new(4, 1 << 20);
+        let _ = BlockEncoder::new(input, &mut compressed, ctx).encode();
+        let mut decompressed = Vec::new();
+        let mut decoder = BlockDecoder::new(&compressed, &mut decompressed);
+        assert!(decoder.decode().is_some());
+        assert_eq!(decompressed, input);
+    }
+}
+
+#[test]
+fn test_pager_sized_header() {
+    use compressor::full::{decode_or_nop, encode_or_nop};
+    use compressor::pager::read_sized_header;
+
+    let input: Vec<u8> = (0..20000u32).map(|i| ((i * i) >> 7) as u8).collect();
+    let ctx = Context::new(2, 0);
+    let mut compressed = Vec::new();
+    let mut encoder = PagerEncoder::new(&input, &mut compressed, ctx);
+    encoder.set_callback(encode_or_nop);
+    encoder.set_page_size(4096);
+    encoder.set_sized_header(true);
+    let _ = encoder.encode();
+    let (_, parts, page_size) = read_sized_header(&compressed).unwrap();
+    assert_eq!((parts, page_size), (5, Some(4096)));
+
+    let mut decompressed = Vec::new();
+    let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+    decoder.set_callback(decode_or_nop);
+    assert!(decoder.decode().is_some());
+    assert_eq!(decompressed, input);
+
+    // Pages that are larger than the saved size are rejected.
+    compressed[4] -= 1;
+    let mut decompressed = Vec::new();
+    let mut decoder = PagerDecoder::new(&compressed, &mut decompressed);
+    decoder.set_callback(decode_or_nop);
+    assert!(decoder.decode().is_none());
+    assert!(decompressed.is_empty());
+}
+
+#[test]
+fn test_block_reader() {
+    let mut input = Vec::new();
+    let mut seed: u32 = 1;
+    for _ in 0..400 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(b"record:");
+        input.push(b'a' + (seed >> 16) as u8 % 5);
+        input.extend(b";value=");
+        input.push(b'0' + (seed >> 24) as u8 % 10);
+        input.extend(b";\n");
+    }
+
+    // Level 10 codes the literals in the context of the match byte.
+    for level in [2, 4, 9, 10] {
+        let ctx = Context::new(level, 1 << 20);
+        let mut compressed = Vec::new();
+        let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        compressed.push(0);
+        let (read, block) = BlockReader::new(&compressed).read().unwrap();
+        assert_eq!(read, compressed.len() - 1);
+        assert_eq!(block.decoded_len(), input.len());
+        assert!(!block.long);
+
+        // Execute the copies.
+        let mut decoded: Vec<u8> = Vec::new();
+        let mut lits = &block.literals[..];
+        for seq in &block.sequences {
+            let (head, rest) = lits.split_at(seq.lit_len as usize);
+            decoded.extend(head);
+            lits = rest;
+            for _ in 0..seq.mat_len {
+                decoded.push(decoded[decoded.len() - seq.offset as usize]);
+            }
+        }
+        assert_eq!(decoded, input);
+
+        // Encode the sequences back into a block.
+        let block = BlockEncoder::encode_sequences(
+            &block.literals,
+            &block.sequences,
+            ctx,
+        )
+        .unwrap();
+        let mut decoded = Vec::new();
+        let _ = BlockDecoder::new(&block, &mut decoded).decode().unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    // Small blocks are not regular blocks.
+    let mut compressed = Vec::new();
+    let ctx = Context::new(4, 1 << 20);
+    let _ = BlockEncoder::new(&input[..100], &mut compressed, ctx).encode();
+    assert!(BlockReader::new(&compressed).read().is_none());
+    assert!(BlockReader::new(&[]).read().is_none());
+}
+
+#[test]
+fn test_page_expansion() {
+    use compressor::block::Sequence;
+    use compressor::full::MAX_PAGE_EXPANSION;
+    use compressor::full::{decode_or_nop, encode_or_nop, max_page_len};
+
+    // Pages that don't compress are stored with a bounded overhead.
+    let mut seed: u32 = 3;
+    let noise: Vec<u8> = (0..5000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    for len in [0, 1, 100, 5000] {
+        let ctx = Context::new(9, 1 << 16);
+        let encoded = encode_or_nop(&noise[..len], ctx);
+        assert!(encoded.len() <= max_page_len(len));
+        assert!(encoded.len() <= len + MAX_PAGE_EXPANSION);
+        let (read, decoded) = decode_or_nop(&encoded).unwrap();
+        assert_eq!((read, &decoded[..]), (encoded.len(), &noise[..len]));
+    }
+
+    // Blocks that are larger than the stored page are rejected.
+    let sequences: Vec<Sequence> = (0..8)
+        .map(|_| Sequence {
+            lit_len: 1,
+            mat_len: 0,
+            offset: 0,
+        })
+        .collect();
+    let ctx = Context::new(9, 1 << 16);
+    let block =
+        BlockEncoder::encode_sequences(&noise[..8], &sequences, ctx).unwrap();
+    assert!(block.len() > max_page_len(8));
+    let mut decoded = Vec::new();
+    let _ = BlockDecoder::new(&block, &mut decoded).decode().unwrap();
+    assert_eq!(decoded, &noise[..8]);
+    assert!(decode_or_nop(&block).is_none());
+}
+
+#[test]
+fn test_reset_points() {
+    use compressor::full::encode_or_nop;
+    use compressor::full::{decode_from_reset, reset_table};
+    use compressor::metadata::{read_metadata, Metadata};
+    use compressor::reader::DecompressBufReader;
+    use compressor::utils::signatures::{FULL_SIG, RESET_TABLE_SIG};
+    use compressor::ChunkSizes;
+    use std::io::Read;
+
+    // The chapters repeat each other, so pages would refer to earlier pages
+    // with global matches and duplicate pages.
+    let chapter: Vec<u8> = (0..9000).map(|i| (i * 7 + i / 13) as u8).collect();
+    let mut input = Vec::new();
+    for _ in 0..5 {
+        input.extend(&chapter);
+    }
+    let points = [9000, 18000, 20000, 31001, 45000, 0];
+
+    let metadata = Metadata::new().with_comment("chapters");
+    let mut chunked = Context::new(5, 1 << 20).with_global_matching();
+    chunked.chunking = Some(ChunkSizes {
+        min: 256,
+        avg: 1024,
+        max: 4096,
+    });
+    let contexts = [
+        Context::new(5, 1 << 12).with_global_matching(),
+        Context::new(2, 5000).with_page_checksums(),
+        chunked,
+    ];
+    for ctx in contexts {
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx)
+            .with_metadata(&metadata)
+            .unwrap()
+            .with_reset_points(&points)
+            .encode();
+        let mut decoded = Vec::new();
+        let _ = FullDecoder::new(&compressed, &mut decoded)
+            .decode()
+            .unwrap();
+        assert_eq!(decoded, input);
+
+        // The points inside of the input are saved with their first page.
+        let saved = read_metadata(&compressed).unwrap();
+        assert_eq!(saved.comment(), Some("chapters"));
+        let table = reset_table(&compressed).unwrap();
+        let offsets: Vec<usize> = table.iter().map(|(at, _)| *at).collect();
+        assert_eq!(offsets, [9000, 18000, 20000, 31001]);
+
+        for at in [0, 9000, 18000, 20000, 31001] {
+            let rest = decode_from_reset(&compressed, at).unwrap();
+            assert_eq!(rest, input[at..]);
+        }
+        assert!(decode_from_reset(&compressed, 100).is_none());
+    }
+
+    // Duplicate pages don't refer to the pages before a reset point.
+    let plain = Context::new(4, 1 << 16);
+    let mut compressed = Vec::new();
+    let mut encoder = PagerEncoder::new(&input, &mut compressed, plain);
+    encoder.set_callback(encode_or_nop);
+    encoder.set_page_size(3000);
+    encoder.set_deduplication(true);
+    encoder.set_reset_points(&[27000]);
+    let table = encoder.reset_pages();
+    assert_eq!(table, [(27000, 9)]);
+    let _ = encoder.encode();
+    let mut decoded = Vec::new();
+    let mut decoder = PagerDecoder::new(&compressed, &mut decoded);
+    decoder.set_callback(compressor::full::decode_or_nop);
+    for _ in 0..9 {
+        decoder.skip_page().unwrap();
+    }
+    let _ = decoder.decode().unwrap();
+    assert_eq!(decoded, input[27000..]);
+
+    // Streams that are stored can be decoded from any offset.
+    let noise: Vec<u8> = (0..5000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    let mut stored = Vec::new();
+    let _ = FullEncoder::new(&noise, &mut stored, plain)
+        .with_reset_points(&[1000])
+        .encode();
+    assert_eq!(decode_from_reset(&stored, 1000).unwrap(), noise[1000..]);
+
+    // Large tables are saved outside of the metadata.
+    let long = chapter.repeat(300);
+    let points: Vec<usize> = (1..10000).map(|i| i * 256).collect();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&long, &mut compressed, Context::new(2, 4096))
+        .with_metadata(&metadata)
+        .unwrap()
+        .with_reset_points(&points)
+        .encode();
+    assert_eq!(reset_table(&compressed).unwrap().len(), points.len());
+    let saved = read_metadata(&compressed).unwrap();
+    assert_eq!(saved, metadata);
+    let rest = decode_from_reset(&compressed, 40960).unwrap();
+    assert_eq!(rest, long[40960..]);
+    let mut decoded = Vec::new();
+    let _ = FullDecoder::new(&compressed, &mut decoded)
+        .decode()
+        .unwrap();
+    assert_eq!(decoded, long);
+    let mut streamed = Vec::new();
+    DecompressBufReader::new(&compressed[..])
+        .read_to_end(&mut streamed)
+        .unwrap();
+    assert_eq!(streamed, long);
+
+    // Damaged tables are rejected.
+    let at = FULL_SIG.len() + metadata.encode(&mut Vec::new()).unwrap();
+    compressed[at + RESET_TABLE_SIG.len()] ^= 0x7f;
+    assert!(reset_table(&compressed).is_none());
+}
+
+#[test]
+fn test_arith_max_expansion() {
+    use compressor::full::decode_prefix;
+
+    // Text that the model predicts, followed by noise that it can't.
+    let mut input = Vec::new();
+    for i in 0..3000u32 {
+        input.extend(format!("{} ", (i * 7) % 900).as_bytes());
+    }
+    let text_len = input.len();
+    input.extend((0..40000).map(|_| rand::random::<u8>()));
+
+    let bounded = Context::new(13, 1 << 14);
+    let unbounded = bounded.with_max_expansion(None);
+    let mut sizes = Vec::new();
+    for ctx in [bounded, unbounded] {
+        let mut compressed = Vec::new();
+        let written = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        assert_eq!(written, compressed.len());
+        let mut decoded = Vec::new();
+        let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+        let prefix = decode_prefix(&compressed, text_len + 10).unwrap();
+        assert_eq!(prefix, &input[..text_len + 10]);
+        sizes.push(compressed.len());
+    }
+    // The noise is stored in pages instead of expanding.
+    assert!(sizes[0] < sizes[1]);
+}
@@ -0,0 +1,73 @@
+use compressor::block::FAST_LEVEL;
+use compressor::budget::{calibrate, fit_level, select_level, Schedule};
+use compressor::full::{FullDecoder, FullEncoder, ARITH_LEVEL};
+use compressor::{Context, Decoder, Encoder};
+use std::time::Duration;
+
+fn text(len: usize) -> Vec<u8> {
+    let words = ["budget ", "level ", "page ", "deadline ", "sample "];
+    let mut input = Vec::new();
+    let mut seed: u32 = 5;
+    while input.len() < len {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(words[(seed >> 16) as usize % words.len()].as_bytes());
+    }
+    input
+}
+
+#[test]
+fn test_fit_level() {
+    let hour = Duration::from_secs(3600);
+    assert_eq!(fit_level(1 << 20, hour, 1.), ARITH_LEVEL);
+    assert_eq!(fit_level(1 << 20, Duration::ZERO, 1.), FAST_LEVEL);
+
+    // The arithmetic coder is faster than the levels below it, but it is only
+    // selected if they fit too.
+    let second = Duration::from_secs(1);
+    assert_eq!(fit_level(1 << 20, second, 1.), 10);
+
+    // Tighter budgets and slower machines select lower levels.
+    let mut prev = ARITH_LEVEL;
+    for ms in [10000, 1000, 100, 10, 1] {
+        let level = fit_level(1 << 20, Duration::from_millis(ms), 1.);
+        assert!(level <= prev);
+        prev = level;
+    }
+    let budget = Duration::from_millis(500);
+    assert!(fit_level(1 << 20, budget, 0.1) < fit_level(1 << 20, budget, 1.));
+
+    let speed = calibrate(&text(1 << 17));
+    assert!(speed.is_finite() && speed > 0.);
+    assert!(select_level(&text(1000), hour) > 9);
+}
+
+#[test]
+fn test_schedule_downgrades() {
+    // An encoder without time left drops to the lowest level.
+    let mut schedule = Schedule::new(1 << 20, Duration::ZERO, 9);
+    assert_eq!(schedule.level(), 9);
+    assert_eq!(schedule.page_done(1 << 16), FAST_LEVEL);
+
+    // The fast level has a place in the calibration table.
+    let mut schedule = Schedule::new(1 << 20, Duration::ZERO, FAST_LEVEL);
+    assert_eq!(schedule.page_done(1 << 16), FAST_LEVEL);
+
+    // The level is never raised above the selected level.
+    let mut schedule = Schedule::new(1 << 20, Duration::from_secs(3600), 5);
+    assert_eq!(schedule.page_done(1 << 16), 5);
+}
+
+#[test]
+fn test_time_budget_round_trip() {
+    let input = text(1 << 19);
+    for ms in [1, 50, 5000] {
+        let budget = Duration::from_millis(ms);
+        let ctx = Context::new(9, 1 << 16).with_time_budget(budget);
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+    }
+}
@@ -148,3 +148,108 @@ fn test_bitvector_bug5() {
     let val2 = bv.pop_word(64);
     assert_eq!(val, val2);
 }
+
+#[test]
+fn test_deserialize_invalid() {
+    let mut bv = Bitvector::new();
+    for i in 0..100 {
+        bv.push_word(i, 7);
+    }
+    let mut output = Vec::new();
+    let written = bv.serialize(&mut output);
+
+    // Every prefix of the stream is too short.
+    for len in 0..written {
+        assert!(Bitvector::deserialize(&output[..len]).is_none());
+    }
+
+    // The bytes after the bitvector are not read.
+    output.extend([1, 2, 3]);
+    let (bv2, read) = Bitvector::deserialize(&output).unwrap();
+    assert_eq!(read, written);
+    assert_eq!(bv, bv2);
+
+    // Bits above the length in the free word are rejected.
+    output[4] |= 0x80;
+    assert!(Bitvector::deserialize(&output).is_none());
+}
+
+#[test]
+fn test_bit_orders() {
+    use compressor::bitstream::{BitOrder, BitReader, BitWriter};
+
+    // The layout of the bits in the bytes of each order.
+    let mut lsb = Vec::new();
+    let mut writer = BitWriter::new(&mut lsb, BitOrder::Lsb);
+    writer.write(0b1, 1);
+    writer.write(0b10, 2);
+    writer.write(0x3f5, 10);
+    assert_eq!(writer.finish(), 3);
+    assert_eq!(lsb, [0b1010_1101, 0b0001_1111]);
+
+    let mut msb = Vec::new();
+    let mut writer = BitWriter::new(&mut msb, BitOrder::Msb);
+    writer.write(0b1, 1);
+    writer.write(0b10, 2);
+    writer.write(0x3f5, 10);
+    assert_eq!(writer.finish(), 3);
+    assert_eq!(msb, [0b1101_1111, 0b1010_1000]);
+
+    // Values of every width round trip, with alignment in between.
+    for order in [BitOrder::Lsb, BitOrder::Msb] {
+        let mut stream = Vec::new();
+        let mut writer = BitWriter::new(&mut stream, order);
+        for num in 0..=56 {
+            writer.write(0x9e37_79b9_7f4a_7c15 >> num, num);
+            if num % 7 == 0 {
+                writer.align();
+            }
+        }
+        let _ = writer.finish();
+
+        let mut reader = BitReader::new(&stream, order);
+        for num in 0..=56 {
+            let val = reader.read(num).unwrap();
+            let expected = (0x9e37_79b9_7f4a_7c15u64 >> num) & ((1 << num) - 1);
+            assert_eq!(val, expected);
+            if num % 7 == 0 {
+                reader.align();
+            }
+        }
+        assert_eq!(reader.consumed(), stream.len());
+        assert_eq!(reader.bits_left(), 0);
+        assert!(reader.read(1).is_none());
+    }
+}
+
+#[test]
+fn test_bit_reversal() {
+    use compressor::bitstream::{reverse_bits, reverse_bits_in_bytes};
+    use compressor::bitstream::{BitOrder, BitReader, BitWriter};
+
+    assert_eq!(reverse_bits(0b110, 3), 0b011);
+    assert_eq!(reverse_bits(0b1, 5), 0b10000);
+    assert_eq!(reverse_bits(0, 0), 0);
+    assert_eq!(reverse_bits(1, 64), 1 << 63);
+
+    // Single bits in one order are read in the other order after the bits of
+    // each byte are reversed.
+    let bits: Vec<u64> = (0..100).map(|i| (i * 7 % 3 == 0) as u64).collect();
+    let mut stream = Vec::new();
+    let mut writer = BitWriter::new(&mut stream, BitOrder::Lsb);
+    for bit in &bits {
+        writer.write(*bit, 1);
+    }
+    let _ = writer.finish();
+    reverse_bits_in_bytes(&mut stream);
+    let mut reader = BitReader::new(&stream, BitOrder::Msb);
+    for bit in &bits {
+        assert_eq!(reader.read(1), Some(*bit));
+    }
+
+    // A reader that runs out of bits consumes nothing.
+    let mut reader = BitReader::new(&[0xab], BitOrder::Lsb);
+    assert_eq!(reader.read(4), Some(0xb));
+    assert!(reader.read(5).is_none());
+    assert_eq!(reader.read(4), Some(0xa));
+}
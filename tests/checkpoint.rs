@@ -0,0 +1,37 @@
+use compressor::checkpoint::Checkpoint;
+
+#[test]
+fn test_checkpoint_encoding() {
+    let state = Checkpoint {
+        fingerprint: 0x1234_5678_9abc_def0,
+        level: 9,
+        page_size: 1 << 24,
+        pages: 17,
+        offset: 5_000_000_000,
+    };
+    let encoded = state.encode();
+    assert_eq!(Checkpoint::decode(&encoded), Some(state));
+
+    let fresh = Checkpoint {
+        pages: 0,
+        offset: 0,
+        ..state
+    };
+    assert!(state.matches(&fresh));
+    assert!(!state.matches(&Checkpoint { level: 4, ..fresh }));
+    assert!(!state.matches(&Checkpoint {
+        fingerprint: 1,
+        ..fresh
+    }));
+
+    // Partial and corrupted records are rejected.
+    for len in 0..encoded.len() {
+        assert_eq!(Checkpoint::decode(&encoded[..len]), None);
+    }
+    let mut corrupted = encoded.clone();
+    corrupted[6] ^= 1;
+    assert_eq!(Checkpoint::decode(&corrupted), None);
+    let mut extended = encoded;
+    extended.push(0);
+    assert_eq!(Checkpoint::decode(&extended), None);
+}
@@ -0,0 +1,71 @@
+use compressor::delta;
+
+fn make_text(seed: usize, len: usize) -> Vec<u8> {
+    let words = ["alpha ", "beta ", "gamma ", "delta ", "omega ", "patch "];
+    let mut text = Vec::new();
+    let mut state = seed;
+    while text.len() < len {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695);
+        text.extend(words[(state >> 33) % words.len()].as_bytes());
+        text.extend(format!("{} ", (state >> 40) % 1000).as_bytes());
+    }
+    text
+}
+
+fn round_trip(base: &[u8], target: &[u8]) -> usize {
+    let patch = delta::encode(base, target);
+    let res = delta::apply(base, &patch).unwrap();
+    assert_eq!(res, target);
+    patch.len()
+}
+
+#[test]
+fn test_delta_round_trip() {
+    round_trip(&[], &[]);
+    round_trip(&[], &[1, 2, 3]);
+    round_trip(&[1, 2, 3], &[]);
+    round_trip(&[1, 2, 3, 4, 5, 6], &[1, 2, 3, 4, 5, 6]);
+    round_trip(b"this is the base", b"this is the target");
+
+    let base = make_text(1, 50000);
+    let mut target = base.clone();
+    // Edit a few locations in the buffer.
+    target[100] = b'#';
+    target.splice(20000..20010, b"inserted text".iter().cloned());
+    target.drain(30000..30500);
+    target.extend(make_text(2, 1000));
+
+    let size = round_trip(&base, &target);
+    assert!(size < target.len() / 10);
+}
+
+#[test]
+fn test_delta_wrong_base() {
+    let base = make_text(1, 5000);
+    let mut target = base.clone();
+    target[10] = b'!';
+    let patch = delta::encode(&base, &target);
+
+    let other = make_text(3, 5000);
+    assert!(delta::apply(&other, &patch).is_none());
+    assert!(delta::apply(&base, &patch[..patch.len() / 2]).is_none());
+    assert!(delta::apply(&base, &[]).is_none());
+}
+
+#[test]
+fn test_delta_invalid_ops() {
+    use delta::{apply_ops, OpsCoding};
+    let base = [1, 2, 3, 4];
+
+    // One literal, then a match of two bytes that starts one byte back.
+    let ops = [1, 9, 2, 1, 0, 0, 0];
+    assert_eq!(apply_ops(&base, &ops, 3, OpsCoding::Patch).unwrap(), [9; 3]);
+
+    // Matches with a zero offset have no source.
+    let ops = [1, 9, 2, 0, 0, 0, 0];
+    assert!(apply_ops(&base, &ops, 3, OpsCoding::Patch).is_none());
+    let ops = [0, 4, 0];
+    assert!(apply_ops(&base, &ops, 4, OpsCoding::Leb128).is_none());
+}
@@ -0,0 +1,129 @@
+use compressor::coding::adaptive::AdaptiveArithmeticDecoder as AAD;
+use compressor::coding::adaptive::AdaptiveArithmeticEncoder as AAE;
+use compressor::lz::dictionary::{inspect_dict, resize_dict};
+use compressor::lz::{LZ4Decoder, LZ4Encoder};
+use compressor::{Context, Decoder, Encoder};
+
+/// Return a dictionary of 'count' distinct phrases of 64 bytes.
+fn phrases(count: usize) -> Vec<u8> {
+    let mut dict = Vec::new();
+    let mut seed: u32 = 7;
+    for _ in 0..count * 64 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        dict.push(b'a' + (seed >> 16) as u8 % 26);
+    }
+    dict
+}
+
+/// Return the size of 'sample' encoded with the LZ4 dictionary 'dict'.
+fn encoded_len(sample: &[u8], dict: &[u8]) -> usize {
+    let mut compressed = Vec::new();
+    let ctx = Context::new(4, 1 << 20);
+    let _ = LZ4Encoder::new(sample, &mut compressed, ctx)
+        .with_dict(dict)
+        .encode();
+    let mut decoded = Vec::new();
+    let res = LZ4Decoder::new(&compressed, &mut decoded)
+        .with_dict(dict)
+        .decode();
+    assert!(res.is_some());
+    assert_eq!(decoded, sample);
+    compressed.len()
+}
+
+#[test]
+fn test_inspect_dict() {
+    let dict = phrases(16);
+    let phrase = |i: usize| &dict[i * 64..i * 64 + 64];
+
+    // The samples use the phrases 3 and 12, and only the first uses 5.
+    let mut samples = Vec::new();
+    for i in 0..4 {
+        let mut sample = format!("sample {} ", i).into_bytes();
+        sample.extend(phrase(3));
+        sample.extend(b" -- ");
+        sample.extend(phrase(12));
+        if i == 0 {
+            sample.extend(phrase(5));
+        }
+        samples.push(sample);
+    }
+    samples.push(b"nothing in common".to_vec());
+    let refs: Vec<&[u8]> = samples.iter().map(|s| &s[..]).collect();
+
+    let report = inspect_dict(&dict, &refs, 64);
+    assert_eq!(report.segments.len(), 16);
+    assert_eq!(report.samples, 5);
+    assert_eq!(report.covered_samples, 4);
+    assert_eq!(report.sample_bytes, refs.iter().map(|s| s.len()).sum());
+    assert!(report.coverage() > 0.5);
+
+    let ranked: Vec<_> =
+        report.ranked().iter().map(|s| s.range.start).collect();
+    assert_eq!(ranked[..2], [3 * 64, 12 * 64]);
+    assert_eq!(ranked[2], 5 * 64);
+    let seg = &report.segments[3];
+    assert_eq!((seg.matched, seg.samples), (4 * 64, 4));
+    assert_eq!(report.segments[5].samples, 1);
+    assert_eq!(report.segments[0].matched, 0);
+
+    let text = format!("{:#}", report);
+    assert!(text.starts_with("dictionary: 1024 bytes, 3 of 16 segments used"));
+    assert!(text.contains("192..256: 256 bytes matched by 4 samples"));
+
+    // The smaller dictionary keeps the phrases that the samples use.
+    let small = resize_dict(&dict, &report, 150);
+    assert_eq!(small.len(), 128);
+    assert_eq!(small[..64], *phrase(3));
+    assert_eq!(small[64..], *phrase(12));
+    assert!(resize_dict(&dict, &report, 10).is_empty());
+    assert_eq!(resize_dict(&dict, &report, 1 << 20).len(), 3 * 64);
+    for sample in &refs[1..] {
+        assert_eq!(encoded_len(sample, &small), encoded_len(sample, &dict));
+    }
+    assert!(encoded_len(refs[1], &small) < encoded_len(refs[1], &[]));
+}
+
+#[test]
+fn test_inspect_dict_window() {
+    // Only the window of the format is described.
+    let dict = vec![b'x'; 70000];
+    let report = inspect_dict(&dict, &[b"xxxxxxxxxxxxxxxxxxxx"], 1 << 12);
+    assert_eq!(report.segments[0].range.start, 70000 - 65535);
+    assert_eq!(report.segments.last().unwrap().range.end, 70000);
+    assert_eq!(report.covered_samples, 1);
+
+    let report = inspect_dict(&[], &[b"sample"], 64);
+    assert!(report.segments.is_empty());
+    assert_eq!(report.matched, 0);
+}
+
+#[test]
+fn test_adaptive_dict() {
+    let mut dict = Vec::new();
+    for i in 0..200 {
+        dict.extend(format!("{{\"id\": {}, \"status\": \"ok\"}}\n", i).bytes());
+    }
+    let message = b"{\"id\": 4711, \"status\": \"ok\"}\n";
+    let ctx = Context::new(13, 1 << 16);
+
+    let mut plain = Vec::new();
+    let _ = AAE::new(message, &mut plain, ctx).encode();
+    let mut primed = Vec::new();
+    let written = AAE::new(message, &mut primed, ctx)
+        .with_dict(&dict)
+        .encode();
+    assert_eq!(written, primed.len());
+    assert!(primed.len() * 2 < plain.len());
+
+    // The stream is decoded with the same dictionary.
+    let mut decoded = Vec::new();
+    let res = AAD::new(&primed, &mut decoded).with_dict(&dict).decode();
+    assert_eq!(res, Some((primed.len(), message.len())));
+    assert_eq!(decoded, message);
+
+    // Other dictionaries don't give the message.
+    let mut decoded = Vec::new();
+    let res = AAD::new(&primed, &mut decoded).decode();
+    assert!(res.is_none() || decoded != message);
+}
@@ -0,0 +1,157 @@
+use compressor::digest::SHA256_ID;
+use compressor::digest::{read_digest_trailer, sha256, DigestHasher, Sha256};
+use compressor::frame::{verify_frame, FRAME_TRAILER_LEN};
+use compressor::full::{encode_vectored, FullDecoder, FullEncoder};
+use compressor::utils::leb128;
+use compressor::utils::signatures::DIGEST_SIG;
+use compressor::{Context, Decoder, Encoder};
+use std::io::IoSlice;
+
+/// Return the lowercase hex encoding of 'bytes'.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A toy hash function with its own id, for testing the pluggable hashers.
+#[derive(Default)]
+struct SumHasher(u64);
+
+impl DigestHasher for SumHasher {
+    fn id(&self) -> u8 {
+        200
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = self.0.wrapping_mul(31).wrapping_add(*byte as u64);
+        }
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+}
+
+fn input() -> Vec<u8> {
+    "the digest covers the uncompressed bytes. "
+        .repeat(400)
+        .into_bytes()
+}
+
+#[test]
+fn test_sha256() {
+    assert_eq!(
+        hex(&sha256(b"")),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    assert_eq!(
+        hex(&sha256(long)),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+
+    // The digest doesn't depend on the sizes of the parts.
+    let data = input();
+    for part in [1, 63, 64, 65, 1000] {
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(part) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), sha256(&data));
+    }
+}
+
+#[test]
+fn test_digest_trailer() {
+    let input = input();
+    for ctx in [
+        Context::new(5, 1 << 12).with_digest(),
+        Context::new(5, 1 << 12).with_digest().with_frame_checksum(),
+        Context::new(0, 1 << 12).with_digest(),
+    ] {
+        let mut compressed = Vec::new();
+        let written = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        assert_eq!(written, compressed.len());
+        let mut decoded = Vec::new();
+        let res = FullDecoder::new(&compressed, &mut decoded).decode();
+        assert_eq!(res, Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+
+        // The frame checksum covers the digest.
+        let end =
+            compressed.len() - ctx.frame_checksum as usize * FRAME_TRAILER_LEN;
+        assert_eq!(
+            verify_frame(&compressed),
+            ctx.frame_checksum.then_some(true)
+        );
+        let start = end - 36;
+        let (len, id, digest) =
+            read_digest_trailer(&compressed[start..]).unwrap();
+        assert_eq!((len, id), (36, SHA256_ID));
+        assert_eq!(digest, sha256(&input));
+
+        // A damaged digest is rejected.
+        let mut damaged = compressed.clone();
+        damaged[end - 1] ^= 1;
+        let mut decoded = Vec::new();
+        assert!(FullDecoder::new(&damaged, &mut decoded).decode().is_none());
+    }
+
+    // The vectored encoder saves the same digest.
+    let bufs = [IoSlice::new(&input[..1000]), IoSlice::new(&input[1000..])];
+    let ctx = Context::new(5, 1 << 12).with_digest();
+    let mut compressed = Vec::new();
+    let _ = encode_vectored(&bufs, &mut compressed, ctx);
+    let start = compressed.len() - 36;
+    let (_, _, digest) = read_digest_trailer(&compressed[start..]).unwrap();
+    assert_eq!(digest, sha256(&input));
+    let mut decoded = Vec::new();
+    let _ = FullDecoder::new(&compressed, &mut decoded)
+        .decode()
+        .unwrap();
+    assert_eq!(decoded, input);
+
+    // Digest lengths that overflow the cursor are rejected.
+    let mut forged = DIGEST_SIG.to_vec();
+    forged.push(SHA256_ID);
+    leb128::encode(u64::MAX, &mut forged);
+    assert!(read_digest_trailer(&forged).is_none());
+}
+
+#[test]
+fn test_digest_hasher() {
+    let input = input();
+    let ctx = Context::new(5, 1 << 12);
+    let mut hasher = SumHasher::default();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx)
+        .with_hasher(&mut hasher)
+        .encode();
+
+    // The decoder checks the digest with the same hash function.
+    let mut hasher = SumHasher::default();
+    let mut decoded = Vec::new();
+    let res = FullDecoder::new(&compressed, &mut decoded)
+        .with_hasher(&mut hasher)
+        .decode();
+    assert_eq!(res, Some((compressed.len(), input.len())));
+
+    // Decoders without the hash function skip the digest.
+    let mut decoded = Vec::new();
+    let res = FullDecoder::new(&compressed, &mut decoded).decode();
+    assert_eq!(res, Some((compressed.len(), input.len())));
+
+    // Streams without a digest of the hash function are rejected.
+    let mut plain = Vec::new();
+    let _ = FullEncoder::new(&input, &mut plain, ctx.with_digest()).encode();
+    let mut hasher = SumHasher::default();
+    let mut decoded = Vec::new();
+    let res = FullDecoder::new(&plain, &mut decoded)
+        .with_hasher(&mut hasher)
+        .decode();
+    assert!(res.is_none());
+}
@@ -1,4 +1,6 @@
-use compressor::coding::entropy::{EntropyDecoder, EntropyEncoder};
+use compressor::coding::entropy::{
+    EntropyDecoder, EntropyEncoder, EntropyTables,
+};
 use compressor::Encoder;
 use compressor::{Context, Decoder};
 use rand_distr::Distribution;
@@ -83,6 +85,29 @@ fn test_simple_decoder() {
     let _ = DecoderTy::new(&vec![0; 10000], &mut output).decode();
 }
 
+#[test]
+fn test_invalid_histograms() {
+    let text = "histograms are validated while they are loaded".repeat(20);
+    let mut encoded = Vec::new();
+    let ctx = Context::new(9, 0);
+    let _ = EncoderTy::new(text.as_bytes(), &mut encoded, ctx).encode();
+    let mut output = Vec::new();
+    assert!(DecoderTy::new(&encoded, &mut output).decode().is_some());
+
+    // The counts of the first symbols exceed the size of the table.
+    let mut modified = vec![255; 20];
+    modified.extend(&encoded);
+    let mut output = Vec::new();
+    assert!(DecoderTy::new(&modified, &mut output).decode().is_none());
+
+    // The counts don't add up to the size of the table.
+    let mut modified = encoded.clone();
+    let pos = modified.iter().position(|x| *x > 1 && *x < 255).unwrap();
+    modified[pos] -= 1;
+    let mut output = Vec::new();
+    assert!(DecoderTy::new(&modified, &mut output).decode().is_none());
+}
+
 #[test]
 fn test_simple_decoder_random() {
     use rand::thread_rng;
@@ -108,3 +133,284 @@ fn test_simple_decoder_random() {
         }
     }
 }
+
+#[test]
+fn test_encoder_out_of_range_symbols() {
+    let ctx = Context::new(9, 1 << 20);
+    let mut compressed = Vec::new();
+
+    // The symbol 7 does not fit in an alphabet of 4 symbols.
+    let input = [0, 1, 2, 3, 7, 1];
+    let mut enc = EntropyEncoder::<4, 64>::new(&input, &mut compressed, ctx);
+    assert!(enc.try_encode().is_none());
+    assert!(compressed.is_empty());
+
+    let input = [0, 1, 2, 3, 3, 1];
+    let mut enc = EntropyEncoder::<4, 64>::new(&input, &mut compressed, ctx);
+    assert_eq!(enc.try_encode(), Some(compressed.len()));
+}
+
+#[test]
+fn test_encoder_reset() {
+    let ctx = Context::new(9, 1 << 20);
+    let inputs: [&[u8]; 3] = [b"first message", b"", b"the second message"];
+
+    // Encode all of the messages with a single encoder.
+    let mut compressed = Vec::new();
+    let mut sizes = Vec::new();
+    {
+        let mut enc = EncoderTy::new(inputs[0], &mut compressed, ctx);
+        for input in inputs {
+            enc.reset(input);
+            sizes.push(enc.encode());
+        }
+    }
+
+    // Decode all of the messages with a single decoder.
+    let mut decompressed = Vec::new();
+    let mut dec = DecoderTy::new(&[], &mut decompressed);
+    let mut cursor = 0;
+    let mut lens = Vec::new();
+    for size in sizes {
+        dec.reset(&compressed[cursor..cursor + size]);
+        let (read, written) = dec.decode().unwrap();
+        assert_eq!(read, size);
+        cursor += size;
+        lens.push(written);
+    }
+    assert_eq!(cursor, compressed.len());
+    assert_eq!(decompressed, inputs.concat());
+    assert_eq!(lens, inputs.iter().map(|x| x.len()).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_split_streams_round_trip() {
+    fn round_trip_split(input: &[u8]) -> usize {
+        let ctx = Context::new(9, 1 << 20);
+        let mut compressed = Vec::new();
+        let mut enc = EncoderTy::new(input, &mut compressed, ctx);
+        let compressed_size = enc.try_encode_split().unwrap();
+        assert_eq!(compressed.len(), compressed_size);
+
+        let mut decompressed = Vec::new();
+        let mut decoder = DecoderTy::new(&compressed, &mut decompressed);
+        let (consumed, written) = decoder.decode_split().unwrap();
+        assert_eq!(compressed.len(), consumed);
+        assert_eq!(input.len(), written);
+        assert_eq!(decompressed, input);
+        compressed_size
+    }
+
+    // Check all of the remainders of the interleaving.
+    let text = "entropy encoding is typically the last stage of a pipeline";
+    for len in 0..12 {
+        round_trip_split(&text.as_bytes()[..len]);
+    }
+    round_trip_split(text.as_bytes());
+
+    let mut rng = rand::thread_rng();
+    let dist = rand_distr::Geometric::new(0.1).unwrap();
+    let input: Vec<u8> = (0..10000)
+        .map(|_| dist.sample(&mut rng).min(255) as u8)
+        .collect();
+    let split_size = round_trip_split(&input);
+
+    // The split encoding costs a few bytes more than the single stream.
+    let mut compressed = Vec::new();
+    let ctx = Context::new(9, 1 << 20);
+    let single_size = EncoderTy::new(&input, &mut compressed, ctx).encode();
+    assert!(split_size < single_size + 64);
+
+    // Truncated inputs are rejected.
+    let mut compressed = Vec::new();
+    let mut enc = EncoderTy::new(&input, &mut compressed, ctx);
+    let _ = enc.try_encode_split().unwrap();
+    let mut decompressed = Vec::new();
+    let mut truncated = DecoderTy::new(&compressed[..100], &mut decompressed);
+    assert!(truncated.decode_split().is_none());
+}
+
+#[test]
+fn test_reused_tables() {
+    let inputs: [&[u8]; 4] = [
+        b"aaaaaaaaaaaaaaaaaaaaaaaabbbbbbbbbbcccd",
+        b"the same tables are reused for every input",
+        b"the same tables are reused for every input",
+        b"zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz",
+    ];
+    let mut enc_tables = EntropyTables::<256, 4096>::new();
+    let mut dec_tables = EntropyTables::<256, 4096>::new();
+    for input in inputs {
+        let mut compressed = Vec::new();
+        let mut enc =
+            EncoderTy::with_tables(input, &mut compressed, enc_tables);
+        let _ = enc.encode();
+        enc_tables = enc.into_tables();
+
+        // Compare with an encoder that uses fresh tables.
+        let mut expected = Vec::new();
+        let ctx = Context::new(9, 1 << 20);
+        let _ = EncoderTy::new(input, &mut expected, ctx).encode();
+        assert_eq!(compressed, expected);
+
+        let mut decompressed = Vec::new();
+        let mut dec =
+            DecoderTy::with_tables(&compressed, &mut decompressed, dec_tables);
+        assert!(dec.decode().is_some());
+        dec_tables = dec.into_tables();
+        assert_eq!(decompressed, input);
+    }
+}
+
+#[test]
+fn test_precise_spread() {
+    use compressor::coding::entropy::PRECISE_SPREAD_LEVEL;
+
+    // A skewed input, with a few frequent symbols and many rare ones.
+    let mut input = Vec::new();
+    for i in 0..20000u32 {
+        let sym = (i * 7919 % 97) as u8;
+        input.push(if sym < 80 { sym % 3 } else { sym });
+    }
+    let mut prime = Vec::new();
+    let ctx = Context::new(PRECISE_SPREAD_LEVEL - 1, 1 << 20);
+    let _ = EncoderTy::new(&input, &mut prime, ctx).encode();
+    let mut precise = Vec::new();
+    let ctx = Context::new(PRECISE_SPREAD_LEVEL, 1 << 20);
+    let _ = EncoderTy::new(&input, &mut precise, ctx).encode();
+    assert_ne!(prime, precise);
+
+    // The decoder selects the layout from the header of the stream.
+    for compressed in [&prime, &precise] {
+        let mut decompressed = Vec::new();
+        let mut decoder = DecoderTy::new(compressed, &mut decompressed);
+        assert_eq!(decoder.decode().unwrap().0, compressed.len());
+        assert_eq!(decompressed, input);
+    }
+
+    // The layout byte follows the histogram, and unknown layouts are invalid.
+    let pos = prime
+        .iter()
+        .zip(&precise)
+        .position(|(a, b)| a != b)
+        .unwrap();
+    assert_eq!((prime[pos], precise[pos]), (0, 1));
+    precise[pos] = 2;
+    let mut output = Vec::new();
+    assert!(DecoderTy::new(&precise, &mut output).decode().is_none());
+}
+
+/// Encode and decode 'input' with a coder of the table size 'TABLESIZE'.
+fn round_trip_table<const TABLESIZE: usize>(input: &[u8]) {
+    let ctx = Context::new(9, 1 << 20);
+    let mut compressed = Vec::new();
+    let _ = EntropyEncoder::<256, TABLESIZE>::new(input, &mut compressed, ctx)
+        .encode();
+    let mut decompressed = Vec::new();
+    let mut decoder =
+        EntropyDecoder::<256, TABLESIZE>::new(&compressed, &mut decompressed);
+    assert_eq!(decoder.decode().unwrap().0, compressed.len());
+    assert_eq!(decompressed, input);
+}
+
+#[test]
+fn test_table_sizes() {
+    // Symbols with frequencies that are powers of two and in between, which
+    // renormalize around the thresholds of the symbols.
+    let mut input = Vec::new();
+    for i in 0..3000u32 {
+        input.push((i.trailing_zeros() * 3 + i % 2) as u8);
+    }
+    round_trip_table::<512>(&input);
+    round_trip_table::<1024>(&input);
+    round_trip_table::<4096>(&input);
+    round_trip_table::<16384>(&input);
+}
+
+#[test]
+fn test_literal_coder_round_trip() {
+    use compressor::coding::literal::{LiteralDecoder, LiteralEncoder};
+
+    // Literals that follow a previous byte, some with a match byte that is
+    // equal to the literal or differs from it in a few bits.
+    let mut input = Vec::new();
+    for i in 0..5000u32 {
+        let byte = (i * 7 % 61) as u8 + b'A';
+        let match_byte = match i % 3 {
+            0 => None,
+            1 => Some(byte),
+            _ => Some(byte ^ (1 << (i % 8))),
+        };
+        input.push((byte, match_byte));
+    }
+
+    let mut stream = Vec::new();
+    let mut encoder = LiteralEncoder::new(&mut stream);
+    let mut prev = 0;
+    for (pos, (byte, match_byte)) in input.iter().enumerate() {
+        encoder.encode(*byte, pos, prev, *match_byte);
+        prev = *byte;
+    }
+    encoder.finish();
+    assert!(stream.len() < input.len());
+
+    let mut decoder = LiteralDecoder::new(&stream).unwrap();
+    let mut prev = 0;
+    for (pos, (byte, match_byte)) in input.iter().enumerate() {
+        assert_eq!(decoder.decode(pos, prev, *match_byte), Some(*byte));
+        prev = *byte;
+    }
+    assert_eq!(decoder.read(), stream.len());
+    assert!(LiteralDecoder::new(&stream[..3]).is_none());
+}
+
+#[test]
+fn test_detect_alignment() {
+    use compressor::coding::literal::detect_alignment;
+
+    let text = "the quick brown fox jumps over the lazy dog. ".repeat(100);
+    assert_eq!(detect_alignment(text.as_bytes()), 1);
+    assert_eq!(detect_alignment(&[]), 1);
+
+    // Little-endian words with a varying low byte and constant high bytes.
+    let mut words = Vec::new();
+    let mut seed: u32 = 1;
+    for _ in 0..4000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        words.extend((0x4100_0000 + (seed >> 16) % 1024).to_le_bytes());
+    }
+    assert_eq!(detect_alignment(&words), 4);
+}
+
+#[test]
+fn test_residual_coder_round_trip() {
+    use compressor::coding::residual::{ResidualDecoder, ResidualEncoder};
+
+    fn round_trip(input: &[u8]) -> usize {
+        let ctx = Context::new(12, 1 << 20).with_hybrid();
+        let mut encoded = Vec::new();
+        let written = ResidualEncoder::new(input, &mut encoded, ctx).encode();
+        assert_eq!(written, encoded.len());
+        let mut decoded = Vec::new();
+        let stat = ResidualDecoder::new(&encoded, &mut decoded).decode();
+        assert_eq!(stat, Some((encoded.len(), input.len())));
+        assert_eq!(decoded, input);
+
+        // Truncated streams are rejected.
+        let mut decoded = Vec::new();
+        let truncated = &encoded[..encoded.len() - 3];
+        assert!(ResidualDecoder::new(truncated, &mut decoded)
+            .decode()
+            .is_none());
+        encoded.len()
+    }
+
+    round_trip(&[]);
+    round_trip(&[7]);
+    // Tokens that depend on the previous tokens compress well.
+    let tokens: Vec<u8> =
+        (0..20000).map(|i| [0x12, 0x40, 0x23][i % 3]).collect();
+    assert!(round_trip(&tokens) < 200);
+    let text = "the quick brown fox jumps over the lazy dog. ".repeat(100);
+    round_trip(text.as_bytes());
+}
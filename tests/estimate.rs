@@ -0,0 +1,147 @@
+use compressor::estimate::estimate_ratio;
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::{Context, Decoder, Encoder};
+
+/// Generate 'len' pseudo-random bytes.
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_estimate_ratio() {
+    assert_eq!(estimate_ratio(&[]), 1.0);
+
+    // Random data is not compressible.
+    let random = random_bytes(1 << 20);
+    assert!(estimate_ratio(&random) < 1.02);
+    assert!(estimate_ratio(&random[..1000]) < 1.5);
+
+    // Repetitive data is very compressible.
+    let zeros = vec![0u8; 1 << 20];
+    assert!(estimate_ratio(&zeros) > 100.);
+
+    let text = "The quick brown fox jumps over the lazy dog. ".repeat(10000);
+    assert!(estimate_ratio(text.as_bytes()) > 10.);
+
+    // Skewed data compresses well even without repetitions.
+    let skewed: Vec<u8> = random.iter().map(|x| x & 0x3).collect();
+    let ratio = estimate_ratio(&skewed);
+    assert!(ratio > 3. && ratio < 5., "{}", ratio);
+}
+
+#[test]
+fn test_full_skips_incompressible_input() {
+    let input = random_bytes(100_000);
+    let ctx = Context::new(9, 1 << 14);
+
+    let mut compressed: Vec<u8> = Vec::new();
+    let written = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    assert_eq!(written, compressed.len());
+    assert!(written < input.len() + 200);
+
+    let mut decompressed: Vec<u8> = Vec::new();
+    let res = FullDecoder::new(&compressed, &mut decompressed).decode();
+    assert_eq!(res, Some((compressed.len(), input.len())));
+    assert_eq!(decompressed, input);
+}
+
+#[test]
+fn test_full_repeated_incompressible_input() {
+    // The repetition is far apart, so estimates of the input don't see it.
+    let random = random_bytes(1 << 18);
+    let input = random.repeat(2);
+    let ctx = Context::new(4, 1 << 20);
+
+    let mut compressed: Vec<u8> = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    assert!(compressed.len() < random.len() + 1000);
+
+    let mut decompressed: Vec<u8> = Vec::new();
+    let res = FullDecoder::new(&compressed, &mut decompressed).decode();
+    assert_eq!(res, Some((compressed.len(), input.len())));
+    assert_eq!(decompressed, input);
+}
+
+#[test]
+fn test_incompressible_page_bypass() {
+    use compressor::full::{decode_or_nop, encode_or_nop};
+    use compressor::utils::signatures::{match_signature, NOP_ENC};
+
+    let ctx = Context::new(9, 1 << 16);
+    let random = random_bytes(1 << 16);
+    let encoded = encode_or_nop(&random, ctx);
+    assert!(match_signature(&encoded, &NOP_ENC));
+    assert_eq!(
+        decode_or_nop(&encoded),
+        Some((encoded.len(), random.clone()))
+    );
+
+    let text = "The quick brown fox jumps over the lazy dog. ".repeat(1000);
+    let encoded = encode_or_nop(text.as_bytes(), ctx);
+    assert!(!match_signature(&encoded, &NOP_ENC));
+    assert!(encoded.len() < text.len() / 10);
+
+    // Pages that repeat incompressible data are compressed.
+    let repeated = random.repeat(2);
+    let encoded = encode_or_nop(&repeated, Context::new(9, 1 << 17));
+    assert!(!match_signature(&encoded, &NOP_ENC));
+    assert!(encoded.len() < random.len() + 1000);
+    assert_eq!(decode_or_nop(&encoded), Some((encoded.len(), repeated)));
+
+    // Mix compressible and incompressible pages in one stream.
+    let mut input = text.as_bytes().to_vec();
+    input.extend(random_bytes(100_000));
+    input.extend(text.as_bytes());
+    let mut compressed: Vec<u8> = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    assert!(compressed.len() < 100_000 + input.len() / 20);
+
+    let mut decompressed: Vec<u8> = Vec::new();
+    let res = FullDecoder::new(&compressed, &mut decompressed).decode();
+    assert_eq!(res, Some((compressed.len(), input.len())));
+    assert_eq!(decompressed, input);
+}
+
+#[test]
+fn test_full_stored_fallback() {
+    fn round_trip(input: &[u8]) -> usize {
+        let ctx = Context::new(9, 1 << 14);
+        let mut compressed: Vec<u8> = vec![7, 7];
+        let written = FullEncoder::new(input, &mut compressed, ctx).encode();
+        assert_eq!(written + 2, compressed.len());
+
+        let mut decompressed: Vec<u8> = Vec::new();
+        let res =
+            FullDecoder::new(&compressed[2..], &mut decompressed).decode();
+        assert_eq!(res, Some((written, input.len())));
+        assert_eq!(decompressed, input);
+        written
+    }
+
+    // Small and incompressible inputs are stored with a small overhead.
+    assert_eq!(round_trip(&[]), 7);
+    assert_eq!(round_trip(b"abc"), 10);
+    assert_eq!(round_trip(&random_bytes(50_000)), 50_009);
+
+    // Compressible inputs are still compressed.
+    let text = "The quick brown fox jumps over the lazy dog. ".repeat(1000);
+    assert!(round_trip(text.as_bytes()) < text.len() / 10);
+
+    // Truncated stored streams are rejected.
+    let mut compressed: Vec<u8> = Vec::new();
+    let ctx = Context::new(9, 1 << 14);
+    let _ = FullEncoder::new(b"abcd", &mut compressed, ctx).encode();
+    compressed.pop();
+    let mut decompressed: Vec<u8> = Vec::new();
+    assert!(FullDecoder::new(&compressed, &mut decompressed)
+        .decode()
+        .is_none());
+}
@@ -0,0 +1,77 @@
+use compressor::frame::{frame_checksum, verify_frame, FrameHasher};
+use compressor::frame::{read_frame_trailer, FRAME_TRAILER_LEN};
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::{decode_exact, Context, Decoder, Encoder};
+
+/// Return the input and its compressed stream, with the frame checksum.
+fn compress_with_frame(level: u8) -> (Vec<u8>, Vec<u8>) {
+    let text = "a frame checksum covers the compressed bytes. ".repeat(3000);
+    let input = text.into_bytes();
+    let ctx = Context::new(level, 1 << 14).with_frame_checksum();
+    let mut compressed = Vec::new();
+    let written = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    assert_eq!(written, compressed.len());
+    (input, compressed)
+}
+
+#[test]
+fn test_frame_hasher() {
+    let data: Vec<u8> =
+        (0..300000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+    let checksum = frame_checksum(&data);
+    for part in [1, 1000, 65536, 100000] {
+        let mut hasher = FrameHasher::new();
+        for chunk in data.chunks(part) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), checksum);
+    }
+    assert_ne!(frame_checksum(&data[1..]), checksum);
+}
+
+#[test]
+fn test_frame_checksum() {
+    for level in [0, 5, 13] {
+        let (input, compressed) = compress_with_frame(level);
+        let end = compressed.len() - FRAME_TRAILER_LEN;
+        let checksum = read_frame_trailer(&compressed[end..]).unwrap();
+        assert_eq!(checksum, frame_checksum(&compressed[..end]));
+        assert_eq!(verify_frame(&compressed), Some(true));
+
+        // The decoder reads the trailer, and checks it.
+        let mut decoded = Vec::new();
+        let stat = decode_exact::<FullDecoder>(&compressed, &mut decoded);
+        assert_eq!(stat, Some(input.len()));
+        assert_eq!(decoded, input);
+
+        // A damaged byte is detected with or without decompression.
+        let mut damaged = compressed.clone();
+        damaged[end / 2] ^= 0x10;
+        assert_eq!(verify_frame(&damaged), Some(false));
+        let mut decoded = Vec::new();
+        assert!(FullDecoder::new(&damaged, &mut decoded).decode().is_none());
+    }
+
+    // Streams without a trailer can't be verified without decompressing.
+    let (input, compressed) = compress_with_frame(5);
+    let mut plain = Vec::new();
+    let ctx = Context::new(5, 1 << 14);
+    let _ = FullEncoder::new(&input, &mut plain, ctx).encode();
+    assert_eq!(plain.len() + FRAME_TRAILER_LEN, compressed.len());
+    assert_eq!(verify_frame(&plain), None);
+    assert_eq!(verify_frame(b"not a stream"), None);
+}
+
+#[test]
+fn test_frame_checksum_stored() {
+    // Incompressible inputs are stored, and still get the trailer.
+    let input: Vec<u8> = (0..5000).map(|_| rand::random::<u8>()).collect();
+    let ctx = Context::new(9, 1 << 14).with_frame_checksum();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    assert_eq!(verify_frame(&compressed), Some(true));
+    let mut decoded = Vec::new();
+    let stat = FullDecoder::new(&compressed, &mut decoded).decode();
+    assert_eq!(stat, Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+}
@@ -0,0 +1,57 @@
+use compressor::framing::{MessageReader, MessageWriter};
+use compressor::Context;
+use std::io::ErrorKind;
+
+/// Return a few messages of different sizes, including an empty message.
+fn messages() -> Vec<Vec<u8>> {
+    let mut messages = vec![b"hello".to_vec(), Vec::new()];
+    messages.push("a longer message that repeats. ".repeat(500).into_bytes());
+    messages.push((0..3000).map(|i| (i * 31 % 251) as u8).collect());
+    messages
+}
+
+#[test]
+fn test_message_framing() {
+    let ctx = Context::new(5, 1 << 16);
+    let mut writer = MessageWriter::new(Vec::new(), ctx);
+    let mut written = 0;
+    for message in messages() {
+        written += writer.write_message(&message).unwrap();
+    }
+    writer.flush().unwrap();
+    let stream = writer.into_inner();
+    assert_eq!(written, stream.len());
+
+    let mut reader = MessageReader::new(&stream[..]);
+    for message in messages() {
+        assert_eq!(reader.read_message().unwrap(), Some(&message[..]));
+    }
+    assert_eq!(reader.read_message().unwrap(), None);
+}
+
+#[test]
+fn test_message_framing_errors() {
+    let ctx = Context::new(5, 1 << 16);
+    let mut writer = MessageWriter::new(Vec::new(), ctx);
+    let _ = writer.write_message(b"first message").unwrap();
+    let _ = writer.write_message(b"second message").unwrap();
+    let stream = writer.into_inner();
+
+    // A truncated record.
+    let mut reader = MessageReader::new(&stream[..stream.len() - 1]);
+    assert!(reader.read_message().is_ok());
+    let err = reader.read_message().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+    // A damaged record.
+    let mut damaged = stream.clone();
+    damaged[1] ^= 0xff;
+    let mut reader = MessageReader::new(&damaged[..]);
+    let err = reader.read_message().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // A length that is longer than the stream.
+    let mut reader = MessageReader::new(&[0xff, 0xff, 0xff, 0x7f, 1][..]);
+    let err = reader.read_message().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,67 @@
+use compressor::coding::frozen::{FrozenDecoder, FrozenEncoder, FrozenModel};
+use compressor::full::{decode_prefix, FullDecoder, FullEncoder};
+use compressor::{decode_exact, Context, Decoder, Encoder};
+
+fn text() -> Vec<u8> {
+    let mut text = Vec::new();
+    for i in 0..6000u32 {
+        let word = ["alpha", "beta", "gamma", "delta"][(i * 7 % 4) as usize];
+        text.extend(format!("{} {}, ", word, i % 97).bytes());
+    }
+    text
+}
+
+#[test]
+fn test_frozen_round_trip() {
+    let text = text();
+    for input in [&b""[..], b"x", &text[..100], &text] {
+        for page_size in [1 << 10, 5000, 1 << 16] {
+            let ctx = Context::new(13, page_size).with_frozen_model();
+            let mut compressed = Vec::new();
+            let written =
+                FullEncoder::new(input, &mut compressed, ctx).encode();
+            assert_eq!(written, compressed.len());
+            let mut decoded = Vec::new();
+            let res = FullDecoder::new(&compressed, &mut decoded).decode();
+            assert_eq!(res, Some((compressed.len(), input.len())));
+            assert_eq!(decoded, input);
+            let prefix = decode_prefix(&compressed, 10).unwrap();
+            assert_eq!(prefix, &input[..10.min(input.len())]);
+        }
+    }
+
+    // The frozen model compresses text, with a loss of ratio.
+    let mut frozen = Vec::new();
+    let ctx = Context::new(13, 1 << 12).with_frozen_model();
+    let _ = FrozenEncoder::new(&text, &mut frozen, ctx).encode();
+    assert!(frozen.len() * 2 < text.len());
+}
+
+#[test]
+fn test_frozen_invalid_streams() {
+    let text = text();
+    let mut compressed = Vec::new();
+    let ctx = Context::new(13, 1 << 13);
+    let _ = FrozenEncoder::new(&text, &mut compressed, ctx).encode();
+    let mut decoded = Vec::new();
+    assert_eq!(
+        decode_exact::<FrozenDecoder>(&compressed, &mut decoded),
+        Some(text.len())
+    );
+
+    // Truncated streams, and streams with damaged page lengths, are rejected.
+    for len in [0, 3, 10, 5000, compressed.len() / 2, compressed.len() - 1] {
+        let mut decoded = Vec::new();
+        let stream = &compressed[..len];
+        assert!(FrozenDecoder::new(stream, &mut decoded).decode().is_none());
+        assert!(decoded.is_empty());
+    }
+
+    // Models with zero probabilities are rejected.
+    let model = FrozenModel::train(&text);
+    let mut saved = Vec::new();
+    let len = model.serialize(&mut saved);
+    assert_eq!(FrozenModel::deserialize(&saved), Some((model, len)));
+    saved[100] = 0;
+    assert!(FrozenModel::deserialize(&saved).is_none());
+}
@@ -0,0 +1,98 @@
+use compressor::handle::Compressor;
+use compressor::Context;
+
+#[test]
+fn test_compressor_reuse() {
+    let mut compressor = Compressor::new(Context::new(4, 1 << 16));
+    for i in 0..20 {
+        let message = format!("message number {} {}", i, "abc".repeat(i * 10));
+        let compressed = compressor.compress(message.as_bytes()).to_vec();
+        let decompressed = compressor.decompress(&compressed).unwrap();
+        assert_eq!(decompressed, message.as_bytes());
+    }
+
+    // The tables of the matchers and of the entropy coders stay in the
+    // compressor, and are reused by the next calls, also after a reset.
+    let message =
+        "a message with some repeated words, words, words. ".repeat(50);
+    let compressed = compressor.compress(message.as_bytes()).to_vec();
+    let bytes = compressor.scratch_bytes();
+    assert!(bytes > 0);
+    compressor.reset();
+    assert_eq!(compressor.scratch_bytes(), bytes);
+    assert_eq!(compressor.compress(message.as_bytes()), compressed);
+
+    compressor.reset();
+    assert!(compressor.decompress(&[1, 2, 3]).is_none());
+    let compressed = compressor.compress(&[]).to_vec();
+    assert_eq!(compressor.decompress(&compressed).unwrap(), &[]);
+}
+
+#[test]
+fn test_shared_handles() {
+    use compressor::handle::{CompressorHandle, DecompressorHandle};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CompressorHandle>();
+    assert_send_sync::<DecompressorHandle>();
+
+    let compressor = CompressorHandle::new(Context::new(4, 1 << 16));
+    let decompressor = DecompressorHandle::new();
+
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let compressor = &compressor;
+            let decompressor = &decompressor;
+            s.spawn(move || {
+                let mut scratch = compressor.scratch();
+                for i in 0..10 {
+                    let message = format!("thread {} message {}", t, i);
+                    let message = message.repeat(i + 1);
+                    let compressed = compressor.compress(message.as_bytes());
+                    let decompressed =
+                        decompressor.decompress(&compressed).unwrap();
+                    assert_eq!(decompressed, message.as_bytes());
+                    assert_eq!(
+                        scratch.compress(message.as_bytes()),
+                        compressed
+                    );
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_compress_batch() {
+    use compressor::handle::{compress_batch, compress_batch_parallel};
+    use compressor::handle::{CompressorHandle, DecompressorHandle};
+
+    let rows: Vec<String> = (0..200)
+        .map(|i| {
+            format!(
+                "{{\"id\": {}, \"name\": \"user{}\", \"score\": {}}}{}",
+                i,
+                i,
+                i * 37 % 1000,
+                " pad".repeat(i % 40)
+            )
+        })
+        .collect();
+    let inputs: Vec<&[u8]> = rows.iter().map(|row| row.as_bytes()).collect();
+    let ctx = Context::new(4, 1 << 16);
+
+    let batch = compress_batch(&inputs, ctx);
+    assert_eq!(batch.len(), inputs.len());
+    let handle = CompressorHandle::new(ctx);
+    let decompressor = DecompressorHandle::new();
+    for (input, compressed) in inputs.iter().zip(batch.iter()) {
+        assert_eq!(*compressed, handle.compress(input));
+        assert_eq!(decompressor.decompress(compressed).unwrap(), *input);
+    }
+
+    for threads in [0, 1, 3, 8, 1000] {
+        assert_eq!(compress_batch_parallel(&inputs, ctx, threads), batch);
+    }
+    assert!(compress_batch(&[], ctx).is_empty());
+    assert!(compress_batch_parallel(&[], ctx, 4).is_empty());
+}
@@ -0,0 +1,161 @@
+use compressor::block::{BlockDecoder, BlockEncoder, Sequence};
+use compressor::full::FullEncoder;
+use compressor::inspect::{inspect_block, inspect_stream, BlockKind, Coding};
+use compressor::pipeline::Pipeline;
+use compressor::{Context, Decoder, Encoder};
+
+#[test]
+fn test_inspect_block() {
+    let mut input = Vec::new();
+    let mut seed: u32 = 11;
+    while input.len() < 50000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(format!("{} ", (seed >> 16) % 500).as_bytes());
+    }
+    let mut compressed = Vec::new();
+    let _ =
+        BlockEncoder::new(&input, &mut compressed, Context::new(5, 1 << 20))
+            .encode();
+
+    let info = inspect_block(&compressed).unwrap();
+    assert_eq!(info.kind, BlockKind::Regular);
+    assert_eq!(info.size, compressed.len());
+    assert_eq!(info.len, input.len());
+    let names: Vec<_> = info.streams.iter().map(|s| s.name).collect();
+    assert_eq!(names, ["literals", "sequences", "offsets"]);
+    assert!(matches!(
+        info.streams[0].pages[0],
+        Coding::Entropy { symbols: 11, .. }
+    ));
+
+    // The sequences cover the input, and each match repeats earlier bytes.
+    let mut pos = 0;
+    for &Sequence {
+        lit_len,
+        mat_len,
+        offset,
+    } in &info.sequences
+    {
+        pos += lit_len as usize;
+        if mat_len > 0 {
+            let src = pos - offset as usize;
+            let len = mat_len as usize;
+            assert_eq!(input[src..src + len], input[pos..pos + len]);
+        }
+        pos += mat_len as usize;
+    }
+    assert_eq!(pos, input.len());
+
+    let text = info.to_string();
+    assert!(text.starts_with(&format!(
+        "regular block: {} -> {} bytes",
+        compressed.len(),
+        input.len()
+    )));
+    assert!(text.contains("\n  literals: "));
+    assert_eq!(text.lines().count(), 5);
+    let full = format!("{:#}", info);
+    assert_eq!(full.lines().count(), 5 + info.sequences.len());
+    assert!(full.contains(&format!("\n    {}", info.sequences[0])));
+
+    assert!(inspect_block(&compressed[..compressed.len() - 1]).is_none());
+}
+
+#[test]
+fn test_inspect_stream() {
+    let mut input = b"0123456789".repeat(3000);
+    input.extend(vec![0; 5000]);
+    input.extend(b"abcdefghij".repeat(3000));
+    let ctx = Context::new(3, 1 << 14);
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+
+    let blocks = inspect_stream(&compressed).unwrap();
+    assert!(!blocks.is_empty());
+    let total: usize = blocks.iter().map(|b| b.len).sum();
+    assert!(total <= input.len());
+
+    let fast = Context::new(9, 1 << 14).with_fast_level(0);
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, fast).encode();
+    let blocks = inspect_stream(&compressed).unwrap();
+    assert!(blocks.iter().all(|b| b.kind == BlockKind::Fast));
+    assert!(blocks[0].to_string().starts_with("fast block: "));
+
+    assert!(inspect_stream(b"not a stream").is_none());
+}
+
+#[test]
+fn test_stream_codecs() {
+    // One sequence: distinct literals, followed by a long repetition.
+    let mut input: Vec<u8> = (0..64).collect();
+    while input.len() < 8192 {
+        input.extend_from_within(..64);
+    }
+    let mut compressed = Vec::new();
+    let ctx = Context::new(5, 1 << 20);
+    let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+    let mut decoded = Vec::new();
+    let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+
+    // The literals are too few for a table, and the tokens are runs.
+    let info = inspect_block(&compressed).unwrap();
+    assert_eq!(info.sequences.len(), 1);
+    let codings: Vec<_> = info.streams.iter().map(|s| s.pages[0]).collect();
+    assert_eq!(codings, [Coding::Raw, Coding::Run, Coding::Run]);
+    assert!(info.to_string().contains("literals: 65 bytes, raw"));
+}
+
+#[test]
+fn test_page_log() {
+    // Text pages, a constant page, and random pages that are stored.
+    let mut input = Vec::new();
+    for i in 0..3000u32 {
+        input.extend(format!("entry {} of the page log, ", i % 200).bytes());
+    }
+    input.resize(input.len() + 20000, 0);
+    let mut seed: u32 = 7;
+    for _ in 0..20000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.push((seed >> 16) as u8);
+    }
+
+    let ctx = Context::new(4, 1 << 14)
+        .with_pipeline(Pipeline::parse("delta:1").unwrap());
+    let mut log = Vec::new();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx)
+        .with_page_log(&mut log)
+        .encode();
+    assert_eq!(log.len(), 1 + input.len() / ctx.block_size);
+
+    let mut offset = 0;
+    for (i, entry) in log.iter().enumerate() {
+        assert_eq!((entry.index, entry.offset), (i, offset));
+        assert_eq!(entry.level, 4);
+        offset += entry.input;
+    }
+    assert_eq!(offset, input.len());
+    let records: usize = log.iter().map(|entry| entry.output).sum();
+    assert!(records < compressed.len());
+    let codecs: Vec<_> = log.iter().map(|entry| entry.codec).collect();
+    assert!(codecs.contains(&"pipeline"));
+    assert!(codecs.contains(&"constant"));
+    assert!(codecs.contains(&"stored"));
+    assert!(log.iter().any(|entry| entry.filter.is_some()));
+
+    // Each entry is a line of JSON.
+    for entry in &log {
+        let line = entry.to_json();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["page"], entry.index);
+        assert_eq!(value["output"], entry.output);
+        assert_eq!(value["codec"], entry.codec);
+        match entry.filter {
+            Some(filter) => assert_eq!(value["filter"], filter.to_string()),
+            None => assert!(value["filter"].is_null()),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use compressor::bitvector::Bitvector;
+use compressor::coding::adaptive::AdaptiveArithmeticDecoder;
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::limits::{max_output, set_max_output, DEFAULT_MAX_OUTPUT};
+use compressor::pager::{
+    write_constant_page, write_sized_header, PagerDecoder,
+};
+use compressor::utils::number_encoding::{decode_array16, Endian};
+use compressor::utils::signatures::ARITH_SIG;
+use compressor::utils::variable_length_encoding::decode_array32;
+use compressor::{Context, Decoder, Encoder};
+
+/// Return a paged stream of pages of up to 1MB with a single constant page of
+/// 'len' bytes.
+fn constant_stream(len: usize) -> Vec<u8> {
+    let mut stream = Vec::new();
+    write_sized_header(1, 1 << 20, &mut stream);
+    write_constant_page(7, len, &mut stream);
+    stream
+}
+
+fn decode_constant(stream: &[u8]) -> Option<(usize, usize)> {
+    fn decode(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        Some((input.len(), input.to_vec()))
+    }
+    let mut output = Vec::new();
+    let mut decoder = PagerDecoder::new(stream, &mut output);
+    decoder.set_callback(decode);
+    decoder.decode()
+}
+
+#[test]
+fn test_declared_lengths() {
+    // A few bytes that declare a page of 4GB, which is larger than the page
+    // size that the header declares.
+    let stream = constant_stream(1 << 32);
+    assert!(stream.len() < 20);
+    assert_eq!(max_output(), DEFAULT_MAX_OUTPUT);
+    assert!(decode_constant(&stream).is_none());
+    assert!(decode_constant(&constant_stream((1 << 20) + 1)).is_none());
+
+    // An arithmetic stream that declares 4GB of output, with an output cap.
+    let mut stream = ARITH_SIG.to_vec();
+    stream.extend([0xff; 4]);
+    stream.extend([0; 16]);
+    let mut output = Vec::new();
+    set_max_output(1 << 30);
+    let stat = AdaptiveArithmeticDecoder::new(&stream, &mut output).decode();
+    set_max_output(DEFAULT_MAX_OUTPUT);
+    assert!(stat.is_none());
+
+    // Arrays that declare more items than the input holds.
+    let stream = [0xff, 0xff, 0xff, 0xff, 1, 2, 3];
+    assert!(decode_array32(&stream, &mut Vec::new()).is_none());
+    assert!(decode_array16(&stream, &mut Vec::new(), Endian::Big).is_none());
+    assert!(Bitvector::deserialize(&stream).is_none());
+}
+
+#[test]
+fn test_output_cap() {
+    assert_eq!(max_output(), DEFAULT_MAX_OUTPUT);
+    let stream = constant_stream(1 << 20);
+    assert_eq!(decode_constant(&stream), Some((stream.len(), 1 << 20)));
+
+    set_max_output(1 << 16);
+    assert!(decode_constant(&stream).is_none());
+
+    // The pages of compressed streams are checked too.
+    let input = vec![0; 1 << 17];
+    let mut encoded = Vec::new();
+    let ctx = Context::new(4, 1 << 18);
+    let _ = FullEncoder::new(&input, &mut encoded, ctx).encode();
+    let mut output = Vec::new();
+    assert!(FullDecoder::new(&encoded, &mut output).decode().is_none());
+
+    set_max_output(DEFAULT_MAX_OUTPUT);
+    assert_eq!(decode_constant(&stream), Some((stream.len(), 1 << 20)));
+}
+
+#[test]
+fn test_large_constant_input() {
+    // Records above 1GB that the encoder emits are decoded without a cap.
+    let input = vec![0; (1 << 30) + (1 << 12)];
+    let mut encoded = Vec::new();
+    let ctx = Context::new(1, 1 << 31);
+    let _ = FullEncoder::new(&input, &mut encoded, ctx).encode();
+    assert!(encoded.len() < 100);
+    let mut output = Vec::new();
+    let (read, written) =
+        FullDecoder::new(&encoded, &mut output).decode().unwrap();
+    assert_eq!((read, written), (encoded.len(), input.len()));
+    assert!(output == input);
+}
@@ -0,0 +1,90 @@
+use compressor::logs::{LogCompressor, LogDecompressor};
+
+/// Return the log record with the index 'i'.
+fn record(i: usize) -> Vec<u8> {
+    let level = ["INFO", "WARN", "DEBUG"][i % 3];
+    let line = format!(
+        "2024-05-01T10:{:02}:{:02} {} server: request {} served in {} ms\n",
+        i / 60 % 60,
+        i % 60,
+        level,
+        i * 7,
+        i % 13
+    );
+    line.into_bytes()
+}
+
+#[test]
+fn test_log_compressor() {
+    let mut compressor = LogCompressor::new(1 << 12);
+    let mut decompressor = LogDecompressor::new(1 << 12);
+    let mut raw = 0;
+    let mut compressed = 0;
+    for i in 0..300 {
+        let chunk = record(i);
+        let frame = compressor.append(&chunk);
+        assert_eq!(decompressor.append(&frame).unwrap(), chunk);
+        raw += chunk.len();
+        compressed += frame.len();
+    }
+    // The records refer to the records before them.
+    assert!(compressed * 2 < raw);
+
+    // Empty chunks are valid too.
+    let frame = compressor.append(&[]);
+    assert_eq!(decompressor.append(&frame).unwrap(), Vec::<u8>::new());
+}
+
+#[test]
+fn test_log_compressor_order() {
+    let mut compressor = LogCompressor::default();
+    let frames: Vec<Vec<u8>> =
+        (0..4).map(|i| compressor.append(&record(i))).collect();
+
+    // Frames that are missing or out of order are rejected, and don't modify
+    // the state of the decompressor.
+    let mut decompressor = LogDecompressor::default();
+    assert!(decompressor.append(&frames[1]).is_none());
+    assert_eq!(decompressor.append(&frames[0]).unwrap(), record(0));
+    assert!(decompressor.append(&frames[2]).is_none());
+    assert!(decompressor.append(&frames[0]).is_none());
+    assert_eq!(decompressor.append(&frames[1]).unwrap(), record(1));
+
+    // A decompressor with a different window does not decode the frames.
+    let mut decompressor = LogDecompressor::new(1 << 10);
+    assert!(decompressor.append(&frames[0]).is_none());
+}
+
+#[test]
+fn test_log_invalid_frames() {
+    use compressor::block::BlockEncoder;
+    use compressor::utils::leb128;
+    use compressor::{Context, Encoder};
+
+    // Return the first frame of a stream, with the operations 'ops'.
+    let frame = |ops: &[u8], len: u64| {
+        let mut frame = Vec::new();
+        leb128::encode(0, &mut frame);
+        leb128::encode(1 << 16, &mut frame);
+        leb128::encode(len, &mut frame);
+        let ctx = Context::new(5, 1 << 16);
+        let _ = BlockEncoder::new(ops, &mut frame, ctx).encode();
+        frame
+    };
+    let mut decompressor = LogDecompressor::new(1 << 16);
+    assert_eq!(
+        decompressor.append(&frame(&[2, 7, 8, 0, 0], 2)).unwrap(),
+        [7, 8]
+    );
+
+    // A match without a source, and lengths that overflow.
+    let mut long_literals = Vec::new();
+    leb128::encode(u64::MAX, &mut long_literals);
+    let mut long_match = vec![1, 7];
+    leb128::encode(u64::MAX, &mut long_match);
+    long_match.push(1);
+    for ops in [&[1, 7, 3, 0, 0, 0][..], &long_literals, &long_match] {
+        let mut decompressor = LogDecompressor::new(1 << 16);
+        assert!(decompressor.append(&frame(ops, 4)).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use compressor::lz::{LZ4Decoder, LZ4Encoder};
+use compressor::lz::{copy_match, LZ4Decoder, LZ4Encoder};
 use compressor::{Context, Decoder, Encoder};
 
 const INPUT0_PLAIN: [u8; 63] = [
@@ -116,3 +116,154 @@ fn test_lz4_encoder_const_inputs() {
     assert_eq!(stream, INPUT0_COMPRESSED);
     assert_eq!(stream.len(), written);
 }
+
+#[test]
+fn test_lz4_dictionary() {
+    fn encode(input: &[u8], dict: &[u8], level: u8) -> Vec<u8> {
+        let ctx = Context::new(level, 1 << 20);
+        let mut compressed: Vec<u8> = Vec::new();
+        let written = LZ4Encoder::new(input, &mut compressed, ctx)
+            .with_dict(dict)
+            .encode();
+        assert_eq!(written, compressed.len());
+        compressed
+    }
+
+    fn decode(compressed: &[u8], dict: &[u8]) -> Option<Vec<u8>> {
+        let mut decompressed: Vec<u8> = Vec::new();
+        let (consumed, _) = LZ4Decoder::new(compressed, &mut decompressed)
+            .with_dict(dict)
+            .decode()?;
+        assert_eq!(consumed, compressed.len());
+        Some(decompressed)
+    }
+
+    let dict =
+        "{\"user\": \"alice\", \"action\": \"login\", \"status\": \"ok\"}";
+    let msg = "{\"user\": \"bob\", \"action\": \"logout\", \"status\": \"ok\"}";
+
+    for level in [1, 4, 9, 11] {
+        let with_dict = encode(msg.as_bytes(), dict.as_bytes(), level);
+        let without = encode(msg.as_bytes(), &[], level);
+        assert!(with_dict.len() < without.len());
+        assert_eq!(
+            decode(&with_dict, dict.as_bytes()).unwrap(),
+            msg.as_bytes()
+        );
+
+        // Decoding without the dictionary must not produce the message.
+        assert_ne!(decode(&with_dict, &[]), Some(msg.as_bytes().to_vec()));
+    }
+
+    // Only the last 64KB of long dictionaries are used.
+    let mut long_dict: Vec<u8> = (0..200000).map(|i| (i % 251) as u8).collect();
+    long_dict.extend(msg.as_bytes());
+    let input: Vec<u8> =
+        msg.bytes().chain((0..5000).map(|i| i as u8)).collect();
+    let compressed = encode(&input, &long_dict, 9);
+    assert_eq!(decode(&compressed, &long_dict).unwrap(), input);
+
+    // Inputs that are too short to compress.
+    for input in [&b""[..], b"abc", b"abcdefghijklmnop"] {
+        let compressed = encode(input, b"abcdefghijklmnop", 4);
+        assert_eq!(decode(&compressed, b"abcdefghijklmnop").unwrap(), input);
+    }
+}
+
+#[test]
+fn test_lz4_strict_decoder() {
+    use compressor::lz::LZ4Error;
+
+    fn decode(input: &[u8], max: usize) -> Result<Vec<u8>, LZ4Error> {
+        let mut stream: Vec<u8> = Vec::new();
+        LZ4Decoder::new(input, &mut stream).decode_strict(max)?;
+        Ok(stream)
+    }
+
+    assert_eq!(decode(&INPUT0_COMPRESSED, 1 << 20).unwrap(), INPUT0_PLAIN);
+    assert_eq!(decode(&INPUT0_COMPRESSED, 63).unwrap(), INPUT0_PLAIN);
+    assert_eq!(decode(&INPUT0_COMPRESSED, 62), Err(LZ4Error::OutputLimit));
+    assert_eq!(decode(&[], 0), Ok(Vec::new()));
+
+    // The literals run past the end of the stream.
+    assert_eq!(decode(&[0x50, 1, 2], 100), Err(LZ4Error::TruncatedLiterals));
+    // The stream ends in the middle of the offset or of a length field.
+    assert_eq!(decode(&[0x10, 1, 1], 100), Err(LZ4Error::BadToken));
+    assert_eq!(decode(&[0xf0, 255], 100), Err(LZ4Error::BadToken));
+    // The stream ends after a match.
+    assert_eq!(decode(&[0x10, 7, 1, 0], 100), Err(LZ4Error::BadToken));
+    // The match refers to bytes before the beginning of the output.
+    let bad_offset = [0x10, 7, 2, 0, 0, 0x10, 7];
+    assert_eq!(decode(&bad_offset, 100), Err(LZ4Error::OffsetBeyondOutput));
+    let zero_offset = [0x10, 7, 0, 0, 0, 0x10, 7];
+    assert_eq!(decode(&zero_offset, 100), Err(LZ4Error::OffsetBeyondOutput));
+
+    // A short stream that expands to a large output.
+    let bomb = [0x1f, 7, 1, 0, 255, 255, 255, 255, 0, 0x10, 7];
+    assert_eq!(decode(&bomb, 1000), Err(LZ4Error::OutputLimit));
+    assert_eq!(decode(&bomb, 2000).unwrap().len(), 1 + 4 + 15 + 4 * 255 + 1);
+}
+
+#[test]
+fn test_lz4_encode_into_slice() {
+    use compressor::lz::lz4::compress_bound;
+
+    assert_eq!(compress_bound(0), 16);
+    assert_eq!(compress_bound(1000), 1019);
+
+    // Pseudo-random bytes are the worst case for the encoder.
+    let mut state: u32 = 17;
+    let random: Vec<u8> = (0..100_000)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+
+    let mut dst = vec![0u8; compress_bound(random.len())];
+    for input in [&random[..], &INPUT0_PLAIN, &[]] {
+        let ctx = Context::new(4, 1 << 20);
+        let mut expected: Vec<u8> = Vec::new();
+        let _ = LZ4Encoder::new(input, &mut expected, ctx).encode();
+
+        let mut unused: Vec<u8> = Vec::new();
+        let mut encoder = LZ4Encoder::new(input, &mut unused, ctx);
+        let written = encoder.encode_into(&mut dst).unwrap();
+        assert!(written <= compress_bound(input.len()));
+        assert_eq!(dst[..written], expected);
+    }
+
+    // The buffer is too small.
+    let ctx = Context::new(9, 1 << 20);
+    let mut unused: Vec<u8> = Vec::new();
+    let mut encoder = LZ4Encoder::new(&INPUT0_PLAIN, &mut unused, ctx);
+    let mut small = [0u8; 39];
+    assert_eq!(encoder.encode_into(&mut small), None);
+    let mut exact = [0u8; 40];
+    assert_eq!(encoder.encode_into(&mut exact), Some(40));
+    assert_eq!(exact, INPUT0_COMPRESSED);
+}
+
+#[test]
+fn test_copy_match() {
+    for offset in 1..=10 {
+        for len in [0, 1, 3, 7, 8, 9, 31, 100] {
+            let mut expected: Vec<u8> = (0..12).collect();
+            let mut output = expected.clone();
+            for _ in 0..len {
+                expected.push(expected[expected.len() - offset]);
+            }
+            copy_match(&mut output, offset, len);
+            assert_eq!(output, expected, "offset {} len {}", offset, len);
+        }
+    }
+
+    // Runs with short periods produce overlapping matches.
+    let mut input = Vec::new();
+    for period in 1..9u8 {
+        for i in 0..(period as usize * 100) {
+            input.push(i as u8 % period + period);
+        }
+    }
+    round_trip(&input);
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "reference-lz4")]
+
+use compressor::lz::{reference, LZ4Decoder, LZ4Encoder};
+use compressor::{Context, Decoder, Encoder};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Return a random input of 'len' bytes. Parts of the input are copied from
+/// earlier in the input, near and far, and the rest are random bytes from a
+/// small or a full alphabet, to exercise all of the fields of the format.
+fn make_input(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    let mut input = Vec::with_capacity(len);
+    while input.len() < len {
+        let size = rng.gen_range(1..300).min(len - input.len());
+        match rng.gen_range(0..4) {
+            0 if !input.is_empty() => {
+                let start = rng.gen_range(0..input.len());
+                for i in 0..size {
+                    input.push(input[start + i % (input.len() - start)]);
+                }
+            }
+            1 => input.extend((0..size).map(|_| rng.gen_range(b'a'..b'e'))),
+            2 => input.extend(std::iter::repeat_n(rng.gen::<u8>(), size)),
+            _ => input.extend((0..size).map(|_| rng.gen::<u8>())),
+        }
+    }
+    input
+}
+
+fn make_inputs() -> Vec<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut inputs = vec![Vec::new(), vec![7], b"abcdabcdabcdabcd".to_vec()];
+    for len in [5, 12, 13, 64, 1000, 70_000, 300_000] {
+        inputs.push(make_input(&mut rng, len));
+    }
+    inputs
+}
+
+#[test]
+fn test_reference_decodes_our_streams() {
+    let contexts = [
+        Context::new(1, 1 << 16),
+        Context::new(4, 1 << 20),
+        Context::new(9, 1 << 20),
+        Context::new(4, 1 << 16).with_fast_level(2),
+    ];
+    for input in make_inputs() {
+        for ctx in contexts {
+            let mut encoded = Vec::new();
+            let _ = LZ4Encoder::new(&input, &mut encoded, ctx).encode();
+            let decoded = reference::decompress(&encoded, input.len()).unwrap();
+            assert_eq!(decoded, input);
+        }
+    }
+}
+
+#[test]
+fn test_we_decode_reference_streams() {
+    for input in make_inputs() {
+        for level in [1, 4, 9, 12] {
+            let encoded = reference::compress(&input, level).unwrap();
+            let mut decoded = Vec::new();
+            let (read, _) =
+                LZ4Decoder::new(&encoded, &mut decoded).decode().unwrap();
+            assert_eq!(read, encoded.len());
+            assert_eq!(decoded, input);
+        }
+    }
+}
+
+#[test]
+fn test_truncated_streams() {
+    // When both decoders accept a truncated stream, they agree on its content.
+    let mut rng = StdRng::seed_from_u64(3);
+    let input = make_input(&mut rng, 5000);
+    let mut encoded = Vec::new();
+    let _ = LZ4Encoder::new(&input, &mut encoded, Context::new(4, 1 << 16))
+        .encode();
+    for len in 0..encoded.len() {
+        let stream = &encoded[..len];
+        let theirs = reference::decompress(stream, input.len());
+        let mut ours = Vec::new();
+        let res = LZ4Decoder::new(stream, &mut ours).decode();
+        if let (Some(theirs), Some(_)) = (&theirs, res) {
+            assert_eq!(theirs, &ours);
+        }
+    }
+}
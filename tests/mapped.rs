@@ -0,0 +1,141 @@
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::mapped::{MappedEncoder, MappedError};
+use compressor::metadata::Metadata;
+use compressor::{Context, Decoder, Encoder};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread;
+
+fn input() -> Vec<u8> {
+    let mut input = Vec::new();
+    for i in 0..8000u32 {
+        input.extend(format!("row {} of the mapped table; ", i % 900).bytes());
+    }
+    input
+}
+
+fn shared(input: &[u8]) -> Vec<AtomicU8> {
+    input.iter().map(|b| AtomicU8::new(*b)).collect()
+}
+
+#[test]
+fn test_mapped_unchanged() {
+    let input = input();
+    // Noise that is stored without compression.
+    let noise: Vec<u8> = (0..5000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    let metadata = Metadata::new().with_comment("table");
+    for ctx in [
+        Context::new(4, 1 << 14),
+        Context::new(2, 1 << 12).with_digest().with_frame_checksum(),
+        Context::new(4, 1 << 16).with_page_checksums(),
+        Context::new(3, 1 << 14).with_global_matching(),
+    ] {
+        let short = [&input[..0], &input[..100], &input[..1 << 14]];
+        for buffer in short.into_iter().chain([&input[..], &noise[..]]) {
+            let shared = shared(buffer);
+            let generation = || 6;
+            let mut stream = Vec::new();
+            let written = MappedEncoder::new(&shared, &mut stream, ctx)
+                .with_metadata(&metadata)
+                .unwrap()
+                .with_generation(&generation)
+                .with_verification()
+                .encode()
+                .unwrap();
+            assert_eq!(written, stream.len());
+
+            // The stream is the stream of the full encoder.
+            let mut expected = Vec::new();
+            let _ = FullEncoder::new(buffer, &mut expected, ctx)
+                .with_metadata(&metadata)
+                .unwrap()
+                .encode();
+            assert_eq!(stream, expected);
+            let mut decoded = Vec::new();
+            let _ = FullDecoder::new(&stream, &mut decoded).decode().unwrap();
+            assert_eq!(decoded, buffer);
+        }
+    }
+}
+
+#[test]
+fn test_mapped_generation_changes() {
+    let input = shared(&input());
+    let ctx = Context::new(4, 1 << 14);
+
+    // A write is in progress.
+    let odd = || 3;
+    let mut stream = Vec::new();
+    let res = MappedEncoder::new(&input, &mut stream, ctx)
+        .with_generation(&odd)
+        .encode();
+    assert_eq!(res, Err(MappedError::Changed));
+
+    // A writer changes the buffer after a few pages, or after the last page.
+    let pages = input.len().div_ceil(ctx.block_size);
+    for change in [1, 3, pages + 1] {
+        let calls = Cell::new(0);
+        let generation = || {
+            calls.set(calls.get() + 1);
+            if calls.get() > change {
+                2
+            } else {
+                0
+            }
+        };
+        let mut stream = Vec::new();
+        let res = MappedEncoder::new(&input, &mut stream, ctx)
+            .with_generation(&generation)
+            .encode();
+        assert_eq!(res, Err(MappedError::Changed));
+        // The encoder stops at the first page after the change.
+        assert_eq!(calls.get(), change + 1);
+    }
+}
+
+#[test]
+fn test_mapped_torn_stream() {
+    let input = shared(&input());
+    let ctx = Context::new(4, 1 << 14);
+
+    // A writer that doesn't update the generation changes the first page
+    // after it was compressed.
+    let calls = Cell::new(0);
+    let generation = || {
+        calls.set(calls.get() + 1);
+        if calls.get() == 3 {
+            input[10].fetch_add(1, Ordering::Relaxed);
+        }
+        0
+    };
+    let mut stream = Vec::new();
+    let res = MappedEncoder::new(&input, &mut stream, ctx)
+        .with_generation(&generation)
+        .with_verification()
+        .encode();
+    assert_eq!(res, Err(MappedError::Torn(0)));
+
+    // A writer on another thread changes the buffer during the compression.
+    // The stream decodes, and the verification reports the change.
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| {
+            let mut i = 0;
+            while !done.load(Ordering::Relaxed) {
+                input[i % input.len()].fetch_add(1, Ordering::Relaxed);
+                i += 4099;
+            }
+        });
+        for _ in 0..4 {
+            let mut stream = Vec::new();
+            let res = MappedEncoder::new(&input, &mut stream, ctx).encode();
+            let mut decoded = Vec::new();
+            let _ = FullDecoder::new(&stream, &mut decoded).decode().unwrap();
+            assert_eq!(res.unwrap(), stream.len());
+            assert_eq!(decoded.len(), input.len());
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+}
@@ -1,4 +1,5 @@
-use compressor::lz::matcher::{Matcher, OptimalMatcher};
+use compressor::lz::matcher::{FastMatcher, Matcher, OptimalMatcher};
+use std::ops::Range;
 
 #[test]
 fn test_matcher() {
@@ -106,3 +107,297 @@ fn test_optimal_sequence2() {
     assert_eq!(vals[7].0.len(), 5);
     assert_eq!(vals[7].1.len(), 0);
 }
+
+#[test]
+fn test_match_coverage() {
+    use compressor::lz::coverage::Segment;
+    use compressor::lz::coverage::{coverage, matched_bytes, to_json, to_ppm};
+
+    let mut input = b"0123456789abcdefghij".repeat(20);
+    input.extend((0..200).map(|i| (i * 7 + i / 3) as u8));
+    let segments = coverage(&input, 9);
+
+    // The segments cover the whole input in order.
+    let mut pos = 0;
+    for segment in &segments {
+        assert_eq!(segment.range().start, pos);
+        pos = segment.range().end;
+        if let Segment::Match(range, offset) = segment {
+            let src = range.start - offset;
+            assert_eq!(input[range.clone()], input[src..src + range.len()]);
+        }
+    }
+    assert_eq!(pos, input.len());
+    let matched = matched_bytes(&segments);
+    assert!((350..=400).contains(&matched));
+
+    let json = to_json(&segments);
+    assert!(json.starts_with(&format!("{{\"len\":600,\"matched\":{}", matched)));
+    assert!(json.contains("{\"kind\":\"literals\",\"start\":0,\"end\":20}"));
+
+    // The text in the first part of the strip is green and the rest is red.
+    let image = to_ppm(&segments, 4, 2);
+    let header = b"P6\n4 2\n255\n";
+    assert_eq!(image[..header.len()], header[..]);
+    let pixels = &image[header.len()..];
+    assert_eq!(pixels.len(), 4 * 2 * 3);
+    assert_eq!(pixels[3..6], [0, 255, 0]);
+    assert_eq!(pixels[9..12], [255, 0, 0]);
+
+    // The levels without a matcher report the matches of the last LZ level.
+    let segments = coverage(&input, 13);
+    assert_eq!(segments, coverage(&input, 12));
+    assert!(matched_bytes(&segments) >= matched);
+}
+
+#[test]
+fn test_optimal_matcher_covers_input() {
+    // Text from a small vocabulary, with many short and overlapping matches.
+    let words = ["the ", "then ", "there ", "other ", "her ", "he ", "in "];
+    let mut input = Vec::new();
+    let mut seed: u32 = 7;
+    while input.len() < 20000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(words[(seed >> 16) as usize % words.len()].as_bytes());
+    }
+
+    // The sequences must cover the input without gaps, and each match must
+    // copy the bytes that it replaces.
+    let mut pos = 0;
+    for (lit, mat) in OptimalMatcher::<65536, 65536, 19, 64>::new(&input) {
+        assert_eq!(lit.start, pos);
+        pos = lit.end;
+        if !mat.is_empty() {
+            assert!(mat.start < pos);
+            assert_eq!(input[mat.clone()], input[pos..pos + mat.len()]);
+        }
+        pos += mat.len();
+    }
+    assert_eq!(pos, input.len());
+}
+
+#[test]
+fn test_global_matches() {
+    use compressor::lz::global::{find_global_matches, GLOBAL_MIN_MATCH};
+
+    let mut input = Vec::new();
+    let mut state: u32 = 1;
+    while input.len() < 100_000 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        input.push((state >> 16) as u8);
+    }
+    input.extend_from_within(20_000..60_000);
+    let pages = [0..50_000, 50_000..100_000, 100_000..140_000];
+
+    // The copy is found in the last page, and its source is in the earlier
+    // pages.
+    let matches = find_global_matches(&input, &pages);
+    assert_eq!(matches.len(), 1);
+    let mat = matches[0];
+    assert!(mat.pos >= 100_000 && mat.src + mat.len <= 100_000);
+    assert!(mat.len >= GLOBAL_MIN_MATCH);
+    assert_eq!(mat.pos - mat.src, 80_000);
+    assert!(
+        input[mat.pos..mat.pos + mat.len] == input[mat.src..mat.src + mat.len]
+    );
+
+    // The source must end before the start of the page of the copy.
+    let whole = 0..input.len();
+    assert!(find_global_matches(&input, &[whole]).is_empty());
+}
+
+#[test]
+fn test_fast_matcher_covers_input() {
+    let mut input = b"abcdefgh".repeat(100);
+    input.extend((0..3000u32).map(|i| (i * i / 7) as u8));
+    input.extend_from_within(100..900);
+
+    let mut pos = 0;
+    let mut matched = 0;
+    for (lit, mat) in FastMatcher::<65536, 65536, 16, 4, 6>::new(&input) {
+        assert_eq!(lit.start, pos);
+        pos = lit.end;
+        if !mat.is_empty() {
+            assert!(mat.start < pos);
+            assert_eq!(input[mat.clone()], input[pos..pos + mat.len()]);
+        }
+        pos += mat.len();
+        matched += mat.len();
+    }
+    assert_eq!(pos, input.len());
+    assert!(matched > 1500, "{}", matched);
+}
+
+#[test]
+fn test_parse_params() {
+    use compressor::block::{BlockDecoder, BlockEncoder};
+    use compressor::lz::matcher::{select_matcher_with, ParseParams};
+    use compressor::{Context, Decoder, Encoder};
+
+    let words = ["the ", "then ", "there ", "other ", "her ", "he ", "in "];
+    let mut input = Vec::new();
+    let mut seed: u32 = 3;
+    while input.len() < 20000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(words[(seed >> 16) as usize % words.len()].as_bytes());
+    }
+
+    let params = [
+        ParseParams::new(1),
+        ParseParams::new(8),
+        ParseParams::new(0).with_literal_penalty(0),
+        ParseParams::new(4)
+            .with_closer_bonus(2)
+            .with_literal_penalty(2),
+        ParseParams::new(4).with_pricing(),
+    ];
+    for params in params {
+        // The sequences cover the input, and the matches copy the bytes that
+        // they replace.
+        let mut pos = 0;
+        let matcher =
+            select_matcher_with::<65536, 65536>(4, Some(params), &input);
+        for (lit, mat) in matcher {
+            assert_eq!(lit.start, pos);
+            pos = lit.end;
+            if !mat.is_empty() {
+                assert!(mat.start < pos);
+                assert_eq!(input[mat.clone()], input[pos..pos + mat.len()]);
+            }
+            pos += mat.len();
+        }
+        assert_eq!(pos, input.len());
+
+        let ctx = Context::new(6, 1 << 20).with_parse_params(params);
+        let mut compressed = Vec::new();
+        let _ = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        let mut decoded = Vec::new();
+        let _ = BlockDecoder::new(&compressed, &mut decoded)
+            .decode()
+            .unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    // The default parameters of the level are the ones of 'PARSE_SEARCH'.
+    let level: Vec<_> =
+        select_matcher_with::<65536, 65536>(10, None, &input).collect();
+    let same: Vec<_> = select_matcher_with::<65536, 65536>(
+        10,
+        Some(ParseParams::new(4)),
+        &input,
+    )
+    .collect();
+    assert_eq!(level, same);
+    let deeper: Vec<_> = select_matcher_with::<65536, 65536>(
+        10,
+        Some(ParseParams::new(6)),
+        &input,
+    )
+    .collect();
+    assert_ne!(level, deeper);
+}
+
+#[test]
+fn test_matcher_banks_wrap_around() {
+    // Many positions share each word, so the banks of the buckets wrap
+    // around many times.
+    let mut input = Vec::new();
+    let mut seed: u32 = 11;
+    while input.len() < 20000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.extend(b"word");
+        input.push(b'a' + (seed >> 16) as u8 % 4);
+    }
+
+    fn check(input: &[u8], matches: &[(Range<usize>, Range<usize>)]) -> usize {
+        let mut pos = 0;
+        let mut matched = 0;
+        for (lit, mat) in matches {
+            assert_eq!(lit.start, pos);
+            pos = lit.end;
+            if !mat.is_empty() {
+                assert!(mat.start < pos);
+                assert_eq!(input[mat.clone()], input[pos..pos + mat.len()]);
+            }
+            pos += mat.len();
+            matched += mat.len();
+        }
+        assert_eq!(pos, input.len());
+        matched
+    }
+
+    let two: Vec<_> = Matcher::<65536, 65536, 12, 2, 1>::new(&input).collect();
+    let three: Vec<_> =
+        Matcher::<65536, 65536, 12, 3, 2>::new(&input).collect();
+    let many: Vec<_> =
+        Matcher::<65536, 65536, 12, 64, 2>::new(&input).collect();
+    let optimal: Vec<_> =
+        OptimalMatcher::<65536, 65536, 12, 24>::new(&input).collect();
+    for matches in [two, three, many, optimal] {
+        assert!(check(&input, &matches) > input.len() / 2);
+    }
+}
+
+#[test]
+fn test_tagged_dictionary() {
+    // The tags only skip entries that can't match, so the matches are the
+    // same with and without them.
+    let mut input = Vec::new();
+    let mut seed: u32 = 5;
+    while input.len() < 30000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let word = (seed >> 16) as usize % 64;
+        input.extend(format!("item{} ", word * word).bytes());
+    }
+
+    let plain: Vec<_> =
+        Matcher::<65536, 65536, 10, 16, 2, false>::new(&input).collect();
+    let tagged: Vec<_> =
+        Matcher::<65536, 65536, 10, 16, 2, true>::new(&input).collect();
+    assert_eq!(plain, tagged);
+
+    let plain: Vec<_> =
+        OptimalMatcher::<65536, 65536, 10, 16, false>::new(&input).collect();
+    let tagged: Vec<_> =
+        OptimalMatcher::<65536, 65536, 10, 16, true>::new(&input).collect();
+    assert_eq!(plain, tagged);
+}
+
+#[test]
+fn test_long_match_table() {
+    // The buckets of the small table are overwritten by the noise, so only the
+    // table of long matches remembers the start of the input.
+    let mut input = Vec::new();
+    let mut seed: u32 = 3;
+    while input.len() < 10000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        input.push((seed >> 16) as u8);
+    }
+    let copy = input[..300].to_vec();
+    input.extend(&copy);
+    input.extend(b"end of the input");
+
+    fn longest(matches: &[(Range<usize>, Range<usize>)]) -> usize {
+        matches.iter().map(|(_, mat)| mat.len()).max().unwrap()
+    }
+
+    let plain: Vec<_> = Matcher::<65536, 65536, 8, 1, 2>::new(&input).collect();
+    let long: Vec<_> =
+        Matcher::<65536, 65536, 8, 1, 2, false, 16>::new(&input).collect();
+    let optimal: Vec<_> =
+        OptimalMatcher::<65536, 65536, 8, 1, false, 16>::new(&input).collect();
+    assert!(longest(&plain) < 200);
+    assert!(longest(&long) >= 290);
+    assert!(longest(&optimal) >= 290);
+
+    for matches in [long, optimal] {
+        let mut pos = 0;
+        for (lit, mat) in matches {
+            assert_eq!(lit.start, pos);
+            pos = lit.end;
+            assert_eq!(input[mat.clone()], input[pos..pos + mat.len()]);
+            pos += mat.len();
+        }
+        assert_eq!(pos, input.len());
+    }
+}
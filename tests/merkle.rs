@@ -0,0 +1,149 @@
+use compressor::frame::verify_frame;
+use compressor::full::{encode_vectored, FullDecoder, FullEncoder};
+use compressor::merkle::HashTree;
+use compressor::utils::leb128;
+use compressor::utils::signatures::{write32, HASH_TREE_SIG};
+use compressor::{Context, Decoder, Encoder};
+use std::io::IoSlice;
+
+fn input() -> Vec<u8> {
+    let mut input = Vec::new();
+    for i in 0..20000u32 {
+        input.extend(format!("page {} of the hash tree; ", i % 700).bytes());
+    }
+    input
+}
+
+fn decode(stream: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (read, _) = FullDecoder::new(stream, &mut decoded).decode()?;
+    assert_eq!(read, stream.len());
+    Some(decoded)
+}
+
+#[test]
+fn test_hash_tree_trailer() {
+    let input = input();
+    for ctx in [
+        Context::new(4, 1 << 14).with_hash_tree(),
+        Context::new(2, 1 << 12)
+            .with_hash_tree()
+            .with_frame_checksum(),
+        Context::new(5, 1 << 16).with_hash_tree().with_digest(),
+    ] {
+        let mut stream = Vec::new();
+        let _ = FullEncoder::new(&input, &mut stream, ctx).encode();
+        assert_eq!(decode(&stream).unwrap(), input);
+        if ctx.frame_checksum {
+            assert_eq!(verify_frame(&stream), Some(true));
+        }
+
+        // The tree is found from the end of the stream, and matches the tree
+        // of the pages.
+        let tail = &stream[stream.len() - 40000.min(stream.len())..];
+        let tree = HashTree::find_trailer(tail).unwrap();
+        let (pages, _) = HashTree::from_stream(&stream).unwrap();
+        assert_eq!(tree, pages);
+        assert_eq!(tree.root(), pages.root());
+        assert_eq!(tree.len(), input.len().div_ceil(ctx.block_size));
+    }
+
+    // Streams without pages have no tree.
+    let mut stream = Vec::new();
+    let ctx = Context::new(4, 1 << 14).with_hash_tree();
+    let _ = FullEncoder::new(b"short", &mut stream, ctx).encode();
+    assert!(HashTree::find_trailer(&stream).is_none());
+    assert_eq!(decode(&stream).unwrap(), b"short");
+
+    // The vectored encoder saves the same tree.
+    let mut vectored = Vec::new();
+    let ctx = Context::new(4, 1 << 14).with_hash_tree();
+    let (head, tail) = input.split_at(12345);
+    let bufs = [IoSlice::new(head), IoSlice::new(tail)];
+    let _ = encode_vectored(&bufs, &mut vectored, ctx);
+    let mut stream = Vec::new();
+    let _ = FullEncoder::new(&input, &mut stream, ctx).encode();
+    assert_eq!(vectored, stream);
+}
+
+#[test]
+fn test_verify_page_ranges() {
+    let input = input();
+    let ctx = Context::new(4, 1 << 13).with_hash_tree();
+    let mut stream = Vec::new();
+    let _ = FullEncoder::new(&input, &mut stream, ctx).encode();
+    let tree = HashTree::find_trailer(&stream).unwrap();
+    let root = tree.root();
+    assert!(tree.len() > 10);
+
+    // A client downloads a few pages and verifies them.
+    let range = tree.page_range(3..7).unwrap();
+    let bytes = &stream[range.clone()];
+    assert_eq!(tree.verify_range(range.start, bytes), Some(3..7));
+    assert_eq!(HashTree::find_trailer(&stream).unwrap().root(), root);
+
+    // Partial pages at the edges of a range are not verified.
+    let bytes = &stream[range.start + 1..range.end + 5];
+    assert_eq!(tree.verify_range(range.start + 1, bytes), Some(4..7));
+    let bytes = &stream[range.start + 1..range.start + 2];
+    assert_eq!(tree.verify_range(range.start + 1, bytes), Some(0..0));
+    assert!(tree.page_range(5..tree.len() + 1).is_none());
+
+    // Damaged pages are detected.
+    let mut damaged = stream[range.clone()].to_vec();
+    damaged[range.len() / 2] ^= 1;
+    assert!(tree.verify_range(range.start, &damaged).is_none());
+
+    // Trees with different leaves have different roots.
+    let mut other = input.clone();
+    other[20000] ^= 1;
+    let mut stream2 = Vec::new();
+    let _ = FullEncoder::new(&other, &mut stream2, ctx).encode();
+    assert_ne!(HashTree::find_trailer(&stream2).unwrap().root(), root);
+
+    // Decoders reject streams with a tree that doesn't match the pages.
+    let tree_start = HashTree::from_stream(&stream).unwrap().1;
+    let mut modified = stream.clone();
+    modified[tree_start + 10] ^= 1;
+    assert!(decode(&modified).is_none());
+}
+
+/// Build a trailer with the offset 'first', the record sizes 'lens' and zero
+/// leaves.
+fn forge_trailer(first: u64, lens: &[u64]) -> Vec<u8> {
+    let mut trailer = Vec::new();
+    trailer.extend(HASH_TREE_SIG);
+    leb128::encode(first, &mut trailer);
+    leb128::encode(lens.len() as u64, &mut trailer);
+    for len in lens {
+        leb128::encode(*len, &mut trailer);
+    }
+    trailer.resize(trailer.len() + lens.len() * 32, 0);
+    let len = trailer.len() + 4 + HASH_TREE_SIG.len();
+    write32(len as u32, &mut trailer);
+    trailer.extend(HASH_TREE_SIG);
+    trailer
+}
+
+#[test]
+fn test_forged_trailer() {
+    let tree = |first, lens: &[u64]| {
+        HashTree::read_trailer(&forge_trailer(first, lens))
+            .unwrap()
+            .1
+    };
+
+    // The sizes and the offset of the first page are covered by the root.
+    let root = tree(10, &[100, 200]).root();
+    assert_eq!(tree(10, &[100, 200]).root(), root);
+    assert_ne!(tree(10, &[200, 100]).root(), root);
+    assert_ne!(tree(10, &[100, 201]).root(), root);
+    assert_ne!(tree(11, &[100, 200]).root(), root);
+
+    // Sizes that overflow are rejected instead of wrapping around.
+    let forged = tree(10, &[u64::MAX, 1]);
+    assert!(forged.page_range(0..2).is_none());
+    assert!(forged.page_range(1..2).is_none());
+    assert!(forged.verify_range(0, &[0; 64]).is_none());
+    assert!(forged.verify_range(usize::MAX, &[0; 64]).is_none());
+}
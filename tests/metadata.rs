@@ -0,0 +1,141 @@
+use compressor::full::{decode_prefix, FullDecoder, FullEncoder};
+use compressor::inspect::inspect_stream;
+use compressor::metadata::{read_metadata, Metadata, MAX_METADATA_LEN};
+use compressor::reader::DecompressBufReader;
+use compressor::scatter::split_pages;
+use compressor::utils::leb128;
+use compressor::utils::signatures::{FULL_SIG, METADATA_SIG};
+use compressor::{Context, Decoder, Encoder};
+use std::io::Read;
+
+fn input() -> Vec<u8> {
+    "the metadata is read without decompressing the stream. "
+        .repeat(300)
+        .into_bytes()
+}
+
+fn metadata() -> Metadata {
+    Metadata::new()
+        .with_comment("nightly backup")
+        .with("tool", "cli 1.x")
+        .with("path", "/home/user/données.txt")
+}
+
+fn compress(input: &[u8], ctx: Context, metadata: &Metadata) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut encoder = FullEncoder::new(input, &mut compressed, ctx)
+        .with_metadata(metadata)
+        .unwrap();
+    let written = encoder.encode();
+    assert_eq!(written, compressed.len());
+    compressed
+}
+
+fn decompress(compressed: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut decoder = FullDecoder::new(compressed, &mut output);
+    let (read, _) = decoder.decode()?;
+    assert_eq!(read, compressed.len());
+    Some(output)
+}
+
+#[test]
+fn test_metadata_map() {
+    let metadata = metadata().with("tool", "cli 2.x");
+    assert_eq!(metadata.comment(), Some("nightly backup"));
+    assert_eq!(metadata.get("tool"), Some("cli 2.x"));
+    assert_eq!(metadata.get("host"), None);
+    assert_eq!(metadata.entries().len(), 3);
+    assert_eq!(metadata.entries()[1].0, "tool");
+
+    let mut record = Vec::new();
+    let len = metadata.encode(&mut record).unwrap();
+    assert_eq!(len, record.len());
+    assert_eq!(Metadata::decode(&record), Some((len, metadata)));
+
+    // Empty maps are not saved.
+    assert_eq!(Metadata::new().encode(&mut record), Some(0));
+    assert_eq!(record.len(), len);
+}
+
+#[test]
+fn test_metadata_round_trip() {
+    let input = input();
+    let metadata = metadata();
+    for level in [0, 2, 4] {
+        let ctx = Context::new(level, 1 << 12);
+        let compressed = compress(&input, ctx, &metadata);
+        assert_eq!(read_metadata(&compressed), Some(metadata.clone()));
+        assert_eq!(decompress(&compressed).unwrap(), input);
+        assert_eq!(decode_prefix(&compressed, 100).unwrap(), input[..100]);
+    }
+
+    // Short inputs are stored, and keep the metadata.
+    let compressed = compress(b"abc", Context::new(4, 1 << 12), &metadata);
+    assert_eq!(read_metadata(&compressed), Some(metadata.clone()));
+    assert_eq!(decompress(&compressed).unwrap(), b"abc");
+
+    // Streams without metadata have an empty map.
+    let plain = compress(&input, Context::new(4, 1 << 12), &Metadata::new());
+    assert_eq!(read_metadata(&plain), Some(Metadata::new()));
+    assert_eq!(read_metadata(&input), None);
+}
+
+#[test]
+fn test_metadata_readers() {
+    let input = input();
+    let metadata = metadata();
+    let ctx = Context::new(4, 1 << 12);
+    let compressed = compress(&input, ctx, &metadata);
+    let plain = compress(&input, ctx, &Metadata::new());
+
+    let blocks = inspect_stream(&compressed).unwrap();
+    assert_eq!(blocks.len(), inspect_stream(&plain).unwrap().len());
+    let pages = split_pages(&compressed).unwrap();
+    assert_eq!(pages, split_pages(&plain).unwrap());
+
+    let mut output = Vec::new();
+    DecompressBufReader::new(&compressed[..])
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(output, input);
+}
+
+#[test]
+fn test_invalid_metadata() {
+    let input = input();
+    let ctx = Context::new(4, 1 << 12);
+    let compressed = compress(&input, ctx, &metadata());
+
+    // A cut off record.
+    let start = FULL_SIG.len();
+    assert!(read_metadata(&compressed[..start + 10]).is_none());
+
+    // Keys must be UTF-8.
+    let bad = Metadata::new().with("k\u{e9}", "v");
+    let mut record = Vec::new();
+    bad.encode(&mut record).unwrap();
+    let at = record.len() - 4;
+    assert_eq!(record[at], 0xc3);
+    record[at] = 0xff;
+    assert!(Metadata::decode(&record).is_none());
+
+    // Records that claim to be larger than the limit.
+    let mut large = FULL_SIG.to_vec();
+    large.extend(METADATA_SIG);
+    leb128::encode(MAX_METADATA_LEN as u64 + 1, &mut large);
+    large.extend(vec![0; MAX_METADATA_LEN + 1]);
+    assert!(read_metadata(&large).is_none());
+    assert!(decompress(&large).is_none());
+
+    // Maps that are larger than the limit are not saved.
+    let value = "x".repeat(MAX_METADATA_LEN);
+    let large = Metadata::new().with("big", &value);
+    let mut record = Vec::new();
+    assert!(large.encode(&mut record).is_none());
+    assert!(record.is_empty());
+    let mut output = Vec::new();
+    assert!(FullEncoder::new(&input, &mut output, ctx)
+        .with_metadata(&large)
+        .is_none());
+}
@@ -1,5 +1,11 @@
-use compressor::nop::{NopDecoder, NopEncoder};
+use compressor::full::{stored_view, FullDecoder, FullEncoder};
+use compressor::nop::{decode_view, NopDecoder, NopEncoder};
+use compressor::pager::page_view;
+use compressor::scatter::split_pages;
+use compressor::utils::leb128;
+use compressor::utils::signatures::START_PAGE_SIG;
 use compressor::{Context, Decoder, Encoder};
+use std::ops::Range;
 
 fn round_trip(input: &[u8]) {
     let mut compressed: Vec<u8> = Vec::new();
@@ -27,3 +33,81 @@ fn test_nop_round_trip() {
     round_trip(&[1, 1]);
     round_trip(&[1, 2, 3, 1, 0, 0, 0, 0, 2, 2, 2, 2, 0, 0, 0]);
 }
+
+/// Return true if 'view' points into 'buffer'.
+fn borrows(view: &[u8], buffer: &[u8]) -> bool {
+    let Range { start, end } = buffer.as_ptr_range();
+    start <= view.as_ptr() && view.as_ptr_range().end <= end
+}
+
+/// Return 'len' bytes that don't compress.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 7u32;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_nop_view() {
+    let input = [1, 2, 3, 4, 5];
+    let mut encoded = Vec::new();
+    let _ = NopEncoder::new(&input, &mut encoded, Context::new(9, 0)).encode();
+    encoded.push(42);
+    let (read, view) = decode_view(&encoded).unwrap();
+    assert_eq!(read, encoded.len() - 1);
+    assert_eq!(view, input);
+    assert!(borrows(view, &encoded));
+    assert!(decode_view(&encoded[..read - 1]).is_none());
+    assert!(decode_view(&input).is_none());
+}
+
+#[test]
+fn test_page_and_stored_views() {
+    // The text pages are compressed, and the noise pages are stored.
+    let text = "compressed pages are decoded. ".repeat(300).into_bytes();
+    let mut input = text[..8192].to_vec();
+    input.extend(noise(8192));
+    for checksums in [false, true] {
+        let mut ctx = Context::new(4, 4096);
+        if checksums {
+            ctx = ctx.with_page_checksums();
+        }
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        let records = split_pages(&compressed).unwrap();
+        assert!(page_view(records[0]).is_none());
+        assert!(page_view(records[1]).is_none());
+        let mut pages: Vec<u8> = Vec::new();
+        for record in &records[2..] {
+            let view = page_view(record).unwrap();
+            assert!(borrows(view, &compressed));
+            pages.extend(view);
+        }
+        assert_eq!(pages, input[8192..]);
+    }
+
+    // A record with a length that overflows is rejected.
+    let mut record = START_PAGE_SIG.to_vec();
+    leb128::encode(u64::MAX, &mut record);
+    assert!(page_view(&record).is_none());
+
+    // Inputs that don't compress are saved in a stored frame.
+    let input = noise(1000);
+    let mut compressed = Vec::new();
+    let ctx = Context::new(4, 4096);
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    let (read, view) = stored_view(&compressed).unwrap();
+    assert_eq!(read, compressed.len());
+    assert_eq!(view, input);
+    assert!(borrows(view, &compressed));
+    let mut decoded = Vec::new();
+    let _ = FullDecoder::new(&compressed, &mut decoded)
+        .decode()
+        .unwrap();
+    assert_eq!(decoded, input);
+    assert!(stored_view(&compressed[..read - 1]).is_none());
+}
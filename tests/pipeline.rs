@@ -0,0 +1,200 @@
+use compressor::block::{BlockDecoder, BlockEncoder};
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::inspect::{inspect_block, BlockKind};
+use compressor::pipeline::{Codec, Pipeline, Transform};
+use compressor::utils::signatures::PIPELINE_BLOCK_SIG;
+use compressor::{decode_exact, Context, Decoder, Encoder};
+
+/// Return records of 4 bytes with slowly changing fields and a few runs.
+fn records() -> Vec<u8> {
+    let mut input = Vec::new();
+    for i in 0..3000u32 {
+        input.extend((i / 3).to_le_bytes());
+        if i % 500 == 0 {
+            input.extend([7; 300]);
+        }
+    }
+    input.extend([1, 2, 3]);
+    input
+}
+
+fn round_trip(input: &[u8], pipeline: Pipeline) -> usize {
+    let mut compressed = Vec::new();
+    let written =
+        pipeline.encode(input, Context::new(5, 1 << 16), &mut compressed);
+    assert_eq!(written, compressed.len());
+    assert_eq!(
+        Pipeline::decode(&compressed).unwrap(),
+        (written, input.to_vec())
+    );
+    written
+}
+
+#[test]
+fn test_pipeline_transforms() {
+    let input = records();
+    let transforms = [
+        Transform::Rle,
+        Transform::Delta(1),
+        Transform::Delta(4),
+        Transform::Transpose(4),
+        Transform::Transpose(31),
+        Transform::Bwt,
+    ];
+    for codec in [Codec::Block, Codec::Stored] {
+        for transform in transforms {
+            let pipeline = Pipeline::new(codec).with(transform);
+            round_trip(&input, pipeline);
+            round_trip(&[], pipeline);
+            round_trip(&[5], pipeline);
+        }
+        let pipeline = Pipeline::new(codec)
+            .with(Transform::Rle)
+            .with(Transform::Transpose(4))
+            .with(Transform::Delta(1));
+        round_trip(&input, pipeline);
+        let pipeline = Pipeline::new(codec)
+            .with(Transform::Bwt)
+            .with(Transform::Rle);
+        round_trip(&input, pipeline);
+    }
+
+    // Grouping the fields of the records helps the block encoder.
+    let plain = round_trip(&input, Pipeline::new(Codec::Block));
+    let columns = Pipeline::new(Codec::Block)
+        .with(Transform::Transpose(4))
+        .with(Transform::Delta(1));
+    assert!(round_trip(&input, columns) < plain);
+}
+
+#[test]
+fn test_pipeline_context() {
+    let input = records();
+    let pipeline = Pipeline::new(Codec::Block)
+        .with(Transform::Transpose(4))
+        .with(Transform::Delta(1));
+    let ctx = Context::new(5, 1 << 16).with_pipeline(pipeline);
+
+    // The block encoder follows the pipeline of the context.
+    let mut compressed = Vec::new();
+    let written = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+    assert_eq!(written, compressed.len());
+    let (_, read) = Pipeline::read_header(&compressed).unwrap();
+    assert_eq!(read, pipeline);
+    let mut decoded = Vec::new();
+    let read = decode_exact::<BlockDecoder>(&compressed, &mut decoded);
+    assert_eq!(read, Some(input.len()));
+    assert_eq!(decoded, input);
+
+    let info = inspect_block(&compressed).unwrap();
+    assert_eq!(info.kind, BlockKind::Pipeline);
+    assert_eq!(info.len, input.len());
+    assert!(info.inner.is_some());
+
+    // The pages of the full encoder are encoded with the pipeline too.
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    let mut decoded = Vec::new();
+    let _ = FullDecoder::new(&compressed, &mut decoded)
+        .decode()
+        .unwrap();
+    assert_eq!(decoded, input);
+}
+
+#[test]
+fn test_pipeline_errors() {
+    let input = records();
+    let pipeline = Pipeline::new(Codec::Stored).with(Transform::Rle);
+    let mut compressed = Vec::new();
+    let _ = pipeline.encode(&input, Context::new(5, 1 << 16), &mut compressed);
+
+    // A truncated block.
+    assert!(Pipeline::decode(&compressed[..compressed.len() - 1]).is_none());
+
+    // An unknown codec, too many stages and an invalid parameter.
+    let mut damaged = compressed.clone();
+    damaged[2] = 1 << 4 | 7;
+    assert!(Pipeline::decode(&damaged).is_none());
+    damaged[2] = 5 << 4 | 1;
+    assert!(Pipeline::decode(&damaged).is_none());
+    damaged[2] = 1 << 4 | 1;
+    damaged[3] = 2 << 5 | 1;
+    assert!(Pipeline::decode(&damaged).is_none());
+
+    // An invalid primary index of the transform.
+    let pipeline = Pipeline::new(Codec::Stored).with(Transform::Bwt);
+    let mut compressed = Vec::new();
+    let _ = pipeline.encode(&input, Context::new(5, 1 << 16), &mut compressed);
+    assert!(Pipeline::decode(&compressed).is_some());
+    compressed[4] = 0;
+    assert!(Pipeline::decode(&compressed).is_none());
+
+    // Pipeline blocks don't nest, so deep nesting can't overflow the stack.
+    let mut nested = Vec::new();
+    for _ in 0..300_000 {
+        nested.extend(PIPELINE_BLOCK_SIG);
+        nested.push(0);
+    }
+    assert!(Pipeline::decode(&nested).is_none());
+    let mut decoded = Vec::new();
+    assert!(BlockDecoder::new(&nested, &mut decoded).decode().is_none());
+
+    // Blocks without a pipeline.
+    let mut block = Vec::new();
+    let _ = BlockEncoder::new(&input, &mut block, Context::new(5, 1 << 16))
+        .encode();
+    assert!(Pipeline::read_header(&block).is_none());
+}
+
+#[test]
+fn test_pipeline_parse() {
+    let pipeline = Pipeline::parse("bwt,rle").unwrap();
+    assert_eq!(pipeline.stages(), [Transform::Bwt, Transform::Rle]);
+    assert_eq!(pipeline.to_string(), "bwt,rle");
+
+    let pipeline = Pipeline::parse("rle,transpose:4,delta:1").unwrap();
+    let expected =
+        [Transform::Rle, Transform::Transpose(4), Transform::Delta(1)];
+    assert_eq!(pipeline.stages(), expected);
+    assert_eq!(pipeline.codec(), Codec::Block);
+    assert_eq!(pipeline.to_string(), "rle,transpose:4,delta:1");
+    assert_eq!(Pipeline::new(Codec::Stored).to_string(), "none (stored)");
+
+    for spec in [
+        "",
+        "rle:1",
+        "bwt:1",
+        "delta",
+        "delta:0",
+        "delta:32",
+        "transpose:1",
+    ] {
+        assert!(Pipeline::parse(spec).is_none(), "{}", spec);
+    }
+    assert!(Pipeline::parse("delta:1,delta:2,rle,rle,rle").is_none());
+    assert!(Pipeline::parse("lzma").is_none());
+}
+
+#[test]
+fn test_bwt() {
+    use compressor::bwt::{forward, inverse, suffix_array};
+
+    assert_eq!(suffix_array(b"banana"), [5, 3, 1, 0, 4, 2]);
+    assert_eq!(forward(b"banana"), (b"annbaa".to_vec(), 4));
+    assert_eq!(inverse(b"annbaa", 4).unwrap(), b"banana");
+
+    let mut text = Vec::new();
+    for i in 0..2000 {
+        text.extend(format!("line {} of {}; ", i % 37, i % 11).bytes());
+    }
+    for input in [&b""[..], b"a", b"aaaaaaaa", b"abababab", &text] {
+        let (output, primary) = forward(input);
+        assert_eq!(output.len(), input.len());
+        assert_eq!(inverse(&output, primary).unwrap(), input);
+    }
+
+    // Invalid primary indices.
+    assert!(inverse(b"annbaa", 0).is_none());
+    assert!(inverse(b"annbaa", 7).is_none());
+    assert!(inverse(b"", 1).is_none());
+}
@@ -0,0 +1,85 @@
+use compressor::pipeline::Pipeline;
+use compressor::profile::TuningProfile;
+use compressor::registry::FileType;
+use compressor::utils::hash::xxh32;
+use compressor::utils::leb128;
+use compressor::utils::signatures::{write32, PROFILE_SIG};
+
+fn text(seed: u32) -> Vec<u8> {
+    let mut text = Vec::new();
+    for i in 0..2000 {
+        text.extend(format!("line {} of file {}\n", i, seed).bytes());
+    }
+    text
+}
+
+#[test]
+fn test_profile_hints() {
+    let (a, b) = (text(1), text(2));
+    let noise: Vec<u8> =
+        (0..40000u32).map(|i| ((i * 7919) >> 3) as u8).collect();
+    let delta = Pipeline::parse("delta:2");
+
+    let mut profile = TuningProfile::new();
+    assert!(profile.lookup(None, &[&a]).is_none());
+    profile.record(None, 9, None, &[&a]);
+    profile.record(Some(FileType::Wav), 4, delta, &[&noise]);
+    assert_eq!(profile.hints().len(), 2);
+
+    // Similar files of the same type reuse the hint.
+    let hint = profile.lookup(None, &[&b]).unwrap();
+    assert_eq!((hint.level, hint.pipeline), (9, None));
+    let hint = profile.lookup(Some(FileType::Wav), &[&noise]).unwrap();
+    assert_eq!((hint.level, hint.pipeline), (4, delta));
+
+    // Files of other types, or with other distributions, don't.
+    assert!(profile.lookup(Some(FileType::Csv), &[&a]).is_none());
+    assert!(profile.lookup(None, &[&noise]).is_none());
+    assert!(profile.lookup(Some(FileType::Wav), &[&a]).is_none());
+
+    // A new choice replaces the hint of the type.
+    profile.record(None, 1, None, &[&noise]);
+    assert_eq!(profile.hints().len(), 2);
+    assert!(profile.lookup(None, &[&a]).is_none());
+    assert_eq!(profile.lookup(None, &[&noise]).unwrap().level, 1);
+}
+
+#[test]
+fn test_profile_encoding() {
+    let mut profile = TuningProfile::new();
+    assert_eq!(
+        TuningProfile::decode(&profile.encode()),
+        Some(profile.clone())
+    );
+    profile.record(None, 9, None, &[&text(1)]);
+    let pipeline = Pipeline::parse("transpose:4,delta:1");
+    profile.record(Some(FileType::Bmp), 5, pipeline, &[&text(3), b"abc"]);
+    let encoded = profile.encode();
+    assert_eq!(TuningProfile::decode(&encoded), Some(profile));
+
+    // Damaged and truncated profiles are rejected.
+    for i in 0..encoded.len() {
+        let mut damaged = encoded.clone();
+        damaged[i] ^= 0x10;
+        assert!(TuningProfile::decode(&damaged).is_none());
+        assert!(TuningProfile::decode(&encoded[..i]).is_none());
+    }
+
+    // Profiles with a single hint of 'level' and the histogram 'hist'.
+    let make = |level: u8, hist: &[u64]| {
+        let mut encoded = PROFILE_SIG.to_vec();
+        encoded.extend([1, 0, level, 0]);
+        for count in hist {
+            leb128::encode(*count, &mut encoded);
+        }
+        let checksum = xxh32(&encoded, 0);
+        write32(checksum, &mut encoded);
+        encoded
+    };
+    let mut hist = vec![16; 256];
+    assert!(TuningProfile::decode(&make(12, &hist)).is_some());
+    assert!(TuningProfile::decode(&make(13, &hist)).is_none());
+    assert!(TuningProfile::decode(&make(255, &hist)).is_none());
+    hist[7] = u32::MAX as u64;
+    assert!(TuningProfile::decode(&make(4, &hist)).is_none());
+}
@@ -0,0 +1,193 @@
+use compressor::full::{encode_or_nop, FullDecoder, FullEncoder};
+use compressor::pager::PagerEncoder;
+use compressor::reader::DecompressBufReader;
+use compressor::utils::signatures::{CHECKED_PAGE_SIG, DUP_PAGE_SIG, FULL_SIG};
+use compressor::{Context, Decoder, Encoder};
+use std::io::{BufRead, Read};
+
+fn compress(input: &[u8], level: u8, block_size: usize) -> Vec<u8> {
+    let mut compressed: Vec<u8> = Vec::new();
+    let ctx = Context::new(level, block_size);
+    let _ = FullEncoder::new(input, &mut compressed, ctx).encode();
+    compressed
+}
+
+fn make_log(lines: usize) -> String {
+    let mut log = String::new();
+    for i in 0..lines {
+        let level = ["INFO", "WARN", "DEBUG"][i % 3];
+        log += &format!(
+            "2024-01-01 12:{:02}:{:02} {} request {} done\n",
+            (i / 60) % 60,
+            i % 60,
+            level,
+            i * 7
+        );
+    }
+    log
+}
+
+#[test]
+fn test_reader_lines() {
+    let log = make_log(2000);
+
+    // Use small pages so that lines cross page boundaries.
+    for block_size in [1 << 10, 1 << 12, 1 << 20] {
+        let compressed = compress(log.as_bytes(), 9, block_size);
+        let reader = DecompressBufReader::new(&compressed[..]);
+        let lines: Vec<String> = reader.lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines.len(), 2000);
+        assert!(lines.iter().eq(log.lines()));
+    }
+}
+
+#[test]
+fn test_reader_formats() {
+    fn read_all(compressed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        DecompressBufReader::new(compressed).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    let log = make_log(300);
+    let mut state: u32 = 17;
+    let random: Vec<u8> = (0..20_000)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let zeros = vec![0u8; 10000];
+
+    // Paged, stored, constant and arithmetic coded streams.
+    for (input, level) in [
+        (log.as_bytes(), 4),
+        (&random[..], 9),
+        (&zeros[..], 9),
+        (&log.as_bytes()[..2000], 13),
+        (&[][..], 9),
+    ] {
+        let compressed = compress(input, level, 1 << 12);
+        assert_eq!(read_all(&compressed).unwrap(), input);
+    }
+
+    // Invalid and truncated streams are reported as errors.
+    assert!(read_all(b"not a compressed stream").is_err());
+    let compressed = compress(log.as_bytes(), 9, 1 << 12);
+    assert!(read_all(&compressed[..compressed.len() - 10]).is_err());
+}
+
+#[test]
+fn test_reader_bounded_memory() {
+    fn decode_bounded(compressed: &[u8], max_page: usize) -> Option<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut reader =
+            DecompressBufReader::new(compressed).with_max_page(max_page);
+        let mut largest = 0;
+        let total = reader
+            .decode_to(|part| {
+                largest = largest.max(part.len());
+                output.extend(part);
+                Ok(())
+            })
+            .ok()?;
+        assert_eq!(total, output.len() as u64);
+        assert!(largest <= max_page);
+        Some(output)
+    }
+
+    let log = make_log(2000);
+    let zeros = vec![0u8; 100000];
+    for (input, level) in [(log.as_bytes(), 9), (&zeros[..], 9)] {
+        let compressed = compress(input, level, 1 << 12);
+        assert_eq!(decode_bounded(&compressed, 1 << 12).unwrap(), input);
+        // Pages that are larger than the bound are rejected.
+        assert!(decode_bounded(&compressed, 1 << 11).is_none());
+    }
+
+    // Streams that are not paged are decoded in one step.
+    let compressed = compress(&log.as_bytes()[..2000], 13, 1 << 12);
+    assert!(decode_bounded(&compressed, 1 << 12).is_none());
+    assert_eq!(decode_bounded(&compress(&[], 9, 1 << 12), 1).unwrap(), b"");
+
+    // A record that declares a huge page is rejected before it is read.
+    let compressed = compress(log.as_bytes(), 9, 1 << 20);
+    assert_eq!(
+        decode_bounded(&compressed, 1 << 20).unwrap(),
+        log.as_bytes()
+    );
+    assert!(decode_bounded(&compressed, 1 << 10).is_none());
+}
+
+#[test]
+fn test_reader_page_checksums() {
+    let log = make_log(2000);
+    let ctx = Context::new(9, 1 << 12).with_page_checksums();
+    let mut compressed: Vec<u8> = Vec::new();
+    let _ = FullEncoder::new(log.as_bytes(), &mut compressed, ctx).encode();
+
+    let mut output = Vec::new();
+    let mut reader = DecompressBufReader::new(&compressed[..]);
+    reader.read_to_end(&mut output).unwrap();
+    assert_eq!(output, log.as_bytes());
+
+    // Pages that don't match their checksum are rejected.
+    let sig = compressed
+        .windows(CHECKED_PAGE_SIG.len())
+        .position(|w| w == CHECKED_PAGE_SIG)
+        .unwrap();
+    let mut damaged = compressed.clone();
+    damaged[sig + CHECKED_PAGE_SIG.len()] ^= 1;
+    let mut reader = DecompressBufReader::new(&damaged[..]);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_reader_rejects_references() {
+    // The pages repeat each other, so they refer to earlier pages.
+    let log = make_log(300);
+    let input = log.repeat(8);
+    let ctx = Context::new(4, 1 << 14).with_global_matching();
+    let mut compressed: Vec<u8> = Vec::new();
+    let _ = FullEncoder::new(input.as_bytes(), &mut compressed, ctx).encode();
+
+    let mut reader = DecompressBufReader::new(&compressed[..]);
+    let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+    // Streams without references are read.
+    let compressed = compress(input.as_bytes(), 4, 1 << 14);
+    let mut output = Vec::new();
+    let mut reader = DecompressBufReader::new(&compressed[..]);
+    reader.read_to_end(&mut output).unwrap();
+    assert_eq!(output, input.as_bytes());
+}
+
+#[test]
+fn test_reader_rejects_duplicate_pages() {
+    // The pages repeat each other, so they are saved as duplicate records.
+    let input = make_log(300).repeat(8);
+    for checksums in [false, true] {
+        let mut ctx = Context::new(4, 1 << 14);
+        if checksums {
+            ctx = ctx.with_page_checksums();
+        }
+        let mut compressed = FULL_SIG.to_vec();
+        let mut encoder =
+            PagerEncoder::new(input.as_bytes(), &mut compressed, ctx);
+        encoder.set_callback(encode_or_nop);
+        encoder.set_page_size(input.len() / 8);
+        encoder.set_deduplication(true);
+        let _ = encoder.encode();
+        assert!(compressed.windows(2).any(|w| w == DUP_PAGE_SIG));
+        let mut decoded = Vec::new();
+        let _ = FullDecoder::new(&compressed, &mut decoded)
+            .decode()
+            .unwrap();
+        assert_eq!(decoded, input.as_bytes());
+
+        let mut reader = DecompressBufReader::new(&compressed[..]);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
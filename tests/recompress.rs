@@ -0,0 +1,222 @@
+use compressor::recompress::{detect_format, recompress, restore, Format};
+use compressor::Context;
+use flate2::write::DeflateEncoder;
+use flate2::{Compress, Compression, FlushCompress};
+use std::io::Write;
+
+/// Return a text that compresses well.
+fn text(len: usize) -> Vec<u8> {
+    let line = "a line of a file that is stored in an archive.\n";
+    line.repeat(len / line.len() + 1).as_bytes()[..len].to_vec()
+}
+
+/// Return 'len' pseudo-random bytes.
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+/// Return a gzip stream with the name 'name' that saves 'data' in stored
+/// deflate blocks of at most 'block' bytes. The trailer is not a valid
+/// checksum, but it is restored as is.
+fn make_gzip(data: &[u8], block: usize) -> Vec<u8> {
+    let mut gzip = vec![0x1f, 0x8b, 0x08, 0x08, 0, 0, 0, 0, 0, 3];
+    gzip.extend(b"file.txt\0");
+    let mut chunks: Vec<&[u8]> = data.chunks(block).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    for (i, chunk) in chunks.iter().enumerate() {
+        gzip.push((i + 1 == chunks.len()) as u8);
+        let len = chunk.len() as u16;
+        gzip.extend(len.to_le_bytes());
+        gzip.extend((!len).to_le_bytes());
+        gzip.extend(*chunk);
+    }
+    gzip.extend([1, 2, 3, 4]);
+    gzip.extend((data.len() as u32).to_le_bytes());
+    gzip
+}
+
+/// Return the raw deflate stream of 'data', encoded by zlib at 'level'.
+fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Return the raw deflate stream of 'data', encoded by zlib at level 9 with a
+/// small window, which no default setting of zlib reproduces.
+fn deflate_small_window(data: &[u8]) -> Vec<u8> {
+    let mut encoder =
+        Compress::new_with_window_bits(Compression::best(), false, 9);
+    let mut stream = Vec::with_capacity(data.len() * 2 + 1000);
+    encoder
+        .compress_vec(data, &mut stream, FlushCompress::Finish)
+        .unwrap();
+    stream
+}
+
+/// Return a gzip stream that saves 'data' in a deflate stream encoded by zlib
+/// at 'level'.
+fn make_deflate_gzip(data: &[u8], level: u32) -> Vec<u8> {
+    let mut gzip = vec![0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 3];
+    gzip.extend(deflate(data, level));
+    gzip.extend([1, 2, 3, 4]);
+    gzip.extend((data.len() as u32).to_le_bytes());
+    gzip
+}
+
+/// Return a zip archive with the entries 'entries' of (method, data), and a
+/// fake central directory.
+fn make_zip(entries: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    make_zip_with(entries, false)
+}
+
+/// Return a zip archive with the entries 'entries' of (method, data). If
+/// 'descriptor' is set, the sizes follow each entry in a data descriptor.
+fn make_zip_with(entries: &[(u16, Vec<u8>)], descriptor: bool) -> Vec<u8> {
+    let mut zip = Vec::new();
+    for (i, (method, data)) in entries.iter().enumerate() {
+        let name = format!("entry{}.txt", i);
+        let sizes = [(data.len() as u32).to_le_bytes(); 2].concat();
+        zip.extend([0x50, 0x4b, 0x03, 0x04, 20, 0]);
+        zip.extend(if descriptor { [8, 0] } else { [0, 0] });
+        zip.extend(method.to_le_bytes());
+        zip.extend([0; 8]);
+        zip.extend(if descriptor {
+            vec![0; 8]
+        } else {
+            sizes.clone()
+        });
+        zip.extend((name.len() as u16).to_le_bytes());
+        zip.extend(0u16.to_le_bytes());
+        zip.extend(name.as_bytes());
+        zip.extend(data);
+        if descriptor {
+            zip.extend([0x50, 0x4b, 0x07, 0x08, 1, 2, 3, 4]);
+            zip.extend(&sizes);
+        }
+    }
+    zip.extend([0x50, 0x4b, 0x05, 0x06]);
+    zip.extend([0; 18]);
+    zip
+}
+
+/// Recompress 'input', and check that it is restored bit-exactly. Returns the
+/// size of the recompressed stream.
+fn round_trip(input: &[u8], format: Format) -> usize {
+    let packed = recompress(input, Context::new(5, 1 << 20)).unwrap();
+    assert_eq!(restore(&packed), Some((format, input.to_vec())));
+    packed.len()
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(detect_format(&make_gzip(b"abc", 10)), Some(Format::Gzip));
+    assert_eq!(detect_format(&make_zip(&[])), None);
+    assert_eq!(
+        detect_format(&make_zip(&[(0, text(10))])),
+        Some(Format::Zip)
+    );
+    assert_eq!(
+        detect_format(&[0x28, 0xb5, 0x2f, 0xfd, 0]),
+        Some(Format::Zstd)
+    );
+    assert_eq!(detect_format(&[0xff, 0xd8, 0xff, 0xe0]), Some(Format::Jpeg));
+    assert_eq!(detect_format(b"plain text"), None);
+    assert!(Format::Gzip.is_supported() && !Format::Jpeg.is_supported());
+}
+
+#[test]
+fn test_recompress_gzip() {
+    let data = text(100000);
+    let gzip = make_gzip(&data, 30000);
+    assert!(round_trip(&gzip, Format::Gzip) * 20 < gzip.len());
+    round_trip(&make_gzip(&[], 100), Format::Gzip);
+
+    // Corrupted blocks can't be restored, and are not recompressed.
+    let mut compressed = gzip.clone();
+    compressed[19] |= 0b10;
+    assert!(recompress(&compressed, Context::new(5, 1 << 20)).is_none());
+    assert!(
+        recompress(&gzip[..gzip.len() - 3], Context::new(5, 1 << 20)).is_none()
+    );
+}
+
+#[test]
+fn test_recompress_deflate_gzip() {
+    // The deflate streams of zlib are decoded and encoded again bit-exactly.
+    let data = [text(200000), random_bytes(50000)].concat();
+    let gzip = make_deflate_gzip(&data, 6);
+    let packed = round_trip(&gzip, Format::Gzip);
+    assert!(packed < gzip.len());
+    for level in [0, 1, 4, 9] {
+        round_trip(&make_deflate_gzip(&data, level), Format::Gzip);
+    }
+    round_trip(&make_deflate_gzip(&[], 6), Format::Gzip);
+
+    // A stream that zlib doesn't reproduce is not recompressed.
+    let mut gzip = vec![0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 3];
+    gzip.extend(deflate_small_window(&random_bytes(2000).repeat(4)));
+    gzip.extend([0; 8]);
+    assert!(recompress(&gzip, Context::new(5, 1 << 20)).is_none());
+
+    // The level of a deflate part is checked when it is restored.
+    let gzip = make_deflate_gzip(&text(50000), 6);
+    let mut packed = recompress(&gzip, Context::new(5, 1 << 20)).unwrap();
+    let level = packed.windows(2).position(|w| w == [1, 6]).unwrap() + 1;
+    packed[level] = 1;
+    assert!(restore(&packed).is_none());
+}
+
+#[test]
+fn test_recompress_zip() {
+    // The stored and the deflated entries are unwrapped, so the deflated copy
+    // of the stored random entry is matched against it. The entry that zlib
+    // doesn't reproduce stays in the wrapper.
+    let random = random_bytes(30000);
+    let window = deflate_small_window(&random_bytes(2000).repeat(4));
+    let zip = make_zip(&[
+        (0, random.clone()),
+        (8, deflate(&text(40000), 9)),
+        (8, window.clone()),
+        (8, deflate(&random, 6)),
+        (0, text(20000)),
+    ]);
+    let packed = round_trip(&zip, Format::Zip);
+    assert!(packed < random.len() + window.len() + 1000);
+
+    // The end of the entries with a data descriptor is found by decoding them.
+    let entries = [(8, deflate(&text(30000), 6)), (8, deflate(&text(10), 9))];
+    let zip = make_zip_with(&entries, true);
+    assert!(round_trip(&zip, Format::Zip) < zip.len());
+
+    // Archives without entries that can be restored are not recompressed.
+    let zip = make_zip(&[(8, window)]);
+    assert!(recompress(&zip, Context::new(5, 1 << 20)).is_none());
+    let zip = make_zip(&[(8, random)]);
+    assert!(recompress(&zip, Context::new(5, 1 << 20)).is_none());
+    let jpeg = [0xff, 0xd8, 0xff, 0xe0, 1, 2, 3];
+    assert!(recompress(&jpeg, Context::new(5, 1 << 20)).is_none());
+}
+
+#[test]
+fn test_restore_errors() {
+    let packed =
+        recompress(&make_gzip(&text(5000), 1000), Context::new(5, 1 << 20))
+            .unwrap();
+    for len in 0..packed.len() {
+        assert!(restore(&packed[..len]).is_none());
+    }
+    let mut extra = packed.clone();
+    extra.push(0);
+    assert!(restore(&extra).is_none());
+}
@@ -0,0 +1,65 @@
+use compressor::pipeline::{Codec, Pipeline, Transform};
+use compressor::registry::FileType;
+use compressor::registry::{default_pipeline, detect_file_type, lookup};
+
+/// Return the header of a WAV file with 'channels' channels of 16-bit samples.
+fn wav_header(channels: u16) -> Vec<u8> {
+    let mut header = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+    header.extend(16u32.to_le_bytes());
+    header.extend(1u16.to_le_bytes());
+    header.extend(channels.to_le_bytes());
+    header.extend(44100u32.to_le_bytes());
+    header.extend((44100 * 2 * channels as u32).to_le_bytes());
+    header.extend((2 * channels).to_le_bytes());
+    header.extend(16u16.to_le_bytes());
+    header
+}
+
+/// Return the header of a BMP file with 'bits' bits per pixel.
+fn bmp_header(bits: u16) -> Vec<u8> {
+    let mut header = b"BM".to_vec();
+    header.resize(28, 0);
+    header.extend(bits.to_le_bytes());
+    header
+}
+
+#[test]
+fn test_detect_file_type() {
+    let elf = b"\x7fELF\x02\x01\x01";
+    assert_eq!(
+        detect_file_type("a.wav", &wav_header(2)),
+        Some(FileType::Wav)
+    );
+    assert_eq!(detect_file_type("a", &bmp_header(24)), Some(FileType::Bmp));
+    assert_eq!(detect_file_type("libz.so", elf), Some(FileType::Elf));
+    assert_eq!(detect_file_type("t.CSV", b"a,b\n"), Some(FileType::Csv));
+    assert_eq!(detect_file_type("a.tsv", b""), Some(FileType::Csv));
+
+    // The content wins over the extension.
+    assert_eq!(detect_file_type("a.csv", elf), Some(FileType::Elf));
+    assert_eq!(detect_file_type("x.bmp", b"text"), Some(FileType::Bmp));
+
+    // RIFF files that are not audio, and unknown files.
+    assert_eq!(detect_file_type("a.avi", b"RIFF\0\0\0\0AVI "), None);
+    assert_eq!(detect_file_type("a.txt", b"hello"), None);
+    assert_eq!(detect_file_type("noext", b""), None);
+}
+
+#[test]
+fn test_default_pipeline() {
+    let delta = |stride| {
+        Some(Pipeline::new(Codec::Block).with(Transform::Delta(stride)))
+    };
+    assert_eq!(default_pipeline(FileType::Wav, &wav_header(2)), delta(4));
+    assert_eq!(default_pipeline(FileType::Wav, &wav_header(1)), delta(2));
+    assert_eq!(default_pipeline(FileType::Wav, &wav_header(16)), None);
+    assert_eq!(default_pipeline(FileType::Wav, b"RIFF"), None);
+    assert_eq!(default_pipeline(FileType::Bmp, &bmp_header(24)), delta(3));
+    assert_eq!(default_pipeline(FileType::Bmp, &bmp_header(32)), delta(4));
+    assert_eq!(default_pipeline(FileType::Bmp, &bmp_header(8)), None);
+    assert_eq!(default_pipeline(FileType::Csv, b"a,b\n"), None);
+    assert_eq!(default_pipeline(FileType::Elf, b"\x7fELF"), None);
+
+    assert_eq!(lookup("song.wav", &wav_header(2)), delta(4));
+    assert_eq!(lookup("a.txt", b"hello"), None);
+}
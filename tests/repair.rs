@@ -0,0 +1,109 @@
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::pager::{read_checked_header, read_header, record_len};
+use compressor::repair::{repair, MissingPage};
+use compressor::utils::signatures::FULL_SIG;
+use compressor::{Context, Decoder, Encoder};
+
+/// Return the input, with a few kinds of pages, and its compressed stream.
+fn compress_with_checksums(page_size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut input = Vec::new();
+    for i in 0..3000u32 {
+        input.extend(format!("{} {};", i % 101, i % 7).as_bytes());
+    }
+    input.extend(vec![9; page_size]);
+    input.extend_from_within(..page_size);
+    let ctx = Context::new(5, page_size).with_page_checksums();
+    let mut compressed = Vec::new();
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    (input, compressed)
+}
+
+/// Return the offsets of the page records of the paged stream 'input'.
+fn record_offsets(input: &[u8]) -> Vec<usize> {
+    let body = &input[FULL_SIG.len()..];
+    let (mut cursor, parts) = read_header(body).unwrap();
+    let mut offsets = Vec::new();
+    for _ in 0..parts {
+        offsets.push(FULL_SIG.len() + cursor);
+        cursor += record_len(&body[cursor..]).unwrap();
+    }
+    offsets
+}
+
+#[test]
+fn test_page_checksums() {
+    let page_size = 4096;
+    let (input, compressed) = compress_with_checksums(page_size);
+    let offsets = record_offsets(&compressed);
+    for &offset in &offsets {
+        let (_, _, len) = read_checked_header(&compressed[offset..]).unwrap();
+        assert!(len <= page_size);
+    }
+
+    let mut decoded = Vec::new();
+    let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+    assert_eq!(decoded, input);
+
+    // The checksum rejects a page that decodes into other bytes.
+    let mut damaged = compressed.clone();
+    damaged[offsets[1] + 2] ^= 1;
+    let mut decoded = Vec::new();
+    assert!(FullDecoder::new(&damaged, &mut decoded).decode().is_none());
+
+    // Checked headers that wrap other checked headers are rejected, without
+    // recursing into each one of them.
+    let (header, _, _) =
+        read_checked_header(&compressed[offsets[0]..]).unwrap();
+    let header = compressed[offsets[0]..offsets[0] + header].to_vec();
+    let mut nested = compressed[..offsets[0]].to_vec();
+    for _ in 0..200_000 {
+        nested.extend(&header);
+    }
+    nested.extend(&compressed[offsets[0]..]);
+    assert!(record_len(&nested[offsets[0]..]).is_none());
+    let mut decoded = Vec::new();
+    assert!(FullDecoder::new(&nested, &mut decoded).decode().is_none());
+}
+
+#[test]
+fn test_repair() {
+    let page_size = 4096;
+    let (input, compressed) = compress_with_checksums(page_size);
+    let report = repair(&compressed).unwrap();
+    assert!(report.is_complete());
+    assert_eq!(report.output, input);
+    assert!(report.to_map().is_empty());
+
+    // Damage the payload of the second page.
+    let offsets = record_offsets(&compressed);
+    let mut damaged = compressed.clone();
+    let mid = (offsets[1] + offsets[2]) / 2;
+    for byte in &mut damaged[mid..mid + 16] {
+        *byte ^= 0x5a;
+    }
+    let report = repair(&damaged).unwrap();
+    assert_eq!(report.recovered, report.pages - 1);
+    let missing = MissingPage {
+        index: 1,
+        offset: page_size,
+        len: Some(page_size),
+    };
+    assert_eq!(report.missing, vec![missing]);
+    assert_eq!(report.output.len(), input.len());
+    assert_eq!(report.output[..page_size], input[..page_size]);
+    let filled = &report.output[page_size..2 * page_size];
+    assert!(filled.iter().all(|&b| b == 0));
+    assert_eq!(report.output[2 * page_size..], input[2 * page_size..]);
+    assert_eq!(report.to_map(), format!("1 {} {}\n", page_size, page_size));
+
+    // The pages after a truncation can't be located.
+    let report = repair(&compressed[..offsets[3] + 3]).unwrap();
+    assert!(report.truncated);
+    assert_eq!(report.recovered, 3);
+    assert_eq!(report.output, input[..3 * page_size]);
+    let map = report.to_map();
+    assert!(map.ends_with(&format!("truncated {}\n", 3 * page_size)));
+
+    assert!(repair(b"not a stream").is_none());
+}
@@ -0,0 +1,93 @@
+use compressor::block::{BlockDecoder, BlockEncoder};
+use compressor::inspect::inspect_block;
+use compressor::rle::{collapse, expand, MIN_RUN};
+use compressor::utils::signatures::RLE_BLOCK_SIG;
+use compressor::{Context, Decoder, Encoder};
+
+/// Return an input with text between long runs of different bytes.
+fn runs_input() -> Vec<u8> {
+    let text = b"the matcher never sees the long runs of this input. ";
+    let mut input = Vec::new();
+    for i in 0..4 {
+        input.extend(text.repeat(20));
+        input.resize(input.len() + MIN_RUN * (i + 1), b'a' + i as u8);
+    }
+    // Adjacent runs and a run that is just too short.
+    input.resize(input.len() + MIN_RUN, b'x');
+    input.resize(input.len() + MIN_RUN - 1, b'y');
+    input.resize(input.len() + 5 * MIN_RUN, b'z');
+    input
+}
+
+#[test]
+fn test_collapse_runs() {
+    let input = runs_input();
+    let (collapsed, tokens) = collapse(&input).unwrap();
+    assert!(collapsed.len() < 4 * 20 * 52 + 4 + MIN_RUN + 4);
+    assert_eq!(expand(&collapsed, &tokens).unwrap(), input);
+
+    assert!(collapse(b"no runs here").is_none());
+    assert!(collapse(&[7; MIN_RUN - 1]).is_none());
+    let (collapsed, tokens) = collapse(&[7; MIN_RUN]).unwrap();
+    assert_eq!(collapsed, [7]);
+    assert_eq!(expand(&collapsed, &tokens).unwrap(), [7; MIN_RUN]);
+
+    // Invalid tokens are rejected.
+    let (collapsed, tokens) = collapse(&input).unwrap();
+    assert!(expand(&collapsed[..10], &tokens).is_none());
+    assert!(expand(&collapsed, &tokens[..tokens.len() - 1]).is_none());
+
+    // Positions and lengths that overflow.
+    let tokens = [
+        1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 0,
+    ];
+    assert!(expand(&collapsed, &tokens).is_none());
+    let tokens = [
+        1, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1,
+    ];
+    assert!(expand(&collapsed, &tokens).is_none());
+}
+
+#[test]
+fn test_rle_blocks() {
+    let input = runs_input();
+    for level in [1, 4, 9, 11] {
+        let ctx = Context::new(level, 1 << 20);
+        let mut compressed = Vec::new();
+        let written = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+        assert_eq!(written, compressed.len());
+        assert!(written < 1000);
+
+        let mut decoded = Vec::new();
+        let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((written, input.len())));
+        assert_eq!(decoded, input);
+    }
+
+    // The pass can be turned off, and the runs are then matched.
+    let ctx = Context::new(4, 1 << 20);
+    let mut collapsed = Vec::new();
+    let _ = BlockEncoder::new(&input, &mut collapsed, ctx).encode();
+    assert!(collapsed.starts_with(&RLE_BLOCK_SIG));
+    let mut compressed = Vec::new();
+    let ctx = ctx.with_rle_pass(false);
+    let written = BlockEncoder::new(&input, &mut compressed, ctx).encode();
+    assert!(!compressed.starts_with(&RLE_BLOCK_SIG));
+    let mut decoded = Vec::new();
+    let mut decoder = BlockDecoder::new(&compressed, &mut decoded);
+    assert_eq!(decoder.decode(), Some((written, input.len())));
+    assert_eq!(decoded, input);
+}
+
+#[test]
+fn test_nested_rle_blocks() {
+    // RLE blocks don't nest, so deep nesting can't overflow the stack.
+    let mut nested = Vec::new();
+    for _ in 0..300_000 {
+        nested.extend(RLE_BLOCK_SIG);
+        nested.push(0);
+    }
+    let mut decoded = Vec::new();
+    assert!(BlockDecoder::new(&nested, &mut decoded).decode().is_none());
+    assert!(inspect_block(&nested).is_none());
+}
@@ -0,0 +1,110 @@
+use compressor::full::FullEncoder;
+use compressor::scatter::{decode_at, split_pages, WriteAt};
+use compressor::{Context, Encoder};
+use std::io;
+use std::sync::Mutex;
+
+/// An output in memory that records the writes.
+#[derive(Default)]
+struct MemoryOutput {
+    data: Mutex<Vec<u8>>,
+}
+
+impl WriteAt for MemoryOutput {
+    fn write_at(&self, data: &[u8], offset: u64) -> io::Result<()> {
+        let mut buffer = self.data.lock().unwrap();
+        let end = offset as usize + data.len();
+        if buffer.len() < end {
+            buffer.resize(end, 0);
+        }
+        buffer[offset as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.data.lock().unwrap().resize(len as usize, 0xaa);
+        Ok(())
+    }
+}
+
+/// Return an input of a few pages of size 'page_size', with a short last page
+/// and pages with a single repeated byte.
+fn make_input(page_size: usize) -> Vec<u8> {
+    let mut input = Vec::new();
+    for i in 0..5000u32 {
+        input.extend(format!("{} ", (i * 13) % 1000).as_bytes());
+    }
+    input.resize(input.len() + 2 * page_size, 7);
+    input.extend(b"the end");
+    input
+}
+
+/// Return the offsets of the pages of a stream with pages of 'page_size'.
+fn page_offsets(len: usize, page_size: usize) -> Vec<u64> {
+    (0..1 + len / page_size)
+        .map(|i| (i * page_size) as u64)
+        .collect()
+}
+
+#[test]
+fn test_decode_at() {
+    let page_size = 1 << 12;
+    let input = make_input(page_size);
+    for ctx in [
+        Context::new(5, page_size),
+        Context::new(0, page_size).with_page_checksums(),
+        Context::new(13, page_size),
+    ] {
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+        let offsets = page_offsets(input.len(), page_size);
+        for threads in [1, 3, 64] {
+            let output = MemoryOutput::default();
+            let size = decode_at(&compressed, &offsets, &output, threads);
+            assert_eq!(size.unwrap(), input.len() as u64);
+            assert_eq!(output.data.into_inner().unwrap(), input);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_decode_at_file() {
+    let page_size = 1 << 12;
+    let input = make_input(page_size);
+    let mut compressed = Vec::new();
+    let ctx = Context::new(3, page_size);
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+
+    let path = std::env::temp_dir().join("compressor_test_decode_at.bin");
+    let file = std::fs::File::create(&path).unwrap();
+    let offsets = page_offsets(input.len(), page_size);
+    let size = decode_at(&compressed, &offsets, &file, 4).unwrap();
+    assert_eq!(size, input.len() as u64);
+    drop(file);
+    assert_eq!(std::fs::read(&path).unwrap(), input);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_decode_at_errors() {
+    let page_size = 1 << 12;
+    let input = make_input(page_size);
+    let mut compressed = Vec::new();
+    let ctx = Context::new(5, page_size);
+    let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+    let offsets = page_offsets(input.len(), page_size);
+    assert_eq!(split_pages(&compressed).unwrap().len(), offsets.len());
+
+    // Offsets that don't match the pages.
+    let output = MemoryOutput::default();
+    assert!(decode_at(&compressed, &offsets[1..], &output, 2).is_err());
+    let mut shifted = offsets.clone();
+    shifted[1] += 1;
+    assert!(decode_at(&compressed, &shifted, &output, 2).is_err());
+
+    // A truncated stream.
+    let truncated = &compressed[..compressed.len() - 10];
+    assert!(decode_at(truncated, &offsets, &output, 2).is_err());
+    assert!(decode_at(b"not a stream", &offsets, &output, 2).is_err());
+}
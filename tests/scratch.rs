@@ -0,0 +1,78 @@
+use compressor::scratch::{recycle_u8, take_u8, with_scratch};
+use compressor::scratch::{BufferPool, Scratch, MAX_POOLED_BYTES};
+
+#[test]
+fn test_buffer_pool() {
+    let mut pool: BufferPool<u32> = BufferPool::new();
+    assert!(pool.is_empty());
+    let buffer = pool.take();
+    assert_eq!(buffer.capacity(), 0);
+
+    let mut buffer = vec![1, 2, 3];
+    buffer.reserve(100);
+    let cap = buffer.capacity();
+    pool.recycle(buffer);
+    assert_eq!(pool.len(), 1);
+
+    // The buffer is returned empty, and keeps its allocation.
+    let buffer = pool.take();
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.capacity(), cap);
+    assert!(pool.is_empty());
+    assert_eq!(pool.bytes(), 0);
+}
+
+#[test]
+fn test_pool_byte_budget() {
+    // The largest buffers are freed when the pool is over the budget.
+    let mut pool: BufferPool<u32> = BufferPool::new();
+    let large = MAX_POOLED_BYTES / 4 / 3 + 1;
+    pool.recycle(Vec::with_capacity(100));
+    pool.recycle(Vec::with_capacity(large));
+    pool.recycle(Vec::with_capacity(large + 1));
+    assert_eq!(pool.len(), 3);
+    pool.recycle(Vec::with_capacity(large - 1));
+    assert_eq!(pool.len(), 3);
+    assert!(pool.bytes() <= MAX_POOLED_BYTES);
+    let mut sizes: Vec<usize> =
+        (0..3).map(|_| pool.take().capacity()).collect();
+    sizes.sort();
+    assert_eq!(sizes[1..], [large - 1, large]);
+    assert!(sizes[0] >= 100);
+    assert_eq!(pool.bytes(), 0);
+}
+
+#[test]
+fn test_thread_local_pool() {
+    let mut buffer = take_u8();
+    buffer.extend([1, 2, 3, 4]);
+    let ptr = buffer.as_ptr();
+    recycle_u8(buffer);
+
+    let buffer = take_u8();
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.as_ptr(), ptr);
+}
+
+#[test]
+fn test_owned_scratch() {
+    // The buffers that are recycled in 'with_scratch' stay in the scratch,
+    // and the pool of the thread is restored.
+    let mut scratch = Scratch::new();
+    let ptr = with_scratch(&mut scratch, || {
+        let mut buffer = take_u8();
+        buffer.resize(1000, 7);
+        let ptr = buffer.as_ptr();
+        recycle_u8(buffer);
+        ptr
+    });
+    assert!(scratch.bytes() >= 1000);
+    assert_eq!(take_u8().capacity(), 0);
+    let reused = with_scratch(&mut scratch, || {
+        let buffer = take_u8();
+        let ptr = buffer.as_ptr();
+        recycle_u8(buffer);
+        ptr
+    });
+    assert_eq!(reused, ptr);
+}
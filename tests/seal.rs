@@ -0,0 +1,159 @@
+use compressor::auto::AUTO_LEVEL;
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::seal::{key_id, seal, unseal, HmacSeal, PageTransform};
+use compressor::utils::hash::{xxh32, xxh64};
+use compressor::utils::leb128;
+use compressor::utils::signatures::{read32, write32, START_PAGE_SIG};
+use compressor::{Context, Decoder, Encoder};
+
+/// A toy cipher that xors the payload with a keystream and appends a tag.
+struct XorCipher {
+    key: u64,
+}
+
+impl XorCipher {
+    fn apply(&self, aad: &[u8], payload: &[u8]) -> Vec<u8> {
+        let seed = xxh64(aad, self.key);
+        payload
+            .iter()
+            .enumerate()
+            .map(|(i, x)| x ^ (xxh64(&i.to_le_bytes(), seed) as u8))
+            .collect()
+    }
+
+    fn tag(&self, aad: &[u8], payload: &[u8]) -> u32 {
+        xxh32(&[aad, payload].concat(), self.key as u32)
+    }
+}
+
+impl PageTransform for XorCipher {
+    fn key_id(&self) -> u64 {
+        self.key >> 32
+    }
+
+    fn encrypt(&self, aad: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut sealed = self.apply(aad, payload);
+        write32(self.tag(aad, &sealed), &mut sealed);
+        sealed
+    }
+
+    fn decrypt(&self, aad: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+        let body = &payload[..payload.len().checked_sub(4)?];
+        if read32(&payload[body.len()..])? != self.tag(aad, body) {
+            return None;
+        }
+        Some(self.apply(aad, body))
+    }
+}
+
+#[test]
+fn test_sealed_round_trip() {
+    let cipher = XorCipher {
+        key: 0x0000_0007_dead_beef,
+    };
+    let text = "Each page is compressed and then encrypted. ".repeat(500);
+    let ctx = Context::new(9, 4096);
+
+    for input in [&b""[..], b"x", text.as_bytes()] {
+        let sealed = seal(input, ctx, &cipher);
+        assert_eq!(key_id(&sealed), Some(7));
+        assert_eq!(unseal(&sealed, &cipher).unwrap(), input);
+    }
+
+    // The pages are compressed before they are encrypted.
+    let sealed = seal(text.as_bytes(), ctx, &cipher);
+    assert!(sealed.len() < text.len() / 4);
+
+    // A different key is rejected.
+    let other = XorCipher {
+        key: 0x0000_0008_dead_beef,
+    };
+    assert!(unseal(&sealed, &other).is_none());
+
+    // Modified and truncated streams are rejected.
+    let mut modified = sealed.clone();
+    let last = modified.len() - 10;
+    modified[last] ^= 1;
+    assert!(unseal(&modified, &cipher).is_none());
+    assert!(unseal(&sealed[..sealed.len() - 1], &cipher).is_none());
+    assert!(key_id(&text.as_bytes()[..20]).is_none());
+
+    // A page length that overflows the cursor is rejected.
+    let header = seal(b"", ctx, &cipher).len();
+    let mut forged = seal(b"x", ctx, &cipher)[..header].to_vec();
+    forged.extend(START_PAGE_SIG);
+    leb128::encode(u64::MAX, &mut forged);
+    assert!(unseal(&forged, &cipher).is_none());
+
+    // The arithmetic and auto levels seal regular blocks.
+    for level in [13, AUTO_LEVEL] {
+        let ctx = Context::new(level, 4096);
+        let sealed = seal(text.as_bytes(), ctx, &cipher);
+        assert_eq!(unseal(&sealed, &cipher).unwrap(), text.as_bytes());
+    }
+}
+
+#[test]
+fn test_seal_trailer() {
+    let cipher = XorCipher {
+        key: 0x0000_0007_dead_beef,
+    };
+    let hmac = HmacSeal::new(b"a secret key");
+    let text = "The seal authenticates the whole stream. ".repeat(500);
+    let ctx = Context::new(9, 4096).with_digest().with_frame_checksum();
+
+    let decode = |stream: &[u8], transform: Option<&dyn PageTransform>| {
+        let mut output = Vec::new();
+        let mut decoder = FullDecoder::new(stream, &mut output);
+        if let Some(transform) = transform {
+            decoder = decoder.with_seal(transform);
+        }
+        let (read, _) = decoder.decode()?;
+        assert_eq!(read, stream.len());
+        Some(output)
+    };
+
+    for transform in [&cipher as &dyn PageTransform, &hmac] {
+        for input in [&b""[..], text.as_bytes()] {
+            let mut stream = Vec::new();
+            let _ = FullEncoder::new(input, &mut stream, ctx)
+                .with_seal(transform)
+                .encode();
+            assert_eq!(decode(&stream, Some(transform)).unwrap(), input);
+            // Decoders without the key skip the seal.
+            assert_eq!(decode(&stream, None).unwrap(), input);
+        }
+
+        // Modified bytes are rejected, also without the other trailers.
+        let mut stream = Vec::new();
+        let plain_ctx = Context::new(9, 4096);
+        let _ = FullEncoder::new(b"abc", &mut stream, plain_ctx)
+            .with_seal(transform)
+            .encode();
+        let stored = stream.iter().position(|x| *x == b'b').unwrap();
+        stream[stored] = b'x';
+        assert_eq!(decode(&stream, None).unwrap(), b"axc");
+        assert!(decode(&stream, Some(transform)).is_none());
+    }
+
+    let mut stream = Vec::new();
+    let _ = FullEncoder::new(text.as_bytes(), &mut stream, ctx)
+        .with_seal(&hmac)
+        .encode();
+    assert!(decode(&stream, Some(&cipher)).is_none());
+    assert!(decode(&stream, Some(&HmacSeal::new(b"other key"))).is_none());
+
+    // Streams without a seal are rejected if the decoder requires one.
+    let mut plain = Vec::new();
+    let _ = FullEncoder::new(text.as_bytes(), &mut plain, ctx).encode();
+    assert!(decode(&plain, None).is_some());
+    assert!(decode(&plain, Some(&hmac)).is_none());
+
+    // The tag of HMAC-SHA256 covers the payload and the associated data.
+    let sealed = hmac.encrypt(b"aad", b"payload");
+    assert_eq!(sealed.len(), 7 + 32);
+    assert_eq!(hmac.decrypt(b"aad", &sealed).unwrap(), b"payload");
+    assert!(hmac.decrypt(b"aaD", &sealed).is_none());
+    assert!(hmac.decrypt(b"aad", &sealed[1..]).is_none());
+    assert_ne!(hmac.key_id(), HmacSeal::new(b"other key").key_id());
+}
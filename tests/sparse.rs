@@ -0,0 +1,114 @@
+use compressor::block::BlockEncoder;
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::pager::{find_holes, MIN_HOLE_SIZE};
+use compressor::reader::DecompressBufReader;
+use compressor::sparse::SparseWriter;
+use compressor::{Context, Decoder, Encoder};
+use std::io::{Cursor, Read, Write};
+
+/// A block of data in the disk image.
+fn data_block() -> String {
+    "a block of the filesystem with some content. ".repeat(100)
+}
+
+/// Return an input that looks like a disk image, with data between holes.
+fn disk_image() -> Vec<u8> {
+    let text = data_block();
+    let mut input = Vec::new();
+    input.extend(text.as_bytes());
+    input.resize(input.len() + 3 * MIN_HOLE_SIZE + 17, 0);
+    input.extend(text.as_bytes());
+    input.resize(input.len() + MIN_HOLE_SIZE - 1, 0);
+    input.extend(text.as_bytes());
+    input.resize(input.len() + 2 * MIN_HOLE_SIZE, 0);
+    input
+}
+
+#[test]
+fn test_find_holes() {
+    let input = disk_image();
+    let holes = find_holes(&input);
+    assert_eq!(holes.len(), 2);
+    assert_eq!(holes[0], (data_block().len(), 3 * MIN_HOLE_SIZE + 17));
+    assert_eq!(holes[1].1, 2 * MIN_HOLE_SIZE);
+    assert_eq!(holes[1].0 + holes[1].1, input.len());
+    assert!(find_holes(&[1, 0, 0, 2]).is_empty());
+}
+
+#[test]
+fn test_sparse_pages() {
+    let input = disk_image();
+    for page_size in [1 << 10, 10000, 1 << 20] {
+        let ctx = Context::new(4, page_size).with_sparse_holes();
+        let mut compressed = Vec::new();
+        let _ = FullEncoder::new(&input, &mut compressed, ctx).encode();
+
+        let mut decoded = Vec::new();
+        let mut decoder = FullDecoder::new(&compressed, &mut decoded);
+        assert_eq!(decoder.decode(), Some((compressed.len(), input.len())));
+        assert_eq!(decoded, input);
+
+        let mut reader = DecompressBufReader::new(&compressed[..]);
+        let mut streamed = Vec::new();
+        reader.read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, input);
+    }
+}
+
+#[test]
+fn test_holes_are_opt_in() {
+    // Matches cross the zero run, unless holes are saved.
+    let random: Vec<u8> = (0..100_000).map(|_| rand::random::<u8>()).collect();
+    let mut input = random.clone();
+    input.resize(input.len() + 2 * MIN_HOLE_SIZE, 0);
+    input.extend(&random);
+
+    let ctx = Context::new(4, 1 << 20);
+    let mut block = Vec::new();
+    let _ = BlockEncoder::new(&input, &mut block, ctx).encode();
+    let mut full = Vec::new();
+    let _ = FullEncoder::new(&input, &mut full, ctx).encode();
+    assert!(full.len() < block.len() + 100);
+
+    let mut sparse = Vec::new();
+    let ctx = ctx.with_sparse_holes();
+    let _ = FullEncoder::new(&input, &mut sparse, ctx).encode();
+    assert!(sparse.len() > 2 * random.len());
+    let mut decoded = Vec::new();
+    let _ = FullDecoder::new(&sparse, &mut decoded).decode().unwrap();
+    assert_eq!(decoded, input);
+}
+
+#[test]
+fn test_sparse_hole_overflow() {
+    use compressor::limits::{set_max_output, DEFAULT_MAX_OUTPUT};
+    use compressor::pager::{decode_sparse_body, write_hole};
+    use compressor::utils::leb128;
+
+    fn decode_nop(_: &[u8]) -> Option<(usize, Vec<u8>)> {
+        None
+    }
+
+    // Two holes whose lengths add up to more than the address space.
+    let mut body = Vec::new();
+    leb128::encode(2, &mut body);
+    write_hole(10, &mut body);
+    write_hole(usize::MAX, &mut body);
+    set_max_output(usize::MAX);
+    assert!(decode_sparse_body(&body, decode_nop).is_none());
+    set_max_output(DEFAULT_MAX_OUTPUT);
+}
+
+#[test]
+fn test_sparse_writer() {
+    let input = disk_image();
+    // Write in pieces, so the holes cross the boundaries of the writes.
+    for chunk in [1, 1000, MIN_HOLE_SIZE + 5, input.len()] {
+        let mut writer = SparseWriter::new(Cursor::new(Vec::new()));
+        for part in input.chunks(chunk) {
+            writer.write_all(part).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+        assert_eq!(file, input);
+    }
+}
@@ -0,0 +1,183 @@
+use compressor::block::{BlockDecoder, BlockEncoder};
+use compressor::coding::adaptive::AdaptiveArithmeticDecoder as AAD;
+use compressor::coding::adaptive::AdaptiveArithmeticEncoder as AAE;
+use compressor::coding::entropy::{EntropyDecoder, EntropyEncoder};
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::lz::{LZ4Decoder, LZ4Encoder};
+use compressor::nop::{NopDecoder, NopEncoder};
+use compressor::pager::{PagerDecoder, PagerEncoder};
+use compressor::{decode_exact, Context, Decoder, Encoder};
+
+/// Check that the decoder reports the exact size of the stream when other
+/// data follows it, and that the strict mode rejects the trailing data.
+macro_rules! check_trailing {
+    ($input:expr, $enc:ty, $dec:ty) => {{
+        let input: &[u8] = $input;
+        let ctx = Context::new(9, 1 << 10);
+        let mut encoded = Vec::new();
+        let written = <$enc>::new(input, &mut encoded, ctx).encode();
+        assert_eq!(written, encoded.len());
+
+        let mut padded = encoded.clone();
+        padded.extend(b"trailing data");
+        for data in [&encoded, &padded] {
+            let mut output = Vec::new();
+            let mut decoder = <$dec>::new(data, &mut output);
+            assert_eq!(decoder.remaining_input(), &data[..]);
+            let stat = decoder.decode();
+            assert_eq!(stat, Some((encoded.len(), input.len())));
+            assert_eq!(decoder.remaining_input(), &data[encoded.len()..]);
+            assert_eq!(output, input);
+        }
+
+        let mut output = Vec::new();
+        assert_eq!(
+            decode_exact::<$dec>(&encoded, &mut output),
+            Some(input.len())
+        );
+        let mut output = b"earlier output".to_vec();
+        assert_eq!(decode_exact::<$dec>(&padded, &mut output), None);
+        assert_eq!(output, b"earlier output");
+    }};
+}
+
+/// Check that decoders leave the output unchanged when they fail on damaged
+/// and truncated streams.
+macro_rules! check_all_or_nothing {
+    ($input:expr, $enc:ty, $dec:ty) => {{
+        let input: &[u8] = $input;
+        let ctx = Context::new(9, 1 << 10);
+        let mut encoded = Vec::new();
+        let _ = <$enc>::new(input, &mut encoded, ctx).encode();
+        let prefix = b"earlier output".to_vec();
+        for i in 0..encoded.len() {
+            let mut damaged = encoded.clone();
+            damaged[i] ^= 0x41;
+            for data in [&encoded[..i], &damaged[..]] {
+                let mut output = prefix.clone();
+                if <$dec>::new(data, &mut output).decode().is_none() {
+                    assert_eq!(output, prefix);
+                }
+            }
+        }
+    }};
+}
+
+#[test]
+fn test_trailing_data() {
+    let text = "the decoders stop at the end of their own stream. ".repeat(60);
+    for input in [&b"short input"[..], text.as_bytes()] {
+        check_trailing!(input, NopEncoder, NopDecoder);
+        check_trailing!(input, BlockEncoder, BlockDecoder);
+        check_trailing!(input, FullEncoder, FullDecoder);
+        check_trailing!(input, EntropyEncoder<256, 4096>, EntropyDecoder<256, 4096>);
+        check_trailing!(input, AAE, AAD);
+    }
+}
+
+#[test]
+fn test_failed_decode_keeps_output() {
+    let text = "failed decoders leave the output as it was. ".repeat(4);
+    let input = text.as_bytes();
+    check_all_or_nothing!(input, NopEncoder, NopDecoder);
+    check_all_or_nothing!(input, BlockEncoder, BlockDecoder);
+    check_all_or_nothing!(input, FullEncoder, FullDecoder);
+    check_all_or_nothing!(input, EntropyEncoder<256, 4096>, EntropyDecoder<256, 4096>);
+
+    // The arithmetic decoder is slow to start, so only a few streams are
+    // checked.
+    let mut encoded = Vec::new();
+    let _ = AAE::new(input, &mut encoded, Context::new(9, 1 << 10)).encode();
+    for len in [1, 5, 8, encoded.len() / 2, encoded.len() - 1] {
+        let mut output = b"earlier output".to_vec();
+        assert!(AAD::new(&encoded[..len], &mut output).decode().is_none());
+        assert_eq!(output, b"earlier output");
+    }
+
+    // The LZ4 decoder starts with an empty output, and leaves it empty.
+    let mut encoded = Vec::new();
+    let _ =
+        LZ4Encoder::new(input, &mut encoded, Context::new(9, 1 << 10)).encode();
+    for i in 0..encoded.len() {
+        let mut output = Vec::new();
+        let stat = LZ4Decoder::new(&encoded[..i], &mut output).decode();
+        if stat.is_none() {
+            assert!(output.is_empty());
+        }
+    }
+}
+
+#[test]
+fn test_embedded_streams() {
+    // A container saves streams with trailers one after the other, and finds
+    // each stream from the end of the previous one.
+    let contexts = [
+        Context::new(2, 1 << 10).with_digest(),
+        Context::new(9, 1 << 12)
+            .with_frame_checksum()
+            .with_hash_tree(),
+        Context::new(0, 1 << 10).with_page_checksums(),
+    ];
+    let text = "each stream reports where it ends. ".repeat(100);
+    let mut container = Vec::new();
+    for ctx in contexts {
+        let _ = FullEncoder::new(text.as_bytes(), &mut container, ctx).encode();
+    }
+    container.extend(b"footer");
+
+    let mut rest = &container[..];
+    let mut outputs = vec![Vec::new(); contexts.len()];
+    for output in outputs.iter_mut() {
+        let mut decoder = FullDecoder::new(rest, output);
+        let (read, _) = decoder.decode().unwrap();
+        assert_eq!(decoder.remaining_input(), &rest[read..]);
+        rest = decoder.remaining_input();
+    }
+    assert_eq!(rest, b"footer");
+    assert!(outputs.iter().all(|output| output == text.as_bytes()));
+
+    // Failed calls leave the whole input.
+    let mut output = Vec::new();
+    let mut decoder = FullDecoder::new(b"footer", &mut output);
+    assert!(decoder.decode().is_none());
+    assert_eq!(decoder.remaining_input(), b"footer");
+}
+
+#[test]
+fn test_lz4_needs_exact_input() {
+    // The LZ4 block format ends at the end of the input, so the stream must
+    // not be followed by other data.
+    let input = "the lz4 block has no end marker. ".repeat(10);
+    let mut encoded = Vec::new();
+    let ctx = Context::new(9, 1 << 10);
+    let _ = LZ4Encoder::new(input.as_bytes(), &mut encoded, ctx).encode();
+    let mut output = Vec::new();
+    let stat = decode_exact::<LZ4Decoder>(&encoded, &mut output);
+    assert_eq!(stat, Some(input.len()));
+
+    encoded.extend(b"trailing data");
+    let mut output = Vec::new();
+    assert!(decode_exact::<LZ4Decoder>(&encoded, &mut output).is_none());
+}
+
+#[test]
+fn test_pager_packets_are_exact() {
+    fn encode(input: &[u8], _ctx: Context) -> Vec<u8> {
+        input.to_vec()
+    }
+    // Consumes one byte less than the packet.
+    fn decode(input: &[u8]) -> Option<(usize, Vec<u8>)> {
+        Some((input.len() - 1, input.to_vec()))
+    }
+
+    let input = b"pages are decoded with a callback";
+    let mut encoded = Vec::new();
+    let mut encoder =
+        PagerEncoder::new(input, &mut encoded, Context::new(1, 8));
+    encoder.set_callback(encode);
+    let _ = encoder.encode();
+    let mut output = Vec::new();
+    let mut decoder = PagerDecoder::new(&encoded, &mut output);
+    decoder.set_callback(decode);
+    assert!(decoder.decode().is_none());
+}
@@ -0,0 +1,132 @@
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::limits::{set_max_output, DEFAULT_MAX_OUTPUT};
+use compressor::lz::{LZ4Decoder, LZ4Encoder};
+use compressor::transcode::{transcode_full_to_lz4, transcode_lz4_to_full};
+use compressor::{Context, Decoder, Encoder};
+
+/// Return a text with repetitions near each other and far from each other.
+fn make_input() -> Vec<u8> {
+    let mut input = Vec::new();
+    for i in 0..2000u32 {
+        input.extend(format!("line {} of {};\n", (i * 7) % 500, i % 3).bytes());
+    }
+    let copy = input[..8000].to_vec();
+    input.extend(&copy);
+    input.extend(b"tail");
+    input
+}
+
+/// Return bytes that don't compress.
+fn make_noise() -> Vec<u8> {
+    let mut seed: u32 = 7;
+    (0..5000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect()
+}
+
+fn decode_full(input: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let (read, _) = FullDecoder::new(input, &mut decoded).decode().unwrap();
+    assert_eq!(read, input.len());
+    decoded
+}
+
+fn decode_lz4(input: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let (read, _) = LZ4Decoder::new(input, &mut decoded).decode().unwrap();
+    assert_eq!(read, input.len());
+    decoded
+}
+
+#[test]
+fn test_lz4_to_full() {
+    let input = make_input();
+    let mut lz4 = Vec::new();
+    let _ =
+        LZ4Encoder::new(&input, &mut lz4, Context::new(4, 1 << 16)).encode();
+
+    // Pages that are smaller and larger than the LZ4 window.
+    for page_size in [1000, 1 << 14, 1 << 20] {
+        let ctx = Context::new(9, page_size).with_page_checksums();
+        let full = transcode_lz4_to_full(&lz4, ctx).unwrap();
+        assert!(page_size < 1 << 20 || full.len() < lz4.len());
+        assert_eq!(decode_full(&full), input);
+    }
+    let full =
+        transcode_lz4_to_full(&lz4, Context::new(4, 1 << 16).with_digest())
+            .unwrap();
+    assert_eq!(decode_full(&full), input);
+
+    // Empty and incompressible streams.
+    let mut empty = Vec::new();
+    let _ = LZ4Encoder::new(&[], &mut empty, Context::new(4, 1 << 16)).encode();
+    let full = transcode_lz4_to_full(&empty, Context::new(4, 1 << 16)).unwrap();
+    assert!(decode_full(&full).is_empty());
+    let noise = make_noise();
+    let mut lz4 = Vec::new();
+    let _ =
+        LZ4Encoder::new(&noise, &mut lz4, Context::new(4, 1 << 16)).encode();
+    let full = transcode_lz4_to_full(&lz4, Context::new(4, 1 << 10)).unwrap();
+    assert_eq!(decode_full(&full), noise);
+
+    // Invalid streams.
+    let ctx = Context::new(4, 1 << 16);
+    assert!(transcode_lz4_to_full(&[0x10, 1, 1], ctx).is_none());
+    assert!(transcode_lz4_to_full(&[0x50, 1, 2], ctx).is_none());
+
+    // Streams that expand beyond the output cap are rejected.
+    let zeros = vec![0; 1 << 20];
+    let mut lz4 = Vec::new();
+    let _ = LZ4Encoder::new(&zeros, &mut lz4, ctx).encode();
+    set_max_output(1 << 16);
+    let capped = transcode_lz4_to_full(&lz4, ctx);
+    set_max_output(DEFAULT_MAX_OUTPUT);
+    assert!(capped.is_none());
+    assert!(transcode_lz4_to_full(&lz4, ctx).is_some());
+}
+
+#[test]
+fn test_full_to_lz4() {
+    let input = make_input();
+    let ctx = Context::new(4, 1 << 16);
+
+    // Regular blocks, fast blocks, long pages, stored and arithmetic streams.
+    let contexts = [
+        Context::new(9, 1 << 14).with_page_checksums(),
+        Context::new(2, 1 << 20).with_frame_checksum(),
+        Context::new(1, 1 << 12).with_fast_level(1),
+        Context::new(13, 1 << 16),
+    ];
+    for encode_ctx in contexts {
+        let mut full = Vec::new();
+        let _ = FullEncoder::new(&input, &mut full, encode_ctx).encode();
+        let lz4 = transcode_full_to_lz4(&full, ctx).unwrap();
+        assert_eq!(decode_lz4(&lz4), input);
+
+        // Round trip back to the full format.
+        let back = transcode_lz4_to_full(&lz4, encode_ctx).unwrap();
+        assert_eq!(decode_full(&back), input);
+    }
+
+    let noise = make_noise();
+    for data in [&noise[..], &[], b"short"] {
+        let mut full = Vec::new();
+        let _ = FullEncoder::new(data, &mut full, ctx).encode();
+        let lz4 = transcode_full_to_lz4(&full, ctx).unwrap();
+        assert_eq!(decode_lz4(&lz4), data);
+    }
+
+    // Damaged streams and trailing bytes are rejected.
+    let mut full = Vec::new();
+    let _ = FullEncoder::new(&input, &mut full, ctx.with_digest()).encode();
+    let last = full.len() - 1;
+    full[last] ^= 1;
+    assert!(transcode_full_to_lz4(&full, ctx).is_none());
+    full[last] ^= 1;
+    full.push(0);
+    assert!(transcode_full_to_lz4(&full, ctx).is_none());
+    assert!(transcode_full_to_lz4(&[], ctx).is_none());
+}
@@ -2,19 +2,22 @@ use compressor::utils::run_length_encoding;
 
 #[test]
 fn test_var_len_encoding_test() {
+    use compressor::utils::number_encoding::Endian;
     use compressor::utils::number_encoding::{decode_array16, encode_array16};
     use compressor::utils::variable_length_encoding::decode_array32;
     use compressor::utils::variable_length_encoding::encode_array32;
 
     fn test_round_trip16(input: &[u16]) {
-        let mut buffer0 = Vec::new();
-        let mut buffer1 = Vec::new();
+        for endian in [Endian::Big, Endian::Little] {
+            let mut buffer0 = Vec::new();
+            let mut buffer1 = Vec::new();
 
-        let wrote = encode_array16(input, &mut buffer0);
-        assert_eq!(buffer0.len(), wrote);
-        let read = decode_array16(&buffer0, &mut buffer1).unwrap();
-        assert_eq!(wrote, read);
-        assert_eq!(input, buffer1);
+            let wrote = encode_array16(input, &mut buffer0, endian);
+            assert_eq!(buffer0.len(), wrote);
+            let read = decode_array16(&buffer0, &mut buffer1, endian).unwrap();
+            assert_eq!(wrote, read);
+            assert_eq!(input, buffer1);
+        }
     }
 
     test_round_trip16(&[]);
@@ -54,3 +57,327 @@ fn test_rle() {
     run_length_encoding::decode(expected, &mut buff);
     assert_eq!(&buff, input);
 }
+
+#[test]
+fn test_stretch_squash() {
+    use compressor::utils::prob::{clamp12, clamp16, squash, stretch};
+    use compressor::utils::prob::{to_prob12, to_prob16, MIN_PROB16};
+
+    assert_eq!(squash(0), 2047);
+    assert_eq!(squash(-5000), 0);
+    assert_eq!(squash(5000), 4095);
+
+    // Squash is monotonic and stretch is its inverse.
+    let mut prev = 0;
+    for x in -2047..2048 {
+        let p = squash(x);
+        assert!(p >= prev);
+        prev = p;
+        assert!((stretch(p) as i32) <= x);
+    }
+    for p in 1..4095 {
+        assert!(stretch(p) <= stretch(p + 1));
+        let diff = squash(stretch(p) as i32) as i32 - p as i32;
+        assert!(diff.abs() <= 1 + p as i32 / 64);
+    }
+
+    assert_eq!(clamp16(0), MIN_PROB16);
+    assert_eq!(clamp16(65535), 65535 - MIN_PROB16);
+    assert_eq!(clamp16(1000), 1000);
+    assert_eq!(clamp12(0), 1);
+    assert_eq!(clamp12(4095), 4094);
+    assert_eq!(to_prob16(4095), 65535);
+    assert_eq!(to_prob12(to_prob16(1234)), 1234);
+}
+
+#[test]
+fn test_hash_reference_vectors() {
+    use compressor::utils::hash::{fnv1a32, fnv1a64, xxh32, xxh64};
+
+    let long = b"Nobody inspects the spammish repetition";
+
+    assert_eq!(xxh32(b"", 0), 0x02cc5d05);
+    assert_eq!(xxh32(b"a", 0), 0x550d7456);
+    assert_eq!(xxh32(b"abc", 0), 0x32d153ff);
+    assert_eq!(xxh32(long, 0), 0xe2293b2f);
+
+    assert_eq!(xxh64(b"", 0), 0xef46db3751d8e999);
+    assert_eq!(xxh64(b"a", 0), 0xd24ec4f1a98c6e5b);
+    assert_eq!(xxh64(b"abc", 0), 0x44bc2cf5ad770999);
+    assert_eq!(xxh64(long, 0), 0xfbcea83c8a378bf1);
+
+    assert_eq!(fnv1a32(b""), 0x811c9dc5);
+    assert_eq!(fnv1a32(b"a"), 0xe40c292c);
+    assert_eq!(fnv1a32(b"foobar"), 0xbf9cf968);
+    assert_eq!(fnv1a64(b""), 0xcbf29ce484222325);
+    assert_eq!(fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(fnv1a64(b"foobar"), 0x85944171f73967e8);
+}
+
+#[test]
+fn test_rolling_hash() {
+    use compressor::utils::hash::RollingHash;
+
+    let input: Vec<u8> = (0..200).map(|i| (i * 7 + i / 3) as u8).collect();
+    let window = 16;
+
+    let mut rolling = RollingHash::new(window);
+    for b in &input[..window] {
+        rolling.update(*b);
+    }
+
+    // Compare the rolling hash to a hash that is computed from scratch.
+    for start in 0..input.len() - window {
+        let mut fresh = RollingHash::new(window);
+        for b in &input[start..start + window] {
+            fresh.update(*b);
+        }
+        assert_eq!(rolling.hash(), fresh.hash());
+        rolling.roll(input[start], input[start + window]);
+    }
+}
+
+#[test]
+fn test_leb128() {
+    use compressor::utils::array_encoding;
+    use compressor::utils::leb128::{decode, encode, encoded_len};
+
+    for val in [0, 1, 127, 128, 300, 16383, 16384, 1 << 32, u64::MAX] {
+        let mut stream = Vec::new();
+        let written = encode(val, &mut stream);
+        assert_eq!(written, stream.len());
+        assert_eq!(written, encoded_len(val));
+        assert_eq!(decode(&stream), Some((written, val)));
+        // Truncated numbers are rejected.
+        assert_eq!(decode(&stream[..written - 1]), None);
+    }
+    assert_eq!(encoded_len(127), 1);
+    assert_eq!(encoded_len(128), 2);
+    assert_eq!(encoded_len(u64::MAX), 10);
+
+    // Numbers that are too long or that overflow are rejected.
+    assert_eq!(decode(&[0x80; 11]), None);
+    let mut overflow = vec![0xff; 9];
+    overflow.push(0x02);
+    assert_eq!(decode(&overflow), None);
+
+    // Short arrays have a single byte of overhead.
+    let mut stream = Vec::new();
+    assert_eq!(array_encoding::encode(&[1, 2, 3], &mut stream), 4);
+    let mut array = Vec::new();
+    assert_eq!(array_encoding::decode(&stream, &mut array), Some(4));
+    assert_eq!(array, [1, 2, 3]);
+    assert_eq!(array_encoding::decode(&stream[..3], &mut array), None);
+}
+
+#[test]
+fn test_bitpack() {
+    use compressor::utils::bitpack::{decode, encode, BLOCK_SIZE};
+
+    fn round_trip(values: &[u16]) -> usize {
+        let mut stream = vec![0xff];
+        let written = encode(values, &mut stream);
+        assert_eq!(written, stream.len() - 1);
+        let mut decoded = Vec::new();
+        assert_eq!(decode(&stream[1..], &mut decoded), Some(written));
+        assert_eq!(decoded, values);
+        written
+    }
+
+    // Values of every width from 1 to 16 bits.
+    for width in 1..=16 {
+        let max = ((1u32 << width) - 1) as u16;
+        let values: Vec<u16> = (0..1000u32)
+            .map(|i| (i * 7919 % (max as u32 + 1)) as u16)
+            .collect();
+        let written = round_trip(&values);
+        let blocks = values.len().div_ceil(BLOCK_SIZE);
+        assert!(
+            written
+                <= 2 + blocks * 3 + (values.len() * width).div_ceil(8) + blocks
+        );
+    }
+
+    // The frame of reference removes the common base of each block.
+    let values: Vec<u16> =
+        (0..BLOCK_SIZE as u16).map(|i| 60000 + i % 4).collect();
+    assert_eq!(round_trip(&values), 2 + 3 + BLOCK_SIZE / 4);
+
+    round_trip(&[]);
+    round_trip(&[5; 3]);
+    round_trip(&[0, u16::MAX, 1]);
+
+    // Truncated and invalid streams are rejected.
+    let mut stream = Vec::new();
+    encode(&values, &mut stream);
+    let mut decoded = Vec::new();
+    assert!(decode(&stream[..stream.len() - 1], &mut decoded).is_none());
+    stream[4] = 17;
+    assert!(decode(&stream, &mut Vec::new()).is_none());
+}
+
+#[test]
+fn test_stream_vbyte() {
+    use compressor::utils::stream_vbyte::{decode, encode};
+
+    fn round_trip(values: &[u32]) -> usize {
+        let mut stream = vec![0xff];
+        let written = encode(values, &mut stream);
+        assert_eq!(written, stream.len() - 1);
+        let mut decoded = Vec::new();
+        assert_eq!(decode(&stream[1..], &mut decoded), Some(written));
+        assert_eq!(decoded, values);
+        written
+    }
+
+    // Check all of the byte lengths and the remainders of the groups.
+    let values = [0, 255, 256, 65535, 65536, 1 << 24, u32::MAX, 7, 300];
+    for len in 0..values.len() {
+        round_trip(&values[..len]);
+    }
+    // One control byte and one data byte for each group of small numbers.
+    assert_eq!(round_trip(&[1, 2, 3, 4, 5, 6, 7, 8]), 1 + 2 + 8);
+
+    // Truncated streams are rejected.
+    let mut stream = Vec::new();
+    encode(&values, &mut stream);
+    for len in 0..stream.len() {
+        assert!(decode(&stream[..len], &mut Vec::new()).is_none());
+    }
+}
+
+#[test]
+fn test_pfor() {
+    use compressor::utils::pfor::{decode, encode, BLOCK_SIZE};
+
+    fn round_trip(values: &[u32]) -> usize {
+        let mut stream = vec![0xff];
+        let written = encode(values, &mut stream);
+        assert_eq!(written, stream.len() - 1);
+        let mut decoded = Vec::new();
+        assert_eq!(decode(&stream[1..], &mut decoded), Some(written));
+        assert_eq!(decoded, values);
+        written
+    }
+
+    round_trip(&[]);
+    round_trip(&[u32::MAX, 0, u32::MAX]);
+    round_trip(&[0, 0, 0, u32::MAX]);
+
+    // Sorted offsets with small gaps take about one byte per value.
+    let offsets: Vec<u32> = (0..1000u32).map(|i| (1 << 30) + i * 37).collect();
+    let written = round_trip(&offsets);
+    assert!(written < offsets.len() * 3 / 4 + 64);
+
+    // A cluster with a few outliers, which are patched as exceptions.
+    let mut values: Vec<u32> = (0..BLOCK_SIZE as u32).map(|i| i % 16).collect();
+    values[10] = 1 << 20;
+    values[100] = u32::MAX;
+    let written = round_trip(&values);
+    assert!(written < BLOCK_SIZE / 2 + 16);
+
+    // Truncated streams are rejected.
+    let mut stream = Vec::new();
+    encode(&values, &mut stream);
+    for len in 0..stream.len() {
+        assert!(decode(&stream[..len], &mut Vec::new()).is_none());
+    }
+}
+
+#[test]
+fn test_stream_decoders() {
+    use compressor::bitvector::Bitvector;
+    use compressor::utils::stream_vbyte;
+    use compressor::utils::two_stream_encoding::{decode_tokens, encode32};
+    use compressor::utils::variable_length_encoding::{decode_all, encode};
+
+    let mut x: u64 = 1;
+    let values: Vec<u32> = (0..5000)
+        .map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ((x >> 33) as u32) >> ((x >> 20) % 32)
+        })
+        .collect();
+
+    // The offset tokens are decoded in the order of the values.
+    let mut bv = Bitvector::new();
+    let tokens: Vec<u8> =
+        values.iter().map(|v| encode32(*v, &mut bv) as u8).collect();
+    let mut decoded = Vec::new();
+    decode_tokens(&tokens, &bv, &mut decoded).unwrap();
+    assert_eq!(decoded, values);
+    assert!(decode_tokens(&tokens[1..], &bv, &mut Vec::new()).is_none());
+
+    // Long arrays go through the SIMD paths.
+    for modulo in [200, 300, u32::MAX] {
+        let values: Vec<u32> = values.iter().map(|v| v % modulo).collect();
+        let mut stream = Vec::new();
+        for val in &values {
+            encode(*val, &mut stream);
+        }
+        let mut decoded = Vec::new();
+        decode_all(&stream, &mut decoded).unwrap();
+        assert_eq!(decoded, values);
+
+        let mut stream = Vec::new();
+        stream_vbyte::encode(&values, &mut stream);
+        let mut decoded = Vec::new();
+        stream_vbyte::decode(&stream, &mut decoded).unwrap();
+        assert_eq!(decoded, values);
+    }
+
+    // A truncated varint is rejected.
+    let mut decoded = Vec::new();
+    assert!(decode_all(&[1, 2, 255], &mut decoded).is_none());
+}
+
+#[test]
+fn test_number_endian() {
+    use compressor::utils::number_encoding::{decode16, decode32, Endian};
+    use compressor::utils::number_encoding::{encode16, encode32};
+    use compressor::utils::signatures::{read32, write32};
+
+    let mut big = Vec::new();
+    encode32(0x01020304, &mut big, Endian::Big);
+    encode16(0x0506, &mut big, Endian::Big);
+    assert_eq!(big, [1, 2, 3, 4, 5, 6]);
+
+    let mut little = Vec::new();
+    encode32(0x01020304, &mut little, Endian::Little);
+    encode16(0x0506, &mut little, Endian::Little);
+    assert_eq!(little, [4, 3, 2, 1, 6, 5]);
+
+    assert_eq!(decode32(&little, Endian::Little), Some((4, 0x01020304)));
+    assert_eq!(decode16(&little[4..], Endian::Little), Some((2, 0x0506)));
+    assert_eq!(decode32(&little, Endian::Big), Some((4, 0x04030201)));
+    assert_eq!(decode16(&little[5..], Endian::Little), None);
+
+    // The checksums of the containers stay big-endian.
+    let mut stream = Vec::new();
+    write32(0x01020304, &mut stream);
+    assert_eq!(stream, big[..4]);
+    assert_eq!(read32(&stream), Some(0x01020304));
+}
+
+#[test]
+fn test_unchecked_copies() {
+    use compressor::utils::unchecked::{copy_chunks, get, range};
+
+    // The copies are the same with and without the 'unsafe-fast' feature.
+    let input: Vec<u8> = (0..40).collect();
+    assert_eq!(get(&input, 39), 39);
+    assert_eq!(range(&input, 3..6), [3, 4, 5]);
+    assert!(range(&input, 40..40).is_empty());
+
+    for offset in 8..20 {
+        for len in 0..40 {
+            let mut expected = input.clone();
+            for _ in 0..len {
+                expected.push(expected[expected.len() - offset]);
+            }
+            let mut output = input.clone();
+            copy_chunks(&mut output, offset, len);
+            assert_eq!(output, expected, "offset {} len {}", offset, len);
+        }
+    }
+}
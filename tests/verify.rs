@@ -0,0 +1,41 @@
+use compressor::verify;
+use compressor::Context;
+
+#[test]
+fn test_verify_report() {
+    let text = "a line of text that repeats, with a number: ".repeat(500);
+    let report = verify(text.as_bytes(), Context::new(9, 1 << 16));
+    assert!(report.correct);
+    assert_eq!(report.input_size, text.len());
+    assert!(report.ratio() > 10.);
+    let stages = report.stages;
+    assert_eq!(stages.total(), report.compressed_size);
+    assert!(stages.literals > 0 && stages.sequences > 0 && stages.offsets > 0);
+
+    // Incompressible inputs are stored.
+    let random: Vec<u8> = (0..5000).map(|_| rand::random::<u8>()).collect();
+    let report = verify(&random, Context::new(9, 1 << 16));
+    assert!(report.correct);
+    assert!(report.stages.stored >= random.len());
+    assert_eq!(report.stages.total(), report.compressed_size);
+
+    // Small pages, and constant pages that are only made of headers.
+    let mut input = text.as_bytes()[..1000].to_vec();
+    input.resize(3000, 0);
+    let report = verify(&input, Context::new(9, 1000));
+    assert!(report.correct);
+    assert!(report.stages.small_blocks > 0);
+    assert_eq!(report.stages.total(), report.compressed_size);
+
+    // The checksums of the pages are part of the container.
+    let ctx = Context::new(9, 1000).with_page_checksums();
+    let checked = verify(&input, ctx);
+    assert!(checked.correct);
+    assert_eq!(checked.stages.small_blocks, report.stages.small_blocks);
+    assert!(checked.stages.other > report.stages.other);
+
+    let report = verify(&text.as_bytes()[..2000], Context::new(13, 1 << 16));
+    assert!(report.correct);
+    assert!(report.stages.arithmetic > 0);
+    assert_eq!(report.stages.total(), report.compressed_size);
+}
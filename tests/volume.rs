@@ -0,0 +1,128 @@
+use compressor::full::{FullDecoder, FullEncoder};
+use compressor::volume::{
+    volume_path, VolumeReader, VolumeWriter, MIN_VOLUME_SIZE,
+};
+use compressor::{Context, Decoder, Encoder};
+use std::cell::RefCell;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+/// A volume that is saved in memory.
+struct MemoryVolume {
+    volumes: Rc<RefCell<Vec<Vec<u8>>>>,
+    index: usize,
+    pos: usize,
+}
+
+impl Write for MemoryVolume {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let volume = &mut self.volumes.borrow_mut()[self.index];
+        let end = self.pos + buf.len();
+        if volume.len() < end {
+            volume.resize(end, 0);
+        }
+        volume[self.pos..end].copy_from_slice(buf);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryVolume {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Start(pos) => self.pos = pos as usize,
+            _ => unimplemented!(),
+        }
+        Ok(self.pos as u64)
+    }
+}
+
+fn split(data: &[u8], volume_size: usize) -> Vec<Vec<u8>> {
+    let volumes = Rc::new(RefCell::new(Vec::new()));
+    let mut writer = VolumeWriter::new(volume_size, |index| {
+        volumes.borrow_mut().push(Vec::new());
+        Ok(MemoryVolume {
+            volumes: volumes.clone(),
+            index,
+            pos: 0,
+        })
+    });
+    writer.write_all(data).unwrap();
+    let count = writer.finish().unwrap();
+    let volumes = volumes.take();
+    assert_eq!(count, volumes.len());
+    volumes
+}
+
+fn join(volumes: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    let readers: Vec<&[u8]> = volumes.iter().map(|v| &v[..]).collect();
+    let mut data = Vec::new();
+    VolumeReader::new(readers).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[test]
+fn test_volumes_round_trip() {
+    let text =
+        "Volumes are written to media with file-size limits. ".repeat(500);
+    let mut compressed: Vec<u8> = Vec::new();
+    let ctx = Context::new(9, 1 << 12);
+    let _ = FullEncoder::new(text.as_bytes(), &mut compressed, ctx).encode();
+
+    for volume_size in [MIN_VOLUME_SIZE, 100, 1000, compressed.len() + 100] {
+        let volumes = split(&compressed, volume_size);
+        assert!(volumes.iter().all(|v| v.len() <= volume_size));
+        assert!(volumes.iter().all(|v| v.len() > 5));
+
+        let joined = join(&volumes).unwrap();
+        assert_eq!(joined, compressed);
+        let mut decompressed: Vec<u8> = Vec::new();
+        let _ = FullDecoder::new(&joined, &mut decompressed)
+            .decode()
+            .unwrap();
+        assert_eq!(decompressed, text.as_bytes());
+    }
+
+    // Empty streams are saved as a single volume.
+    let volumes = split(&[], 100);
+    assert_eq!(volumes.len(), 1);
+    assert_eq!(join(&volumes).unwrap(), []);
+
+    assert_eq!(volume_path("file.rz", 0), "file.rz.001");
+    assert_eq!(volume_path("file.rz", 11), "file.rz.012");
+}
+
+#[test]
+fn test_volumes_out_of_order() {
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let mut volumes = split(&data, 100);
+    assert_eq!(join(&volumes).unwrap(), data);
+
+    // Missing and reordered volumes are detected.
+    volumes.swap(1, 2);
+    assert!(join(&volumes).is_err());
+    volumes.remove(1);
+    assert!(join(&volumes).is_err());
+
+    // Missing volumes at the end of the stream are detected too.
+    let mut volumes = split(&data, 100);
+    let last = volumes.pop().unwrap();
+    let err = join(&volumes).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(join(&volumes[..1]).is_err());
+    assert!(join(&[]).is_err());
+
+    // Volumes after the last volume are rejected.
+    volumes.push(last);
+    assert_eq!(join(&volumes).unwrap(), data);
+    let extra = split(&data, 50);
+    volumes.push(extra[volumes.len()].clone());
+    assert!(join(&volumes).is_err());
+
+    // Files that are not volumes are rejected.
+    assert!(join(&[data]).is_err());
+}